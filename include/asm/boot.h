@@ -2,6 +2,13 @@
 #define ASM_BOOT_H_
 
 #define BOOT_STACK_SIZE 0x4000
+/* Stack of exceptions taken with a bad stack pointer, also the overflow guard */
+#define EXCEPTION_STACK_SIZE 0x2000
 #define KERNEL_ALIGN 0x1000  /* 4KB - matches Makefile alignment */
 
+/* Link and load address, set per board by the Makefile */
+#ifndef LOAD_ADDR
+#define LOAD_ADDR 0x40080000
+#endif
+
 #endif // ASM_BOOT_H_
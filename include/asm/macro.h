@@ -14,7 +14,7 @@
 	stp x6, x7, [sp, #48]
 	stp x8, x9, [sp, #64]
 	stp x10, x11, [sp, #80]
-	stp x12, x12, [sp, #96]
+	stp x12, x13, [sp, #96]
 	stp x14, x15, [sp, #112]
 	stp x16, x17, [sp, #128]
 	stp x18, x19, [sp, #144]
@@ -18,5 +18,6 @@
 #define SPSR_EL_SERR_MASK (1 << 8)
 #define SPSR_EL_DEBUG_MASK (1 << 9)
 #define SPSR_EL_M_EL1 (5)
+#define SPSR_EL_M_EL2H (9)
 
 #endif // SYSTEM_H_
@@ -0,0 +1,105 @@
+//! Generic board described by the device tree
+//!
+//! Nothing is known at build time: the console UART, the GIC, the flash, the
+//! RTC, the watchdog, the kernel command line and the board model are looked
+//! up in the DTB passed by the firmware.
+//! RAM comes from the `/memory` nodes when the memory map is built. Anything
+//! missing from the DTB stays absent, except the console: without a PL011
+//! node, or without a DTB, the console sweep looks for one (see
+//! [`board`](super#console-sweep)).
+
+use super::heartbeat::{Heartbeat, NoHeartbeat};
+use super::{
+    BoardConfig, DebugChannel, GicConfig, GicKind, Identity, Performance, RecordStore, UartConfig,
+    UartKind,
+};
+use crate::parsers::fdt::Fdt;
+
+/// Maximum length of the kernel command line copied from `/chosen`
+const BOOTARGS_SIZE: usize = 256;
+
+/// Maximum length of the board model copied from the root node
+const MODEL_SIZE: usize = 64;
+
+/// Kernel command line copied out of the DTB, which may be moved later
+static mut BOOTARGS: [u8; BOOTARGS_SIZE] = [0; BOOTARGS_SIZE];
+/// Board model copied out of the DTB
+static mut MODEL: [u8; MODEL_SIZE] = [0; MODEL_SIZE];
+
+/// Nothing may describe a console, so the console sweep looks for one
+pub const CONSOLE_SWEEP: bool = true;
+
+/// Empty configuration, completed by [`init`]
+pub const CONFIG: BoardConfig = BoardConfig {
+    name: b"generic-dtb",
+    identity: Identity {
+        vendor: b"",
+        product: b"",
+        version: b"",
+        serial: b"",
+    },
+    uart: UartConfig {
+        kind: UartKind::None,
+        base: 0,
+        clock: 0,
+        baudrate: 0,
+    },
+    ram: &[],
+    devices: &[],
+    gic: GicConfig {
+        kind: GicKind::None,
+        dist_base: 0,
+        cpu_base: 0,
+    },
+    flash: None,
+    bootrecord: RecordStore::None,
+    bootargs: b"",
+    debug: DebugChannel::None,
+    psci: None,
+};
+
+/// Fills `config` from the device tree
+///
+/// Devices are left to the [driver registry](crate::drivers::registry), which probes them
+/// next.
+pub fn init(config: &mut BoardConfig, fdt: &Fdt) {
+    if let Ok(Some(chosen)) = fdt.chosen()
+        && let Some(args) = chosen.property(b"bootargs")
+    {
+        let args = args.split(|&c| c == 0).next().unwrap_or(&[]);
+        let buf = &raw mut BOOTARGS;
+        let buf = unsafe { &mut *buf };
+        let len = args.len().min(BOOTARGS_SIZE);
+
+        buf[..len].copy_from_slice(&args[..len]);
+        config.bootargs = &buf[..len];
+    }
+
+    if let Ok(Some(root)) = fdt.find_node(|node| node.depth == 1)
+        && let Some(model) = root.property(b"model")
+    {
+        let model = model.split(|&c| c == 0).next().unwrap_or(&[]);
+        let buf = &raw mut MODEL;
+        let buf = unsafe { &mut *buf };
+        let len = model.len().min(MODEL_SIZE);
+
+        buf[..len].copy_from_slice(&model[..len]);
+        config.identity.product = &buf[..len];
+    }
+}
+
+/// No LED on this board
+pub fn heartbeat() -> impl Heartbeat {
+    return NoHeartbeat;
+}
+
+/// The key given at build time, if any
+pub fn payload_key() -> Option<[u8; 32]> {
+    return super::BUILD_PAYLOAD_KEY;
+}
+
+/// Nothing is known about the clocks and voltages of the board
+pub fn set_boot_performance(_level: Performance) {}
+
+/// Nothing was changed
+pub fn set_handoff_performance() {}
@@ -0,0 +1,158 @@
+//! Heartbeat indicator
+//!
+//! Headless boards often have nothing but an LED to show what they are
+//! doing. Each board drives its indicator through the [`Heartbeat`] trait
+//! (boards without one use [`NoHeartbeat`]), and this module plays a
+//! [`Pattern`] on it from the generic timer interrupt, so the indicator keeps
+//! blinking while the boot flow is busy.
+//!
+//! The boot flow blinks slowly while loading, turns the indicator on just
+//! before the handoff, and the fatal paths switch to a fast blink (fatal
+//! exception) or SOS (any other panic).
+
+use crate::drivers::timer::generic;
+use crate::interrupt;
+
+/// Duration of a pattern step in microseconds
+pub const STEP_US: u64 = 100_000;
+
+/// Something that can be turned on and off to show progress
+pub trait Heartbeat {
+    /// Prepares the indicator, called once before it is first set
+    fn init(&mut self) {}
+
+    /// Turns the indicator on or off
+    fn set(&mut self, on: bool);
+}
+
+/// Indicator of boards without one: does nothing
+pub struct NoHeartbeat;
+
+impl Heartbeat for NoHeartbeat {
+    fn set(&mut self, _on: bool) {}
+}
+
+/// What the indicator shows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Always on
+    Solid,
+    /// On and off for one second each
+    SlowBlink,
+    /// On and off for one step each
+    FastBlink,
+    /// `... --- ...` in Morse code, repeated
+    Sos,
+}
+
+impl Pattern {
+    /// Returns the steps of the pattern as a bit mask and its length
+    ///
+    /// Bit `n` of the mask is the state of the indicator during step `n`.
+    fn steps(self) -> (u64, u32) {
+        return match self {
+            Pattern::Solid => (1, 1),
+            Pattern::SlowBlink => (0x3ff, 20),
+            Pattern::FastBlink => (1, 2),
+            // Dots of one step, dashes of three, one step between symbols,
+            // three between letters and seven before repeating
+            Pattern::Sos => (0b10101 | 0b11101110111 << 8 | 0b10101 << 22, 34),
+        };
+    }
+}
+
+/// Pattern being played
+static mut PATTERN: Pattern = Pattern::Solid;
+
+/// Current step of the pattern
+static mut STEP: u32 = 0;
+
+/// Whether the indicator was initialized
+static mut STARTED: bool = false;
+
+/// Whether the pattern is played from the timer interrupt
+static mut TICKING: bool = false;
+
+/// Sets the indicator of the board on or off
+fn set(on: bool) {
+    let mut led = super::selected::heartbeat();
+
+    unsafe {
+        if !STARTED {
+            led.init();
+            STARTED = true;
+        }
+    }
+    led.set(on);
+}
+
+/// Starts playing `pattern` from the generic timer interrupt
+///
+/// Brings up interrupts if needed. Without an interrupt controller or a
+/// counter frequency the indicator is only set to the first step of the
+/// pattern.
+pub fn start(pattern: Pattern) {
+    self::pattern(pattern);
+
+    if interrupt::init()
+        && generic::start_tick(STEP_US, tick)
+        && interrupt::enable(generic::timer_intid())
+    {
+        unsafe {
+            TICKING = true;
+        }
+        interrupt::unmask();
+    }
+}
+
+/// Stops the timer interrupt, leaving the indicator in its current state
+pub fn stop() {
+    interrupt::shutdown();
+    generic::stop_tick();
+    unsafe {
+        TICKING = false;
+    }
+}
+
+/// Switches to `pattern` from a fatal path and keeps it playing
+///
+/// Fatal paths may run with IRQs masked (e.g., in an exception handler), so
+/// they are unmasked again if the pattern is played from the timer interrupt.
+pub fn fatal(pattern: Pattern) {
+    self::pattern(pattern);
+    if unsafe { TICKING } {
+        interrupt::unmask();
+    }
+}
+
+/// Switches to `pattern`, starting from its first step
+pub fn pattern(pattern: Pattern) {
+    interrupt::without_interrupts(|| {
+        unsafe {
+            PATTERN = pattern;
+            STEP = 0;
+        }
+        set(pattern.steps().0 & 1 != 0);
+    });
+}
+
+/// Checks whether the pattern is played from the timer interrupt, which
+/// then fires every [`STEP_US`]
+pub fn is_ticking() -> bool {
+    return unsafe { TICKING };
+}
+
+/// Returns the pattern being played
+pub fn current() -> Pattern {
+    return unsafe { PATTERN };
+}
+
+/// Advances the pattern by one step, called on every timer tick
+fn tick() {
+    let (mask, len) = current().steps();
+
+    unsafe {
+        STEP = (STEP + 1) % len;
+        set(mask >> STEP & 1 != 0);
+    }
+}
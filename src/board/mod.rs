@@ -0,0 +1,617 @@
+//! Board support
+//!
+//! This module describes the platform the bootloader runs on: where the
+//! console UART is, which RAM to use when there is no device tree, the
+//! interrupt controller, the flash holding the environment and the default
+//! kernel command line. Drivers and the boot flow take their addresses from
+//! [`config`] instead of hard-coded constants.
+//!
+//! The board is selected at build time with exactly one of the mutually
+//! exclusive cargo features:
+//!
+//! - `board-qemu-virt` (default): QEMU `virt` machine
+//! - `board-raspi4`: Raspberry Pi 4 Model B
+//! - `board-generic-dtb`: everything is discovered from the device tree
+//!   passed by the firmware at runtime
+//!
+//! Each board also provides its progress indicator, see [`heartbeat`], the
+//! key of encrypted payloads, see [`payload_key`], and the control of its
+//! clocks and voltages, see [`set_boot_performance`].
+//!
+//! The channel debug-level log output goes to, see [`DebugChannel`], comes
+//! from the board configuration unless the `DEBUG_CHANNEL` environment
+//! variable overrides it at build time.
+//!
+//! # Console sweep
+//!
+//! On a board nothing describes, neither the board configuration nor a DTB
+//! gives the console. As a last resort, [`board_init`] then probes the
+//! usual UART locations of common SoCs for one. The sweep is on for the
+//! `board-generic-dtb` board and off for the others, which know their
+//! console; building with `CONSOLE_SWEEP=1` or `CONSOLE_SWEEP=0` overrides
+//! that. A console found this way is reported in the boot banner.
+
+use crate::bootid;
+use crate::cpu;
+use crate::drivers::registry;
+use crate::drivers::timer::generic;
+use crate::drivers::uart::{ns16550, pl011};
+use crate::error;
+use crate::exception::{self, Conduit};
+use crate::log::{self, Level};
+use crate::memory::map;
+use crate::parsers::fdt::{self, Fdt, FdtError, SynthBuffer};
+use crate::utilities::print::u64_to_hex;
+
+#[cfg(not(any(
+    feature = "board-qemu-virt",
+    feature = "board-raspi4",
+    feature = "board-generic-dtb"
+)))]
+compile_error!("one board feature must be enabled (e.g., board-qemu-virt)");
+
+#[cfg(any(
+    all(feature = "board-qemu-virt", feature = "board-raspi4"),
+    all(feature = "board-qemu-virt", feature = "board-generic-dtb"),
+    all(feature = "board-raspi4", feature = "board-generic-dtb")
+))]
+compile_error!("board features are mutually exclusive, enable only one");
+
+pub mod heartbeat;
+
+#[cfg(feature = "board-generic-dtb")]
+mod generic_dtb;
+#[cfg(feature = "board-qemu-virt")]
+mod qemu_virt;
+#[cfg(feature = "board-raspi4")]
+mod raspi4;
+
+#[cfg(feature = "board-generic-dtb")]
+use generic_dtb as selected;
+#[cfg(feature = "board-qemu-virt")]
+use qemu_virt as selected;
+#[cfg(feature = "board-raspi4")]
+use raspi4 as selected;
+
+/// Kind of UART used as the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartKind {
+    /// No console UART is known
+    None,
+    /// ARM PL011
+    Pl011,
+    /// 16550 compatible, registers 4 bytes apart (see [`ns16550`]), only
+    /// found by the console sweep
+    Ns16550,
+}
+
+impl UartKind {
+    /// Returns the name of the UART kind
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            UartKind::None => b"none",
+            UartKind::Pl011 => b"PL011",
+            UartKind::Ns16550 => b"16550",
+        };
+    }
+}
+
+/// Console UART description
+#[derive(Clone, Copy, Debug)]
+pub struct UartConfig {
+    /// Kind of UART
+    pub kind: UartKind,
+    /// Physical base address of its registers
+    pub base: usize,
+    /// Reference clock frequency in Hz
+    pub clock: u32,
+    /// Baud rate to program
+    pub baudrate: u32,
+}
+
+/// Kind of interrupt controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GicKind {
+    /// No interrupt controller is known
+    None,
+    /// GICv2 (distributor and CPU interface)
+    V2,
+    /// GICv3 (distributor and redistributors)
+    V3,
+}
+
+/// Interrupt controller description
+#[derive(Clone, Copy, Debug)]
+pub struct GicConfig {
+    /// Kind of GIC
+    pub kind: GicKind,
+    /// Physical base address of the distributor
+    pub dist_base: usize,
+    /// Physical base address of the CPU interface (GICv2) or of the
+    /// redistributors (GICv3)
+    pub cpu_base: usize,
+}
+
+/// Flash and environment location
+#[derive(Clone, Copy, Debug)]
+pub struct FlashConfig {
+    /// Physical base address of the flash
+    pub base: usize,
+    /// Size of the flash in bytes
+    pub size: usize,
+    /// Offset of the environment within the flash
+    pub env_offset: usize,
+    /// Size of the environment in bytes
+    pub env_size: usize,
+}
+
+/// Where the persistent boot record is kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordStore {
+    /// Nowhere: boot counting starts over on every boot
+    None,
+    /// RAM preserved across resets (e.g., battery-backed SRAM)
+    Ram {
+        /// Physical address of the record
+        base: usize,
+    },
+    /// An erase block of the flash, not shared with anything else
+    Flash {
+        /// Offset of the block from the start of the flash
+        offset: usize,
+    },
+}
+
+/// Where debug-level log output goes (see [`log`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugChannel {
+    /// Nowhere separate: debug output goes to the console
+    None,
+    /// A second PL011, clocked like the console and set up for output only
+    Pl011 {
+        /// Physical base address of its registers
+        base: usize,
+    },
+    /// The semihosting debug console of a debugger or of QEMU run with
+    /// `-semihosting`; without one listening, the first write faults
+    Semihosting,
+}
+
+/// Identity of a board, reported to the payload in the
+/// [SMBIOS tables](crate::tables::smbios)
+///
+/// An empty string means the value is unknown.
+#[derive(Clone, Copy, Debug)]
+pub struct Identity {
+    /// Manufacturer
+    pub vendor: &'static [u8],
+    /// Product name
+    pub product: &'static [u8],
+    /// Hardware revision
+    pub version: &'static [u8],
+    /// Serial number
+    pub serial: &'static [u8],
+}
+
+/// Description of a board
+#[derive(Clone, Copy, Debug)]
+pub struct BoardConfig {
+    /// Human-readable board name
+    pub name: &'static [u8],
+    /// Vendor, model and serial number of the board
+    pub identity: Identity,
+    /// Console UART
+    pub uart: UartConfig,
+    /// `(base, size)` RAM ranges used when the device tree doesn't describe
+    /// any memory
+    pub ram: &'static [(usize, usize)],
+    /// `(base, size)` ranges of device registers, never loaded or run from
+    pub devices: &'static [(usize, usize)],
+    /// Interrupt controller
+    pub gic: GicConfig,
+    /// Flash holding the environment, if the board has one
+    pub flash: Option<FlashConfig>,
+    /// Storage of the boot record (see [`bootreason`](crate::bootreason))
+    pub bootrecord: RecordStore,
+    /// Kernel command line used when none is configured
+    pub bootargs: &'static [u8],
+    /// Debug channel, unless overridden at build time with `DEBUG_CHANNEL`
+    pub debug: DebugChannel,
+    /// Instruction PSCI calls are made with, or `None` to go by the
+    /// exception level (see [`psci_conduit`](Self::psci_conduit))
+    pub psci: Option<Conduit>,
+}
+
+impl BoardConfig {
+    /// Returns the instruction PSCI calls are made with
+    ///
+    /// The `/psci` node of the DTB gives it, or else the board. Boards
+    /// leaving it open use `smc` from EL2 and above, where the firmware is
+    /// the next level up, and `hvc` from EL1, where a hypervisor is.
+    pub fn psci_conduit(&self) -> Conduit {
+        return match self.psci {
+            Some(conduit) => conduit,
+            None if cpu::current_el() >= 2 => Conduit::Smc,
+            None => Conduit::Hvc,
+        };
+    }
+}
+
+/// Configuration of the board the bootloader was built for
+static mut CONFIG: BoardConfig = selected::CONFIG;
+
+/// DTB built from the board configuration when the firmware passes none
+static mut SYNTH_DTB: SynthBuffer = SynthBuffer([0; fdt::SYNTH_SIZE]);
+
+/// Base address, kind and reference clock of the UARTs the console sweep
+/// looks for, in order: the PL011 of QEMU `virt`, Raspberry Pi 4, Arm FVP
+/// and Juno, then the 16550 of Rockchip RK3399 and RK3568, Allwinner A64
+/// and H6/H616, whose clock isn't needed
+const SWEEP_CANDIDATES: [(usize, UartKind, u32); 8] = [
+    (0x0900_0000, UartKind::Pl011, 24_000_000),
+    (0xfe20_1000, UartKind::Pl011, 48_000_000),
+    (0x1c09_0000, UartKind::Pl011, 24_000_000),
+    (0x7ff8_0000, UartKind::Pl011, 7_273_800),
+    (0xff1a_0000, UartKind::Ns16550, 0),
+    (0xfe66_0000, UartKind::Ns16550, 0),
+    (0x01c2_8000, UartKind::Ns16550, 0),
+    (0x0500_0000, UartKind::Ns16550, 0),
+];
+/// Time the console sweep may take, in microseconds
+const SWEEP_LIMIT_US: u64 = 50_000;
+/// Baud rate programmed into a console found by probing
+const FALLBACK_BAUDRATE: u32 = 115_200;
+
+/// Whether the console sweep runs: the board's choice, unless building with
+/// `CONSOLE_SWEEP=0` or `CONSOLE_SWEEP=1` overrides it
+const BUILD_CONSOLE_SWEEP: bool = match option_env!("CONSOLE_SWEEP") {
+    Some(value) => !bytes_eq(value.as_bytes(), b"0"),
+    None => selected::CONSOLE_SWEEP,
+};
+
+/// Whether the console was found by the console sweep
+static mut CONSOLE_SWEPT: bool = false;
+
+/// Key for encrypted payloads, given at build time as 64 hex digits in the
+/// `PAYLOAD_KEY` environment variable
+const BUILD_PAYLOAD_KEY: Option<[u8; 32]> = match option_env!("PAYLOAD_KEY") {
+    Some(hex) => Some(parse_key(hex.as_bytes())),
+    None => None,
+};
+
+/// Whether the console receives input: building with `UART_INPUT=0` leaves
+/// its receiver off, for setups where nothing can be typed
+const BUILD_UART_INPUT: bool = match option_env!("UART_INPUT") {
+    Some(value) => !bytes_eq(value.as_bytes(), b"0"),
+    None => true,
+};
+
+/// Debug channel given at build time in the `DEBUG_CHANNEL` environment
+/// variable: `semihosting`, `pl011:<hex base>` or `none`
+const BUILD_DEBUG_CHANNEL: Option<DebugChannel> = match option_env!("DEBUG_CHANNEL") {
+    Some(spec) => Some(parse_debug_channel(spec.as_bytes())),
+    None => None,
+};
+
+/// Parses a `DEBUG_CHANNEL` value, failing the build if it's malformed
+const fn parse_debug_channel(spec: &[u8]) -> DebugChannel {
+    const PL011: &[u8] = b"pl011:";
+    const USAGE: &str = "DEBUG_CHANNEL must be none, semihosting or pl011:<hex base>";
+
+    if bytes_eq(spec, b"none") {
+        return DebugChannel::None;
+    }
+    if bytes_eq(spec, b"semihosting") {
+        return DebugChannel::Semihosting;
+    }
+    if spec.len() <= PL011.len() || !bytes_eq(spec.split_at(PL011.len()).0, PL011) {
+        panic!("{}", USAGE);
+    }
+    let mut digits = spec.split_at(PL011.len()).1;
+    if digits.len() > 2 && digits[0] == b'0' && (digits[1] == b'x' || digits[1] == b'X') {
+        digits = digits.split_at(2).1;
+    }
+    let mut base = 0usize;
+    let mut i = 0;
+    assert!(!digits.is_empty() && digits.len() <= 16, "{}", USAGE);
+    while i < digits.len() {
+        let digit = match digits[i] {
+            b'0'..=b'9' => digits[i] - b'0',
+            b'a'..=b'f' => digits[i] - b'a' + 10,
+            b'A'..=b'F' => digits[i] - b'A' + 10,
+            b'_' => {
+                i += 1;
+                continue;
+            }
+            _ => panic!("{}", USAGE),
+        };
+        base = (base << 4) | digit as usize;
+        i += 1;
+    }
+    return DebugChannel::Pl011 { base };
+}
+
+/// Compares byte strings in a const context
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut i = 0;
+
+    if a.len() != b.len() {
+        return false;
+    }
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(matches!(
+    parse_debug_channel(b"semihosting"),
+    DebugChannel::Semihosting
+));
+const _: () = assert!(matches!(
+    parse_debug_channel(b"pl011:0x0904_0000"),
+    DebugChannel::Pl011 { base: 0x0904_0000 }
+));
+
+/// Parses 64 hex digits into a key, failing the build if they're malformed
+const fn parse_key(hex: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    let mut i = 0;
+
+    assert!(hex.len() == 64, "PAYLOAD_KEY must be 64 hex digits");
+    while i < 64 {
+        let digit = match hex[i] {
+            b'0'..=b'9' => hex[i] - b'0',
+            b'a'..=b'f' => hex[i] - b'a' + 10,
+            b'A'..=b'F' => hex[i] - b'A' + 10,
+            _ => panic!("PAYLOAD_KEY must be 64 hex digits"),
+        };
+        key[i / 2] = (key[i / 2] << 4) | digit;
+        i += 1;
+    }
+    return key;
+}
+
+/// Returns the key encrypted payloads are decrypted with, if the board has one
+pub fn payload_key() -> Option<[u8; 32]> {
+    return selected::payload_key();
+}
+
+/// Performance asked of the board's clocks and voltages during the boot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Performance {
+    /// Only waiting for the user, e.g. during the autoboot countdown: as
+    /// low as the board allows
+    Low,
+    /// Loading and checking the payload: as fast as the board allows
+    Full,
+}
+
+/// Sets the clocks and voltages of the board for the phase of the boot
+/// that `level` describes
+///
+/// Board code programs its platform-specific registers, if it has any;
+/// boards without clock or voltage control ignore it.
+pub fn set_boot_performance(level: Performance) {
+    selected::set_boot_performance(level);
+}
+
+/// Puts the clocks and voltages of the board back as the payload expects
+/// them, normally as the firmware left them, before the handoff
+pub fn set_handoff_performance() {
+    selected::set_handoff_performance();
+}
+
+/// Returns the configuration of the board
+pub fn config() -> &'static BoardConfig {
+    let config = &raw const CONFIG;
+
+    unsafe {
+        return &*config;
+    }
+}
+
+/// Completes the board configuration and brings up the console
+///
+/// Called by the assembly entry code with the DTB passed by the firmware,
+/// before anything is printed, and returns the DTB to use from then on.
+/// The [boot ID](bootid) is generated first, so even early output can carry
+/// it. Boards that are described at runtime fill in their configuration
+/// from the DTB here, then the devices of the DTB are probed by the
+/// [driver registry](registry), which completes whatever the board left
+/// out. A PL011 console described in the DTB takes its reference clock from
+/// there, so the baud rate is right whatever clock the board assumed, and
+/// the `/psci` node gives the PSCI conduit. If that leaves no console, the
+/// console sweep may look for one (see the
+/// [module documentation](self#console-sweep)).
+///
+/// # Without a DTB
+///
+/// Started standalone, the bootloader may find anything in x0. The DTB is
+/// read with [`fdt::probe_blob`], so an address nothing answers at is
+/// reported rather than faulting. Without a valid DTB, the board
+/// configuration is used as is: RAM, console, GIC and PSCI conduit. Once
+/// the console is up, that is reported once, and a DTB describing the board
+/// is built with [`fdt::synthesize`] and returned, so the devices are
+/// listed as usual and a Linux kernel can still be booted. If even that
+/// fails, 0 is returned, so the address isn't read again, and the memory
+/// map comes from the board configuration too.
+#[unsafe(no_mangle)]
+pub extern "C" fn board_init(dtb: usize) -> usize {
+    let config = &raw mut CONFIG;
+    let config = unsafe { &mut *config };
+
+    bootid::init();
+    let tree = unsafe { fdt::probe_blob(dtb) }.and_then(Fdt::new);
+    if let Ok(tree) = &tree {
+        selected::init(config, tree);
+        registry::init_all(tree, config);
+        if config.uart.kind == UartKind::Pl011 {
+            config.uart.clock = pl011::fdt_clock(tree, config.uart.base, config.uart.clock);
+        }
+        if let Some(conduit) = fdt_psci_conduit(tree) {
+            config.psci = Some(conduit);
+        }
+    }
+    if config.uart.kind == UartKind::None {
+        sweep_console(config, tree.as_ref().ok());
+    }
+    setup_console(config);
+
+    if let Some(channel) = BUILD_DEBUG_CHANNEL {
+        config.debug = channel;
+    }
+    if !log::set_debug_channel(config.debug, &config.uart) {
+        pl011::println(b"No PL011 at the debug channel, debug output stays on the console");
+    }
+    log::load_format();
+    let dtb = match tree {
+        Ok(_) => dtb,
+        Err(e) => synthesize_dtb(config, dtb, e),
+    };
+    log::stage(b"board_init");
+    exception::capture_context(b"board_init");
+    return dtb;
+}
+
+/// Returns the conduit the `/psci` node of `tree` gives, if any
+fn fdt_psci_conduit(tree: &Fdt) -> Option<Conduit> {
+    let psci = tree.find_path(b"/psci").ok()??;
+
+    return match psci.property_str(b"method")? {
+        b"smc" => Some(Conduit::Smc),
+        b"hvc" => Some(Conduit::Hvc),
+        _ => None,
+    };
+}
+
+/// Reports that `dtb` isn't a valid DTB, because of `e`, and builds one from
+/// `config` to be used instead
+///
+/// Returns the address of the DTB built, or 0 if it couldn't be built.
+fn synthesize_dtb(config: &mut BoardConfig, dtb: usize, e: FdtError) -> usize {
+    let buf = &raw mut SYNTH_DTB;
+    let buf = unsafe { &mut (*buf).0 };
+    let mut hex = [0u8; 16];
+
+    error::print_error(&e.into());
+    log::print(Level::Warn, b"No valid DTB at 0x");
+    log::print(Level::Warn, u64_to_hex(dtb as u64, &mut hex));
+    log::println(Level::Warn, b", using the board configuration");
+    if fdt::synthesize(config, buf).is_err() {
+        log::println(
+            Level::Warn,
+            b"Could not build a DTB from the board configuration",
+        );
+        return 0;
+    }
+    let Ok(tree) = Fdt::new(buf) else {
+        return 0;
+    };
+    registry::init_all(&tree, config);
+    log::print(
+        Level::Info,
+        b"Built a DTB from the board configuration at 0x",
+    );
+    log::println(Level::Info, u64_to_hex(buf.as_ptr() as u64, &mut hex));
+    return buf.as_ptr() as usize;
+}
+
+/// Brings up the console the board was built with
+///
+/// This is the part of [`board_init`] the first stage of a two-stage boot
+/// needs. There is no DTB nor probing for a console, so a board without a
+/// built-in console stays silent.
+pub fn init_console() {
+    let config = &raw mut CONFIG;
+
+    setup_console(unsafe { &mut *config });
+}
+
+/// Brings up the console UART of `config`, if it has one
+fn setup_console(config: &mut BoardConfig) {
+    match config.uart.kind {
+        UartKind::Pl011 => {
+            let configured = pl011::init_uart(
+                config.uart.base as *mut u32,
+                config.uart.clock,
+                config.uart.baudrate,
+            )
+            .and_then(|()| {
+                pl011::set_input(BUILD_UART_INPUT);
+                return pl011::configure_uart();
+            });
+            // Without a console the error stays in the early output buffer
+            if let Err(e) = configured {
+                error::print_error(&e.into());
+            }
+        }
+        UartKind::Ns16550 => {
+            ns16550::init(config.uart.base);
+            pl011::add_sink(ns16550::putchar);
+        }
+        UartKind::None => {}
+    }
+}
+
+/// Checks whether `addr` is RAM, for the memory map or for the `/memory`
+/// nodes of `tree`
+fn is_ram(tree: Option<&Fdt>, addr: usize) -> bool {
+    let mut ram = map::is_ram(addr);
+
+    if let Some(tree) = tree {
+        let _ = tree.for_each_memory_range(|base, size| {
+            ram |= addr as u64 >= base && addr as u64 - base < size;
+        });
+    }
+    return ram;
+}
+
+/// Looks for a console at the [`SWEEP_CANDIDATES`] locations
+///
+/// Used when neither the board nor the DTB describe a console, if the sweep
+/// is on (see [`BUILD_CONSOLE_SWEEP`]). The first candidate whose
+/// identification registers answer becomes the console. Candidates that
+/// are RAM, for `tree` or the board, are skipped: RAM would pass for a
+/// 16550. The sweep gives up after [`SWEEP_LIMIT_US`].
+fn sweep_console(config: &mut BoardConfig, tree: Option<&Fdt>) {
+    let frequency = generic::frequency();
+    let start = generic::counter();
+
+    if !BUILD_CONSOLE_SWEEP {
+        return;
+    }
+    for &(base, kind, clock) in SWEEP_CANDIDATES.iter() {
+        if generic::ticks_to_us(generic::counter() - start, frequency) > SWEEP_LIMIT_US {
+            return;
+        }
+        if is_ram(tree, base) {
+            continue;
+        }
+        let found = match kind {
+            UartKind::Pl011 => pl011::is_pl011_at(base),
+            UartKind::Ns16550 => ns16550::is_16550_at(base),
+            UartKind::None => false,
+        };
+        if found {
+            config.uart = UartConfig {
+                kind,
+                base,
+                clock,
+                baudrate: FALLBACK_BAUDRATE,
+            };
+            unsafe {
+                CONSOLE_SWEPT = true;
+            }
+            return;
+        }
+    }
+}
+
+/// Returns whether the console was found by the console sweep
+pub fn console_swept() -> bool {
+    return unsafe { CONSOLE_SWEPT };
+}
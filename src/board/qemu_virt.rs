@@ -0,0 +1,76 @@
+//! QEMU `virt` machine
+//!
+//! Addresses match QEMU's `virt` memory map with `gic-version=3`, as used by
+//! the `run` target of the Makefile.
+
+use super::heartbeat::{Heartbeat, NoHeartbeat};
+use super::{
+    BoardConfig, DebugChannel, FlashConfig, GicConfig, GicKind, Identity, Performance, RecordStore,
+    UartConfig, UartKind,
+};
+use crate::parsers::fdt::Fdt;
+
+/// The console is known, so there is no console sweep
+pub const CONSOLE_SWEEP: bool = false;
+
+/// QEMU `virt` board configuration
+pub const CONFIG: BoardConfig = BoardConfig {
+    name: b"qemu-virt",
+    identity: Identity {
+        vendor: b"QEMU",
+        product: b"QEMU virt",
+        version: b"",
+        serial: b"",
+    },
+    uart: UartConfig {
+        kind: UartKind::Pl011,
+        base: 0x0900_0000,
+        clock: 24_000_000,
+        baudrate: 115_200,
+    },
+    // QEMU's default 128 MiB
+    ram: &[(0x4000_0000, 0x0800_0000)],
+    // GIC, UARTs, RTC, GPIO, virtio and PCIe windows; the flash below is
+    // left out as code may run from it
+    devices: &[(0x0800_0000, 0x3800_0000)],
+    gic: GicConfig {
+        kind: GicKind::V3,
+        dist_base: 0x0800_0000,
+        cpu_base: 0x080a_0000,
+    },
+    // Second flash bank, the first one holds the firmware
+    flash: Some(FlashConfig {
+        base: 0x0400_0000,
+        size: 0x0400_0000,
+        env_offset: 0,
+        env_size: 0x4_0000,
+    }),
+    // The block following the environment
+    bootrecord: RecordStore::Flash { offset: 0x4_0000 },
+    bootargs: b"console=ttyAMA0",
+    // The second UART (0x0904_0000) only exists with a second -serial, so
+    // it is selected with DEBUG_CHANNEL=pl011:0x09040000 when wanted
+    debug: DebugChannel::None,
+    // QEMU answers `smc` when it emulates EL2 (`virtualization=on`) and
+    // `hvc` otherwise, which is where the bootloader starts
+    psci: None,
+};
+
+/// Nothing to discover at runtime
+pub fn init(_config: &mut BoardConfig, _fdt: &Fdt) {}
+
+/// No LED on this board
+pub fn heartbeat() -> impl Heartbeat {
+    return NoHeartbeat;
+}
+
+/// The key given at build time, if any
+pub fn payload_key() -> Option<[u8; 32]> {
+    return super::BUILD_PAYLOAD_KEY;
+}
+
+/// The emulated CPU has no clocks or voltages to set
+pub fn set_boot_performance(_level: Performance) {}
+
+/// Nothing was changed
+pub fn set_handoff_performance() {}
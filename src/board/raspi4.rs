@@ -0,0 +1,110 @@
+//! Raspberry Pi 4 Model B
+//!
+//! Addresses are the ARM physical addresses in the default (low peripheral)
+//! mode set up by the VideoCore firmware.
+
+use super::heartbeat::Heartbeat;
+use super::{
+    BoardConfig, DebugChannel, GicConfig, GicKind, Identity, Performance, RecordStore, UartConfig,
+    UartKind,
+};
+use crate::exception::Conduit;
+use crate::parsers::fdt::Fdt;
+use crate::utilities::mmio;
+
+/// Physical base address of the GPIO controller
+const GPIO_BASE: usize = 0xfe20_0000;
+/// GPIO pin driving the green activity LED (active high)
+const ACT_LED_PIN: usize = 42;
+/// GPIO Function Select registers offset (10 pins per register)
+const GPFSEL_OFF: usize = 0x00;
+/// GPIO Pin Output Set registers offset (32 pins per register)
+const GPSET_OFF: usize = 0x1c;
+/// GPIO Pin Output Clear registers offset (32 pins per register)
+const GPCLR_OFF: usize = 0x28;
+/// GPFSEL function value of an output pin
+const GPFSEL_OUTPUT: u32 = 0b001;
+
+/// The console is known, so there is no console sweep
+pub const CONSOLE_SWEEP: bool = false;
+
+/// Raspberry Pi 4 board configuration
+pub const CONFIG: BoardConfig = BoardConfig {
+    name: b"raspi4",
+    identity: Identity {
+        vendor: b"Raspberry Pi Foundation",
+        product: b"Raspberry Pi 4 Model B",
+        version: b"",
+        serial: b"",
+    },
+    uart: UartConfig {
+        kind: UartKind::Pl011,
+        base: 0xfe20_1000,
+        clock: 48_000_000,
+        baudrate: 115_200,
+    },
+    // Memory below the VideoCore carve-out, present on every RAM size
+    ram: &[(0, 0x3b40_0000)],
+    // Main peripherals, including the GIC
+    devices: &[(0xfc00_0000, 0x0400_0000)],
+    gic: GicConfig {
+        kind: GicKind::V2,
+        dist_base: 0xff84_1000,
+        cpu_base: 0xff84_2000,
+    },
+    // The environment lives on the SD card, not in flash
+    flash: None,
+    bootrecord: RecordStore::None,
+    bootargs: b"console=ttyAMA0,115200",
+    debug: DebugChannel::None,
+    // TF-A, as the firmware's own ARM stub has no PSCI
+    psci: Some(Conduit::Smc),
+};
+
+/// Nothing to discover at runtime
+pub fn init(_config: &mut BoardConfig, _fdt: &Fdt) {}
+
+/// Green activity LED, driven directly through the GPIO controller
+pub struct ActLed;
+
+impl Heartbeat for ActLed {
+    fn init(&mut self) {
+        let offset = GPFSEL_OFF + ACT_LED_PIN / 10 * 4;
+        let shift = ACT_LED_PIN % 10 * 3;
+
+        unsafe {
+            let fsel = mmio::read_mmio32(GPIO_BASE, offset);
+            let fsel = (fsel & !(0b111 << shift)) | GPFSEL_OUTPUT << shift;
+            mmio::write_mmio32(GPIO_BASE, offset, fsel);
+        }
+    }
+
+    fn set(&mut self, on: bool) {
+        let reg = if on { GPSET_OFF } else { GPCLR_OFF };
+
+        unsafe {
+            mmio::write_mmio32(
+                GPIO_BASE,
+                reg + ACT_LED_PIN / 32 * 4,
+                1 << (ACT_LED_PIN % 32),
+            );
+        }
+    }
+}
+
+/// The activity LED
+pub fn heartbeat() -> impl Heartbeat {
+    return ActLed;
+}
+
+/// The key given at build time, if any
+pub fn payload_key() -> Option<[u8; 32]> {
+    return super::BUILD_PAYLOAD_KEY;
+}
+
+/// The ARM clock is set through the VideoCore firmware's mailbox, which
+/// has no driver yet, so it's left as the firmware set it
+pub fn set_boot_performance(_level: Performance) {}
+
+/// Nothing was changed
+pub fn set_handoff_performance() {}
@@ -0,0 +1,1119 @@
+//! Payload handoff
+//!
+//! This module implements the final step of the bootloader: handing control
+//! to the loaded payload. Besides the plain Linux-style handoff performed by
+//! the assembly entry code, it provides a richer handoff for non-Linux
+//! payloads through the [`BootInfo`] block.
+//!
+//! # BootInfo ABI
+//!
+//! [`BootInfo`] is a stable, versioned interface between the bootloader and
+//! the payload. Its layout is fixed (`#[repr(C)]`, little-endian, no implicit
+//! padding) and checked at compile time. The rules for evolving it are:
+//!
+//! - `magic` is always [`BOOTINFO_MAGIC`] and always at offset 0
+//! - `version` is bumped whenever the meaning of an existing field changes
+//! - New fields are only ever appended, and `size` grows accordingly, so a
+//!   payload must only read fields that fit within `size`
+//!
+//! The block is placed at a page-aligned address inside the bootloader image,
+//! which is reported as a reserved range in the block itself.
+//!
+//! # DTB placement
+//!
+//! Before the kernel is loaded, [`prepare_boot`] records the RAM and the
+//! ranges in use in the [`memory`](crate::memory) registry and moves the DTB
+//! to a free, aligned location with [`place_dtb`], so loading the kernel can't
+//! clobber it. Any later DTB edit operates on the relocated copy, e.g.
+//! pointing `/chosen` to the [SMBIOS tables](crate::tables::smbios) and the
+//! [event log](crate::measure).
+//! [`relocate_dtb`] does the same for a blob known only by its address,
+//! keeping it clear of the kernel once that is reserved.
+//!
+//! # Handoff state
+//!
+//! Right before the jump, [`before_handoff`] puts pointer authentication and
+//! BTI in the state described in the [`pauth`](crate::pauth) module, so a
+//! kernel built with branch protection starts the same way on every core.
+//!
+//! The payload normally starts at EL1. Built with the `el2-handoff` feature,
+//! or after the monitor's `handoff el2` command, [`enter_payload`] leaves it
+//! at EL2 instead with [`boot_el2`], for hypervisors, in a defined nVHE or
+//! (`el2-vhe`, `handoff el2-vhe`) VHE state. Both paths quiesce the
+//! bootloader the same way before the jump.
+
+use crate::board;
+use crate::board::heartbeat::{self, Pattern};
+use crate::board::{Performance, RecordStore};
+use crate::bootid;
+use crate::bootreason;
+#[cfg(feature = "console-capture")]
+use crate::capture;
+use crate::cpu;
+use crate::drivers;
+use crate::drivers::timer::generic;
+use crate::drivers::uart::pl011;
+#[cfg(feature = "ramfb-console")]
+use crate::drivers::video::{console, ramfb};
+use crate::error::{self, BootError};
+use crate::exception;
+use crate::log::{self, Level, Value};
+use crate::measure;
+use crate::memory::map::{self, RegionKind};
+use crate::memory::poison;
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+use crate::memory::space::{self, Demand};
+#[cfg(not(feature = "stage1"))]
+use crate::monitor;
+use crate::parsers::elf::{self, LoadedImage};
+use crate::parsers::fdt::{self, Fdt, FdtError};
+use crate::parsers::image::{MemorySource, UNKNOWN_LEN};
+use crate::pauth;
+use crate::power;
+#[cfg(feature = "psci-responder")]
+use crate::psci;
+use crate::selftest::{self, Outcome, SelfTest};
+use crate::tables::smbios;
+use crate::utilities::guid::Guid;
+use crate::utilities::print::u64_to_hex;
+#[cfg(feature = "selftest-exit")]
+use crate::utilities::semihosting;
+
+use core::mem::{self, offset_of};
+use core::{ptr, slice};
+
+/// BootInfo magic number: the ASCII string "BOOTINFO" read as a little-endian u64
+pub const BOOTINFO_MAGIC: u64 = 0x4f464e49544f4f42;
+/// Current version of the BootInfo layout
+pub const BOOTINFO_VERSION: u32 = 1;
+/// Maximum number of RAM ranges reported in a BootInfo block
+pub const BOOTINFO_MAX_RAM_RANGES: usize = 8;
+/// Maximum number of reserved ranges reported in a BootInfo block
+pub const BOOTINFO_MAX_RESERVED_RANGES: usize = 16;
+/// Alignment of the BootInfo block handed to the payload
+const BOOTINFO_ALIGN: usize = 4096;
+
+/// Extra room left after a relocated DTB for later property edits
+pub const DTB_HEADROOM: usize = 0x10000;
+/// Maximum size of a relocated DTB (including headroom) per the arm64 boot protocol
+const DTB_MAX_SIZE: usize = 2 << 20;
+/// Minimum alignment of the DTB required by the arm64 boot protocol
+const DTB_MIN_ALIGN: usize = 8;
+/// Older kernels require the DTB within this distance of the kernel start
+pub const DTB_KERNEL_WINDOW: usize = 512 << 20;
+
+/// A physical memory range
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MemRange {
+    /// Physical base address of the range
+    pub base: u64,
+    /// Size of the range in bytes
+    pub size: u64,
+}
+
+impl MemRange {
+    /// An empty range, used to fill unused slots
+    const EMPTY: MemRange = MemRange { base: 0, size: 0 };
+}
+
+/// Handoff information block passed to non-Linux payloads
+///
+/// Describes where the bootloader was, what it loaded, the memory map and the
+/// console. See the module documentation for the ABI rules.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct BootInfo {
+    /// Always [`BOOTINFO_MAGIC`]
+    pub magic: u64,
+    /// Layout version, currently [`BOOTINFO_VERSION`]
+    pub version: u32,
+    /// Size of this structure in bytes
+    pub size: u32,
+    /// Physical address of the first byte of the bootloader image
+    pub bootloader_start: u64,
+    /// Physical address one past the last byte of the bootloader image
+    pub bootloader_end: u64,
+    /// Lowest address occupied by the loaded image
+    pub image_start: u64,
+    /// Address one past the end of the loaded image (including BSS)
+    pub image_end: u64,
+    /// Entry point of the loaded image
+    pub image_entry: u64,
+    /// Base address of the console UART (0 if there is none)
+    pub uart_base: u64,
+    /// Baud rate of the console UART
+    pub uart_baud: u32,
+    /// Number of valid entries in `ram_ranges`
+    pub ram_range_count: u32,
+    /// Number of valid entries in `reserved_ranges`
+    pub reserved_range_count: u32,
+    /// Reserved for future use, always 0
+    pub reserved0: u32,
+    /// Random seed for the payload (0 if no entropy source was available)
+    pub rng_seed: u64,
+    /// Value of the system counter when the block was built
+    pub boot_ticks: u64,
+    /// Frequency of the system counter in Hz
+    pub counter_freq: u64,
+    /// RAM ranges available to the payload
+    pub ram_ranges: [MemRange; BOOTINFO_MAX_RAM_RANGES],
+    /// Ranges the payload must not overwrite
+    pub reserved_ranges: [MemRange; BOOTINFO_MAX_RESERVED_RANGES],
+    /// Physical address of the measured-boot event log (0 if there is none)
+    pub event_log_base: u64,
+    /// Size of the measured-boot event log region in bytes
+    pub event_log_size: u64,
+    /// [Boot ID](crate::bootid) of the current boot
+    pub boot_id: u64,
+    /// Physical address of the [SMBIOS](crate::tables::smbios) entry point
+    /// (0 if there is none)
+    pub smbios_entry: u64,
+    /// GUID tagging the loaded image, in the byte order of GPT and UEFI
+    /// (all zeros if there is none)
+    pub kernel_guid: [u8; 16],
+    /// GUID tagging the DTB, in the same byte order (all zeros if there is
+    /// none)
+    pub dtb_guid: [u8; 16],
+    /// GUID tagging the initrd, in the same byte order (all zeros if there
+    /// is none)
+    pub initrd_guid: [u8; 16],
+}
+
+// The layout is an ABI: any change here must be deliberate
+const _: () = assert!(mem::size_of::<BootInfo>() == 568);
+const _: () = assert!(offset_of!(BootInfo, magic) == 0);
+const _: () = assert!(offset_of!(BootInfo, version) == 8);
+const _: () = assert!(offset_of!(BootInfo, size) == 12);
+const _: () = assert!(offset_of!(BootInfo, bootloader_start) == 16);
+const _: () = assert!(offset_of!(BootInfo, bootloader_end) == 24);
+const _: () = assert!(offset_of!(BootInfo, image_start) == 32);
+const _: () = assert!(offset_of!(BootInfo, image_end) == 40);
+const _: () = assert!(offset_of!(BootInfo, image_entry) == 48);
+const _: () = assert!(offset_of!(BootInfo, uart_base) == 56);
+const _: () = assert!(offset_of!(BootInfo, uart_baud) == 64);
+const _: () = assert!(offset_of!(BootInfo, ram_range_count) == 68);
+const _: () = assert!(offset_of!(BootInfo, reserved_range_count) == 72);
+const _: () = assert!(offset_of!(BootInfo, reserved0) == 76);
+const _: () = assert!(offset_of!(BootInfo, rng_seed) == 80);
+const _: () = assert!(offset_of!(BootInfo, boot_ticks) == 88);
+const _: () = assert!(offset_of!(BootInfo, counter_freq) == 96);
+const _: () = assert!(offset_of!(BootInfo, ram_ranges) == 104);
+const _: () = assert!(offset_of!(BootInfo, reserved_ranges) == 232);
+const _: () = assert!(offset_of!(BootInfo, event_log_base) == 488);
+const _: () = assert!(offset_of!(BootInfo, event_log_size) == 496);
+const _: () = assert!(offset_of!(BootInfo, boot_id) == 504);
+const _: () = assert!(offset_of!(BootInfo, smbios_entry) == 512);
+const _: () = assert!(offset_of!(BootInfo, kernel_guid) == 520);
+const _: () = assert!(offset_of!(BootInfo, dtb_guid) == 536);
+const _: () = assert!(offset_of!(BootInfo, initrd_guid) == 552);
+
+impl BootInfo {
+    /// An empty, valid BootInfo block
+    const EMPTY: BootInfo = BootInfo {
+        magic: BOOTINFO_MAGIC,
+        version: BOOTINFO_VERSION,
+        size: mem::size_of::<BootInfo>() as u32,
+        bootloader_start: 0,
+        bootloader_end: 0,
+        image_start: 0,
+        image_end: 0,
+        image_entry: 0,
+        uart_base: 0,
+        uart_baud: 0,
+        ram_range_count: 0,
+        reserved_range_count: 0,
+        reserved0: 0,
+        rng_seed: 0,
+        boot_ticks: 0,
+        counter_freq: 0,
+        ram_ranges: [MemRange::EMPTY; BOOTINFO_MAX_RAM_RANGES],
+        reserved_ranges: [MemRange::EMPTY; BOOTINFO_MAX_RESERVED_RANGES],
+        event_log_base: 0,
+        event_log_size: 0,
+        boot_id: 0,
+        smbios_entry: 0,
+        kernel_guid: [0; 16],
+        dtb_guid: [0; 16],
+        initrd_guid: [0; 16],
+    };
+}
+
+/// Builder for [`BootInfo`] blocks
+///
+/// The bootloader extents, console details and counter values are filled in
+/// automatically; the loaded image and memory ranges are provided by the
+/// caller, the latter usually through [`BootInfoBuilder::memory_map`]. Ranges that don't fit in the fixed-size arrays are dropped.
+pub struct BootInfoBuilder {
+    info: BootInfo,
+}
+
+impl BootInfoBuilder {
+    /// Creates a builder with no image and no memory ranges
+    pub fn new() -> Self {
+        return BootInfoBuilder {
+            info: BootInfo::EMPTY,
+        };
+    }
+
+    /// Records the extents and entry point of the loaded image
+    pub fn image(mut self, image: &LoadedImage) -> Self {
+        self.info.image_start = image.start as u64;
+        self.info.image_end = image.end as u64;
+        self.info.image_entry = image.entry as u64;
+        return self;
+    }
+
+    /// Adds a RAM range available to the payload
+    pub fn ram_range(mut self, base: u64, size: u64) -> Self {
+        let count = self.info.ram_range_count as usize;
+
+        if count < BOOTINFO_MAX_RAM_RANGES {
+            self.info.ram_ranges[count] = MemRange { base, size };
+            self.info.ram_range_count += 1;
+        }
+        return self;
+    }
+
+    /// Adds a range the payload must not overwrite
+    pub fn reserved_range(mut self, base: u64, size: u64) -> Self {
+        let count = self.info.reserved_range_count as usize;
+
+        if count < BOOTINFO_MAX_RESERVED_RANGES {
+            self.info.reserved_ranges[count] = MemRange { base, size };
+            self.info.reserved_range_count += 1;
+        }
+        return self;
+    }
+
+    /// Adds the RAM ranges of the memory map and all current reservations
+    pub fn memory_map(mut self) -> Self {
+        for region in map::regions().iter().filter(|r| r.kind == RegionKind::Ram) {
+            self = self.ram_range(region.base as u64, region.size as u64);
+        }
+        for r in reserve::reservations() {
+            self = self.reserved_range(r.base as u64, r.size as u64);
+        }
+        return self;
+    }
+
+    /// Tags the loaded image with `guid`
+    pub fn kernel_guid(mut self, guid: Guid) -> Self {
+        self.info.kernel_guid = guid.to_mixed_endian();
+        return self;
+    }
+
+    /// Tags the DTB with `guid`
+    pub fn dtb_guid(mut self, guid: Guid) -> Self {
+        self.info.dtb_guid = guid.to_mixed_endian();
+        return self;
+    }
+
+    /// Tags the initrd with `guid`
+    pub fn initrd_guid(mut self, guid: Guid) -> Self {
+        self.info.initrd_guid = guid.to_mixed_endian();
+        return self;
+    }
+
+    /// Sets the random seed handed to the payload
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.info.rng_seed = seed;
+        return self;
+    }
+
+    /// Finishes the block
+    ///
+    /// Fills in the bootloader extents (also reported as a reserved range, as
+    /// the block itself lives there), the console UART, the counter, the
+    /// measured-boot event log, the boot ID and the SMBIOS tables.
+    pub fn build(self) -> BootInfo {
+        let (start, end) = bootloader_extents();
+        let mut builder = self.reserved_range(start as u64, (end - start) as u64);
+
+        builder.info.bootloader_start = start as u64;
+        builder.info.bootloader_end = end as u64;
+        builder.info.uart_base = pl011::base_addr() as u64;
+        builder.info.uart_baud = pl011::baudrate();
+        builder.info.counter_freq = generic::frequency();
+        builder.info.boot_ticks = generic::counter();
+        if let Some((base, size)) = measure::region() {
+            builder.info.event_log_base = base as u64;
+            builder.info.event_log_size = size as u64;
+        }
+        builder.info.boot_id = bootid::id();
+        builder.info.smbios_entry = smbios::entry_point().unwrap_or(0) as u64;
+        return builder.info;
+    }
+}
+
+impl Default for BootInfoBuilder {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Address of the DTB handed to the payload, 0 if there is none
+static mut BOOT_DTB: usize = 0;
+/// Address of the DTB as passed by firmware
+static mut FIRMWARE_DTB: usize = 0;
+
+/// Page-aligned storage for the BootInfo block handed to the payload
+#[repr(C, align(4096))]
+struct BootInfoSlot(BootInfo);
+
+const _: () = assert!(mem::align_of::<BootInfoSlot>() == BOOTINFO_ALIGN);
+
+/// BootInfo block handed to the payload, inside the bootloader image
+static mut BOOT_INFO_SLOT: BootInfoSlot = BootInfoSlot(BootInfo::EMPTY);
+
+unsafe extern "C" {
+    /// First byte of the bootloader image (provided by the linker script)
+    static __bootloader_start: u8;
+    /// End of the bootloader image (provided by the linker script)
+    static __bootloader_end: u8;
+    /// Top of the boot stack, which follows the image (provided by the linker script)
+    static boot_stack: u8;
+    /// Drops to the payload at `entry` with `arg0` in x0 and `arg1` in x1
+    fn switch_to_elx(arg0: usize, entry: usize, arg1: usize) -> !;
+    /// Enters the payload at `entry` at EL2 with `arg0` in x0, after loading
+    /// the registers of `state`
+    fn enter_el2(arg0: usize, entry: usize, state: &El2State) -> !;
+}
+
+/// Returns the start and end addresses of the bootloader image
+pub fn bootloader_extents() -> (usize, usize) {
+    let start = &raw const __bootloader_start as usize;
+    let end = &raw const __bootloader_end as usize;
+
+    return (start, end);
+}
+
+/// Copies `info` into its reserved slot and returns the slot's address
+fn place_boot_info(info: &BootInfo) -> usize {
+    unsafe {
+        BOOT_INFO_SLOT.0 = *info;
+        return &raw const BOOT_INFO_SLOT as usize;
+    }
+}
+
+/// Jumps to a payload passing a BootInfo block
+///
+/// The block is copied to a page-aligned reserved address and its pointer is
+/// passed in x0 (x1 is 0).
+pub fn boot_with_info(entry: usize, info: &BootInfo) -> ! {
+    let info_addr = place_boot_info(info);
+
+    unsafe {
+        switch_to_elx(info_addr, entry, 0);
+    }
+}
+
+/// Jumps to a payload passing both a DTB and a BootInfo block
+///
+/// The DTB address is passed in x0 as in the Linux boot protocol, and the
+/// BootInfo block is placed as in [`boot_with_info`] with its pointer in x1.
+pub fn boot_with_info_and_dtb(entry: usize, dtb: usize, info: &BootInfo) -> ! {
+    let info_addr = place_boot_info(info);
+
+    unsafe {
+        switch_to_elx(dtb, entry, info_addr);
+    }
+}
+
+/// Exception level the payload is entered at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandoffMode {
+    /// EL1, dropping from EL2 as in the Linux boot protocol
+    El1,
+    /// EL2 with HCR_EL2.E2H clear (nVHE)
+    El2,
+    /// EL2 with HCR_EL2.E2H set (VHE)
+    El2Vhe,
+}
+
+/// Mode used when the features don't say otherwise
+#[cfg(not(feature = "el2-handoff"))]
+const DEFAULT_HANDOFF_MODE: HandoffMode = HandoffMode::El1;
+/// Mode used when the features don't say otherwise
+#[cfg(all(feature = "el2-handoff", not(feature = "el2-vhe")))]
+const DEFAULT_HANDOFF_MODE: HandoffMode = HandoffMode::El2;
+/// Mode used when the features don't say otherwise
+#[cfg(feature = "el2-vhe")]
+const DEFAULT_HANDOFF_MODE: HandoffMode = HandoffMode::El2Vhe;
+
+/// Mode used by [`enter_payload`]
+static mut HANDOFF_MODE: HandoffMode = DEFAULT_HANDOFF_MODE;
+
+/// Returns the exception level the payload is entered at
+pub fn handoff_mode() -> HandoffMode {
+    return unsafe { HANDOFF_MODE };
+}
+
+/// Sets the exception level the payload is entered at
+pub fn set_handoff_mode(mode: HandoffMode) {
+    unsafe {
+        HANDOFF_MODE = mode;
+    }
+}
+
+/// Errors reported when the payload can't be entered at EL2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandoffError {
+    /// The bootloader wasn't entered at EL2, and can't raise its own
+    /// exception level
+    NotAtEl2,
+    /// VHE was asked for on a core without FEAT_VHE
+    NoVhe,
+}
+
+/// HCR_EL2.RW: EL1 is AArch64
+const HCR_EL2_RW: u64 = 1 << 31;
+/// HCR_EL2.E2H: EL2 hosts an operating system (VHE)
+const HCR_EL2_E2H: u64 = 1 << 34;
+/// SCTLR_EL2 with its RES1 bits set, MMU and caches off, little-endian
+const SCTLR_EL2_NVHE: u64 = 0x30c5_0830;
+/// SCTLR_EL2 with E2H set (the SCTLR_EL1 layout): the bits RES1 in
+/// Armv8.0 set, MMU and caches off, little-endian
+const SCTLR_EL2_VHE: u64 = 0x30d0_0800;
+/// TCR_EL2 with its RES1 bits set, other fields zero
+const TCR_EL2_NVHE: u64 = 0x8080_0000;
+/// TCR_EL2 with E2H set (the TCR_EL1 layout): walks of both halves
+/// disabled (EPD0, EPD1)
+const TCR_EL2_VHE: u64 = 0x0080_0080;
+/// MAIR_EL2 attributes 0 to 3: Device-nGnRnE, Device-nGnRE, Normal
+/// non-cacheable, Normal write-back
+const MAIR_EL2_DEFAULT: u64 = 0xff44_0400;
+/// VTCR_EL2 with its RES1 bit set: stage 2 stays off, HCR_EL2.VM being clear
+const VTCR_EL2_DEFAULT: u64 = 0x8000_0000;
+/// CPTR_EL2 with its RES1 bits set, FP/SIMD and SVE not trapped
+const CPTR_EL2_NVHE: u64 = 0x33ff;
+/// CPTR_EL2 with E2H set (the CPACR_EL1 layout): FP/SIMD not trapped (FPEN)
+const CPTR_EL2_VHE: u64 = 0x30_0000;
+
+/// EL2 registers loaded by `enter_el2` in transition.S, with the MMU off:
+/// the offsets are checked below
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct El2State {
+    hcr: u64,
+    sctlr: u64,
+    tcr: u64,
+    mair: u64,
+    vtcr: u64,
+    cptr: u64,
+    sp: u64,
+}
+
+const _: () = assert!(offset_of!(El2State, hcr) == 0);
+const _: () = assert!(offset_of!(El2State, sctlr) == 8);
+const _: () = assert!(offset_of!(El2State, tcr) == 16);
+const _: () = assert!(offset_of!(El2State, mair) == 24);
+const _: () = assert!(offset_of!(El2State, vtcr) == 32);
+const _: () = assert!(offset_of!(El2State, cptr) == 40);
+const _: () = assert!(offset_of!(El2State, sp) == 48);
+
+/// Returns the EL2 registers the payload starts with, with the stack
+/// pointer `sp`, with E2H set if `vhe`
+const fn el2_state(vhe: bool, sp: u64) -> El2State {
+    if vhe {
+        return El2State {
+            hcr: HCR_EL2_RW | HCR_EL2_E2H,
+            sctlr: SCTLR_EL2_VHE,
+            tcr: TCR_EL2_VHE,
+            mair: MAIR_EL2_DEFAULT,
+            vtcr: VTCR_EL2_DEFAULT,
+            cptr: CPTR_EL2_VHE,
+            sp,
+        };
+    }
+    return El2State {
+        hcr: HCR_EL2_RW,
+        sctlr: SCTLR_EL2_NVHE,
+        tcr: TCR_EL2_NVHE,
+        mair: MAIR_EL2_DEFAULT,
+        vtcr: VTCR_EL2_DEFAULT,
+        cptr: CPTR_EL2_NVHE,
+        sp,
+    };
+}
+
+const _: () = assert!(el2_state(false, 0).hcr & HCR_EL2_E2H == 0);
+const _: () = assert!(el2_state(true, 0).hcr & HCR_EL2_E2H != 0);
+// EL1 stays AArch64 and stage 2 off, and the MMU, caches and big-endian
+// data accesses are off
+const _: () = assert!(el2_state(false, 0).hcr == HCR_EL2_RW);
+const _: () = assert!(el2_state(false, 0).sctlr & (1 << 25 | 1 << 12 | 1 << 2 | 1) == 0);
+const _: () = assert!(el2_state(true, 0).sctlr & (1 << 25 | 1 << 12 | 1 << 2 | 1) == 0);
+// FP/SIMD isn't trapped: TFP in the nVHE layout, FPEN 0b11 in the VHE one
+const _: () = assert!(el2_state(false, 0).cptr & (1 << 10) == 0);
+const _: () = assert!(el2_state(true, 0).cptr >> 20 & 3 == 3);
+
+/// Last step of the handoff shared by every exception level
+///
+/// Records the boot as successful, prints the `handoff` event of the jump
+/// to `entry` with `dtb` in `mode`, leaves the heartbeat indicator on and
+/// stops its timer interrupt, handing interrupts back to the payload, then
+/// quiesces the drivers (see [`drivers::quiesce_all`]).
+fn quiesce(entry: usize, dtb: usize, mode: HandoffMode) {
+    let (el, vhe) = match mode {
+        HandoffMode::El1 => (1, 0),
+        HandoffMode::El2 => (2, 0),
+        HandoffMode::El2Vhe => (2, 1),
+    };
+
+    bootreason::mark_boot_successful();
+    log::event(
+        Level::Info,
+        b"handoff",
+        &[
+            (b"entry", Value::Hex(entry as u64)),
+            (b"dtb", Value::Hex(dtb as u64)),
+            (b"el", Value::Dec(el)),
+            (b"vhe", Value::Dec(vhe)),
+        ],
+    );
+    heartbeat::pattern(Pattern::Solid);
+    heartbeat::stop();
+    board::set_handoff_performance();
+    drivers::quiesce_all();
+}
+
+/// Jumps to the payload at `entry` with `dtb` in x0, at the exception level
+/// of [`handoff_mode`]
+///
+/// Called by the assembly entry code after [`before_handoff`]. If the
+/// payload can't be entered at EL2, the error is printed and the boot fails.
+#[unsafe(no_mangle)]
+pub extern "C" fn enter_payload(dtb: usize, entry: usize) -> ! {
+    if handoff_mode() != HandoffMode::El1 {
+        let e: BootError = boot_el2(entry, dtb).into();
+        error::print_error(&e);
+        error::boot_failed(e.code());
+    }
+    quiesce(entry, dtb, HandoffMode::El1);
+    unsafe {
+        switch_to_elx(dtb, entry, 0);
+    }
+}
+
+/// Jumps to a payload at `entry` staying at EL2, with `dtb` in x0
+///
+/// HCR_EL2.E2H is set if [`handoff_mode`] is [`HandoffMode::El2Vhe`]. The
+/// payload starts with:
+///
+/// | Register  | Value                                                    |
+/// |-----------|----------------------------------------------------------|
+/// | x0        | `dtb`                                                    |
+/// | x1 to x3  | 0                                                        |
+/// | PSTATE    | EL2h, AArch64, D, A, I and F masked                      |
+/// | SP_EL2    | Top of the bootloader's boot stack                       |
+/// | HCR_EL2   | RW, plus E2H for VHE: no traps, stage 2 off              |
+/// | SCTLR_EL2 | RES1 bits: MMU and caches off, little-endian             |
+/// | TCR_EL2   | RES1 bits for nVHE, EPD0 and EPD1 for VHE                |
+/// | MAIR_EL2  | Device-nGnRnE, Device-nGnRE, Normal NC, Normal WB        |
+/// | VTCR_EL2  | RES1 bits                                                |
+/// | CPTR_EL2  | FP/SIMD not trapped                                      |
+/// | VBAR_EL2  | The bootloader's vectors, until the payload sets its own |
+///
+/// The payload must set up the rest of EL2, including its own stack, before
+/// turning on its MMU. Shares [`quiesce`] with the EL1 handoff.
+///
+/// Returns only if the payload can't be entered at EL2: when the
+/// bootloader itself was entered at EL1 (or EL3) or VHE is missing, before
+/// anything is changed.
+pub fn boot_el2(entry: usize, dtb: usize) -> HandoffError {
+    let mode = handoff_mode();
+    let vhe = mode == HandoffMode::El2Vhe;
+
+    if cpu::current_el() != 2 {
+        return HandoffError::NotAtEl2;
+    }
+    if vhe && !cpu::has_vhe() {
+        return HandoffError::NoVhe;
+    }
+    let state = el2_state(vhe, &raw const boot_stack as u64);
+
+    quiesce(entry, dtb, mode);
+    unsafe {
+        enter_el2(dtb, entry, &state);
+    }
+}
+
+/// Errors reported while placing the DTB
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaceError {
+    /// The blob is not a valid DTB
+    InvalidDtb(FdtError),
+    /// The blob plus headroom exceeds the 2 MiB limit of the boot protocol
+    TooLarge,
+    /// No suitable free region was found, or it couldn't be reserved
+    NoSpace(ReserveError),
+}
+
+/// Relocates the DTB to a safe, aligned location
+///
+/// Validates `dtb`, finds a free RAM region for it plus [`DTB_HEADROOM`]
+/// bytes of room for later edits, copies it there and reserves the region
+/// for [`ReserveTag::Dtb`]. The region is aligned to its own power-of-two size
+/// so it never crosses a 2 MiB boundary, as older kernels require. Returns the
+/// address to hand to the payload in x0.
+pub fn place_dtb(dtb: &[u8]) -> Result<usize, PlaceError> {
+    return place_dtb_within(dtb, 0, None);
+}
+
+/// Relocates the DTB like [`place_dtb`], within 512 MiB of `kernel_start`
+///
+/// Older kernels only map the first 512 MiB from their own start early on,
+/// so the DTB must sit in `[kernel_start, kernel_start + 512 MiB)`.
+pub fn place_dtb_near_kernel(dtb: &[u8], kernel_start: usize) -> Result<usize, PlaceError> {
+    return place_dtb_within(
+        dtb,
+        kernel_start,
+        Some(kernel_start.saturating_add(DTB_KERNEL_WINDOW)),
+    );
+}
+
+/// Relocates the DTB like [`place_dtb_near_kernel`] if the kernel starts at
+/// `kernel_start`, or like [`place_dtb`] if no kernel is known (`None`)
+pub fn place_dtb_for_kernel(dtb: &[u8], kernel_start: Option<usize>) -> Result<usize, PlaceError> {
+    return match kernel_start {
+        Some(kernel_start) => place_dtb_near_kernel(dtb, kernel_start),
+        None => place_dtb(dtb),
+    };
+}
+
+/// Returns the size of the region a DTB of `len` bytes is placed in, which
+/// is also its alignment
+fn dtb_region_size(len: usize) -> usize {
+    return (len + DTB_HEADROOM).next_power_of_two().max(DTB_MIN_ALIGN);
+}
+
+/// Relocates the DTB like [`place_dtb`], starting at or above `min` and
+/// ending at or below `limit` if given
+fn place_dtb_within(dtb: &[u8], min: usize, limit: Option<usize>) -> Result<usize, PlaceError> {
+    let header = fdt::parse_header(dtb).map_err(PlaceError::InvalidDtb)?;
+    let len = header.totalsize as usize;
+    let size = dtb_region_size(len);
+    let src = dtb.as_ptr() as usize;
+
+    if size > DTB_MAX_SIZE {
+        return Err(PlaceError::TooLarge);
+    }
+
+    // Keep the source out of the search so the copy can't overlap it
+    let src_reserved = reserve::reserve(src, len, ReserveTag::Dtb).is_ok();
+    let dst = reserve::allocate_within(size, size, min, limit, ReserveTag::Dtb);
+    if src_reserved {
+        reserve::release(src, ReserveTag::Dtb);
+    }
+    let dst = dst.map_err(PlaceError::NoSpace)?;
+
+    unsafe {
+        ptr::copy_nonoverlapping(dtb.as_ptr(), dst as *mut u8, len);
+        ptr::write_bytes((dst + len) as *mut u8, 0, size - len);
+    }
+
+    return Ok(dst);
+}
+
+/// Relocates the DTB at `src` clear of the kernel and returns the address to
+/// pass in x0
+///
+/// Checks the magic and `totalsize` of the blob, then moves it like
+/// [`place_dtb_near_kernel`] if the kernel's destination is reserved, or like
+/// [`place_dtb`] otherwise. Either way the copy is reserved for
+/// [`ReserveTag::Dtb`], so nothing placed later lands on it. Returns `src`
+/// itself if the blob is invalid or can't be moved.
+///
+/// # Safety
+///
+/// `src` must be readable as described for [`fdt::blob_at`].
+pub unsafe fn relocate_dtb(src: usize) -> usize {
+    let Ok(blob) = (unsafe { fdt::blob_at(src) }) else {
+        return src;
+    };
+    let kernel = reserve::reservations()
+        .iter()
+        .find(|r| r.tag == ReserveTag::Kernel);
+    let placed = place_dtb_for_kernel(blob, kernel.map(|kernel| kernel.base));
+
+    return placed.unwrap_or(src);
+}
+
+/// Size of [`TEST_DTB`]: a header, an empty reservation map and a root node
+/// holding an empty `/chosen`
+const TEST_DTB_SIZE: usize = fdt::FDT_HEADER_SIZE + 16 + 32;
+/// Bytes following [`TEST_DTB`] that aren't part of it
+const TEST_DTB_TRAILER: usize = 16;
+
+/// Self-test blob, followed by [`TEST_DTB_TRAILER`] bytes of 0xff
+static TEST_DTB: [u32; (TEST_DTB_SIZE + TEST_DTB_TRAILER) / 4] = {
+    let header = fdt::FDT_HEADER_SIZE as u32;
+    let size = TEST_DTB_SIZE as u32;
+    let words = [
+        // Header
+        fdt::FDT_MAGIC,
+        size,
+        header + 16,
+        size,
+        header,
+        17,
+        16,
+        0,
+        0,
+        32,
+        // Reservation map terminator
+        0,
+        0,
+        0,
+        0,
+        // Root node with an empty name, holding "chosen"
+        1,
+        0,
+        1,
+        0x6368_6f73,
+        0x656e_0000,
+        2,
+        2,
+        9,
+        // Trailer
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+    ];
+    let mut blob = [0u32; (TEST_DTB_SIZE + TEST_DTB_TRAILER) / 4];
+    let mut i = 0;
+    while i < blob.len() {
+        blob[i] = words[i].to_be();
+        i += 1;
+    }
+    blob
+};
+
+/// Distance from the first copy of [`TEST_DTB`] to the kernel start the
+/// self-test places it near
+const TEST_KERNEL_OFFSET: usize = 1 << 20;
+
+/// Self-test: [`TEST_DTB`] is moved, exactly `totalsize` bytes of it are
+/// copied, and the copy is reserved; a blob without the magic stays put.
+/// Once the event log is set up, its region is recorded in `/chosen` of the
+/// copy.
+/// Placed near a kernel starting past the first copy, the blob lands in the
+/// kernel's window, not in the lower memory left free.
+///
+/// Skipped if no RAM is known.
+pub fn selftest() -> Outcome {
+    let src = TEST_DTB.as_ptr() as usize;
+
+    if map::regions().is_empty() {
+        return Outcome::Skipped;
+    }
+    let dst = unsafe { relocate_dtb(src) };
+    if dst == src {
+        return Outcome::Fail;
+    }
+
+    let original = unsafe { slice::from_raw_parts(src as *const u8, TEST_DTB_SIZE) };
+    let copy = unsafe { slice::from_raw_parts(dst as *const u8, TEST_DTB_SIZE + TEST_DTB_TRAILER) };
+    let copied = fdt::parse_header(copy).is_ok_and(|h| h.totalsize as usize == TEST_DTB_SIZE)
+        && copy[..TEST_DTB_SIZE] == *original
+        && copy[TEST_DTB_SIZE..].iter().all(|&b| b == 0);
+    let reserved = reserve::reservations()
+        .iter()
+        .any(|r| r.tag == ReserveTag::Dtb && r.base == dst && r.size >= TEST_DTB_SIZE);
+    // The word after the magic is no magic
+    let rejected = unsafe { relocate_dtb(src + 4) } == src + 4;
+    let exported = match measure::region() {
+        Some((base, size)) => {
+            export_event_log(dst);
+            let chosen = |name| {
+                let blob = unsafe { fdt::blob_at(dst) }.ok()?;
+                return Fdt::new(blob).ok()?.get_u64(b"/chosen", name);
+            };
+            chosen(measure::CHOSEN_BASE_PROPERTY) == Some(base as u64)
+                && chosen(measure::CHOSEN_SIZE_PROPERTY) == Some(size as u64)
+        }
+        None => true,
+    };
+
+    reserve::discard(dst, ReserveTag::Dtb);
+
+    let kernel_start = dst + TEST_KERNEL_OFFSET;
+    let windowed = if map::is_ram_range(kernel_start, TEST_KERNEL_OFFSET) {
+        let near = place_dtb_near_kernel(original, kernel_start);
+        if let Ok(near) = near {
+            reserve::discard(near, ReserveTag::Dtb);
+        }
+        near.is_ok_and(|near| near >= kernel_start && near < kernel_start + DTB_KERNEL_WINDOW)
+    } else {
+        true
+    };
+
+    if copied && reserved && rejected && exported && windowed {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Registers the self-tests of the boot path
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"dtb",
+        run: selftest,
+    });
+}
+
+/// Prepares memory for loading the kernel and returns the DTB to hand over
+///
+/// Builds the memory map from the DTB [`board_init`](board::board_init)
+/// settled on, the firmware's or one built from the board configuration,
+/// falling back to the board's RAM ranges if it describes none, and adds
+/// the board's device ranges and the memory the DTB reserves.
+/// Reserves the bootloader and that memory, then plans the room the kernel needs with
+/// [`space::plan`]: the staged kernel ELF, its destination and the DTB. A
+/// kernel whose destination overlaps its staged ELF has the ELF moved out
+/// of the way, `kernel_elf` being updated to its new address. The staged
+/// ELF and the destination are reserved, the SMBIOS tables built, then the
+/// DTB moved out of the way
+/// with [`place_dtb_for_kernel`] and points it to the tables. If the DTB can't be moved, a
+/// warning is printed and the original address is returned; a missing one
+/// was reported by `board_init` already.
+///
+/// The boot is counted in the persistent boot record, the board is asked
+/// for full performance and the heartbeat indicator blinks slowly from here
+/// until the handoff. How the core idles is chosen from the DTB (see
+/// [`power`]). Built with the
+/// `selftest-exit` feature, the self-tests run here instead and the
+/// bootloader exits with their result.
+#[unsafe(no_mangle)]
+pub extern "C" fn prepare_boot(dtb: usize, kernel_elf: &mut usize) -> usize {
+    let (start, _) = bootloader_extents();
+    let stack_top = &raw const boot_stack as usize;
+    let mut kernel_start = 0;
+
+    exception::capture_context(b"prepare_boot");
+    board::set_boot_performance(Performance::Full);
+    heartbeat::start(Pattern::SlowBlink);
+    bootreason::init();
+
+    let tree = unsafe { fdt::blob_at(dtb) }.and_then(Fdt::new);
+    power::init(tree.as_ref().ok());
+    // Without a DTB, board_init already said so
+    if let Ok(tree) = &tree
+        && map::add_ram_from_fdt(tree).is_err()
+    {
+        log::println(Level::Warn, b"Malformed /memory node in DTB");
+    }
+    if let Ok(tree) = &tree
+        && map::add_reserved_from_fdt(tree).is_err()
+    {
+        log::println(Level::Warn, b"Memory map full, reserved memory missing");
+    }
+    if map::regions().is_empty() {
+        for &(base, size) in board::config().ram {
+            map::add_region(base, size, RegionKind::Ram);
+        }
+    }
+    if !map::add_board_devices() {
+        log::println(Level::Warn, b"Memory map full, device ranges missing");
+    }
+
+    // Everything in use before the kernel is loaded
+    let _ = reserve::reserve(start, stack_top - start, ReserveTag::Bootloader);
+    if let RecordStore::Ram { base } = board::config().bootrecord {
+        let _ = reserve::reserve(base, bootreason::RECORD_SIZE, ReserveTag::BootRecord);
+    }
+    for region in map::regions() {
+        if region.kind != RegionKind::Reserved {
+            continue;
+        }
+        if reserve::reserve(region.base, region.size, ReserveTag::Firmware).is_err() {
+            log::println(Level::Warn, b"Reserved memory overlaps memory in use");
+        }
+    }
+    let staged = unsafe { MemorySource::new(*kernel_elf, UNKNOWN_LEN) };
+    let kernel = elf::inspect_elf(&staged);
+    if let Ok((image, file_size)) = kernel {
+        let dtb_size = tree
+            .as_ref()
+            .map_or(0, |tree| dtb_region_size(tree.as_bytes().len()));
+        let demand = Demand::new(*kernel_elf, file_size)
+            .extent(image.start, image.end - image.start)
+            .dtb(dtb_size, dtb_size);
+        let _ = reserve::reserve(*kernel_elf, file_size, ReserveTag::Staging);
+        match space::plan(&demand) {
+            Ok(placement) => match space::relocate(&demand, &placement) {
+                Ok(relocated) => *kernel_elf = relocated,
+                Err(_) => log::println(Level::Warn, b"Could not move the kernel ELF"),
+            },
+            // Loading the kernel fails too, and reports why
+            Err(_) => log::println(Level::Warn, b"Not enough memory to load the kernel"),
+        }
+        if reserve::reserve(image.start, image.end - image.start, ReserveTag::Kernel).is_err() {
+            log::println(Level::Warn, b"Kernel overlaps memory in use!");
+        }
+        kernel_start = image.start;
+    }
+
+    #[cfg(feature = "console-capture")]
+    if capture::start().is_err() {
+        log::println(Level::Warn, b"No room for the console capture");
+    }
+    if measure::init().is_err() {
+        log::println(Level::Warn, b"No room for the measured boot log");
+    }
+    if smbios::install().is_err() {
+        log::println(Level::Warn, b"No room for the SMBIOS tables");
+    }
+    #[cfg(feature = "ramfb-console")]
+    if console::start(ramfb::DEFAULT_MODE).is_err() {
+        log::println(
+            Level::Warn,
+            b"No ramfb display, the console stays on serial",
+        );
+    }
+    if let Ok((_, file_size)) = kernel {
+        let image = unsafe { slice::from_raw_parts(*kernel_elf as *const u8, file_size) };
+        measure_component(b"kernel", image, *kernel_elf);
+    }
+
+    // No kernel is known when its image couldn't be loaded
+    let kernel = (kernel_start != 0).then_some(kernel_start);
+    let placed = match tree.map(|tree| place_dtb_for_kernel(tree.as_bytes(), kernel)) {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(_)) => {
+            log::println(
+                Level::Warn,
+                b"Could not relocate the DTB, leaving it in place",
+            );
+            dtb
+        }
+        Err(_) => dtb,
+    };
+
+    if placed != dtb {
+        export_smbios(placed);
+        export_event_log(placed);
+    }
+
+    unsafe {
+        FIRMWARE_DTB = dtb;
+        BOOT_DTB = placed;
+    }
+    log::stage(b"prepare_boot");
+
+    #[cfg(feature = "selftest-exit")]
+    selftest_and_exit();
+    return placed;
+}
+
+/// Returns the size of the region reserved for the relocated DTB at `dtb`,
+/// which it may grow into
+fn dtb_capacity(dtb: usize) -> Option<usize> {
+    return reserve::reservations()
+        .iter()
+        .find(|r| r.base == dtb && r.tag == ReserveTag::Dtb)
+        .map(|r| r.size);
+}
+
+/// Points `/chosen` of the relocated DTB at `dtb` to the SMBIOS tables
+///
+/// The property is added in the headroom reserved with the DTB.
+fn export_smbios(dtb: usize) {
+    let Some(entry) = smbios::entry_point() else {
+        return;
+    };
+    let Some(capacity) = dtb_capacity(dtb) else {
+        return;
+    };
+    let result =
+        unsafe { fdt::set_chosen_u64(dtb, capacity, smbios::CHOSEN_PROPERTY, entry as u64) };
+
+    if result.is_err() {
+        log::println(Level::Warn, b"Could not add the SMBIOS tables to the DTB");
+    }
+}
+
+/// Records the measured-boot event log region in `/chosen` of the
+/// relocated DTB at `dtb`, as [`measure::CHOSEN_BASE_PROPERTY`] and
+/// [`measure::CHOSEN_SIZE_PROPERTY`]
+///
+/// The properties are added in the headroom reserved with the DTB.
+fn export_event_log(dtb: usize) {
+    let Some((base, size)) = measure::region() else {
+        return;
+    };
+    let Some(capacity) = dtb_capacity(dtb) else {
+        return;
+    };
+    let result = unsafe {
+        fdt::set_chosen_u64(dtb, capacity, measure::CHOSEN_BASE_PROPERTY, base as u64).and_then(
+            |_| fdt::set_chosen_u64(dtb, capacity, measure::CHOSEN_SIZE_PROPERTY, size as u64),
+        )
+    };
+
+    if result.is_err() {
+        log::println(Level::Warn, b"Could not add the event log to the DTB");
+    }
+}
+
+/// Runs the self-tests and exits through semihosting with their status
+#[cfg(feature = "selftest-exit")]
+fn selftest_and_exit() {
+    selftest::register_builtin();
+    semihosting::exit(selftest::run_all().status());
+}
+
+/// Measures a component loaded from `addr`, warning if it can't be logged
+fn measure_component(name: &[u8], data: &[u8], addr: usize) {
+    let mut desc = [0u8; 24];
+    let mut hex = [0u8; 16];
+    let digits = u64_to_hex(addr as u64, &mut hex);
+
+    desc[..6].copy_from_slice(b"ram@0x");
+    desc[6..6 + digits.len()].copy_from_slice(digits);
+    if measure::measure(name, data, &desc[..6 + digits.len()]).is_err() {
+        log::print(Level::Warn, b"Could not measure ");
+        log::println(Level::Warn, name);
+    }
+}
+
+/// Last bootloader step before jumping to the kernel
+///
+/// Called by the assembly entry code once the kernel is loaded. Measures the
+/// final DTB handed to the kernel and prints the measured-boot log so the
+/// console records what is about to run, along with the warnings suppressed
+/// by rate limiting. If a serial break was received, or a key interrupts
+/// the `bootdelay` countdown (see [`monitor::countdown`]), the monitor is
+/// entered first; otherwise the `bootcmd` environment variable,
+/// if set, runs with [`monitor::autoboot`], and the monitor is entered if it
+/// doesn't end with `boot`. Memory poisoned since it was freed is checked
+/// for stray writes in debug builds (see [`poison`]). Then pointer
+/// authentication and BTI are set up for the payload, and with the
+/// `psci-responder` feature its PSCI calls are trapped (see the `psci`
+/// module) if it runs at EL1. [`enter_payload`] then jumps to it.
+#[unsafe(no_mangle)]
+pub extern "C" fn before_handoff() {
+    let (dtb, source) = unsafe { (BOOT_DTB, FIRMWARE_DTB) };
+
+    #[cfg(not(feature = "stage1"))]
+    if pl011::break_received() || monitor::countdown() || monitor::autoboot() == Some(false) {
+        monitor::enter(None);
+    }
+
+    if let Ok(blob) = unsafe { fdt::blob_at(dtb) } {
+        measure_component(b"dtb", blob, source);
+    }
+    measure::report();
+    log::report_suppressed();
+    if poison::ENABLED && poison::scan() != 0 {
+        log::println(Level::Warn, b"Freed memory was written after it was freed!");
+    }
+    pauth::prepare_handoff();
+    #[cfg(feature = "psci-responder")]
+    if handoff_mode() == HandoffMode::El1 {
+        psci::install_psci_trap();
+    }
+    log::stage(b"before_handoff");
+}
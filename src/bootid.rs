@@ -0,0 +1,64 @@
+//! Per-boot unique ID
+//!
+//! Logs collected from a fleet of boards are hard to attribute once
+//! interleaved. Every boot gets a random 64-bit ID, from the
+//! [`rng`](crate::utilities::rng) when the core has one and from the counter
+//! otherwise, generated by [`init`] before anything is printed. It is shown
+//! in the boot banner and kept in the boot record, the measured-boot log
+//! header and the [`BootInfo`](crate::boot::BootInfo) block, so a crash
+//! record, a log or a payload can be matched with the console output of the
+//! same boot.
+//!
+//! Built with the `bootid-prefix` feature, every console line starts with
+//! the low 16 bits of the ID, e.g., `[3f2a] `, early output included.
+
+use crate::drivers::uart::pl011;
+use crate::utilities::print::u64_to_hex_padded;
+use crate::utilities::rng;
+
+/// Digits of the hex form of an ID
+const ID_DIGITS: usize = 16;
+
+/// ID of the current boot, 0 until [`init`]
+static mut BOOT_ID: u64 = 0;
+
+/// Generates the ID of the current boot
+///
+/// Called by the board setup before anything is printed. Calling it again
+/// keeps the ID.
+pub fn init() {
+    if id() != 0 {
+        return;
+    }
+    // 0 means no ID, so it is never handed out
+    let id = match rng::random_u64().unwrap_or_else(rng::counter_entropy) {
+        0 => 1,
+        id => id,
+    };
+
+    unsafe {
+        BOOT_ID = id;
+    }
+    #[cfg(feature = "bootid-prefix")]
+    pl011::set_id_prefix(Some(id as u16));
+}
+
+/// Returns the ID of the current boot, 0 before [`init`]
+pub fn id() -> u64 {
+    unsafe {
+        return BOOT_ID;
+    }
+}
+
+/// Formats `id` as 16 lowercase hex digits
+pub fn format(id: u64) -> [u8; ID_DIGITS] {
+    let mut buf = [0u8; ID_DIGITS];
+
+    u64_to_hex_padded(id, &mut buf);
+    return buf;
+}
+
+/// Prints the ID of the current boot
+pub fn print() {
+    pl011::print(&format(id()));
+}
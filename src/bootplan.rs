@@ -0,0 +1,839 @@
+//! Boot sources and boot plans
+//!
+//! The images the bootloader hands over can come from several transports.
+//! Each transport implements [`BootSource`], which hands out a component
+//! ([`Component`]) as a [`SourceStream`]: a blob in memory, since every
+//! loader here works on memory.
+//!
+//! A [`BootPlan`] pairs each component with the source it comes from and
+//! runs the whole sequence through the same pipeline: open the component,
+//! pass it to the loader for its kind and log the outcome. Adding a transport
+//! only means adding a source; the loaders don't change.
+//!
+//! # Timeouts and aborting
+//!
+//! Each component can be given a time limit, with [`BootPlan::set_timeout`]
+//! or by a `timeout_<component>=<seconds>` line (e.g., `timeout_kernel=30`)
+//! in a configuration file loaded earlier by the same plan. Sources and
+//! loaders receive the resulting [`Deadline`] and check it, and `Ctrl-C`, at
+//! every chunk or block, so a stalled transfer fails the step with a
+//! timeout or aborted error instead of hanging. Memory reserved for a step
+//! that fails is released, so nothing half-written stays registered.
+//!
+//! # Retries
+//!
+//! A step failing with a [transient](BootError::is_transient) error (e.g., a
+//! timeout) is tried again, after a delay, up to [`DEFAULT_RETRIES`] more
+//! times unless [`BootPlan::set_retries`] or the `retries=<count>` and
+//! `retry_delay=<seconds>` configuration lines say otherwise. A permanent
+//! error fails the plan at once. When the plan fails, the attempts made at
+//! every step are summed up on the console; the caller then falls back to
+//! the monitor.
+//!
+//! # Verifying
+//!
+//! For catching bad RAM or a faulty copy during bring-up, a plan can read
+//! the kernel image again once loaded and compare it with the copy, at the
+//! cost of a second pass, with [`BootPlan::set_verify`] or a `verify=1`
+//! configuration line; the staged kernel is verified when the `verify`
+//! environment variable is `1`. The address of the first byte that differs is
+//! printed with the error.
+//!
+//! # Sources
+//!
+//! - [`RamSource`]: components already in memory, e.g. the kernel staged
+//!   after the bootloader or the DTB passed by the firmware
+//! - [`XmodemSource`]: a component uploaded over the console
+//! - [`CachedSource`]: another source, whose component is kept across warm
+//!   resets by the [warm cache](crate::warmcache) and not transferred again
+//!   while it's intact
+//!
+//! # Encrypted components
+//!
+//! A component starting with an [`EncryptedHeader`] is decrypted before it
+//! reaches its loader. The header is followed by the AES-256-GCM ciphertext;
+//! the tag covers the header up to the tag as well. The key comes from
+//! [`board::payload_key`]. The plaintext is written to freshly allocated
+//! memory only once the tag checks out, so a wrong key or a tampered image
+//! fails the boot instead of being loaded.
+
+use crate::board;
+use crate::boot;
+use crate::cpu;
+use crate::drivers::timer::generic::{self, Deadline};
+use crate::drivers::uart::pl011;
+use crate::error::{self, BootError};
+use crate::log::{self, Level};
+use crate::memory::map;
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+use crate::memory::space::{self, Demand};
+use crate::parsers::elf::{self, ElfError, LoadedImage};
+use crate::parsers::fdt;
+use crate::parsers::image::{MemorySource, UNKNOWN_LEN};
+use crate::serial::xmodem;
+use crate::utilities::aes::{self, Aes256, NONCE_SIZE, TAG_SIZE};
+use crate::utilities::print;
+use crate::warmcache;
+
+use core::mem::{self, offset_of};
+use core::ptr;
+use core::slice;
+
+/// Maximum number of steps in a boot plan
+pub const MAX_STEPS: usize = 4;
+/// Encrypted component magic number: the ASCII string "ENCIMAGE" read as a
+/// little-endian u64
+pub const ENCRYPTED_MAGIC: u64 = 0x4547414d49434e45;
+/// Current version of the encrypted component format
+pub const ENCRYPTED_VERSION: u32 = 1;
+/// Alignment of decrypted components
+const DECRYPT_ALIGN: usize = 4096;
+/// Prefix of the configuration keys setting the timeout of a component
+const TIMEOUT_KEY: &[u8] = b"timeout_";
+/// Configuration key setting the number of retries
+const RETRIES_KEY: &[u8] = b"retries";
+/// Configuration key setting the delay before a retry
+const RETRY_DELAY_KEY: &[u8] = b"retry_delay";
+/// Configuration key turning verifying the loaded kernel on (1) or off (0)
+const VERIFY_KEY: &[u8] = b"verify";
+/// Number of retries of a step failing with a transient error, by default
+pub const DEFAULT_RETRIES: u32 = 2;
+/// Delay before a retry in milliseconds, by default
+pub const DEFAULT_RETRY_DELAY_MS: u64 = 1000;
+
+/// A component of a boot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Component {
+    /// The kernel ELF image
+    Kernel,
+    /// The device tree blob
+    Dtb,
+    /// The initial ramdisk
+    Initrd,
+    /// A boot configuration file
+    Config,
+}
+
+impl Component {
+    /// Every component, in declaration order
+    pub const ALL: [Component; 4] = [
+        Component::Kernel,
+        Component::Dtb,
+        Component::Initrd,
+        Component::Config,
+    ];
+
+    /// Returns the name of the component
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            Component::Kernel => b"kernel",
+            Component::Dtb => b"dtb",
+            Component::Initrd => b"initrd",
+            Component::Config => b"config",
+        };
+    }
+}
+
+/// Errors reported by boot sources
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceError {
+    /// The source doesn't provide the component
+    Unavailable,
+    /// The component's size is needed but unknown
+    UnknownSize,
+    /// The plan has no room for another step
+    PlanFull,
+    /// The step didn't finish within its timeout
+    Timeout,
+    /// The step was aborted with `Ctrl-C`
+    Aborted,
+}
+
+/// Errors reported while decrypting a component
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecryptError {
+    /// The board has no payload key
+    NoKey,
+    /// The header has an unsupported version or size
+    BadHeader,
+    /// The tag doesn't match: wrong key, or corrupted or tampered image
+    AuthFailed,
+    /// No memory could be reserved for the plaintext
+    NoSpace(ReserveError),
+}
+
+/// Header of an encrypted component
+///
+/// All values are little-endian. The ciphertext starts `header_size` bytes
+/// after the start of the header.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EncryptedHeader {
+    /// Always [`ENCRYPTED_MAGIC`]
+    pub magic: u64,
+    /// Format version, currently [`ENCRYPTED_VERSION`]
+    pub version: u32,
+    /// Size of this header in bytes
+    pub header_size: u32,
+    /// Size of the ciphertext (and the plaintext) in bytes
+    pub payload_len: u64,
+    /// GCM nonce
+    pub nonce: [u8; NONCE_SIZE],
+    /// Reserved for future use, always 0
+    pub reserved: u32,
+    /// GCM tag of the ciphertext, with the header up to here as additional
+    /// authenticated data
+    pub tag: [u8; TAG_SIZE],
+}
+
+const _: () = assert!(mem::size_of::<EncryptedHeader>() == 56);
+const _: () = assert!(offset_of!(EncryptedHeader, nonce) == 24);
+const _: () = assert!(offset_of!(EncryptedHeader, tag) == 40);
+
+/// A component opened from a source
+#[derive(Clone, Copy, Debug)]
+pub struct SourceStream {
+    /// Address of the blob in memory
+    pub base: usize,
+    /// Size of the blob in bytes, or `None` if only its own header tells
+    pub len: Option<usize>,
+}
+
+/// A transport the components of a boot can come from
+pub trait BootSource {
+    /// Returns the name of the source, used in log messages
+    fn name(&self) -> &'static [u8];
+
+    /// Makes `component` available in memory
+    ///
+    /// A source that has to wait for its transport gives up at `deadline`.
+    fn open(&mut self, component: Component, deadline: Deadline)
+    -> Result<SourceStream, BootError>;
+}
+
+/// Components already in memory
+///
+/// Each component is given by its address and, if known, its size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RamSource {
+    /// Kernel, DTB, initrd and config blobs, in [`Component`] order
+    blobs: [Option<SourceStream>; 4],
+}
+
+impl RamSource {
+    /// Creates a source providing no component
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Provides `component` from `base`, `len` bytes long if known
+    pub fn with(mut self, component: Component, base: usize, len: Option<usize>) -> Self {
+        self.blobs[component as usize] = Some(SourceStream { base, len });
+        return self;
+    }
+}
+
+impl BootSource for RamSource {
+    fn name(&self) -> &'static [u8] {
+        return b"ram";
+    }
+
+    fn open(
+        &mut self,
+        component: Component,
+        _deadline: Deadline,
+    ) -> Result<SourceStream, BootError> {
+        return self.blobs[component as usize].ok_or(SourceError::Unavailable.into());
+    }
+}
+
+/// A component uploaded over the console with XMODEM
+///
+/// The upload is received into `max_len` bytes at `dst` when the component
+/// is opened, so each source provides a single component.
+#[derive(Clone, Copy, Debug)]
+pub struct XmodemSource {
+    /// Where the upload is received
+    dst: usize,
+    /// Size of the receive buffer in bytes
+    max_len: usize,
+}
+
+impl XmodemSource {
+    /// Creates a source receiving into `max_len` bytes at `dst`
+    pub fn new(dst: usize, max_len: usize) -> Self {
+        return Self { dst, max_len };
+    }
+}
+
+impl BootSource for XmodemSource {
+    fn name(&self) -> &'static [u8] {
+        return b"xmodem";
+    }
+
+    fn open(
+        &mut self,
+        _component: Component,
+        deadline: Deadline,
+    ) -> Result<SourceStream, BootError> {
+        let dst = unsafe { core::slice::from_raw_parts_mut(self.dst as *mut u8, self.max_len) };
+
+        pl011::println(b"Waiting for XMODEM transfer...");
+        let len = xmodem::receive(dst, deadline)?;
+        return Ok(SourceStream {
+            base: self.dst,
+            len: Some(len),
+        });
+    }
+}
+
+/// A source whose component is kept in the [warm cache](warmcache)
+///
+/// Opening the component looks for an intact image cached from
+/// `description` first, e.g. one uploaded before a warm reset, and only
+/// opens `inner` without one. What `inner` provides replaces the cached
+/// image, if its size is known.
+pub struct CachedSource<'a> {
+    /// Source of the component when it isn't cached
+    inner: &'a mut dyn BootSource,
+    /// Where the component comes from, compared with the cached image's
+    description: &'a [u8],
+}
+
+impl<'a> CachedSource<'a> {
+    /// Creates a source caching the component of `inner`, which comes from
+    /// `description`
+    pub fn new(inner: &'a mut dyn BootSource, description: &'a [u8]) -> Self {
+        return Self { inner, description };
+    }
+}
+
+impl BootSource for CachedSource<'_> {
+    fn name(&self) -> &'static [u8] {
+        return self.inner.name();
+    }
+
+    fn open(
+        &mut self,
+        component: Component,
+        deadline: Deadline,
+    ) -> Result<SourceStream, BootError> {
+        if let Some(image) = warmcache::lookup(self.description) {
+            log::print(Level::Info, b"Using the image cached from ");
+            log::println(Level::Info, self.description);
+            return Ok(SourceStream {
+                base: image.base,
+                len: Some(image.len),
+            });
+        }
+
+        let stream = self.inner.open(component, deadline)?;
+        if let Some(len) = stream.len {
+            warmcache::record(self.description, stream.base, len);
+        }
+        return Ok(stream);
+    }
+}
+
+/// What a boot plan loaded
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Loaded {
+    /// The loaded kernel
+    pub kernel: Option<LoadedImage>,
+    /// Address of the relocated DTB
+    pub dtb: Option<usize>,
+    /// Address and size of the reserved initrd
+    pub initrd: Option<(usize, usize)>,
+    /// Address and size of the configuration file
+    pub config: Option<(usize, usize)>,
+}
+
+/// The sources of the components of a boot, in loading order
+pub struct BootPlan<'a> {
+    steps: [Option<(Component, &'a mut dyn BootSource)>; MAX_STEPS],
+    count: usize,
+    /// Time limit of each component in milliseconds, in [`Component`] order
+    timeouts: [Option<u64>; 4],
+    /// Number of retries of a step failing with a transient error
+    retries: u32,
+    /// Delay before a retry in milliseconds
+    retry_delay_ms: u64,
+    /// Whether the loaded kernel is read back and compared with its image
+    verify: bool,
+    /// Number of attempts made at each step
+    attempts: [u32; MAX_STEPS],
+    /// Error of the last attempt at each step
+    errors: [Option<BootError>; MAX_STEPS],
+}
+
+impl<'a> BootPlan<'a> {
+    /// Creates an empty plan
+    pub fn new() -> Self {
+        return Self {
+            steps: [const { None }; MAX_STEPS],
+            count: 0,
+            timeouts: [None; 4],
+            retries: DEFAULT_RETRIES,
+            retry_delay_ms: DEFAULT_RETRY_DELAY_MS,
+            verify: false,
+            attempts: [0; MAX_STEPS],
+            errors: [None; MAX_STEPS],
+        };
+    }
+
+    /// Limits the step loading `component` to `ms` milliseconds
+    ///
+    /// `None` removes the limit.
+    pub fn set_timeout(&mut self, component: Component, ms: Option<u64>) {
+        self.timeouts[component as usize] = ms;
+    }
+
+    /// Retries a step failing with a transient error `retries` times, `delay_ms`
+    /// milliseconds after each failure
+    pub fn set_retries(&mut self, retries: u32, delay_ms: u64) {
+        self.retries = retries;
+        self.retry_delay_ms = delay_ms;
+    }
+
+    /// Reads the loaded kernel back and compares it with its image, see
+    /// the [module documentation](self#verifying)
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Applies the `timeout_<component>=<seconds>`, `retries=<count>`,
+    /// `retry_delay=<seconds>` and `verify=<0|1>` lines of `config`
+    ///
+    /// Other lines, unknown components and malformed values are ignored.
+    pub fn apply_config(&mut self, config: &[u8]) {
+        for line in config.split(|&c| c == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let Some(eq) = line.iter().position(|&c| c == b'=') else {
+                continue;
+            };
+            let (key, value) = (&line[..eq], &line[eq + 1..]);
+            let Some(value) = parse_decimal(value) else {
+                continue;
+            };
+            if key == RETRIES_KEY {
+                self.retries = value.min(u32::MAX as u64) as u32;
+            } else if key == RETRY_DELAY_KEY {
+                self.retry_delay_ms = value.saturating_mul(1000);
+            } else if key == VERIFY_KEY {
+                self.verify = value != 0;
+            } else if let Some(name) = key.strip_prefix(TIMEOUT_KEY)
+                && let Some(component) = Component::ALL.into_iter().find(|c| c.name() == name)
+            {
+                self.set_timeout(component, Some(value.saturating_mul(1000)));
+            }
+        }
+    }
+
+    /// Appends a step loading `component` from `source`
+    pub fn add(
+        &mut self,
+        component: Component,
+        source: &'a mut dyn BootSource,
+    ) -> Result<(), BootError> {
+        if self.count == MAX_STEPS {
+            return Err(SourceError::PlanFull.into());
+        }
+        self.steps[self.count] = Some((component, source));
+        self.count += 1;
+        return Ok(());
+    }
+
+    /// Runs every step in order
+    ///
+    /// Each step is logged as `Loading <component> from <source>`, and each
+    /// failure is printed with [`error::print_error`]. A step failing with a
+    /// transient error is retried as described in the
+    /// [module documentation](self#retries); once a step fails for good, the
+    /// attempts are summed up and the error is returned, the remaining steps
+    /// being skipped. A configuration file loaded by a step is applied with
+    /// [`apply_config`](Self::apply_config) to the following ones.
+    pub fn execute(&mut self) -> Result<Loaded, BootError> {
+        let mut loaded = Loaded::default();
+
+        for i in 0..self.count {
+            let Some((component, source)) = &mut self.steps[i] else {
+                continue;
+            };
+            let component = *component;
+            log::print(Level::Info, b"Loading ");
+            log::print(Level::Info, component.name());
+            log::print(Level::Info, b" from ");
+            log::println(Level::Info, source.name());
+
+            let result = loop {
+                let deadline = match self.timeouts[component as usize] {
+                    Some(ms) => Deadline::after_ms(ms),
+                    None => Deadline::NEVER,
+                };
+                self.attempts[i] += 1;
+                let result = source.open(component, deadline).and_then(|stream| {
+                    run_step(component, stream, deadline, self.verify, &mut loaded)
+                });
+                let Err(e) = result else {
+                    break result;
+                };
+                self.errors[i] = Some(e);
+                error::print_error(&e);
+                if !should_retry(&e, self.attempts[i], self.retries) {
+                    break result;
+                }
+                log::print(Level::Warn, b"Retrying ");
+                log::print(Level::Warn, component.name());
+                log::print(Level::Warn, b" in ");
+                log::print(
+                    Level::Warn,
+                    print::u64_to_dec(self.retry_delay_ms, &mut [0u8; 20]),
+                );
+                log::println(Level::Warn, b" ms");
+                generic::delay_ms(self.retry_delay_ms);
+            };
+            if let Err(e) = result {
+                self.print_summary(i);
+                return Err(e);
+            }
+            if let (Component::Config, Some((base, len))) = (component, loaded.config) {
+                self.apply_config(unsafe { slice::from_raw_parts(base as *const u8, len) });
+            }
+        }
+        return Ok(loaded);
+    }
+}
+
+impl BootPlan<'_> {
+    /// Sums up the attempts made at the steps up to `failed`, which failed
+    fn print_summary(&self, failed: usize) {
+        let mut dec = [0u8; 20];
+
+        log::println(Level::Error, b"Boot attempts:");
+        for (i, step) in self.steps[..=failed].iter().enumerate() {
+            let Some((component, source)) = step else {
+                continue;
+            };
+            log::print(Level::Error, b"  ");
+            log::print(Level::Error, component.name());
+            log::print(Level::Error, b" from ");
+            log::print(Level::Error, source.name());
+            log::print(Level::Error, b": ");
+            log::print(
+                Level::Error,
+                print::u64_to_dec(self.attempts[i] as u64, &mut dec),
+            );
+            match self.attempts[i] {
+                1 => log::print(Level::Error, b" attempt, "),
+                _ => log::print(Level::Error, b" attempts, "),
+            }
+            match (i == failed, self.errors[i]) {
+                (true, Some(e)) => {
+                    log::print(Level::Error, b"failed with ");
+                    error::print_code(e.code());
+                    match e.is_transient() {
+                        true => log::println(Level::Error, b" (transient)"),
+                        false => log::println(Level::Error, b" (permanent)"),
+                    }
+                }
+                _ => log::println(Level::Error, b"loaded"),
+            }
+        }
+    }
+}
+
+impl Default for BootPlan<'_> {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Decrypts and loads `stream` as `component`, giving up at `deadline` and
+/// verifying a kernel if `verify` is set
+///
+/// The plaintext of an encrypted component is discarded if the step fails,
+/// and once loaded if it's a kernel or a DTB, whose loaders copy it.
+fn run_step(
+    component: Component,
+    stream: SourceStream,
+    deadline: Deadline,
+    verify: bool,
+    loaded: &mut Loaded,
+) -> Result<(), BootError> {
+    check_interrupted(deadline)?;
+    let plain = decrypt(stream)?;
+    let result =
+        check_interrupted(deadline).and_then(|_| load(component, plain, deadline, verify, loaded));
+
+    let copied = matches!(component, Component::Kernel | Component::Dtb);
+    if plain.base != stream.base && (result.is_err() || copied) {
+        reserve::discard(plain.base, ReserveTag::Staging);
+    }
+    return result;
+}
+
+/// Checks whether a step failing with `error` after `attempts` attempts
+/// gets another one, `retries` being allowed
+const fn should_retry(error: &BootError, attempts: u32, retries: u32) -> bool {
+    return error.is_transient() && attempts <= retries;
+}
+
+const TIMEOUT: BootError = BootError::Source(SourceError::Timeout);
+const _: () = assert!(should_retry(&TIMEOUT, 1, 2));
+const _: () = assert!(should_retry(&TIMEOUT, 2, 2));
+const _: () = assert!(!should_retry(&TIMEOUT, 3, 2));
+const _: () = assert!(!should_retry(&TIMEOUT, 1, 0));
+const _: () = assert!(!should_retry(
+    &BootError::Source(SourceError::Unavailable),
+    1,
+    2
+));
+
+/// Fails if `deadline` has passed or `Ctrl-C` was typed
+fn check_interrupted(deadline: Deadline) -> Result<(), BootError> {
+    if deadline.expired() {
+        return Err(SourceError::Timeout.into());
+    }
+    if pl011::ctrlc() {
+        return Err(SourceError::Aborted.into());
+    }
+    return Ok(());
+}
+
+/// Parses a decimal number
+fn parse_decimal(s: &[u8]) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    return s.iter().try_fold(0u64, |value, &c| {
+        let digit = (c as char).to_digit(10)?;
+        return value.checked_mul(10)?.checked_add(digit as u64);
+    });
+}
+
+/// Reads the header of `stream` if it is an encrypted component
+///
+/// Returns `None` if `stream` is too short for a header or doesn't start with
+/// [`ENCRYPTED_MAGIC`]. The header is checked to describe a ciphertext inside
+/// `stream`, or inside RAM if the length of `stream` isn't known.
+fn encrypted_header(stream: &SourceStream) -> Result<Option<EncryptedHeader>, DecryptError> {
+    if stream
+        .len
+        .is_some_and(|total| total < mem::size_of::<EncryptedHeader>())
+    {
+        return Ok(None);
+    }
+    // Sources don't promise any alignment
+    let header = unsafe { ptr::read_unaligned(stream.base as *const EncryptedHeader) };
+
+    if header.magic != ENCRYPTED_MAGIC {
+        return Ok(None);
+    }
+    let header_size = header.header_size as usize;
+    let size = usize::try_from(header.payload_len)
+        .ok()
+        .and_then(|len| header_size.checked_add(len));
+    let fits = match (size, stream.len) {
+        (None, _) => false,
+        (Some(size), Some(total)) => size <= total,
+        (Some(size), None) => map::is_ram_range(stream.base, size),
+    };
+    if header.version != ENCRYPTED_VERSION
+        || header_size < mem::size_of::<EncryptedHeader>()
+        || !fits
+    {
+        return Err(DecryptError::BadHeader);
+    }
+    return Ok(Some(header));
+}
+
+/// Decrypts `stream` if it is an encrypted component
+///
+/// Returns the plaintext, in memory reserved for [`ReserveTag::Staging`], or
+/// `stream` itself if it isn't encrypted.
+fn decrypt(stream: SourceStream) -> Result<SourceStream, BootError> {
+    let Some(header) = encrypted_header(&stream)? else {
+        return Ok(stream);
+    };
+    let header_size = header.header_size as usize;
+    let len = header.payload_len as usize;
+    let key = board::payload_key().ok_or(DecryptError::NoKey)?;
+
+    log::println(Level::Debug, b"Decrypting...");
+    // Keep the ciphertext out of the search so the plaintext can't overlap it
+    let src_reserved =
+        reserve::reserve(stream.base, header_size + len, ReserveTag::Staging).is_ok();
+    let dst = reserve::allocate(len, DECRYPT_ALIGN, None, ReserveTag::Staging);
+    if src_reserved {
+        reserve::release(stream.base, ReserveTag::Staging);
+    }
+    let dst = dst.map_err(DecryptError::NoSpace)?;
+
+    let cipher = Aes256::new(&key, cpu::has_aes());
+    let result = unsafe {
+        let aad = slice::from_raw_parts(stream.base as *const u8, offset_of!(EncryptedHeader, tag));
+        let ciphertext = slice::from_raw_parts((stream.base + header_size) as *const u8, len);
+        let plaintext = slice::from_raw_parts_mut(dst as *mut u8, len);
+        aes::gcm_decrypt(
+            &cipher,
+            &header.nonce,
+            aad,
+            ciphertext,
+            &header.tag,
+            plaintext,
+        )
+    };
+    if result.is_err() {
+        reserve::discard(dst, ReserveTag::Staging);
+        return Err(DecryptError::AuthFailed.into());
+    }
+
+    return Ok(SourceStream {
+        base: dst,
+        len: Some(len),
+    });
+}
+
+/// Passes `stream` to the loader for `component`, recording it in `loaded`
+///
+/// A kernel is only loaded once [`space::plan`] found room for it; if not,
+/// what it needs and what's free is printed with [`space::print_report`].
+/// With `verify`, it's read back once loaded, the first byte that differs
+/// being printed.
+fn load(
+    component: Component,
+    stream: SourceStream,
+    deadline: Deadline,
+    verify: bool,
+    loaded: &mut Loaded,
+) -> Result<(), BootError> {
+    match component {
+        Component::Kernel => {
+            let src = unsafe { MemorySource::new(stream.base, stream.len.unwrap_or(UNKNOWN_LEN)) };
+            let (image, file_size) = elf::inspect_elf(&src)?;
+            let demand =
+                Demand::new(stream.base, file_size).extent(image.start, image.end - image.start);
+            let placement = space::plan(&demand).inspect_err(|_| space::print_report(&demand))?;
+            // A kernel loaded over its own staging is moved out of the way first
+            let staged = space::relocate(&demand, &placement)?;
+            let src = unsafe { MemorySource::new(staged, file_size) };
+            let result = elf::load_elf(&src, deadline, Some(print::progress), verify);
+            if staged != stream.base {
+                reserve::discard(staged, ReserveTag::Staging);
+            }
+            if let Err(ElfError::VerifyMismatch(addr)) = result {
+                log::print(Level::Error, b"Loaded kernel differs from its image at 0x");
+                log::println(Level::Error, print::u64_to_hex(addr as u64, &mut [0u8; 16]));
+            }
+            loaded.kernel = Some(result?);
+        }
+        Component::Dtb => {
+            let blob = unsafe { fdt::blob_at(stream.base) }?;
+            let kernel_start = loaded.kernel.map(|kernel| kernel.start);
+            loaded.dtb = Some(boot::place_dtb_for_kernel(blob, kernel_start)?);
+        }
+        Component::Initrd => {
+            let len = stream.len.ok_or(SourceError::UnknownSize)?;
+            // A decrypted initrd is already reserved for staging; hand it over
+            reserve::release(stream.base, ReserveTag::Staging);
+            reserve::reserve(stream.base, len, ReserveTag::Initrd)?;
+            loaded.initrd = Some((stream.base, len));
+        }
+        Component::Config => {
+            let len = stream.len.ok_or(SourceError::UnknownSize)?;
+            loaded.config = Some((stream.base, len));
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Builds an encrypted component with a `payload_len` byte ciphertext,
+    /// one byte into the returned buffer so its header is unaligned
+    fn component(payload_len: u64, ciphertext: usize) -> Vec<u8> {
+        let mut blob = vec![0u8; 1 + mem::size_of::<EncryptedHeader>() + ciphertext];
+        let header = &mut blob[1..];
+        header[0..8].copy_from_slice(&ENCRYPTED_MAGIC.to_le_bytes());
+        header[8..12].copy_from_slice(&ENCRYPTED_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(mem::size_of::<EncryptedHeader>() as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&payload_len.to_le_bytes());
+        return blob;
+    }
+
+    /// Reads the header of the component built by [`component`]
+    fn header_of(blob: &[u8], len: Option<usize>) -> Result<Option<EncryptedHeader>, DecryptError> {
+        let stream = SourceStream {
+            base: blob[1..].as_ptr() as usize,
+            len,
+        };
+        return encrypted_header(&stream);
+    }
+
+    #[test]
+    fn unaligned_header() {
+        let blob = component(16, 16);
+        let header = header_of(&blob, Some(blob.len() - 1)).unwrap().unwrap();
+        assert_eq!(header.payload_len, 16);
+        assert_eq!(
+            header.header_size as usize,
+            mem::size_of::<EncryptedHeader>()
+        );
+    }
+
+    #[test]
+    fn short_stream_is_plain() {
+        let blob = component(0, 0);
+        let short = mem::size_of::<EncryptedHeader>() - 1;
+        assert_eq!(
+            header_of(&blob, Some(short)).map(|h| h.is_some()),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn other_magic_is_plain() {
+        let mut blob = component(16, 16);
+        blob[1] ^= 1;
+        assert_eq!(
+            header_of(&blob, Some(blob.len() - 1)).map(|h| h.is_some()),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn truncated_ciphertext() {
+        let blob = component(17, 16);
+        assert_eq!(
+            header_of(&blob, Some(blob.len() - 1)).map(|h| h.is_some()),
+            Err(DecryptError::BadHeader)
+        );
+    }
+
+    #[test]
+    fn overflowing_length() {
+        let blob = component(u64::MAX - 8, 0);
+        assert_eq!(
+            header_of(&blob, Some(blob.len() - 1)).map(|h| h.is_some()),
+            Err(DecryptError::BadHeader)
+        );
+        assert_eq!(
+            header_of(&blob, None).map(|h| h.is_some()),
+            Err(DecryptError::BadHeader)
+        );
+    }
+
+    #[test]
+    fn unknown_length_outside_ram() {
+        // Host memory is outside the board's RAM
+        let blob = component(16, 16);
+        assert_eq!(
+            header_of(&blob, None).map(|h| h.is_some()),
+            Err(DecryptError::BadHeader)
+        );
+    }
+}
@@ -0,0 +1,407 @@
+//! Boot counter and boot-reason tracking
+//!
+//! This module keeps a small record across resets: how many times the board
+//! has booted, how the previous boot ended, whether a watchdog fired and
+//! where the last panic happened. It is kept wherever the board
+//! configuration says ([`RecordStore`]): an erase block of the flash or RAM
+//! that survives resets. Boards without persistent storage start over on
+//! every boot.
+//!
+//! [`init`] reads the record early in the boot flow, works out why the
+//! previous boot ended, prints it (e.g., `Boot #1234, last reset: watchdog`)
+//! and marks the current boot as in progress. The boot is then finalized by
+//! [`mark_boot_successful`], [`record_panic`] or [`record_watchdog`]; a boot
+//! that never gets there is reported as interrupted next time. A panic
+//! caused by a fatal exception also keeps the exception's syndrome and
+//! address, see [`record_exception`]. Each record carries the
+//! [boot ID](crate::bootid) of the boot that wrote it, so a crash reported
+//! by the next boot can be matched with the console output of the crashed
+//! one.
+//!
+//! # Record format
+//!
+//! The record is a [`RecordHeader`] followed by `size` bytes of
+//! [`BootRecord`], little-endian. The header carries a CRC-32 of the payload;
+//! a record with a bad magic or checksum is discarded with a warning and
+//! counting starts over from zero. Newer versions of the format may only
+//! append fields, so a record written by a newer bootloader is still read,
+//! and its extra fields are dropped when it is written back.
+
+use crate::board::{self, RecordStore};
+use crate::bootid;
+use crate::cpu;
+use crate::drivers::flash::cfi;
+use crate::drivers::rtc;
+use crate::drivers::uart::pl011;
+use crate::exception;
+use crate::log;
+use crate::utilities::crc32::crc32;
+use crate::utilities::print::{print_hex_u8, u64_to_dec, u64_to_hex};
+
+use core::mem::{self, size_of};
+use core::{ptr, slice};
+
+/// Record magic number: the ASCII string "BREC" read as a little-endian u32
+pub const RECORD_MAGIC: u32 = 0x43455242;
+/// Current version of the record format
+pub const RECORD_VERSION: u16 = 3;
+/// Size of the whole record (header and payload) in bytes
+pub const RECORD_SIZE: usize = size_of::<RecordHeader>() + size_of::<BootRecord>();
+/// Largest payload accepted from a newer record format
+const MAX_PAYLOAD_SIZE: usize = 1024;
+/// Maximum length of the recorded panic file name
+const PANIC_FILE_SIZE: usize = 48;
+
+/// Header of the stored record
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct RecordHeader {
+    /// Always [`RECORD_MAGIC`]
+    pub magic: u32,
+    /// Format version of the writer, currently [`RECORD_VERSION`]
+    pub version: u16,
+    /// Size of the payload following the header in bytes
+    pub size: u16,
+    /// CRC-32 of the payload
+    pub crc: u32,
+}
+
+/// How a boot ended, as stored in the record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BootStatus {
+    /// No boot recorded yet
+    Unknown = 0,
+    /// The boot started and hasn't finished
+    InProgress = 1,
+    /// The payload was started
+    Succeeded = 2,
+    /// The bootloader panicked
+    Panicked = 3,
+}
+
+/// Payload of the stored record
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct BootRecord {
+    /// Number of boots, including the current one
+    pub boot_count: u32,
+    /// A [`BootStatus`] value
+    pub status: u8,
+    /// Non-zero if a watchdog fired during the boot
+    pub watchdog_fired: u8,
+    /// Reserved for future use, always 0
+    pub reserved: u16,
+    /// Line of the last panic
+    pub panic_line: u32,
+    /// File of the last panic, NUL-padded (truncated from the start if too
+    /// long)
+    pub panic_file: [u8; PANIC_FILE_SIZE],
+    /// ESR of the fatal exception behind the last panic, 0 if there was
+    /// none (added in version 2)
+    pub exception_esr: u32,
+    /// ELR of that exception (added in version 2)
+    pub exception_elr: u64,
+    /// [Boot ID](crate::bootid) of the boot that wrote the record (added in
+    /// version 3)
+    pub boot_id: u64,
+}
+
+/// Why the previous boot ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    /// No previous boot is recorded
+    PowerOn,
+    /// The previous boot started the payload
+    Normal,
+    /// The previous boot panicked
+    Panic,
+    /// A watchdog fired during the previous boot
+    Watchdog,
+    /// The previous boot stopped before finishing (e.g., a hang or power
+    /// loss)
+    Interrupted,
+}
+
+impl ResetReason {
+    /// Returns the name of the reason
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            ResetReason::PowerOn => b"power-on",
+            ResetReason::Normal => b"normal",
+            ResetReason::Panic => b"panic",
+            ResetReason::Watchdog => b"watchdog",
+            ResetReason::Interrupted => b"interrupted",
+        };
+    }
+}
+
+/// Record of the current boot
+static mut RECORD: BootRecord = BootRecord {
+    boot_count: 0,
+    status: BootStatus::Unknown as u8,
+    watchdog_fired: 0,
+    reserved: 0,
+    panic_line: 0,
+    panic_file: [0; PANIC_FILE_SIZE],
+    exception_esr: 0,
+    exception_elr: 0,
+    boot_id: 0,
+};
+
+/// Why the previous boot ended, set by [`init`]
+static mut LAST_RESET: ResetReason = ResetReason::PowerOn;
+/// Boot ID of the previous boot, 0 if unknown, set by [`init`]
+static mut LAST_BOOT_ID: u64 = 0;
+
+/// Returns the address of the stored record, if the board has one
+fn store_addr() -> Option<usize> {
+    let config = board::config();
+
+    match config.bootrecord {
+        RecordStore::None => return None,
+        RecordStore::Ram { base } => return Some(base),
+        RecordStore::Flash { offset } => return config.flash.map(|flash| flash.base + offset),
+    }
+}
+
+/// Returns a mutable reference to the record of the current boot
+fn record_mut() -> &'static mut BootRecord {
+    let record = &raw mut RECORD;
+
+    unsafe {
+        return &mut *record;
+    }
+}
+
+/// Returns a copy of the record of the current boot
+pub fn record() -> BootRecord {
+    return *record_mut();
+}
+
+/// Returns why the previous boot ended
+pub fn last_reset() -> ResetReason {
+    return unsafe { LAST_RESET };
+}
+
+/// Returns the boot ID of the previous boot, 0 if it isn't known
+pub fn last_boot_id() -> u64 {
+    return unsafe { LAST_BOOT_ID };
+}
+
+/// Reads the record stored at `addr`
+///
+/// Returns `None` if there is no valid record. Fields missing from an
+/// older, shorter record are left zeroed.
+fn load(addr: usize) -> Option<BootRecord> {
+    let header = unsafe { ptr::read_volatile(addr as *const RecordHeader) };
+    let size = header.size as usize;
+
+    if header.magic != RECORD_MAGIC || header.version == 0 || size > MAX_PAYLOAD_SIZE {
+        return None;
+    }
+    let payload =
+        unsafe { slice::from_raw_parts((addr + size_of::<RecordHeader>()) as *const u8, size) };
+    if crc32(payload) != header.crc {
+        return None;
+    }
+
+    let mut record: BootRecord = unsafe { mem::zeroed() };
+    let len = size.min(size_of::<BootRecord>());
+    unsafe {
+        ptr::copy_nonoverlapping(payload.as_ptr(), &raw mut record as *mut u8, len);
+    }
+    return Some(record);
+}
+
+/// Writes the record of the current boot to its store
+///
+/// Prints a warning if the store can't be written.
+fn save() {
+    let Some(addr) = store_addr() else {
+        return;
+    };
+    let record = record();
+    let payload =
+        unsafe { slice::from_raw_parts(&raw const record as *const u8, size_of::<BootRecord>()) };
+    let header = RecordHeader {
+        magic: RECORD_MAGIC,
+        version: RECORD_VERSION,
+        size: size_of::<BootRecord>() as u16,
+        crc: crc32(payload),
+    };
+
+    // Whole words, so the flash can be programmed word by word
+    let mut words = [0u32; RECORD_SIZE / 4];
+    unsafe {
+        let bytes = words.as_mut_ptr() as *mut u8;
+        ptr::copy_nonoverlapping(
+            &raw const header as *const u8,
+            bytes,
+            size_of::<RecordHeader>(),
+        );
+        ptr::copy_nonoverlapping(
+            payload.as_ptr(),
+            bytes.add(size_of::<RecordHeader>()),
+            payload.len(),
+        );
+    }
+
+    let ok = match board::config().bootrecord {
+        RecordStore::Flash { .. } => unsafe {
+            cfi::erase_block(addr) && cfi::program(addr, &words)
+        },
+        _ => {
+            for (i, &word) in words.iter().enumerate() {
+                unsafe {
+                    ptr::write_volatile((addr as *mut u32).add(i), word);
+                }
+            }
+            true
+        }
+    };
+    if !ok {
+        pl011::println(b"Could not write the boot record");
+    }
+}
+
+/// Reads the stored record, reports the previous boot and starts a new one
+///
+/// Prints the boot number and why the previous boot ended. A corrupted
+/// record is replaced by a zeroed one with a warning.
+pub fn init() {
+    let record = record_mut();
+
+    if let Some(addr) = store_addr() {
+        match load(addr) {
+            Some(stored) => *record = stored,
+            None => {
+                let header = unsafe { ptr::read_volatile(addr as *const RecordHeader) };
+                // An erased or never written store isn't worth a warning
+                if header.magic != u32::MAX && header.magic != 0 {
+                    pl011::println(b"Boot record corrupted, starting over");
+                }
+            }
+        }
+    }
+
+    let reason = if record.watchdog_fired != 0 {
+        ResetReason::Watchdog
+    } else {
+        match record.status {
+            s if s == BootStatus::Succeeded as u8 => ResetReason::Normal,
+            s if s == BootStatus::Panicked as u8 => ResetReason::Panic,
+            s if s == BootStatus::InProgress as u8 => ResetReason::Interrupted,
+            _ => ResetReason::PowerOn,
+        }
+    };
+    unsafe {
+        LAST_RESET = reason;
+        LAST_BOOT_ID = record.boot_id;
+    }
+
+    record.boot_count = record.boot_count.wrapping_add(1);
+    record.boot_id = bootid::id();
+    record.status = BootStatus::InProgress as u8;
+    record.watchdog_fired = 0;
+    save();
+
+    print_banner();
+    // Only a panic of this boot may leave an exception in the record
+    record.exception_esr = 0;
+    record.exception_elr = 0;
+}
+
+/// Prints the boot number and ID, why the previous boot ended, the console
+/// if the console sweep found it, the date from the RTC if there is one,
+/// and the executing core
+///
+/// The banner also goes to the debug channel, see [`log`].
+pub fn print_banner() {
+    let record = record();
+
+    log::to_all_sinks(|| {
+        pl011::print(b"Boot #");
+        pl011::print(u64_to_dec(record.boot_count as u64, &mut [0u8; 20]));
+        pl011::print(b" (ID ");
+        bootid::print();
+        pl011::print(b"), last reset: ");
+        pl011::print(last_reset().name());
+        if last_reset() == ResetReason::Panic {
+            let len = record.panic_file.iter().position(|&c| c == 0);
+            pl011::print(b" at ");
+            pl011::print(&record.panic_file[..len.unwrap_or(PANIC_FILE_SIZE)]);
+            pl011::print(b":");
+            pl011::print(u64_to_dec(record.panic_line as u64, &mut [0u8; 20]));
+        }
+        if last_reset() == ResetReason::Panic && record.exception_esr != 0 {
+            let ec = exception::esr_ec(record.exception_esr as u64);
+            pl011::print(b", exception EC=0x");
+            print_hex_u8(ec);
+            pl011::print(b" (");
+            pl011::print(exception::ec_name(ec).as_bytes());
+            pl011::print(b") at 0x");
+            pl011::print(u64_to_hex(record.exception_elr, &mut [0u8; 16]));
+        }
+        if last_reset() == ResetReason::Panic && last_boot_id() != 0 {
+            pl011::print(b", boot ID ");
+            pl011::print(&bootid::format(last_boot_id()));
+        }
+        pl011::print(b"\n");
+        if board::console_swept() {
+            let uart = board::config().uart;
+            pl011::print(b"Console found by probing: ");
+            pl011::print(uart.kind.name());
+            pl011::print(b" at 0x");
+            pl011::println(u64_to_hex(uart.base as u64, &mut [0u8; 16]));
+        }
+        if let (Some(backend), Some(now)) = (rtc::backend(), rtc::now()) {
+            pl011::print(b"Date: ");
+            pl011::print(&now.format());
+            pl011::print(b" UTC (");
+            pl011::print(backend.name());
+            pl011::println(b")");
+        }
+        cpu::print_cpu_info();
+    });
+}
+
+/// Records that the current boot succeeded
+///
+/// Called just before the payload is started.
+pub fn mark_boot_successful() {
+    record_mut().status = BootStatus::Succeeded as u8;
+    save();
+}
+
+/// Records that the current boot panicked at `file`:`line`
+pub fn record_panic(file: &[u8], line: u32) {
+    let record = record_mut();
+    // Keep the end of long paths, the file name is the useful part
+    let file = &file[file.len().saturating_sub(PANIC_FILE_SIZE)..];
+
+    record.status = BootStatus::Panicked as u8;
+    record.panic_line = line;
+    record.panic_file = [0; PANIC_FILE_SIZE];
+    record.panic_file[..file.len()].copy_from_slice(file);
+    save();
+}
+
+/// Records the fatal exception about to cause a panic
+///
+/// Kept with the panic location by the following [`record_panic`].
+pub fn record_exception(esr: u64, elr: u64) {
+    let record = record_mut();
+
+    record.exception_esr = esr as u32;
+    record.exception_elr = elr;
+}
+
+/// Records that a watchdog fired during the current boot
+///
+/// Meant for watchdog drivers with an early warning (pre-timeout) interrupt,
+/// so the next boot reports the watchdog as the reset reason.
+pub fn record_watchdog() {
+    record_mut().watchdog_fired = 1;
+    save();
+}
@@ -0,0 +1,237 @@
+//! Compressed console capture
+//!
+//! Serial captures of verbose boots get long. While capture is on, every
+//! byte printed to the console is also compressed, as it is printed, into a
+//! reserved RAM region with [`Lz77`]; the per-byte cost is small and fixed,
+//! so boot timing isn't distorted. [`save`] later prints the capture as a
+//! base64 blob between [`BEGIN_MARKER`] and [`END_MARKER`] lines, ready to be
+//! copied into a bug report. The monitor drives it with `log start`,
+//! `log stop` and `log save`; built with the `console-capture` feature,
+//! capture starts as soon as the boot has memory to put it in.
+//!
+//! Once the region is full, capture stops; the saved blob still decodes to
+//! everything captured up to then.
+//!
+//! # Blob format
+//!
+//! All values are little-endian:
+//!
+//! | Offset | Size | Content                                              |
+//! |--------|------|------------------------------------------------------|
+//! | 0      | 4    | [`BLOB_MAGIC`]                                       |
+//! | 4      | 4    | Size of the captured output in bytes                 |
+//! | 8      | n    | [`lz77`](crate::utilities::lz77) tokens              |
+//! | 8 + n  | 4    | CRC-32 of the tokens                                 |
+//!
+//! # Decoding on the host
+//!
+//! Paste the lines from the BEGIN marker to the END marker into a file and
+//! run this Python script on it; a truncated or mangled paste fails the CRC
+//! check.
+//!
+//! ```text
+//! import base64, struct, sys, zlib
+//!
+//! text = open(sys.argv[1]).read()
+//! body = text.split("-----BEGIN CONSOLE LOG-----")[1].split("-----END CONSOLE LOG-----")[0]
+//! blob = base64.b64decode("".join(body.split()))
+//! assert blob[:4] == b"CLOG", "not a console log"
+//! size, = struct.unpack("<I", blob[4:8])
+//! tokens, crc = blob[8:-4], struct.unpack("<I", blob[-4:])[0]
+//! assert zlib.crc32(tokens) == crc, "truncated or corrupted paste"
+//! out, i = bytearray(), 0
+//! while i < len(tokens):
+//!     token, i = tokens[i], i + 1
+//!     if token < 0x80:
+//!         out += tokens[i:i + token + 1]
+//!         i += token + 1
+//!     else:
+//!         distance, i = tokens[i] | tokens[i + 1] << 8, i + 2
+//!         for _ in range((token & 0x7f) + 3):
+//!             out.append(out[-distance])
+//! assert len(out) == size, "size mismatch"
+//! sys.stdout.write(out.decode(errors="replace"))
+//! ```
+
+use crate::drivers::uart::pl011;
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+use crate::utilities::crc32::crc32_update;
+use crate::utilities::lz77::Lz77;
+
+/// Size of the capture region in bytes
+pub const CAPTURE_SIZE: usize = 256 * 1024;
+/// First 4 bytes of a saved blob
+pub const BLOB_MAGIC: [u8; 4] = *b"CLOG";
+/// Line printed before the base64 blob
+pub const BEGIN_MARKER: &[u8] = b"-----BEGIN CONSOLE LOG-----";
+/// Line printed after the base64 blob
+pub const END_MARKER: &[u8] = b"-----END CONSOLE LOG-----";
+/// Base64 characters per line of the blob
+const LINE_LEN: usize = 76;
+
+/// Base64 alphabet
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Compressor of the captured output
+static mut COMPRESSOR: Lz77 = Lz77::new();
+/// Base address of the capture region, 0 until [`start`] reserves it
+static mut REGION: usize = 0;
+/// Whether printed output is being captured
+static mut ACTIVE: bool = false;
+
+/// Returns the compressor of the captured output
+fn compressor() -> &'static mut Lz77 {
+    let compressor = &raw mut COMPRESSOR;
+
+    return unsafe { &mut *compressor };
+}
+
+/// Starts a new capture, discarding the previous one
+///
+/// The capture region is reserved on the first call.
+pub fn start() -> Result<(), ReserveError> {
+    let mut region = unsafe { REGION };
+
+    if region == 0 {
+        region = reserve::allocate(CAPTURE_SIZE, 4096, None, ReserveTag::ConsoleLog)?;
+    }
+    unsafe {
+        REGION = region;
+        compressor().reset(region as *mut u8, CAPTURE_SIZE);
+        ACTIVE = true;
+    }
+    return Ok(());
+}
+
+/// Stops capturing, keeping what was captured for [`save`]
+pub fn stop() {
+    unsafe {
+        ACTIVE = false;
+    }
+    compressor().flush();
+}
+
+/// Checks whether printed output is being captured
+pub fn is_active() -> bool {
+    unsafe {
+        return ACTIVE && !compressor().is_full();
+    }
+}
+
+/// Captures `c`, if capture is on
+///
+/// Called by the console for every printed byte.
+pub fn feed(c: u8) {
+    if unsafe { ACTIVE } {
+        compressor().push(c);
+    }
+}
+
+/// Returns the size of the captured output and of its compressed form
+pub fn sizes() -> (usize, usize) {
+    let compressor = compressor();
+
+    return (compressor.encoded_len(), compressor.output().len());
+}
+
+/// Prints the capture as a base64 blob between marker lines
+///
+/// Capture is paused while the blob is printed, then resumes if it was on.
+/// Prints a notice instead if capture was never started.
+pub fn save() {
+    let active = unsafe { ACTIVE };
+
+    if unsafe { REGION } == 0 {
+        pl011::println(b"No console capture");
+        return;
+    }
+    unsafe {
+        ACTIVE = false;
+    }
+    let compressor = compressor();
+    compressor.flush();
+
+    let tokens = compressor.output();
+    let size = (compressor.encoded_len() as u32).to_le_bytes();
+    let crc = crc32_update(0, tokens).to_le_bytes();
+    let mut encoder = Base64Writer::new();
+
+    pl011::println(BEGIN_MARKER);
+    for part in [&BLOB_MAGIC[..], &size, tokens, &crc] {
+        for &byte in part {
+            encoder.push(byte);
+        }
+    }
+    encoder.finish();
+    pl011::println(END_MARKER);
+    if compressor.is_full() {
+        pl011::println(b"(capture region full, later output missing)");
+    }
+
+    unsafe {
+        ACTIVE = active;
+    }
+}
+
+/// Streaming base64 encoder printing to the console in lines
+struct Base64Writer {
+    /// Bytes waiting for a full group of 3
+    group: [u8; 3],
+    /// Number of valid bytes in `group`
+    len: usize,
+    /// Characters printed on the current line
+    column: usize,
+}
+
+impl Base64Writer {
+    /// Creates an encoder at the start of a line
+    fn new() -> Self {
+        return Self {
+            group: [0; 3],
+            len: 0,
+            column: 0,
+        };
+    }
+
+    /// Encodes `byte`
+    fn push(&mut self, byte: u8) {
+        self.group[self.len] = byte;
+        self.len += 1;
+        if self.len == 3 {
+            self.write_group();
+        }
+    }
+
+    /// Encodes the last, partial group with padding and ends the line
+    fn finish(&mut self) {
+        if self.len != 0 {
+            self.group[self.len..].fill(0);
+            self.write_group();
+        }
+        if self.column != 0 {
+            pl011::print(b"\n");
+        }
+    }
+
+    /// Prints the 4 characters of the current group
+    fn write_group(&mut self) {
+        let [a, b, c] = self.group;
+        let indices = [
+            a >> 2,
+            (a & 0x3) << 4 | b >> 4,
+            (b & 0xf) << 2 | c >> 6,
+            c & 0x3f,
+        ];
+        let mut chars = indices.map(|i| BASE64_CHARS[i as usize]);
+
+        // A partial group of n bytes keeps n + 1 characters
+        chars[self.len + 1..].fill(b'=');
+        pl011::print(&chars);
+        self.len = 0;
+        self.column += chars.len();
+        if self.column == LINE_LEN {
+            pl011::print(b"\n");
+            self.column = 0;
+        }
+    }
+}
@@ -0,0 +1,381 @@
+//! CPU state helpers
+//!
+//! This module provides small accessors for the state of the executing core
+//! that other modules need, such as the current exception level and the
+//! number of the executing core, identifies the core for diagnostics and
+//! parks the cores the bootloader doesn't use.
+
+use crate::drivers::uart::pl011;
+use crate::selftest::{self, Outcome, SelfTest};
+use crate::utilities::print::{u64_to_dec, u64_to_hex};
+
+use crate::asm;
+use core::ptr;
+
+/// Returns the current exception level (0 to 3)
+#[inline(always)]
+pub fn current_el() -> u8 {
+    let el: u64;
+
+    unsafe {
+        asm!("mrs {}, CurrentEL", out(reg) el, options(nomem, nostack));
+    }
+    return ((el >> 2) & 0x3) as u8;
+}
+
+/// ID_AA64ISAR0_EL1 AES field shift
+const ISAR0_AES_SHIFT: u64 = 4;
+/// ID_AA64ISAR0_EL1 AES field mask (after shifting)
+const ISAR0_AES_MASK: u64 = 0xf;
+
+/// Checks whether the core implements the AES instructions
+pub fn has_aes() -> bool {
+    let isar0: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0, options(nomem, nostack));
+    }
+    return (isar0 >> ISAR0_AES_SHIFT) & ISAR0_AES_MASK != 0;
+}
+
+/// ID_AA64ISAR0_EL1 RNDR field shift
+const ISAR0_RNDR_SHIFT: u64 = 60;
+/// ID_AA64ISAR0_EL1 RNDR field mask (after shifting)
+const ISAR0_RNDR_MASK: u64 = 0xf;
+
+/// Checks whether the core implements the RNDR random number register
+pub fn has_rndr() -> bool {
+    let isar0: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0, options(nomem, nostack));
+    }
+    return (isar0 >> ISAR0_RNDR_SHIFT) & ISAR0_RNDR_MASK != 0;
+}
+
+/// ID_AA64ISAR1_EL1 APA field shift (QARMA5 address authentication)
+const ISAR1_APA_SHIFT: u64 = 4;
+/// ID_AA64ISAR1_EL1 API field shift (implementation defined address
+/// authentication)
+const ISAR1_API_SHIFT: u64 = 8;
+/// ID_AA64ISAR2_EL1 APA3 field shift (QARMA3 address authentication)
+const ISAR2_APA3_SHIFT: u64 = 12;
+/// Mask of the ID register fields above (after shifting)
+const ISAR_AUTH_MASK: u64 = 0xf;
+
+/// Checks whether the core implements address authentication (FEAT_PAuth),
+/// with any of its algorithms
+pub fn has_pauth() -> bool {
+    let (isar1, isar2): (u64, u64);
+
+    // ID_AA64ISAR2_EL1 is spelled out: older assemblers don't know its name
+    unsafe {
+        asm!(
+            "mrs {}, id_aa64isar1_el1",
+            "mrs {}, s3_0_c0_c6_2",
+            out(reg) isar1,
+            out(reg) isar2,
+            options(nomem, nostack),
+        );
+    }
+    return (isar1 >> ISAR1_APA_SHIFT) & ISAR_AUTH_MASK != 0
+        || (isar1 >> ISAR1_API_SHIFT) & ISAR_AUTH_MASK != 0
+        || (isar2 >> ISAR2_APA3_SHIFT) & ISAR_AUTH_MASK != 0;
+}
+
+/// ID_AA64PFR1_EL1 BT field shift
+const PFR1_BT_SHIFT: u64 = 0;
+/// ID_AA64PFR1_EL1 BT field mask (after shifting)
+const PFR1_BT_MASK: u64 = 0xf;
+
+/// Checks whether the core implements branch target identification
+/// (FEAT_BTI)
+pub fn has_bti() -> bool {
+    let pfr1: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64pfr1_el1", out(reg) pfr1, options(nomem, nostack));
+    }
+    return (pfr1 >> PFR1_BT_SHIFT) & PFR1_BT_MASK != 0;
+}
+
+/// ID_AA64MMFR1_EL1 VH field shift
+const MMFR1_VH_SHIFT: u64 = 8;
+/// ID_AA64MMFR1_EL1 VH field mask (after shifting)
+const MMFR1_VH_MASK: u64 = 0xf;
+
+/// Checks whether the core implements the virtualization host extensions
+/// (FEAT_VHE), which HCR_EL2.E2H enables
+pub fn has_vhe() -> bool {
+    let mmfr1: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64mmfr1_el1", out(reg) mmfr1, options(nomem, nostack));
+    }
+    return (mmfr1 >> MMFR1_VH_SHIFT) & MMFR1_VH_MASK != 0;
+}
+
+/// Cores numbered by each affinity level below the top one
+///
+/// 16 is the most a GICv3 can target within one Aff0 range, so linear
+/// numbers stay unique on every board supported here.
+const CORES_PER_CLUSTER: usize = 16;
+/// Values of each affinity level above Aff0
+const AFFINITY_VALUES: usize = 256;
+
+/// Returns the value of MPIDR_EL1, which identifies the executing core
+#[inline(always)]
+pub fn read_mpidr() -> u64 {
+    let mpidr: u64;
+
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack));
+    }
+    return mpidr;
+}
+
+/// Maps the affinity fields of an MPIDR_EL1 value to a linear core number
+///
+/// Aff0 numbers cores within a cluster of up to [`CORES_PER_CLUSTER`],
+/// Aff1 to Aff3 number the clusters. For example, `0x80000000` (Aff0 0) is
+/// core 0 and `0x80000102` (Aff1 1, Aff0 2) is core 18.
+pub const fn mpidr_to_core_id(mpidr: u64) -> usize {
+    let aff0 = (mpidr & 0xff) as usize;
+    let aff1 = ((mpidr >> 8) & 0xff) as usize;
+    let aff2 = ((mpidr >> 16) & 0xff) as usize;
+    let aff3 = ((mpidr >> 32) & 0xff) as usize;
+    let cluster = (aff3 * AFFINITY_VALUES + aff2) * AFFINITY_VALUES + aff1;
+
+    return cluster * CORES_PER_CLUSTER + aff0;
+}
+
+// Single cluster, as on QEMU virt and the Raspberry Pi 4
+const _: () = assert!(mpidr_to_core_id(0x8000_0000) == 0 && mpidr_to_core_id(0x8000_0003) == 3);
+// The documented example, Aff1 1 and Aff0 2
+const _: () = assert!(mpidr_to_core_id(0x8000_0102) == 18);
+// The U and MT bits are not affinity
+const _: () = assert!(mpidr_to_core_id(0xc100_0001) == 1);
+// Aff2 and Aff3, past the MT bit and the reserved bits 31 to 24
+const _: () = assert!(mpidr_to_core_id(0x8001_0000) == 256 * 16);
+const _: () = assert!(mpidr_to_core_id(0x01_8000_0000) == 256 * 256 * 16);
+
+/// Returns the linear number of the executing core
+pub fn core_id() -> usize {
+    return mpidr_to_core_id(read_mpidr());
+}
+
+/// Identification of a core, decoded from MIDR_EL1 and MPIDR_EL1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuInfo {
+    /// Implementer code, e.g. 0x41 for Arm
+    pub implementer: u8,
+    /// Major revision (the `r` in `rNpM`)
+    pub variant: u8,
+    /// Architecture code, 0xf when the ID registers describe it
+    pub architecture: u8,
+    /// Implementer-defined part number, e.g. 0xd08 for Cortex-A72
+    pub part: u16,
+    /// Minor revision (the `p` in `rNpM`)
+    pub revision: u8,
+    /// Affinity levels Aff0 to Aff3 of the core
+    pub affinity: [u8; 4],
+}
+
+impl CpuInfo {
+    /// Returns the name of the implementer, or `unknown`
+    pub fn implementer_name(&self) -> &'static [u8] {
+        return match self.implementer {
+            0x41 => b"Arm",
+            0x42 => b"Broadcom",
+            0x43 => b"Cavium",
+            0x46 => b"Fujitsu",
+            0x48 => b"HiSilicon",
+            0x4e => b"NVIDIA",
+            0x51 => b"Qualcomm",
+            0x61 => b"Apple",
+            0xc0 => b"Ampere",
+            _ => b"unknown",
+        };
+    }
+
+    /// Returns the name of the part for the parts the bootloader is run on,
+    /// or `None`
+    pub fn part_name(&self) -> Option<&'static [u8]> {
+        if self.implementer != 0x41 {
+            return None;
+        }
+        return match self.part {
+            0xd03 => Some(b"Cortex-A53"),
+            0xd04 => Some(b"Cortex-A35"),
+            0xd05 => Some(b"Cortex-A55"),
+            0xd07 => Some(b"Cortex-A57"),
+            0xd08 => Some(b"Cortex-A72"),
+            0xd09 => Some(b"Cortex-A73"),
+            0xd0b => Some(b"Cortex-A76"),
+            0xd0c => Some(b"Neoverse-N1"),
+            0xd40 => Some(b"Neoverse-V1"),
+            0xd49 => Some(b"Neoverse-N2"),
+            0xd0f => Some(b"Foundation"),
+            _ => None,
+        };
+    }
+}
+
+/// Returns the value of MIDR_EL1, which identifies the core's design
+#[inline(always)]
+pub fn read_midr() -> u64 {
+    let midr: u64;
+
+    unsafe {
+        asm!("mrs {}, midr_el1", out(reg) midr, options(nomem, nostack));
+    }
+    return midr;
+}
+
+/// Decodes MIDR_EL1 and MPIDR_EL1 values
+pub const fn decode_cpu_info(midr: u64, mpidr: u64) -> CpuInfo {
+    return CpuInfo {
+        implementer: ((midr >> 24) & 0xff) as u8,
+        variant: ((midr >> 20) & 0xf) as u8,
+        architecture: ((midr >> 16) & 0xf) as u8,
+        part: ((midr >> 4) & 0xfff) as u16,
+        revision: (midr & 0xf) as u8,
+        affinity: [
+            mpidr as u8,
+            (mpidr >> 8) as u8,
+            (mpidr >> 16) as u8,
+            (mpidr >> 32) as u8,
+        ],
+    };
+}
+
+// Cortex-A72 r0p3, core 1 of the Raspberry Pi 4
+const _: () = {
+    let info = decode_cpu_info(0x410f_d083, 0x8000_0001);
+    assert!(info.implementer == 0x41);
+    assert!(info.variant == 0);
+    assert!(info.architecture == 0xf);
+    assert!(info.part == 0xd08);
+    assert!(info.revision == 3);
+    assert!(info.affinity[0] == 1 && info.affinity[1] == 0);
+};
+// Cortex-A57 r1p3 in cluster 1
+const _: () = {
+    let info = decode_cpu_info(0x411f_d073, 0x8100_0102);
+    assert!(info.variant == 1 && info.part == 0xd07 && info.revision == 3);
+    assert!(info.affinity[0] == 2 && info.affinity[1] == 1 && info.affinity[2] == 0);
+};
+
+/// Returns the identification of the executing core
+pub fn cpu_info() -> CpuInfo {
+    return decode_cpu_info(read_midr(), read_mpidr());
+}
+
+/// Prints the identification of the executing core
+///
+/// For example: `CPU: Arm Cortex-A72 r0p3 (part 0xd08), affinity 0.0.0.1`.
+pub fn print_cpu_info() {
+    let info = cpu_info();
+    let mut buf = [0u8; 20];
+
+    pl011::print(b"CPU: ");
+    pl011::print(info.implementer_name());
+    if let Some(name) = info.part_name() {
+        pl011::print(b" ");
+        pl011::print(name);
+    }
+    pl011::print(b" r");
+    pl011::print(u64_to_dec(info.variant as u64, &mut buf));
+    pl011::print(b"p");
+    pl011::print(u64_to_dec(info.revision as u64, &mut buf));
+    pl011::print(b" (implementer 0x");
+    pl011::print(u64_to_hex(info.implementer as u64, &mut buf));
+    pl011::print(b", part 0x");
+    pl011::print(u64_to_hex(info.part as u64, &mut buf));
+    pl011::print(b"), affinity ");
+    for (i, aff) in info.affinity.iter().rev().enumerate() {
+        if i != 0 {
+            pl011::print(b".");
+        }
+        pl011::print(u64_to_hex(*aff as u64, &mut buf));
+    }
+    pl011::println(b"");
+}
+
+/// Waits until the value at `release_addr` becomes nonzero and returns it
+///
+/// `wait` is called between reads; it is expected to sleep until something
+/// may have changed, e.g. with `wfe`.
+///
+/// # Safety
+///
+/// `release_addr` must point to a readable, aligned `usize`.
+pub unsafe fn poll_release(release_addr: *const usize, mut wait: impl FnMut()) -> usize {
+    loop {
+        let entry = unsafe { ptr::read_volatile(release_addr) };
+        if entry != 0 {
+            return entry;
+        }
+        wait();
+    }
+}
+
+/// Entry point written to the release address of [`selftest_poll_release`]
+const TEST_ENTRY: usize = 0x4008_0000;
+/// Number of waits [`selftest_poll_release`] makes before the release
+const TEST_WAITS: usize = 3;
+
+/// Self-test: [`poll_release`] keeps waiting while the release address
+/// holds 0, returns the entry point written there once it does, and
+/// doesn't wait at all for a core already released
+fn selftest_poll_release() -> Outcome {
+    let mut release = 0usize;
+    let addr = &raw mut release;
+    let mut waits = 0;
+
+    let entry = unsafe {
+        poll_release(addr, || {
+            waits += 1;
+            if waits == TEST_WAITS {
+                ptr::write_volatile(addr, TEST_ENTRY);
+            }
+        })
+    };
+    if entry != TEST_ENTRY || waits != TEST_WAITS {
+        return Outcome::Fail;
+    }
+
+    let mut waits = 0;
+    let entry = unsafe { poll_release(addr, || waits += 1) };
+    if entry != TEST_ENTRY || waits != 0 {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Registers the self-tests of the module
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"poll-release",
+        run: selftest_poll_release,
+    });
+}
+
+/// Parks the executing core until it's released through `release_addr`
+///
+/// This is the spin-table boot method: with all interrupts masked, the core
+/// sleeps in `wfe` until another core writes an entry point to
+/// `release_addr` (and issues `sev`), then branches there.
+///
+/// # Safety
+///
+/// `release_addr` must point to a readable, aligned `usize`, and the value
+/// written there must be the address of code the core can run.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn park_core(release_addr: *const usize) -> ! {
+    unsafe {
+        asm!("msr daifset, #0xf", options(nomem, nostack));
+        let entry = poll_release(release_addr, || asm!("wfe", options(nomem, nostack)));
+        asm!("br {}", in(reg) entry, options(noreturn));
+    }
+}
@@ -0,0 +1,933 @@
+//! Self-hosted debug support
+//!
+//! This module drives the AArch64 self-hosted debug architecture so the
+//! bootloader can act as a minimal educational debugger for the payload it
+//! loads.
+//!
+//! # Single-step
+//!
+//! [`enable_single_step`] arms the software-step state machine: once the
+//! bootloader returns to the payload, exactly one instruction executes before
+//! a software-step exception is taken. The synchronous exception handlers
+//! forward it to [`handle_debug_exception`], which calls the callback
+//! registered with [`set_step_handler`] and re-arms the step if asked to.
+//!
+//! # Hardware breakpoints
+//!
+//! [`set_breakpoint`] programs one of the breakpoint register pairs
+//! (`DBGBVR<n>_EL1`/`DBGBCR<n>_EL1`) to trap when the instruction at an
+//! address is executed. Breakpoint exceptions are forwarded to the callback
+//! registered with [`set_breakpoint_handler`]. The number of implemented
+//! breakpoints is given by [`breakpoint_count`].
+//!
+//! # Watchpoints
+//!
+//! [`set_watchpoint`] programs one of the watchpoint register pairs
+//! (`DBGWVR<n>_EL1`/`DBGWCR<n>_EL1`) to trap loads, stores or both to a
+//! range: up to 8 bytes within an aligned doubleword, or an aligned power of
+//! two. Watchpoint exceptions are forwarded to the callback registered with
+//! [`set_watchpoint_handler`], which can describe them with [`watch_hit`].
+//! The access hasn't happened yet when the exception is taken, so to resume,
+//! the watchpoints are disabled, the accessing instruction is single-stepped
+//! and they're enabled again, before stepping goes on as it was. The number
+//! of implemented watchpoints is given by [`watchpoint_count`].
+//!
+//! When running at EL2, debug exceptions from the payload are routed to the
+//! bootloader by setting `MDCR_EL2.TDE`.
+
+use crate::cpu;
+use crate::exception::{self, Regs};
+use crate::selftest::{self, Outcome, SelfTest};
+
+use crate::asm;
+use core::ptr;
+
+/// MDSCR_EL1 Software step enable bit
+const MDSCR_SS: u64 = 1 << 0;
+/// MDSCR_EL1 Local (kernel) debug enable bit
+const MDSCR_KDE: u64 = 1 << 13;
+/// MDSCR_EL1 Monitor debug events (breakpoints and watchpoints) enable bit
+const MDSCR_MDE: u64 = 1 << 15;
+/// SPSR_ELx Software step bit
+const SPSR_SS: u64 = 1 << 21;
+/// MDCR_EL2 Trap debug exceptions (route them to EL2) bit
+const MDCR_EL2_TDE: u64 = 1 << 8;
+
+/// DBGBCR<n>_EL1 Enable bit
+const DBGBCR_E: u64 = 1 << 0;
+/// DBGBCR<n>_EL1 Privilege mode control: match at EL1 and EL0
+const DBGBCR_PMC_EL1_EL0: u64 = 0b11 << 1;
+/// DBGBCR<n>_EL1 Byte address select: match the whole A64 instruction
+const DBGBCR_BAS_A64: u64 = 0b1111 << 5;
+/// DBGBCR<n>_EL1 Higher mode control: also match at EL2
+const DBGBCR_HMC: u64 = 1 << 13;
+/// ID_AA64DFR0_EL1 number of breakpoints minus one field shift
+const DFR0_BRPS_SHIFT: u64 = 12;
+/// ID_AA64DFR0_EL1 number of breakpoints minus one field mask (after shifting)
+const DFR0_BRPS_MASK: u64 = 0xf;
+
+/// DBGWCR<n>_EL1 Enable bit
+const DBGWCR_E: u64 = 1 << 0;
+/// DBGWCR<n>_EL1 Privilege access control: match at EL1 and EL0
+const DBGWCR_PAC_EL1_EL0: u64 = 0b11 << 1;
+/// DBGWCR<n>_EL1 Load/store control field shift
+const DBGWCR_LSC_SHIFT: u64 = 3;
+/// DBGWCR<n>_EL1 Byte address select field shift
+const DBGWCR_BAS_SHIFT: u64 = 5;
+/// DBGWCR<n>_EL1 Higher mode control: also match at EL2
+const DBGWCR_HMC: u64 = 1 << 13;
+/// DBGWCR<n>_EL1 Address mask field shift
+const DBGWCR_MASK_SHIFT: u64 = 24;
+/// Largest DBGWCR<n>_EL1 address mask: 2 GiB
+const DBGWCR_MASK_MAX: u32 = 31;
+/// ID_AA64DFR0_EL1 number of watchpoints minus one field shift
+const DFR0_WRPS_SHIFT: u64 = 20;
+/// ID_AA64DFR0_EL1 number of watchpoints minus one field mask (after shifting)
+const DFR0_WRPS_MASK: u64 = 0xf;
+/// ESR_ELx Write not Read bit (watchpoints)
+const ESR_WNR: u64 = 1 << 6;
+/// Most watchpoints the architecture allows
+pub const MAX_WATCHPOINTS: usize = 16;
+
+/// Accesses a watchpoint traps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchAccess {
+    /// Loads
+    Read,
+    /// Stores
+    Write,
+    /// Loads and stores
+    ReadWrite,
+}
+
+impl WatchAccess {
+    /// Returns the DBGWCR<n>_EL1 load/store control value
+    const fn lsc(self) -> u64 {
+        return match self {
+            WatchAccess::Read => 0b01,
+            WatchAccess::Write => 0b10,
+            WatchAccess::ReadWrite => 0b11,
+        };
+    }
+
+    /// Returns the name of the accesses, as the monitor takes them
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            WatchAccess::Read => b"r",
+            WatchAccess::Write => b"w",
+            WatchAccess::ReadWrite => b"rw",
+        };
+    }
+}
+
+/// A watched range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watch {
+    /// First byte watched
+    pub addr: u64,
+    /// Number of bytes watched
+    pub len: u64,
+    /// Accesses trapped
+    pub access: WatchAccess,
+}
+
+/// A watchpoint exception, as described by [`watch_hit`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Watchpoint whose range holds the address, if one does
+    pub index: Option<u8>,
+    /// Address accessed, from FAR_ELx
+    pub addr: u64,
+    /// Address of the accessing instruction
+    pub pc: u64,
+    /// Whether the access is a store
+    pub write: bool,
+}
+
+/// Callback invoked on every software-step exception
+///
+/// Receives the register state of the stepped code, which it may modify.
+/// Returns `true` to step another instruction or `false` to stop stepping.
+pub type StepHandler = fn(regs: &mut Regs) -> bool;
+
+/// Callback invoked on every breakpoint exception
+///
+/// Receives the register state of the code that hit the breakpoint, which it
+/// may modify. Returns `true` to resume or `false` to report the exception as
+/// fatal. Execution resumes at `regs.elr`, so returning `true` without
+/// clearing the breakpoint or moving `elr` traps again immediately.
+pub type BreakpointHandler = fn(regs: &mut Regs) -> bool;
+
+/// Registered software-step callback
+static mut STEP_HANDLER: Option<StepHandler> = None;
+
+/// Callback invoked on every watchpoint exception
+///
+/// Receives the register state of the code whose access was trapped, which
+/// it may modify. Returns `true` to resume, performing the access, or
+/// `false` to report the exception as fatal.
+pub type WatchpointHandler = fn(regs: &mut Regs) -> bool;
+
+/// Registered breakpoint callback
+static mut BREAKPOINT_HANDLER: Option<BreakpointHandler> = None;
+
+/// Registered watchpoint callback
+static mut WATCHPOINT_HANDLER: Option<WatchpointHandler> = None;
+
+/// Ranges of the watchpoints set, by index
+static mut WATCHES: [Option<Watch>; MAX_WATCHPOINTS] = [None; MAX_WATCHPOINTS];
+
+/// While stepping over a watched access, whether single-step was armed
+/// before, to go on stepping once the watchpoints are back
+static mut STEPPING_OVER: Option<bool> = None;
+
+/// Returns `mdscr` with software step enabled or disabled
+///
+/// Enabling also sets `KDE` so step exceptions can target the exception level
+/// the bootloader runs at.
+pub const fn mdscr_single_step(mdscr: u64, enable: bool) -> u64 {
+    if enable {
+        return mdscr | MDSCR_SS | MDSCR_KDE;
+    }
+    return mdscr & !MDSCR_SS;
+}
+
+const _: () = assert!(mdscr_single_step(0, true) == MDSCR_SS | MDSCR_KDE);
+// Disabling keeps KDE and the other bits, for breakpoints and watchpoints
+const _: () =
+    assert!(mdscr_single_step(MDSCR_MDE | MDSCR_KDE | MDSCR_SS, false) == MDSCR_MDE | MDSCR_KDE);
+
+/// Checks whether `esr` describes a software-step exception
+pub const fn is_software_step(esr: u64) -> bool {
+    let ec = exception::esr_ec(esr);
+
+    return ec == exception::EC_SOFTSTP_LOW || ec == exception::EC_SOFTSTP_CUR;
+}
+
+const _: () = assert!(is_software_step(
+    (exception::EC_SOFTSTP_CUR as u64) << 26 | 0x22
+));
+const _: () = assert!(is_software_step((exception::EC_SOFTSTP_LOW as u64) << 26));
+const _: () = assert!(!is_software_step((exception::EC_BREAKPT_CUR as u64) << 26));
+
+/// Checks whether `esr` describes a breakpoint exception
+pub const fn is_breakpoint(esr: u64) -> bool {
+    let ec = exception::esr_ec(esr);
+
+    return ec == exception::EC_BREAKPT_LOW || ec == exception::EC_BREAKPT_CUR;
+}
+
+const _: () = assert!(is_breakpoint(
+    (exception::EC_BREAKPT_CUR as u64) << 26 | 0x22
+));
+const _: () = assert!(is_breakpoint((exception::EC_BREAKPT_LOW as u64) << 26));
+const _: () = assert!(!is_breakpoint((exception::EC_WATCHPT_CUR as u64) << 26));
+
+/// Checks whether `esr` describes a watchpoint exception
+pub const fn is_watchpoint(esr: u64) -> bool {
+    let ec = exception::esr_ec(esr);
+
+    return ec == exception::EC_WATCHPT_LOW || ec == exception::EC_WATCHPT_CUR;
+}
+
+const _: () = assert!(is_watchpoint(
+    (exception::EC_WATCHPT_CUR as u64) << 26 | 0x22
+));
+const _: () = assert!(is_watchpoint((exception::EC_WATCHPT_LOW as u64) << 26));
+const _: () = assert!(!is_watchpoint((exception::EC_SOFTSTP_CUR as u64) << 26));
+
+/// Returns the DBGBCR<n>_EL1 value of an enabled or disabled breakpoint
+///
+/// An enabled breakpoint matches an A64 instruction executed at EL0, EL1 or
+/// EL2.
+pub const fn bcr_value(enable: bool) -> u64 {
+    let bcr = DBGBCR_HMC | DBGBCR_BAS_A64 | DBGBCR_PMC_EL1_EL0;
+
+    if enable {
+        return bcr | DBGBCR_E;
+    }
+    return bcr;
+}
+
+const _: () = assert!(bcr_value(true) == 0x21e7 && bcr_value(false) == 0x21e6);
+
+/// Extracts the number of implemented breakpoints from ID_AA64DFR0_EL1
+pub const fn breakpoints_from_dfr0(dfr0: u64) -> u8 {
+    return ((dfr0 >> DFR0_BRPS_SHIFT) & DFR0_BRPS_MASK) as u8 + 1;
+}
+
+// BRPs holds the count minus one (Cortex-A57: 6 breakpoints)
+const _: () = assert!(breakpoints_from_dfr0(0x1030_5106) == 6);
+const _: () = assert!(breakpoints_from_dfr0(0) == 1 && breakpoints_from_dfr0(0xf000) == 16);
+
+/// Returns the number of hardware breakpoints implemented by the core
+pub fn breakpoint_count() -> u8 {
+    return breakpoints_from_dfr0(read_dfr0());
+}
+
+/// Returns the DBGWVR<n>_EL1 and enabled DBGWCR<n>_EL1 values watching
+/// `watch`, or `None` if the range can't be watched by one watchpoint
+///
+/// A range within an aligned doubleword selects its bytes; a larger one
+/// must be an aligned power of two, masked out of the address. Matches at
+/// EL0, EL1 and EL2, like [`bcr_value`].
+pub const fn watch_registers(watch: Watch) -> Option<(u64, u64)> {
+    let Watch { addr, len, access } = watch;
+    let wcr = DBGWCR_HMC | access.lsc() << DBGWCR_LSC_SHIFT | DBGWCR_PAC_EL1_EL0 | DBGWCR_E;
+
+    if len == 0 {
+        return None;
+    }
+    if addr % 8 + len <= 8 {
+        let bas = ((1 << len) - 1) << (addr % 8);
+        return Some((addr & !7, wcr | bas << DBGWCR_BAS_SHIFT));
+    }
+    let mask = len.trailing_zeros();
+    if !len.is_power_of_two() || addr % len != 0 || mask > DBGWCR_MASK_MAX {
+        return None;
+    }
+    return Some((
+        addr,
+        wcr | 0xff << DBGWCR_BAS_SHIFT | (mask as u64) << DBGWCR_MASK_SHIFT,
+    ));
+}
+
+/// Builds a [`Watch`] for the const checks of [`watch_registers`]
+const fn test_watch(addr: u64, len: u64, access: WatchAccess) -> Option<(u64, u64)> {
+    return watch_registers(Watch { addr, len, access });
+}
+
+// A byte: BAS bit 3, stores only, EL2 to EL0, enabled
+const _: () = assert!(matches!(
+    test_watch(0x1003, 1, WatchAccess::Write),
+    Some((0x1000, 0x2117))
+));
+// A word at the top of a doubleword, loads and stores
+const _: () = assert!(matches!(
+    test_watch(0x1004, 4, WatchAccess::ReadWrite),
+    Some((0x1000, 0x3e1f))
+));
+// A page, masked: BAS all ones, MASK 12
+const _: () = assert!(matches!(
+    test_watch(0x4000_0000, 0x1000, WatchAccess::Read),
+    Some((0x4000_0000, 0x0c00_3fef))
+));
+// Across a doubleword, not a power of two, misaligned, empty
+const _: () = assert!(test_watch(0x1006, 4, WatchAccess::Write).is_none());
+const _: () = assert!(test_watch(0x1000, 24, WatchAccess::Write).is_none());
+const _: () = assert!(test_watch(0x1008, 16, WatchAccess::Write).is_none());
+const _: () = assert!(test_watch(0x1000, 0, WatchAccess::Write).is_none());
+
+/// Extracts the number of implemented watchpoints from ID_AA64DFR0_EL1
+pub const fn watchpoints_from_dfr0(dfr0: u64) -> u8 {
+    return ((dfr0 >> DFR0_WRPS_SHIFT) & DFR0_WRPS_MASK) as u8 + 1;
+}
+
+// WRPs holds the count minus one (Cortex-A57: 4 watchpoints)
+const _: () = assert!(watchpoints_from_dfr0(0x1030_5106) == 4);
+const _: () = assert!(watchpoints_from_dfr0(0) == 1 && watchpoints_from_dfr0(0xf0_0000) == 16);
+// Neither count reads the other's field
+const _: () = assert!(watchpoints_from_dfr0(0xf000) == 1 && breakpoints_from_dfr0(0xf0_0000) == 1);
+
+/// Returns the number of hardware watchpoints implemented by the core
+pub fn watchpoint_count() -> u8 {
+    return watchpoints_from_dfr0(read_dfr0());
+}
+
+/// Reads ID_AA64DFR0_EL1
+fn read_dfr0() -> u64 {
+    let dfr0: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64dfr0_el1", out(reg) dfr0, options(nomem, nostack));
+    }
+    return dfr0;
+}
+
+/// Registers the callback invoked on software-step exceptions
+///
+/// Passing `None` removes it; a step exception without a callback is treated
+/// as a fatal exception.
+pub fn set_step_handler(handler: Option<StepHandler>) {
+    unsafe {
+        STEP_HANDLER = handler;
+    }
+}
+
+/// Registers the callback invoked on breakpoint exceptions
+///
+/// Passing `None` removes it; a breakpoint exception without a callback is
+/// treated as a fatal exception.
+pub fn set_breakpoint_handler(handler: Option<BreakpointHandler>) {
+    unsafe {
+        BREAKPOINT_HANDLER = handler;
+    }
+}
+
+/// Registers the callback invoked on watchpoint exceptions
+///
+/// Passing `None` removes it; a watchpoint exception without a callback is
+/// treated as a fatal exception.
+pub fn set_watchpoint_handler(handler: Option<WatchpointHandler>) {
+    unsafe {
+        WATCHPOINT_HANDLER = handler;
+    }
+}
+
+/// Returns the registered watchpoint callback
+pub fn watchpoint_handler() -> Option<WatchpointHandler> {
+    return unsafe { WATCHPOINT_HANDLER };
+}
+
+/// Sets hardware breakpoint `index` on the instruction at `addr`
+///
+/// Enables debug exceptions as for [`enable_single_step`] and sets
+/// `MDSCR_EL1.MDE`. Returns `false` if `index` isn't below
+/// [`breakpoint_count`].
+pub fn set_breakpoint(index: u8, addr: u64) -> bool {
+    if index >= breakpoint_count() {
+        return false;
+    }
+
+    enable_debug_exceptions();
+    write_mdscr(read_mdscr() | MDSCR_MDE | MDSCR_KDE);
+    write_breakpoint(index, addr & !3, bcr_value(true));
+    return true;
+}
+
+/// Clears hardware breakpoint `index`
+///
+/// Returns `false` if `index` isn't below [`breakpoint_count`].
+pub fn clear_breakpoint(index: u8) -> bool {
+    if index >= breakpoint_count() {
+        return false;
+    }
+
+    write_breakpoint(index, 0, bcr_value(false));
+    return true;
+}
+
+/// Sets hardware watchpoint `index` on `watch`, replacing what it watched
+///
+/// Enables debug exceptions and sets `MDSCR_EL1.MDE` as [`set_breakpoint`]
+/// does. Returns `false` if `index` isn't below [`watchpoint_count`] or the
+/// range can't be watched (see [`watch_registers`]).
+pub fn set_watchpoint(index: u8, watch: Watch) -> bool {
+    let Some((wvr, wcr)) = watch_registers(watch) else {
+        return false;
+    };
+    if index >= watchpoint_count() {
+        return false;
+    }
+
+    enable_debug_exceptions();
+    write_mdscr(read_mdscr() | MDSCR_MDE | MDSCR_KDE);
+    write_watchpoint(index, wvr, wcr);
+    unsafe {
+        WATCHES[index as usize] = Some(watch);
+    }
+    return true;
+}
+
+/// Clears hardware watchpoint `index`
+///
+/// Returns `false` if `index` isn't set.
+pub fn clear_watchpoint(index: u8) -> bool {
+    if watchpoint(index).is_none() {
+        return false;
+    }
+
+    write_watchpoint(index, 0, 0);
+    unsafe {
+        WATCHES[index as usize] = None;
+    }
+    return true;
+}
+
+/// Returns what watchpoint `index` watches, if it's set
+pub fn watchpoint(index: u8) -> Option<Watch> {
+    let watches = &raw const WATCHES;
+
+    return unsafe { (*watches).get(index as usize).copied().flatten() };
+}
+
+/// Describes the watchpoint exception trapped in `regs`
+///
+/// The watchpoint is the one whose range holds the accessed address, or
+/// shares its doubleword, as an access wider than the watched bytes may
+/// report its first address.
+pub fn watch_hit(regs: &Regs) -> WatchHit {
+    let addr = exception::read_far();
+    let overlaps =
+        |watch: &Watch| watch.addr < (addr | 7) + 1 && addr & !7 < watch.addr + watch.len;
+    let index = (0..MAX_WATCHPOINTS as u8).find(|&i| watchpoint(i).as_ref().is_some_and(overlaps));
+
+    return WatchHit {
+        index,
+        addr,
+        pc: regs.elr,
+        write: regs.esr & ESR_WNR != 0,
+    };
+}
+
+/// Disables or enables again the watchpoints set
+fn arm_watchpoints(enable: bool) {
+    for index in 0..MAX_WATCHPOINTS as u8 {
+        let Some(watch) = watchpoint(index) else {
+            continue;
+        };
+        // Set watchpoints were checked when set
+        if let Some((wvr, wcr)) = watch_registers(watch) {
+            let wcr = if enable { wcr } else { wcr & !DBGWCR_E };
+            write_watchpoint(index, wvr, wcr);
+        }
+    }
+}
+
+/// Lets the access trapped in `regs` happen: disables the watchpoints and
+/// steps the accessing instruction, [`handle_debug_exception`] enabling
+/// them again after the step
+fn step_over_watched(regs: &mut Regs) {
+    let stepping = read_mdscr() & MDSCR_SS != 0;
+
+    arm_watchpoints(false);
+    unsafe {
+        STEPPING_OVER = Some(stepping);
+    }
+    write_mdscr(mdscr_single_step(read_mdscr(), true));
+    regs.spsr |= SPSR_SS;
+}
+
+/// Unlocks the OS lock and, at EL2, routes debug exceptions to EL2
+fn enable_debug_exceptions() {
+    unsafe {
+        // The OS lock is set on cold reset and blocks all debug exceptions
+        asm!("msr oslar_el1, xzr", "isb", options(nostack));
+        if cpu::current_el() == 2 {
+            let mdcr: u64;
+            asm!("mrs {}, mdcr_el2", out(reg) mdcr, options(nomem, nostack));
+            asm!("msr mdcr_el2, {}", in(reg) mdcr | MDCR_EL2_TDE, options(nostack));
+        }
+    }
+}
+
+/// Arms single-step for the next exception return
+///
+/// Unlocks the OS lock, sets `MDSCR_EL1.SS` and the `SS` bit of the current
+/// exception level's SPSR so that the code the bootloader returns to executes
+/// one instruction and then traps. At EL2 debug exceptions are routed to EL2.
+pub fn enable_single_step() {
+    enable_debug_exceptions();
+    write_mdscr(mdscr_single_step(read_mdscr(), true));
+    write_spsr(read_spsr() | SPSR_SS);
+}
+
+/// Disarms single-step
+pub fn disable_single_step() {
+    write_mdscr(mdscr_single_step(read_mdscr(), false));
+    write_spsr(read_spsr() & !SPSR_SS);
+}
+
+/// Handles debug exceptions the bootloader knows how to resume from
+///
+/// Called by the synchronous exception handlers. Returns `true` if the
+/// exception was handled and execution can resume with `regs`, or `false` if
+/// it must be reported as fatal.
+pub fn handle_debug_exception(regs: &mut Regs) -> bool {
+    if is_breakpoint(regs.esr) {
+        let handler = unsafe { BREAKPOINT_HANDLER };
+        return handler.is_some_and(|handler| handler(regs));
+    }
+    if is_watchpoint(regs.esr) {
+        let handler = unsafe { WATCHPOINT_HANDLER };
+        if !handler.is_some_and(|handler| handler(regs)) {
+            return false;
+        }
+        step_over_watched(regs);
+        return true;
+    }
+    if !is_software_step(regs.esr) {
+        return false;
+    }
+
+    // Stepped over a watched access: watch again, and stop stepping unless
+    // it was stepping already
+    let stepping_over = &raw mut STEPPING_OVER;
+    if let Some(stepping) = unsafe { (*stepping_over).take() } {
+        arm_watchpoints(true);
+        if !stepping {
+            write_mdscr(mdscr_single_step(read_mdscr(), false));
+            regs.spsr &= !SPSR_SS;
+            return true;
+        }
+    }
+
+    let handler = unsafe { STEP_HANDLER };
+    let Some(handler) = handler else {
+        write_mdscr(mdscr_single_step(read_mdscr(), false));
+        return false;
+    };
+
+    // The step completed: SPSR.SS must be set again to step once more
+    if handler(regs) {
+        regs.spsr |= SPSR_SS;
+    } else {
+        write_mdscr(mdscr_single_step(read_mdscr(), false));
+        regs.spsr &= !SPSR_SS;
+    }
+    return true;
+}
+
+/// Reads MDSCR_EL1
+fn read_mdscr() -> u64 {
+    let mdscr: u64;
+
+    unsafe {
+        asm!("mrs {}, mdscr_el1", out(reg) mdscr, options(nomem, nostack));
+    }
+    return mdscr;
+}
+
+/// Writes MDSCR_EL1
+fn write_mdscr(mdscr: u64) {
+    unsafe {
+        asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr, options(nostack));
+    }
+}
+
+/// Writes the value/control register pair of breakpoint `index`
+///
+/// The register names encode the index, hence one instruction pair per index.
+fn write_breakpoint(index: u8, bvr: u64, bcr: u64) {
+    macro_rules! write_pair {
+        ($bvr:literal, $bcr:literal) => {
+            asm!(
+                concat!("msr ", $bvr, ", {}"),
+                concat!("msr ", $bcr, ", {}"),
+                "isb",
+                in(reg) bvr,
+                in(reg) bcr,
+                options(nostack)
+            )
+        };
+    }
+
+    unsafe {
+        match index {
+            0 => write_pair!("dbgbvr0_el1", "dbgbcr0_el1"),
+            1 => write_pair!("dbgbvr1_el1", "dbgbcr1_el1"),
+            2 => write_pair!("dbgbvr2_el1", "dbgbcr2_el1"),
+            3 => write_pair!("dbgbvr3_el1", "dbgbcr3_el1"),
+            4 => write_pair!("dbgbvr4_el1", "dbgbcr4_el1"),
+            5 => write_pair!("dbgbvr5_el1", "dbgbcr5_el1"),
+            6 => write_pair!("dbgbvr6_el1", "dbgbcr6_el1"),
+            7 => write_pair!("dbgbvr7_el1", "dbgbcr7_el1"),
+            8 => write_pair!("dbgbvr8_el1", "dbgbcr8_el1"),
+            9 => write_pair!("dbgbvr9_el1", "dbgbcr9_el1"),
+            10 => write_pair!("dbgbvr10_el1", "dbgbcr10_el1"),
+            11 => write_pair!("dbgbvr11_el1", "dbgbcr11_el1"),
+            12 => write_pair!("dbgbvr12_el1", "dbgbcr12_el1"),
+            13 => write_pair!("dbgbvr13_el1", "dbgbcr13_el1"),
+            14 => write_pair!("dbgbvr14_el1", "dbgbcr14_el1"),
+            _ => write_pair!("dbgbvr15_el1", "dbgbcr15_el1"),
+        }
+    }
+}
+
+/// Writes the value/control register pair of watchpoint `index`
+fn write_watchpoint(index: u8, wvr: u64, wcr: u64) {
+    macro_rules! write_pair {
+        ($wvr:literal, $wcr:literal) => {
+            asm!(
+                concat!("msr ", $wvr, ", {}"),
+                concat!("msr ", $wcr, ", {}"),
+                "isb",
+                in(reg) wvr,
+                in(reg) wcr,
+                options(nostack)
+            )
+        };
+    }
+
+    unsafe {
+        match index {
+            0 => write_pair!("dbgwvr0_el1", "dbgwcr0_el1"),
+            1 => write_pair!("dbgwvr1_el1", "dbgwcr1_el1"),
+            2 => write_pair!("dbgwvr2_el1", "dbgwcr2_el1"),
+            3 => write_pair!("dbgwvr3_el1", "dbgwcr3_el1"),
+            4 => write_pair!("dbgwvr4_el1", "dbgwcr4_el1"),
+            5 => write_pair!("dbgwvr5_el1", "dbgwcr5_el1"),
+            6 => write_pair!("dbgwvr6_el1", "dbgwcr6_el1"),
+            7 => write_pair!("dbgwvr7_el1", "dbgwcr7_el1"),
+            8 => write_pair!("dbgwvr8_el1", "dbgwcr8_el1"),
+            9 => write_pair!("dbgwvr9_el1", "dbgwcr9_el1"),
+            10 => write_pair!("dbgwvr10_el1", "dbgwcr10_el1"),
+            11 => write_pair!("dbgwvr11_el1", "dbgwcr11_el1"),
+            12 => write_pair!("dbgwvr12_el1", "dbgwcr12_el1"),
+            13 => write_pair!("dbgwvr13_el1", "dbgwcr13_el1"),
+            14 => write_pair!("dbgwvr14_el1", "dbgwcr14_el1"),
+            _ => write_pair!("dbgwvr15_el1", "dbgwcr15_el1"),
+        }
+    }
+}
+
+/// Reads the SPSR of the current exception level
+fn read_spsr() -> u64 {
+    let spsr: u64;
+
+    unsafe {
+        match cpu::current_el() {
+            1 => asm!("mrs {}, spsr_el1", out(reg) spsr, options(nomem, nostack)),
+            2 => asm!("mrs {}, spsr_el2", out(reg) spsr, options(nomem, nostack)),
+            _ => asm!("mrs {}, spsr_el3", out(reg) spsr, options(nomem, nostack)),
+        }
+    }
+    return spsr;
+}
+
+/// Writes the SPSR of the current exception level
+fn write_spsr(spsr: u64) {
+    unsafe {
+        match cpu::current_el() {
+            1 => asm!("msr spsr_el1, {}", in(reg) spsr, options(nostack)),
+            2 => asm!("msr spsr_el2, {}", in(reg) spsr, options(nostack)),
+            _ => asm!("msr spsr_el3, {}", in(reg) spsr, options(nostack)),
+        }
+    }
+}
+
+/// Variable the self-test watches
+static mut TEST_WATCHED: u64 = 0;
+/// Watchpoint exceptions seen by the self-test, and the last one
+static mut TEST_HITS: (u32, Option<WatchHit>) = (0, None);
+
+/// Watchpoint callback of the self-test, recording the hit and resuming
+fn test_watch_hit(regs: &mut Regs) -> bool {
+    let hit = watch_hit(regs);
+
+    unsafe {
+        TEST_HITS = (TEST_HITS.0 + 1, Some(hit));
+    }
+    return true;
+}
+
+/// Stores `value` at `addr` and returns the address of the store
+#[inline(never)]
+fn test_poke(addr: *mut u64, value: u64) -> u64 {
+    let pc: u64;
+
+    unsafe {
+        asm!(
+            "adr {pc}, 1f",
+            "1: str {value}, [{addr}]",
+            pc = out(reg) pc,
+            value = in(reg) value,
+            addr = in(reg) addr,
+            options(nostack)
+        );
+    }
+    return pc;
+}
+
+/// Breakpoint exceptions seen by the breakpoint self-test, and the address
+/// of the last one
+static mut TEST_BREAKS: (u32, u64) = (0, 0);
+
+/// Function the breakpoint self-test sets its breakpoint on
+#[inline(never)]
+fn test_break_target() {
+    unsafe {
+        asm!("nop", options(nomem, nostack));
+    }
+}
+
+/// Breakpoint callback of the self-test: records the hit and clears the
+/// breakpoint, so execution resumes past it
+fn test_break_hit(regs: &mut Regs) -> bool {
+    unsafe {
+        TEST_BREAKS = (TEST_BREAKS.0 + 1, regs.elr);
+    }
+    clear_breakpoint(breakpoint_count() - 1);
+    return true;
+}
+
+/// Self-test: a breakpoint on a function is reported with the function's
+/// address when it's called, and calling it again once the handler cleared
+/// the breakpoint doesn't trap
+///
+/// Uses the last breakpoint, at the bootloader's own exception level, with
+/// debug exceptions unmasked for the test only.
+pub fn selftest_breakpoint() -> Outcome {
+    let target = test_break_target as *const () as u64;
+    let handler = unsafe { BREAKPOINT_HANDLER };
+    let daif: u64;
+
+    unsafe {
+        TEST_BREAKS = (0, 0);
+        asm!("mrs {}, daif", "msr daifclr, #8", out(reg) daif, options(nostack));
+    }
+    set_breakpoint_handler(Some(test_break_hit));
+    set_breakpoint(breakpoint_count() - 1, target);
+    test_break_target();
+    let first = unsafe { TEST_BREAKS };
+    test_break_target();
+    let (hits, _) = unsafe { TEST_BREAKS };
+    clear_breakpoint(breakpoint_count() - 1);
+    set_breakpoint_handler(handler);
+    unsafe {
+        asm!("msr daif, {}", in(reg) daif, options(nostack));
+    }
+
+    if first == (1, target) && hits == 1 {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// `brk` immediate arming the single-step self-test, claimed by no other
+/// handler
+const TEST_STEP_BRK_IMM: u16 = 0x5357;
+
+#[cfg(not(feature = "stage1"))]
+const _: () = assert!(TEST_STEP_BRK_IMM != crate::monitor::MONITOR_BRK_IMM);
+
+/// Instructions the single-step self-test steps through
+const TEST_STEPS: u32 = 3;
+
+/// Steps taken by the single-step self-test, and the addresses of the first
+/// and the last
+static mut TEST_STEPPED: (u32, u64, u64) = (0, 0, 0);
+
+/// `brk` callback of the single-step self-test: arms the step of the code
+/// following the `brk`
+fn test_arm_step(imm: u16, regs: &mut Regs) {
+    if imm != TEST_STEP_BRK_IMM {
+        return;
+    }
+    enable_debug_exceptions();
+    write_mdscr(mdscr_single_step(read_mdscr(), true));
+    regs.spsr |= SPSR_SS;
+}
+
+/// Step callback of the single-step self-test, recording the step and going
+/// on for [`TEST_STEPS`] instructions
+fn test_step(regs: &mut Regs) -> bool {
+    let stepped = &raw mut TEST_STEPPED;
+    let stepped = unsafe { &mut *stepped };
+
+    if stepped.0 == 0 {
+        stepped.1 = regs.elr;
+    }
+    stepped.0 += 1;
+    stepped.2 = regs.elr;
+    return stepped.0 < TEST_STEPS;
+}
+
+/// Self-test: armed from a `brk`, exactly three instructions are
+/// stepped, one at a time, and the code after them runs normally
+///
+/// Steps at the bootloader's own exception level, with debug exceptions
+/// unmasked for the test only.
+pub fn selftest_single_step() -> Outcome {
+    let step_handler = unsafe { STEP_HANDLER };
+    let brk_handler = exception::set_brk_handler(Some(test_arm_step));
+    let daif: u64;
+
+    unsafe {
+        TEST_STEPPED = (0, 0, 0);
+        asm!("mrs {}, daif", "msr daifclr, #8", out(reg) daif, options(nostack));
+    }
+    set_step_handler(Some(test_step));
+    unsafe {
+        asm!(
+            "brk #{imm}",
+            "nop",
+            "nop",
+            "nop",
+            "nop",
+            "nop",
+            imm = const TEST_STEP_BRK_IMM,
+            options(nostack)
+        );
+    }
+    set_step_handler(step_handler);
+    exception::set_brk_handler(brk_handler);
+    unsafe {
+        asm!("msr daif, {}", in(reg) daif, options(nostack));
+    }
+
+    let (steps, first, last) = unsafe { TEST_STEPPED };
+    let disarmed = read_mdscr() & MDSCR_SS == 0;
+    if steps == TEST_STEPS && last - first == 4 * (TEST_STEPS as u64 - 1) && disarmed {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Self-test: a store to a watched variable is reported with the storing
+/// instruction and the variable's address, then happens once resumed; the
+/// watchpoint is back after the step over it, and loads aren't trapped
+///
+/// Uses the first free watchpoint, at the bootloader's own exception level,
+/// with debug exceptions unmasked for the test only.
+pub fn selftest_watchpoint() -> Outcome {
+    let watched = &raw mut TEST_WATCHED;
+    let addr = watched as u64;
+    let Some(index) = (0..watchpoint_count()).find(|&i| watchpoint(i).is_none()) else {
+        return Outcome::Skipped;
+    };
+    let handler = watchpoint_handler();
+    let daif: u64;
+
+    unsafe {
+        TEST_HITS = (0, None);
+        asm!("mrs {}, daif", "msr daifclr, #8", out(reg) daif, options(nostack));
+    }
+    set_watchpoint_handler(Some(test_watch_hit));
+    set_watchpoint(
+        index,
+        Watch {
+            addr,
+            len: 8,
+            access: WatchAccess::Write,
+        },
+    );
+    let pc = test_poke(watched, 0x5a5a);
+    let stored = unsafe { ptr::read_volatile(watched) };
+    let (hits, first) = unsafe { TEST_HITS };
+    test_poke(watched, 0xa5a5);
+    let (rearmed, _) = unsafe { TEST_HITS };
+    clear_watchpoint(index);
+    set_watchpoint_handler(handler);
+    unsafe {
+        asm!("msr daif, {}", in(reg) daif, options(nostack));
+    }
+
+    let reported = first
+        == Some(WatchHit {
+            index: Some(index),
+            addr,
+            pc,
+            write: true,
+        });
+    if reported && hits == 1 && stored == 0x5a5a && rearmed == 2 {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Registers the self-tests of the debug support
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"breakpoint",
+        run: selftest_breakpoint,
+    });
+    selftest::register(SelfTest {
+        name: b"single-step",
+        run: selftest_single_step,
+    });
+    selftest::register(SelfTest {
+        name: b"watchpoint",
+        run: selftest_watchpoint,
+    });
+}
@@ -0,0 +1,235 @@
+//! Diagnostic switches for payload debugging
+//!
+//! This module provides toggles that make the CPU stricter than usual, so
+//! bugs in a payload surface as exceptions with a clear cause (see
+//! [`exception`](crate::exception)) rather than silently working on one core
+//! and failing on another.
+//!
+//! The switches apply to the exception level the bootloader runs at, which is
+//! also where payloads started from the monitor with `go` run.
+//!
+//! [`crash`] deliberately triggers each kind of fatal failure, listed in
+//! [`CRASHES`], to check on real hardware that the fatal paths themselves
+//! work: each must end with the decoded dump, the panic report and the halt,
+//! with the exception kept in the boot record for the next boot.
+
+use crate::cpu;
+use crate::utilities::mmio;
+
+use crate::asm;
+use core::hint::black_box;
+
+/// SCTLR_ELx.A: alignment checking of data accesses
+const SCTLR_A: u64 = 1 << 1;
+/// SCTLR_ELx.SA: alignment checking of the stack pointer
+const SCTLR_SA: u64 = 1 << 3;
+/// Address past the physical address range of most cores, where both
+/// instruction fetches and data accesses abort
+const ABORT_ADDR: usize = 0xffff_ffff_f000;
+/// `brk` immediate no handler claims
+const CRASH_BRK_IMM: u16 = 0xdead;
+
+#[cfg(not(feature = "stage1"))]
+const _: () = assert!(CRASH_BRK_IMM != crate::monitor::MONITOR_BRK_IMM);
+
+/// A deliberately fatal scenario
+#[derive(Clone, Copy)]
+pub struct Crash {
+    /// Name used to select the scenario
+    pub name: &'static [u8],
+    /// What the scenario does
+    pub description: &'static [u8],
+    /// Function triggering the failure
+    run: fn() -> !,
+}
+
+/// Scenarios run by [`crash`]
+pub const CRASHES: [Crash; 6] = [
+    Crash {
+        name: b"sp-align",
+        description: b"Access memory through a misaligned SP",
+        run: crash_sp_align,
+    },
+    Crash {
+        name: b"brk",
+        description: b"Execute a brk no handler claims",
+        run: crash_brk,
+    },
+    Crash {
+        name: b"wild-branch",
+        description: b"Branch to an address with nothing to execute",
+        run: crash_wild_branch,
+    },
+    Crash {
+        name: b"stack-overflow",
+        description: b"Recurse past the bottom of the boot stack",
+        run: crash_stack_overflow,
+    },
+    Crash {
+        name: b"panic",
+        description: b"Call panic!",
+        run: crash_panic,
+    },
+    Crash {
+        name: b"serror",
+        description: b"Read a device address that aborts (SError where supported)",
+        run: crash_serror,
+    },
+];
+
+/// Enables or disables strict alignment checking (SCTLR_ELx.A)
+///
+/// With checking enabled, any unaligned data access raises an alignment
+/// fault, even to Normal memory. The bootloader itself is built for
+/// unaligned accesses to be allowed, so checking should only be left on
+/// while running code under test. Returns whether checking was enabled
+/// before.
+pub fn alignment_check(enable: bool) -> bool {
+    return update_sctlr(SCTLR_A, enable);
+}
+
+/// Sets or clears `bits` in SCTLR_ELx of the current exception level
+///
+/// Returns whether they were all set before.
+fn update_sctlr(bits: u64, set: bool) -> bool {
+    let el = cpu::current_el();
+    let mut sctlr: u64;
+
+    unsafe {
+        match el {
+            1 => asm!("mrs {}, sctlr_el1", out(reg) sctlr),
+            2 => asm!("mrs {}, sctlr_el2", out(reg) sctlr),
+            _ => asm!("mrs {}, sctlr_el3", out(reg) sctlr),
+        }
+    }
+    let previous = sctlr & bits == bits;
+
+    if set {
+        sctlr |= bits;
+    } else {
+        sctlr &= !bits;
+    }
+    unsafe {
+        match el {
+            1 => asm!("msr sctlr_el1, {}", in(reg) sctlr),
+            2 => asm!("msr sctlr_el2, {}", in(reg) sctlr),
+            _ => asm!("msr sctlr_el3, {}", in(reg) sctlr),
+        }
+        asm!("isb");
+    }
+    return previous;
+}
+
+/// Checks whether strict alignment checking is enabled
+pub fn alignment_check_enabled() -> bool {
+    let sctlr: u64;
+
+    unsafe {
+        match cpu::current_el() {
+            1 => asm!("mrs {}, sctlr_el1", out(reg) sctlr),
+            2 => asm!("mrs {}, sctlr_el2", out(reg) sctlr),
+            _ => asm!("mrs {}, sctlr_el3", out(reg) sctlr),
+        }
+    }
+    return sctlr & SCTLR_A != 0;
+}
+
+/// Triggers the fatal scenario called `name`
+///
+/// Doesn't return if there is such a scenario (see [`CRASHES`]); returns
+/// `false` otherwise.
+pub fn crash(name: &[u8]) -> bool {
+    match CRASHES.iter().find(|c| c.name == name) {
+        Some(crash) => (crash.run)(),
+        None => return false,
+    }
+}
+
+/// Loads through a stack pointer 8 bytes off, with SP alignment checking on
+fn crash_sp_align() -> ! {
+    update_sctlr(SCTLR_SA, true);
+    unsafe {
+        asm!(
+            "mov x9, sp",
+            "sub x9, x9, #8",
+            "mov sp, x9",
+            "ldr x9, [sp]",
+            // Faults even if SP alignment checking is unimplemented
+            "udf #0",
+            options(noreturn),
+        );
+    }
+}
+
+/// Executes `brk` with an immediate neither the monitor nor the debugger
+/// claims
+fn crash_brk() -> ! {
+    unsafe {
+        asm!("brk #{imm}", imm = const CRASH_BRK_IMM, options(nomem, nostack));
+    }
+    panic!("brk returned");
+}
+
+/// Branches to [`ABORT_ADDR`]
+fn crash_wild_branch() -> ! {
+    unsafe {
+        asm!("br {}", in(reg) ABORT_ADDR, options(noreturn));
+    }
+}
+
+/// Returns the current stack pointer
+fn stack_pointer() -> usize {
+    let sp: usize;
+
+    unsafe {
+        asm!("mov {}, sp", out(reg) sp, options(nomem, nostack));
+    }
+    return sp;
+}
+
+/// Recurses with 512-byte frames until the stack runs out
+///
+/// Without an MMU there is no guard page to fault on, so the recursion
+/// stops one frame past `limit` and faults itself, as a guard page would.
+#[inline(never)]
+fn recurse(limit: usize) -> u8 {
+    let mut frame = black_box([0u8; 512]);
+
+    if stack_pointer() < limit {
+        unsafe {
+            asm!("udf #0", options(noreturn));
+        }
+    }
+    frame[0] = recurse(limit);
+    return black_box(frame)[0];
+}
+
+/// Recurses past the bottom of the boot stack
+fn crash_stack_overflow() -> ! {
+    let limit = &raw const boot_stack_limit as usize;
+
+    recurse(limit);
+    panic!("stack overflow not caught");
+}
+
+/// Panics
+fn crash_panic() -> ! {
+    panic!("crash test");
+}
+
+/// Reads [`ABORT_ADDR`] without the MMIO probe
+///
+/// QEMU reports a synchronous data abort; real interconnects often signal
+/// an SError instead.
+fn crash_serror() -> ! {
+    unsafe {
+        black_box(mmio::read_mmio32(ABORT_ADDR, 0));
+        asm!("dsb sy", "isb", options(nostack));
+    }
+    panic!("device access didn't abort");
+}
+
+unsafe extern "C" {
+    /// Lowest address of the boot stack (provided by the linker script)
+    static boot_stack_limit: u8;
+}
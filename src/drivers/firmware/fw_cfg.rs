@@ -0,0 +1,224 @@
+//! QEMU fw_cfg driver
+//!
+//! QEMU hands files to the guest, and takes some back, through its firmware
+//! configuration device: an item is selected by its 16-bit key, then read
+//! through the data register or transferred by DMA. Named items (e.g.,
+//! `etc/ramfb`) are listed in the file directory, where [`find_file`] looks
+//! up their key and size. Items can only be written by DMA, see
+//! [`write_file`].
+//!
+//! Only the MMIO interface of Arm machines is handled. Like the PL031, the
+//! device is only known when found in the device tree (see
+//! [`registry`](crate::drivers::registry)).
+
+use crate::drivers::lifecycle::{Lifecycle, WrongState};
+use crate::selftest::Outcome;
+use crate::utilities::mmio::{self, Width};
+
+use core::ptr;
+use core::sync::atomic::{Ordering, fence};
+
+/// Data register offset: reads the selected item a byte at a time
+const DATA_OFF: usize = 0x00;
+/// Selector register offset (big-endian, 16 bits)
+const SELECTOR_OFF: usize = 0x08;
+/// DMA address register offset (big-endian, 64 bits)
+const DMA_OFF: usize = 0x10;
+/// Key of the signature item
+const KEY_SIGNATURE: u16 = 0x0000;
+/// Key of the feature bitmap item (little-endian, 32 bits)
+const KEY_ID: u16 = 0x0001;
+/// Key of the file directory item
+const KEY_FILE_DIR: u16 = 0x0019;
+/// Contents of the signature item
+const SIGNATURE: [u8; 4] = *b"QEMU";
+/// Feature bit: the DMA interface is available
+const ID_DMA: u32 = 1 << 1;
+/// DMA control bit: the transfer failed
+const DMA_ERROR: u32 = 1 << 0;
+/// DMA control bit: select the item in the upper 16 bits first
+const DMA_SELECT: u32 = 1 << 3;
+/// DMA control bit: write to the item
+const DMA_WRITE: u32 = 1 << 4;
+/// Size of a file name in the file directory, NUL included
+const FILE_NAME_SIZE: usize = 56;
+/// Size of a file directory entry: size, key, reserved and name
+const FILE_ENTRY_SIZE: usize = 8 + FILE_NAME_SIZE;
+
+/// Errors reported by the fw_cfg driver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FwCfgError {
+    /// No fw_cfg device is known, or it doesn't have the QEMU signature
+    NotPresent,
+    /// The device has no DMA interface, so items can't be written
+    NoDma,
+    /// The device reported an error for a DMA transfer
+    DmaFailed,
+}
+
+/// A file of the fw_cfg file directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct File {
+    /// Key selecting the file
+    pub key: u16,
+    /// Size of the file in bytes
+    pub size: u32,
+}
+
+/// DMA access descriptor, all fields big-endian
+#[repr(C)]
+struct DmaAccess {
+    /// Operation, then 0 once done (or [`DMA_ERROR`])
+    control: u32,
+    /// Number of bytes to transfer
+    length: u32,
+    /// Physical address of the guest buffer
+    address: u64,
+}
+
+/// Base address of the device, 0 until [`init`] is called
+static mut BASE: usize = 0;
+/// Lifecycle of the driver
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Records the base address of the fw_cfg device at `base`
+///
+/// Fails, changing nothing, if the driver is already set up.
+pub fn init(base: usize) -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).start()?;
+        BASE = base;
+    }
+    return Ok(());
+}
+
+/// Stops the driver for the payload
+///
+/// DMA transfers complete before returning, so none is left in flight. Fails
+/// if it isn't set up or is already quiesced.
+pub fn quiesce() -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe { (*lifecycle).quiesce() }?;
+    return Ok(());
+}
+
+/// Forgets the device, so [`init`] can set it up again
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        BASE = 0;
+    }
+}
+
+/// Returns the base address of the device, if [`init`] was called
+pub fn base() -> Option<usize> {
+    let base = unsafe { BASE };
+
+    return (base != 0).then_some(base);
+}
+
+/// Selects the item `key` and reads its first `buf.len()` bytes
+fn read_item(base: usize, key: u16, buf: &mut [u8]) {
+    unsafe {
+        mmio::write_mmio(base, SELECTOR_OFF, Width::B16, key.swap_bytes() as u64);
+        for byte in buf.iter_mut() {
+            *byte = mmio::read_mmio(base, DATA_OFF, Width::B8) as u8;
+        }
+    }
+}
+
+/// Returns the base address of the device, if it has the QEMU signature
+fn device() -> Result<usize, FwCfgError> {
+    let base = base().ok_or(FwCfgError::NotPresent)?;
+    let mut signature = [0u8; 4];
+
+    read_item(base, KEY_SIGNATURE, &mut signature);
+    if signature != SIGNATURE {
+        return Err(FwCfgError::NotPresent);
+    }
+    return Ok(base);
+}
+
+/// Looks `name` up in the file directory
+///
+/// Returns `Ok(None)` if QEMU doesn't provide that file.
+pub fn find_file(name: &[u8]) -> Result<Option<File>, FwCfgError> {
+    let base = device()?;
+    let mut count = [0u8; 4];
+    let mut entry = [0u8; FILE_ENTRY_SIZE];
+
+    // The entries are read on from where the count ends
+    read_item(base, KEY_FILE_DIR, &mut count);
+    for _ in 0..u32::from_be_bytes(count) {
+        for byte in entry.iter_mut() {
+            *byte = unsafe { mmio::read_mmio(base, DATA_OFF, Width::B8) } as u8;
+        }
+        let entry_name = &entry[8..];
+        let len = entry_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(FILE_NAME_SIZE);
+        if &entry_name[..len] == name {
+            return Ok(Some(File {
+                key: u16::from_be_bytes([entry[4], entry[5]]),
+                size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]),
+            }));
+        }
+    }
+    return Ok(None);
+}
+
+/// Writes `data` to the start of `file` by DMA
+///
+/// The transfer completes before returning; QEMU acts on the file (e.g.,
+/// sets up the display for `etc/ramfb`) as it does.
+pub fn write_file(file: &File, data: &[u8]) -> Result<(), FwCfgError> {
+    let base = device()?;
+    let mut id = [0u8; 4];
+
+    read_item(base, KEY_ID, &mut id);
+    if u32::from_le_bytes(id) & ID_DMA == 0 {
+        return Err(FwCfgError::NoDma);
+    }
+    let mut access = DmaAccess {
+        control: ((file.key as u32) << 16 | DMA_SELECT | DMA_WRITE).to_be(),
+        length: (data.len() as u32).to_be(),
+        address: (data.as_ptr() as u64).to_be(),
+    };
+
+    // The descriptor and the data must be in memory before QEMU reads them
+    fence(Ordering::SeqCst);
+    unsafe {
+        let address = &raw mut access as u64;
+        mmio::write_mmio(base, DMA_OFF, Width::B64, address.swap_bytes());
+    }
+    loop {
+        let control = u32::from_be(unsafe { ptr::read_volatile(&raw const access.control) });
+        if control & DMA_ERROR != 0 {
+            return Err(FwCfgError::DmaFailed);
+        }
+        if control == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Self-test: the device has the QEMU signature and its file directory can
+/// be searched
+///
+/// Skipped if no fw_cfg device is known.
+pub fn selftest() -> Outcome {
+    if base().is_none() {
+        return Outcome::Skipped;
+    }
+    return match find_file(b"") {
+        Ok(None) => Outcome::Pass,
+        _ => Outcome::Fail,
+    };
+}
@@ -0,0 +1,3 @@
+//! Firmware interface drivers module
+
+pub mod fw_cfg;
@@ -0,0 +1,154 @@
+//! CFI parallel NOR flash driver
+//!
+//! This module programs NOR flash using the Intel/Sharp command set (CFI
+//! primary vendor command set 0x0001), as emulated by QEMU's `pflash_cfi01`.
+//! The flash is assumed to sit on a 32-bit bus made of two interleaved x16
+//! devices, like the flash banks of QEMU's `virt` machine, so every command
+//! is sent to both devices at once.
+//!
+//! Outside of [`erase_block`] and [`program`] the flash is left in read array
+//! mode, where it can be read like memory.
+
+use crate::board;
+use crate::selftest::Outcome;
+use crate::utilities::mmio;
+
+/// Replicates a command or status byte into both x16 devices of the bus
+const fn lanes(value: u8) -> u32 {
+    return value as u32 * 0x0001_0001;
+}
+
+/// CFI query command
+const CMD_CFI_QUERY: u32 = lanes(0x98);
+/// Word offset the CFI query command is written to
+const CFI_QUERY_ADDR: usize = 0x55;
+/// Word offset of the "QRY" signature in CFI query mode
+const CFI_SIGNATURE_ADDR: usize = 0x10;
+/// Read array command: back to reading the flash contents
+const CMD_READ_ARRAY: u32 = lanes(0xff);
+/// Read status register command
+const CMD_READ_STATUS: u32 = lanes(0x70);
+/// Clear status register command
+const CMD_CLEAR_STATUS: u32 = lanes(0x50);
+/// Block erase setup command
+const CMD_BLOCK_ERASE: u32 = lanes(0x20);
+/// Word program setup command
+const CMD_PROGRAM: u32 = lanes(0x40);
+/// Block lock setup command
+const CMD_LOCK_SETUP: u32 = lanes(0x60);
+/// Confirm command for erase and unlock
+const CMD_CONFIRM: u32 = lanes(0xd0);
+/// Status register: device ready
+const SR_READY: u32 = lanes(1 << 7);
+/// Status register: erase, program, voltage or lock error
+const SR_ERRORS: u32 = lanes(0b0011_1010);
+
+/// Sends `cmd` to the flash at `addr`
+unsafe fn command(addr: usize, cmd: u32) {
+    unsafe {
+        mmio::write_mmio32(addr, 0, cmd);
+    }
+}
+
+/// Waits for the current operation to finish and returns to read array mode
+///
+/// Returns `false` if the operation reported an error.
+unsafe fn wait_ready(addr: usize) -> bool {
+    let mut status;
+
+    unsafe {
+        command(addr, CMD_READ_STATUS);
+        loop {
+            status = mmio::read_mmio32(addr, 0);
+            if status & SR_READY == SR_READY {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        command(addr, CMD_CLEAR_STATUS);
+        command(addr, CMD_READ_ARRAY);
+    }
+    return status & SR_ERRORS == 0;
+}
+
+/// Checks whether the flash at `base` answers a CFI query
+///
+/// Reads the "QRY" signature from both devices of the bus, then returns to
+/// read array mode.
+///
+/// # Safety
+///
+/// `base` must be the address of a CFI flash with the layout described in
+/// the module documentation.
+pub unsafe fn query(base: usize) -> bool {
+    let mut found = true;
+
+    unsafe {
+        command(base + CFI_QUERY_ADDR * 4, CMD_CFI_QUERY);
+        for (i, &c) in b"QRY".iter().enumerate() {
+            let value = mmio::read_mmio32(base, (CFI_SIGNATURE_ADDR + i) * 4);
+            found &= value == lanes(c);
+        }
+        command(base, CMD_READ_ARRAY);
+    }
+    return found;
+}
+
+/// Self-test: the board flash must answer a CFI query
+///
+/// Skipped if the board has no flash.
+pub fn selftest() -> Outcome {
+    let Some(flash) = board::config().flash else {
+        return Outcome::Skipped;
+    };
+
+    match unsafe { query(flash.base) } {
+        true => return Outcome::Pass,
+        false => return Outcome::Fail,
+    }
+}
+
+/// Unlocks and erases the block starting at `addr`
+///
+/// Returns `false` if the flash reported an error.
+///
+/// # Safety
+///
+/// `addr` must be the address of an erase block of a CFI flash with the
+/// layout described in the module documentation.
+pub unsafe fn erase_block(addr: usize) -> bool {
+    unsafe {
+        command(addr, CMD_LOCK_SETUP);
+        command(addr, CMD_CONFIRM);
+        if !wait_ready(addr) {
+            return false;
+        }
+        command(addr, CMD_BLOCK_ERASE);
+        command(addr, CMD_CONFIRM);
+        return wait_ready(addr);
+    }
+}
+
+/// Programs `data` at `addr`, one 32-bit word at a time
+///
+/// The destination must have been erased. Returns `false` if the flash
+/// reported an error.
+///
+/// # Safety
+///
+/// `addr` must be 4-byte aligned and lie in a CFI flash with the layout
+/// described in the module documentation, with room for `data`.
+pub unsafe fn program(addr: usize, data: &[u32]) -> bool {
+    for (i, &word) in data.iter().enumerate() {
+        let dst = addr + i * 4;
+
+        unsafe {
+            command(dst, CMD_PROGRAM);
+            mmio::write_mmio32(dst, 0, word);
+            if !wait_ready(dst) {
+                return false;
+            }
+        }
+    }
+    return true;
+}
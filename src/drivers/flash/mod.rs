@@ -0,0 +1,3 @@
+//! Flash drivers module
+
+pub mod cfi;
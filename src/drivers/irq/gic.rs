@@ -0,0 +1,441 @@
+//! ARM Generic Interrupt Controller driver
+//!
+//! This module provides a minimal driver for GICv2 and GICv3, enough for the
+//! bootloader to take the private interrupts (SGIs and PPIs) of the boot CPU,
+//! such as the generic timer interrupt. Shared peripheral interrupts are not
+//! supported.
+//!
+//! With GICv3 the CPU interface is accessed through the `ICC_*` system
+//! registers and the redistributor of the boot CPU is assumed to be the first
+//! one at the configured base address.
+
+use crate::board::{GicConfig, GicKind};
+use crate::cpu;
+use crate::drivers::timer::generic;
+use crate::interrupt;
+use crate::selftest::Outcome;
+use crate::utilities::mmio::{self, MmioBus, PhysBus};
+
+use crate::asm;
+
+/// Interrupt ID returned when no interrupt is pending
+pub const SPURIOUS_INTID: u32 = 1023;
+/// Number of private interrupts (SGIs and PPIs)
+const PRIVATE_INTIDS: u32 = 32;
+/// Priority given to enabled interrupts (lower is more urgent)
+const DEFAULT_PRIORITY: u8 = 0xa0;
+/// Priority mask letting every priority through
+const PRIORITY_MASK_ALL: u32 = 0xff;
+
+// Distributor registers (both versions)
+/// Distributor Control Register offset
+const GICD_CTLR: usize = 0x0000;
+/// GICD_CTLR Enable bit (GICv2) / EnableGrp0 bit (GICv3)
+const GICD_CTLR_ENABLE: u32 = 1 << 0;
+/// GICD_CTLR EnableGrp1 bit (GICv3, non-secure view: EnableGrp1A)
+const GICD_CTLR_ENABLE_GRP1: u32 = 1 << 1;
+/// GICD_CTLR Affinity routing enable bit (GICv3, non-secure view: ARE_NS)
+const GICD_CTLR_ARE: u32 = 1 << 4;
+/// GICD_CTLR Register write pending bit (GICv3)
+const GICD_CTLR_RWP_BIT: u32 = 31;
+/// Interrupt Set-Enable Registers offset (GICv2 banked for private IDs)
+const GICD_ISENABLER: usize = 0x0100;
+/// Interrupt Clear-Enable Registers offset
+const GICD_ICENABLER: usize = 0x0180;
+/// Interrupt Priority Registers offset
+const GICD_IPRIORITYR: usize = 0x0400;
+/// Software Generated Interrupt Register offset (GICv2)
+const GICD_SGIR: usize = 0x0f00;
+/// GICD_SGIR TargetListFilter: forward to the requesting CPU only
+const GICD_SGIR_TO_SELF: u32 = 0b10 << 24;
+
+// GICv2 CPU interface registers
+/// CPU Interface Control Register offset
+const GICC_CTLR: usize = 0x00;
+/// GICC_CTLR Enable bit
+const GICC_CTLR_ENABLE: u32 = 1 << 0;
+/// Priority Mask Register offset
+const GICC_PMR: usize = 0x04;
+/// Interrupt Acknowledge Register offset
+const GICC_IAR: usize = 0x0c;
+/// End of Interrupt Register offset
+const GICC_EOIR: usize = 0x10;
+/// GICC_IAR interrupt ID field mask
+const GICC_IAR_INTID_MASK: u64 = 0x3ff;
+
+// GICv3 redistributor registers
+/// Redistributor Wake Register offset
+const GICR_WAKER: usize = 0x0014;
+/// GICR_WAKER ProcessorSleep bit
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+/// GICR_WAKER ChildrenAsleep bit
+const GICR_WAKER_CHILDREN_ASLEEP_BIT: u32 = 2;
+/// Offset of the SGI and PPI frame from the redistributor base
+const GICR_SGI_BASE: usize = 0x1_0000;
+/// Interrupt Group Register 0 offset (SGI frame)
+const GICR_IGROUPR0: usize = 0x0080;
+/// Interrupt Set-Enable Register 0 offset (SGI frame)
+const GICR_ISENABLER0: usize = 0x0100;
+/// Interrupt Clear-Enable Register 0 offset (SGI frame)
+const GICR_ICENABLER0: usize = 0x0180;
+/// Interrupt Priority Registers offset (SGI frame)
+const GICR_IPRIORITYR: usize = 0x0400;
+
+/// ICC_SGI1R_EL1 INTID field shift
+const ICC_SGI1R_INTID_SHIFT: u64 = 24;
+/// ICC_SGI1R_EL1 Aff1 field shift
+const ICC_SGI1R_AFF1_SHIFT: u64 = 16;
+/// ICC_SGI1R_EL1 Aff2 field shift
+const ICC_SGI1R_AFF2_SHIFT: u64 = 32;
+/// ICC_SGI1R_EL1 Aff3 field shift
+const ICC_SGI1R_AFF3_SHIFT: u64 = 48;
+/// ICC_SGI1R_EL1 RS (range selector) field shift
+const ICC_SGI1R_RS_SHIFT: u64 = 44;
+/// ICC_IAR1_EL1 interrupt ID field mask
+const ICC_IAR_INTID_MASK: u64 = 0xff_ffff;
+/// ICC_SRE_ELx System register enable bit
+const ICC_SRE_SRE: u64 = 1 << 0;
+/// ICC_SRE_EL2 Enable bit: lets EL1 access ICC_SRE_EL1
+const ICC_SRE_EL2_ENABLE: u64 = 1 << 3;
+
+/// SGI sent by [`selftest`]
+const SELFTEST_SGI: u32 = 15;
+/// Time allowed for the SGI to be delivered, in microseconds
+const SELFTEST_TIMEOUT_US: u64 = 10_000;
+
+/// Configuration of the GIC, set by [`init`]
+static mut GIC: GicConfig = GicConfig {
+    kind: GicKind::None,
+    dist_base: 0,
+    cpu_base: 0,
+};
+
+/// Returns the configuration of the initialized GIC
+fn gic() -> GicConfig {
+    let gic = &raw const GIC;
+
+    unsafe {
+        return *gic;
+    }
+}
+
+/// Initializes the distributor and the CPU interface of the boot CPU
+///
+/// All private interrupts are left disabled; enable the ones to be taken
+/// with [`enable_private`]. Returns `false` if there is no supported GIC, or
+/// if a GICv3 is used from an exception level other than EL1 or EL2.
+pub fn init(config: &GicConfig) -> bool {
+    let ok = init_on(&mut PhysBus, config);
+
+    if ok {
+        unsafe {
+            GIC = *config;
+        }
+    }
+    return ok;
+}
+
+/// Performs the bring-up of [`init`] through `bus`, without making `config`
+/// the GIC the other functions use
+///
+/// The GICv3 CPU interface is programmed through its system registers, not
+/// `bus`.
+pub fn init_on<B: MmioBus>(bus: &mut B, config: &GicConfig) -> bool {
+    return match config.kind {
+        GicKind::V2 => init_v2_on(bus, config),
+        GicKind::V3 => init_v3_on(bus, config),
+        GicKind::None => false,
+    };
+}
+
+/// Brings up a GICv2
+fn init_v2_on<B: MmioBus>(bus: &mut B, config: &GicConfig) -> bool {
+    unsafe {
+        bus.write32(config.dist_base + GICD_ICENABLER, u32::MAX);
+        bus.write32(config.dist_base + GICD_CTLR, GICD_CTLR_ENABLE);
+        bus.write32(config.cpu_base + GICC_PMR, PRIORITY_MASK_ALL);
+        bus.write32(config.cpu_base + GICC_CTLR, GICC_CTLR_ENABLE);
+    }
+    return true;
+}
+
+/// Brings up a GICv3
+fn init_v3_on<B: MmioBus>(bus: &mut B, config: &GicConfig) -> bool {
+    unsafe {
+        let sre: u64;
+        match cpu::current_el() {
+            1 => {
+                asm!("mrs {}, icc_sre_el1", out(reg) sre);
+                asm!("msr icc_sre_el1, {}", "isb", in(reg) sre | ICC_SRE_SRE);
+            }
+            2 => {
+                asm!("mrs {}, icc_sre_el2", out(reg) sre);
+                let sre = sre | ICC_SRE_SRE | ICC_SRE_EL2_ENABLE;
+                asm!("msr icc_sre_el2, {}", "isb", in(reg) sre);
+            }
+            _ => return false,
+        }
+    }
+
+    wake_v3_on(bus, config);
+
+    // CPU interface
+    unsafe {
+        asm!(
+            "msr icc_pmr_el1, {pmr}",
+            "msr icc_igrpen1_el1, {enable}",
+            "isb",
+            pmr = in(reg) PRIORITY_MASK_ALL as u64,
+            enable = in(reg) 1u64,
+        );
+    }
+    return true;
+}
+
+/// Sets up the distributor and the boot CPU's redistributor of a GICv3
+fn wake_v3_on<B: MmioBus>(bus: &mut B, config: &GicConfig) {
+    let sgi = config.cpu_base + GICR_SGI_BASE;
+
+    unsafe {
+        // Distributor: affinity routing and group 1 interrupts
+        bus.write32(config.dist_base + GICD_CTLR, GICD_CTLR_ARE);
+        wait_rwp_on(bus, config.dist_base);
+        bus.write32(
+            config.dist_base + GICD_CTLR,
+            GICD_CTLR_ARE | GICD_CTLR_ENABLE_GRP1,
+        );
+        wait_rwp_on(bus, config.dist_base);
+
+        // Redistributor: wake it up, private interrupts in group 1
+        let waker = bus.read32(config.cpu_base + GICR_WAKER);
+        bus.write32(
+            config.cpu_base + GICR_WAKER,
+            waker & !GICR_WAKER_PROCESSOR_SLEEP,
+        );
+        while bus.test_bit32(config.cpu_base + GICR_WAKER, GICR_WAKER_CHILDREN_ASLEEP_BIT) {
+            core::hint::spin_loop();
+        }
+        bus.write32(sgi + GICR_ICENABLER0, u32::MAX);
+        bus.write32(sgi + GICR_IGROUPR0, u32::MAX);
+    }
+}
+
+/// Waits for a GICv3 distributor register write to take effect
+fn wait_rwp_on<B: MmioBus>(bus: &mut B, dist_base: usize) {
+    unsafe {
+        while bus.test_bit32(dist_base + GICD_CTLR, GICD_CTLR_RWP_BIT) {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Enables the private interrupt `intid` (an SGI or PPI) on the boot CPU
+///
+/// Returns `false` if the GIC isn't initialized or `intid` isn't private.
+pub fn enable_private(intid: u32) -> bool {
+    let gic = gic();
+    let (base, set_enable, priority) = match gic.kind {
+        GicKind::V2 => (gic.dist_base, GICD_ISENABLER, GICD_IPRIORITYR),
+        GicKind::V3 => (
+            gic.cpu_base + GICR_SGI_BASE,
+            GICR_ISENABLER0,
+            GICR_IPRIORITYR,
+        ),
+        GicKind::None => return false,
+    };
+
+    if intid >= PRIVATE_INTIDS {
+        return false;
+    }
+    unsafe {
+        mmio::write_mmio(
+            base,
+            priority + intid as usize,
+            mmio::Width::B8,
+            DEFAULT_PRIORITY as u64,
+        );
+        mmio::write_mmio32(base, set_enable, 1 << intid);
+    }
+    return true;
+}
+
+/// Disables the private interrupt `intid` on the boot CPU
+pub fn disable_private(intid: u32) {
+    let gic = gic();
+    let (base, clear_enable) = match gic.kind {
+        GicKind::V2 => (gic.dist_base, GICD_ICENABLER),
+        GicKind::V3 => (gic.cpu_base + GICR_SGI_BASE, GICR_ICENABLER0),
+        GicKind::None => return,
+    };
+
+    if intid < PRIVATE_INTIDS {
+        unsafe {
+            mmio::write_mmio32(base, clear_enable, 1 << intid);
+        }
+    }
+}
+
+/// Acknowledges the highest priority pending interrupt
+///
+/// Returns its ID, or [`SPURIOUS_INTID`] if none is pending. Every
+/// acknowledged interrupt must be completed with [`end_of_interrupt`].
+pub fn acknowledge() -> u32 {
+    let gic = gic();
+    let iar: u64;
+
+    match gic.kind {
+        GicKind::V2 => unsafe {
+            iar = mmio::read_mmio32(gic.cpu_base, GICC_IAR) as u64;
+            return (iar & GICC_IAR_INTID_MASK) as u32;
+        },
+        GicKind::V3 => unsafe {
+            asm!("mrs {}, icc_iar1_el1", out(reg) iar);
+            return (iar & ICC_IAR_INTID_MASK) as u32;
+        },
+        GicKind::None => return SPURIOUS_INTID,
+    }
+}
+
+/// Signals the end of the handling of interrupt `intid`
+pub fn end_of_interrupt(intid: u32) {
+    let gic = gic();
+
+    match gic.kind {
+        GicKind::V2 => unsafe {
+            mmio::write_mmio32(gic.cpu_base, GICC_EOIR, intid);
+        },
+        GicKind::V3 => unsafe {
+            asm!("msr icc_eoir1_el1, {}", "isb", in(reg) intid as u64);
+        },
+        GicKind::None => {}
+    }
+}
+
+/// Sends SGI `intid` to the executing CPU
+///
+/// Returns `false` if the GIC isn't initialized or `intid` isn't an SGI.
+pub fn send_sgi_to_self(intid: u32) -> bool {
+    let gic = gic();
+
+    if intid >= 16 {
+        return false;
+    }
+    match gic.kind {
+        GicKind::V2 => unsafe {
+            mmio::write_mmio32(gic.dist_base, GICD_SGIR, GICD_SGIR_TO_SELF | intid);
+        },
+        GicKind::V3 => {
+            let mpidr = cpu::read_mpidr();
+            let aff = |shift: u64| (mpidr >> shift) & 0xff;
+            // The target list covers Aff0 values in ranges of 16
+            let sgi1r = (intid as u64) << ICC_SGI1R_INTID_SHIFT
+                | aff(32) << ICC_SGI1R_AFF3_SHIFT
+                | aff(16) << ICC_SGI1R_AFF2_SHIFT
+                | aff(8) << ICC_SGI1R_AFF1_SHIFT
+                | (aff(0) / 16) << ICC_SGI1R_RS_SHIFT
+                | 1 << (aff(0) % 16);
+            unsafe {
+                asm!("msr icc_sgi1r_el1, {}", "isb", in(reg) sgi1r);
+            }
+        }
+        GicKind::None => return false,
+    }
+    return true;
+}
+
+/// Self-test: an SGI sent to the executing CPU must be delivered
+///
+/// Runs with IRQs masked and polls the CPU interface for the SGI. Skipped
+/// if the GIC isn't initialized.
+pub fn selftest() -> Outcome {
+    if gic().kind == GicKind::None {
+        return Outcome::Skipped;
+    }
+
+    return interrupt::without_interrupts(|| {
+        let deadline = generic::counter().saturating_add(generic::us_to_ticks(
+            SELFTEST_TIMEOUT_US,
+            generic::frequency(),
+        ));
+        let mut outcome = Outcome::Fail;
+
+        enable_private(SELFTEST_SGI);
+        send_sgi_to_self(SELFTEST_SGI);
+        while generic::counter() < deadline {
+            let intid = acknowledge();
+            if intid == SPURIOUS_INTID {
+                continue;
+            }
+            end_of_interrupt(intid);
+            if intid == SELFTEST_SGI {
+                outcome = Outcome::Pass;
+                break;
+            }
+        }
+        disable_private(SELFTEST_SGI);
+        return outcome;
+    });
+}
+
+/// Self-test: the GICv2 bring-up of [`init_on`] and the GICv3 distributor
+/// and redistributor set-up, run on a [`MockBus`], perform their register
+/// accesses in order, without changing the GIC in use
+///
+/// [`MockBus`]: crate::utilities::mmio::mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_init_sequence() -> Outcome {
+    use crate::utilities::mmio::mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const DIST: usize = 0x1_0000;
+    const CPU: usize = 0x2_0000;
+    let v2 = GicConfig {
+        kind: GicKind::V2,
+        dist_base: DIST,
+        cpu_base: CPU,
+    };
+    let v3 = GicConfig {
+        kind: GicKind::V3,
+        ..v2
+    };
+    let sgi = CPU + GICR_SGI_BASE;
+    let expected_v2 = [
+        (AccessKind::Write, DIST + GICD_ICENABLER, u32::MAX),
+        (AccessKind::Write, DIST + GICD_CTLR, GICD_CTLR_ENABLE),
+        (AccessKind::Write, CPU + GICC_PMR, PRIORITY_MASK_ALL),
+        (AccessKind::Write, CPU + GICC_CTLR, GICC_CTLR_ENABLE),
+    ];
+    // The redistributor is asleep but its interfaces are already awake:
+    // the mock doesn't clear ChildrenAsleep by itself
+    let asleep = GICR_WAKER_PROCESSOR_SLEEP;
+    let grp1 = GICD_CTLR_ARE | GICD_CTLR_ENABLE_GRP1;
+    let expected_v3 = [
+        (AccessKind::Write, DIST + GICD_CTLR, GICD_CTLR_ARE),
+        (AccessKind::Read, DIST + GICD_CTLR, GICD_CTLR_ARE),
+        (AccessKind::Write, DIST + GICD_CTLR, grp1),
+        (AccessKind::Read, DIST + GICD_CTLR, grp1),
+        (AccessKind::Read, CPU + GICR_WAKER, asleep),
+        (AccessKind::Write, CPU + GICR_WAKER, 0),
+        (AccessKind::Read, CPU + GICR_WAKER, 0),
+        (AccessKind::Write, sgi + GICR_ICENABLER0, u32::MAX),
+        (AccessKind::Write, sgi + GICR_IGROUPR0, u32::MAX),
+    ];
+    let matches = |bus: &MockBus, expected: &[(AccessKind, usize, u32)]| {
+        let log = bus.log().iter().map(|a| (a.kind, a.addr, a.width, a.value));
+        return log.eq(expected
+            .iter()
+            .map(|&(kind, addr, value)| (kind, addr, 4, value as u64)));
+    };
+    let mut bus = MockBus::new();
+
+    if !init_on(&mut bus, &v2) || !matches(&bus, &expected_v2) {
+        return Outcome::Fail;
+    }
+    let mut bus = MockBus::new();
+    bus.set_read(CPU + GICR_WAKER, asleep as u64);
+    wake_v3_on(&mut bus, &v3);
+    if !matches(&bus, &expected_v3) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,3 @@
+//! Interrupt controller drivers module
+
+pub mod gic;
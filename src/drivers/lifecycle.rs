@@ -0,0 +1,269 @@
+//! Driver lifecycle
+//!
+//! A driver instance goes through four states:
+//!
+//! | State             | Meaning                                         |
+//! |-------------------|-------------------------------------------------|
+//! | [`Uninitialized`] | Nothing known about the device                  |
+//! | [`Probed`]        | Its base address and settings are recorded      |
+//! | [`Active`]        | The device is programmed and in use             |
+//! | [`Quiesced`]      | Stopped for the payload, which now owns it      |
+//!
+//! A driver keeps its state in a [`Lifecycle`], whose transitions its entry
+//! points go through: setting up a driver that is already set up, or one
+//! stopped for the handoff, fails with [`WrongState`] instead of running
+//! again over live state. [`quiesce_all`](super::quiesce_all) stops every
+//! driver before the payload starts, once. Only [`Lifecycle::teardown`]
+//! brings a driver back to [`Uninitialized`], from where it can be set up
+//! again.
+//!
+//! [`Uninitialized`]: State::Uninitialized
+//! [`Probed`]: State::Probed
+//! [`Active`]: State::Active
+//! [`Quiesced`]: State::Quiesced
+
+use crate::drivers::firmware::fw_cfg;
+use crate::drivers::rtc::goldfish;
+use crate::drivers::watchdog::sp805;
+use crate::interrupt;
+use crate::selftest::Outcome;
+
+/// State of a driver instance, see the [module documentation](self)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Nothing known about the device
+    Uninitialized,
+    /// The device is known but not programmed
+    Probed,
+    /// The device is programmed and in use
+    Active,
+    /// The device is stopped for the payload
+    Quiesced,
+}
+
+/// A transition was asked of a driver in a state it can't be made from,
+/// which is the one given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrongState(pub State);
+
+/// State of a driver instance and its transitions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lifecycle {
+    /// Current state
+    state: State,
+}
+
+impl Lifecycle {
+    /// Returns the lifecycle of a driver not set up yet
+    pub const fn new() -> Lifecycle {
+        return Lifecycle {
+            state: State::Uninitialized,
+        };
+    }
+
+    /// Returns the current state
+    pub const fn state(&self) -> State {
+        return self.state;
+    }
+
+    /// Checks whether the device is programmed and in use
+    pub const fn is_active(&self) -> bool {
+        return matches!(self.state, State::Active);
+    }
+
+    /// Records that the device was found: from [`State::Uninitialized`] to
+    /// [`State::Probed`]
+    pub const fn probe(&mut self) -> Result<(), WrongState> {
+        if !matches!(self.state, State::Uninitialized) {
+            return Err(WrongState(self.state));
+        }
+        self.state = State::Probed;
+        return Ok(());
+    }
+
+    /// Records that the device was programmed: from [`State::Probed`] to
+    /// [`State::Active`]
+    pub const fn activate(&mut self) -> Result<(), WrongState> {
+        if !matches!(self.state, State::Probed) {
+            return Err(WrongState(self.state));
+        }
+        self.state = State::Active;
+        return Ok(());
+    }
+
+    /// Probes and activates at once, for drivers set up in one step
+    pub const fn start(&mut self) -> Result<(), WrongState> {
+        if !matches!(self.state, State::Uninitialized) {
+            return Err(WrongState(self.state));
+        }
+        self.state = State::Active;
+        return Ok(());
+    }
+
+    /// Records that the device was stopped for the payload: from
+    /// [`State::Probed`] or [`State::Active`] to [`State::Quiesced`]
+    ///
+    /// Returns the state it was in, so the caller knows whether the device
+    /// was programmed.
+    pub const fn quiesce(&mut self) -> Result<State, WrongState> {
+        let previous = self.state;
+
+        if !matches!(previous, State::Probed | State::Active) {
+            return Err(WrongState(previous));
+        }
+        self.state = State::Quiesced;
+        return Ok(previous);
+    }
+
+    /// Forgets the device, from any state, and returns the state it was in
+    pub const fn teardown(&mut self) -> State {
+        let previous = self.state;
+
+        self.state = State::Uninitialized;
+        return previous;
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Returns a lifecycle in `state`
+const fn in_state(state: State) -> Lifecycle {
+    return Lifecycle { state };
+}
+
+/// Sets up a driver, quiesces it, fails to set it up again, then tears it
+/// down and sets it up again
+const fn cycle() -> bool {
+    let mut lifecycle = Lifecycle::new();
+
+    return lifecycle.probe().is_ok()
+        && lifecycle.activate().is_ok()
+        && matches!(lifecycle.quiesce(), Ok(State::Active))
+        && matches!(lifecycle.probe(), Err(WrongState(State::Quiesced)))
+        && matches!(lifecycle.teardown(), State::Quiesced)
+        && lifecycle.start().is_ok()
+        && lifecycle.is_active();
+}
+
+const _: () = assert!(cycle());
+const _: () = assert!(matches!(
+    in_state(State::Probed).probe(),
+    Err(WrongState(State::Probed))
+));
+const _: () = assert!(matches!(
+    in_state(State::Active).probe(),
+    Err(WrongState(State::Active))
+));
+const _: () = assert!(matches!(
+    in_state(State::Uninitialized).activate(),
+    Err(WrongState(State::Uninitialized))
+));
+const _: () = assert!(matches!(
+    in_state(State::Active).activate(),
+    Err(WrongState(State::Active))
+));
+const _: () = assert!(matches!(
+    in_state(State::Active).start(),
+    Err(WrongState(State::Active))
+));
+const _: () = assert!(matches!(
+    in_state(State::Probed).quiesce(),
+    Ok(State::Probed)
+));
+const _: () = assert!(matches!(
+    in_state(State::Uninitialized).quiesce(),
+    Err(WrongState(State::Uninitialized))
+));
+const _: () = assert!(matches!(
+    in_state(State::Quiesced).quiesce(),
+    Err(WrongState(State::Quiesced))
+));
+
+/// Base the self-test sets drivers up with; never accessed
+const TEST_BASE: usize = 0x1000;
+
+/// Entry points of a driver set up in one step
+struct Entries {
+    /// Sets the driver up at a base address
+    init: fn(usize) -> Result<(), WrongState>,
+    /// Stops it for the payload
+    quiesce: fn() -> Result<(), WrongState>,
+    /// Forgets it
+    teardown: fn(),
+    /// Returns its base address, if set up
+    base: fn() -> Option<usize>,
+}
+
+/// Drives the driver of `entries` from [`State::Uninitialized`] through a
+/// set-up, a quiesce and a second set-up, checking the transitions refused
+/// along the way
+fn cycle_driver(entries: &Entries) -> bool {
+    (entries.teardown)();
+    if (entries.init)(TEST_BASE).is_err()
+        || (entries.init)(TEST_BASE) != Err(WrongState(State::Active))
+    {
+        return false;
+    }
+    if (entries.quiesce)().is_err()
+        || (entries.quiesce)() != Err(WrongState(State::Quiesced))
+        || (entries.init)(TEST_BASE) != Err(WrongState(State::Quiesced))
+    {
+        return false;
+    }
+    (entries.teardown)();
+    if (entries.base)().is_some() || (entries.quiesce)() != Err(WrongState(State::Uninitialized)) {
+        return false;
+    }
+    return (entries.init)(TEST_BASE).is_ok() && (entries.base)() == Some(TEST_BASE);
+}
+
+/// Self-test: the Goldfish RTC, SP805 and fw_cfg drivers, whose set-up only
+/// records their base, go through set-up, quiesce and set-up again, and
+/// refuse to be set up twice or quiesced twice
+///
+/// Each driver is set up again at its own base afterwards, if it had one.
+/// The PL031, whose set-up starts its counter, and the console and
+/// interrupt controller, in use, are left out.
+pub fn selftest() -> Outcome {
+    let drivers = [
+        Entries {
+            init: goldfish::init,
+            quiesce: goldfish::quiesce,
+            teardown: goldfish::teardown,
+            base: goldfish::base,
+        },
+        Entries {
+            init: sp805::init,
+            quiesce: sp805::quiesce,
+            teardown: sp805::teardown,
+            base: sp805::base,
+        },
+        Entries {
+            init: fw_cfg::init,
+            quiesce: fw_cfg::quiesce,
+            teardown: fw_cfg::teardown,
+            base: fw_cfg::base,
+        },
+    ];
+
+    // Nothing may use the test base, not even an interrupt handler
+    return interrupt::without_interrupts(|| {
+        for driver in drivers.iter() {
+            let base = (driver.base)();
+            let passed = cycle_driver(driver);
+
+            (driver.teardown)();
+            if let Some(base) = base {
+                let _ = (driver.init)(base);
+            }
+            if !passed {
+                return Outcome::Fail;
+            }
+        }
+        return Outcome::Pass;
+    });
+}
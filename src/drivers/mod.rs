@@ -1,3 +1,150 @@
 //! Device drivers module
+//!
+//! Drivers holding state follow the [`lifecycle`] of a driver instance;
+//! [`quiesce_all`] stops them all before the payload starts.
 
+use crate::interrupt;
+use crate::selftest::{self, SelfTest};
+
+pub mod firmware;
+pub mod flash;
+pub mod irq;
+pub mod lifecycle;
+#[cfg(not(feature = "stage1"))]
+pub mod nvme;
+pub mod pci;
+pub mod registry;
+pub mod rtc;
+pub mod timer;
 pub mod uart;
+#[cfg(not(feature = "stage1"))]
+pub mod video;
+pub mod watchdog;
+
+/// Registers the self-tests of the drivers
+pub fn register_selftests() {
+    let tests = [
+        SelfTest {
+            name: b"uart",
+            run: uart::pl011::selftest,
+        },
+        SelfTest {
+            name: b"uart-no-input",
+            run: uart::pl011::selftest_no_input,
+        },
+        SelfTest {
+            name: b"early-replay",
+            run: uart::pl011::selftest_early_replay,
+        },
+        SelfTest {
+            name: b"timer",
+            run: timer::generic::selftest,
+        },
+        SelfTest {
+            name: b"delay",
+            run: timer::generic::selftest_delay,
+        },
+        SelfTest {
+            name: b"flash",
+            run: flash::cfi::selftest,
+        },
+        SelfTest {
+            name: b"gic",
+            run: irq::gic::selftest,
+        },
+        SelfTest {
+            name: b"registry",
+            run: registry::selftest,
+        },
+        SelfTest {
+            name: b"fw-cfg",
+            run: firmware::fw_cfg::selftest,
+        },
+        SelfTest {
+            name: b"uart-clock",
+            run: registry::selftest_uart_clock,
+        },
+        SelfTest {
+            name: b"platform-bus",
+            run: registry::selftest_bus,
+        },
+        SelfTest {
+            name: b"lifecycle",
+            run: lifecycle::selftest,
+        },
+        SelfTest {
+            name: b"rtc",
+            run: rtc::selftest,
+        },
+    ];
+
+    for test in tests {
+        selftest::register(test);
+    }
+    #[cfg(not(feature = "stage1"))]
+    selftest::register(SelfTest {
+        name: b"fbcon",
+        run: video::console::selftest,
+    });
+    #[cfg(not(feature = "stage1"))]
+    selftest::register(SelfTest {
+        name: b"nvme",
+        run: nvme::selftest,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"gic-init",
+        run: irq::gic::selftest_init_sequence,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"uart-configure",
+        run: uart::pl011::selftest_configure,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"uart-baud",
+        run: uart::pl011::selftest_set_baud,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"uart-try-putchar",
+        run: uart::pl011::selftest_try_putchar,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"uart-dump",
+        run: uart::pl011::selftest_dump_registers,
+    });
+}
+
+/// Whether [`quiesce_all`] ran
+static mut QUIESCED: bool = false;
+
+/// Stops every driver for the payload
+///
+/// Masks the bootloader's interrupts and gives their routing back, drains
+/// the console UART and masks its interrupts, and quiesces the RTC,
+/// watchdog and fw_cfg drivers, whose devices keep running for the payload,
+/// and the NVMe driver, whose controller is disabled. Drivers never set up
+/// are left alone. Only the first call does anything;
+/// returns whether this one did.
+pub fn quiesce_all() -> bool {
+    unsafe {
+        if QUIESCED {
+            return false;
+        }
+        QUIESCED = true;
+    }
+
+    interrupt::shutdown();
+    // Fails only for drivers that were never set up
+    let _ = uart::pl011::quiesce();
+    let _ = rtc::pl031::quiesce();
+    let _ = rtc::goldfish::quiesce();
+    let _ = watchdog::sp805::quiesce();
+    let _ = firmware::fw_cfg::quiesce();
+    #[cfg(not(feature = "stage1"))]
+    let _ = nvme::quiesce();
+    return true;
+}
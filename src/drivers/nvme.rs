@@ -0,0 +1,910 @@
+//! NVMe driver
+//!
+//! Reads the namespaces of an NVMe SSD, for booting from it. The controller
+//! is the first function of the PCIe root bus with the NVMe class code (see
+//! [`pci`]); its registers are BAR 0. Only reading is supported.
+//!
+//! [`init`] resets the controller, sets up the admin queue pair, identifies
+//! the controller and creates a single I/O queue pair. Commands are then
+//! submitted one at a time: the entry is written to the submission queue,
+//! the tail doorbell rung, and the completion queue polled until an entry
+//! with the expected phase bit appears, or a timeout expires. Interrupts
+//! stay masked. A command timing out leaves the controller to be reset by
+//! the next call.
+//!
+//! The queues, a PRP list and a bounce buffer are allocated from free RAM
+//! and reserved as [`ReserveTag::Dma`]. The MMU is off, so every access is
+//! uncached and memory shared with the controller needs no cache
+//! maintenance, only barriers ordering it before the doorbell writes.
+//!
+//! Data goes straight to the caller's buffer, described by PRP entries: the
+//! second entry points at the next page for a transfer touching two pages,
+//! or at a PRP list for more. [`read_lbas`] splits reads longer than the
+//! list, or than the controller's maximum transfer, into several commands.
+//!
+//! [`NvmeSource`] serves a namespace as an [`ImageSource`], so the loaders
+//! read an image straight from the disk.
+//!
+//! [`quiesce`] disables the controller and stops its DMA before the
+//! handoff, leaving it to the payload's driver.
+
+use crate::drivers::lifecycle::{Lifecycle, State, WrongState};
+use crate::drivers::pci::{self, Function, PciError};
+use crate::drivers::timer::generic::Deadline;
+use crate::log::{self, Level};
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+use crate::parsers::image::ImageSource;
+use crate::selftest::Outcome;
+use crate::utilities::mmio::{self, Width};
+
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{Ordering, fence};
+
+/// Class code, subclass and programming interface of NVMe controllers
+pub const NVME_CLASS: u32 = 0x01_08_02;
+/// Memory page size used with the controller
+const PAGE_SIZE: usize = 4096;
+/// Controller Capabilities register offset (64 bits)
+const CAP_OFF: usize = 0x00;
+/// Interrupt Mask Set register offset
+const INTMS_OFF: usize = 0x0c;
+/// Controller Configuration register offset
+const CC_OFF: usize = 0x14;
+/// Controller Status register offset
+const CSTS_OFF: usize = 0x1c;
+/// Admin Queue Attributes register offset
+const AQA_OFF: usize = 0x24;
+/// Admin Submission Queue base address register offset (64 bits)
+const ASQ_OFF: usize = 0x28;
+/// Admin Completion Queue base address register offset (64 bits)
+const ACQ_OFF: usize = 0x30;
+/// Offset of the first doorbell register
+const DOORBELL_OFF: usize = 0x1000;
+/// CAP.CSS bit: the NVM command set is supported
+const CAP_CSS_NVM: u64 = 1 << 37;
+/// CC.EN bit: the controller is enabled
+const CC_EN: u32 = 1 << 0;
+/// CC.IOSQES: size of an I/O submission queue entry, as a power of two
+const CC_IOSQES: u32 = 6 << 16;
+/// CC.IOCQES: size of an I/O completion queue entry, as a power of two
+const CC_IOCQES: u32 = 4 << 20;
+/// CSTS.RDY bit: the controller is ready
+const CSTS_RDY: u32 = 1 << 0;
+/// CSTS.CFS bit: the controller had a fatal error
+const CSTS_CFS: u32 = 1 << 1;
+/// Size of a submission queue entry in bytes
+const SQ_ENTRY_SIZE: usize = 64;
+/// Size of a completion queue entry in bytes
+const CQ_ENTRY_SIZE: usize = 16;
+/// Entries of each queue, as many submission entries as fit in a page
+const QUEUE_DEPTH: u16 = (PAGE_SIZE / SQ_ENTRY_SIZE) as u16;
+/// Completion entry status bit: the phase tag
+const CQE_PHASE: u32 = 1 << 16;
+/// Admin opcode: create an I/O submission queue
+const OPC_CREATE_IO_SQ: u8 = 0x01;
+/// Admin opcode: create an I/O completion queue
+const OPC_CREATE_IO_CQ: u8 = 0x05;
+/// Admin opcode: identify
+const OPC_IDENTIFY: u8 = 0x06;
+/// NVM opcode: read
+const OPC_READ: u8 = 0x02;
+/// Identify CNS value: a namespace
+const CNS_NAMESPACE: u32 = 0x00;
+/// Identify CNS value: the controller
+const CNS_CONTROLLER: u32 = 0x01;
+/// Create queue flag: the queue is physically contiguous
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+/// ID of the I/O queue pair
+const IO_QUEUE_ID: u16 = 1;
+/// Number of entries of a PRP list, filling a page
+const PRP_LIST_ENTRIES: usize = PAGE_SIZE / 8;
+/// Most bytes a command transfers, as far as the PRP list goes: a transfer
+/// not starting on a page boundary touches one page more than its length
+/// fills
+const MAX_TRANSFER: usize = PRP_LIST_ENTRIES * PAGE_SIZE;
+/// Most blocks a command reads, its count field being 16 bits wide
+const MAX_BLOCKS: usize = 1 << 16;
+/// Time a command is given to complete
+const COMMAND_TIMEOUT_MS: u64 = 5000;
+/// Length of the model number in the identify controller data
+const MODEL_SIZE: usize = 40;
+
+/// Pages of the memory shared with the controller
+#[derive(Clone, Copy)]
+#[repr(usize)]
+enum DmaPage {
+    /// Admin submission queue
+    AdminSq,
+    /// Admin completion queue
+    AdminCq,
+    /// I/O submission queue
+    IoSq,
+    /// I/O completion queue
+    IoCq,
+    /// PRP list of the transfer in progress
+    PrpList,
+    /// Identify data and blocks read for callers whose buffer doesn't fit
+    Bounce,
+}
+
+/// Number of [`DmaPage`]s
+const DMA_PAGES: usize = 6;
+
+/// Errors reported by the NVMe driver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NvmeError {
+    /// No NVMe controller is on the PCIe root bus
+    NotPresent,
+    /// Its BAR couldn't be enabled
+    Pci(PciError),
+    /// The controller doesn't support 4 KiB pages or the NVM command set
+    Unsupported,
+    /// The controller didn't become ready or disabled in time
+    NotReady,
+    /// The controller reported a fatal error
+    Fatal,
+    /// A command didn't complete in time
+    Timeout,
+    /// A command completed with the status given (status code type and
+    /// status code)
+    Status(u16),
+    /// The namespace doesn't exist or isn't active
+    NoNamespace,
+    /// The blocks are past the end of the namespace
+    OutOfRange,
+    /// The buffer is too small or not 4-byte aligned
+    BadBuffer,
+    /// No free RAM for the queues
+    Reserve(ReserveError),
+    /// The driver is stopped for the payload
+    WrongState(WrongState),
+}
+
+impl NvmeError {
+    /// Returns a description of the error
+    pub fn message(&self) -> &'static [u8] {
+        return match self {
+            NvmeError::NotPresent => b"no NVMe controller found",
+            NvmeError::Pci(e) => e.message(),
+            NvmeError::Unsupported => b"controller not supported",
+            NvmeError::NotReady => b"controller not ready",
+            NvmeError::Fatal => b"controller fatal status",
+            NvmeError::Timeout => b"command timed out",
+            NvmeError::Status(_) => b"command failed",
+            NvmeError::NoNamespace => b"no such namespace",
+            NvmeError::OutOfRange => b"blocks past the end of the namespace",
+            NvmeError::BadBuffer => b"buffer too small or misaligned",
+            NvmeError::Reserve(_) => b"no memory for the queues",
+            NvmeError::WrongState(_) => b"driver stopped",
+        };
+    }
+}
+
+impl From<PciError> for NvmeError {
+    fn from(e: PciError) -> Self {
+        return NvmeError::Pci(e);
+    }
+}
+
+impl From<ReserveError> for NvmeError {
+    fn from(e: ReserveError) -> Self {
+        return NvmeError::Reserve(e);
+    }
+}
+
+impl From<WrongState> for NvmeError {
+    fn from(e: WrongState) -> Self {
+        return NvmeError::WrongState(e);
+    }
+}
+
+/// An NVMe controller set up by [`init`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Controller {
+    /// PCIe function of the controller
+    pub function: Function,
+    /// CPU address of its registers
+    pub base: usize,
+    /// Model number, padded with spaces
+    pub model: [u8; MODEL_SIZE],
+    /// Number of namespaces, the highest valid namespace ID
+    pub namespaces: u32,
+    /// Most bytes a read command transfers
+    pub max_transfer: usize,
+}
+
+/// A namespace of the controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Namespace {
+    /// Namespace ID
+    pub nsid: u32,
+    /// Size of a logical block in bytes
+    pub block_size: usize,
+    /// Number of logical blocks
+    pub blocks: u64,
+}
+
+/// A submission and completion queue pair
+#[derive(Clone, Copy, Debug)]
+struct Queue {
+    /// Queue ID, 0 for the admin queues
+    id: u16,
+    /// Address of the submission queue
+    sq: usize,
+    /// Address of the completion queue
+    cq: usize,
+    /// Number of entries of each queue
+    depth: u16,
+    /// Next submission entry to write
+    tail: u16,
+    /// Next completion entry to read
+    head: u16,
+    /// Phase tag of the completion entries not read yet
+    phase: bool,
+}
+
+impl Queue {
+    /// Returns the empty queue pair `id` in the pages `sq` and `cq`
+    const fn new(id: u16, sq: usize, cq: usize, depth: u16) -> Queue {
+        return Queue {
+            id,
+            sq,
+            cq,
+            depth,
+            tail: 0,
+            head: 0,
+            phase: true,
+        };
+    }
+}
+
+/// A command for a submission queue
+#[derive(Clone, Copy, Debug, Default)]
+struct Command {
+    /// Opcode
+    opcode: u8,
+    /// Namespace ID, 0 if the command isn't about a namespace
+    nsid: u32,
+    /// First PRP entry
+    prp1: u64,
+    /// Second PRP entry, or address of a PRP list
+    prp2: u64,
+    /// Command dword 10
+    cdw10: u32,
+    /// Command dword 11
+    cdw11: u32,
+    /// Command dword 12
+    cdw12: u32,
+}
+
+/// State of the controller between commands
+#[derive(Clone, Copy, Debug)]
+struct Driver {
+    /// What [`init`] found
+    controller: Controller,
+    /// Distance between two doorbell registers
+    doorbell_stride: usize,
+    /// Time the controller takes to become ready or disabled
+    ready_timeout_ms: u64,
+    /// Admin queue pair
+    admin: Queue,
+    /// I/O queue pair
+    io: Queue,
+    /// Command ID of the next command
+    next_cid: u16,
+}
+
+/// Controller set up by [`init`], if any
+static mut DRIVER: Option<Driver> = None;
+/// Namespace identified last, kept for the following reads
+static mut NAMESPACE: Option<Namespace> = None;
+/// Address of the [`DmaPage`]s, 0 until allocated
+static mut DMA_BASE: usize = 0;
+/// Lifecycle of the driver
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Returns the address of `page` of the memory shared with the controller
+fn dma_page(page: DmaPage) -> usize {
+    return unsafe { DMA_BASE } + page as usize * PAGE_SIZE;
+}
+
+/// Waits for CSTS.RDY to become `ready`
+///
+/// Fails with [`NvmeError::Fatal`] if the controller reports a fatal error
+/// while becoming ready, and [`NvmeError::NotReady`] after `timeout_ms`.
+fn wait_ready(base: usize, ready: bool, timeout_ms: u64) -> Result<(), NvmeError> {
+    let deadline = Deadline::after_ms(timeout_ms);
+
+    loop {
+        let csts = unsafe { mmio::read_mmio32(base, CSTS_OFF) };
+        if (csts & CSTS_RDY != 0) == ready {
+            return Ok(());
+        }
+        if ready && csts & CSTS_CFS != 0 {
+            return Err(NvmeError::Fatal);
+        }
+        if deadline.expired() {
+            return Err(NvmeError::NotReady);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Disables the controller at `base`, waiting for it to stop
+fn disable(base: usize, timeout_ms: u64) -> Result<(), NvmeError> {
+    let cc = unsafe { mmio::read_mmio32(base, CC_OFF) };
+
+    if cc & CC_EN != 0 {
+        unsafe { mmio::write_mmio32(base, CC_OFF, cc & !CC_EN) };
+    }
+    return wait_ready(base, false, timeout_ms);
+}
+
+/// Writes `command` to the submission queue of `queue`, rings its doorbell
+/// and waits for the completion
+///
+/// Returns dword 0 of the completion entry, or the error status.
+fn submit(driver: &mut Driver, io: bool, command: &Command) -> Result<u32, NvmeError> {
+    let base = driver.controller.base;
+    let stride = driver.doorbell_stride;
+    let cid = driver.next_cid;
+    let queue = if io {
+        &mut driver.io
+    } else {
+        &mut driver.admin
+    };
+    let entry = [
+        command.opcode as u32 | (cid as u32) << 16,
+        command.nsid,
+        0,
+        0,
+        0,
+        0,
+        command.prp1 as u32,
+        (command.prp1 >> 32) as u32,
+        command.prp2 as u32,
+        (command.prp2 >> 32) as u32,
+        command.cdw10,
+        command.cdw11,
+        command.cdw12,
+        0,
+        0,
+        0,
+    ];
+
+    driver.next_cid = cid.wrapping_add(1);
+    let slot = queue.sq + queue.tail as usize * SQ_ENTRY_SIZE;
+    for (i, &dword) in entry.iter().enumerate() {
+        unsafe { ptr::write_volatile((slot + i * 4) as *mut u32, dword) };
+    }
+    queue.tail = (queue.tail + 1) % queue.depth;
+    // The entry and what it points at must be in memory before the doorbell
+    fence(Ordering::SeqCst);
+    let sq_doorbell = DOORBELL_OFF + 2 * queue.id as usize * stride;
+    unsafe { mmio::write_mmio32(base, sq_doorbell, queue.tail as u32) };
+
+    let deadline = Deadline::after_ms(COMMAND_TIMEOUT_MS);
+    let completion = queue.cq + queue.head as usize * CQ_ENTRY_SIZE;
+    let status = loop {
+        let status = unsafe { ptr::read_volatile((completion + 12) as *const u32) };
+        if (status & CQE_PHASE != 0) == queue.phase {
+            break status;
+        }
+        if deadline.expired() {
+            return Err(NvmeError::Timeout);
+        }
+        core::hint::spin_loop();
+    };
+    fence(Ordering::SeqCst);
+    let result = unsafe { ptr::read_volatile(completion as *const u32) };
+
+    queue.head += 1;
+    if queue.head == queue.depth {
+        queue.head = 0;
+        queue.phase = !queue.phase;
+    }
+    let cq_doorbell = DOORBELL_OFF + (2 * queue.id as usize + 1) * stride;
+    unsafe { mmio::write_mmio32(base, cq_doorbell, queue.head as u32) };
+
+    // Status code type and status code, above the phase tag
+    let code = (status >> 17 & 0x7ff) as u16;
+    if code != 0 {
+        return Err(NvmeError::Status(code));
+    }
+    return Ok(result);
+}
+
+/// Submits `command` to the controller set up by [`init`]
+///
+/// A command timing out forgets the controller, so the next call resets it.
+fn execute(io: bool, command: &Command) -> Result<u32, NvmeError> {
+    let driver = &raw mut DRIVER;
+    let Some(driver) = (unsafe { &mut *driver }) else {
+        return Err(NvmeError::NotPresent);
+    };
+    let result = submit(driver, io, command);
+
+    if result == Err(NvmeError::Timeout) {
+        forget();
+    }
+    return result;
+}
+
+/// Forgets the controller and the namespace, keeping the DMA memory
+fn forget() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        DRIVER = None;
+        NAMESPACE = None;
+    }
+}
+
+/// Identifies the controller (`cns` [`CNS_CONTROLLER`]) or namespace `nsid`
+/// into the bounce page and returns it
+fn identify(cns: u32, nsid: u32) -> Result<&'static [u8], NvmeError> {
+    let page = dma_page(DmaPage::Bounce);
+
+    execute(
+        false,
+        &Command {
+            opcode: OPC_IDENTIFY,
+            nsid,
+            prp1: page as u64,
+            cdw10: cns,
+            ..Command::default()
+        },
+    )?;
+    return Ok(unsafe { slice::from_raw_parts(page as *const u8, PAGE_SIZE) });
+}
+
+/// Reads the little-endian u32 at `off` of `data`
+fn le32(data: &[u8], off: usize) -> u32 {
+    return u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]]);
+}
+
+/// Sets up the NVMe controller, unless it already is, and returns it
+///
+/// Finds the controller on the PCIe root bus, enables its BAR, resets it
+/// and brings it up with an admin queue pair, identifies it and creates
+/// the I/O queue pair, as described in the [module documentation](self).
+pub fn init() -> Result<Controller, NvmeError> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    match unsafe { (*lifecycle).state() } {
+        State::Active => {
+            if let Some(driver) = unsafe { DRIVER } {
+                return Ok(driver.controller);
+            }
+        }
+        State::Quiesced => return Err(WrongState(State::Quiesced).into()),
+        _ => {}
+    }
+
+    let function = pci::find_class(NVME_CLASS).ok_or(NvmeError::NotPresent)?;
+    let base = pci::enable_bar(function, 0)? as usize;
+    let cap = unsafe { mmio::read_mmio(base, CAP_OFF, Width::B64) };
+    // CAP.MPSMIN, the smallest page size, as 4 KiB << MPSMIN
+    if cap >> 48 & 0xf != 0 || cap & CAP_CSS_NVM == 0 {
+        return Err(NvmeError::Unsupported);
+    }
+    // CAP.TO in 500 ms units, CAP.DSTRD as 4 << DSTRD bytes, CAP.MQES 0-based
+    let ready_timeout_ms = (cap >> 24 & 0xff).max(1) * 500;
+    let doorbell_stride = 4 << (cap >> 32 & 0xf);
+    let depth = QUEUE_DEPTH.min((cap & 0xffff) as u16 + 1);
+
+    unsafe {
+        if DMA_BASE == 0 {
+            let size = DMA_PAGES * PAGE_SIZE;
+            DMA_BASE = reserve::allocate(size, PAGE_SIZE, None, ReserveTag::Dma)?;
+        }
+        ptr::write_bytes(DMA_BASE as *mut u8, 0, DMA_PAGES * PAGE_SIZE);
+    }
+
+    disable(base, ready_timeout_ms)?;
+    let admin = Queue::new(
+        0,
+        dma_page(DmaPage::AdminSq),
+        dma_page(DmaPage::AdminCq),
+        depth,
+    );
+    unsafe {
+        let sizes = (depth as u32 - 1) << 16 | (depth as u32 - 1);
+        mmio::write_mmio32(base, AQA_OFF, sizes);
+        mmio::write_mmio(base, ASQ_OFF, Width::B64, admin.sq as u64);
+        mmio::write_mmio(base, ACQ_OFF, Width::B64, admin.cq as u64);
+        // Completions are polled
+        mmio::write_mmio32(base, INTMS_OFF, u32::MAX);
+        mmio::write_mmio32(base, CC_OFF, CC_IOCQES | CC_IOSQES | CC_EN);
+    }
+    wait_ready(base, true, ready_timeout_ms)?;
+
+    unsafe {
+        DRIVER = Some(Driver {
+            controller: Controller {
+                function,
+                base,
+                model: [b' '; MODEL_SIZE],
+                namespaces: 0,
+                max_transfer: MAX_TRANSFER,
+            },
+            doorbell_stride,
+            ready_timeout_ms,
+            admin,
+            io: Queue::new(
+                IO_QUEUE_ID,
+                dma_page(DmaPage::IoSq),
+                dma_page(DmaPage::IoCq),
+                depth,
+            ),
+            next_cid: 0,
+        });
+        NAMESPACE = None;
+    }
+    let result = bring_up(depth);
+    if result.is_err() {
+        forget();
+    }
+    return result;
+}
+
+/// Identifies the controller enabled by [`init`] and creates its I/O queue
+/// pair of `depth` entries
+fn bring_up(depth: u16) -> Result<Controller, NvmeError> {
+    let data = identify(CNS_CONTROLLER, 0)?;
+    let mut model = [0u8; MODEL_SIZE];
+    model.copy_from_slice(&data[24..24 + MODEL_SIZE]);
+    // MDTS, in minimum pages as a power of two, 0 for no limit
+    let mdts = data[77];
+    let namespaces = le32(data, 516);
+    let sizes = (depth as u32 - 1) << 16 | IO_QUEUE_ID as u32;
+
+    execute(
+        false,
+        &Command {
+            opcode: OPC_CREATE_IO_CQ,
+            prp1: dma_page(DmaPage::IoCq) as u64,
+            cdw10: sizes,
+            cdw11: QUEUE_CONTIGUOUS,
+            ..Command::default()
+        },
+    )?;
+    execute(
+        false,
+        &Command {
+            opcode: OPC_CREATE_IO_SQ,
+            prp1: dma_page(DmaPage::IoSq) as u64,
+            cdw10: sizes,
+            cdw11: (IO_QUEUE_ID as u32) << 16 | QUEUE_CONTIGUOUS,
+            ..Command::default()
+        },
+    )?;
+
+    let driver = &raw mut DRIVER;
+    let lifecycle = &raw mut LIFECYCLE;
+    let Some(driver) = (unsafe { &mut *driver }) else {
+        return Err(NvmeError::NotPresent);
+    };
+    let controller = &mut driver.controller;
+    controller.model = model;
+    controller.namespaces = namespaces;
+    if mdts != 0 && mdts < 20 {
+        controller.max_transfer = MAX_TRANSFER.min(PAGE_SIZE << mdts);
+    }
+    unsafe { (*lifecycle).start() }?;
+    return Ok(*controller);
+}
+
+/// Returns the namespace `nsid`, identifying it unless it was the last one
+pub fn namespace(nsid: u32) -> Result<Namespace, NvmeError> {
+    if let Some(namespace) = unsafe { NAMESPACE }
+        && namespace.nsid == nsid
+    {
+        return Ok(namespace);
+    }
+    let controller = init()?;
+    if nsid == 0 || nsid > controller.namespaces {
+        return Err(NvmeError::NoNamespace);
+    }
+
+    let data = identify(CNS_NAMESPACE, nsid)?;
+    let blocks = u64::from_le_bytes(data[0..8].try_into().unwrap_or([0; 8]));
+    // FLBAS selects the LBA format in use, whose LBADS is the block size
+    // as a power of two
+    let format = le32(data, 128 + 4 * (data[26] & 0xf) as usize);
+    let shift = format >> 16 & 0xff;
+    if blocks == 0 {
+        return Err(NvmeError::NoNamespace);
+    }
+    // Blocks are read through a bounce page when the caller's buffer can't
+    // take them directly
+    if !(9..=12).contains(&shift) {
+        return Err(NvmeError::Unsupported);
+    }
+    let namespace = Namespace {
+        nsid,
+        block_size: 1 << shift,
+        blocks,
+    };
+    unsafe {
+        NAMESPACE = Some(namespace);
+    }
+    return Ok(namespace);
+}
+
+/// Fills the PRP entries of a transfer of `len` bytes at `addr`
+///
+/// Returns PRP1 and PRP2. PRP2 is 0 for a transfer within a page, the
+/// second page for one touching two, and otherwise `list_addr`, `list`
+/// then holding the address of every page after the first.
+const fn prps(
+    addr: u64,
+    len: usize,
+    list: &mut [u64; PRP_LIST_ENTRIES],
+    list_addr: u64,
+) -> (u64, u64) {
+    let page = addr & !(PAGE_SIZE as u64 - 1);
+    let pages = ((addr - page) as usize + len).div_ceil(PAGE_SIZE);
+
+    if pages <= 1 {
+        return (addr, 0);
+    }
+    if pages == 2 {
+        return (addr, page + PAGE_SIZE as u64);
+    }
+    let mut i = 0;
+    while i < pages - 1 {
+        list[i] = page + ((i + 1) * PAGE_SIZE) as u64;
+        i += 1;
+    }
+    return (addr, list_addr);
+}
+
+const _: () = {
+    let mut list = [0u64; PRP_LIST_ENTRIES];
+    assert!(matches!(
+        prps(0x4000_0200, 0x200, &mut list, 0x9000),
+        (0x4000_0200, 0)
+    ));
+    assert!(matches!(
+        prps(0x4000_0000, 0x2000, &mut list, 0x9000),
+        (0x4000_0000, 0x4000_1000)
+    ));
+    assert!(matches!(
+        prps(0x4000_0200, 0x2000, &mut list, 0x9000),
+        (0x4000_0200, 0x9000)
+    ));
+    assert!(list[0] == 0x4000_1000 && list[1] == 0x4000_2000);
+    // A transfer of the largest size, not page aligned, fills the list
+    assert!(matches!(
+        prps(0x4000_0200, MAX_TRANSFER, &mut list, 0x9000),
+        (0x4000_0200, 0x9000)
+    ));
+    assert!(list[PRP_LIST_ENTRIES - 1] == 0x4000_0000 + (PRP_LIST_ENTRIES * PAGE_SIZE) as u64);
+};
+
+/// Reads `count` blocks of `namespace` from `lba` to `addr`, in one command
+fn read_command(
+    namespace: &Namespace,
+    lba: u64,
+    count: usize,
+    addr: usize,
+) -> Result<(), NvmeError> {
+    let list_addr = dma_page(DmaPage::PrpList);
+    let list = unsafe { &mut *(list_addr as *mut [u64; PRP_LIST_ENTRIES]) };
+    let (prp1, prp2) = prps(
+        addr as u64,
+        count * namespace.block_size,
+        list,
+        list_addr as u64,
+    );
+
+    execute(
+        true,
+        &Command {
+            opcode: OPC_READ,
+            nsid: namespace.nsid,
+            prp1,
+            prp2,
+            cdw10: lba as u32,
+            cdw11: (lba >> 32) as u32,
+            cdw12: count as u32 - 1,
+        },
+    )?;
+    return Ok(());
+}
+
+/// Returns the most blocks of `namespace` a read command transfers
+fn max_blocks(namespace: &Namespace) -> usize {
+    let max_transfer = unsafe { DRIVER }.map_or(MAX_TRANSFER, |d| d.controller.max_transfer);
+
+    return (max_transfer / namespace.block_size).clamp(1, MAX_BLOCKS);
+}
+
+/// Reads `count` logical blocks of namespace `nsid` from `lba` into the
+/// start of `buf`
+///
+/// `buf` must be 4-byte aligned and hold the blocks. Longer reads are split
+/// into several commands.
+pub fn read_lbas(nsid: u32, lba: u64, count: usize, buf: &mut [u8]) -> Result<(), NvmeError> {
+    let namespace = namespace(nsid)?;
+    let len = count
+        .checked_mul(namespace.block_size)
+        .ok_or(NvmeError::OutOfRange)?;
+
+    if buf.len() < len || !(buf.as_ptr() as usize).is_multiple_of(4) {
+        return Err(NvmeError::BadBuffer);
+    }
+    if lba
+        .checked_add(count as u64)
+        .is_none_or(|end| end > namespace.blocks)
+    {
+        return Err(NvmeError::OutOfRange);
+    }
+
+    let mut done = 0;
+    while done < count {
+        let blocks = (count - done).min(max_blocks(&namespace));
+        let addr = buf.as_mut_ptr() as usize + done * namespace.block_size;
+        read_command(&namespace, lba + done as u64, blocks, addr)?;
+        done += blocks;
+    }
+    return Ok(());
+}
+
+/// Reads block `lba` of `namespace` into the bounce page and returns it
+fn read_bounced(namespace: &Namespace, lba: u64) -> Result<&'static [u8], NvmeError> {
+    let page = dma_page(DmaPage::Bounce);
+
+    if lba >= namespace.blocks {
+        return Err(NvmeError::OutOfRange);
+    }
+    read_command(namespace, lba, 1, page)?;
+    return Ok(unsafe { slice::from_raw_parts(page as *const u8, namespace.block_size) });
+}
+
+/// A namespace, or the part of it from a given block, read as an image
+///
+/// Reads starting on a block boundary into a 4-byte aligned buffer of at
+/// least a block go straight to the buffer, up to a command's worth; others
+/// read one block through the bounce page. A failed read is logged and
+/// ends the image.
+#[derive(Clone, Copy, Debug)]
+pub struct NvmeSource {
+    /// The namespace read
+    namespace: Namespace,
+    /// Block the image starts at
+    start: u64,
+    /// Size of the image in bytes
+    len: usize,
+}
+
+impl NvmeSource {
+    /// Creates a source for the image at block `start` of namespace `nsid`
+    ///
+    /// The image is `len` bytes long, or runs to the end of the namespace.
+    pub fn new(nsid: u32, start: u64, len: Option<usize>) -> Result<NvmeSource, NvmeError> {
+        let namespace = namespace(nsid)?;
+        let blocks = namespace
+            .blocks
+            .checked_sub(start)
+            .ok_or(NvmeError::OutOfRange)?;
+        let available = (blocks as usize).saturating_mul(namespace.block_size);
+
+        return Ok(NvmeSource {
+            namespace,
+            start,
+            len: len.map_or(available, |len| len.min(available)),
+        });
+    }
+}
+
+impl ImageSource for NvmeSource {
+    fn read_at(&self, off: usize, buf: &mut [u8]) -> usize {
+        let wanted = buf.len().min(self.len.saturating_sub(off));
+        let block_size = self.namespace.block_size;
+        let lba = self.start + (off / block_size) as u64;
+        let within = off % block_size;
+
+        if wanted == 0 {
+            return 0;
+        }
+        let result =
+            if within == 0 && wanted >= block_size && (buf.as_ptr() as usize).is_multiple_of(4) {
+                let count = (wanted / block_size).min(max_blocks(&self.namespace));
+                let len = count * block_size;
+                read_lbas(self.namespace.nsid, lba, count, &mut buf[..len]).map(|_| len)
+            } else {
+                read_bounced(&self.namespace, lba).map(|block| {
+                    let len = wanted.min(block_size - within);
+                    buf[..len].copy_from_slice(&block[within..within + len]);
+                    len
+                })
+            };
+
+        match result {
+            Ok(len) => return len,
+            Err(e) => {
+                log::print(Level::Error, b"NVMe read failed: ");
+                log::println(Level::Error, e.message());
+                return 0;
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+}
+
+/// Stops the driver for the payload
+///
+/// A controller set up is disabled and can't master the bus any more, and
+/// its DMA memory is freed. Fails if it isn't set up or is already
+/// quiesced.
+pub fn quiesce() -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe { (*lifecycle).quiesce() }?;
+    if let Some(driver) = unsafe { DRIVER } {
+        let controller = driver.controller;
+        if disable(controller.base, driver.ready_timeout_ms).is_err() {
+            log::println(Level::Warn, b"NVMe controller did not stop");
+        }
+        pci::disable_bus_master(controller.function);
+    }
+    unsafe {
+        DRIVER = None;
+        NAMESPACE = None;
+        if DMA_BASE != 0 {
+            reserve::discard(DMA_BASE, ReserveTag::Dma);
+            DMA_BASE = 0;
+        }
+    }
+    return Ok(());
+}
+
+/// Size of the self-test's read, which doesn't start on a page boundary, so
+/// it needs a PRP list
+const TEST_READ_SIZE: usize = 2 * PAGE_SIZE;
+/// Offset of the self-test's read into its buffer
+const TEST_READ_OFFSET: usize = 512;
+
+/// Self-test: blocks read in one command, through a PRP list, match the
+/// same blocks read one at a time, and a read past the end of the
+/// namespace is refused
+///
+/// Skipped without an NVMe controller.
+pub fn selftest() -> Outcome {
+    if pci::find_class(NVME_CLASS).is_none() {
+        return Outcome::Skipped;
+    }
+    let Ok(namespace) = namespace(1) else {
+        return Outcome::Fail;
+    };
+    let count = (TEST_READ_SIZE / namespace.block_size).min(namespace.blocks as usize);
+    let Ok(base) = reserve::allocate(
+        TEST_READ_OFFSET + TEST_READ_SIZE,
+        PAGE_SIZE,
+        None,
+        ReserveTag::Staging,
+    ) else {
+        return Outcome::Fail;
+    };
+    let buf =
+        unsafe { slice::from_raw_parts_mut((base + TEST_READ_OFFSET) as *mut u8, TEST_READ_SIZE) };
+
+    let mut passed = read_lbas(1, 0, count, buf).is_ok();
+    for (lba, expected) in buf.chunks(namespace.block_size).take(count).enumerate() {
+        passed &= read_bounced(&namespace, lba as u64).is_ok_and(|block| block == expected);
+    }
+    passed &= read_lbas(1, namespace.blocks, 1, buf) == Err(NvmeError::OutOfRange);
+    reserve::discard(base, ReserveTag::Staging);
+
+    if !passed {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,327 @@
+//! PCI Express host bridge with an ECAM configuration space
+//!
+//! The generic host bridge of QEMU's `virt` machine, and of most Arm
+//! servers, maps the configuration space of every function as a 4 KiB
+//! window of its ECAM region: bus, device and function number select the
+//! window. The host is only known when found in the device tree, as a
+//! `pci-host-ecam-generic` node (see [`registry`](crate::drivers::registry)),
+//! whose `ranges` give the window of memory addresses for the BARs.
+//!
+//! Only what booting from a PCIe device needs is handled: functions of the
+//! root bus are found by their class code ([`find_class`]), and
+//! [`enable_bar`] gives a memory BAR an address from the 32-bit memory
+//! window if the firmware left it unassigned. Bridges aren't followed, so a
+//! device behind a switch isn't found.
+
+use crate::drivers::lifecycle::{Lifecycle, WrongState};
+use crate::parsers::fdt::{self, Node};
+use crate::utilities::mmio;
+
+/// Number of devices on a bus
+const DEVICES_PER_BUS: u8 = 32;
+/// Number of functions of a multi-function device
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+/// Configuration space offset of the vendor ID (low 16 bits) and device ID
+const VENDOR_ID_OFF: usize = 0x00;
+/// Configuration space offset of the command (low 16 bits) and status
+/// registers
+const COMMAND_OFF: usize = 0x04;
+/// Configuration space offset of the revision ID (low 8 bits) and class
+/// code
+const CLASS_OFF: usize = 0x08;
+/// Configuration space offset of the header type, in bits 23..16
+const HEADER_TYPE_OFF: usize = 0x0c;
+/// Configuration space offset of BAR 0
+const BAR0_OFF: usize = 0x10;
+/// Number of BARs of a type 0 header
+const BAR_COUNT: usize = 6;
+/// Vendor ID read from a function that isn't there
+const NO_VENDOR: u32 = 0xffff;
+/// Header type bit: the device has more than one function
+const HEADER_MULTI_FUNCTION: u32 = 1 << 23;
+/// Command bit: the function decodes memory accesses
+const COMMAND_MEMORY: u32 = 1 << 1;
+/// Command bit: the function may master the bus (DMA)
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+/// BAR bit: I/O space rather than memory
+const BAR_IO: u32 = 1 << 0;
+/// BAR type field: 64-bit memory BAR, taking two registers
+const BAR_TYPE_64: u32 = 0b10 << 1;
+/// BAR bits that aren't part of the address
+const BAR_FLAGS_MASK: u32 = 0xf;
+/// `ranges` space code of 32-bit memory, in bits 25..24 of the first cell
+const SPACE_MEMORY_32: u32 = 0b10;
+/// Number of cells of a PCI address in `ranges`
+const PCI_ADDRESS_CELLS: usize = 3;
+
+/// Errors reported by the PCIe host driver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PciError {
+    /// No host bridge is known
+    NoHost,
+    /// The BAR doesn't exist, or is an I/O BAR
+    BadBar,
+    /// The memory window has no room left for the BAR
+    NoSpace,
+}
+
+impl PciError {
+    /// Returns a description of the error
+    pub fn message(&self) -> &'static [u8] {
+        return match self {
+            PciError::NoHost => b"no PCIe host bridge",
+            PciError::BadBar => b"no such memory BAR",
+            PciError::NoSpace => b"PCIe memory window full",
+        };
+    }
+}
+
+/// A PCIe host bridge with an ECAM configuration space
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Host {
+    /// CPU address of the configuration space of the first bus
+    pub config_base: usize,
+    /// Number of the first bus, the root bus
+    pub first_bus: u8,
+    /// CPU address of the 32-bit memory window
+    pub mem_base: u64,
+    /// Bus address of the 32-bit memory window, which BARs hold
+    pub mem_bus_base: u64,
+    /// Size of the 32-bit memory window, 0 if there is none
+    pub mem_size: u64,
+}
+
+/// A function of the root bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Function {
+    /// Device number, below 32
+    pub device: u8,
+    /// Function number, below 8
+    pub function: u8,
+}
+
+/// Host bridge recorded by [`init`]
+static mut HOST: Option<Host> = None;
+/// Bytes of the memory window given to BARs so far
+static mut MEM_USED: u64 = 0;
+/// Lifecycle of the driver
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Returns the host bridge described by `node`, a `pci-host-ecam-generic`
+/// node
+///
+/// The configuration space is the first `reg` entry, the first bus comes
+/// from `bus-range` (0 without it) and the memory window is the first
+/// 32-bit memory entry of `ranges`. Returns `None` without a `reg`.
+pub fn host_from_node(node: &Node) -> Option<Host> {
+    let (config_base, _) = node.reg(0)?;
+    let first_bus = node
+        .property(b"bus-range")
+        .and_then(|range| fdt::be32(range, 0))
+        .unwrap_or(0);
+    let mut host = Host {
+        config_base: config_base as usize,
+        first_bus: first_bus as u8,
+        mem_base: 0,
+        mem_bus_base: 0,
+        mem_size: 0,
+    };
+
+    // Each entry: PCI address, CPU address in the parent's cells, size
+    let size_cells = node.property_u32(b"#size-cells").unwrap_or(2);
+    let entry_size = (PCI_ADDRESS_CELLS + node.address_cells as usize + size_cells as usize) * 4;
+    let ranges = node.property(b"ranges").unwrap_or(&[]);
+    for entry in ranges.chunks_exact(entry_size) {
+        let space = fdt::be32(entry, 0)? >> 24 & 0b11;
+        let cpu_off = PCI_ADDRESS_CELLS * 4;
+        let size_off = cpu_off + node.address_cells as usize * 4;
+        if space == SPACE_MEMORY_32 {
+            host.mem_bus_base = fdt::be64(entry, 4)?;
+            host.mem_base =
+                fdt::read_cells(entry, cpu_off, node.address_cells)?.wrapping_add(node.translation);
+            host.mem_size = fdt::read_cells(entry, size_off, size_cells)?;
+            break;
+        }
+    }
+    return Some(host);
+}
+
+/// Records the host bridge `host`
+///
+/// Fails, changing nothing, if the driver is already set up.
+pub fn init(host: Host) -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).start()?;
+        HOST = Some(host);
+        MEM_USED = 0;
+    }
+    return Ok(());
+}
+
+/// Forgets the host bridge, so [`init`] can set it up again
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        HOST = None;
+    }
+}
+
+/// Returns the host bridge, if [`init`] was called
+pub fn host() -> Option<Host> {
+    return unsafe { HOST };
+}
+
+/// Returns the address of the configuration register at `off` of `function`
+fn config_addr(host: &Host, function: Function, off: usize) -> usize {
+    return host.config_base
+        + ((function.device as usize) << 15 | (function.function as usize) << 12 | off);
+}
+
+/// Reads the 32-bit configuration register at `off` of `function`
+///
+/// Returns all ones, like a read from a function that isn't there, without
+/// a host bridge.
+pub fn read_config(function: Function, off: usize) -> u32 {
+    let Some(host) = host() else {
+        return u32::MAX;
+    };
+
+    return unsafe { mmio::read_mmio32(config_addr(&host, function, off), 0) };
+}
+
+/// Writes the 32-bit configuration register at `off` of `function`
+pub fn write_config(function: Function, off: usize, value: u32) {
+    if let Some(host) = host() {
+        unsafe { mmio::write_mmio32(config_addr(&host, function, off), 0, value) };
+    }
+}
+
+/// Checks whether `function` is there
+fn is_present(function: Function) -> bool {
+    return read_config(function, VENDOR_ID_OFF) & 0xffff != NO_VENDOR;
+}
+
+/// Finds the first function of the root bus whose class code, subclass and
+/// programming interface are `class` (e.g., 0x010802 for NVMe)
+pub fn find_class(class: u32) -> Option<Function> {
+    host()?;
+    for device in 0..DEVICES_PER_BUS {
+        let first = Function {
+            device,
+            function: 0,
+        };
+        if !is_present(first) {
+            continue;
+        }
+        let functions = match read_config(first, HEADER_TYPE_OFF) & HEADER_MULTI_FUNCTION {
+            0 => 1,
+            _ => FUNCTIONS_PER_DEVICE,
+        };
+        for function in 0..functions {
+            let candidate = Function { device, function };
+            if is_present(candidate) && read_config(candidate, CLASS_OFF) >> 8 == class {
+                return Some(candidate);
+            }
+        }
+    }
+    return None;
+}
+
+/// Rounds `value` up to a multiple of `align` (a power of two)
+const fn align_up(value: u64, align: u64) -> u64 {
+    return (value + align - 1) & !(align - 1);
+}
+
+/// Returns the size of a memory BAR from the value read back after writing
+/// all ones to it (both halves for a 64-bit BAR), 0 if it decodes nothing
+const fn bar_size(readback: u64) -> u64 {
+    let mask = readback & !(BAR_FLAGS_MASK as u64);
+
+    return (!mask).wrapping_add(1) & mask;
+}
+
+const _: () = assert!(bar_size(0xffff_ffff_ffff_c004) == 0x4000);
+const _: () = assert!(bar_size(0x0000_0000_fff0_0000) == 0x10_0000);
+const _: () = assert!(bar_size(0) == 0);
+const _: () = assert!(align_up(0x1000_4000, 0x4000) == 0x1000_4000);
+const _: () = assert!(align_up(0x1000_4001, 0x4000) == 0x1000_8000);
+
+/// Enables memory BAR `index` of `function` and returns its CPU address
+///
+/// A BAR left unassigned by the firmware is given the next free range of
+/// the 32-bit memory window, aligned to its size; an assigned one keeps its
+/// address, taken to be the same for the CPU unless it lies in the window.
+/// Memory decoding and bus mastering are then turned on, for the device's
+/// registers and DMA.
+pub fn enable_bar(function: Function, index: usize) -> Result<u64, PciError> {
+    let host = host().ok_or(PciError::NoHost)?;
+    let off = BAR0_OFF + index * 4;
+    let low = read_config(function, off);
+
+    if index >= BAR_COUNT || low & BAR_IO != 0 {
+        return Err(PciError::BadBar);
+    }
+    let is_64 = low & BAR_TYPE_64 != 0;
+    if is_64 && index + 1 >= BAR_COUNT {
+        return Err(PciError::BadBar);
+    }
+    let high = if is_64 {
+        read_config(function, off + 4)
+    } else {
+        0
+    };
+    let mut bus_addr = (high as u64) << 32 | (low & !BAR_FLAGS_MASK) as u64;
+    let command = read_config(function, COMMAND_OFF) & 0xffff;
+
+    if bus_addr == 0 {
+        // Sized with decoding off, so the probe value decodes nothing
+        write_config(function, COMMAND_OFF, command & !COMMAND_MEMORY);
+        write_config(function, off, u32::MAX);
+        let mut readback = read_config(function, off) as u64 | 0xffff_ffff_0000_0000;
+        if is_64 {
+            write_config(function, off + 4, u32::MAX);
+            readback = (read_config(function, off + 4) as u64) << 32 | readback & 0xffff_ffff;
+        }
+        let size = bar_size(readback);
+        let used = unsafe { MEM_USED };
+        let start = align_up(host.mem_bus_base + used, size.max(1));
+        if size == 0 || start + size > host.mem_bus_base + host.mem_size {
+            write_config(function, off, low);
+            if is_64 {
+                write_config(function, off + 4, high);
+            }
+            write_config(function, COMMAND_OFF, command);
+            return Err(PciError::NoSpace);
+        }
+        write_config(function, off, start as u32 | low & BAR_FLAGS_MASK);
+        if is_64 {
+            write_config(function, off + 4, (start >> 32) as u32);
+        }
+        unsafe {
+            MEM_USED = start + size - host.mem_bus_base;
+        }
+        bus_addr = start;
+    }
+    write_config(
+        function,
+        COMMAND_OFF,
+        command | COMMAND_MEMORY | COMMAND_BUS_MASTER,
+    );
+
+    let window = host.mem_bus_base..host.mem_bus_base + host.mem_size;
+    if window.contains(&bus_addr) {
+        return Ok(bus_addr - host.mem_bus_base + host.mem_base);
+    }
+    return Ok(bus_addr);
+}
+
+/// Stops `function` from mastering the bus, so it can't DMA any more
+pub fn disable_bus_master(function: Function) {
+    let command = read_config(function, COMMAND_OFF) & 0xffff;
+
+    write_config(function, COMMAND_OFF, command & !COMMAND_BUS_MASTER);
+}
@@ -0,0 +1,699 @@
+//! Driver registry
+//!
+//! Instead of taking each base address from a hard-coded board description,
+//! drivers can be found in the device tree: every [`Driver`] of [`DRIVERS`]
+//! names the `compatible` strings of its devices and a probe function, and
+//! [`init_all`] probes every enabled node matching one of them.
+//!
+//! Probes of drivers the rest of the bootloader brings up later (console
+//! UART, GIC, flash) fill in the [`BoardConfig`]; the others (PL031 or
+//! Goldfish RTC, SP805 watchdog, generic timer, QEMU fw_cfg, PCIe host
+//! bridge) set up their driver right away. A device the board already provides (e.g., a second UART, or the
+//! GIC of a board with a fixed configuration) is reported as
+//! [present](ProbeError::Present) and left alone.
+//!
+//! # Deferred probes
+//!
+//! A probe needing a device that isn't probed yet returns
+//! [`ProbeError::Deferred`] with what it waits for; for instance, the timer
+//! waits for the interrupt controller its interrupts go to. Deferred probes
+//! are tried again after a pass in which another probe succeeded, for up to
+//! [`MAX_PASSES`] passes, so the order of [`DRIVERS`] and of the nodes
+//! doesn't matter. What happened to each device is kept for the monitor's
+//! `devices` command, see [`devices`].
+//!
+//! Devices behind a bus, such as the platform bus of QEMU's `virt` machine,
+//! are found like the others: their `reg` is translated through the bus's
+//! `ranges` (see [`Node::reg`]).
+//!
+//! The `generic-dtb` board is described entirely this way.
+
+use crate::board::{BoardConfig, FlashConfig, GicConfig, GicKind, UartConfig, UartKind};
+use crate::drivers::firmware::fw_cfg;
+use crate::drivers::pci;
+use crate::drivers::rtc::{self, goldfish, pl031};
+use crate::drivers::timer::generic;
+use crate::drivers::uart::pl011;
+use crate::drivers::watchdog::sp805;
+use crate::parsers::fdt::{self, Fdt, Node};
+use crate::selftest::Outcome;
+
+/// UART clock assumed when the DTB doesn't give one
+const DEFAULT_UART_CLOCK: u32 = 24_000_000;
+/// Baud rate programmed into the console UART
+const DEFAULT_BAUDRATE: u32 = 115_200;
+/// Maximum number of devices probed
+pub const MAX_DEVICES: usize = 16;
+/// Maximum number of passes over the devices
+pub const MAX_PASSES: usize = 4;
+/// Longest node name kept for [`devices`]
+const NODE_NAME_SIZE: usize = 32;
+
+/// Why a device wasn't probed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeError {
+    /// The device needs the one named, which isn't probed yet
+    Deferred(&'static [u8]),
+    /// The node has no usable `reg` entry
+    NoReg,
+    /// The board or an earlier node already provides the device, or its
+    /// driver is already set up
+    Present,
+}
+
+impl ProbeError {
+    /// Returns a description of the error
+    pub fn message(&self) -> &'static [u8] {
+        return match self {
+            ProbeError::Deferred(_) => b"deferred",
+            ProbeError::NoReg => b"no usable reg entry",
+            ProbeError::Present => b"already present",
+        };
+    }
+}
+
+/// Probe function of a driver, called with a node it's compatible with
+pub type Probe = fn(config: &mut BoardConfig, fdt: &Fdt, node: &Node) -> Result<(), ProbeError>;
+
+/// A driver that can be found in the device tree
+#[derive(Clone, Copy)]
+pub struct Driver {
+    /// Name shown by the `devices` command
+    pub name: &'static [u8],
+    /// `compatible` strings of the devices it drives
+    pub compatible: &'static [&'static [u8]],
+    /// Sets up the device of a matching node
+    pub probe: Probe,
+}
+
+/// Drivers probed by [`init_all`]
+///
+/// A node is probed by the first driver matching one of its `compatible`
+/// strings.
+pub const DRIVERS: [Driver; 10] = [
+    Driver {
+        name: b"pl011",
+        compatible: &[b"arm,pl011"],
+        probe: probe_pl011,
+    },
+    Driver {
+        name: b"gicv3",
+        compatible: &[b"arm,gic-v3"],
+        probe: probe_gic_v3,
+    },
+    Driver {
+        name: b"gicv2",
+        compatible: &[b"arm,gic-400", b"arm,cortex-a15-gic"],
+        probe: probe_gic_v2,
+    },
+    Driver {
+        name: b"timer",
+        compatible: &[b"arm,armv8-timer", b"arm,armv7-timer"],
+        probe: probe_timer,
+    },
+    Driver {
+        name: b"cfi-flash",
+        compatible: &[b"cfi-flash"],
+        probe: probe_cfi_flash,
+    },
+    Driver {
+        name: b"pl031",
+        compatible: &[b"arm,pl031"],
+        probe: probe_pl031,
+    },
+    Driver {
+        name: b"goldfish-rtc",
+        compatible: &[b"google,goldfish-rtc"],
+        probe: probe_goldfish_rtc,
+    },
+    Driver {
+        name: b"sp805",
+        compatible: &[b"arm,sp805"],
+        probe: probe_sp805,
+    },
+    Driver {
+        name: b"fw-cfg",
+        compatible: &[b"qemu,fw-cfg-mmio"],
+        probe: probe_fw_cfg,
+    },
+    Driver {
+        name: b"pcie-ecam",
+        compatible: &[b"pci-host-ecam-generic"],
+        probe: probe_pcie_ecam,
+    },
+];
+
+/// A device found in the device tree, and how its probe went
+#[derive(Clone, Copy, Debug)]
+pub struct Device {
+    /// Name of its driver
+    pub driver: &'static [u8],
+    /// Node name, truncated to [`NODE_NAME_SIZE`] bytes
+    name: [u8; NODE_NAME_SIZE],
+    /// Length of the node name
+    name_len: usize,
+    /// Outcome of the last probe
+    pub result: Result<(), ProbeError>,
+}
+
+impl Device {
+    /// Returns the name of the device's node
+    pub fn name(&self) -> &[u8] {
+        return &self.name[..self.name_len];
+    }
+}
+
+/// Devices found by [`init_all`]
+static mut DEVICES: [Option<Device>; MAX_DEVICES] = [None; MAX_DEVICES];
+
+/// Probes the devices of `fdt` with [`DRIVERS`], keeping the outcome for
+/// [`devices`]
+///
+/// Returns the number of devices probed.
+pub fn init_all(fdt: &Fdt, config: &mut BoardConfig) -> usize {
+    let devices = &raw mut DEVICES;
+
+    return probe(fdt, &DRIVERS, config, unsafe { &mut *devices });
+}
+
+/// Returns the devices found by [`init_all`], in device tree order
+pub fn devices() -> impl Iterator<Item = Device> {
+    let devices = &raw const DEVICES;
+
+    return unsafe { &*devices }.iter().flatten().copied();
+}
+
+/// Checks whether `node` is enabled: without a `status`, or with `okay`
+fn is_enabled(node: &Node) -> bool {
+    return node
+        .property_str(b"status")
+        .is_none_or(|status| status == b"okay" || status == b"ok");
+}
+
+/// Probes the enabled nodes of `fdt` matching one of `drivers`, as
+/// described in the [module documentation](self)
+///
+/// Each device is recorded in `devices`, whose size limits how many are
+/// probed. Returns the number of devices probed.
+pub fn probe(
+    fdt: &Fdt,
+    drivers: &[Driver],
+    config: &mut BoardConfig,
+    devices: &mut [Option<Device>],
+) -> usize {
+    let mut nodes: [Option<(Probe, Node)>; MAX_DEVICES] = [const { None }; MAX_DEVICES];
+    let capacity = devices.len().min(MAX_DEVICES);
+    let mut count = 0;
+
+    let _ = fdt.find_node(|node| {
+        let driver = drivers
+            .iter()
+            .find(|d| d.compatible.iter().any(|&c| node.is_compatible(c)));
+        if let Some(driver) = driver
+            && is_enabled(node)
+        {
+            let len = node.name.len().min(NODE_NAME_SIZE);
+            let mut device = Device {
+                driver: driver.name,
+                name: [0; NODE_NAME_SIZE],
+                name_len: len,
+                result: Err(ProbeError::Deferred(b"")),
+            };
+            device.name[..len].copy_from_slice(&node.name[..len]);
+            devices[count] = Some(device);
+            nodes[count] = Some((driver.probe, node.clone()));
+            count += 1;
+        }
+        // Stops the walk once the table is full
+        return count == capacity;
+    });
+    for device in devices[count..].iter_mut() {
+        *device = None;
+    }
+
+    for _ in 0..MAX_PASSES {
+        let mut progress = false;
+
+        for (device, node) in devices.iter_mut().zip(nodes.iter()) {
+            let (Some(device), Some((probe, node))) = (device, node) else {
+                continue;
+            };
+            if let Err(ProbeError::Deferred(_)) = device.result {
+                device.result = probe(config, fdt, node);
+                progress |= device.result.is_ok();
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    return devices
+        .iter()
+        .flatten()
+        .filter(|device| device.result.is_ok())
+        .count();
+}
+
+/// Returns the base of the first `reg` entry of `node`
+fn reg_base(node: &Node) -> Result<usize, ProbeError> {
+    return node
+        .reg(0)
+        .map(|(base, _)| base as usize)
+        .ok_or(ProbeError::NoReg);
+}
+
+/// Makes the PL011 of `node` the console, unless there already is one
+fn probe_pl011(config: &mut BoardConfig, fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+
+    if config.uart.kind != UartKind::None {
+        return Err(ProbeError::Present);
+    }
+    config.uart = UartConfig {
+        kind: UartKind::Pl011,
+        base,
+        clock: pl011::node_clock(fdt, node).unwrap_or(DEFAULT_UART_CLOCK),
+        baudrate: DEFAULT_BAUDRATE,
+    };
+    return Ok(());
+}
+
+/// Uses the GIC of `node` unless one is already known
+///
+/// The distributor is the first `reg` entry, the CPU interface or the
+/// redistributors the second one.
+fn probe_gic(config: &mut BoardConfig, node: &Node, kind: GicKind) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+    let (cpu, _) = node.reg(1).ok_or(ProbeError::NoReg)?;
+
+    if config.gic.kind != GicKind::None {
+        return Err(ProbeError::Present);
+    }
+    config.gic = GicConfig {
+        kind,
+        dist_base: base,
+        cpu_base: cpu as usize,
+    };
+    return Ok(());
+}
+
+/// Uses the GICv3 of `node`
+fn probe_gic_v3(config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    return probe_gic(config, node, GicKind::V3);
+}
+
+/// Uses the GICv2 of `node`
+fn probe_gic_v2(config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    return probe_gic(config, node, GicKind::V2);
+}
+
+/// Takes the EL1 physical timer interrupt from the generic timer's node
+///
+/// Waits for the interrupt controller the interrupt goes to. `interrupts`
+/// lists the secure, non-secure, virtual and hypervisor timer PPIs, as
+/// three cells each; without it, the default PPI is kept.
+fn probe_timer(config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    if config.gic.kind == GicKind::None {
+        return Err(ProbeError::Deferred(b"interrupt controller"));
+    }
+    if let Some(interrupts) = node.property(b"interrupts")
+        && interrupts.len().is_multiple_of(12)
+        && let Some(ppi) = fdt::be32(interrupts, 16)
+    {
+        generic::set_timer_intid(ppi + 16);
+    }
+    return Ok(());
+}
+
+/// Uses the CFI flash of `node`, without an environment
+fn probe_cfi_flash(config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    let (base, size) = node.reg(0).ok_or(ProbeError::NoReg)?;
+
+    if config.flash.is_some() {
+        return Err(ProbeError::Present);
+    }
+    config.flash = Some(FlashConfig {
+        base: base as usize,
+        size: size as usize,
+        env_offset: 0,
+        env_size: 0,
+    });
+    return Ok(());
+}
+
+/// Initializes the PL031 RTC of `node`, unless there already is an RTC
+fn probe_pl031(_config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+
+    if rtc::backend().is_some() {
+        return Err(ProbeError::Present);
+    }
+    return pl031::init(base).map_err(|_| ProbeError::Present);
+}
+
+/// Initializes the Goldfish RTC of `node`, unless there already is an RTC
+fn probe_goldfish_rtc(
+    _config: &mut BoardConfig,
+    _fdt: &Fdt,
+    node: &Node,
+) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+
+    if rtc::backend().is_some() {
+        return Err(ProbeError::Present);
+    }
+    return goldfish::init(base).map_err(|_| ProbeError::Present);
+}
+
+/// Initializes the SP805 watchdog of `node`
+fn probe_sp805(_config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+
+    if sp805::base().is_some() {
+        return Err(ProbeError::Present);
+    }
+    return sp805::init(base).map_err(|_| ProbeError::Present);
+}
+
+/// Records the QEMU fw_cfg device of `node`
+fn probe_fw_cfg(_config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+
+    if fw_cfg::base().is_some() {
+        return Err(ProbeError::Present);
+    }
+    return fw_cfg::init(base).map_err(|_| ProbeError::Present);
+}
+
+/// Records the PCIe host bridge of `node`
+fn probe_pcie_ecam(_config: &mut BoardConfig, _fdt: &Fdt, node: &Node) -> Result<(), ProbeError> {
+    let host = pci::host_from_node(node).ok_or(ProbeError::NoReg)?;
+
+    if pci::host().is_some() {
+        return Err(ProbeError::Present);
+    }
+    return pci::init(host).map_err(|_| ProbeError::Present);
+}
+
+/// Capacity of the self-test's device tree
+const TEST_DTB_CAPACITY: usize = 768;
+/// Offset of the self-test's structure block: right after the header and
+/// an empty memory reservation block
+const TEST_DTB_STRUCT: usize = fdt::FDT_HEADER_SIZE + 16;
+/// Strings block of the self-test's device tree
+const TEST_DTB_STRINGS: &[u8] =
+    b"#address-cells\0#size-cells\0compatible\0reg\0clock-frequency\0ranges\0";
+/// Offset of `#address-cells` in [`TEST_DTB_STRINGS`]
+const TEST_ADDRESS_CELLS: u32 = 0;
+/// Offset of `#size-cells` in [`TEST_DTB_STRINGS`]
+const TEST_SIZE_CELLS: u32 = 15;
+/// Offset of `compatible` in [`TEST_DTB_STRINGS`]
+const TEST_COMPATIBLE: u32 = 27;
+/// Offset of `reg` in [`TEST_DTB_STRINGS`]
+const TEST_REG: u32 = 38;
+/// Offset of `clock-frequency` in [`TEST_DTB_STRINGS`]
+const TEST_CLOCK_FREQUENCY: u32 = 42;
+/// Offset of `ranges` in [`TEST_DTB_STRINGS`]
+const TEST_RANGES: u32 = 58;
+/// Base of the self-test's platform bus
+const TEST_BUS_BASE: u32 = 0x0c00_0000;
+/// Address of the self-test's Goldfish RTC on its platform bus
+const TEST_BUS_RTC: u32 = 0x1000;
+/// Reference clock of the self-test's UART, other than
+/// [`DEFAULT_UART_CLOCK`]
+const TEST_UART_CLOCK: u32 = 48_000_000;
+/// Devices of the self-test's device tree: node name, `compatible`, base
+/// and `clock-frequency` (0 for none)
+const TEST_DEVICES: [(&[u8], &[u8], u32, u32); 3] = [
+    (
+        b"uart@9000000\0",
+        b"arm,pl011\0arm,primecell\0",
+        0x0900_0000,
+        TEST_UART_CLOCK,
+    ),
+    (
+        b"rtc@9010000\0",
+        b"arm,pl031\0arm,primecell\0",
+        0x0901_0000,
+        0,
+    ),
+    (
+        b"gpio@9030000\0",
+        b"arm,pl061\0arm,primecell\0",
+        0x0903_0000,
+        0,
+    ),
+];
+
+/// Device tree of the self-test: [`TEST_DEVICES`] under a root node with two
+/// address and two size cells, then a platform bus at [`TEST_BUS_BASE`],
+/// with one address and one size cell, holding a Goldfish RTC at
+/// [`TEST_BUS_RTC`]
+static TEST_DTB: [u8; TEST_DTB_CAPACITY] = test_dtb();
+
+/// Writes `value` big-endian at `off` and returns the offset past it
+const fn put32(buf: &mut [u8; TEST_DTB_CAPACITY], off: usize, value: u32) -> usize {
+    let bytes = value.to_be_bytes();
+    let mut i = 0;
+
+    while i < 4 {
+        buf[off + i] = bytes[i];
+        i += 1;
+    }
+    return off + 4;
+}
+
+/// Writes `data` at `off` and returns the offset past it, padded to 4 bytes
+const fn put_bytes(buf: &mut [u8; TEST_DTB_CAPACITY], off: usize, data: &[u8]) -> usize {
+    let mut i = 0;
+
+    while i < data.len() {
+        buf[off + i] = data[i];
+        i += 1;
+    }
+    return (off + data.len()).next_multiple_of(4);
+}
+
+/// Writes a property token and returns the offset past it
+const fn put_prop(buf: &mut [u8; TEST_DTB_CAPACITY], off: usize, name: u32, value: &[u8]) -> usize {
+    let off = put32(buf, off, 3);
+    let off = put32(buf, off, value.len() as u32);
+    let off = put32(buf, off, name);
+    return put_bytes(buf, off, value);
+}
+
+/// Builds [`TEST_DTB`]
+const fn test_dtb() -> [u8; TEST_DTB_CAPACITY] {
+    let mut buf = [0u8; TEST_DTB_CAPACITY];
+    let mut off = TEST_DTB_STRUCT;
+    let mut i = 0;
+
+    off = put32(&mut buf, off, 1);
+    off = put_bytes(&mut buf, off, b"\0");
+    off = put_prop(&mut buf, off, TEST_ADDRESS_CELLS, &2u32.to_be_bytes());
+    off = put_prop(&mut buf, off, TEST_SIZE_CELLS, &2u32.to_be_bytes());
+    while i < TEST_DEVICES.len() {
+        let (name, compatible, base, clock) = TEST_DEVICES[i];
+        let mut reg = [0u8; 16];
+        let base = base.to_be_bytes();
+        let mut j = 0;
+        while j < 4 {
+            reg[4 + j] = base[j];
+            j += 1;
+        }
+        reg[14] = 0x10;
+
+        off = put32(&mut buf, off, 1);
+        off = put_bytes(&mut buf, off, name);
+        off = put_prop(&mut buf, off, TEST_COMPATIBLE, compatible);
+        off = put_prop(&mut buf, off, TEST_REG, &reg);
+        if clock != 0 {
+            off = put_prop(&mut buf, off, TEST_CLOCK_FREQUENCY, &clock.to_be_bytes());
+        }
+        off = put32(&mut buf, off, 2);
+        i += 1;
+    }
+
+    let bus = TEST_BUS_BASE.to_be_bytes();
+    let ranges = [
+        0, 0, 0, 0, 0, 0, 0, 0, bus[0], bus[1], bus[2], bus[3], 0x02, 0, 0, 0,
+    ];
+    let rtc = TEST_BUS_RTC.to_be_bytes();
+    let reg = [rtc[0], rtc[1], rtc[2], rtc[3], 0, 0, 0x10, 0];
+    off = put32(&mut buf, off, 1);
+    off = put_bytes(&mut buf, off, b"platform-bus@c000000\0");
+    off = put_prop(
+        &mut buf,
+        off,
+        TEST_COMPATIBLE,
+        b"qemu,platform\0simple-bus\0",
+    );
+    off = put_prop(&mut buf, off, TEST_ADDRESS_CELLS, &1u32.to_be_bytes());
+    off = put_prop(&mut buf, off, TEST_SIZE_CELLS, &1u32.to_be_bytes());
+    off = put_prop(&mut buf, off, TEST_RANGES, &ranges);
+    off = put32(&mut buf, off, 1);
+    off = put_bytes(&mut buf, off, b"rtc@1000\0");
+    off = put_prop(&mut buf, off, TEST_COMPATIBLE, b"google,goldfish-rtc\0");
+    off = put_prop(&mut buf, off, TEST_REG, &reg);
+    off = put32(&mut buf, off, 2);
+    off = put32(&mut buf, off, 2);
+
+    off = put32(&mut buf, off, 2);
+    off = put32(&mut buf, off, 9);
+
+    let strings = off;
+    let end = put_bytes(&mut buf, strings, TEST_DTB_STRINGS);
+    put32(&mut buf, 0, fdt::FDT_MAGIC);
+    put32(&mut buf, 4, end as u32);
+    put32(&mut buf, 8, TEST_DTB_STRUCT as u32);
+    put32(&mut buf, 12, strings as u32);
+    put32(&mut buf, 16, fdt::FDT_HEADER_SIZE as u32);
+    put32(&mut buf, 20, 17);
+    put32(&mut buf, 24, 16);
+    put32(&mut buf, 32, TEST_DTB_STRINGS.len() as u32);
+    put32(&mut buf, 36, (strings - TEST_DTB_STRUCT) as u32);
+    return buf;
+}
+
+/// Bases the self-test's probes were called with, in [`TEST_DEVICES`] order
+static mut TEST_FOUND: [usize; 3] = [0; 3];
+
+/// Records a call of the self-test's probe for `index` with `node`
+fn record(index: usize, node: &Node) -> Result<(), ProbeError> {
+    let base = reg_base(node)?;
+
+    unsafe {
+        TEST_FOUND[index] = base;
+    }
+    return Ok(());
+}
+
+/// Self-test: probing a small device tree probes the devices it has, with
+/// their bases, and only those; a probe deferred until a later node is
+/// probed succeeds on the next pass, and one never satisfied stays deferred
+pub fn selftest() -> Outcome {
+    let drivers = [
+        Driver {
+            name: b"rtc",
+            compatible: &[b"arm,pl031"],
+            probe: |_, _, node| match unsafe { TEST_FOUND[2] } {
+                0 => Err(ProbeError::Deferred(b"gpio")),
+                _ => record(1, node),
+            },
+        },
+        Driver {
+            name: b"gpio",
+            compatible: &[b"arm,pl061"],
+            probe: |_, _, node| record(2, node),
+        },
+        Driver {
+            name: b"uart",
+            compatible: &[b"arm,pl011"],
+            probe: |_, _, _| Err(ProbeError::Deferred(b"nothing")),
+        },
+        Driver {
+            name: b"watchdog",
+            compatible: &[b"arm,sp805"],
+            probe: |_, _, node| record(0, node),
+        },
+    ];
+    let Ok(tree) = Fdt::new(&TEST_DTB) else {
+        return Outcome::Fail;
+    };
+    let mut config = *crate::board::config();
+    let mut devices = [None; MAX_DEVICES];
+
+    unsafe {
+        TEST_FOUND = [0; 3];
+    }
+    let count = probe(&tree, &drivers, &mut config, &mut devices);
+    let found = unsafe { TEST_FOUND };
+    if count != 2 || found != [0, 0x0901_0000, 0x0903_0000] {
+        return Outcome::Fail;
+    }
+    let expected: [(&[u8], &[u8], _); 3] = [
+        (
+            b"uart@9000000",
+            b"uart",
+            Err(ProbeError::Deferred(b"nothing")),
+        ),
+        (b"rtc@9010000", b"rtc", Ok(())),
+        (b"gpio@9030000", b"gpio", Ok(())),
+    ];
+    for (device, (name, driver, result)) in devices.iter().zip(expected) {
+        if !device.is_some_and(|d| d.name() == name && d.driver == driver && d.result == result) {
+            return Outcome::Fail;
+        }
+    }
+    if devices[3..].iter().any(|d| d.is_some()) {
+        return Outcome::Fail;
+    }
+
+    // The real PL011 probe makes the UART the console, once
+    config.uart.kind = UartKind::None;
+    probe(&tree, &DRIVERS[..1], &mut config, &mut devices);
+    if config.uart.kind != UartKind::Pl011
+        || config.uart.base != 0x0900_0000
+        || config.uart.clock != TEST_UART_CLOCK
+    {
+        return Outcome::Fail;
+    }
+    probe(&tree, &DRIVERS[..1], &mut config, &mut devices);
+    if devices[0].map(|d| d.result) != Some(Err(ProbeError::Present)) {
+        return Outcome::Fail;
+    }
+
+    return Outcome::Pass;
+}
+
+/// Self-test: the console UART's reference clock is read from its DTB node
+/// and sets the baud rate divisor, and the given clock is kept for a base
+/// without a PL011 node or a node without a clock
+pub fn selftest_uart_clock() -> Outcome {
+    let Ok(tree) = Fdt::new(&TEST_DTB) else {
+        return Outcome::Fail;
+    };
+    let clock = pl011::fdt_clock(&tree, 0x0900_0000, DEFAULT_UART_CLOCK);
+
+    // 48 MHz at 115200: IBRD 26, FBRD 2
+    if clock != TEST_UART_CLOCK || pl011::divisor(clock, DEFAULT_BAUDRATE) != Some(26 << 6 | 2) {
+        return Outcome::Fail;
+    }
+    if pl011::fdt_clock(&tree, 0x0901_0000, DEFAULT_UART_CLOCK) != DEFAULT_UART_CLOCK
+        || pl011::fdt_clock(&tree, 0x0a00_0000, DEFAULT_UART_CLOCK) != DEFAULT_UART_CLOCK
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Self-test: a device on a platform bus is probed at its address
+/// translated through the bus's `ranges`
+pub fn selftest_bus() -> Outcome {
+    let drivers = [Driver {
+        name: b"goldfish-rtc",
+        compatible: &[b"google,goldfish-rtc"],
+        probe: |_, _, node| match node.reg(0) {
+            Some((0x0c00_1000, 0x1000)) => Ok(()),
+            _ => Err(ProbeError::NoReg),
+        },
+    }];
+    let Ok(tree) = Fdt::new(&TEST_DTB) else {
+        return Outcome::Fail;
+    };
+    let mut config = *crate::board::config();
+    let mut devices = [None; MAX_DEVICES];
+
+    if probe(&tree, &drivers, &mut config, &mut devices) != 1
+        || devices[0].is_none_or(|d| d.name() != b"rtc@1000")
+    {
+        return Outcome::Fail;
+    }
+    // Nodes on the root's bus keep their address
+    let uart = tree.find_compatible(b"arm,pl011");
+    if !uart.is_ok_and(|node| node.and_then(|n| n.reg(0)) == Some((0x0900_0000, 0x1000))) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,85 @@
+//! Goldfish real-time clock driver
+//!
+//! The Goldfish RTC, found on newer QEMU machines in place of a PL031,
+//! counts nanoseconds since the Unix epoch in a 64-bit register read as two
+//! 32-bit halves: reading the low half latches the high half, so the two
+//! always belong to the same count. Like the PL031, it has no board
+//! configuration entry and is found in the device tree (see
+//! [`registry`](crate::drivers::registry)).
+//!
+//! The alarm and interrupt registers (0x08 to 0x1c) are left alone: the
+//! bootloader never sets an alarm.
+
+use crate::drivers::lifecycle::{Lifecycle, WrongState};
+use crate::utilities::mmio;
+
+/// Time register, low 32 bits; reading it latches [`TIME_HIGH`]
+const TIME_LOW: usize = 0x00;
+/// Time register, high 32 bits, as latched by the last read of [`TIME_LOW`]
+const TIME_HIGH: usize = 0x04;
+/// Nanoseconds in a second
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Base address of the RTC, 0 until [`init`] is called
+static mut BASE: usize = 0;
+/// Lifecycle of the driver
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Sets up the RTC at `base`
+///
+/// The counter always runs and keeps its value: setting the time is left
+/// to the OS. Fails, changing nothing, if the driver is already set up.
+pub fn init(base: usize) -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).start()?;
+        BASE = base;
+    }
+    return Ok(());
+}
+
+/// Stops the driver for the payload
+///
+/// No alarm was set, so there is nothing to stop. Fails if it isn't set up
+/// or is already quiesced.
+pub fn quiesce() -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe { (*lifecycle).quiesce() }?;
+    return Ok(());
+}
+
+/// Forgets the RTC, so [`init`] can set it up again
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        BASE = 0;
+    }
+}
+
+/// Returns the base address of the RTC, if [`init`] was called
+pub fn base() -> Option<usize> {
+    let base = unsafe { BASE };
+
+    return (base != 0).then_some(base);
+}
+
+/// Returns the current time in nanoseconds, if the RTC is initialized
+pub fn nanoseconds() -> Option<u64> {
+    let base = base()?;
+
+    unsafe {
+        // The low half first: it latches the high half
+        let low = mmio::read_mmio32(base, TIME_LOW);
+        let high = mmio::read_mmio32(base, TIME_HIGH);
+        return Some((high as u64) << 32 | low as u64);
+    }
+}
+
+/// Returns the current time in seconds, if the RTC is initialized
+pub fn seconds() -> Option<u64> {
+    return nanoseconds().map(|ns| ns / NANOS_PER_SECOND);
+}
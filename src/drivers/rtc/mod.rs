@@ -0,0 +1,204 @@
+//! Real-time clock drivers module
+//!
+//! A machine has at most one RTC in use: a PL031 or, on newer QEMU
+//! machines, a Goldfish RTC. Whichever the device tree describes is set up
+//! by the [`registry`](crate::drivers::registry); the rest of the
+//! bootloader reads the time through [`seconds`] and [`now`], whatever the
+//! device.
+
+pub mod goldfish;
+pub mod pl031;
+
+use crate::drivers::timer::generic;
+use crate::selftest::Outcome;
+use crate::utilities::print::u64_to_dec;
+
+/// Seconds in a day
+const SECONDS_PER_DAY: u64 = 86_400;
+/// Length of a date and time formatted by [`DateTime::format`]
+pub const DATETIME_SIZE: usize = 19;
+
+/// Device providing the time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// ARM PL031
+    Pl031,
+    /// Goldfish RTC
+    Goldfish,
+}
+
+impl Backend {
+    /// Returns the name of the device's driver
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            Backend::Pl031 => b"pl031",
+            Backend::Goldfish => b"goldfish",
+        };
+    }
+}
+
+/// Returns the RTC in use, if one was set up
+pub fn backend() -> Option<Backend> {
+    if pl031::base().is_some() {
+        return Some(Backend::Pl031);
+    }
+    if goldfish::base().is_some() {
+        return Some(Backend::Goldfish);
+    }
+    return None;
+}
+
+/// Returns the current time in seconds since the Unix epoch, if there is
+/// an RTC
+pub fn seconds() -> Option<u64> {
+    return match backend()? {
+        Backend::Pl031 => pl031::seconds().map(|s| s as u64),
+        Backend::Goldfish => goldfish::seconds(),
+    };
+}
+
+/// Returns the current date and time (UTC), if there is an RTC
+pub fn now() -> Option<DateTime> {
+    return seconds().map(DateTime::from_unix);
+}
+
+/// A date and time of the proleptic Gregorian calendar, UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    /// Year
+    pub year: u64,
+    /// Month, 1 to 12
+    pub month: u8,
+    /// Day of the month, 1 to 31
+    pub day: u8,
+    /// Hour, 0 to 23
+    pub hour: u8,
+    /// Minute, 0 to 59
+    pub minute: u8,
+    /// Second, 0 to 59
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns the date and time `seconds` after the Unix epoch
+    ///
+    /// Days are turned into a date counting from 1 March 0000, so that the
+    /// leap day ends the year and every 400-year era has the same length.
+    pub const fn from_unix(seconds: u64) -> DateTime {
+        let time = seconds % SECONDS_PER_DAY;
+        // 719468 days from 1 March 0000 to 1 January 1970
+        let days = seconds / SECONDS_PER_DAY + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // Months from March, each of 30 or 31 days but February
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        return DateTime {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        };
+    }
+
+    /// Formats the date and time as `YYYY-MM-DD HH:MM:SS`
+    ///
+    /// Years past 9999 keep their last four digits.
+    pub fn format(&self) -> [u8; DATETIME_SIZE] {
+        let mut buf = *b"0000-00-00 00:00:00";
+        let fields = [
+            (0, 4, self.year),
+            (5, 2, self.month as u64),
+            (8, 2, self.day as u64),
+            (11, 2, self.hour as u64),
+            (14, 2, self.minute as u64),
+            (17, 2, self.second as u64),
+        ];
+
+        // Digits go right-aligned, over the zeros
+        for (off, width, value) in fields {
+            u64_to_dec(value, &mut buf[off..off + width]);
+        }
+        return buf;
+    }
+}
+
+const _: () = assert!(matches!(
+    DateTime::from_unix(0),
+    DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0
+    }
+));
+// A leap day, and the last second of a year
+const _: () = assert!(matches!(
+    DateTime::from_unix(951_827_696),
+    DateTime {
+        year: 2000,
+        month: 2,
+        day: 29,
+        hour: 12,
+        minute: 34,
+        second: 56
+    }
+));
+const _: () = assert!(matches!(
+    DateTime::from_unix(4_102_444_799),
+    DateTime {
+        year: 2099,
+        month: 12,
+        day: 31,
+        hour: 23,
+        minute: 59,
+        second: 59
+    }
+));
+
+/// Time [`selftest`] lets pass between its two readings, in milliseconds
+const TEST_DELAY_MS: u64 = 1_500;
+
+/// Self-test: the RTC in use gives valid dates, and its time advances by
+/// one or two seconds over a delay of 1.5 s
+///
+/// Skipped when no RTC was set up.
+pub fn selftest() -> Outcome {
+    let Some(device) = backend() else {
+        return Outcome::Skipped;
+    };
+    let (Some(before), Some(date)) = (seconds(), now()) else {
+        return Outcome::Fail;
+    };
+
+    generic::delay_ms(TEST_DELAY_MS);
+    let (Some(after), Some(later)) = (seconds(), now()) else {
+        return Outcome::Fail;
+    };
+    let valid = |date: DateTime| {
+        return (1..=12).contains(&date.month)
+            && (1..=31).contains(&date.day)
+            && date.hour < 24
+            && date.minute < 60
+            && date.second < 60;
+    };
+
+    if backend() != Some(device)
+        || !valid(date)
+        || !valid(later)
+        || !matches!(after.checked_sub(before), Some(1..=2))
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
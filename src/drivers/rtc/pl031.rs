@@ -0,0 +1,75 @@
+//! ARM PL031 real-time clock driver
+//!
+//! The PL031 counts seconds in a 32-bit register, usually since the Unix
+//! epoch. It has no board configuration entry: the driver learns its base
+//! address when the device is found in the device tree (see
+//! [`registry`](crate::drivers::registry)).
+
+use crate::drivers::lifecycle::{Lifecycle, WrongState};
+use crate::utilities::mmio;
+
+/// Data register: current time in seconds
+const RTCDR: usize = 0x00;
+/// Control register
+const RTCCR: usize = 0x0c;
+/// RTCCR: the counter is running
+const RTCCR_START: u32 = 1 << 0;
+
+/// Base address of the RTC, 0 until [`init`] is called
+static mut BASE: usize = 0;
+/// Lifecycle of the driver
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Sets up the RTC at `base` and starts its counter if it's stopped
+///
+/// The counter keeps the value it had: setting the time is left to the OS.
+/// Fails, changing nothing, if the driver is already set up.
+pub fn init(base: usize) -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).start()?;
+        BASE = base;
+        if !mmio::test_bit32(base, RTCCR, 0) {
+            mmio::write_mmio32(base, RTCCR, RTCCR_START);
+        }
+    }
+    return Ok(());
+}
+
+/// Stops the driver for the payload
+///
+/// The counter keeps running for the payload. Fails if it isn't set up or is
+/// already quiesced.
+pub fn quiesce() -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe { (*lifecycle).quiesce() }?;
+    return Ok(());
+}
+
+/// Forgets the RTC, so [`init`] can set it up again
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        BASE = 0;
+    }
+}
+
+/// Returns the base address of the RTC, if [`init`] was called
+pub fn base() -> Option<usize> {
+    let base = unsafe { BASE };
+
+    return (base != 0).then_some(base);
+}
+
+/// Returns the current time in seconds, if the RTC is initialized
+pub fn seconds() -> Option<u32> {
+    let base = base()?;
+
+    unsafe {
+        return Some(mmio::read_mmio32(base, RTCDR));
+    }
+}
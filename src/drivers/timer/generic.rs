@@ -0,0 +1,380 @@
+//! ARM Generic Timer driver for AArch64
+//!
+//! This module provides access to the architectural system counter that every
+//! ARMv8-A core implements. The counter runs at a fixed frequency reported by
+//! `CNTFRQ_EL0` and is readable from any exception level without any device
+//! setup, which makes it usable very early during boot.
+//!
+//! Busy-wait delays are built on the counter. On platforms where firmware
+//! never programs the counter frequency, they fall back to a busy loop whose
+//! speed is measured by [`calibrate_loop`].
+//!
+//! [`print_uptime`] reports the time elapsed since the counter started,
+//! normally at reset.
+//!
+//! Waits that must not last forever take a [`Deadline`], a point in time on
+//! the counter.
+//!
+//! The EL1 physical timer (`CNTP_*_EL0`) can also raise a periodic interrupt
+//! (see [`start_tick`]) for background work that must keep running during
+//! long operations.
+
+use crate::drivers::uart::pl011;
+use crate::selftest::Outcome;
+use crate::utilities::print;
+
+use crate::asm;
+
+/// Busy-loop iterations per microsecond used when no counter frequency is set
+///
+/// Deliberately high so uncalibrated delays are too long rather than too
+/// short; [`calibrate_loop`] replaces it with a measured value.
+const DEFAULT_LOOPS_PER_US: u64 = 1000;
+/// Number of busy-loop iterations timed by [`calibrate_loop`]
+const CALIBRATION_LOOPS: u64 = 1 << 16;
+
+/// Interrupt ID of the EL1 physical timer when the device tree doesn't
+/// give one (PPI 14)
+pub const DEFAULT_TIMER_INTID: u32 = 30;
+/// CNTP_CTL_EL0 Enable bit
+const CNTP_CTL_ENABLE: u64 = 1 << 0;
+
+/// Callback invoked on every timer tick, from interrupt context
+pub type TickHandler = fn();
+
+/// Busy-loop iterations per microsecond used by the fallback delay
+static mut LOOPS_PER_US: u64 = DEFAULT_LOOPS_PER_US;
+
+/// Registered tick callback
+static mut TICK_HANDLER: Option<TickHandler> = None;
+
+/// Counter ticks between two timer interrupts
+static mut TICK_PERIOD: u64 = 0;
+
+/// Number of timer interrupts handled
+static mut TICK_COUNT: u64 = 0;
+
+/// Interrupt ID of the EL1 physical timer
+static mut TIMER_INTID: u32 = DEFAULT_TIMER_INTID;
+
+/// Reads the current value of the physical counter (`CNTPCT_EL0`)
+///
+/// An `isb` is issued first so the read is not speculated ahead of earlier
+/// instructions.
+#[inline(always)]
+pub fn counter() -> u64 {
+    let ticks: u64;
+
+    unsafe {
+        asm!("isb", "mrs {}, cntpct_el0", out(reg) ticks, options(nomem, nostack));
+    }
+    return ticks;
+}
+
+/// Reads the frequency of the system counter in Hz (`CNTFRQ_EL0`)
+///
+/// The value is programmed by firmware; a value of 0 means it was never set.
+#[inline(always)]
+pub fn frequency() -> u64 {
+    let freq: u64;
+
+    unsafe {
+        asm!("mrs {}, cntfrq_el0", out(reg) freq, options(nomem, nostack));
+    }
+    return freq;
+}
+
+/// Converts a duration in milliseconds to system counter ticks
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    return ms.saturating_mul(frequency()) / 1000;
+}
+
+/// Converts a duration in microseconds to ticks at `freq` Hz
+pub const fn us_to_ticks(us: u64, freq: u64) -> u64 {
+    let ticks = us as u128 * freq as u128 / 1_000_000;
+
+    if ticks > u64::MAX as u128 {
+        return u64::MAX;
+    }
+    return ticks as u64;
+}
+
+const _: () = assert!(us_to_ticks(1_000_000, 62_500_000) == 62_500_000);
+const _: () = assert!(us_to_ticks(3, 62_500_000) == 187 && us_to_ticks(1, 0) == 0);
+const _: () = assert!(us_to_ticks(u64::MAX, 1_000_000_000) == u64::MAX);
+
+/// A point in time after which a wait gives up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadline {
+    /// Counter value at which the deadline expires, `u64::MAX` for never
+    at: u64,
+}
+
+impl Deadline {
+    /// A deadline that never expires
+    pub const NEVER: Deadline = Deadline { at: u64::MAX };
+
+    /// Returns the deadline `ms` milliseconds from now
+    ///
+    /// Without a counter frequency, time can't be measured and the deadline
+    /// never expires.
+    pub fn after_ms(ms: u64) -> Deadline {
+        if frequency() == 0 {
+            return Deadline::NEVER;
+        }
+        return Deadline {
+            at: counter().saturating_add(ms_to_ticks(ms)),
+        };
+    }
+
+    /// Returns whichever of `self` and `other` expires first
+    pub fn min(self, other: Deadline) -> Deadline {
+        return Deadline {
+            at: self.at.min(other.at),
+        };
+    }
+
+    /// Checks whether the deadline has passed
+    pub fn expired(&self) -> bool {
+        return self.at != u64::MAX && counter() >= self.at;
+    }
+}
+
+/// Converts `ticks` of a counter running at `freq` Hz to milliseconds
+///
+/// Rounds down; returns 0 if `freq` is 0.
+pub const fn ticks_to_ms(ticks: u64, freq: u64) -> u64 {
+    if freq == 0 {
+        return 0;
+    }
+    return (ticks as u128 * 1000 / freq as u128) as u64;
+}
+
+// 54 MHz (Raspberry Pi 4) and 62.5 MHz (QEMU) counters
+const _: () = assert!(ticks_to_ms(66_654_000, 54_000_000) == 1234);
+const _: () = assert!(ticks_to_ms(54_000_000 * 3600, 54_000_000) == 3_600_000);
+const _: () = assert!(ticks_to_ms(62_437_500, 62_500_000) == 999);
+const _: () = assert!(ticks_to_ms(u64::MAX, 1000) == u64::MAX);
+const _: () = assert!(ticks_to_ms(12345, 0) == 0);
+
+/// Converts `ticks` of a counter running at `freq` Hz to microseconds
+///
+/// Rounds down; returns 0 if `freq` is 0.
+pub const fn ticks_to_us(ticks: u64, freq: u64) -> u64 {
+    if freq == 0 {
+        return 0;
+    }
+    return (ticks as u128 * 1_000_000 / freq as u128) as u64;
+}
+
+const _: () = assert!(ticks_to_us(62_500_000, 62_500_000) == 1_000_000);
+const _: () = assert!(ticks_to_us(125, 62_500_000) == 2);
+const _: () = assert!(ticks_to_us(12345, 0) == 0);
+
+/// Returns the milliseconds elapsed since the counter started
+///
+/// The counter normally starts at reset. Returns 0 if the counter frequency
+/// isn't set.
+pub fn uptime_ms() -> u64 {
+    return ticks_to_ms(counter(), frequency());
+}
+
+/// Returns the microseconds elapsed since the counter started, 0 if the
+/// counter frequency isn't set
+pub fn uptime_us() -> u64 {
+    return ticks_to_us(counter(), frequency());
+}
+
+/// Prints the time elapsed since the counter started
+///
+/// Printed as seconds with millisecond precision, e.g. `Uptime: 1.234 s`.
+pub fn print_uptime() {
+    pl011::print(b"Uptime: ");
+    if frequency() == 0 {
+        pl011::println(b"unknown (counter frequency not set)");
+        return;
+    }
+    print::print_fixed(uptime_ms(), 3);
+    pl011::println(b" s");
+}
+
+/// Spins for `loops` iterations of the calibrated busy loop
+#[inline(never)]
+fn spin(loops: u64) {
+    for _ in 0..loops {
+        core::hint::spin_loop();
+    }
+}
+
+/// Converts a loops-per-tick measurement to loops per microsecond at
+/// `freq` Hz, never returning less than 1
+pub const fn loops_per_us(loops_per_tick: u64, freq: u64) -> u64 {
+    let loops = us_to_ticks(loops_per_tick, freq);
+
+    if loops == 0 {
+        return 1;
+    }
+    return loops;
+}
+
+// 16 loops per tick of a 62.5 MHz counter is 1000 loops per microsecond
+const _: () = assert!(loops_per_us(16, 62_500_000) == 1000);
+const _: () = assert!(loops_per_us(1, 1_000_000) == 1 && loops_per_us(1, 999_999) == 1);
+
+/// Measures how many busy-loop iterations run per counter tick
+///
+/// Times a fixed number of iterations against the system counter and returns
+/// iterations per tick (at least 1). If the counter frequency is known, the
+/// result is also recorded so delays on platforms whose counter later becomes
+/// unusable (or is never given a frequency) spin for the right time.
+pub fn calibrate_loop() -> u64 {
+    let start = counter();
+    spin(CALIBRATION_LOOPS);
+    let ticks = (counter() - start).max(1);
+    let loops_per_tick = (CALIBRATION_LOOPS / ticks).max(1);
+
+    let freq = frequency();
+    if freq != 0 {
+        unsafe {
+            LOOPS_PER_US = loops_per_us(loops_per_tick, freq);
+        }
+    }
+    return loops_per_tick;
+}
+
+/// Busy-waits for `us` microseconds
+///
+/// Uses the system counter if its frequency is set, and the calibrated busy
+/// loop otherwise.
+pub fn delay_us(us: u64) {
+    let freq = frequency();
+
+    if freq == 0 {
+        spin(us.saturating_mul(unsafe { LOOPS_PER_US }));
+        return;
+    }
+
+    let deadline = counter().saturating_add(us_to_ticks(us, freq));
+    while counter() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Busy-waits for `ms` milliseconds
+pub fn delay_ms(ms: u64) {
+    delay_us(ms.saturating_mul(1000));
+}
+
+/// Delay timed by [`selftest_delay`], in microseconds
+const TEST_DELAY_US: u64 = 2_000;
+/// Longest [`selftest_delay`] accepts the delay to take, in microseconds:
+/// generous, as an emulator may be descheduled
+const TEST_DELAY_MAX_US: u64 = 500_000;
+
+/// Self-test: [`delay_us`] waits at least as long as asked, and not
+/// unreasonably longer, and [`calibrate_loop`] measures at least one loop
+/// per tick
+///
+/// Skipped if the counter frequency isn't set, as nothing can time the
+/// delay then.
+pub fn selftest_delay() -> Outcome {
+    let freq = frequency();
+
+    if freq == 0 {
+        return Outcome::Skipped;
+    }
+    let start = counter();
+    delay_us(TEST_DELAY_US);
+    let elapsed = ticks_to_us(counter() - start, freq);
+
+    if (TEST_DELAY_US..=TEST_DELAY_MAX_US).contains(&elapsed) && calibrate_loop() >= 1 {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Lowest counter frequency accepted by [`selftest`], in Hz
+const MIN_SANE_FREQUENCY: u64 = 1_000_000;
+/// Highest counter frequency accepted by [`selftest`], in Hz
+const MAX_SANE_FREQUENCY: u64 = 1_000_000_000;
+
+/// Self-test: checks the counter frequency and that the counter runs
+///
+/// The frequency must be set and within [`MIN_SANE_FREQUENCY`] and
+/// [`MAX_SANE_FREQUENCY`] (ARMv8.6 fixes it at 1 GHz), and the counter must
+/// advance while the delay loop spins.
+pub fn selftest() -> Outcome {
+    let freq = frequency();
+
+    if !(MIN_SANE_FREQUENCY..=MAX_SANE_FREQUENCY).contains(&freq) {
+        return Outcome::Fail;
+    }
+    let start = counter();
+    spin(CALIBRATION_LOOPS);
+    if counter() <= start {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Returns the interrupt ID of the EL1 physical timer
+pub fn timer_intid() -> u32 {
+    return unsafe { TIMER_INTID };
+}
+
+/// Sets the interrupt ID of the EL1 physical timer, as found in the device
+/// tree
+pub fn set_timer_intid(intid: u32) {
+    unsafe {
+        TIMER_INTID = intid;
+    }
+}
+
+/// Starts raising [`timer_intid`] every `period_us` microseconds
+///
+/// `handler` is called on each tick by [`handle_tick`], which the interrupt
+/// dispatcher must call when [`timer_intid`] fires. Returns `false` if the
+/// counter frequency isn't set, in which case the timer isn't started.
+pub fn start_tick(period_us: u64, handler: TickHandler) -> bool {
+    let period = us_to_ticks(period_us, frequency()).max(1);
+
+    if frequency() == 0 {
+        return false;
+    }
+    unsafe {
+        TICK_HANDLER = Some(handler);
+        TICK_PERIOD = period;
+        asm!(
+            "msr cntp_tval_el0, {period}",
+            "msr cntp_ctl_el0, {ctl}",
+            "isb",
+            period = in(reg) period,
+            ctl = in(reg) CNTP_CTL_ENABLE,
+        );
+    }
+    return true;
+}
+
+/// Stops the periodic timer interrupt
+pub fn stop_tick() {
+    unsafe {
+        asm!("msr cntp_ctl_el0, xzr", "isb");
+        TICK_HANDLER = None;
+    }
+}
+
+/// Returns the number of timer interrupts handled since the boot
+pub fn tick_count() -> u64 {
+    return unsafe { TICK_COUNT };
+}
+
+/// Handles a timer interrupt: schedules the next tick and calls the handler
+pub fn handle_tick() {
+    unsafe {
+        asm!("msr cntp_tval_el0, {}", "isb", in(reg) TICK_PERIOD);
+        TICK_COUNT += 1;
+        if let Some(handler) = TICK_HANDLER {
+            handler();
+        }
+    }
+}
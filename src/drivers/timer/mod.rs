@@ -0,0 +1,3 @@
+//! Timer drivers module
+
+pub mod generic;
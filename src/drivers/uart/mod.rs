@@ -1,3 +1,7 @@
-//! PL011 UART driver module
+//! UART drivers
+//!
+//! - [`pl011`]: The ARM PL011, the console of every known board
+//! - [`ns16550`]: 16550 compatible UARTs, for a console found by probing
 
+pub mod ns16550;
 pub mod pl011;
@@ -0,0 +1,107 @@
+//! 16550 UART driver, output only
+//!
+//! A 16550 compatible UART becomes the console only when the board's console
+//! sweep finds one (see [`board_init`](crate::board::board_init)), on a board
+//! nothing describes. Its registers are taken to be 8 bits wide and 4 bytes
+//! apart, as on the Arm SoCs that have one (e.g., the DesignWare APB UART).
+//!
+//! Nothing tells the reference clock of such a UART, so [`init`] keeps the
+//! baud rate and line settings the firmware programmed and only makes sure
+//! the FIFOs are on. The console output reaches it through a
+//! [sink](crate::drivers::uart::pl011::add_sink) of the console: there is
+//! no input from it.
+
+use crate::drivers::timer::generic;
+use crate::utilities::mmio;
+
+/// Distance between two registers, in bytes
+const REG_STRIDE: usize = 4;
+/// Transmit holding register (write, DLAB clear)
+const THR_OFF: usize = 0;
+/// FIFO control register (write)
+const FCR_OFF: usize = 2 * REG_STRIDE;
+/// Line control register
+const LCR_OFF: usize = 3 * REG_STRIDE;
+/// Line status register
+const LSR_OFF: usize = 5 * REG_STRIDE;
+/// Scratch register
+const SCR_OFF: usize = 7 * REG_STRIDE;
+
+/// FCR: enable the FIFOs and clear both of them
+const FCR_FIFO_ENABLE_CLEAR: u32 = 0b111;
+/// LCR: divisor latch access bit, hiding the transmit holding register
+const LCR_DLAB: u32 = 1 << 7;
+/// LSR: the transmit holding register is empty
+const LSR_THRE: u32 = 1 << 5;
+
+/// Patterns the scratch register must hold back for [`is_16550_at`]
+const SCRATCH_PATTERNS: [u32; 2] = [0x5a, 0xa5];
+
+/// Time a character may wait for room in the transmitter, in microseconds:
+/// a byte at 9600 baud, with margin
+const TX_TIMEOUT_US: u64 = 2_000;
+
+/// Base address of the UART, or 0 until [`init`]
+static mut BASE: usize = 0;
+
+/// Checks whether a 16550 answers at `base`
+///
+/// The scratch register is read with [`mmio::probe_read32`], so an address
+/// nothing decodes is reported as `false` rather than faulting, then must
+/// hold two patterns back; its value is put back afterwards. RAM
+/// passes this check too, so `base` must be known not to be RAM.
+pub fn is_16550_at(base: usize) -> bool {
+    let Ok(saved) = mmio::probe_read32(base, SCR_OFF) else {
+        return false;
+    };
+    let holds = |pattern: u32| unsafe {
+        mmio::write_mmio32(base, SCR_OFF, pattern);
+        return mmio::read_mmio32(base, SCR_OFF) & 0xff == pattern;
+    };
+
+    let found = SCRATCH_PATTERNS.iter().all(|&pattern| holds(pattern));
+    unsafe {
+        mmio::write_mmio32(base, SCR_OFF, saved);
+    }
+    return found;
+}
+
+/// Makes the 16550 at `base` the UART [`putchar`] writes to
+///
+/// Clears the divisor latch access bit and turns the FIFOs on, leaving the
+/// baud rate and line settings as they are.
+pub fn init(base: usize) {
+    unsafe {
+        let lcr = mmio::read_mmio32(base, LCR_OFF);
+        mmio::write_mmio32(base, LCR_OFF, lcr & !LCR_DLAB);
+        mmio::write_mmio32(base, FCR_OFF, FCR_FIFO_ENABLE_CLEAR);
+        BASE = base;
+    }
+}
+
+/// Returns the base address of the UART, or 0 if [`init`] wasn't called
+pub fn base_addr() -> usize {
+    return unsafe { BASE };
+}
+
+/// Transmits `c`, once there is room in the transmitter
+///
+/// Gives up on the character after 2 ms, so a stuck UART can't hang the
+/// console. Does nothing before [`init`].
+pub fn putchar(c: u8) {
+    let base = base_addr();
+    let frequency = generic::frequency();
+    let start = generic::counter();
+
+    if base == 0 {
+        return;
+    }
+    unsafe {
+        while mmio::read_mmio32(base, LSR_OFF) & LSR_THRE == 0 {
+            if generic::ticks_to_us(generic::counter() - start, frequency) > TX_TIMEOUT_US {
+                return;
+            }
+        }
+        mmio::write_mmio32(base, THR_OFF, c as u32);
+    }
+}
@@ -4,9 +4,50 @@
 //! commonly used in ARM development boards and QEMU. It handles initialization,
 //! configuration, and basic character output via memory-mapped I/O (MMIO).
 //!
-//! The driver supports configurable baud rates, data bits, and stop bits.
+//! The driver supports configurable baud rates, data bits, and stop bits, as
+//! well as polled character input. Where nothing can be typed, the receiver
+//! can be left off with [`set_input`]; [`getchar`] then fails at once rather
+//! than waiting forever.
+//!
+//! Output printed before the UART is configured (e.g., by the board setup or
+//! an early panic) is kept in a small ring buffer and written out, in order,
+//! once [`configure_uart`] completes. If the buffer overflows, the oldest
+//! bytes are dropped and their number is reported.
+//!
+//! With [`set_core_prefix`], each printed line starts with `[cpuN] `, the
+//! number of the core that printed it. With [`set_id_prefix`], it starts
+//! with a tag such as the low bits of the [boot ID](crate::bootid) instead,
+//! followed by the core prefix if that is on too.
+//!
+//! Everything printed is also handed to the console
+//! [`capture`](crate::capture), which keeps it while capture is on, to the
+//! mirror set with [`set_mirror`], if any, and to the sinks added with
+//! [`add_sink`] (e.g., the framebuffer
+//! [console](crate::drivers::video::console)).
+//!
+//! The reference clock the baud rate is derived from can be read from the
+//! UART's DTB node with [`fdt_clock`], as boards don't all clock their
+//! PL011 the same. [`set_baud`] changes the rate of a configured UART on the
+//! fly, e.g. to a faster one after a handshake.
+//!
+//! A second PL011 can be set up for output only with [`init_tx_only`] and
+//! written with [`print_at`], e.g., as the [`log`](crate::log) debug channel.
+//!
+//! The console goes through the driver
+//! [lifecycle](crate::drivers::lifecycle): [`init_uart`] probes it and
+//! [`configure_uart`] activates it, each once, and [`quiesce`] hands it to
+//! the payload. Printing keeps working in every state.
 
-use crate::utilities::mmio;
+use crate::capture;
+use crate::cpu;
+use crate::drivers::lifecycle::{Lifecycle, State, WrongState};
+use crate::drivers::timer::generic;
+use crate::parsers::fdt::{self, Fdt, Node};
+use crate::selftest::Outcome;
+use crate::utilities::mmio::{self, MmioBus, PhysBus};
+#[cfg(feature = "mock")]
+use crate::utilities::print::CaptureSink;
+use crate::utilities::print::{Console, Uart, u64_to_dec, u64_to_hex, u64_to_hex_padded};
 use core::ptr::{null_mut, write_volatile};
 
 /// UART PL011 device configuration
@@ -24,6 +65,21 @@ struct UartPl011 {
     data_bits: u8,
     /// Number of stop bits (1 or 2)
     stop_bits: u8,
+    /// Whether the receiver is enabled by [`configure_uart`]
+    input: bool,
+}
+
+/// Errors reported while configuring the UART
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartError {
+    /// [`init_uart`] hasn't been called with a base address
+    NotInitialized,
+    /// The baud rate is zero or out of range for the base clock
+    BadBaudrate,
+    /// The receiver isn't enabled, so no input can be read
+    NoInput,
+    /// The console is already set up, or stopped for the payload
+    WrongState(WrongState),
 }
 
 // PL011 Register Offsets
@@ -31,8 +87,20 @@ struct UartPl011 {
 const DR_OFF: usize = 0x00;
 /// Flag Register offset - contains status flags
 const FR_OFF: usize = 0x18;
-/// Flag Register BUSY bit - indicates UART is transmitting
-const FR_BUSY: u32 = 1 << 3;
+/// Data Register Break Error bit - a break condition was received
+const DR_BE: u32 = 1 << 10;
+/// Data Register error bits (overrun, break, parity, framing)
+const DR_ERRORS: u32 = 0xf << 8;
+/// Data Register overrun error bit
+const DR_OE: u32 = 1 << 11;
+/// Data Register parity error bit
+const DR_PE: u32 = 1 << 9;
+/// Flag Register BUSY bit number - indicates UART is transmitting
+const FR_BUSY_BIT: u32 = 3;
+/// Flag Register RXFE bit - indicates the receive FIFO is empty
+const FR_RXFE: u32 = 1 << 4;
+/// Flag Register TXFF bit - indicates the transmit FIFO is full
+const FR_TXFF: u32 = 1 << 5;
 /// Integer Baud Rate Divisor Register offset
 const IBRD_OFF: usize = 0x24;
 /// Fractional Baud Rate Divisor Register offset
@@ -47,12 +115,137 @@ const LCR_STP2: u32 = 1 << 3;
 const CR_OFF: usize = 0x30;
 /// Control Register UART Enable bit
 const CR_UARTEN: u32 = 1 << 0;
+/// Control Register Loopback Enable bit
+const CR_LBE: u32 = 1 << 7;
 /// Control Register Transmit Enable bit
 const CR_TXEN: u32 = 1 << 8;
+/// Control Register Receive Enable bit
+const CR_RXE: u32 = 1 << 9;
 /// Interrupt Mask Set/Clear Register offset
 const IMSC_OFF: usize = 0x38;
+/// Interrupt Clear Register offset
+const ICR_OFF: usize = 0x44;
+/// ICR: every interrupt of the PL011
+const ICR_ALL: u32 = 0x7ff;
 /// DMA Control Register offset
 const DMACR_OFF: usize = 0x48;
+/// Peripheral and PrimeCell identification registers offset
+pub const ID_OFF: usize = 0xfe0;
+/// Identification register values of a PL011 (PeriphID0-3, PCellID0-3),
+/// with the revision and configuration bits masked out
+pub const PL011_ID: [u32; 8] = [0x11, 0x10, 0x04, 0x00, 0x0d, 0xf0, 0x05, 0xb1];
+/// Bits of each identification register that identify the part
+const PL011_ID_MASK: [u32; 8] = [0xff, 0xff, 0x0f, 0x00, 0xff, 0xff, 0xff, 0xff];
+
+/// Configuration registers shown by `dump_registers`, with their labels
+const DUMP_REGISTERS: [(&[u8], usize); 7] = [
+    (b"FR   ", FR_OFF),
+    (b"IBRD ", IBRD_OFF),
+    (b"FBRD ", FBRD_OFF),
+    (b"LCR  ", LCR_OFF),
+    (b"CR   ", CR_OFF),
+    (b"IMSC ", IMSC_OFF),
+    (b"DMACR", DMACR_OFF),
+];
+
+/// Size of the buffer holding output printed before the UART is configured
+const EARLY_BUF_SIZE: usize = 1024;
+
+/// Ring buffer of output printed before the UART is configured
+struct EarlyBuffer {
+    data: [u8; EARLY_BUF_SIZE],
+    /// Index of the oldest byte
+    start: usize,
+    /// Number of bytes held
+    len: usize,
+    /// Number of bytes dropped because the buffer was full
+    lost: usize,
+}
+
+impl EarlyBuffer {
+    /// An empty buffer
+    const EMPTY: EarlyBuffer = EarlyBuffer {
+        data: [0; EARLY_BUF_SIZE],
+        start: 0,
+        len: 0,
+        lost: 0,
+    };
+
+    /// Appends `c`, dropping the oldest byte if full
+    fn push(&mut self, c: u8) {
+        if self.len == EARLY_BUF_SIZE {
+            self.start = (self.start + 1) % EARLY_BUF_SIZE;
+            self.len -= 1;
+            self.lost += 1;
+        }
+        self.data[(self.start + self.len) % EARLY_BUF_SIZE] = c;
+        self.len += 1;
+    }
+
+    /// Removes and returns the oldest byte, if there is one
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            self.start = 0;
+            return None;
+        }
+        let c = self.data[self.start];
+        self.start = (self.start + 1) % EARLY_BUF_SIZE;
+        self.len -= 1;
+        return Some(c);
+    }
+
+    /// Returns the number of bytes dropped since the last call
+    fn take_lost(&mut self) -> usize {
+        let lost = self.lost;
+
+        self.lost = 0;
+        return lost;
+    }
+}
+
+/// Output waiting for the UART to be configured
+static mut EARLY: EarlyBuffer = EarlyBuffer::EMPTY;
+
+/// Whether the UART is configured and output goes straight to it
+static mut READY: bool = false;
+/// Whether the UART is configured with its receiver enabled
+static mut INPUT: bool = false;
+
+/// Lifecycle of the console UART
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Whether a break was received by [`try_getchar`] and not reported yet
+static mut BREAK_PENDING: bool = false;
+
+/// Character typed to abort a long operation (`Ctrl-C`)
+const CTRL_C: u8 = 0x03;
+
+/// Whether printed lines start with the number of the printing core
+static mut CORE_PREFIX: bool = false;
+/// Tag printed at the start of each line, if any
+static mut ID_PREFIX: Option<u16> = None;
+/// Whether the next printed character starts a line
+static mut AT_LINE_START: bool = true;
+
+/// Mirror of printed output
+///
+/// Called with every byte printed to the console, without the core prefix.
+pub type Mirror = fn(u8);
+
+/// Mirror set with [`set_mirror`]
+static mut MIRROR: Option<Mirror> = None;
+
+/// Maximum number of sinks
+pub const MAX_SINKS: usize = 4;
+
+/// Output sink
+///
+/// Called with every byte printed to the console, without the line
+/// prefixes, for as long as the bootloader runs.
+pub type Sink = fn(u8);
+
+/// Sinks added with [`add_sink`], in the order they were added
+static mut SINKS: [Option<Sink>; MAX_SINKS] = [None; MAX_SINKS];
 
 /// Global UART device instance
 static mut UART: UartPl011 = UartPl011 {
@@ -61,23 +254,124 @@ static mut UART: UartPl011 = UartPl011 {
     baudrate: 0,
     data_bits: 0,
     stop_bits: 0,
+    input: false,
 };
 
 /// Initializes the global UART device with the given parameters
 ///
 /// This function must be called before any UART operations. It sets up the
-/// UART configuration with 8 data bits and 1 stop bit by default.
+/// UART configuration with 8 data bits and 1 stop bit by default, receiver
+/// enabled (see [`set_input`]). Fails, changing nothing, if the UART was
+/// already probed and not [torn down](teardown) since.
 #[unsafe(no_mangle)]
-pub fn init_uart(base_addr: *mut u32, base_clock: u32, baudrate: u32) {
+pub fn init_uart(base_addr: *mut u32, base_clock: u32, baudrate: u32) -> Result<(), UartError> {
+    let lifecycle = &raw mut LIFECYCLE;
+
     unsafe {
+        (*lifecycle).probe().map_err(UartError::WrongState)?;
+        READY = false;
+        INPUT = false;
         UART = UartPl011 {
             base_addr: base_addr,
             base_clock: base_clock,
             baudrate: baudrate,
             data_bits: 8,
             stop_bits: 1,
+            input: true,
         };
     }
+    return Ok(());
+}
+
+/// Sets whether [`configure_uart`] enables the receiver
+///
+/// Setups where nothing can ever be typed leave it off, so reads fail at
+/// once instead of waiting forever.
+pub fn set_input(enabled: bool) {
+    unsafe {
+        UART.input = enabled;
+    }
+}
+
+/// Returns the frequency of the reference clock of the PL011 `node`
+///
+/// Follows the first entry of `clocks` (the UART clock) to a fixed-rate
+/// clock node, falling back to a `clock-frequency` property on the UART.
+pub fn node_clock(fdt: &Fdt, node: &Node) -> Option<u32> {
+    if let Some(phandle) = node.property(b"clocks").and_then(|v| fdt::be32(v, 0))
+        && let Ok(Some(clock)) = fdt.find_phandle(phandle)
+        && let Some(freq) = clock.property_u32(b"clock-frequency")
+    {
+        return Some(freq);
+    }
+    return node.property_u32(b"clock-frequency");
+}
+
+/// Returns the frequency of the reference clock of the PL011 at `base`, as
+/// given by its DTB node
+///
+/// Returns `fallback` if the DTB has no PL011 at `base`, or doesn't give
+/// its clock.
+pub fn fdt_clock(fdt: &Fdt, base: usize, fallback: u32) -> u32 {
+    let node = fdt.find_node(|node| {
+        node.is_compatible(b"arm,pl011") && node.reg(0).is_some_and(|(reg, _)| reg as usize == base)
+    });
+
+    return match node {
+        Ok(Some(node)) => node_clock(fdt, &node).unwrap_or(fallback),
+        _ => fallback,
+    };
+}
+
+/// Checks whether a PL011 answers at `base`
+///
+/// Reads its identification registers with [`mmio::probe_read32`], so an
+/// address nothing decodes is reported as `false` rather than faulting.
+pub fn is_pl011_at(base: usize) -> bool {
+    for (i, (&id, &mask)) in PL011_ID.iter().zip(PL011_ID_MASK.iter()).enumerate() {
+        match mmio::probe_read32(base, ID_OFF + i * 4) {
+            Ok(value) if value & mask == id => {}
+            _ => return false,
+        }
+    }
+    return true;
+}
+
+/// Returns the base address of the global UART device
+///
+/// Returns 0 if `init_uart` has not been called yet.
+pub fn base_addr() -> usize {
+    unsafe {
+        return UART.base_addr as usize;
+    }
+}
+
+/// Points the global UART device at the PL011 at `base`, and returns the
+/// previous base address
+///
+/// The PL011 at `base` must already be configured, e.g., with
+/// [`init_tx_only`]: output goes on there without reprogramming anything.
+pub fn retarget(base: usize) -> usize {
+    unsafe {
+        let previous = UART.base_addr as usize;
+        UART.base_addr = base as *mut u32;
+        return previous;
+    }
+}
+
+/// Returns the baud rate the global UART device was configured with
+pub fn baudrate() -> u32 {
+    unsafe {
+        return UART.baudrate;
+    }
+}
+
+/// Returns the reference clock the baud rate of the global UART device is
+/// derived from, in Hz
+pub fn base_clock() -> u32 {
+    unsafe {
+        return UART.base_clock;
+    }
 }
 
 /// Configures the UART device according to the initialized parameters
@@ -90,77 +384,311 @@ pub fn init_uart(base_addr: *mut u32, base_clock: u32, baudrate: u32) {
 /// 5. Configures the data frame format (data bits, stop bits)
 /// 6. Masks all interrupts
 /// 7. Disables DMA
-/// 8. Enables transmission
+/// 8. Enables transmission and reception
 /// 9. Re-enables the UART
+///
+/// Output buffered until then is written out afterwards. Fails, leaving the
+/// output buffered, if [`init_uart`] hasn't provided a base address or the
+/// baud rate can't be derived from the base clock, and, changing nothing, if
+/// the UART is already configured or quiesced.
 #[unsafe(no_mangle)]
-pub fn configure_uart() {
+pub fn configure_uart() -> Result<(), UartError> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    if base_addr() == 0 {
+        return Err(UartError::NotInitialized);
+    }
+    if baud_divisor().is_none() {
+        return Err(UartError::BadBaudrate);
+    }
+    unsafe { (*lifecycle).activate() }.map_err(UartError::WrongState)?;
+    configure_uart_on(&mut PhysBus);
+    unsafe {
+        READY = true;
+        INPUT = mmio::read_mmio32(base_addr(), CR_OFF) & CR_RXE != 0;
+    }
+    flush_early();
+    return Ok(());
+}
+
+/// Stops the console UART for the payload
+///
+/// Once the transmitter is idle, masks and clears its interrupts and turns
+/// its DMA requests off, leaving it enabled so the payload's early console
+/// can keep using it. Nothing is programmed if it was only probed. Fails if
+/// it isn't probed or is already quiesced.
+pub fn quiesce() -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    if unsafe { (*lifecycle).quiesce() }? == State::Active {
+        let base = base_addr();
+        while !uart_ready() {}
+        unsafe {
+            mmio::write_mmio32(base, IMSC_OFF, 0);
+            mmio::write_mmio32(base, ICR_OFF, ICR_ALL);
+            mmio::write_mmio32(base, DMACR_OFF, 0);
+        }
+    }
+    return Ok(());
+}
+
+/// Forgets the console UART, so [`init_uart`] can set it up again
+///
+/// Output is buffered again until the next [`configure_uart`].
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        READY = false;
+        INPUT = false;
+    }
+}
+
+/// Returns whether the UART is configured with its receiver enabled, so
+/// input can be read
+pub fn has_input() -> bool {
+    unsafe {
+        return INPUT;
+    }
+}
+
+/// Returns whether the next printed character starts a console line
+pub fn at_line_start() -> bool {
+    unsafe {
+        return AT_LINE_START;
+    }
+}
+
+/// Returns whether the UART is configured, so output isn't buffered anymore
+pub fn is_ready() -> bool {
+    unsafe {
+        return READY;
+    }
+}
+
+/// Appends `c` to the early output buffer, dropping the oldest byte if full
+fn buffer_early(c: u8) {
+    let early = &raw mut EARLY;
+
+    unsafe { (*early).push(c) };
+}
+
+/// Writes out and empties the early output buffer
+fn flush_early() {
+    let early = &raw mut EARLY;
+    let early = unsafe { &mut *early };
+    let lost = early.take_lost();
+    let mut buf = [0u8; 20];
+
+    if lost != 0 {
+        print(b"[");
+        print(u64_to_dec(lost as u64, &mut buf));
+        println(b" bytes of early output lost]");
+    }
+    while let Some(c) = early.pop() {
+        putchar(c);
+    }
+}
+
+/// Early output of [`selftest_early_replay`], printed in two pieces
+const TEST_EARLY: [&[u8]; 2] = [b"first message\n", b"second message\n"];
+
+/// Self-test: output kept in an early buffer comes back in the order it
+/// was printed, and a buffer that overflowed keeps the newest bytes, in
+/// order, and counts the dropped ones
+pub fn selftest_early_replay() -> Outcome {
+    let mut early = EarlyBuffer::EMPTY;
+    let mut replayed = [0u8; 64];
+    let mut len = 0;
+
+    for c in TEST_EARLY.iter().flat_map(|piece| piece.iter()) {
+        early.push(*c);
+    }
+    while let Some(c) = early.pop() {
+        replayed[len] = c;
+        len += 1;
+    }
+    let expected = TEST_EARLY.iter().flat_map(|piece| piece.iter());
+    if !replayed[..len].iter().eq(expected) || early.take_lost() != 0 {
+        return Outcome::Fail;
+    }
+
+    // Byte i is i % 251, so a byte out of place shows
+    let total = EARLY_BUF_SIZE + 5;
+    for i in 0..total {
+        early.push((i % 251) as u8);
+    }
+    if early.take_lost() != total - EARLY_BUF_SIZE || early.take_lost() != 0 {
+        return Outcome::Fail;
+    }
+    for i in total - EARLY_BUF_SIZE..total {
+        if early.pop() != Some((i % 251) as u8) {
+            return Outcome::Fail;
+        }
+    }
+    if early.pop().is_some() {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Performs the sequence of [`configure_uart`] through `bus`
+pub fn configure_uart_on<B: MmioBus>(bus: &mut B) {
+    let uart = &raw const UART;
+
+    configure_device_on(bus, unsafe { &*uart });
+}
+
+/// Performs the sequence of [`configure_uart`] for `uart` through `bus`
+fn configure_device_on<B: MmioBus>(bus: &mut B, uart: &UartPl011) {
     let mut cfg: u32;
+    let base = uart.base_addr as usize;
+    let baud_div = divisor(uart.base_clock, uart.baudrate).unwrap_or(0);
     // 1. Disable the UART
     unsafe {
-        mmio::write_mmio32(UART.base_addr as usize, CR_OFF, CR_UARTEN);
+        let cr = bus.read32(base + CR_OFF);
+        bus.write32(base + CR_OFF, cr & !CR_UARTEN);
     }
     // 2. Wait for the end of TX
-    loop {
-        if uart_ready() {
-            break;
-        }
-    }
+    while unsafe { bus.test_bit32(base + FR_OFF, FR_BUSY_BIT) } {}
     // 3. Flush TX FIFO
     unsafe {
-        mmio::write_mmio32(UART.base_addr as usize, LCR_OFF, !LCR_FEN);
+        let lcr = bus.read32(base + LCR_OFF);
+        bus.write32(base + LCR_OFF, lcr & !LCR_FEN);
     }
     // 4. Set speed
-    uart_set_speed();
+    uart_set_speed_on(bus, base, baud_div);
     // 5. Configure the data frame format
     // 5.1 Word length: bits 5 and 6
-    cfg = 0;
+    cfg = ((uart.data_bits as u32 - 1) & 0x3) << 5;
+    // 5.2 Use 1 or 2 stop bits: bit LCR_STP2
+    if uart.stop_bits == 2 {
+        cfg |= LCR_STP2;
+    }
     unsafe {
-        cfg |= ((UART.data_bits as u32 - 1) & 0x3) << 5;
-        // 5.2 Use 1 or 2 stop bits: bit LCR_STP2
-        if UART.stop_bits == 2 {
-            cfg |= LCR_STP2;
-        }
-        mmio::write_mmio32(UART.base_addr as usize, LCR_OFF, cfg);
+        bus.write32(base + LCR_OFF, cfg);
     }
     // 6. Mask all interrupts
     unsafe {
-        mmio::write_mmio32(UART.base_addr as usize, IMSC_OFF, 0x0);
-    // 7. Disable DMA
-        mmio::write_mmio32(UART.base_addr as usize, DMACR_OFF, 0x0);
-    // 8. Enable TX and UART
-        mmio::write_mmio32(UART.base_addr as usize, CR_OFF, CR_TXEN | CR_UARTEN);
+        bus.write32(base + IMSC_OFF, 0x0);
+        // 7. Disable DMA
+        bus.write32(base + DMACR_OFF, 0x0);
+        // 8. Enable TX, RX if asked for, and UART
+        let rx = if uart.input { CR_RXE } else { 0 };
+        bus.write32(base + CR_OFF, CR_TXEN | rx | CR_UARTEN);
     }
 }
 
 /// Checks if the UART is ready for transmission
 #[inline(always)]
 fn uart_ready() -> bool {
+    return uart_ready_on(&mut PhysBus);
+}
+
+/// Checks through `bus` if the UART is ready for transmission
+#[inline(always)]
+fn uart_ready_on<B: MmioBus>(bus: &mut B) -> bool {
     unsafe {
-        return (mmio::read_mmio32(UART.base_addr as usize, FR_OFF) & FR_BUSY) == 0;
+        return !bus.test_bit32(UART.base_addr as usize + FR_OFF, FR_BUSY_BIT);
     }
 }
 
-/// Configures the UART baud rate based on the base clock and desired baudrate
+/// Configures the baud rate of the UART at `base` from its divisor
 ///
 /// The baud rate divisor is calculated as: `(4 * base_clock) / baudrate`
-/// The integer part is written to IBRD and the fractional part to FBRD.
-fn uart_set_speed() {
-    let baud_div;
+/// (see [`divisor`]). The integer part is written to IBRD and the
+/// fractional part to FBRD.
+fn uart_set_speed_on<B: MmioBus>(bus: &mut B, base: usize, baud_div: u32) {
+    unsafe {
+        bus.write32(base + IBRD_OFF, (baud_div >> 6) & 0xffff);
+        bus.write32(base + FBRD_OFF, baud_div & 0x3f);
+    }
+}
+
+/// Changes the baud rate of the global UART device to `rate`
+///
+/// The new rate is stored, and if the UART is configured, it's programmed
+/// without a full reconfiguration: once the transmitter is idle, the UART is
+/// disabled, the TX FIFO flushed, the divisor written and the UART enabled
+/// again, keeping the rest of its settings. Output printed before the call
+/// goes out at the old rate. Fails, changing nothing, if the rate can't be
+/// derived from the base clock.
+pub fn set_baud(rate: u32) -> Result<(), UartError> {
+    let Some(baud_div) = divisor(base_clock(), rate) else {
+        return Err(UartError::BadBaudrate);
+    };
+
+    unsafe {
+        UART.baudrate = rate;
+    }
+    if is_ready() {
+        reprogram_baud_on(&mut PhysBus, base_addr(), baud_div);
+    }
+    return Ok(());
+}
+
+/// Writes the divisor `baud_div` to the enabled PL011 at `base` through
+/// `bus`, in the sequence of [`set_baud`]
+fn reprogram_baud_on<B: MmioBus>(bus: &mut B, base: usize, baud_div: u32) {
+    unsafe {
+        // 1. Wait for the end of TX
+        while bus.test_bit32(base + FR_OFF, FR_BUSY_BIT) {}
+        // 2. Disable the UART
+        let cr = bus.read32(base + CR_OFF);
+        bus.write32(base + CR_OFF, cr & !CR_UARTEN);
+        // 3. Flush the TX FIFO by turning the FIFOs off
+        let lcr = bus.read32(base + LCR_OFF);
+        bus.write32(base + LCR_OFF, lcr & !LCR_FEN);
+        // 4. Set the speed; it only takes effect with the next LCR write
+        bus.write32(base + IBRD_OFF, (baud_div >> 6) & 0xffff);
+        bus.write32(base + FBRD_OFF, baud_div & 0x3f);
+        // 5. Restore the frame format and FIFOs, then re-enable the UART
+        bus.write32(base + LCR_OFF, lcr);
+        bus.write32(base + CR_OFF, cr);
+    }
+}
 
+/// Returns the divisor of the global UART device, see [`divisor`]
+fn baud_divisor() -> Option<u32> {
     unsafe {
-        baud_div = 4 * UART.base_clock / UART.baudrate;
-        mmio::write_mmio32(UART.base_addr as usize, IBRD_OFF, (baud_div >> 6) & 0xffff);
-        mmio::write_mmio32(UART.base_addr as usize, FBRD_OFF, baud_div & 0x3f);
+        return divisor(UART.base_clock, UART.baudrate);
     }
 }
 
+/// Returns the divisor giving `baudrate` from a `base_clock` reference
+/// clock, in 1/64 units, if the IBRD can hold it
+///
+/// The integer part must be between 1 and 0xffff.
+pub const fn divisor(base_clock: u32, baudrate: u32) -> Option<u32> {
+    if baudrate == 0 {
+        return None;
+    }
+    let baud_div = 4 * base_clock as u64 / baudrate as u64;
+    if baud_div >> 6 < 1 || baud_div >> 6 > 0xffff {
+        return None;
+    }
+    return Some(baud_div as u32);
+}
+
+// 24 MHz at 115200: IBRD 13, FBRD 1
+const _: () = assert!(matches!(divisor(24_000_000, 115_200), Some(0x341)));
+const _: () = assert!(divisor(24_000_000, 0).is_none());
+const _: () = assert!(divisor(1_000, 115_200).is_none());
+
 /// Transmits a single character via UART
 ///
 /// Waits until the UART is ready before writing the character
-/// to the data register.
+/// to the data register. Until the UART is configured, the character is kept
+/// in the early output buffer instead (forever if the board has no known
+/// console).
 fn putchar(c: u8) {
     let addr;
 
+    if !is_ready() {
+        buffer_early(c);
+        return;
+    }
     loop {
         if uart_ready() {
             break;
@@ -172,15 +700,687 @@ fn putchar(c: u8) {
     }
 }
 
+/// Transmits a single character if there is room in the TX FIFO
+///
+/// Never waits: returns `true` if the character was written, or `false` if
+/// the transmit FIFO is full (or the UART isn't configured yet). Meant for
+/// output loops that must not block, such as draining a software buffer.
+pub fn try_putchar(c: u8) -> bool {
+    if !is_ready() {
+        return false;
+    }
+    return try_putchar_on(&mut PhysBus, base_addr(), c);
+}
+
+/// Transmits `c` through `bus` like [`try_putchar`], to the UART at `base`
+fn try_putchar_on<B: MmioBus>(bus: &mut B, base: usize, c: u8) -> bool {
+    unsafe {
+        if bus.read32(base + FR_OFF) & FR_TXFF != 0 {
+            return false;
+        }
+        bus.write32(base + DR_OFF, c as u32);
+    }
+    return true;
+}
+
+/// Self-test: [`try_putchar`], run on a [`MockBus`], writes nothing and
+/// reports it when FR says the TX FIFO is full, and writes the character
+/// to DR once there is room
+///
+/// [`MockBus`]: crate::utilities::mmio::mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_try_putchar() -> Outcome {
+    use crate::utilities::mmio::mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const BASE: usize = 0x1000;
+    let mut bus = MockBus::new();
+
+    // Full: FR is read and nothing is written
+    bus.set_read(BASE + FR_OFF, (FR_TXFF | FR_RXFE) as u64);
+    if try_putchar_on(&mut bus, BASE, b'x') || bus.writes().count() != 0 {
+        return Outcome::Fail;
+    }
+    let polled = bus.log().len() == 1 && bus.log()[0].addr == BASE + FR_OFF;
+
+    // Room again: the character goes to DR
+    bus.set_read(BASE + FR_OFF, FR_RXFE as u64);
+    bus.expect_write(BASE + DR_OFF, 4, b'x' as u64);
+    let sent = try_putchar_on(&mut bus, BASE, b'x');
+    let last = bus.log().last().map(|a| a.kind);
+
+    if polled && sent && bus.expectations_met() && last == Some(AccessKind::Write) {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Enables or disables the `[cpuN] ` prefix on each printed line
+///
+/// With several cores printing, the prefix tells which core wrote a line.
+pub fn set_core_prefix(enabled: bool) {
+    unsafe {
+        CORE_PREFIX = enabled;
+    }
+}
+
+/// Sets the `[xxxx] ` tag printed at the start of each line, or removes it
+///
+/// The tag is shown as 4 hex digits, e.g., the low 16 bits of the boot ID so
+/// that interleaved captures of several boards can be told apart.
+pub fn set_id_prefix(tag: Option<u16>) {
+    unsafe {
+        ID_PREFIX = tag;
+    }
+}
+
+/// Sets the mirror of printed output, or removes it with `None`
+///
+/// Returns the previous mirror, so it can be put back.
+pub fn set_mirror(mirror: Option<Mirror>) -> Option<Mirror> {
+    unsafe {
+        let previous = MIRROR;
+        MIRROR = mirror;
+        return previous;
+    }
+}
+
+/// Adds `sink` to the outputs of the console
+///
+/// Unlike the mirror, sinks stay for good. Returns `false` if
+/// [`MAX_SINKS`] sinks were already added.
+pub fn add_sink(sink: Sink) -> bool {
+    let sinks = &raw mut SINKS;
+
+    match unsafe { (*sinks).iter_mut().find(|slot| slot.is_none()) } {
+        Some(slot) => {
+            *slot = Some(sink);
+            return true;
+        }
+        None => return false,
+    }
+}
+
+/// Hands `c` to the capture, the mirror and the sinks, then transmits it
+fn emit(c: u8) {
+    capture::feed(c);
+    if let Some(mirror) = unsafe { MIRROR } {
+        mirror(c);
+    }
+    for sink in unsafe { SINKS }.into_iter().flatten() {
+        sink(c);
+    }
+    putchar(c);
+}
+
+/// Transmits `c`, preceded by the line prefixes if it starts a line
+fn put(c: u8) {
+    let mut buf = [0u8; 20];
+    let (at_start, tag) = unsafe { (AT_LINE_START, ID_PREFIX) };
+    let prefix = unsafe { CORE_PREFIX && at_start };
+
+    if let Some(tag) = tag
+        && at_start
+    {
+        let digits = u64_to_hex_padded(tag as u64, &mut buf[..4]);
+        b"["
+            .iter()
+            .chain(digits)
+            .chain(b"] ")
+            .for_each(|&p| putchar(p));
+    }
+    if prefix {
+        let id = u64_to_dec(cpu::core_id() as u64, &mut buf);
+        b"[cpu"
+            .iter()
+            .chain(id)
+            .chain(b"] ")
+            .for_each(|&p| putchar(p));
+    }
+    unsafe {
+        AT_LINE_START = c == b'\n';
+    }
+    emit(c);
+}
+
 /// Prints a byte slice to the UART
 pub fn print(s: &[u8]) {
     for &c in s {
-        putchar(c);
+        put(c);
     }
 }
 
 /// Prints a byte slice followed by a newline to the UART
 pub fn println(s: &[u8]) {
     print(s);
-    putchar(b'\n');
+    put(b'\n');
+}
+
+/// Transmits `bytes` as they are
+///
+/// Unlike [`print`], no line prefix is added and nothing reaches the
+/// capture, the mirror or the sinks, nor counts as the start of a line.
+/// Meant for binary protocols such as XMODEM.
+pub fn write_raw(bytes: &[u8]) {
+    for &c in bytes {
+        putchar(c);
+    }
+}
+
+/// Configures the PL011 at `base` for transmission only
+///
+/// Programs 8N1 at `baudrate` from a `base_clock` reference clock, with
+/// interrupts masked and the receiver off; the global UART is left alone.
+/// Returns `false`, doing nothing, if no PL011 answers at `base` or the
+/// baud rate is out of range for the clock.
+pub fn init_tx_only(base: usize, base_clock: u32, baudrate: u32) -> bool {
+    let Some(baud_div) = divisor(base_clock, baudrate) else {
+        return false;
+    };
+    if !is_pl011_at(base) {
+        return false;
+    }
+    unsafe {
+        mmio::write_mmio32(base, CR_OFF, 0);
+        mmio::write_mmio32(base, IBRD_OFF, baud_div >> 6);
+        mmio::write_mmio32(base, FBRD_OFF, baud_div & 0x3f);
+        // 8 data bits, 1 stop bit, FIFOs on
+        mmio::write_mmio32(base, LCR_OFF, 0x3 << 5 | LCR_FEN);
+        mmio::write_mmio32(base, IMSC_OFF, 0);
+        mmio::write_mmio32(base, DMACR_OFF, 0);
+        mmio::write_mmio32(base, CR_OFF, CR_TXEN | CR_UARTEN);
+    }
+    return true;
+}
+
+/// Prints `s` to the PL011 at `base`, set up with [`init_tx_only`]
+///
+/// Waits for room in the TX FIFO before each byte. Nothing goes through
+/// the core prefix, the capture or the mirror.
+pub fn print_at(base: usize, s: &[u8]) {
+    for &c in s {
+        unsafe {
+            while mmio::read_mmio32(base, FR_OFF) & FR_TXFF != 0 {}
+            mmio::write_mmio32(base, DR_OFF, c as u32);
+        }
+    }
+}
+
+/// Reads a received character, if one is available
+///
+/// Returns `None` if the receive FIFO is empty. Characters received with an
+/// error (framing, parity, break or overrun) are discarded; a break is
+/// remembered for [`break_received`] and [`take_break`], the other errors
+/// are warned about, rate-limited as they can come in floods.
+pub fn try_getchar() -> Option<u8> {
+    let data;
+
+    if base_addr() == 0 {
+        return None;
+    }
+    unsafe {
+        if mmio::read_mmio32(UART.base_addr as usize, FR_OFF) & FR_RXFE != 0 {
+            return None;
+        }
+        data = mmio::read_mmio32(UART.base_addr as usize, DR_OFF);
+    }
+    if data & DR_BE != 0 {
+        unsafe {
+            BREAK_PENDING = true;
+        }
+    } else if data & DR_ERRORS != 0 {
+        let kind: &[u8] = match data {
+            d if d & DR_OE != 0 => b"overrun",
+            d if d & DR_PE != 0 => b"parity",
+            _ => b"framing",
+        };
+        crate::log_warn_ratelimited!(b"uart-rx", b"UART receive error: ", kind);
+    }
+    if data & DR_ERRORS != 0 {
+        return None;
+    }
+    return Some(data as u8);
+}
+
+/// Waits for a character and returns it
+///
+/// Fails at once with [`UartError::NoInput`] if the receiver isn't enabled
+/// (see [`has_input`]), rather than waiting for input that can't come.
+pub fn getchar() -> Result<u8, UartError> {
+    if !has_input() {
+        return Err(UartError::NoInput);
+    }
+    loop {
+        if let Some(c) = try_getchar() {
+            return Ok(c);
+        }
+    }
+}
+
+/// Byte sent through the loopback by [`selftest`]
+const LOOPBACK_PATTERN: u8 = 0xa5;
+/// Time allowed for the looped back byte to arrive, in microseconds
+const LOOPBACK_TIMEOUT_US: u64 = 10_000;
+
+/// Self-test: sends a byte through the internal loopback
+///
+/// Output is paused meanwhile: the transmitter is drained, loopback is
+/// enabled, the receive FIFO is emptied, and [`LOOPBACK_PATTERN`] must be
+/// received back. Skipped if the UART isn't configured or has no input.
+pub fn selftest() -> Outcome {
+    let base = base_addr();
+
+    if !is_ready() || !has_input() {
+        return Outcome::Skipped;
+    }
+    unsafe {
+        while mmio::test_bit32(base, FR_OFF, FR_BUSY_BIT) {
+            core::hint::spin_loop();
+        }
+        let cr = mmio::read_mmio32(base, CR_OFF);
+        mmio::write_mmio32(base, CR_OFF, cr | CR_LBE);
+        while mmio::read_mmio32(base, FR_OFF) & FR_RXFE == 0 {
+            mmio::read_mmio32(base, DR_OFF);
+        }
+
+        mmio::write_mmio32(base, DR_OFF, LOOPBACK_PATTERN as u32);
+        let deadline = generic::counter().saturating_add(generic::us_to_ticks(
+            LOOPBACK_TIMEOUT_US,
+            generic::frequency(),
+        ));
+        let mut received = None;
+        while received.is_none() && generic::counter() < deadline {
+            if mmio::read_mmio32(base, FR_OFF) & FR_RXFE == 0 {
+                received = Some(mmio::read_mmio32(base, DR_OFF));
+            }
+        }
+        while mmio::test_bit32(base, FR_OFF, FR_BUSY_BIT) {
+            core::hint::spin_loop();
+        }
+        mmio::write_mmio32(base, CR_OFF, cr);
+
+        return match received {
+            Some(data) if data & DR_ERRORS == 0 && data as u8 == LOOPBACK_PATTERN => Outcome::Pass,
+            _ => Outcome::Fail,
+        };
+    }
+}
+
+/// Self-test: [`has_input`] follows the receiver enable bit, and
+/// [`getchar`] fails at once on a UART without input instead of waiting
+///
+/// The UART is made to look input-less for the duration of the read only.
+/// Skipped if the UART isn't configured.
+pub fn selftest_no_input() -> Outcome {
+    if !is_ready() {
+        return Outcome::Skipped;
+    }
+    let enabled = unsafe { mmio::read_mmio32(base_addr(), CR_OFF) } & CR_RXE != 0;
+    if has_input() != enabled {
+        return Outcome::Fail;
+    }
+
+    let input = unsafe { INPUT };
+    unsafe {
+        INPUT = false;
+    }
+    let result = getchar();
+    unsafe {
+        INPUT = input;
+    }
+    if result != Err(UartError::NoInput) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Self-test: [`set_baud`]'s sequence, run on a [`MockBus`], waits for the
+/// transmitter before any write, then disables the UART, flushes the TX
+/// FIFO, writes the new divisor and enables the UART again, in that order
+///
+/// [`MockBus`]: crate::utilities::mmio::mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_set_baud() -> Outcome {
+    use crate::utilities::mmio::mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const BASE: usize = 0x1000;
+    // 48 MHz at 921600: IBRD 3, FBRD 16
+    const DIVISOR: u32 = 3 << 6 | 16;
+    let cr = CR_TXEN | CR_RXE | CR_UARTEN;
+    let lcr = 0x3 << 5 | LCR_FEN;
+    let mut bus = MockBus::new();
+
+    if divisor(48_000_000, 921_600) != Some(DIVISOR) {
+        return Outcome::Fail;
+    }
+    bus.set_read(BASE + CR_OFF, cr as u64);
+    bus.set_read(BASE + LCR_OFF, lcr as u64);
+    bus.expect_write(BASE + CR_OFF, 4, (cr & !CR_UARTEN) as u64);
+    bus.expect_write(BASE + LCR_OFF, 4, (lcr & !LCR_FEN) as u64);
+    bus.expect_write(BASE + IBRD_OFF, 4, 3);
+    bus.expect_write(BASE + FBRD_OFF, 4, 16);
+    bus.expect_write(BASE + LCR_OFF, 4, lcr as u64);
+    bus.expect_write(BASE + CR_OFF, 4, cr as u64);
+    reprogram_baud_on(&mut bus, BASE, DIVISOR);
+
+    let first = bus.log()[0];
+    if first.kind != AccessKind::Read || first.addr != BASE + FR_OFF {
+        return Outcome::Fail;
+    }
+    if !bus.expectations_met() {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Self-test: [`configure_uart`]'s sequence, run on a [`MockBus`] for a UART
+/// the firmware left enabled, clears UARTEN and FEN by read-modify-write,
+/// waits for the transmitter, then programs the divisor, the frame format,
+/// the interrupt mask and DMA before enabling the UART again, in that order
+///
+/// [`MockBus`]: crate::utilities::mmio::mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_configure() -> Outcome {
+    use crate::utilities::mmio::mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const BASE: usize = 0x1000;
+    let uart = UartPl011 {
+        base_addr: BASE as *mut u32,
+        base_clock: 48_000_000,
+        baudrate: 921_600,
+        data_bits: 8,
+        stop_bits: 2,
+        input: true,
+    };
+    let cr = CR_TXEN | CR_RXE | CR_UARTEN;
+    let lcr = 0x3 << 5 | LCR_FEN;
+    // Reads and writes, in order; 48 MHz at 921600 is IBRD 3, FBRD 16
+    let expected = [
+        (AccessKind::Read, CR_OFF, cr),
+        (AccessKind::Write, CR_OFF, cr & !CR_UARTEN),
+        (AccessKind::Read, FR_OFF, 0),
+        (AccessKind::Read, LCR_OFF, lcr),
+        (AccessKind::Write, LCR_OFF, lcr & !LCR_FEN),
+        (AccessKind::Write, IBRD_OFF, 3),
+        (AccessKind::Write, FBRD_OFF, 16),
+        (AccessKind::Write, LCR_OFF, 0x3 << 5 | LCR_STP2),
+        (AccessKind::Write, IMSC_OFF, 0),
+        (AccessKind::Write, DMACR_OFF, 0),
+        (AccessKind::Write, CR_OFF, cr),
+    ];
+    let mut bus = MockBus::new();
+
+    bus.set_read(BASE + CR_OFF, cr as u64);
+    bus.set_read(BASE + LCR_OFF, lcr as u64);
+    configure_device_on(&mut bus, &uart);
+
+    let log = bus.log().iter().map(|a| (a.kind, a.addr, a.width, a.value));
+    let expected = expected
+        .iter()
+        .map(|&(kind, off, value)| (kind, BASE + off, 4, value as u64));
+    if !log.eq(expected) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Checks whether `Ctrl-C` was typed
+///
+/// Drains the receive FIFO, returning `true` if any of the pending
+/// characters was `Ctrl-C`. Other characters are dropped, but a break among
+/// them is still reported by [`break_received`]. Meant to be polled by long
+/// operations that can be aborted, between two steps.
+pub fn ctrlc() -> bool {
+    let mut found = false;
+
+    if base_addr() == 0 {
+        return false;
+    }
+    while unsafe { mmio::read_mmio32(base_addr(), FR_OFF) } & FR_RXFE == 0 {
+        found |= try_getchar() == Some(CTRL_C);
+    }
+    return found;
+}
+
+/// Takes the break remembered by [`try_getchar`], if any
+///
+/// Unlike [`break_received`], leaves the receive FIFO alone, so characters
+/// typed after the break are still read.
+pub fn take_break() -> bool {
+    unsafe {
+        let found = BREAK_PENDING;
+        BREAK_PENDING = false;
+        return found;
+    }
+}
+
+/// Checks whether a break condition was received
+///
+/// Drains the receive FIFO, returning `true` if any of the pending
+/// characters was flagged with a break error, or [`try_getchar`] received
+/// one earlier.
+pub fn break_received() -> bool {
+    let mut found;
+
+    if base_addr() == 0 {
+        return false;
+    }
+    unsafe {
+        found = BREAK_PENDING;
+        BREAK_PENDING = false;
+        while mmio::read_mmio32(UART.base_addr as usize, FR_OFF) & FR_RXFE == 0 {
+            if mmio::read_mmio32(UART.base_addr as usize, DR_OFF) & DR_BE != 0 {
+                found = true;
+            }
+        }
+    }
+    return found;
+}
+
+/// Prints the configuration registers of the UART
+///
+/// Shows FR, IBRD, FBRD, LCR, CR, IMSC and DMACR, each read with a 32-bit
+/// MMIO access, to diagnose the driver's own configuration.
+pub fn dump_registers() {
+    if base_addr() == 0 {
+        return;
+    }
+    write_registers_on(&mut PhysBus, base_addr(), &mut Uart);
+}
+
+/// Writes the configuration registers of the UART at `base` on `bus` to
+/// `out`, as [`dump_registers`] prints them
+pub fn write_registers_on<B: MmioBus>(bus: &mut B, base: usize, out: &mut dyn Console) {
+    let mut buf = [0u8; 16];
+
+    out.writeln(b"PL011 registers:");
+    for (label, off) in DUMP_REGISTERS {
+        let value = unsafe { bus.read32(base + off) };
+
+        out.write(label);
+        out.write(b": 0x");
+        out.writeln(u64_to_hex(value as u64, &mut buf));
+    }
+}
+
+/// Output of [`write_registers_on`] for the registers of
+/// [`selftest_dump_registers`]
+#[cfg(feature = "mock")]
+const TEST_DUMP: &[u8] = b"PL011 registers:
+FR   : 0x90
+IBRD : 0x1a
+FBRD : 0x3
+LCR  : 0x70
+CR   : 0x301
+IMSC : 0x0
+DMACR: 0x0
+";
+
+/// Self-test: [`write_registers_on`], run on a [`MockBus`], reads every
+/// configuration register once, in order, and labels each value
+///
+/// [`MockBus`]: crate::utilities::mmio::mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_dump_registers() -> Outcome {
+    use crate::utilities::mmio::mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const BASE: usize = 0x1000;
+    let mut bus = MockBus::new();
+    let mut buf = [0u8; 160];
+    let mut sink = CaptureSink::new(&mut buf);
+
+    // Both FIFOs empty (TXFE is bit 7); 24 MHz at 115200, 8N1 with FIFOs,
+    // TX and RX enabled
+    bus.set_read(BASE + FR_OFF, (1 << 7 | FR_RXFE) as u64);
+    bus.set_read(BASE + IBRD_OFF, 26);
+    bus.set_read(BASE + FBRD_OFF, 3);
+    bus.set_read(BASE + LCR_OFF, (0x3 << 5 | LCR_FEN) as u64);
+    bus.set_read(BASE + CR_OFF, (CR_TXEN | CR_RXE | CR_UARTEN) as u64);
+    write_registers_on(&mut bus, BASE, &mut sink);
+
+    let reads = bus.log().iter().map(|a| (a.kind, a.addr, a.width));
+    let expected = DUMP_REGISTERS
+        .iter()
+        .map(|&(_, off)| (AccessKind::Read, BASE + off, 4));
+    if !reads.eq(expected) || sink.overflowed() || sink.bytes() != TEST_DUMP {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::mmio::mock::{AccessKind, MockBus};
+    use std::vec::Vec;
+
+    /// Base address of the mocked UART, never accessed
+    const BASE: usize = 0x1000;
+    /// Divisor of a 48 MHz clock at 921600 baud: IBRD 3, FBRD 16
+    const DIVISOR: u32 = 3 << 6 | 16;
+
+    /// A UART with a 48 MHz clock at 921600 baud and 8 data bits
+    fn uart(stop_bits: u8, input: bool) -> UartPl011 {
+        return UartPl011 {
+            base_addr: BASE as *mut u32,
+            base_clock: 48_000_000,
+            baudrate: 921_600,
+            data_bits: 8,
+            stop_bits,
+            input,
+        };
+    }
+
+    /// Returns the accesses of `bus` as (kind, offset, value), checking that
+    /// they are all 32 bits wide
+    fn accesses(bus: &MockBus) -> Vec<(AccessKind, usize, u32)> {
+        return bus
+            .log()
+            .iter()
+            .inspect(|a| assert_eq!(a.width, 4, "access to {:#x}", a.addr))
+            .map(|a| (a.kind, a.addr - BASE, a.value as u32))
+            .collect();
+    }
+
+    /// Returns the writes of `bus` as (offset, value)
+    fn writes(bus: &MockBus) -> Vec<(usize, u32)> {
+        return accesses(bus)
+            .into_iter()
+            .filter(|&(kind, _, _)| kind == AccessKind::Write)
+            .map(|(_, off, value)| (off, value))
+            .collect();
+    }
+
+    #[test]
+    fn divisor_of_test_clock() {
+        assert_eq!(divisor(48_000_000, 921_600), Some(DIVISOR));
+    }
+
+    #[test]
+    fn configure_from_reset() {
+        // Every register reads as its reset value of 0
+        let mut bus = MockBus::new();
+
+        configure_device_on(&mut bus, &uart(1, false));
+        assert_eq!(
+            writes(&bus),
+            [
+                (CR_OFF, 0),
+                (LCR_OFF, 0),
+                (IBRD_OFF, 3),
+                (FBRD_OFF, 16),
+                (LCR_OFF, 0x3 << 5),
+                (IMSC_OFF, 0),
+                (DMACR_OFF, 0),
+                (CR_OFF, CR_TXEN | CR_UARTEN),
+            ]
+        );
+    }
+
+    #[test]
+    fn configure_enabled_by_firmware() {
+        let cr = CR_TXEN | CR_RXE | CR_UARTEN;
+        let lcr = 0x3 << 5 | LCR_FEN;
+        let mut bus = MockBus::new();
+
+        bus.set_read(BASE + CR_OFF, cr as u64);
+        bus.set_read(BASE + LCR_OFF, lcr as u64);
+        configure_device_on(&mut bus, &uart(2, true));
+        assert_eq!(
+            accesses(&bus),
+            [
+                (AccessKind::Read, CR_OFF, cr),
+                (AccessKind::Write, CR_OFF, cr & !CR_UARTEN),
+                (AccessKind::Read, FR_OFF, 0),
+                (AccessKind::Read, LCR_OFF, lcr),
+                (AccessKind::Write, LCR_OFF, lcr & !LCR_FEN),
+                (AccessKind::Write, IBRD_OFF, 3),
+                (AccessKind::Write, FBRD_OFF, 16),
+                (AccessKind::Write, LCR_OFF, 0x3 << 5 | LCR_STP2),
+                (AccessKind::Write, IMSC_OFF, 0),
+                (AccessKind::Write, DMACR_OFF, 0),
+                (AccessKind::Write, CR_OFF, cr),
+            ]
+        );
+    }
+
+    #[test]
+    fn configure_only_clears_uarten_and_fen() {
+        // Loopback and 7 data bits with FIFOs, left behind by the firmware
+        let cr = CR_LBE | CR_TXEN | CR_UARTEN;
+        let lcr = 0x2 << 5 | LCR_FEN;
+        let mut bus = MockBus::new();
+
+        bus.set_read(BASE + CR_OFF, cr as u64);
+        bus.set_read(BASE + LCR_OFF, lcr as u64);
+        configure_device_on(&mut bus, &uart(1, true));
+        assert_eq!(writes(&bus)[0], (CR_OFF, CR_LBE | CR_TXEN));
+        assert_eq!(writes(&bus)[1], (LCR_OFF, 0x2 << 5));
+    }
+
+    #[test]
+    fn reprogram_baud_keeps_settings() {
+        let cr = CR_TXEN | CR_RXE | CR_UARTEN;
+        let lcr = 0x3 << 5 | LCR_FEN;
+        let mut bus = MockBus::new();
+
+        bus.set_read(BASE + CR_OFF, cr as u64);
+        bus.set_read(BASE + LCR_OFF, lcr as u64);
+        reprogram_baud_on(&mut bus, BASE, DIVISOR);
+        assert_eq!(accesses(&bus)[0], (AccessKind::Read, FR_OFF, 0));
+        assert_eq!(
+            writes(&bus),
+            [
+                (CR_OFF, cr & !CR_UARTEN),
+                (LCR_OFF, lcr & !LCR_FEN),
+                (IBRD_OFF, 3),
+                (FBRD_OFF, 16),
+                (LCR_OFF, lcr),
+                (CR_OFF, cr),
+            ]
+        );
+    }
 }
@@ -0,0 +1,292 @@
+//! Framebuffer console
+//!
+//! Draws text on a [`Framebuffer`] with the [`font`]: characters go at a
+//! cursor that moves right, wraps at the end of a line and moves to the
+//! next line on `\n`. Past the last line, the text scrolls up a line. `\r`
+//! returns to the start of the line and backspace moves back a character.
+//! ANSI escape sequences, such as those of the line editor, are skipped
+//! rather than drawn.
+//!
+//! [`start`] sets up a [`ramfb`] display and adds the console as a sink of
+//! the PL011 console, so it shows everything printed there. Serial stays
+//! the primary console: input is only read from it, and output printed
+//! before [`start`] isn't shown.
+
+use super::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::ramfb::{self, Framebuffer, Mode, PixelFormat, RamfbError};
+use crate::drivers::uart::pl011;
+use crate::selftest::Outcome;
+
+use core::ptr;
+
+/// Color of the text (0xRRGGBB)
+const FOREGROUND: u32 = 0xaaaaaa;
+/// Color of the background (0xRRGGBB)
+const BACKGROUND: u32 = 0x000000;
+/// Escape character, starting ANSI escape sequences
+const ESC: u8 = 0x1b;
+/// Backspace character
+const BACKSPACE: u8 = 0x08;
+
+/// Errors reported when starting the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// The display couldn't be set up, or is smaller than a character
+    Display(RamfbError),
+    /// The PL011 console has no room for another sink
+    NoSink,
+}
+
+impl ConsoleError {
+    /// Returns a description of the error
+    pub fn message(&self) -> &'static [u8] {
+        return match self {
+            ConsoleError::Display(RamfbError::NoDevice) => b"no ramfb display",
+            ConsoleError::Display(RamfbError::BadMode) => b"unsupported display mode",
+            ConsoleError::Display(RamfbError::Reserve(_)) => b"no room for the framebuffer",
+            ConsoleError::Display(RamfbError::FwCfg(_)) => b"no usable fw_cfg device",
+            ConsoleError::NoSink => b"no room for another console output",
+        };
+    }
+}
+
+impl From<RamfbError> for ConsoleError {
+    fn from(e: RamfbError) -> Self {
+        return ConsoleError::Display(e);
+    }
+}
+
+/// Where the console is in an escape sequence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Escape {
+    /// Not in an escape sequence
+    None,
+    /// After [`ESC`]
+    Started,
+    /// In a control sequence (`ESC [`), until its final byte
+    Control,
+}
+
+/// Text console on a framebuffer
+pub struct Console {
+    /// Framebuffer drawn on
+    fb: Framebuffer,
+    /// Number of characters in a line
+    cols: usize,
+    /// Number of lines
+    rows: usize,
+    /// Column of the cursor
+    col: usize,
+    /// Line of the cursor
+    row: usize,
+    /// Pixel value of the text
+    fg: u32,
+    /// Pixel value of the background
+    bg: u32,
+    /// Escape sequence being skipped
+    escape: Escape,
+}
+
+impl Console {
+    /// Creates a console on `fb`, with the cursor at the top left
+    ///
+    /// The framebuffer is expected to hold at least one character.
+    pub fn new(fb: Framebuffer) -> Self {
+        return Self {
+            fb,
+            cols: fb.width / GLYPH_WIDTH,
+            rows: fb.height / GLYPH_HEIGHT,
+            col: 0,
+            row: 0,
+            fg: fb.format.encode(FOREGROUND),
+            bg: fb.format.encode(BACKGROUND),
+            escape: Escape::None,
+        };
+    }
+
+    /// Writes `c` at the cursor, or acts on it if it's a control character
+    pub fn putc(&mut self, c: u8) {
+        match (self.escape, c) {
+            (Escape::None, ESC) => self.escape = Escape::Started,
+            (Escape::Started, b'[') => self.escape = Escape::Control,
+            (Escape::Started, _) => self.escape = Escape::None,
+            (Escape::Control, 0x40..=0x7e) => self.escape = Escape::None,
+            (Escape::Control, _) => {}
+            (Escape::None, b'\n') => self.newline(),
+            (Escape::None, b'\r') => self.col = 0,
+            (Escape::None, BACKSPACE) => self.col = self.col.saturating_sub(1),
+            (Escape::None, c) if c < b' ' => {}
+            (Escape::None, c) => {
+                if self.col == self.cols {
+                    self.newline();
+                }
+                self.draw(c);
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Moves the cursor to the start of the next line, scrolling if it's
+    /// past the last one
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        self.scroll();
+    }
+
+    /// Moves the text up a line and clears the last line
+    ///
+    /// The lines are moved with a single overlapping copy, which the
+    /// compiler's `memmove` does a word at a time; redrawing them character
+    /// by character would be far slower on a large display.
+    fn scroll(&mut self) {
+        let line = self.fb.stride * GLYPH_HEIGHT;
+
+        unsafe {
+            ptr::copy(
+                (self.fb.base + line) as *const u8,
+                self.fb.base as *mut u8,
+                line * (self.rows - 1),
+            );
+        }
+        self.clear_line(self.rows - 1);
+    }
+
+    /// Fills text line `row` with the background
+    fn clear_line(&mut self, row: usize) {
+        for y in row * GLYPH_HEIGHT..(row + 1) * GLYPH_HEIGHT {
+            for x in 0..self.cols * GLYPH_WIDTH {
+                self.put_pixel(x, y, self.bg);
+            }
+        }
+    }
+
+    /// Draws the glyph of `c` at the cursor
+    fn draw(&mut self, c: u8) {
+        let glyph = font::glyph(c);
+        let (left, top) = (self.col * GLYPH_WIDTH, self.row * GLYPH_HEIGHT);
+
+        for (y, bits) in glyph.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                let color = match bits & (0x80 >> x) {
+                    0 => self.bg,
+                    _ => self.fg,
+                };
+                self.put_pixel(left + x, top + y, color);
+            }
+        }
+    }
+
+    /// Sets pixel (`x`, `y`) to the pixel value `color`
+    fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+        let bytes = self.fb.format.bytes_per_pixel();
+        let addr = self.fb.base + y * self.fb.stride + x * bytes;
+
+        unsafe {
+            match bytes {
+                2 => ptr::write_volatile(addr as *mut u16, color as u16),
+                _ => ptr::write_volatile(addr as *mut u32, color),
+            }
+        }
+    }
+}
+
+/// Console started by [`start`], if any
+static mut CONSOLE: Option<Console> = None;
+
+/// PL011 sink drawing the printed output on [`CONSOLE`]
+fn sink(c: u8) {
+    let console = &raw mut CONSOLE;
+
+    if let Some(console) = unsafe { (*console).as_mut() } {
+        console.putc(c);
+    }
+}
+
+/// Sets up a ramfb display in `mode` and mirrors the console on it
+///
+/// Returns the framebuffer of the console, which keeps the mode it was
+/// first started in.
+pub fn start(mode: Mode) -> Result<Framebuffer, ConsoleError> {
+    let console = &raw mut CONSOLE;
+    let fb = ramfb::init(mode)?;
+
+    if fb.width < GLYPH_WIDTH || fb.height < GLYPH_HEIGHT {
+        return Err(RamfbError::BadMode.into());
+    }
+    unsafe {
+        if (*console).is_none() {
+            *console = Some(Console::new(fb));
+            if !pl011::add_sink(sink) {
+                *console = None;
+                return Err(ConsoleError::NoSink);
+            }
+        }
+    }
+    return Ok(fb);
+}
+
+/// Returns the framebuffer of the console, if it's started
+pub fn framebuffer() -> Option<Framebuffer> {
+    let console = &raw const CONSOLE;
+
+    return unsafe { (*console).as_ref() }.map(|console| console.fb);
+}
+
+/// Width of the self-test's framebuffer: two characters
+const TEST_WIDTH: usize = 2 * GLYPH_WIDTH;
+/// Height of the self-test's framebuffer: two lines
+const TEST_HEIGHT: usize = 2 * GLYPH_HEIGHT;
+
+/// Framebuffer of the self-test, in [`PixelFormat::Xrgb8888`]
+static mut TEST_FB: [u32; TEST_WIDTH * TEST_HEIGHT] = [0; TEST_WIDTH * TEST_HEIGHT];
+
+/// Checks whether line `row` of the self-test's framebuffer shows `text`
+fn test_shows(row: usize, text: &[u8; 2]) -> bool {
+    let fb = &raw const TEST_FB;
+    let fb = unsafe { &*fb };
+
+    for (col, &c) in text.iter().enumerate() {
+        for (y, bits) in font::glyph(c).iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                let pixel = fb[(row * GLYPH_HEIGHT + y) * TEST_WIDTH + col * GLYPH_WIDTH + x];
+                let expected = match bits & (0x80 >> x) {
+                    0 => BACKGROUND,
+                    _ => FOREGROUND,
+                };
+                if pixel != expected {
+                    return false;
+                }
+            }
+        }
+    }
+    return true;
+}
+
+/// Self-test: text drawn on a two by two character framebuffer shows the
+/// glyphs of the font, wraps, scrolls up a line when full, and skips escape
+/// sequences
+pub fn selftest() -> Outcome {
+    let fb = &raw mut TEST_FB;
+    let mut console = Console::new(Framebuffer {
+        base: unsafe { (*fb).as_mut_ptr() } as usize,
+        width: TEST_WIDTH,
+        height: TEST_HEIGHT,
+        stride: TEST_WIDTH * 4,
+        format: PixelFormat::Xrgb8888,
+    });
+
+    b"ab\x1b[2Kcd".iter().for_each(|&c| console.putc(c));
+    if !(test_shows(0, b"ab") && test_shows(1, b"cd")) {
+        return Outcome::Fail;
+    }
+    // A third line scrolls "cd" to the top and starts from a blank line
+    b"\ne\x08f".iter().for_each(|&c| console.putc(c));
+    if !(test_shows(0, b"cd") && test_shows(1, b"f ")) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,5 @@
+//! Video drivers module
+
+pub mod console;
+pub mod font;
+pub mod ramfb;
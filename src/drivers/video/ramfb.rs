@@ -0,0 +1,214 @@
+//! QEMU ramfb display driver
+//!
+//! ramfb is a display without registers: the guest picks a mode and a
+//! framebuffer in its own RAM, and hands both to QEMU by writing the
+//! `etc/ramfb` file of [`fw_cfg`]. QEMU then shows whatever is drawn there.
+//! The device only exists when QEMU runs with `-device ramfb`.
+//!
+//! The framebuffer is allocated from free RAM and reserved as
+//! [`ReserveTag::Framebuffer`], so nothing placed later overwrites it.
+
+use crate::drivers::firmware::fw_cfg::{self, FwCfgError};
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+
+use core::ptr;
+
+/// Name of the fw_cfg file configuring the display
+const RAMFB_FILE: &[u8] = b"etc/ramfb";
+/// Size of the configuration written to [`RAMFB_FILE`]
+const CONFIG_SIZE: usize = 28;
+/// Largest width or height QEMU accepts
+const MAX_DIMENSION: usize = 16384;
+/// Alignment of the framebuffer
+const FRAMEBUFFER_ALIGN: usize = 4096;
+
+/// Layout of a pixel in the framebuffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 32 bits: blue in the low byte, then green and red; top byte unused
+    Xrgb8888,
+    /// 32 bits: red in the low byte, then green and blue; top byte unused
+    Xbgr8888,
+    /// 16 bits: 5 bits of red on top, 6 of green, 5 of blue
+    Rgb565,
+}
+
+impl PixelFormat {
+    /// All formats, in the order they are listed
+    pub const ALL: [PixelFormat; 3] = [
+        PixelFormat::Xrgb8888,
+        PixelFormat::Xbgr8888,
+        PixelFormat::Rgb565,
+    ];
+
+    /// Returns the name of the format
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            PixelFormat::Xrgb8888 => b"xrgb8888",
+            PixelFormat::Xbgr8888 => b"xbgr8888",
+            PixelFormat::Rgb565 => b"rgb565",
+        };
+    }
+
+    /// Returns the format named `name`, if there is one
+    pub fn from_name(name: &[u8]) -> Option<PixelFormat> {
+        return PixelFormat::ALL.into_iter().find(|f| f.name() == name);
+    }
+
+    /// Returns the DRM fourcc code QEMU knows the format by
+    fn fourcc(self) -> u32 {
+        let code = match self {
+            PixelFormat::Xrgb8888 => b"XR24",
+            PixelFormat::Xbgr8888 => b"XB24",
+            PixelFormat::Rgb565 => b"RG16",
+        };
+
+        return u32::from_le_bytes(*code);
+    }
+
+    /// Returns the size of a pixel in bytes
+    pub fn bytes_per_pixel(self) -> usize {
+        return match self {
+            PixelFormat::Xrgb8888 | PixelFormat::Xbgr8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        };
+    }
+
+    /// Returns the pixel value of the color `rgb` (0xRRGGBB)
+    pub fn encode(self, rgb: u32) -> u32 {
+        let (r, g, b) = ((rgb >> 16) & 0xff, (rgb >> 8) & 0xff, rgb & 0xff);
+
+        return match self {
+            PixelFormat::Xrgb8888 => rgb & 0xff_ffff,
+            PixelFormat::Xbgr8888 => b << 16 | g << 8 | r,
+            PixelFormat::Rgb565 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+        };
+    }
+}
+
+/// Display mode to set up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mode {
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Layout of the pixels
+    pub format: PixelFormat,
+}
+
+/// Mode used unless another one is asked for
+pub const DEFAULT_MODE: Mode = Mode {
+    width: 1024,
+    height: 768,
+    format: PixelFormat::Xrgb8888,
+};
+
+/// A framebuffer shown on the display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    /// Address of the top left pixel
+    pub base: usize,
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Distance in bytes from a line to the next
+    pub stride: usize,
+    /// Layout of the pixels
+    pub format: PixelFormat,
+}
+
+impl Framebuffer {
+    /// Returns the size of the framebuffer in bytes
+    pub fn size(&self) -> usize {
+        return self.stride * self.height;
+    }
+}
+
+/// Errors reported while setting up the display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RamfbError {
+    /// QEMU has no ramfb device
+    NoDevice,
+    /// The width or height is zero or too large
+    BadMode,
+    /// No free RAM for the framebuffer
+    Reserve(ReserveError),
+    /// The fw_cfg device is missing or failed
+    FwCfg(FwCfgError),
+}
+
+impl From<ReserveError> for RamfbError {
+    fn from(e: ReserveError) -> Self {
+        return RamfbError::Reserve(e);
+    }
+}
+
+impl From<FwCfgError> for RamfbError {
+    fn from(e: FwCfgError) -> Self {
+        return RamfbError::FwCfg(e);
+    }
+}
+
+/// Framebuffer set up by [`init`], if any
+static mut FRAMEBUFFER: Option<Framebuffer> = None;
+
+/// Returns the configuration of [`RAMFB_FILE`] showing `fb`
+fn config(fb: &Framebuffer) -> [u8; CONFIG_SIZE] {
+    let mut config = [0u8; CONFIG_SIZE];
+
+    config[0..8].copy_from_slice(&(fb.base as u64).to_be_bytes());
+    config[8..12].copy_from_slice(&fb.format.fourcc().to_be_bytes());
+    // 12..16: flags, none defined
+    config[16..20].copy_from_slice(&(fb.width as u32).to_be_bytes());
+    config[20..24].copy_from_slice(&(fb.height as u32).to_be_bytes());
+    config[24..28].copy_from_slice(&(fb.stride as u32).to_be_bytes());
+    return config;
+}
+
+/// Sets up the display in `mode`, with a cleared framebuffer
+///
+/// Once set up, the display keeps its framebuffer: later calls return it
+/// whatever their mode.
+pub fn init(mode: Mode) -> Result<Framebuffer, RamfbError> {
+    if let Some(fb) = framebuffer() {
+        return Ok(fb);
+    }
+    if !(1..=MAX_DIMENSION).contains(&mode.width) || !(1..=MAX_DIMENSION).contains(&mode.height) {
+        return Err(RamfbError::BadMode);
+    }
+    let file = fw_cfg::find_file(RAMFB_FILE)?.ok_or(RamfbError::NoDevice)?;
+    if (file.size as usize) < CONFIG_SIZE {
+        return Err(RamfbError::NoDevice);
+    }
+
+    let stride = mode.width * mode.format.bytes_per_pixel();
+    let size = stride * mode.height;
+    let base = reserve::allocate(size, FRAMEBUFFER_ALIGN, None, ReserveTag::Framebuffer)?;
+    let fb = Framebuffer {
+        base,
+        width: mode.width,
+        height: mode.height,
+        stride,
+        format: mode.format,
+    };
+    unsafe {
+        ptr::write_bytes(base as *mut u8, 0, size);
+    }
+    if let Err(e) = fw_cfg::write_file(&file, &config(&fb)) {
+        reserve::discard(base, ReserveTag::Framebuffer);
+        return Err(e.into());
+    }
+    unsafe {
+        FRAMEBUFFER = Some(fb);
+    }
+    return Ok(fb);
+}
+
+/// Returns the framebuffer set up by [`init`], if any
+pub fn framebuffer() -> Option<Framebuffer> {
+    unsafe {
+        return FRAMEBUFFER;
+    }
+}
@@ -0,0 +1,3 @@
+//! Watchdog drivers module
+
+pub mod sp805;
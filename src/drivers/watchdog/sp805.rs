@@ -0,0 +1,101 @@
+//! ARM SP805 watchdog driver
+//!
+//! The SP805 counts down and resets the system when its counter runs out a
+//! second time without being reloaded. Firmware may leave it running, so
+//! the bootloader needs to be able to [`kick`] or [`stop`] it. Like the
+//! PL031, it's only known when found in the device tree (see
+//! [`registry`](crate::drivers::registry)).
+
+use crate::drivers::lifecycle::{Lifecycle, WrongState};
+use crate::utilities::mmio;
+
+/// Load register: value the counter restarts from
+const WDOG_LOAD: usize = 0x000;
+/// Control register
+const WDOG_CONTROL: usize = 0x008;
+/// Interrupt clear register: any write reloads the counter
+const WDOG_INTCLR: usize = 0x00c;
+/// Lock register: other registers are read-only unless unlocked
+const WDOG_LOCK: usize = 0xc00;
+/// Value written to [`WDOG_LOCK`] to unlock the registers
+const UNLOCK_KEY: u32 = 0x1acc_e551;
+/// Any other value locks them again
+const LOCK_KEY: u32 = 0;
+
+/// Base address of the watchdog, 0 until [`init`] is called
+static mut BASE: usize = 0;
+/// Lifecycle of the driver
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Records the base address of the watchdog at `base`
+///
+/// Its state is left alone: a watchdog armed by the firmware stays armed.
+/// Fails, changing nothing, if the driver is already set up.
+pub fn init(base: usize) -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).start()?;
+        BASE = base;
+    }
+    return Ok(());
+}
+
+/// Stops the driver for the payload
+///
+/// The watchdog is left as it is: armed, it stays armed for the payload to
+/// kick. Fails if it isn't set up or is already quiesced.
+pub fn quiesce() -> Result<(), WrongState> {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe { (*lifecycle).quiesce() }?;
+    return Ok(());
+}
+
+/// Forgets the watchdog, so [`init`] can set it up again
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    unsafe {
+        (*lifecycle).teardown();
+        BASE = 0;
+    }
+}
+
+/// Returns the base address of the watchdog, if [`init`] was called
+pub fn base() -> Option<usize> {
+    let base = unsafe { BASE };
+
+    return (base != 0).then_some(base);
+}
+
+/// Reloads the counter
+///
+/// Does nothing if the watchdog isn't initialized.
+pub fn kick() {
+    let Some(base) = base() else {
+        return;
+    };
+
+    unsafe {
+        mmio::write_mmio32(base, WDOG_LOCK, UNLOCK_KEY);
+        mmio::write_mmio32(base, WDOG_INTCLR, 1);
+        mmio::write_mmio32(base, WDOG_LOCK, LOCK_KEY);
+    }
+}
+
+/// Disables the counter and its reset output
+///
+/// Does nothing if the watchdog isn't initialized.
+pub fn stop() {
+    let Some(base) = base() else {
+        return;
+    };
+
+    unsafe {
+        mmio::write_mmio32(base, WDOG_LOCK, UNLOCK_KEY);
+        mmio::write_mmio32(base, WDOG_CONTROL, 0);
+        mmio::write_mmio32(base, WDOG_LOAD, u32::MAX);
+        mmio::write_mmio32(base, WDOG_LOCK, LOCK_KEY);
+    }
+}
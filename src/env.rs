@@ -0,0 +1,184 @@
+//! Environment variables
+//!
+//! A small table of `name=value` strings, read by the monitor's scripts
+//! (`${name}` and `run <name>`) and by the autoboot path, which runs
+//! `${bootcmd}` when it is set.
+//!
+//! The table starts from the environment block of the board's flash (see
+//! [`FlashConfig`](crate::board::FlashConfig)), read the first time a
+//! variable is looked up: one `name=value` line per variable, ending at the
+//! first NUL or erased (0xff) byte. Changes made with [`set`] and [`unset`]
+//! only last until the next reset.
+//!
+//! Variables are kept in fixed buffers: at most [`MAX_VARS`] of them, with
+//! names of up to [`NAME_SIZE`] bytes of letters, digits and `_`, and
+//! values of up to [`VALUE_SIZE`] bytes.
+
+use crate::board;
+
+use core::slice;
+
+/// Maximum number of variables
+pub const MAX_VARS: usize = 16;
+/// Longest variable name
+pub const NAME_SIZE: usize = 32;
+/// Longest variable value
+pub const VALUE_SIZE: usize = 256;
+
+/// Errors reported when setting a variable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvError {
+    /// The name is empty, too long or has a character other than a letter,
+    /// a digit or `_`
+    BadName,
+    /// The value is longer than [`VALUE_SIZE`]
+    ValueTooLong,
+    /// [`MAX_VARS`] variables are already set
+    Full,
+}
+
+/// A variable
+#[derive(Clone, Copy)]
+struct Var {
+    /// Name, `name_len` bytes long
+    name: [u8; NAME_SIZE],
+    /// Length of the name
+    name_len: usize,
+    /// Value, `value_len` bytes long
+    value: [u8; VALUE_SIZE],
+    /// Length of the value
+    value_len: usize,
+}
+
+/// The variables, `None` for free slots
+static mut VARS: [Option<Var>; MAX_VARS] = [None; MAX_VARS];
+
+/// Whether the environment block was read into [`VARS`]
+static mut LOADED: bool = false;
+
+/// Checks whether `name` can name a variable
+pub const fn is_valid_name(name: &[u8]) -> bool {
+    let mut i = 0;
+
+    if name.is_empty() || name.len() > NAME_SIZE {
+        return false;
+    }
+    while i < name.len() {
+        if !(name[i].is_ascii_alphanumeric() || name[i] == b'_') {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(is_valid_name(b"bootcmd") && is_valid_name(b"boot_2"));
+const _: () = assert!(!is_valid_name(b"") && !is_valid_name(b"a-b") && !is_valid_name(b"a b"));
+
+/// Returns the variable table, reading the environment block first if
+/// needed
+fn vars() -> &'static mut [Option<Var>; MAX_VARS] {
+    let vars = &raw mut VARS;
+    let vars = unsafe { &mut *vars };
+
+    unsafe {
+        if !LOADED {
+            LOADED = true;
+            if let Some(flash) = board::config().flash
+                && flash.env_size != 0
+            {
+                let block = slice::from_raw_parts(
+                    (flash.base + flash.env_offset) as *const u8,
+                    flash.env_size,
+                );
+                load(block);
+            }
+        }
+    }
+    return vars;
+}
+
+/// Sets the variables of the `name=value` lines of `text`
+///
+/// Reading stops at the first NUL or 0xff byte. Lines without `=` and
+/// variables that can't be set are skipped. Returns how many were set.
+pub fn load(text: &[u8]) -> usize {
+    let end = text
+        .iter()
+        .position(|&c| c == 0 || c == 0xff)
+        .unwrap_or(text.len());
+    let mut count = 0;
+
+    for line in text[..end].split(|&c| c == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some(eq) = line.iter().position(|&c| c == b'=') else {
+            continue;
+        };
+        if set(&line[..eq], &line[eq + 1..]).is_ok() {
+            count += 1;
+        }
+    }
+    return count;
+}
+
+/// Returns the value of the variable `name`, if set
+///
+/// The value is changed in place by a later [`set`] of the same name, so
+/// callers running it as a script copy it first.
+pub fn get(name: &[u8]) -> Option<&'static [u8]> {
+    return vars()
+        .iter()
+        .flatten()
+        .find(|var| &var.name[..var.name_len] == name)
+        .map(|var| &var.value[..var.value_len]);
+}
+
+/// Sets the variable `name` to `value`, replacing its value if already set
+pub fn set(name: &[u8], value: &[u8]) -> Result<(), EnvError> {
+    if !is_valid_name(name) {
+        return Err(EnvError::BadName);
+    }
+    if value.len() > VALUE_SIZE {
+        return Err(EnvError::ValueTooLong);
+    }
+    let vars = vars();
+    let slot = match vars
+        .iter()
+        .position(|var| var.is_some_and(|var| &var.name[..var.name_len] == name))
+    {
+        Some(index) => index,
+        None => vars
+            .iter()
+            .position(|var| var.is_none())
+            .ok_or(EnvError::Full)?,
+    };
+
+    let mut var = Var {
+        name: [0; NAME_SIZE],
+        name_len: name.len(),
+        value: [0; VALUE_SIZE],
+        value_len: value.len(),
+    };
+    var.name[..name.len()].copy_from_slice(name);
+    var.value[..value.len()].copy_from_slice(value);
+    vars[slot] = Some(var);
+    return Ok(());
+}
+
+/// Removes the variable `name`, returning `false` if it wasn't set
+pub fn unset(name: &[u8]) -> bool {
+    for var in vars().iter_mut() {
+        if var.is_some_and(|v| &v.name[..v.name_len] == name) {
+            *var = None;
+            return true;
+        }
+    }
+    return false;
+}
+
+/// Calls `f` with the name and value of every variable, in table order
+pub fn for_each(mut f: impl FnMut(&[u8], &[u8])) {
+    for var in vars().iter().flatten() {
+        f(&var.name[..var.name_len], &var.value[..var.value_len]);
+    }
+}
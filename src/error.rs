@@ -0,0 +1,558 @@
+//! Crate-wide boot error
+//!
+//! Every subsystem reports failures with its own error enum. [`BootError`]
+//! wraps them all, so a boot path mixing several subsystems can propagate any
+//! of them with `?`, and [`print_error`] renders the failure for the console.
+//!
+//! # Error codes
+//!
+//! Functions called from assembly can't return a `Result`. They return 0 on
+//! success and [`BootError::code`] otherwise, a stable `u32` laid out as:
+//!
+//! | Bits   | Meaning                                                 |
+//! |--------|---------------------------------------------------------|
+//! | 31..16 | Subsystem (1 ELF, 2 FDT, 3 reservations, 4 measured     |
+//! |        | boot, 5 XMODEM, 6 UART, 7 DTB placement, 8 boot source, |
+//! |        | 9 decryption, 10 memory plan, 11 handoff, 12 driver     |
+//! |        | lifecycle)                                              |
+//! | 15..8  | Variant within the subsystem, starting at 1             |
+//! | 7..0   | Variant of the underlying error, 0 if there is none     |
+//!
+//! Every error code is non-zero, so the caller can branch with `cbnz`.
+//!
+//! # Transient errors
+//!
+//! [`BootError::is_transient`] tells errors that may go away on another
+//! attempt (timeouts, corrupted serial transfers) from permanent ones (bad
+//! images, failed authentication, missing memory or features, an abort
+//! requested with `Ctrl-C`). Boot plans only retry the former.
+
+use crate::boot::{HandoffError, PlaceError};
+use crate::bootplan::{DecryptError, SourceError};
+use crate::drivers::lifecycle::{State, WrongState};
+use crate::drivers::uart::pl011::{self, UartError};
+use crate::log;
+use crate::measure::MeasureError;
+use crate::memory::reserve::ReserveError;
+use crate::memory::space::SpaceError;
+#[cfg(not(feature = "stage1"))]
+use crate::monitor;
+use crate::parsers::elf::ElfError;
+use crate::parsers::fdt::FdtError;
+use crate::selftest::{self, Outcome, SelfTest};
+use crate::serial::xmodem::XmodemError;
+use crate::utilities::print::{CaptureSink, Console, Uart, u64_to_hex};
+
+/// Any error reported while booting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootError {
+    /// The kernel image was rejected
+    Elf(ElfError),
+    /// A DTB couldn't be parsed
+    Fdt(FdtError),
+    /// A memory range couldn't be reserved
+    Reserve(ReserveError),
+    /// The measured boot log failed
+    Measure(MeasureError),
+    /// An XMODEM transfer failed
+    Xmodem(XmodemError),
+    /// The console couldn't be configured
+    Uart(UartError),
+    /// The DTB couldn't be relocated
+    Place(PlaceError),
+    /// A boot source couldn't provide a component
+    Source(SourceError),
+    /// An encrypted component couldn't be decrypted
+    Decrypt(DecryptError),
+    /// There isn't enough memory to load an image
+    Space(SpaceError),
+    /// The payload can't be entered as asked
+    Handoff(HandoffError),
+    /// A driver was asked to set up or stop in a state it can't
+    WrongState(WrongState),
+}
+
+impl From<ElfError> for BootError {
+    fn from(e: ElfError) -> Self {
+        return BootError::Elf(e);
+    }
+}
+
+impl From<FdtError> for BootError {
+    fn from(e: FdtError) -> Self {
+        return BootError::Fdt(e);
+    }
+}
+
+impl From<ReserveError> for BootError {
+    fn from(e: ReserveError) -> Self {
+        return BootError::Reserve(e);
+    }
+}
+
+impl From<MeasureError> for BootError {
+    fn from(e: MeasureError) -> Self {
+        return BootError::Measure(e);
+    }
+}
+
+impl From<XmodemError> for BootError {
+    fn from(e: XmodemError) -> Self {
+        return BootError::Xmodem(e);
+    }
+}
+
+impl From<UartError> for BootError {
+    fn from(e: UartError) -> Self {
+        return BootError::Uart(e);
+    }
+}
+
+impl From<PlaceError> for BootError {
+    fn from(e: PlaceError) -> Self {
+        return BootError::Place(e);
+    }
+}
+
+impl From<SourceError> for BootError {
+    fn from(e: SourceError) -> Self {
+        return BootError::Source(e);
+    }
+}
+
+impl From<DecryptError> for BootError {
+    fn from(e: DecryptError) -> Self {
+        return BootError::Decrypt(e);
+    }
+}
+
+impl From<SpaceError> for BootError {
+    fn from(e: SpaceError) -> Self {
+        return BootError::Space(e);
+    }
+}
+
+impl From<HandoffError> for BootError {
+    fn from(e: HandoffError) -> Self {
+        return BootError::Handoff(e);
+    }
+}
+
+impl From<WrongState> for BootError {
+    fn from(e: WrongState) -> Self {
+        return BootError::WrongState(e);
+    }
+}
+
+const _: () = assert!(BootError::Xmodem(XmodemError::TooManyRetries).is_transient());
+const _: () = assert!(BootError::Source(SourceError::Timeout).is_transient());
+const _: () = assert!(!BootError::Source(SourceError::Aborted).is_transient());
+const _: () = assert!(!BootError::Elf(ElfError::NotElf).is_transient());
+const _: () = assert!(!BootError::Decrypt(DecryptError::AuthFailed).is_transient());
+
+/// How one level of a [`BootError`] is reported
+struct Description {
+    /// Subsystem number, bits 31..16 of the code
+    subsystem: u32,
+    /// Subsystem name
+    area: &'static [u8],
+    /// Variant number within the subsystem, starting at 1
+    variant: u32,
+    /// What went wrong
+    message: &'static [u8],
+    /// The underlying error, if this one wraps another
+    cause: Option<BootError>,
+}
+
+impl BootError {
+    /// Returns the stable error code passed across the `extern "C"` boundary
+    ///
+    /// See the [module documentation](self) for the layout.
+    pub const fn code(&self) -> u32 {
+        let description = self.describe();
+        let cause = match description.cause {
+            Some(cause) => cause.describe().variant,
+            None => 0,
+        };
+
+        return description.subsystem << 16 | description.variant << 8 | cause;
+    }
+
+    /// Checks whether another attempt at the failed operation may succeed
+    ///
+    /// See the [module documentation](self#transient-errors).
+    pub const fn is_transient(&self) -> bool {
+        return matches!(
+            self,
+            BootError::Xmodem(
+                XmodemError::Timeout | XmodemError::BadBlock | XmodemError::TooManyRetries
+            ) | BootError::Source(SourceError::Timeout)
+                | BootError::Elf(ElfError::Timeout)
+        );
+    }
+
+    /// Returns how this error is reported
+    const fn describe(&self) -> Description {
+        let mut cause = None;
+        let (subsystem, area, variant, message): (u32, &[u8], u32, &[u8]) = match *self {
+            BootError::Elf(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    ElfError::NotElf => (1, b"not an ELF file"),
+                    ElfError::NotElf64 => (2, b"not a 64-bit ELF file"),
+                    ElfError::BadEndianness => (3, b"invalid data encoding"),
+                    ElfError::BadOsAbi => (4, b"invalid OS/ABI"),
+                    ElfError::BadType => (5, b"not an executable"),
+                    ElfError::BadMachine => (6, b"invalid machine"),
+                    ElfError::BadVersion => (7, b"invalid version"),
+                    ElfError::BadEhsize => (8, b"invalid header size"),
+                    ElfError::BigEndianUnsupported => (9, b"big-endian images are not supported"),
+                    ElfError::DeviceTarget => (10, b"segment placed over device memory"),
+                    ElfError::Timeout => (11, b"load timed out"),
+                    ElfError::Aborted => (12, b"load aborted"),
+                    ElfError::TooManySegments => (13, b"too many program headers"),
+                    ElfError::Truncated => (14, b"image truncated"),
+                    ElfError::VerifyMismatch(_) => (15, b"loaded data differs from the image"),
+                    ElfError::ReservedTarget => (16, b"segment placed over reserved memory"),
+                    ElfError::BadSegment => (17, b"invalid segment bounds"),
+                };
+                (1, b"ELF", variant, message)
+            }
+            BootError::Fdt(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    FdtError::BadMagic => (1, b"bad magic"),
+                    FdtError::BadVersion => (2, b"unsupported version"),
+                    FdtError::Truncated => (3, b"truncated blob"),
+                    FdtError::BadStructure => (4, b"malformed structure block"),
+                    FdtError::NoNode => (5, b"node not found"),
+                    FdtError::NoSpace => (6, b"no room for the edit"),
+                    FdtError::BadLayout => (7, b"blocks outside the blob or overlapping"),
+                    FdtError::Unreadable => (8, b"nothing readable at the address"),
+                };
+                (2, b"FDT", variant, message)
+            }
+            BootError::Reserve(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    ReserveError::Overlap => (1, b"overlaps a reservation"),
+                    ReserveError::Full => (2, b"no free reservation slot"),
+                    ReserveError::NoSpace => (3, b"no free memory"),
+                };
+                (3, b"reservation", variant, message)
+            }
+            BootError::Measure(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    MeasureError::NotInitialized => (1, b"log not initialized"),
+                    MeasureError::NoSpace(inner) => {
+                        cause = Some(BootError::Reserve(inner));
+                        (2, b"no room for the log")
+                    }
+                    MeasureError::LogFull => (3, b"log full"),
+                    MeasureError::Corrupted => (4, b"log corrupted"),
+                };
+                (4, b"measured boot", variant, message)
+            }
+            BootError::Xmodem(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    XmodemError::Timeout => (1, b"timeout"),
+                    XmodemError::Cancelled => (2, b"cancelled by the sender"),
+                    XmodemError::BufferTooSmall => (3, b"buffer too small"),
+                    XmodemError::BadBlock => (4, b"bad block"),
+                    XmodemError::Aborted => (5, b"aborted"),
+                    XmodemError::TooManyRetries => (6, b"too many retries"),
+                    XmodemError::BadHeader => (7, b"bad YMODEM header"),
+                };
+                (5, b"XMODEM", variant, message)
+            }
+            BootError::Uart(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    UartError::NotInitialized => (1, b"no base address"),
+                    UartError::BadBaudrate => (2, b"baud rate out of range"),
+                    UartError::NoInput => (3, b"no input"),
+                    UartError::WrongState(inner) => {
+                        cause = Some(BootError::WrongState(inner));
+                        (4, b"console in the wrong state")
+                    }
+                };
+                (6, b"UART", variant, message)
+            }
+            BootError::Place(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    PlaceError::InvalidDtb(inner) => {
+                        cause = Some(BootError::Fdt(inner));
+                        (1, b"invalid DTB")
+                    }
+                    PlaceError::TooLarge => (2, b"DTB too large"),
+                    PlaceError::NoSpace(inner) => {
+                        cause = Some(BootError::Reserve(inner));
+                        (3, b"no room for the DTB")
+                    }
+                };
+                (7, b"DTB placement", variant, message)
+            }
+            BootError::Source(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    SourceError::Unavailable => (1, b"component not available"),
+                    SourceError::UnknownSize => (2, b"component size unknown"),
+                    SourceError::PlanFull => (3, b"too many steps"),
+                    SourceError::Timeout => (4, b"timed out"),
+                    SourceError::Aborted => (5, b"aborted"),
+                };
+                (8, b"boot source", variant, message)
+            }
+            BootError::Decrypt(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    DecryptError::NoKey => (1, b"no payload key"),
+                    DecryptError::BadHeader => (2, b"bad header"),
+                    DecryptError::AuthFailed => (3, b"authentication failed"),
+                    DecryptError::NoSpace(inner) => {
+                        cause = Some(BootError::Reserve(inner));
+                        (4, b"no room for the plaintext")
+                    }
+                };
+                (9, b"decryption", variant, message)
+            }
+            BootError::Space(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    SpaceError::Destination => (1, b"destination is not free RAM"),
+                    SpaceError::Scratch => (2, b"no room to decode the image"),
+                    SpaceError::Dtb => (3, b"no room for the DTB"),
+                    SpaceError::Initrd => (4, b"no room for the initrd"),
+                };
+                (10, b"memory plan", variant, message)
+            }
+            BootError::Handoff(e) => {
+                let (variant, message): (u32, &[u8]) = match e {
+                    HandoffError::NotAtEl2 => (1, b"bootloader not running at EL2"),
+                    HandoffError::NoVhe => (2, b"VHE not implemented"),
+                };
+                (11, b"handoff", variant, message)
+            }
+            BootError::WrongState(WrongState(state)) => {
+                let (variant, message): (u32, &[u8]) = match state {
+                    State::Uninitialized => (1, b"driver not set up"),
+                    State::Probed => (2, b"driver already probed"),
+                    State::Active => (3, b"driver already active"),
+                    State::Quiesced => (4, b"driver stopped for the payload"),
+                };
+                (12, b"driver lifecycle", variant, message)
+            }
+        };
+
+        return Description {
+            subsystem,
+            area,
+            variant,
+            message,
+            cause,
+        };
+    }
+}
+
+/// One error of every variant, with a wrapped error of each kind under
+/// the variants that wrap one
+const ALL_ERRORS: [BootError; 65] = [
+    BootError::Elf(ElfError::NotElf),
+    BootError::Elf(ElfError::NotElf64),
+    BootError::Elf(ElfError::BadEndianness),
+    BootError::Elf(ElfError::BadOsAbi),
+    BootError::Elf(ElfError::BadType),
+    BootError::Elf(ElfError::BadMachine),
+    BootError::Elf(ElfError::BadVersion),
+    BootError::Elf(ElfError::BadEhsize),
+    BootError::Elf(ElfError::BigEndianUnsupported),
+    BootError::Elf(ElfError::DeviceTarget),
+    BootError::Elf(ElfError::Timeout),
+    BootError::Elf(ElfError::Aborted),
+    BootError::Elf(ElfError::TooManySegments),
+    BootError::Elf(ElfError::Truncated),
+    BootError::Elf(ElfError::VerifyMismatch(0x4008_0000)),
+    BootError::Elf(ElfError::ReservedTarget),
+    BootError::Elf(ElfError::BadSegment),
+    BootError::Fdt(FdtError::BadMagic),
+    BootError::Fdt(FdtError::BadVersion),
+    BootError::Fdt(FdtError::Truncated),
+    BootError::Fdt(FdtError::BadStructure),
+    BootError::Fdt(FdtError::NoNode),
+    BootError::Fdt(FdtError::NoSpace),
+    BootError::Fdt(FdtError::BadLayout),
+    BootError::Fdt(FdtError::Unreadable),
+    BootError::Reserve(ReserveError::Overlap),
+    BootError::Reserve(ReserveError::Full),
+    BootError::Reserve(ReserveError::NoSpace),
+    BootError::Measure(MeasureError::NotInitialized),
+    BootError::Measure(MeasureError::NoSpace(ReserveError::NoSpace)),
+    BootError::Measure(MeasureError::LogFull),
+    BootError::Measure(MeasureError::Corrupted),
+    BootError::Xmodem(XmodemError::Timeout),
+    BootError::Xmodem(XmodemError::Cancelled),
+    BootError::Xmodem(XmodemError::BufferTooSmall),
+    BootError::Xmodem(XmodemError::BadBlock),
+    BootError::Xmodem(XmodemError::Aborted),
+    BootError::Xmodem(XmodemError::TooManyRetries),
+    BootError::Xmodem(XmodemError::BadHeader),
+    BootError::Uart(UartError::NotInitialized),
+    BootError::Uart(UartError::BadBaudrate),
+    BootError::Uart(UartError::NoInput),
+    BootError::Uart(UartError::WrongState(WrongState(State::Quiesced))),
+    BootError::Place(PlaceError::InvalidDtb(FdtError::Truncated)),
+    BootError::Place(PlaceError::TooLarge),
+    BootError::Place(PlaceError::NoSpace(ReserveError::Full)),
+    BootError::Source(SourceError::Unavailable),
+    BootError::Source(SourceError::UnknownSize),
+    BootError::Source(SourceError::PlanFull),
+    BootError::Source(SourceError::Timeout),
+    BootError::Source(SourceError::Aborted),
+    BootError::Decrypt(DecryptError::NoKey),
+    BootError::Decrypt(DecryptError::BadHeader),
+    BootError::Decrypt(DecryptError::AuthFailed),
+    BootError::Decrypt(DecryptError::NoSpace(ReserveError::Overlap)),
+    BootError::Space(SpaceError::Destination),
+    BootError::Space(SpaceError::Scratch),
+    BootError::Space(SpaceError::Dtb),
+    BootError::Space(SpaceError::Initrd),
+    BootError::Handoff(HandoffError::NotAtEl2),
+    BootError::Handoff(HandoffError::NoVhe),
+    BootError::WrongState(WrongState(State::Uninitialized)),
+    BootError::WrongState(WrongState(State::Probed)),
+    BootError::WrongState(WrongState(State::Active)),
+    BootError::WrongState(WrongState(State::Quiesced)),
+];
+
+/// Checks that the codes of [`ALL_ERRORS`] are non-zero and distinct
+const fn codes_are_distinct() -> bool {
+    let mut i = 0;
+
+    while i < ALL_ERRORS.len() {
+        let code = ALL_ERRORS[i].code();
+        let mut j = i + 1;
+        if code == 0 {
+            return false;
+        }
+        while j < ALL_ERRORS.len() {
+            if ALL_ERRORS[j].code() == code {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(codes_are_distinct());
+const _: () = assert!(BootError::Elf(ElfError::NotElf).code() == 0x0001_0100);
+const _: () = assert!(BootError::Elf(ElfError::ReservedTarget).code() == 0x0001_1000);
+const _: () =
+    assert!(BootError::Place(PlaceError::InvalidDtb(FdtError::Truncated)).code() == 0x0007_0103);
+const _: () =
+    assert!(BootError::Measure(MeasureError::NoSpace(ReserveError::Full)).code() == 0x0004_0202);
+const _: () = assert!(
+    BootError::Uart(UartError::WrongState(WrongState(State::Active))).code() == 0x0006_0403
+);
+const _: () = assert!(BootError::WrongState(WrongState(State::Quiesced)).code() == 0x000c_0400);
+
+/// Prints `err` with its code and the chain of underlying errors
+///
+/// For example: `Error 0x00070103: DTB placement: invalid DTB: FDT: truncated
+/// blob`. Errors also go to the debug channel, see [`log`].
+pub fn print_error(err: &BootError) {
+    log::to_all_sinks(|| write_error(&mut Uart, err));
+}
+
+/// Writes `err` with its code and the chain of underlying errors, as
+/// [`print_error`]
+fn write_error(out: &mut dyn Console, err: &BootError) {
+    let mut next = Some(*err);
+
+    out.write(b"Error ");
+    write_code(out, err.code());
+    while let Some(e) = next {
+        let description = e.describe();
+        out.write(b": ");
+        out.write(description.area);
+        out.write(b": ");
+        out.write(description.message);
+        next = description.cause;
+    }
+    out.writeln(b"");
+}
+
+/// Prints `code` as 8 zero-padded hexadecimal digits with a `0x` prefix
+pub fn print_code(code: u32) {
+    write_code(&mut Uart, code);
+}
+
+/// Writes `code` as [`print_code`]
+fn write_code(out: &mut dyn Console, code: u32) {
+    let mut buf = [b'0'; 8];
+
+    u64_to_hex(code as u64, &mut buf);
+    out.write(b"0x");
+    out.write(&buf);
+}
+
+/// Self-test: every error is written with its code and, for the errors
+/// wrapping another, the whole chain
+fn selftest_print() -> Outcome {
+    let cases: [(BootError, &[u8]); 3] = [
+        (
+            BootError::Place(PlaceError::InvalidDtb(FdtError::Truncated)),
+            b"Error 0x00070103: DTB placement: invalid DTB: FDT: truncated blob\n",
+        ),
+        (
+            BootError::Uart(UartError::WrongState(WrongState(State::Quiesced))),
+            b"Error 0x00060404: UART: console in the wrong state: driver lifecycle: \
+              driver stopped for the payload\n",
+        ),
+        (
+            BootError::Xmodem(XmodemError::TooManyRetries),
+            b"Error 0x00050600: XMODEM: too many retries\n",
+        ),
+    ];
+    let mut buf = [0u8; 128];
+    let mut sink = CaptureSink::new(&mut buf);
+
+    for (err, expected) in cases {
+        sink.clear();
+        write_error(&mut sink, &err);
+        if sink.overflowed() || sink.bytes() != expected {
+            return Outcome::Fail;
+        }
+    }
+
+    // Every error is written on one line, starting with its own code
+    for err in ALL_ERRORS.iter() {
+        let mut prefix = *b"Error 0x00000000: ";
+        u64_to_hex(err.code() as u64, &mut prefix[8..16]);
+        sink.clear();
+        write_error(&mut sink, err);
+        let line = sink.bytes();
+        let newlines = line.iter().filter(|&&b| b == b'\n').count();
+        if sink.overflowed() || !line.starts_with(&prefix) || newlines != 1 {
+            return Outcome::Fail;
+        }
+    }
+    return Outcome::Pass;
+}
+
+/// Registers the self-tests of the module
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"boot-error",
+        run: selftest_print,
+    });
+}
+
+/// Recovery path of the assembly entry code when loading the kernel fails
+///
+/// `code` is the [`BootError::code`] returned by the failing call, whose
+/// error has already been printed. Enters the monitor so the failure can be
+/// investigated; there is nothing to boot afterwards, so it then panics.
+#[unsafe(no_mangle)]
+pub extern "C" fn boot_failed(code: u32) -> ! {
+    pl011::print(b"Boot failed with error ");
+    print_code(code);
+    pl011::println(b"");
+    #[cfg(not(feature = "stage1"))]
+    monitor::enter(None);
+    panic!("No bootable kernel");
+}
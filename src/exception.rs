@@ -6,11 +6,914 @@
 //! the faulting instruction and register state before panicking.
 //!
 //! The module supports both "bad mode" handlers (for unexpected exception
-//! levels) and normal exception handlers.
+//! levels), normal exception handlers and handlers for exceptions taken from
+//! a lower exception level (the loaded payload). Synchronous handlers first
+//! give the [`monitor`] and [`debug`] modules, and the alignment fault and
+//! data abort callbacks registered with [`set_alignment_handler`] and
+//! [`set_data_abort_handler`], a chance to handle the exception; if one does,
+//! they return and execution resumes with the possibly modified registers.
+//!
+//! Exceptions taken on the current exception level's stack first check the
+//! stack pointer: one that is misaligned, or that ran past the bottom of the
+//! boot stack, is reported by [`do_bad_stack`] from a separate exception
+//! stack, as saving the registers on it would fault again. Fatal exceptions
+//! are kept in the boot record (see [`bootreason`]).
+//!
+//! SMC and HVC instructions trapped from the payload are decoded by
+//! [`decode_smc`] into the function ID and arguments of the SMC Calling
+//! Convention, and named if they are PSCI calls. Once its trap is installed,
+//! the [`psci`] responder answers them; otherwise they are reported as
+//! fatal like any unexpected exception.
+//!
+//! A panic from normal code has no exception frame to dump: the registers
+//! recorded last by [`capture_context`] at key points of the boot, or by a
+//! handler for the code it interrupted, are printed instead (see
+//! [`Context`]).
+//!
+//! The dump is written by [`write_exception`] to any [`Console`], with the
+//! fault address and the faulting instruction read through a
+//! [`DumpSource`], so the dumps of synthetic exceptions can be checked
+//! against the expected output kept in `tests/golden`. The code around the
+//! faulting instruction is part of the dump; [`dump_instructions`] prints
+//! it around any address.
 
-use crate::utilities::print::{print_hex_u64, print_hex_u8};
+use crate::utilities::disasm::{self, DISASM_BUF_SIZE};
+use crate::utilities::mmio::{self, ProbeError};
+use crate::utilities::print::{
+    CaptureSink, Console, Uart, print_hex_u8, print_hex_u64, u64_to_dec, u64_to_hex,
+    u64_to_hex_padded, write_hex_u8, write_hex_u64,
+};
+use crate::board::heartbeat::{self, Pattern};
+use crate::boot;
+use crate::bootreason;
+use crate::cpu;
+use crate::debug;
+use crate::interrupt;
+use crate::log;
+use crate::memory::map;
+#[cfg(not(feature = "stage1"))]
+use crate::monitor;
+use crate::psci;
+use crate::selftest::{self, Outcome, SelfTest};
 use crate::drivers::uart::pl011;
 
+use crate::asm;
+use core::mem::{self, offset_of};
+
+/// ESR_ELx Exception Class field shift
+const ESR_EC_SHIFT: u64 = 26;
+/// ESR_ELx Exception Class field mask (after shifting)
+const ESR_EC_MASK: u64 = 0x3f;
+
+/// Exception Class: HVC instruction execution in AArch64 state
+pub const EC_HVC64: u8 = 0x16;
+/// Exception Class: SMC instruction execution in AArch64 state
+pub const EC_SMC64: u8 = 0x17;
+/// Exception Class: Trapped pointer authentication instruction or key access
+pub const EC_PAC_TRAP: u8 = 0x09;
+/// Exception Class: Branch target exception (FEAT_BTI)
+pub const EC_BTI: u8 = 0x0d;
+/// Exception Class: Pointer authentication failure (FEAT_FPAC)
+pub const EC_PAC_FAIL: u8 = 0x1c;
+/// Exception Class: Instruction Abort from a lower exception level
+pub const EC_IABT_LOW: u8 = 0x20;
+/// Exception Class: Instruction Abort from the current exception level
+pub const EC_IABT_CUR: u8 = 0x21;
+/// Exception Class: PC alignment fault
+pub const EC_PC_ALIGN: u8 = 0x22;
+/// Exception Class: Data Abort from a lower exception level
+pub const EC_DABT_LOW: u8 = 0x24;
+/// Exception Class: Data Abort from the current exception level
+pub const EC_DABT_CUR: u8 = 0x25;
+/// Exception Class: SP alignment fault
+pub const EC_SP_ALIGN: u8 = 0x26;
+/// Exception Class: Breakpoint exception from a lower exception level
+pub const EC_BREAKPT_LOW: u8 = 0x30;
+/// Exception Class: Breakpoint exception from the current exception level
+pub const EC_BREAKPT_CUR: u8 = 0x31;
+/// Exception Class: Software step exception from a lower exception level
+pub const EC_SOFTSTP_LOW: u8 = 0x32;
+/// Exception Class: Software step exception from the current exception level
+pub const EC_SOFTSTP_CUR: u8 = 0x33;
+/// Exception Class: Watchpoint exception from a lower exception level
+pub const EC_WATCHPT_LOW: u8 = 0x34;
+/// Exception Class: Watchpoint exception from the current exception level
+pub const EC_WATCHPT_CUR: u8 = 0x35;
+/// Exception Class: BRK instruction execution in AArch64 state
+pub const EC_BRK64: u8 = 0x3c;
+
+/// ESR_ELx FnV bit: FAR is not valid (data aborts)
+const ESR_FNV: u64 = 1 << 10;
+/// ESR_ELx Data Fault Status Code field mask (data aborts)
+const ESR_DFSC_MASK: u64 = 0x3f;
+/// Data Fault Status Code: alignment fault
+pub const DFSC_ALIGNMENT: u8 = 0x21;
+/// ESR_ELx ISV bit: the syndrome fields below are valid (data aborts)
+const ESR_ISV: u64 = 1 << 24;
+/// ESR_ELx Syndrome Access Size field shift (data aborts, ISV set)
+const ESR_SAS_SHIFT: u64 = 22;
+/// ESR_ELx Syndrome Access Size field mask (after shifting)
+const ESR_SAS_MASK: u64 = 0x3;
+/// ESR_ELx WnR bit: the abort was caused by a write (data aborts)
+const ESR_WNR: u64 = 1 << 6;
+/// ESR_ELx BTYPE field mask (branch target exceptions)
+const ESR_BTYPE_MASK: u64 = 0x3;
+/// ESR_ELx key field mask (pointer authentication failures): bit 1 set for
+/// a data key, bit 0 set for a B key
+const ESR_PAC_KEY_MASK: u64 = 0x3;
+/// ESR_ELx imm16 field mask (HVC and SMC)
+const ESR_IMM16_MASK: u64 = 0xffff;
+/// SMC Calling Convention: function ID bit of fast calls
+const SMCCC_FAST_CALL: u32 = 1 << 31;
+/// SMC Calling Convention: function ID bit of the 64-bit convention
+const SMCCC_64: u32 = 1 << 30;
+/// SMC Calling Convention: owning entity field shift
+const SMCCC_OWNER_SHIFT: u32 = 24;
+/// SMC Calling Convention: owning entity field mask (after shifting)
+const SMCCC_OWNER_MASK: u32 = 0x3f;
+/// SMC Calling Convention: function number field mask
+const SMCCC_FUNCTION_MASK: u32 = 0xffff;
+/// SMC Calling Convention: owning entity of the standard secure services,
+/// PSCI among them
+pub const SMCCC_OWNER_STANDARD: u8 = 4;
+/// PSCI SYSTEM_OFF function ID
+pub const PSCI_SYSTEM_OFF: u32 = 0x8400_0008;
+/// Names of the PSCI functions, by function number
+const PSCI_FUNCTIONS: [&[u8]; 0x16] = [
+    b"PSCI_VERSION",
+    b"CPU_SUSPEND",
+    b"CPU_OFF",
+    b"CPU_ON",
+    b"AFFINITY_INFO",
+    b"MIGRATE",
+    b"MIGRATE_INFO_TYPE",
+    b"MIGRATE_INFO_UP_CPU",
+    b"SYSTEM_OFF",
+    b"SYSTEM_RESET",
+    b"PSCI_FEATURES",
+    b"CPU_FREEZE",
+    b"CPU_DEFAULT_SUSPEND",
+    b"NODE_HW_STATE",
+    b"SYSTEM_SUSPEND",
+    b"PSCI_SET_SUSPEND_MODE",
+    b"PSCI_STAT_RESIDENCY",
+    b"PSCI_STAT_COUNT",
+    b"SYSTEM_RESET2",
+    b"MEM_PROTECT",
+    b"MEM_PROTECT_CHECK_RANGE",
+    b"SYSTEM_OFF2",
+];
+/// SPSR_ELx M[4]: the exception was taken from AArch32 state
+const SPSR_M_AARCH32: u64 = 1 << 4;
+/// SPSR_ELx M[0]: the exception was taken using SP_ELx rather than SP_EL0
+const SPSR_M_SP_ELX: u64 = 1 << 0;
+/// Translation granule assumed when breaking down fault addresses
+const FAULT_GRANULE: usize = 4096;
+
+/// Callback invoked on alignment faults from data accesses
+///
+/// Receives the register state of the faulting code, which it may modify,
+/// and the faulting data address. Returns `true` if it dealt with the access
+/// (e.g., emulated it and moved `regs.elr` past the instruction), in which
+/// case execution resumes; `false` reports the fault as fatal.
+pub type AlignmentHandler = fn(regs: &mut Regs, far: u64) -> bool;
+
+/// Registered alignment fault callback
+static mut ALIGNMENT_HANDLER: Option<AlignmentHandler> = None;
+
+/// Callback invoked on data aborts taken from the current exception level
+///
+/// Receives the register state of the faulting code, which it may modify,
+/// and the faulting data address. Returns `true` if it recovered from the
+/// abort (e.g., moved `regs.elr` past the access), in which case execution
+/// resumes; `false` reports the abort as fatal.
+pub type DataAbortHandler = fn(regs: &mut Regs, far: u64) -> bool;
+
+/// Registered data abort callback
+static mut DATA_ABORT_HANDLER: Option<DataAbortHandler> = None;
+
+/// Callback invoked on SErrors taken from the current exception level
+///
+/// Receives the register state of the interrupted code. Returns `true` if
+/// the SError is expected (e.g., caused by a bus probe), in which case
+/// execution resumes; `false` reports it as fatal.
+pub type SErrorHandler = fn(regs: &mut Regs) -> bool;
+
+/// Registered SError callback
+static mut SERROR_HANDLER: Option<SErrorHandler> = None;
+
+/// Callback invoked on `brk` instructions executed at the current exception
+/// level
+///
+/// Receives the immediate of the `brk` and the register state of the code
+/// that executed it, which it may modify. Execution always resumes, past
+/// the `brk`.
+pub type BrkHandler = fn(imm: u16, regs: &mut Regs);
+
+/// Registered `brk` callback
+static mut BRK_HANDLER: Option<BrkHandler> = None;
+
+/// ESR_ELx ISS of a `brk`: the immediate of the instruction
+const ESR_BRK_IMM_MASK: u64 = 0xffff;
+
+/// Extracts the Exception Class from an ESR_ELx value
+pub const fn esr_ec(esr: u64) -> u8 {
+    return ((esr >> ESR_EC_SHIFT) & ESR_EC_MASK) as u8;
+}
+
+/// Returns a description of Exception Class `ec`
+///
+/// Covers the whole AArch64 exception class table, including classes only
+/// raised with optional features or for AArch32 lower levels; unallocated
+/// values are reported as reserved.
+pub const fn ec_name(ec: u8) -> &'static str {
+    return match ec {
+        0x00 => "Unknown reason",
+        0x01 => "Trapped WFI/WFE",
+        0x03 => "Trapped AArch32 MCR/MRC (coproc 0b1111)",
+        0x04 => "Trapped AArch32 MCRR/MRRC (coproc 0b1111)",
+        0x05 => "Trapped AArch32 MCR/MRC (coproc 0b1110)",
+        0x06 => "Trapped AArch32 LDC/STC",
+        0x07 => "Trapped SVE/SIMD/FP access",
+        0x08 => "Trapped AArch32 VMRS (ID group)",
+        0x09 => "Trapped pointer authentication instruction",
+        0x0a => "Trapped LD64B/ST64B",
+        0x0c => "Trapped AArch32 MRRC (coproc 0b1110)",
+        0x0d => "Branch target exception",
+        0x0e => "Illegal execution state",
+        0x11 => "SVC in AArch32",
+        0x12 => "HVC in AArch32",
+        0x13 => "SMC in AArch32",
+        0x15 => "SVC in AArch64",
+        0x16 => "HVC in AArch64",
+        0x17 => "SMC in AArch64",
+        0x18 => "Trapped MSR/MRS/system instruction",
+        0x19 => "Trapped SVE access",
+        0x1a => "Trapped ERET/ERETAA/ERETAB",
+        0x1b => "TSTART in TME",
+        0x1c => "Pointer authentication failure",
+        0x1d => "Trapped SME access",
+        0x1e => "Granule protection check",
+        0x1f => "Implementation defined exception to EL3",
+        0x20 => "Instruction abort from a lower EL",
+        0x21 => "Instruction abort from the current EL",
+        0x22 => "PC alignment fault",
+        0x24 => "Data abort from a lower EL",
+        0x25 => "Data abort from the current EL",
+        0x26 => "SP alignment fault",
+        0x27 => "Memory operation exception",
+        0x28 => "Trapped AArch32 floating-point exception",
+        0x2c => "Trapped AArch64 floating-point exception",
+        0x2f => "SError",
+        0x30 => "Breakpoint from a lower EL",
+        0x31 => "Breakpoint from the current EL",
+        0x32 => "Software step from a lower EL",
+        0x33 => "Software step from the current EL",
+        0x34 => "Watchpoint from a lower EL",
+        0x35 => "Watchpoint from the current EL",
+        0x38 => "BKPT in AArch32",
+        0x3a => "Vector catch in AArch32",
+        0x3c => "BRK in AArch64",
+        _ => "Reserved",
+    };
+}
+
+/// Checks that [`ec_name`] names Exception Class `ec` `expected`
+const fn ec_name_is(ec: u8, expected: &str) -> bool {
+    let name = ec_name(ec).as_bytes();
+    let expected = expected.as_bytes();
+    let mut i = 0;
+
+    if name.len() != expected.len() {
+        return false;
+    }
+    while i < name.len() {
+        if name[i] != expected[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(ec_name_is(0x00, "Unknown reason"));
+const _: () = assert!(ec_name_is(0x01, "Trapped WFI/WFE"));
+const _: () = assert!(ec_name_is(0x07, "Trapped SVE/SIMD/FP access"));
+const _: () = assert!(ec_name_is(0x0e, "Illegal execution state"));
+const _: () = assert!(ec_name_is(EC_HVC64, "HVC in AArch64"));
+const _: () = assert!(ec_name_is(EC_SMC64, "SMC in AArch64"));
+const _: () = assert!(ec_name_is(0x18, "Trapped MSR/MRS/system instruction"));
+const _: () = assert!(ec_name_is(EC_IABT_LOW, "Instruction abort from a lower EL"));
+const _: () = assert!(ec_name_is(EC_DABT_CUR, "Data abort from the current EL"));
+const _: () = assert!(ec_name_is(EC_SP_ALIGN, "SP alignment fault"));
+const _: () = assert!(ec_name_is(0x2f, "SError"));
+const _: () = assert!(ec_name_is(EC_SOFTSTP_LOW, "Software step from a lower EL"));
+const _: () = assert!(ec_name_is(EC_WATCHPT_LOW, "Watchpoint from a lower EL"));
+const _: () = assert!(ec_name_is(EC_BRK64, "BRK in AArch64"));
+// Unallocated classes, and the top of the 6-bit field
+const _: () = assert!(ec_name_is(0x02, "Reserved") && ec_name_is(0x23, "Reserved"));
+const _: () = assert!(ec_name_is(0x3f, "Reserved"));
+
+/// Checks whether an ESR_ELx value describes a data access alignment fault
+///
+/// Those are raised for unaligned accesses when `SCTLR_ELx.A` is set, and
+/// for accesses that must always be aligned (e.g., exclusives and atomics).
+pub const fn is_alignment_fault(esr: u64) -> bool {
+    let ec = esr_ec(esr);
+
+    return (ec == EC_DABT_LOW || ec == EC_DABT_CUR)
+        && (esr & ESR_DFSC_MASK) as u8 == DFSC_ALIGNMENT;
+}
+
+const _: () = assert!(is_alignment_fault(0x97c0_0021) && is_alignment_fault(0x9200_0021));
+const _: () = assert!(!is_alignment_fault(0x9600_0010) && !is_alignment_fault(0x9600_0044));
+// Same DFSC value under the instruction abort and SP alignment classes
+const _: () = assert!(!is_alignment_fault(0x8600_0021) && !is_alignment_fault(0x9a00_0021));
+
+/// Names the key whose authentication failed, from the ESR_ELx value of a
+/// pointer authentication failure
+pub const fn pac_fail_key(esr: u64) -> &'static [u8] {
+    return match esr & ESR_PAC_KEY_MASK {
+        0 => b"IA",
+        1 => b"IB",
+        2 => b"DA",
+        _ => b"DB",
+    };
+}
+
+/// Describes the branch that raised a branch target exception, from its
+/// ESR_ELx value
+///
+/// The BTYPE field is the kind of the last indirect branch: BLR sets `0b10`,
+/// BR sets `0b01` through x16 or x17 and `0b11` otherwise.
+pub const fn bti_branch(esr: u64) -> &'static [u8] {
+    return match esr & ESR_BTYPE_MASK {
+        0b01 => b"Jump through x16/x17",
+        0b10 => b"Call",
+        0b11 => b"Jump",
+        _ => b"Branch",
+    };
+}
+
+const _: () = assert!(matches!(pac_fail_key(0x7200_0001), b"IB"));
+const _: () = assert!(matches!(pac_fail_key(0x7200_0002), b"DA"));
+const _: () = assert!(matches!(bti_branch(0x3600_0002), b"Call"));
+const _: () = assert!(matches!(bti_branch(0x3600_0003), b"Jump"));
+
+/// Instruction a call was made with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conduit {
+    /// `smc`, to the secure monitor
+    Smc,
+    /// `hvc`, to the hypervisor
+    Hvc,
+}
+
+/// An SMC or HVC call, following the SMC Calling Convention
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmcInfo {
+    /// Instruction the call was made with
+    pub conduit: Conduit,
+    /// Immediate of the instruction, 0 for SMC Calling Convention calls
+    pub imm: u16,
+    /// Function ID, from w0
+    pub function_id: u32,
+    /// Arguments, from x1 to x6
+    pub args: [u64; 6],
+}
+
+impl SmcInfo {
+    /// Checks whether the call is a fast call, run without being preempted
+    pub const fn is_fast(&self) -> bool {
+        return self.function_id & SMCCC_FAST_CALL != 0;
+    }
+
+    /// Checks whether the call uses the 64-bit convention
+    pub const fn is_64(&self) -> bool {
+        return self.function_id & SMCCC_64 != 0;
+    }
+
+    /// Returns the service the call is for, e.g. [`SMCCC_OWNER_STANDARD`]
+    pub const fn owner(&self) -> u8 {
+        return (self.function_id >> SMCCC_OWNER_SHIFT & SMCCC_OWNER_MASK) as u8;
+    }
+
+    /// Returns the name of the PSCI function called, if it is one
+    pub const fn psci_name(&self) -> Option<&'static [u8]> {
+        let function = (self.function_id & SMCCC_FUNCTION_MASK) as usize;
+
+        if !self.is_fast() || self.owner() != SMCCC_OWNER_STANDARD {
+            return None;
+        }
+        if function >= PSCI_FUNCTIONS.len() {
+            return None;
+        }
+        return Some(PSCI_FUNCTIONS[function]);
+    }
+}
+
+/// Decodes the SMC or HVC call that raised the exception described by
+/// `regs`
+///
+/// Returns `None` if the exception isn't a trapped AArch64 SMC or HVC. Note
+/// that the ELR of a trapped SMC is the SMC itself, while that of an HVC is
+/// the next instruction: emulating an SMC means moving past it.
+pub const fn decode_smc(regs: &Regs) -> Option<SmcInfo> {
+    let conduit = match esr_ec(regs.esr) {
+        EC_SMC64 => Conduit::Smc,
+        EC_HVC64 => Conduit::Hvc,
+        _ => return None,
+    };
+
+    return Some(SmcInfo {
+        conduit,
+        imm: (regs.esr & ESR_IMM16_MASK) as u16,
+        function_id: regs.x0 as u32,
+        args: [regs.x1, regs.x2, regs.x3, regs.x4, regs.x5, regs.x6],
+    });
+}
+
+/// A PSCI SYSTEM_OFF call, `smc #0`, as trapped from the payload
+const TEST_SYSTEM_OFF: Regs = Regs {
+    x0: PSCI_SYSTEM_OFF as u64,
+    x1: 0x1111,
+    esr: (EC_SMC64 as u64) << ESR_EC_SHIFT | 1 << 25,
+    ..Regs::ZERO
+};
+
+const _: () = assert!(matches!(
+    decode_smc(&TEST_SYSTEM_OFF),
+    Some(SmcInfo {
+        conduit: Conduit::Smc,
+        imm: 0,
+        function_id: PSCI_SYSTEM_OFF,
+        args: [0x1111, 0, 0, 0, 0, 0],
+    })
+));
+const _: () = assert!(matches!(
+    decode_smc(&TEST_SYSTEM_OFF),
+    Some(call) if call.is_fast()
+        && !call.is_64()
+        && call.owner() == SMCCC_OWNER_STANDARD
+        && matches!(call.psci_name(), Some(b"SYSTEM_OFF"))
+));
+// The immediate of an HVC, and the upper half of x0 ignored
+const _: () = assert!(matches!(
+    decode_smc(&Regs {
+        x0: 0xffff_ffff_c400_0003,
+        esr: 0x5a00_0000 | 0x42,
+        ..Regs::ZERO
+    }),
+    Some(SmcInfo {
+        conduit: Conduit::Hvc,
+        imm: 0x42,
+        function_id: 0xc400_0003,
+        ..
+    })
+));
+// A data abort isn't a call
+const _: () = assert!(
+    decode_smc(&Regs {
+        esr: 0x9600_0021,
+        ..TEST_SYSTEM_OFF
+    })
+    .is_none()
+);
+
+/// Reads the Fault Address Register of the current exception level
+pub fn read_far() -> u64 {
+    let far: u64;
+
+    unsafe {
+        match cpu::current_el() {
+            1 => asm!("mrs {}, far_el1", out(reg) far),
+            2 => asm!("mrs {}, far_el2", out(reg) far),
+            _ => asm!("mrs {}, far_el3", out(reg) far),
+        }
+    }
+    return far;
+}
+
+/// Registers the callback invoked on alignment faults
+///
+/// Passing `None` removes it; alignment faults are then fatal.
+pub fn set_alignment_handler(handler: Option<AlignmentHandler>) {
+    unsafe {
+        ALIGNMENT_HANDLER = handler;
+    }
+}
+
+/// Gives an alignment fault to the registered callback
+///
+/// Returns `true` if execution can resume.
+fn handle_alignment_fault(regs: &mut Regs) -> bool {
+    if !is_alignment_fault(regs.esr) {
+        return false;
+    }
+
+    match unsafe { ALIGNMENT_HANDLER } {
+        Some(handler) => return handler(regs, read_far()),
+        None => return false,
+    }
+}
+
+/// Registers the callback invoked on data aborts from the current level
+///
+/// Passing `None` removes it; data aborts are then fatal. Returns the
+/// previously registered callback, so a temporary one can be removed by
+/// restoring it.
+pub fn set_data_abort_handler(handler: Option<DataAbortHandler>) -> Option<DataAbortHandler> {
+    unsafe {
+        let previous = DATA_ABORT_HANDLER;
+        DATA_ABORT_HANDLER = handler;
+        return previous;
+    }
+}
+
+/// Registers the callback invoked on SErrors from the current level
+///
+/// Passing `None` removes it; SErrors are then fatal. Returns the previously
+/// registered callback.
+pub fn set_serror_handler(handler: Option<SErrorHandler>) -> Option<SErrorHandler> {
+    unsafe {
+        let previous = SERROR_HANDLER;
+        SERROR_HANDLER = handler;
+        return previous;
+    }
+}
+
+/// Registers the callback invoked on `brk` instructions from the current
+/// level
+///
+/// Passing `None` removes it; a `brk` is then fatal, unless it is the
+/// monitor's. Returns the previously registered callback.
+pub fn set_brk_handler(handler: Option<BrkHandler>) -> Option<BrkHandler> {
+    unsafe {
+        let previous = BRK_HANDLER;
+        BRK_HANDLER = handler;
+        return previous;
+    }
+}
+
+/// Checks whether the FAR is invalid for the abort described by `esr`
+///
+/// Set for some external aborts, whose address isn't known.
+pub fn far_not_valid(esr: u64) -> bool {
+    return esr & ESR_FNV != 0;
+}
+
+/// Gives a data abort to the registered callback
+///
+/// Returns `true` if execution can resume.
+fn handle_data_abort(regs: &mut Regs) -> bool {
+    if esr_ec(regs.esr) != EC_DABT_CUR {
+        return false;
+    }
+
+    match unsafe { DATA_ABORT_HANDLER } {
+        Some(handler) => return handler(regs, read_far()),
+        None => return false,
+    }
+}
+
+/// Gives a `brk` to the registered callback, then moves `regs.elr` past it
+///
+/// Returns `true` if execution can resume.
+fn handle_brk(regs: &mut Regs) -> bool {
+    if esr_ec(regs.esr) != EC_BRK64 {
+        return false;
+    }
+
+    match unsafe { BRK_HANDLER } {
+        Some(handler) => handler((regs.esr & ESR_BRK_IMM_MASK) as u16, regs),
+        None => return false,
+    }
+    // The preferred return address of a brk is the brk itself
+    regs.elr += 4;
+    return true;
+}
+
+/// Gives an SError to the registered callback
+///
+/// Returns `true` if execution can resume.
+fn handle_serror(regs: &mut Regs) -> bool {
+    match unsafe { SERROR_HANDLER } {
+        Some(handler) => return handler(regs),
+        None => return false,
+    }
+}
+
+/// Splits `addr` into the base of its `granule`-sized page and the offset
+/// within it
+///
+/// `granule` must be a power of two.
+pub const fn page_split(addr: u64, granule: usize) -> (u64, u64) {
+    let mask = granule as u64 - 1;
+
+    return (addr & !mask, addr & mask);
+}
+
+/// Checks that [`page_split`] splits `addr` into `page` and `offset`
+const fn page_split_is(addr: u64, granule: usize, page: u64, offset: u64) -> bool {
+    let split = page_split(addr, granule);
+
+    return split.0 == page && split.1 == offset;
+}
+
+const _: () = assert!(page_split_is(0x4000_1234, 4096, 0x4000_1000, 0x234));
+const _: () = assert!(page_split_is(0x4000_1000, 4096, 0x4000_1000, 0));
+const _: () = assert!(page_split_is(0x4000_1fff, 4096, 0x4000_1000, 0xfff));
+const _: () = assert!(page_split_is(0, 4096, 0, 0));
+const _: () = assert!(page_split_is(u64::MAX, 4096, 0xffff_ffff_ffff_f000, 0xfff));
+const _: () = assert!(page_split_is(0x4000_1234, 65536, 0x4000_0000, 0x1234));
+
+/// Writes a fault address with the page it falls in and the offset within
+/// that page, for pages of `granule` bytes (a power of two)
+///
+/// E.g., `Fault address 0x...1234: page 0x...1000 + 0x234 (4 KiB pages)`,
+/// which makes it easier to match the address with a mapped region.
+pub fn write_fault_addr_breakdown(out: &mut dyn Console, far: u64, granule: usize) {
+    let (page, offset) = page_split(far, granule);
+    let mut buf = [0u8; 20];
+
+    out.write(b"Fault address 0x");
+    write_hex_u64(out, far);
+    out.write(b": page 0x");
+    write_hex_u64(out, page);
+    out.write(b" + 0x");
+    out.write(u64_to_hex(offset, &mut buf));
+    out.write(b" (");
+    out.write(u64_to_dec(granule as u64 / 1024, &mut buf));
+    out.writeln(b" KiB pages)");
+}
+
+/// Returns the size in bytes of the data access that caused an abort
+///
+/// Taken from the syndrome when ESR_ELx.ISV is set, otherwise decoded from
+/// the faulting instruction, read from `source`, for the common load/store
+/// forms (single register, pair and exclusive). Returns `None` if neither
+/// tells.
+fn access_size(esr: u64, elr: u64, source: &DumpSource) -> Option<usize> {
+    if esr & ESR_ISV != 0 {
+        return Some(1 << ((esr >> ESR_SAS_SHIFT) & ESR_SAS_MASK));
+    }
+
+    let opcode = (source.read_instr)(elr).ok()?;
+    let size = 1 << (opcode >> 30);
+    // Load/store register, general-purpose registers
+    if opcode & 0x3c00_0000 == 0x3800_0000 {
+        return Some(size);
+    }
+    // Load/store pair, general-purpose registers: 32-bit or 64-bit each
+    if opcode & 0x3c00_0000 == 0x2800_0000 {
+        return Some(2 * if opcode & (1 << 31) != 0 { 8 } else { 4 });
+    }
+    // Load/store exclusive and ordered
+    if opcode & 0x3f00_0000 == 0x0800_0000 {
+        return Some(size);
+    }
+    return None;
+}
+
+/// Writes the details of a data access alignment fault
+///
+/// For example: `  8-byte read at 0x0000000040001004 (misaligned by 4)`.
+fn write_alignment_fault(out: &mut dyn Console, regs: &Regs, source: &DumpSource) {
+    let far = source.far;
+    let size = access_size(regs.esr, regs.elr, source);
+    let mut buf = [0u8; 20];
+
+    out.write(b"  ");
+    match size {
+        Some(size) => {
+            out.write(u64_to_dec(size as u64, &mut buf));
+            out.write(b"-byte ");
+        }
+        None => out.write(b"Unknown-size "),
+    }
+    match regs.esr & ESR_WNR {
+        0 => out.write(b"read at 0x"),
+        _ => out.write(b"write at 0x"),
+    }
+    write_hex_u64(out, far);
+    if let Some(size) = size {
+        out.write(b" (misaligned by ");
+        out.write(u64_to_dec(far % size as u64, &mut buf));
+        out.write(b")");
+    }
+    out.write(b"\n");
+}
+
+/// Writes a short explanation of the fault before the generic dump
+///
+/// Starts with the exception class and its description (e.g.,
+/// `EC=0x25 (Data abort from the current EL)`). Alignment faults are a common
+/// and confusing crash, so they are called out with a one-line message and
+/// their details: the misaligned address and access size for data accesses,
+/// the rule broken for PC and SP alignment faults. Faults caused by branch
+/// protection are named too: the key of a pointer authentication failure,
+/// the branch of a BTI exception, and a trapped pointer authentication
+/// instruction. Data aborts also show the fault address broken down into
+/// page and offset, and trapped SMC and HVC calls their function ID.
+fn write_fault_cause(out: &mut dyn Console, regs: &Regs, source: &DumpSource) {
+    let ec = esr_ec(regs.esr);
+
+    out.write(b"EC=0x");
+    write_hex_u8(out, ec);
+    out.write(b" (");
+    out.write(ec_name(ec).as_bytes());
+    out.writeln(b")");
+    let message: Option<&[u8]> = match ec {
+        _ if is_alignment_fault(regs.esr) => Some(b"Alignment fault at 0x"),
+        EC_PC_ALIGN => Some(b"PC alignment fault at 0x"),
+        EC_SP_ALIGN => Some(b"SP alignment fault at 0x"),
+        EC_PAC_FAIL => Some(b"Pointer authentication failure at 0x"),
+        EC_BTI => Some(b"Branch target exception at 0x"),
+        _ => None,
+    };
+
+    if let Some(message) = message {
+        out.write(message);
+        write_hex_u64(out, regs.elr);
+        out.write(b"\n");
+    }
+    match ec {
+        _ if is_alignment_fault(regs.esr) => write_alignment_fault(out, regs, source),
+        // ELR is the misaligned target itself; FAR only repeats it
+        EC_PC_ALIGN => out.writeln(b"  Branch target is not a multiple of 4"),
+        // No address is involved, FAR is UNKNOWN
+        EC_SP_ALIGN => out.writeln(b"  SP is not a multiple of 16 (FAR not valid)"),
+        EC_PAC_FAIL => {
+            out.write(b"  Authentication with the ");
+            out.write(pac_fail_key(regs.esr));
+            out.writeln(b" key failed: the pointer was corrupted or signed with another key");
+        }
+        EC_BTI => {
+            out.write(b"  ");
+            out.write(bti_branch(regs.esr));
+            out.writeln(b" to an instruction that isn't a BTI landing pad");
+        }
+        EC_PAC_TRAP => {
+            out.writeln(b"  Pointer authentication isn't enabled for the faulting level")
+        }
+        _ => {}
+    }
+    if matches!(ec, EC_IABT_LOW | EC_IABT_CUR | EC_DABT_LOW | EC_DABT_CUR) {
+        write_fault_addr_breakdown(out, source.far, FAULT_GRANULE);
+    }
+    if let Some(call) = decode_smc(regs) {
+        write_smc(out, &call);
+    }
+}
+
+/// Writes a trapped SMC or HVC call: the instruction, the function ID and
+/// its PSCI name if it has one
+fn write_smc(out: &mut dyn Console, call: &SmcInfo) {
+    let mut buf = [0u8; 16];
+
+    match call.conduit {
+        Conduit::Smc => out.write(b"  smc #0x"),
+        Conduit::Hvc => out.write(b"  hvc #0x"),
+    }
+    out.write(u64_to_hex(call.imm as u64, &mut buf));
+    out.write(b", function 0x");
+    out.write(u64_to_hex(call.function_id as u64, &mut buf));
+    if let Some(name) = call.psci_name() {
+        out.write(b" (PSCI ");
+        out.write(name);
+        out.write(b")");
+    }
+    out.write(b"\n");
+}
+
+/// Exception vector tables of the bootloader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorTable {
+    /// Records any exception in the early fault record and spins; used until
+    /// the console is up
+    Early,
+    /// Saves the registers and calls the handlers of this module
+    Full,
+}
+
+/// `EarlyFaultRecord::magic` of a valid record: "ELYFAULT" read as a
+/// little-endian u64
+const EARLY_FAULT_MAGIC: u64 = 0x544c554146594c45;
+
+/// An exception taken through the early vectors
+///
+/// Written by the assembly early vectors, which rely on this layout.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct EarlyFaultRecord {
+    /// [`EARLY_FAULT_MAGIC`] once a fault is recorded
+    magic: u64,
+    /// Index of the vector taken (0 to 15, in table order)
+    kind: u64,
+    /// ESR_ELx of the fault
+    esr: u64,
+    /// ELR_ELx of the fault
+    elr: u64,
+    /// FAR_ELx of the fault
+    far: u64,
+}
+
+/// Fault recorded by the early vectors
+///
+/// Kept out of the loaded image and never cleared at boot, so a fault before
+/// the console is up can be reported after a reset that keeps RAM.
+#[unsafe(export_name = "early_fault_record")]
+#[unsafe(link_section = ".noinit")]
+static mut EARLY_FAULT: EarlyFaultRecord = EarlyFaultRecord {
+    magic: 0,
+    kind: 0,
+    esr: 0,
+    elr: 0,
+    far: 0,
+};
+
+unsafe extern "C" {
+    /// Full vector table (provided by vectors.S)
+    static evt: u8;
+    /// Early vector table (provided by vectors.S)
+    static early_evt: u8;
+    /// Lowest address of the boot stack (provided by the linker script)
+    static boot_stack_limit: u8;
+}
+
+/// Points VBAR_ELx of the current exception level at `table`
+///
+/// Returns the table installed before, if it was one of the bootloader's, so
+/// a temporary switch can be undone.
+pub fn install_vectors(table: VectorTable) -> Option<VectorTable> {
+    let early = &raw const early_evt as u64;
+    let full = &raw const evt as u64;
+    let base = match table {
+        VectorTable::Early => early,
+        VectorTable::Full => full,
+    };
+    let previous: u64;
+
+    unsafe {
+        match cpu::current_el() {
+            1 => asm!("mrs {}, vbar_el1", out(reg) previous),
+            2 => asm!("mrs {}, vbar_el2", out(reg) previous),
+            _ => asm!("mrs {}, vbar_el3", out(reg) previous),
+        }
+        match cpu::current_el() {
+            1 => asm!("msr vbar_el1, {}", in(reg) base),
+            2 => asm!("msr vbar_el2, {}", in(reg) base),
+            _ => asm!("msr vbar_el3, {}", in(reg) base),
+        }
+        asm!("isb");
+    }
+    if previous == early {
+        return Some(VectorTable::Early);
+    }
+    if previous == full {
+        return Some(VectorTable::Full);
+    }
+    return None;
+}
+
+/// Reports a fault recorded by the early vectors, then clears the record
+///
+/// Returns whether there was one.
+pub fn report_early_fault() -> bool {
+    let record = &raw mut EARLY_FAULT;
+    let record = unsafe { &mut *record };
+
+    if record.magic != EARLY_FAULT_MAGIC {
+        return false;
+    }
+
+    pl011::print(b"Early fault captured before console: vector ");
+    pl011::print(u64_to_dec(record.kind, &mut [0u8; 20]));
+    pl011::print(b", EC=0x");
+    print_hex_u8(esr_ec(record.esr));
+    pl011::print(b" (");
+    pl011::print(ec_name(esr_ec(record.esr)).as_bytes());
+    pl011::println(b")");
+    for (name, value) in [(b"esr", record.esr), (b"elr", record.elr), (b"far", record.far)] {
+        pl011::print(name);
+        pl011::print(b": 0x");
+        print_hex_u64(value);
+        pl011::print(b"\n");
+    }
+    record.magic = 0;
+    return true;
+}
+
+/// Switches from the early to the full vectors once the console is up
+///
+/// Called by the assembly entry code after the board setup. A fault the
+/// early vectors recorded on a previous boot is reported first.
+#[unsafe(no_mangle)]
+pub extern "C" fn init_exceptions() {
+    install_vectors(VectorTable::Full);
+    report_early_fault();
+}
+
 /// CPU register state at the time of an exception
 ///
 /// This struct captures all general-purpose registers (x0-x30) and special
@@ -27,44 +930,83 @@ use crate::drivers::uart::pl011;
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Regs {
-    x0: u64,
-    x1: u64,
-    x2: u64,
-    x3: u64,
-    x4: u64,
-    x5: u64,
-    x6: u64,
-    x7: u64,
-    x8: u64,
-    x9: u64,
-    x10: u64,
-    x11: u64,
-    x12: u64,
-    x13: u64,
-    x14: u64,
-    x15: u64,
-    x16: u64,
-    x17: u64,
-    x18: u64,
-    x19: u64,
-    x20: u64,
-    x21: u64,
-    x22: u64,
-    x23: u64,
-    x24: u64,
-    x25: u64,
-    x26: u64,
-    x27: u64,
-    x28: u64,
-    x29: u64,
-    x30: u64,
-    esr: u64,
-    elr: u64,
-    spsr: u64,
-    zr: u64,
+    pub x0: u64,
+    pub x1: u64,
+    pub x2: u64,
+    pub x3: u64,
+    pub x4: u64,
+    pub x5: u64,
+    pub x6: u64,
+    pub x7: u64,
+    pub x8: u64,
+    pub x9: u64,
+    pub x10: u64,
+    pub x11: u64,
+    pub x12: u64,
+    pub x13: u64,
+    pub x14: u64,
+    pub x15: u64,
+    pub x16: u64,
+    pub x17: u64,
+    pub x18: u64,
+    pub x19: u64,
+    pub x20: u64,
+    pub x21: u64,
+    pub x22: u64,
+    pub x23: u64,
+    pub x24: u64,
+    pub x25: u64,
+    pub x26: u64,
+    pub x27: u64,
+    pub x28: u64,
+    pub x29: u64,
+    pub x30: u64,
+    pub esr: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub zr: u64,
 }
 
 impl Regs {
+    /// All registers zero
+    pub const ZERO: Regs = Regs {
+        x0: 0,
+        x1: 0,
+        x2: 0,
+        x3: 0,
+        x4: 0,
+        x5: 0,
+        x6: 0,
+        x7: 0,
+        x8: 0,
+        x9: 0,
+        x10: 0,
+        x11: 0,
+        x12: 0,
+        x13: 0,
+        x14: 0,
+        x15: 0,
+        x16: 0,
+        x17: 0,
+        x18: 0,
+        x19: 0,
+        x20: 0,
+        x21: 0,
+        x22: 0,
+        x23: 0,
+        x24: 0,
+        x25: 0,
+        x26: 0,
+        x27: 0,
+        x28: 0,
+        x29: 0,
+        x30: 0,
+        esr: 0,
+        elr: 0,
+        spsr: 0,
+        zr: 0,
+    };
+
     /// Register names for iteration
     const NAMES: [&'static str; 35] = [
         "x0 ", "x1 ", "x2 ", "x3 ", "x4 ", "x5 ", "x6 ", "x7 ", "x8 ", "x9 ", "x10", "x11", "x12",
@@ -73,7 +1015,7 @@ impl Regs {
     ];
 
     /// Convert registers to an array for easy iteration
-    pub fn as_array(&self) -> [u64; 35] {
+    pub const fn as_array(&self) -> [u64; 35] {
         [
             self.x0, self.x1, self.x2, self.x3, self.x4, self.x5, self.x6, self.x7, self.x8,
             self.x9, self.x10, self.x11, self.x12, self.x13, self.x14, self.x15, self.x16,
@@ -93,75 +1035,606 @@ impl Regs {
 
     /// Print all registers to UART
     pub fn print(&self) {
-        pl011::println(b"\nRegisters:");
+        self.write(&mut Uart);
+    }
+
+    /// Writes all registers to `out`, as [`Regs::print`] prints them
+    pub fn write(&self, out: &mut dyn Console) {
+        out.writeln(b"\nRegisters:");
         for (name, value) in self.iter() {
-            pl011::print(name.as_bytes());
-            pl011::print(b": 0x");
-            print_hex_u64(value);
-            pl011::print(b"\n");
+            out.write(name.as_bytes());
+            out.write(b": 0x");
+            write_hex_u64(out, value);
+            out.write(b"\n");
         }
     }
 }
 
-/// Prints the faulting instruction at the exception address
+// Layout shared with the entry code of vectors.S and [`capture_context`]
+const _: () = assert!(mem::size_of::<Regs>() == 280);
+const _: () = assert!(offset_of!(Regs, x30) == 240);
+const _: () = assert!(offset_of!(Regs, elr) == 256);
+const _: () = assert!(offset_of!(Regs, spsr) == 264);
+
+/// Size of the register frame the entry code of vectors.S pushes, so the
+/// stack pointer of the interrupted code is right above a saved [`Regs`]
+const REGS_FRAME_SIZE: u64 = 288;
+
+/// Register state recorded at a known point, printed if a panic follows
+///
+/// A panic from normal code has no exception frame to dump, so the boot
+/// records its registers at key points with [`capture_context`], and the
+/// exception handlers record the interrupted registers while they run. The
+/// panic handler prints the last one recorded (see [`last_context`]).
+#[derive(Clone, Copy, Debug)]
+pub struct Context {
+    /// Where the registers were recorded
+    pub label: &'static [u8],
+    /// General-purpose registers; `elr` holds the program counter and
+    /// `spsr` the processor state, as for an exception
+    pub regs: Regs,
+    /// Stack pointer
+    pub sp: u64,
+}
+
+impl Context {
+    /// Returns the context of the code interrupted by an exception taken
+    /// on the current exception level, whose registers are `regs`
+    fn trapped(label: &'static [u8], regs: &Regs) -> Context {
+        return Context {
+            label,
+            regs: *regs,
+            sp: regs as *const Regs as u64 + REGS_FRAME_SIZE,
+        };
+    }
+
+    /// Prints the label, the registers and the stack pointer
+    pub fn print(&self) {
+        pl011::print(b"Last known context: ");
+        pl011::println(self.label);
+        self.regs.print();
+        pl011::print(b"sp : 0x");
+        print_hex_u64(self.sp);
+        pl011::print(b"\n");
+    }
+}
+
+/// Context recorded last, see [`Context`]
+static mut LAST_CONTEXT: Option<Context> = None;
+
+/// Records `context` as the last known one, or forgets it with `None`
+///
+/// Returns the context recorded before, so a temporary one can be undone by
+/// restoring it.
+pub fn set_context(context: Option<Context>) -> Option<Context> {
+    let last = &raw mut LAST_CONTEXT;
+
+    unsafe {
+        let previous = *last;
+        *last = context;
+        return previous;
+    }
+}
+
+/// Returns the context recorded last, if any
+pub fn last_context() -> Option<Context> {
+    let last = &raw const LAST_CONTEXT;
+
+    return unsafe { *last };
+}
+
+/// Records the current registers as the last known context, named `label`
 ///
-/// Reads and displays the 32-bit instruction at the address stored in the
-/// Exception Link Register (ELR), which points to the instruction that
-/// caused the exception.
-fn print_faulting_instr(elr: u64) {
-    let opcode: u32;
-    let addr = (elr & !3) as *const u32;
+/// Inlined so the registers are those of the caller: x29 and x30 are its
+/// frame pointer and link register, `elr` the address of the capture and
+/// `spsr` the current NZCV, DAIF, exception level and stack pointer
+/// selection in the SPSR layout. x16 and x17 are used by the capture itself.
+#[inline(always)]
+pub fn capture_context(label: &'static [u8]) {
+    let mut regs = Regs::ZERO;
+    let sp: u64;
 
-    pl011::print(b"Faulting instruction at 0x");
-    print_hex_u64(elr);
-    pl011::print(b": ");
     unsafe {
-        opcode = addr.read_volatile();
+        asm!(
+            "stp x0, x1, [x16, #0]",
+            "stp x2, x3, [x16, #16]",
+            "stp x4, x5, [x16, #32]",
+            "stp x6, x7, [x16, #48]",
+            "stp x8, x9, [x16, #64]",
+            "stp x10, x11, [x16, #80]",
+            "stp x12, x13, [x16, #96]",
+            "stp x14, x15, [x16, #112]",
+            "stp x16, x17, [x16, #128]",
+            "stp x18, x19, [x16, #144]",
+            "stp x20, x21, [x16, #160]",
+            "stp x22, x23, [x16, #176]",
+            "stp x24, x25, [x16, #192]",
+            "stp x26, x27, [x16, #208]",
+            "stp x28, x29, [x16, #224]",
+            "str x30, [x16, #240]",
+            "adr x17, .",
+            "str x17, [x16, #256]",
+            "mrs x17, nzcv",
+            "mrs {tmp}, daif",
+            "orr x17, x17, {tmp}",
+            "mrs {tmp}, CurrentEL",
+            "orr x17, x17, {tmp}",
+            "mrs {tmp}, SPSel",
+            "orr x17, x17, {tmp}",
+            "str x17, [x16, #264]",
+            "mov {sp}, sp",
+            in("x16") &raw mut regs,
+            out("x17") _,
+            tmp = out(reg) _,
+            sp = out(reg) sp,
+            options(nostack),
+        );
+    }
+    set_context(Some(Context { label, regs, sp }));
+}
+
+/// Caller-saved register state saved by the lightweight IRQ entry
+///
+/// With the `fast-irq` feature, the IRQ vector of the current exception
+/// level saves only the registers a Rust handler may clobber: x0-x18 and
+/// the link register. The callee-saved x19-x29 are preserved by the handler
+/// itself, and ESR/ELR/SPSR stay in the system registers as long as the
+/// handler doesn't take another exception. This roughly halves the
+/// save/restore work for frequent interrupts, at the cost of handlers
+/// seeing (and being able to change) only this subset. Synchronous
+/// exceptions always save the full [`Regs`].
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct FastRegs {
+    pub x0: u64,
+    pub x1: u64,
+    pub x2: u64,
+    pub x3: u64,
+    pub x4: u64,
+    pub x5: u64,
+    pub x6: u64,
+    pub x7: u64,
+    pub x8: u64,
+    pub x9: u64,
+    pub x10: u64,
+    pub x11: u64,
+    pub x12: u64,
+    pub x13: u64,
+    pub x14: u64,
+    pub x15: u64,
+    pub x16: u64,
+    pub x17: u64,
+    pub x18: u64,
+    pub x30: u64,
+}
+
+// Layout shared with the fast_irq_entry macro of vectors.S
+const _: () = assert!(mem::size_of::<FastRegs>() == 160);
+const _: () = assert!(offset_of!(FastRegs, x0) == 0);
+const _: () = assert!(offset_of!(FastRegs, x18) == 144);
+const _: () = assert!(offset_of!(FastRegs, x30) == 152);
+
+impl FastRegs {
+    /// Returns the full register state for a diagnostic dump
+    ///
+    /// ESR, ELR and SPSR are read from the system registers; the registers
+    /// the fast entry doesn't save read as 0.
+    pub fn to_regs(&self) -> Regs {
+        let (esr, elr, spsr): (u64, u64, u64);
+
+        unsafe {
+            match cpu::current_el() {
+                1 => asm!(
+                    "mrs {}, esr_el1",
+                    "mrs {}, elr_el1",
+                    "mrs {}, spsr_el1",
+                    out(reg) esr,
+                    out(reg) elr,
+                    out(reg) spsr
+                ),
+                2 => asm!(
+                    "mrs {}, esr_el2",
+                    "mrs {}, elr_el2",
+                    "mrs {}, spsr_el2",
+                    out(reg) esr,
+                    out(reg) elr,
+                    out(reg) spsr
+                ),
+                _ => asm!(
+                    "mrs {}, esr_el3",
+                    "mrs {}, elr_el3",
+                    "mrs {}, spsr_el3",
+                    out(reg) esr,
+                    out(reg) elr,
+                    out(reg) spsr
+                ),
+            }
+        }
+        return self.with_syndrome(esr, elr, spsr);
+    }
+
+    /// Returns the full register state with the given syndrome, exception
+    /// return address and saved PSTATE
+    ///
+    /// The registers the fast entry doesn't save read as 0.
+    pub const fn with_syndrome(&self, esr: u64, elr: u64, spsr: u64) -> Regs {
+        return Regs {
+            x0: self.x0,
+            x1: self.x1,
+            x2: self.x2,
+            x3: self.x3,
+            x4: self.x4,
+            x5: self.x5,
+            x6: self.x6,
+            x7: self.x7,
+            x8: self.x8,
+            x9: self.x9,
+            x10: self.x10,
+            x11: self.x11,
+            x12: self.x12,
+            x13: self.x13,
+            x14: self.x14,
+            x15: self.x15,
+            x16: self.x16,
+            x17: self.x17,
+            x18: self.x18,
+            x19: 0,
+            x20: 0,
+            x21: 0,
+            x22: 0,
+            x23: 0,
+            x24: 0,
+            x25: 0,
+            x26: 0,
+            x27: 0,
+            x28: 0,
+            x29: 0,
+            x30: self.x30,
+            esr,
+            elr,
+            spsr,
+            zr: 0,
+        };
+    }
+}
+
+// The saved subset reaches the handler's registers, the rest read as 0
+const _: () = {
+    let fast = FastRegs {
+        x0: 1,
+        x1: 2,
+        x2: 3,
+        x3: 4,
+        x4: 5,
+        x5: 6,
+        x6: 7,
+        x7: 8,
+        x8: 9,
+        x9: 10,
+        x10: 11,
+        x11: 12,
+        x12: 13,
+        x13: 14,
+        x14: 15,
+        x15: 16,
+        x16: 17,
+        x17: 18,
+        x18: 19,
+        x30: 31,
+    };
+    let regs = fast.with_syndrome(0x5600_0000, 0x4008_0000, 0x3c5);
+    let regs = regs.as_array();
+    let mut i = 0;
+
+    while i <= 30 {
+        let expected = match i {
+            0..=18 | 30 => i as u64 + 1,
+            _ => 0,
+        };
+        assert!(regs[i] == expected);
+        i += 1;
     }
+    assert!(regs[31] == 0x5600_0000 && regs[32] == 0x4008_0000 && regs[33] == 0x3c5);
+    assert!(regs[34] == 0);
+};
+
+/// Checks whether the code at `addr` can be read for a diagnostic
+///
+/// Only RAM and the bootloader image are read, as a wild ELR may point at
+/// unmapped or device memory. Returns why the read is refused otherwise.
+fn check_readable(addr: usize, len: usize) -> Result<(), &'static [u8]> {
+    let (start, end) = boot::bootloader_extents();
+
+    if addr >= start && addr.saturating_add(len) <= end {
+        return Ok(());
+    }
+    if map::is_ram_range(addr, len) {
+        return Ok(());
+    }
+    return Err(b"not in RAM");
+}
+
+/// Reads the instruction at `elr`
+///
+/// The read is checked with [`check_readable`] and done through
+/// [`mmio::probe_read32`]; returns why the instruction can't be read
+/// otherwise.
+fn read_instr(elr: u64) -> Result<u32, &'static [u8]> {
+    let addr = (elr & !3) as usize;
+
+    check_readable(addr, 4)?;
+    return match mmio::probe_read32(addr, 0) {
+        Ok(opcode) => Ok(opcode),
+        Err(ProbeError::DecodeError) => Err(b"read aborted"),
+        Err(ProbeError::Timeout) => Err(b"read timed out"),
+        Err(ProbeError::Busy) => Err(b"fault during a probe"),
+    };
+}
+
+/// State an exception dump reads besides the saved registers
+///
+/// [`DumpSource::live`] reads it from the running core. A dump of
+/// synthetic registers is given a fixed one instead, with a fake reader of
+/// the faulting instruction, so its output is the same on every run.
+#[derive(Clone, Copy)]
+pub struct DumpSource {
+    /// Current exception level
+    pub current_el: u8,
+    /// Fault Address Register of the exception
+    pub far: u64,
+    /// Reads the instruction at an address, or returns why it can't
+    pub read_instr: fn(u64) -> Result<u32, &'static [u8]>,
+}
+
+impl DumpSource {
+    /// Returns the state of the running core, whose instructions are only
+    /// read from RAM or the bootloader image
+    pub fn live() -> DumpSource {
+        return DumpSource {
+            current_el: cpu::current_el(),
+            far: read_far(),
+            read_instr,
+        };
+    }
+}
+
+/// Writes the faulting instruction at the exception address
+///
+/// Writes the 32-bit instruction at the address stored in the Exception
+/// Link Register (ELR), which points to the instruction that caused the
+/// exception, followed by its disassembly. The instruction is read from
+/// `source`; on the core that read is checked (see [`read_instr`]), so a
+/// wild ELR writes `<instruction unreadable>` and the reason instead of
+/// faulting again before the register dump.
+fn write_faulting_instr(out: &mut dyn Console, elr: u64, source: &DumpSource) {
+    out.write(b"Faulting instruction at 0x");
+    write_hex_u64(out, elr);
+    out.write(b": ");
+    let opcode = match (source.read_instr)(elr) {
+        Ok(opcode) => opcode,
+        Err(reason) => {
+            out.write(b"<instruction unreadable> (");
+            out.write(reason);
+            out.writeln(b")");
+            return;
+        }
+    };
 
     for i in 0..4 {
         if i == 0 {
-            pl011::print(b"[");
-            print_hex_u8((opcode >> (i * 8)) as u8);
-            pl011::print(b"]")
+            out.write(b"[");
+            write_hex_u8(out, (opcode >> (i * 8)) as u8);
+            out.write(b"]")
         } else {
-            print_hex_u8((opcode >> (i * 8)) as u8);
+            write_hex_u8(out, (opcode >> (i * 8)) as u8);
         }
 
         if i < 3 {
-            pl011::print(b" ");
+            out.write(b" ");
         }
     }
 
-    pl011::print(b"\n");
+    out.write(b"  ");
+    out.write(disasm::disassemble(opcode, elr & !3, &mut [0u8; DISASM_BUF_SIZE]));
+    out.write(b"\n");
 }
 
-/// Prints all CPU registers from the saved register state
-fn print_regs(regs: *const Regs) {
-    // Print register dump
-    unsafe {
-        if let Some(regs) = regs.as_ref() {
-            regs.print();
+/// Number of instructions before the faulting one in the dump
+const DUMP_WINDOW_BEFORE: usize = 4;
+/// Number of instructions after the faulting one in the dump
+const DUMP_WINDOW_AFTER: usize = 2;
+
+/// Writes the instruction at `pc`, with `before` instructions before it and
+/// `after` instructions after it, one per line
+///
+/// Each line has the address, the opcode and the disassembly; the line of
+/// `pc` is marked with an arrow:
+///
+/// ```text
+///    0x0000000040080008: f2e00020  movk x0, #0x1, lsl #48
+/// => 0x000000004008000c: f9400000  ldr x0, [x0]
+///    0x0000000040080010: d65f03c0  ret
+/// ```
+///
+/// Instructions are read from `source`; one that can't be read writes
+/// `<unreadable>` and the reason instead. The window stops at the ends of
+/// the address space.
+pub fn write_instructions(
+    out: &mut dyn Console,
+    pc: u64,
+    before: usize,
+    after: usize,
+    source: &DumpSource,
+) {
+    let pc = pc & !3;
+    let first = pc - (before as u64).min(pc / 4) * 4;
+    let last = pc.saturating_add((after as u64).min((u64::MAX - pc) / 4) * 4);
+    let mut hex = [0u8; 8];
+    let mut text = [0u8; DISASM_BUF_SIZE];
+    let mut addr = first;
+
+    loop {
+        match addr == pc {
+            true => out.write(b"=> 0x"),
+            false => out.write(b"   0x"),
+        }
+        write_hex_u64(out, addr);
+        out.write(b": ");
+        match (source.read_instr)(addr) {
+            Ok(opcode) => {
+                out.write(u64_to_hex_padded(opcode as u64, &mut hex));
+                out.write(b"  ");
+                out.writeln(disasm::disassemble(opcode, addr, &mut text));
+            }
+            Err(reason) => {
+                out.write(b"<unreadable> (");
+                out.write(reason);
+                out.writeln(b")");
+            }
         }
+        if addr == last {
+            return;
+        }
+        addr += 4;
+    }
+}
+
+/// Prints the instructions around `pc` to the UART, see
+/// [`write_instructions`]
+///
+/// Only RAM and the bootloader image are read, with a bus probe, so a
+/// window reaching into unmapped or device memory prints `<unreadable>`
+/// lines instead of faulting.
+pub fn dump_instructions(pc: u64, before: usize, after: usize) {
+    write_instructions(&mut Uart, pc, before, after, &DumpSource::live());
+}
+
+/// Writes a summary of the PSTATE saved in `spsr`
+///
+/// Set flags are shown in upper case, e.g. `PSTATE: nZCv DaIF EL2h`.
+fn write_pstate(out: &mut dyn Console, spsr: u64) {
+    let flag = |bit: u32, c: u8| {
+        if spsr & (1 << bit) != 0 {
+            return c;
+        }
+        return c.to_ascii_lowercase();
+    };
+    let nzcv = [flag(31, b'N'), flag(30, b'Z'), flag(29, b'C'), flag(28, b'V')];
+    let daif = [flag(9, b'D'), flag(8, b'A'), flag(7, b'I'), flag(6, b'F')];
+
+    out.write(b"PSTATE: ");
+    out.write(&nzcv);
+    out.write(b" ");
+    out.write(&daif);
+    if spsr & SPSR_M_AARCH32 != 0 {
+        out.writeln(b" AArch32");
+        return;
+    }
+    out.write(b" EL");
+    out.write(u64_to_dec((spsr >> 2) & 0x3, &mut [0u8; 20]));
+    if spsr & SPSR_M_SP_ELX != 0 {
+        out.writeln(b"h");
+    } else {
+        out.writeln(b"t");
     }
 }
 
+/// Writes the whole context of an exception in one consistent format
+///
+/// Writes the handler `name`, the current exception level, the decoded
+/// exception class with the fault address where one applies, the faulting
+/// instruction and, if it could be read, the code around it, the register
+/// block and a summary of the saved PSTATE. What isn't in `regs` is taken
+/// from `source`.
+///
+/// The format is checked byte for byte against the files of
+/// `tests/golden` by the `dump-golden` self-test.
+pub fn write_exception(out: &mut dyn Console, name: &[u8], regs: &Regs, source: &DumpSource) {
+    out.writeln(name);
+    out.write(b"CurrentEL: ");
+    out.writeln(u64_to_dec(source.current_el as u64, &mut [0u8; 20]));
+    write_fault_cause(out, regs, source);
+    write_faulting_instr(out, regs.elr, source);
+    if (source.read_instr)(regs.elr).is_ok() {
+        out.writeln(b"Code:");
+        write_instructions(out, regs.elr, DUMP_WINDOW_BEFORE, DUMP_WINDOW_AFTER, source);
+    }
+    regs.write(out);
+    write_pstate(out, regs.spsr);
+}
+
+/// Prints the whole context of an exception in one consistent format
+///
+/// See [`write_exception`]; the state of the core is read with
+/// [`DumpSource::live`]. The dump also goes to the debug channel, see
+/// [`log::to_all_sinks`].
+pub fn dump_exception(name: &[u8], regs: &Regs) {
+    let source = DumpSource::live();
+
+    log::to_all_sinks(|| write_exception(&mut Uart, name, regs, &source));
+}
+
+/// Ends a fatal exception: blinks the heartbeat fast and panics
+///
+/// The syndrome and address of the exception are kept in the boot record,
+/// next to the panic location, for the next boot to report.
+fn fatal(regs: &Regs) -> ! {
+    // The registers were just dumped
+    set_context(None);
+    bootreason::record_exception(regs.esr, regs.elr);
+    heartbeat::pattern(Pattern::FastBlink);
+    panic!();
+}
+
+/// Whether [`do_bad_stack`] was entered, to stop if the dump itself faults
+static mut BAD_STACK_TAKEN: bool = false;
+
+/// Handles an exception taken with an unusable stack pointer
+///
+/// Called by the entry code of the current exception level, on the
+/// exception stack, when `sp` was misaligned or the register frame would
+/// have gone past the bottom of the boot stack (an overflow). Prints the
+/// dump and why `sp` was refused, then panics. A second bad stack pointer,
+/// from a fault while handling the first, stops the core without printing.
+#[unsafe(no_mangle)]
+pub extern "C" fn do_bad_stack(regs: &mut Regs, sp: u64) -> ! {
+    let limit = &raw const boot_stack_limit as u64;
+    let mut buf = [0u8; 16];
+
+    if unsafe { BAD_STACK_TAKEN } {
+        loop {
+            unsafe {
+                asm!("wfe", options(nomem, nostack));
+            }
+        }
+    }
+    unsafe {
+        BAD_STACK_TAKEN = true;
+    }
+    dump_exception(b"Bad stack pointer in exception handler", regs);
+    log::to_all_sinks(|| {
+        pl011::print(b"SP: 0x");
+        pl011::print(u64_to_hex(sp, &mut buf));
+        if !sp.is_multiple_of(16) {
+            pl011::println(b" (not a multiple of 16)");
+        } else {
+            pl011::print(b" (stack overflow, limit 0x");
+            pl011::print(u64_to_hex(limit, &mut buf));
+            pl011::println(b")");
+        }
+    });
+    fatal(regs);
+}
+
 /// Handles synchronous exceptions from an unexpected exception level
 ///
 /// This "bad mode" handler is called when a synchronous exception occurs
 /// from an exception level that should not normally generate exceptions.
 /// It prints diagnostic information and panics.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_bad_sync(regs: *const Regs) -> ! {
-    let elr;
-
-    pl011::println(b"Bad mode in Synchronous Exception handler");
-    unsafe {
-        elr = (&*regs).elr;
-    }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+pub extern "C" fn do_bad_sync(regs: &mut Regs) -> ! {
+    dump_exception(b"Bad mode in Synchronous Exception handler", regs);
+    fatal(regs);
 }
 
 /// Handles IRQ (Interrupt Request) from an unexpected exception level
@@ -170,16 +1643,9 @@ pub extern "C" fn do_bad_sync(regs: *const Regs) -> ! {
 /// level that should not normally generate interrupts. It prints diagnostic
 /// information and panics.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_bad_irq(regs: *const Regs) -> ! {
-    let elr;
-
-    pl011::println(b"Bad mode in IRQ handler");
-    unsafe {
-        elr = (&*regs).elr;
-    }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+pub extern "C" fn do_bad_irq(regs: &mut Regs) -> ! {
+    dump_exception(b"Bad mode in IRQ handler", regs);
+    fatal(regs);
 }
 
 /// Handles FIQ (Fast Interrupt Request) from an unexpected exception level
@@ -188,16 +1654,9 @@ pub extern "C" fn do_bad_irq(regs: *const Regs) -> ! {
 /// level that should not normally generate fast interrupts. It prints
 /// diagnostic information and panics.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_bad_fiq(regs: *const Regs) -> ! {
-    let elr;
-
-    pl011::println(b"Bad mode in FIQ handler");
-    unsafe {
-        elr = (&*regs).elr;
-    }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+pub extern "C" fn do_bad_fiq(regs: &mut Regs) -> ! {
+    dump_exception(b"Bad mode in FIQ handler", regs);
+    fatal(regs);
 }
 
 /// Handles SError (System Error) from an unexpected exception level
@@ -206,51 +1665,78 @@ pub extern "C" fn do_bad_fiq(regs: *const Regs) -> ! {
 /// exception level that should not normally generate SErrors. It prints
 /// diagnostic information and panics.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_bad_serror(regs: *const Regs) -> ! {
-    let elr;
-
-    pl011::println(b"Bad mode in SError handler");
-    unsafe {
-        elr = (&*regs).elr;
-    }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+pub extern "C" fn do_bad_serror(regs: &mut Regs) -> ! {
+    dump_exception(b"Bad mode in SError handler", regs);
+    fatal(regs);
 }
 
 /// Handles synchronous exceptions from the current exception level
 ///
 /// Called when a synchronous exception occurs (e.g., undefined instruction,
-/// data abort, etc.). Prints diagnostic information including the faulting
-/// instruction and register state, then panics.
+/// data abort, etc.). The monitor `brk`, debug exceptions handled by the
+/// [`debug`] module and other `brk`s, alignment faults and data aborts
+/// handled by the registered callbacks resume execution; anything else
+/// prints diagnostic
+/// information including the faulting instruction and register state, then
+/// panics. While it runs, the interrupted registers are the last known
+/// [`Context`], for a panic in a handler to print.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_sync(regs: *const Regs) -> ! {
-    let elr;
+pub extern "C" fn do_sync(regs: &mut Regs) {
+    let previous = set_context(Some(Context::trapped(b"synchronous exception", regs)));
 
-    pl011::println(b"Synchronous Exception handler");
-    unsafe {
-        elr = (&*regs).elr;
+    #[cfg(not(feature = "stage1"))]
+    if monitor::handle_brk(regs) {
+        set_context(previous);
+        return;
     }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+    if debug::handle_debug_exception(regs)
+        || handle_brk(regs)
+        || handle_alignment_fault(regs)
+        || handle_data_abort(regs)
+    {
+        set_context(previous);
+        return;
+    }
+
+    dump_exception(b"Synchronous Exception handler", regs);
+    fatal(regs);
 }
 
 /// Handles IRQ (Interrupt Request) from the current exception level
 ///
-/// Called when an interrupt request is received. Prints diagnostic
-/// information and panics (as interrupt handling is not yet implemented).
+/// Called when an interrupt request is received. Interrupts enabled by the
+/// [`interrupt`] module are dispatched there; any other IRQ prints diagnostic
+/// information and panics. Like [`do_sync`], it records the interrupted
+/// registers as the last known [`Context`] while it runs.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_irq(regs: *const Regs) -> ! {
-    let elr;
+pub extern "C" fn do_irq(regs: &mut Regs) {
+    let previous = set_context(Some(Context::trapped(b"IRQ", regs)));
 
-    pl011::println(b"IRQ handler");
-    unsafe {
-        elr = (&*regs).elr;
+    if interrupt::handle() {
+        set_context(previous);
+        return;
     }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+
+    dump_exception(b"IRQ handler", regs);
+    fatal(regs);
+}
+
+/// Handles IRQ from the current exception level through the fast entry
+///
+/// Used instead of [`do_irq`] with the `fast-irq` feature. Interrupts enabled
+/// by the [`interrupt`] module are dispatched there; any other IRQ prints
+/// the saved subset of the registers and panics.
+#[cfg(feature = "fast-irq")]
+#[unsafe(no_mangle)]
+pub extern "C" fn do_irq_fast(regs: &mut FastRegs) {
+    if interrupt::handle() {
+        return;
+    }
+
+    let regs = regs.to_regs();
+
+    dump_exception(b"IRQ handler (fast entry)", &regs);
+    fatal(&regs);
 }
 
 /// Handles FIQ (Fast Interrupt Request) from the current exception level
@@ -258,31 +1744,458 @@ pub extern "C" fn do_irq(regs: *const Regs) -> ! {
 /// Called when a fast interrupt request is received. Prints diagnostic
 /// information and panics (as FIQ handling is not yet implemented).
 #[unsafe(no_mangle)]
-pub extern "C" fn do_fiq(regs: *const Regs) -> ! {
-    let elr;
-
-    pl011::println(b"FIQ handler");
-    unsafe {
-        elr = (&*regs).elr;
-    }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+pub extern "C" fn do_fiq(regs: &mut Regs) -> ! {
+    dump_exception(b"FIQ handler", regs);
+    fatal(regs);
 }
 
 /// Handles SError (System Error) from the current exception level
 ///
 /// Called when a system error occurs (e.g., asynchronous external abort).
+/// An SError expected by the registered callback resumes execution; any
+/// other prints diagnostic information and panics.
+#[unsafe(no_mangle)]
+pub extern "C" fn do_serror(regs: &mut Regs) {
+    if handle_serror(regs) {
+        return;
+    }
+
+    dump_exception(b"SError handler", regs);
+    fatal(regs);
+}
+
+/// Handles synchronous exceptions from a lower exception level
+///
+/// Called when the payload traps to the bootloader (e.g., a debug exception
+/// routed to EL2). The monitor `brk`, debug exceptions handled by the
+/// [`debug`] module, PSCI calls answered by the [`psci`] responder and
+/// alignment faults handled by the registered callback resume the payload;
+/// anything else prints diagnostic information and panics.
+#[unsafe(no_mangle)]
+pub extern "C" fn do_lower_sync(regs: &mut Regs) {
+    #[cfg(not(feature = "stage1"))]
+    if monitor::handle_brk(regs) {
+        return;
+    }
+    if debug::handle_debug_exception(regs)
+        || psci::handle_call(regs)
+        || handle_alignment_fault(regs)
+    {
+        return;
+    }
+
+    dump_exception(b"Lower EL Synchronous Exception handler", regs);
+    fatal(regs);
+}
+
+/// Handles IRQ (Interrupt Request) routed from a lower exception level
+///
+/// Prints diagnostic information and panics (as interrupt handling is not
+/// yet implemented).
+#[unsafe(no_mangle)]
+pub extern "C" fn do_lower_irq(regs: &mut Regs) -> ! {
+    dump_exception(b"Lower EL IRQ handler", regs);
+    fatal(regs);
+}
+
+/// Handles FIQ (Fast Interrupt Request) routed from a lower exception level
+///
+/// Prints diagnostic information and panics (as FIQ handling is not yet
+/// implemented).
+#[unsafe(no_mangle)]
+pub extern "C" fn do_lower_fiq(regs: &mut Regs) -> ! {
+    dump_exception(b"Lower EL FIQ handler", regs);
+    fatal(regs);
+}
+
+/// Handles SError (System Error) routed from a lower exception level
+///
 /// Prints diagnostic information and panics.
 #[unsafe(no_mangle)]
-pub extern "C" fn do_serror(regs: *const Regs) -> ! {
-    let elr;
+pub extern "C" fn do_lower_serror(regs: &mut Regs) -> ! {
+    dump_exception(b"Lower EL SError handler", regs);
+    fatal(regs);
+}
+
+/// Self-test: a captured context holds the current stack pointer, frame
+/// pointer and processor state, and the previous context is restored
+fn selftest_context() -> Outcome {
+    let previous = last_context();
+    let (sp, fp): (u64, u64);
 
-    pl011::println(b"SError handler");
+    capture_context(b"selftest");
     unsafe {
-        elr = (&*regs).elr;
+        asm!("mov {}, sp", "mov {}, x29", out(reg) sp, out(reg) fp, options(nomem, nostack));
     }
-    print_faulting_instr(elr);
-    print_regs(regs);
-    panic!();
+    let captured = last_context();
+    set_context(previous);
+
+    let Some(context) = captured else {
+        return Outcome::Fail;
+    };
+    let el = (context.regs.spsr >> 2) & 0x3;
+    if context.label != b"selftest"
+        || context.sp != sp
+        || context.regs.x29 != fp
+        || el != cpu::current_el() as u64
+        || context.regs.spsr & SPSR_M_SP_ELX == 0
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Address of [`GOLDEN_CODE`] in the fake memory of the self-tests
+const GOLDEN_CODE_BASE: u64 = 0x4008_0000;
+/// Code in the fake memory of the self-tests
+const GOLDEN_CODE: [u32; 8] = [
+    0xd100_83ff, // sub sp, sp, #32
+    0xd2a2_4680, // mov x0, #0x12340000
+    0xf2e0_0020, // movk x0, #0x1, lsl #48
+    0xf900_0fe0, // str x0, [sp, #24]
+    0xf940_0000, // ldr x0, [x0]
+    0xd63f_0020, // blr x1
+    0xd400_0003, // smc #0
+    0xd65f_03c0, // ret
+];
+
+/// Fake memory holding [`GOLDEN_CODE`] and nothing else
+fn golden_code(addr: u64) -> Result<u32, &'static [u8]> {
+    let index = addr.wrapping_sub(GOLDEN_CODE_BASE) / 4;
+
+    return match GOLDEN_CODE.get(index as usize) {
+        Some(&opcode) => Ok(opcode),
+        None => Err(b"not in RAM"),
+    };
+}
+
+/// Fake memory that can't be read
+fn golden_unreadable(_elr: u64) -> Result<u32, &'static [u8]> {
+    return Err(b"not in RAM");
+}
+
+/// A synthetic exception and the dump expected of it
+struct GoldenDump {
+    /// Name of the file of `tests/golden` holding the expected dump
+    file: &'static [u8],
+    /// Name of the handler
+    name: &'static [u8],
+    /// Registers of the exception
+    regs: Regs,
+    /// State of the core and memory when it's taken
+    source: DumpSource,
+    /// Expected dump, byte for byte
+    expected: &'static [u8],
+}
+
+/// Exceptions whose dumps are checked by [`selftest_dump_golden`]
+///
+/// The expected dumps are the files of `tests/golden`. A change of format
+/// must update them in the same commit, so it shows as a diff of those
+/// files; the self-test prints the dump it produced when one differs.
+const GOLDEN_DUMPS: [GoldenDump; 3] = [
+    // 8-byte load from an address misaligned by 4, with its syndrome
+    GoldenDump {
+        file: b"alignment.txt",
+        name: b"Synchronous Exception handler",
+        regs: Regs {
+            x0: 0x4000_1004,
+            x1: 8,
+            x29: 0x4010_fff0,
+            x30: 0x4008_0abc,
+            esr: (EC_DABT_CUR as u64) << ESR_EC_SHIFT
+                | 1 << 25
+                | ESR_ISV
+                | 3 << ESR_SAS_SHIFT
+                | DFSC_ALIGNMENT as u64,
+            elr: 0x4008_0010,
+            spsr: 0x6000_03c9,
+            ..Regs::ZERO
+        },
+        source: DumpSource {
+            current_el: 2,
+            far: 0x4000_1004,
+            read_instr: golden_code,
+        },
+        expected: include_bytes!("../tests/golden/alignment.txt"),
+    },
+    // Jump to an address outside RAM
+    GoldenDump {
+        file: b"unreadable.txt",
+        name: b"Synchronous Exception handler",
+        regs: Regs {
+            x16: 0xdead_bee0,
+            x30: 0x4008_0220,
+            esr: (EC_IABT_CUR as u64) << ESR_EC_SHIFT | 1 << 25 | 0x0f,
+            elr: 0xdead_bee0,
+            spsr: 0x8000_03c9,
+            ..Regs::ZERO
+        },
+        source: DumpSource {
+            current_el: 2,
+            far: 0xdead_bee0,
+            read_instr: golden_unreadable,
+        },
+        expected: include_bytes!("../tests/golden/unreadable.txt"),
+    },
+    // PSCI SYSTEM_OFF trapped from the payload
+    GoldenDump {
+        file: b"smc.txt",
+        name: b"Lower EL Synchronous Exception handler",
+        regs: Regs {
+            elr: 0x4008_0018,
+            spsr: 0x0000_03c5,
+            ..TEST_SYSTEM_OFF
+        },
+        source: DumpSource {
+            current_el: 2,
+            far: 0,
+            read_instr: golden_code,
+        },
+        expected: include_bytes!("../tests/golden/smc.txt"),
+    },
+];
+
+/// Buffer the dumps of [`selftest_dump_golden`] are written to
+static mut GOLDEN_BUF: [u8; 2048] = [0; 2048];
+
+/// Self-test: the dumps of synthetic exceptions match the files of
+/// `tests/golden` byte for byte
+///
+/// A dump that differs is printed between markers, to be compared with its
+/// file or, if the change of format is intended, to replace it.
+fn selftest_dump_golden() -> Outcome {
+    let buf = &raw mut GOLDEN_BUF;
+    let mut sink = CaptureSink::new(unsafe { &mut *buf });
+    let mut outcome = Outcome::Pass;
+
+    for golden in GOLDEN_DUMPS.iter() {
+        sink.clear();
+        write_exception(&mut sink, golden.name, &golden.regs, &golden.source);
+        if !sink.overflowed() && sink.bytes() == golden.expected {
+            continue;
+        }
+        pl011::print(b"\n--- produced tests/golden/");
+        pl011::println(golden.file);
+        pl011::print(sink.bytes());
+        pl011::println(b"--- end");
+        outcome = Outcome::Fail;
+    }
+    return outcome;
+}
+
+/// Lines [`selftest_dump_lines`] expects in the dump of an SError taken
+/// from EL1 at the second instruction of [`GOLDEN_CODE`]
+const TEST_DUMP_LINES: [&[u8]; 7] = [
+    b"Lower EL SError handler",
+    b"CurrentEL: 2",
+    b"EC=0x2f (SError)",
+    b"=> 0x0000000040080004: d2a24680  mov x0, #0x12340000",
+    b"x0 : 0x0000000012340000",
+    b"x30: 0x0000000040080abc",
+    b"PSTATE: nzCv DAIF EL1h",
+];
+
+/// Self-test: the dump of a synthetic exception other than a data abort
+/// holds the key lines of each part, from the handler name to the PSTATE
+/// summary, and no fault address breakdown
+fn selftest_dump_lines() -> Outcome {
+    let regs = Regs {
+        x0: 0x1234_0000,
+        x30: 0x4008_0abc,
+        esr: 0x2f << ESR_EC_SHIFT | 1 << 25,
+        elr: 0x4008_0004,
+        spsr: 0x2000_03c5,
+        ..Regs::ZERO
+    };
+    let source = DumpSource {
+        current_el: 2,
+        far: 0,
+        read_instr: golden_code,
+    };
+    let buf = &raw mut GOLDEN_BUF;
+    let mut sink = CaptureSink::new(unsafe { &mut *buf });
+
+    write_exception(&mut sink, TEST_DUMP_LINES[0], &regs, &source);
+    let dump = sink.bytes();
+    let lines = || dump.split(|&b| b == b'\n');
+    let has_line = |expected: &[u8]| lines().any(|line| line == expected);
+    if sink.overflowed()
+        || !dump.starts_with(TEST_DUMP_LINES[0])
+        || !TEST_DUMP_LINES.iter().all(|&line| has_line(line))
+        || lines().any(|line| line.starts_with(b"Fault address"))
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Window of one instruction around the `ldr` of [`GOLDEN_CODE`]
+const TEST_WINDOW: &[u8] = b"   0x000000004008000c: f9000fe0  str x0, [sp, #24]
+=> 0x0000000040080010: f9400000  ldr x0, [x0]
+   0x0000000040080014: d63f0020  blr x1
+";
+/// Window running past the end of [`GOLDEN_CODE`]
+const TEST_WINDOW_END: &[u8] = b"   0x0000000040080018: d4000003  smc #0x0
+=> 0x000000004008001c: d65f03c0  ret
+   0x0000000040080020: <unreadable> (not in RAM)
+";
+/// Window cut at the start of the address space
+const TEST_WINDOW_START: &[u8] = b"   0x0000000000000000: <unreadable> (not in RAM)
+=> 0x0000000000000004: <unreadable> (not in RAM)
+";
+
+/// Self-test: windows of instructions are written with the arrow on the
+/// line of the given address, unreadable instructions and the start of the
+/// address space are handled, and the code of the bootloader reads back
+fn selftest_instr_window() -> Outcome {
+    let source = DumpSource {
+        current_el: 2,
+        far: 0,
+        read_instr: golden_code,
+    };
+    let windows: [(u64, usize, usize, &[u8]); 3] = [
+        (0x4008_0010, 1, 1, TEST_WINDOW),
+        (0x4008_001c, 1, 1, TEST_WINDOW_END),
+        (0x4, 3, 0, TEST_WINDOW_START),
+    ];
+    let mut buf = [0u8; 256];
+    let mut sink = CaptureSink::new(&mut buf);
+
+    for (pc, before, after, expected) in windows {
+        sink.clear();
+        write_instructions(&mut sink, pc, before, after, &source);
+        if sink.overflowed() || sink.bytes() != expected {
+            return Outcome::Fail;
+        }
+    }
+
+    // The live reader gets this very function, which isn't all zeros
+    let pc = selftest_instr_window as *const () as u64;
+    let mut prefix = *b"=> 0x0000000000000000: ";
+    u64_to_hex_padded(pc, &mut prefix[5..21]);
+    sink.clear();
+    write_instructions(&mut sink, pc, 0, 0, &DumpSource::live());
+    let line = sink.bytes();
+    let opcode = line.get(prefix.len()..prefix.len() + 8).unwrap_or(b"");
+    if !line.starts_with(&prefix)
+        || opcode.len() != 8
+        || !opcode.iter().all(u8::is_ascii_hexdigit)
+        || opcode == b"00000000"
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// `brk` immediate of the self-test, claimed by no other handler
+const TEST_BRK_IMM: u16 = 0x7e57;
+
+#[cfg(not(feature = "stage1"))]
+const _: () = assert!(TEST_BRK_IMM != monitor::MONITOR_BRK_IMM);
+
+/// Immediates received by [`record_brk`], in order
+static mut TEST_BRKS: [Option<u16>; 2] = [None; 2];
+
+/// `brk` callback of the self-test: records the immediate
+fn record_brk(imm: u16, _regs: &mut Regs) {
+    let brks = &raw mut TEST_BRKS;
+    let brks = unsafe { &mut *brks };
+
+    if let Some(slot) = brks.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(imm);
+    }
+}
+
+/// Self-test: the registered callback receives the immediate of a `brk`,
+/// synthetic then executed, and execution resumes after it
+fn selftest_brk_handler() -> Outcome {
+    let elr = GOLDEN_CODE_BASE;
+    let mut regs = Regs {
+        esr: (EC_BRK64 as u64) << ESR_EC_SHIFT | 1 << 25 | 0x1234,
+        elr,
+        ..Regs::ZERO
+    };
+
+    unsafe {
+        TEST_BRKS = [None; 2];
+    }
+    let without = handle_brk(&mut regs);
+    let previous = set_brk_handler(Some(record_brk));
+    let synthetic = handle_brk(&mut regs);
+    let resumed = synthetic && regs.elr == elr + 4;
+    unsafe {
+        asm!("brk #{imm}", imm = const TEST_BRK_IMM, options(nostack));
+    }
+    set_brk_handler(previous);
+
+    if without || !resumed || unsafe { TEST_BRKS } != [Some(0x1234), Some(TEST_BRK_IMM)] {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Fault address received by [`record_alignment_fault`], or 0
+static mut TEST_ALIGNMENT_FAR: u64 = 0;
+
+/// Alignment fault callback of the self-test: records the fault address
+/// and skips the faulting instruction
+fn record_alignment_fault(regs: &mut Regs, far: u64) -> bool {
+    unsafe {
+        TEST_ALIGNMENT_FAR = far;
+    }
+    regs.elr += 4;
+    return true;
+}
+
+/// Self-test: a load exclusive from an address misaligned by 4, which
+/// always takes an alignment fault, reaches the registered callback with
+/// the misaligned address as FAR, and execution resumes after it
+fn selftest_alignment_handler() -> Outcome {
+    let target = [0u64; 2];
+    let addr = target.as_ptr() as u64 + 4;
+    let previous = unsafe { ALIGNMENT_HANDLER };
+
+    unsafe {
+        TEST_ALIGNMENT_FAR = 0;
+    }
+    set_alignment_handler(Some(record_alignment_fault));
+    unsafe {
+        asm!("ldxr {}, [{}]", out(reg) _, in(reg) addr, options(nostack));
+        asm!("clrex", options(nomem, nostack));
+    }
+    set_alignment_handler(previous);
+
+    if unsafe { TEST_ALIGNMENT_FAR } != addr {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Registers the self-tests of the module
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"context",
+        run: selftest_context,
+    });
+    selftest::register(SelfTest {
+        name: b"dump-golden",
+        run: selftest_dump_golden,
+    });
+    selftest::register(SelfTest {
+        name: b"dump-lines",
+        run: selftest_dump_lines,
+    });
+    selftest::register(SelfTest {
+        name: b"instr-window",
+        run: selftest_instr_window,
+    });
+    selftest::register(SelfTest {
+        name: b"brk-handler",
+        run: selftest_brk_handler,
+    });
+    selftest::register(SelfTest {
+        name: b"alignment-handler",
+        run: selftest_alignment_handler,
+    });
 }
@@ -0,0 +1,184 @@
+//! Interrupt handling
+//!
+//! This module takes interrupts in the bootloader itself, so background work
+//! such as the heartbeat LED keeps running while the boot flow is busy (e.g.,
+//! copying a large image). [`init`] brings up the GIC described by the board
+//! configuration and routes IRQs to the exception level the bootloader runs
+//! at; the IRQ handler calls [`handle`] to dispatch them.
+//!
+//! Only the generic timer interrupt is dispatched; spurious and unexpected
+//! interrupts are warned about, rate-limited so a storm of them can't flood
+//! the console. Before the payload is started, [`shutdown`] masks interrupts again and gives the routing back to
+//! the payload. The interrupt controller follows the driver
+//! [lifecycle](crate::drivers::lifecycle): it's set up once, quiesced by
+//! [`shutdown`], and only [`teardown`] lets [`init`] set it up again.
+
+use crate::board;
+use crate::cpu;
+use crate::drivers::irq::gic;
+use crate::drivers::lifecycle::{Lifecycle, State};
+use crate::drivers::timer::generic;
+use crate::log;
+use crate::utilities::print::u64_to_dec;
+
+use crate::asm;
+
+/// HCR_EL2 Physical IRQ routing bit: take IRQs at EL2
+const HCR_EL2_IMO: u64 = 1 << 4;
+/// DAIF IRQ mask bit
+const DAIF_I: u64 = 1 << 7;
+
+/// Lifecycle of the interrupt controller: active once [`init`] succeeded,
+/// quiesced by [`shutdown`]
+static mut LIFECYCLE: Lifecycle = Lifecycle::new();
+
+/// Returns whether [`init`] succeeded and [`shutdown`] wasn't called since
+fn is_active() -> bool {
+    let lifecycle = &raw const LIFECYCLE;
+
+    return unsafe { (*lifecycle).is_active() };
+}
+
+/// Initializes the interrupt controller and routes IRQs to the bootloader
+///
+/// IRQs stay masked until [`unmask`] is called. Returns `false` if the board
+/// has no supported interrupt controller, or if it was quiesced by
+/// [`shutdown`] and not [torn down](teardown) since. Calling it again after
+/// success does nothing.
+pub fn init() -> bool {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    match unsafe { (*lifecycle).state() } {
+        State::Active => return true,
+        State::Uninitialized => {}
+        State::Probed | State::Quiesced => return false,
+    }
+    if !gic::init(&board::config().gic) {
+        return false;
+    }
+
+    if cpu::current_el() == 2 {
+        set_hcr_el2_imo(true);
+    }
+    unsafe {
+        // Only fails if not uninitialized, checked above
+        let _ = (*lifecycle).start();
+    }
+    return true;
+}
+
+/// Sets or clears `HCR_EL2.IMO`, routing IRQs to EL2 or to EL1
+fn set_hcr_el2_imo(enable: bool) {
+    let hcr: u64;
+
+    unsafe {
+        asm!("mrs {}, hcr_el2", out(reg) hcr);
+        let hcr = if enable {
+            hcr | HCR_EL2_IMO
+        } else {
+            hcr & !HCR_EL2_IMO
+        };
+        asm!("msr hcr_el2, {}", "isb", in(reg) hcr);
+    }
+}
+
+/// Enables the private interrupt `intid` at the interrupt controller
+pub fn enable(intid: u32) -> bool {
+    return is_active() && gic::enable_private(intid);
+}
+
+/// Unmasks IRQs on the current CPU
+pub fn unmask() {
+    unsafe {
+        asm!("msr daifclr, #2");
+    }
+}
+
+/// Masks IRQs on the current CPU
+pub fn mask() {
+    unsafe {
+        asm!("msr daifset, #2");
+    }
+}
+
+/// Runs `f` with IRQs masked, restoring the previous mask afterwards
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let daif: u64;
+
+    unsafe {
+        asm!("mrs {}, daif", out(reg) daif);
+    }
+    mask();
+    let result = f();
+    if daif & DAIF_I == 0 {
+        unmask();
+    }
+    return result;
+}
+
+/// Dispatches a pending IRQ
+///
+/// Called by the IRQ exception handler. Returns `false` if interrupts were
+/// never initialized, in which case the IRQ is unexpected.
+pub fn handle() -> bool {
+    if !is_active() {
+        return false;
+    }
+
+    let intid = gic::acknowledge();
+    if intid == gic::SPURIOUS_INTID {
+        crate::log_warn_ratelimited!(b"irq-spurious", b"Spurious interrupt");
+        return true;
+    }
+
+    match intid {
+        intid if intid == generic::timer_intid() => {
+            generic::handle_tick();
+            log::tick();
+        }
+        _ => {
+            let mut buf = [0u8; 20];
+            let digits = u64_to_dec(intid as u64, &mut buf);
+            crate::log_warn_ratelimited!(b"irq-unexpected", b"Unexpected interrupt ", digits);
+            gic::disable_private(intid);
+        }
+    }
+    gic::end_of_interrupt(intid);
+    return true;
+}
+
+/// Masks IRQs and gives their routing back to the payload
+///
+/// Disables the interrupts enabled by the bootloader and, at EL2, stops
+/// routing IRQs to EL2 so the payload at EL1 receives them. The interrupt
+/// controller is then quiesced; calling it again only masks IRQs.
+pub fn shutdown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    mask();
+    if !is_active() {
+        return;
+    }
+
+    gic::disable_private(generic::timer_intid());
+    if cpu::current_el() == 2 {
+        set_hcr_el2_imo(false);
+    }
+    unsafe {
+        let _ = (*lifecycle).quiesce();
+    }
+}
+
+/// Forgets the interrupt controller after [`shutdown`], so [`init`] can set
+/// it up again
+///
+/// Does nothing while it's active: it must be shut down first.
+pub fn teardown() {
+    let lifecycle = &raw mut LIFECYCLE;
+
+    if !is_active() {
+        unsafe {
+            (*lifecycle).teardown();
+        }
+    }
+}
@@ -3,22 +3,131 @@
 //! This crate implements a minimal bootloader for the AArch64 architecture,
 //! designed for educational purposes
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+// The host tests stub out the inline assembly (see `asm`), which leaves the
+// code around it unreachable and its operands unused
+#![cfg_attr(
+    test,
+    allow(unused, unreachable_code, unused_unsafe, clippy::never_loop)
+)]
 
+#[cfg(not(any(test, feature = "stage1")))]
 use core::panic::PanicInfo;
 
+#[cfg(not(any(test, feature = "stage1")))]
+use board::heartbeat::{self, Pattern};
+#[cfg(not(any(test, feature = "stage1")))]
+use drivers::timer::generic;
+#[cfg(not(any(test, feature = "stage1")))]
+use drivers::uart::pl011;
+#[cfg(not(any(test, feature = "stage1")))]
+use utilities::print;
+
+/// Inline assembly, as used throughout the crate
+///
+/// The host tests (`cargo test`) only exercise code that doesn't depend on
+/// the core, such as the parsers and the drivers run against a `MockBus`.
+/// AArch64 instructions can't be assembled for the host, so there `asm!`
+/// expands to `unreachable!()`.
+#[cfg(not(test))]
+pub(crate) use core::arch::asm;
+#[cfg(test)]
+macro_rules! asm {
+    ($($tokens:tt)*) => {
+        unreachable!("AArch64 inline assembly run on the host")
+    };
+}
+#[cfg(test)]
+pub(crate) use asm;
+
+pub mod board;
+pub mod boot;
+pub mod bootid;
+pub mod bootplan;
+pub mod bootreason;
+pub mod capture;
+pub mod cpu;
+pub mod debug;
+pub mod diagnostics;
+pub mod env;
+pub mod error;
 pub mod parsers;
+pub mod serial;
 pub mod exception;
+pub mod interrupt;
+pub mod log;
+pub mod measure;
+pub mod memory;
+#[cfg(not(feature = "stage1"))]
+pub mod monitor;
+pub mod pauth;
+pub mod power;
+pub mod psci;
+#[cfg(not(feature = "stage1"))]
+pub mod script;
+pub mod selftest;
+#[cfg(feature = "stage1")]
+pub mod stage1;
+
+// The first stage has no display driver to mirror the console on
+#[cfg(all(feature = "stage1", feature = "ramfb-console"))]
+compile_error!("the stage1 and ramfb-console features can't be combined");
+pub mod tables;
 pub mod drivers;
 pub mod utilities;
+pub mod warmcache;
 
 /// Panic handler for the bootloader
 ///
 /// When a panic occurs, this handler is invoked. Currently, it enters an
-/// infinite loop, halting execution. In a real bootloader, this might
-/// perform cleanup or print diagnostic information.
+/// infinite loop, halting execution. While halted it prints a heartbeat dot
+/// every second, so a hung board can be told apart from a panicked one, and
+/// the heartbeat indicator blinks SOS (or keeps blinking fast after a fatal
+/// exception). Where the panic happened is printed, with the message when
+/// it is a plain string, and kept in the boot record for the next boot,
+/// followed by the registers of the last known context if one was recorded
+/// (see [`exception::Context`]). In a real bootloader, this might perform
+/// cleanup.
+#[cfg(not(any(test, feature = "stage1")))]
 #[panic_handler]
-pub fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+pub fn panic(info: &PanicInfo) -> ! {
+    let period = generic::frequency();
+
+    log::to_all_sinks(|| {
+        pl011::print(b"Panic");
+        if let Some(location) = info.location() {
+            pl011::print(b" at ");
+            pl011::print(location.file().as_bytes());
+            pl011::print(b":");
+            pl011::print(print::u64_to_dec(location.line() as u64, &mut [0u8; 20]));
+        }
+        if let Some(message) = info.message().as_str()
+            && !message.is_empty()
+        {
+            pl011::print(b": ");
+            pl011::print(message.as_bytes());
+        }
+        pl011::print(b"\n");
+        if let Some(context) = exception::last_context() {
+            context.print();
+        }
+    });
+    if let Some(location) = info.location() {
+        bootreason::record_panic(location.file().as_bytes(), location.line());
+    }
+
+    match heartbeat::current() {
+        Pattern::FastBlink => heartbeat::fatal(Pattern::FastBlink),
+        _ => heartbeat::fatal(Pattern::Sos),
+    }
+    let mut next = generic::counter() + period;
+
+    loop {
+        // Without a programmed counter frequency there is no timer to beat on
+        if period != 0 && generic::counter() >= next {
+            print::heartbeat(1);
+            next += period;
+        }
+    }
 }
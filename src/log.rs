@@ -0,0 +1,1174 @@
+//! Leveled output to the console and the debug channel
+//!
+//! Messages are printed at a [`Level`] with [`print`] and [`println`]. Each
+//! level has a mask of the sinks it goes to: the console ([`SINK_CONSOLE`])
+//! and the debug channel ([`SINK_DEBUG`]) chosen by the board, see
+//! [`DebugChannel`]. Without a debug channel every level goes to the
+//! console. Once one is set up, debug-level output goes only to it while the
+//! other levels go to both, so verbose output can be kept in a file without
+//! cluttering the console; [`set_sinks`] changes the mask of a level.
+//!
+//! Output that must reach both sinks whatever the masks, such as the boot
+//! banner, boot errors and exception dumps, is printed inside
+//! [`to_all_sinks`]: everything printed to the console meanwhile is mirrored
+//! to the debug channel. [`set_tee`] mirrors all console output that way,
+//! e.g., for a demo where the console shows the boot while the debug channel
+//! keeps everything. [`TeeConsole`] does the same for one [`Console`].
+//!
+//! A second PL011 can also be set up as the debug channel at run time with
+//! [`init_debug_uart`], e.g., to keep logs on one port while the monitor
+//! runs on the console. [`debug_print`] and [`debug_println`] always write
+//! to the debug channel, whatever the level masks.
+//!
+//! Warnings that can fire thousands of times, such as UART receive errors,
+//! go through [`log_warn_ratelimited!`](crate::log_warn_ratelimited): only
+//! the first [`RATELIMIT_BURST`] occurrences of each key are printed. The
+//! others are counted, and a summary of how many were suppressed is printed
+//! before the next unrelated line, or from the timer tick (see [`tick`]).
+//! [`report_suppressed`] lists the totals before the handoff.
+//!
+//! With QEMU `virt`, building with `DEBUG_CHANNEL=pl011:0x09040000` and
+//! running with `-serial stdio -serial file:debug.log` keeps the debug
+//! output in `debug.log`, while the banner and errors appear in both.
+//!
+//! # Structured records
+//!
+//! For tools scraping the serial output, each line printed through this
+//! module can also ([`Format::Both`]) or instead ([`Format::KeyValue`]) be
+//! printed as a record on a line of its own:
+//!
+//! ```text
+//! ts=1234567 level=4 mod=boot msg="Kernel overlaps memory in use!"
+//! ```
+//!
+//! `ts` is the uptime in microseconds, `level` the syslog severity (3 error,
+//! 4 warning, 6 info, 7 debug) and `mod` the module that printed the line,
+//! e.g. `drivers::uart::pl011`. In the quoted message, `"` and `\` are
+//! escaped with a backslash, tab, CR and LF as `\t`, `\r` and `\n`, and
+//! other bytes outside printable ASCII as `\xHH`; the final newline isn't
+//! part of it. Messages over [`RECORD_SIZE`] bytes are cut and marked
+//! `trunc=1`. A record is only printed once its line is complete, after the
+//! text in [`Format::Both`], and starts a new console line if needed, so
+//! text and records never share a line. Output printed straight to the
+//! console, such as the banner, error reports and exception dumps, has no
+//! record.
+//!
+//! The format is [`Format::Text`] by default, [`Format::Both`] when built
+//! with the `structured-log` feature, and the `logfmt` environment variable
+//! (`text`, `kv` or `both`) overrides it once the board is set up, or at
+//! once when set with the monitor's `setenv`.
+//!
+//! Events with well-known semantics are only printed as records, with
+//! `event=<name>` in place of `msg` and these fixed keys:
+//!
+//! | Event     | Keys                                                      |
+//! |-----------|-----------------------------------------------------------|
+//! | `stage`   | `stage` name, `us` since the previous stage ended         |
+//! | `load`    | `component`, `result` (`ok` or `error`), `entry` or `code`|
+//! | `measure` | `component`, `size`, `sha256`, `source` (quoted)          |
+//! | `handoff` | `entry`, `dtb`, `el` (1 or 2), `vhe` (0 or 1)             |
+//!
+//! Numbers are decimal, except `entry`, `dtb` and `code`, which are
+//! hexadecimal with a `0x` prefix, and `sha256`, 64 hex digits. Stages are
+//! `board_init`, `prepare_boot`, `load_kernel` and `before_handoff`.
+
+use crate::board::{DebugChannel, UartConfig};
+use crate::drivers::timer::generic;
+use crate::drivers::uart::pl011;
+use crate::env;
+use crate::interrupt;
+use crate::selftest::{self, Outcome, SelfTest};
+use crate::utilities::print::{Console, HEX_CHARS, u64_to_dec, u64_to_hex, u64_to_hex_padded};
+use crate::utilities::semihosting;
+
+use core::panic::Location;
+use core::ptr;
+
+/// Severity of a message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// The boot can't go on as asked
+    Error,
+    /// Something is off, but the boot goes on
+    Warn,
+    /// Progress of the boot
+    Info,
+    /// Details only useful when debugging
+    Debug,
+}
+
+/// Number of levels
+const LEVELS: usize = 4;
+
+/// Sink bit of the console
+pub const SINK_CONSOLE: u8 = 1 << 0;
+/// Sink bit of the debug channel
+pub const SINK_DEBUG: u8 = 1 << 1;
+/// Sink bits of both sinks
+pub const SINK_ALL: u8 = SINK_CONSOLE | SINK_DEBUG;
+
+/// Masks of the levels without a debug channel
+const CONSOLE_ONLY: [u8; LEVELS] = [SINK_CONSOLE; LEVELS];
+/// Masks of the levels with a debug channel, in [`Level`] order
+const SPLIT: [u8; LEVELS] = [SINK_ALL, SINK_ALL, SINK_ALL, SINK_DEBUG];
+
+/// Occurrences of a rate-limited warning printed before the others are
+/// suppressed
+pub const RATELIMIT_BURST: u64 = 3;
+/// Number of rate-limited warnings counted at once
+pub const RATELIMIT_KEYS: usize = 8;
+/// Least time between two summaries printed from the timer tick, in ms
+const SUMMARY_INTERVAL_MS: u64 = 1000;
+
+/// Counters of a rate-limited warning
+#[derive(Clone, Copy)]
+struct Limited {
+    /// Key the warning is counted under
+    key: &'static [u8],
+    /// Occurrences so far
+    count: u64,
+    /// Occurrences suppressed since the last summary
+    pending: u64,
+    /// Value of [`RateLimits::uses`] when last seen
+    last_use: u64,
+}
+
+/// Counters of the rate-limited warnings
+#[derive(Clone, Copy)]
+struct RateLimits {
+    /// Counted warnings, `None` for free slots
+    keys: [Option<Limited>; RATELIMIT_KEYS],
+    /// Occurrences of all warnings so far, dating each key's last use
+    uses: u64,
+    /// Occurrences suppressed of the warnings evicted from [`Self::keys`]
+    evicted: u64,
+    /// Of those, the ones suppressed since the last summary
+    evicted_pending: u64,
+    /// Uptime of the last summary, in ms
+    last_summary: u64,
+}
+
+/// Rate-limited warnings, shared with the interrupt handlers
+static mut RATELIMITS: RateLimits = RateLimits {
+    keys: [None; RATELIMIT_KEYS],
+    uses: 0,
+    evicted: 0,
+    evicted_pending: 0,
+    last_summary: 0,
+};
+/// Set while summaries are printed, so [`print`] doesn't print them again
+static mut SUMMARIZING: bool = false;
+
+/// Sink mask of each level, indexed by [`Level`]
+static mut SINKS: [u8; LEVELS] = CONSOLE_ONLY;
+/// Debug channel in use
+static mut CHANNEL: DebugChannel = DebugChannel::None;
+/// Whether console output is being mirrored to the debug channel
+static mut MIRRORING: bool = false;
+
+/// How the lines printed through this module come out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Human-readable text only
+    Text,
+    /// Structured records only
+    KeyValue,
+    /// Human-readable text, each line followed by its structured record
+    Both,
+}
+
+/// Format used when the feature doesn't say otherwise
+#[cfg(not(feature = "structured-log"))]
+const DEFAULT_FORMAT: Format = Format::Text;
+/// Format used when the feature doesn't say otherwise
+#[cfg(feature = "structured-log")]
+const DEFAULT_FORMAT: Format = Format::Both;
+
+/// Environment variable choosing the format
+pub const FORMAT_VAR: &[u8] = b"logfmt";
+/// Longest message kept in a record, in bytes before escaping
+pub const RECORD_SIZE: usize = 256;
+
+/// Line being collected into a record
+struct Record {
+    /// Whether a line is being collected
+    open: bool,
+    /// Level of the line
+    level: Level,
+    /// Source file of the code that started the line
+    file: &'static str,
+    /// Bytes of the line so far, `len` of them
+    buf: [u8; RECORD_SIZE],
+    /// Number of bytes in `buf`
+    len: usize,
+    /// Whether bytes were dropped past [`RECORD_SIZE`]
+    truncated: bool,
+}
+
+/// Format of the lines printed through this module
+static mut FORMAT: Format = DEFAULT_FORMAT;
+/// Line being collected while the format has records
+static mut RECORD: Record = Record {
+    open: false,
+    level: Level::Info,
+    file: "",
+    buf: [0; RECORD_SIZE],
+    len: 0,
+    truncated: false,
+};
+/// Uptime at the end of the last stage, in microseconds
+static mut LAST_STAGE_US: u64 = 0;
+
+/// Sets up `channel` as the debug channel and resets the level masks
+///
+/// A PL011 channel is programmed like the `console`. Returns `false`,
+/// leaving everything on the console, if no PL011 answers at its address.
+pub fn set_debug_channel(channel: DebugChannel, console: &UartConfig) -> bool {
+    return match channel {
+        DebugChannel::Pl011 { base } => init_debug_uart(base, console.clock, console.baudrate),
+        DebugChannel::None | DebugChannel::Semihosting => {
+            use_channel(channel);
+            true
+        }
+    };
+}
+
+/// Sets up the PL011 at `base` as the debug channel, at `baudrate` from a
+/// `clock` reference clock, and resets the level masks
+///
+/// The PL011 is programmed for transmission only; the console is left
+/// alone. Returns `false`, leaving the debug channel as it was, if no PL011
+/// answers at `base` or the baud rate is out of range for the clock.
+pub fn init_debug_uart(base: usize, clock: u32, baudrate: u32) -> bool {
+    if !pl011::init_tx_only(base, clock, baudrate) {
+        return false;
+    }
+    use_channel(DebugChannel::Pl011 { base });
+    return true;
+}
+
+/// Makes `channel` the debug channel, with the level masks that go with it
+fn use_channel(channel: DebugChannel) {
+    unsafe {
+        CHANNEL = channel;
+        SINKS = match channel {
+            DebugChannel::None => CONSOLE_ONLY,
+            _ => SPLIT,
+        };
+    }
+}
+
+/// Returns the debug channel in use
+pub fn debug_channel() -> DebugChannel {
+    unsafe {
+        return CHANNEL;
+    }
+}
+
+/// Sets the sinks `level` goes to, as [`SINK_CONSOLE`] and [`SINK_DEBUG`]
+/// bits
+pub fn set_sinks(level: Level, sinks: u8) {
+    unsafe {
+        SINKS[level as usize] = sinks & SINK_ALL;
+    }
+}
+
+/// Returns the sinks `level` goes to
+pub fn sinks(level: Level) -> u8 {
+    unsafe {
+        return SINKS[level as usize];
+    }
+}
+
+/// Writes `s` to the debug channel, if there is one
+fn write_debug(s: &[u8]) {
+    match debug_channel() {
+        DebugChannel::None => {}
+        DebugChannel::Pl011 { base } => pl011::print_at(base, s),
+        DebugChannel::Semihosting => semihosting::write(s),
+    }
+}
+
+/// Prints `s` to the debug channel, whatever the level masks
+///
+/// Nothing is printed without a debug channel.
+pub fn debug_print(s: &[u8]) {
+    write_debug(s);
+}
+
+/// Prints `s` followed by a newline to the debug channel, whatever the
+/// level masks
+pub fn debug_println(s: &[u8]) {
+    write_debug(s);
+    write_debug(b"\n");
+}
+
+/// The debug channel, see [`debug_print`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DebugConsole;
+
+impl Console for DebugConsole {
+    fn write(&mut self, bytes: &[u8]) {
+        write_debug(bytes);
+    }
+}
+
+/// Console writing everything to two consoles, the first one first
+///
+/// `TeeConsole::new(Uart, DebugConsole)` does for code written against a
+/// [`Console`] what [`set_tee`] does for all console output.
+#[derive(Debug)]
+pub struct TeeConsole<A: Console, B: Console> {
+    /// Console written first
+    first: A,
+    /// Console written second
+    second: B,
+}
+
+impl<A: Console, B: Console> TeeConsole<A, B> {
+    /// Returns a console writing to `first`, then to `second`
+    pub fn new(first: A, second: B) -> TeeConsole<A, B> {
+        return TeeConsole { first, second };
+    }
+
+    /// Returns the two consoles
+    pub fn into_parts(self) -> (A, B) {
+        return (self.first, self.second);
+    }
+}
+
+impl<A: Console, B: Console> Console for TeeConsole<A, B> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.first.write(bytes);
+        self.second.write(bytes);
+    }
+}
+
+/// Mirrors a byte printed to the console to the debug channel
+fn mirror(c: u8) {
+    write_debug(&[c]);
+}
+
+/// Writes `s` to the sinks of `level`
+fn write_sinks(level: Level, s: &[u8]) {
+    let sinks = sinks(level);
+    let mirrored = unsafe { MIRRORING };
+
+    if sinks & SINK_CONSOLE != 0 {
+        pl011::print(s);
+    }
+    // While mirroring, the console copy already reached the debug channel
+    if sinks & SINK_DEBUG != 0 && !(mirrored && sinks & SINK_CONSOLE != 0) {
+        write_debug(s);
+    }
+}
+
+/// Prints `s` at `level`
+///
+/// Starting a line, first prints the summaries of suppressed warnings. With
+/// a [`Format`] that has records, the line is also collected into one,
+/// printed once the line ends.
+#[track_caller]
+pub fn print(level: Level, s: &[u8]) {
+    let format = format();
+
+    if pl011::at_line_start() && !record_open() {
+        summarize();
+    }
+    if format != Format::KeyValue {
+        write_sinks(level, s);
+    }
+    if format != Format::Text {
+        collect(level, Location::caller().file(), s);
+    }
+}
+
+/// Prints `s` followed by a newline at `level`
+#[track_caller]
+pub fn println(level: Level, s: &[u8]) {
+    print(level, s);
+    print(level, b"\n");
+}
+
+/// Returns the format of the lines printed through this module
+pub fn format() -> Format {
+    return unsafe { FORMAT };
+}
+
+/// Sets the format of the lines printed through this module
+///
+/// A line being collected is printed as a record first.
+pub fn set_format(format: Format) {
+    let record = &raw mut RECORD;
+
+    unsafe {
+        if (*record).open {
+            flush(&mut *record);
+        }
+        FORMAT = format;
+    }
+}
+
+/// Sets the format from the `logfmt` environment variable, if set
+///
+/// Called once the board is set up, as the environment may be in its flash.
+/// An unknown value is reported and leaves the format alone.
+pub fn load_format() {
+    let format = match env::get(FORMAT_VAR) {
+        None => return,
+        Some(b"text") => Format::Text,
+        Some(b"kv") => Format::KeyValue,
+        Some(b"both") => Format::Both,
+        Some(value) => {
+            print(Level::Warn, b"Unknown logfmt ");
+            print(Level::Warn, value);
+            println(Level::Warn, b", expected text, kv or both");
+            return;
+        }
+    };
+    set_format(format);
+}
+
+/// Checks whether a line is being collected into a record
+fn record_open() -> bool {
+    return unsafe { RECORD.open };
+}
+
+/// Adds `s`, printed at `level` by code in `file`, to the line being
+/// collected, printing the record of each line it ends
+///
+/// A change of level ends the line, as its record has a single level.
+fn collect(level: Level, file: &'static str, mut s: &[u8]) {
+    let record = &raw mut RECORD;
+    let record = unsafe { &mut *record };
+
+    if record.open && record.level != level {
+        flush(record);
+    }
+    while !s.is_empty() {
+        if !record.open {
+            record.open = true;
+            record.level = level;
+            record.file = file;
+            record.len = 0;
+            record.truncated = false;
+        }
+        let (line, rest, ended) = match s.iter().position(|&c| c == b'\n') {
+            Some(i) => (&s[..i], &s[i + 1..], true),
+            None => (s, &s[s.len()..], false),
+        };
+        let kept = line.len().min(RECORD_SIZE - record.len);
+        record.buf[record.len..record.len + kept].copy_from_slice(&line[..kept]);
+        record.len += kept;
+        record.truncated |= kept < line.len();
+        if ended {
+            flush(record);
+        }
+        s = rest;
+    }
+}
+
+/// Prints the record of the line collected in `record` and closes it
+fn flush(record: &mut Record) {
+    record.open = false;
+    emit(
+        record.level,
+        record.file,
+        Body::Message(&record.buf[..record.len], record.truncated),
+    );
+}
+
+/// What a record carries after its common keys
+#[derive(Clone, Copy)]
+enum Body<'a> {
+    /// A line of text, and whether it was cut
+    Message(&'a [u8], bool),
+    /// An event with its fields
+    Event(&'a [u8], &'a [(&'a [u8], Value<'a>)]),
+}
+
+/// Value of an event field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// Decimal number
+    Dec(u64),
+    /// Hexadecimal number with a `0x` prefix
+    Hex(u64),
+    /// Identifier written as is, without spaces, quotes or `=`
+    Name(&'a [u8]),
+    /// Free text, quoted and escaped like messages
+    Str(&'a [u8]),
+    /// Bytes as lowercase hex digits, e.g. a digest
+    Bytes(&'a [u8]),
+}
+
+/// Prints a record to the sinks of `level`, on a console line of its own
+fn emit(level: Level, file: &str, body: Body) {
+    if sinks(level) & SINK_CONSOLE != 0 && !pl011::at_line_start() {
+        pl011::print(b"\n");
+    }
+    write_record(
+        &mut |s| write_sinks(level, s),
+        generic::uptime_us(),
+        level,
+        file,
+        body,
+    );
+    write_sinks(level, b"\n");
+}
+
+/// Returns the syslog severity of `level`
+const fn severity(level: Level) -> u64 {
+    return match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug => 7,
+    };
+}
+
+/// Escapes `byte` for a quoted value into `out` and returns its length
+const fn escape(byte: u8, out: &mut [u8; 4]) -> usize {
+    let simple = match byte {
+        b'"' => b'"',
+        b'\\' => b'\\',
+        b'\t' => b't',
+        b'\r' => b'r',
+        b'\n' => b'n',
+        0x20..=0x7e => {
+            out[0] = byte;
+            return 1;
+        }
+        _ => 0,
+    };
+
+    out[0] = b'\\';
+    if simple != 0 {
+        out[1] = simple;
+        return 2;
+    }
+    out[1] = b'x';
+    out[2] = HEX_CHARS[(byte >> 4) as usize];
+    out[3] = HEX_CHARS[(byte & 0xf) as usize];
+    return 4;
+}
+
+/// Checks that `byte` escapes to `expected`, in a const context
+const fn escapes_to(byte: u8, expected: &[u8]) -> bool {
+    let mut out = [0u8; 4];
+    let len = escape(byte, &mut out);
+    let mut i = 0;
+
+    if len != expected.len() {
+        return false;
+    }
+    while i < len {
+        if out[i] != expected[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(escapes_to(b'a', b"a") && escapes_to(b' ', b" "));
+const _: () = assert!(escapes_to(b'"', b"\\\"") && escapes_to(b'\\', b"\\\\"));
+const _: () = assert!(escapes_to(b'\n', b"\\n") && escapes_to(b'\t', b"\\t"));
+const _: () = assert!(escapes_to(0x1b, b"\\x1b") && escapes_to(0xff, b"\\xff"));
+const _: () = assert!(escapes_to(0x7f, b"\\x7f") && escapes_to(0, b"\\x00"));
+
+/// Writes `s` quoted and escaped through `out`
+fn write_quoted(out: &mut dyn FnMut(&[u8]), s: &[u8]) {
+    let mut buf = [0u8; 4];
+
+    out(b"\"");
+    for &byte in s {
+        let len = escape(byte, &mut buf);
+        out(&buf[..len]);
+    }
+    out(b"\"");
+}
+
+/// Writes the module of source file `file` through `out`, e.g.
+/// `drivers::uart::pl011` for `src/drivers/uart/pl011.rs`
+fn write_module(out: &mut dyn FnMut(&[u8]), file: &str) {
+    let path = file.as_bytes();
+    let start = path
+        .windows(4)
+        .rposition(|w| w == b"src/")
+        .map_or(0, |i| i + 4);
+    let path = &path[start..];
+    let path = path.strip_suffix(b".rs").unwrap_or(path);
+    let path = path.strip_suffix(b"/mod").unwrap_or(path);
+
+    for (i, part) in path.split(|&c| c == b'/').enumerate() {
+        if i != 0 {
+            out(b"::");
+        }
+        out(part);
+    }
+}
+
+/// Writes the record of `body`, printed at `level` by code in `file` at
+/// uptime `ts`, through `out`, without the final newline
+fn write_record(out: &mut dyn FnMut(&[u8]), ts: u64, level: Level, file: &str, body: Body) {
+    let mut buf = [0u8; 20];
+
+    out(b"ts=");
+    out(u64_to_dec(ts, &mut buf));
+    out(b" level=");
+    out(u64_to_dec(severity(level), &mut buf));
+    out(b" mod=");
+    write_module(out, file);
+    match body {
+        Body::Message(msg, truncated) => {
+            out(b" msg=");
+            write_quoted(out, msg);
+            if truncated {
+                out(b" trunc=1");
+            }
+        }
+        Body::Event(name, fields) => {
+            out(b" event=");
+            out(name);
+            for &(key, value) in fields {
+                out(b" ");
+                out(key);
+                out(b"=");
+                match value {
+                    Value::Dec(n) => out(u64_to_dec(n, &mut buf)),
+                    Value::Hex(n) => {
+                        out(b"0x");
+                        out(u64_to_hex(n, &mut buf));
+                    }
+                    Value::Name(name) => out(name),
+                    Value::Str(s) => write_quoted(out, s),
+                    Value::Bytes(bytes) => {
+                        for &byte in bytes {
+                            out(u64_to_hex_padded(byte as u64, &mut buf[..2]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Prints the event `name` with `fields` as a record at `level`
+///
+/// Events are only printed in the formats that have records; see the
+/// [module documentation](self#structured-records) for the well-known ones.
+#[track_caller]
+pub fn event(level: Level, name: &[u8], fields: &[(&[u8], Value)]) {
+    if format() == Format::Text {
+        return;
+    }
+    emit(level, Location::caller().file(), Body::Event(name, fields));
+}
+
+/// Prints the `stage` event marking the end of boot stage `name`, with the
+/// time since the previous stage ended
+#[track_caller]
+pub fn stage(name: &[u8]) {
+    let now = generic::uptime_us();
+    let last = unsafe { LAST_STAGE_US };
+
+    unsafe {
+        LAST_STAGE_US = now;
+    }
+    event(
+        Level::Info,
+        b"stage",
+        &[
+            (b"stage", Value::Name(name)),
+            (b"us", Value::Dec(now.saturating_sub(last))),
+        ],
+    );
+}
+
+/// Mirrors all console output to the debug channel, or stops
+///
+/// Returns `false`, doing nothing, if there is no debug channel.
+pub fn set_tee(enable: bool) -> bool {
+    if debug_channel() == DebugChannel::None {
+        return false;
+    }
+    match enable {
+        true => pl011::set_mirror(Some(mirror)),
+        false => pl011::set_mirror(None),
+    };
+    unsafe {
+        MIRRORING = enable;
+    }
+    return true;
+}
+
+/// Checks whether all console output is mirrored to the debug channel
+pub fn tee_enabled() -> bool {
+    unsafe {
+        return MIRRORING;
+    }
+}
+
+/// Runs `f`, mirroring everything it prints to the console to the debug
+/// channel
+///
+/// Used for output that must reach both sinks, such as exception dumps.
+pub fn to_all_sinks<R>(f: impl FnOnce() -> R) -> R {
+    if debug_channel() == DebugChannel::None {
+        return f();
+    }
+    let previous = pl011::set_mirror(Some(mirror));
+    let mirrored = unsafe { MIRRORING };
+
+    unsafe {
+        MIRRORING = true;
+    }
+    let result = f();
+    pl011::set_mirror(previous);
+    unsafe {
+        MIRRORING = mirrored;
+    }
+    return result;
+}
+
+/// Counts an occurrence of the warning `key`, and returns whether it's among
+/// the first [`RATELIMIT_BURST`] ones, to be printed
+///
+/// When all [`RATELIMIT_KEYS`] slots are in use, a new key takes the slot
+/// of the least recently seen one, whose suppressed occurrences are still
+/// counted in the totals.
+pub fn ratelimit(key: &'static [u8]) -> bool {
+    return interrupt::without_interrupts(|| {
+        let limits = &raw mut RATELIMITS;
+        let limits = unsafe { &mut *limits };
+
+        limits.uses += 1;
+        let slot = match limits
+            .keys
+            .iter()
+            .position(|l| l.is_some_and(|l| l.key == key))
+        {
+            Some(i) => i,
+            None => {
+                let i = match limits.keys.iter().position(|l| l.is_none()) {
+                    Some(i) => i,
+                    None => (0..RATELIMIT_KEYS)
+                        .min_by_key(|&i| limits.keys[i].map_or(0, |l| l.last_use))
+                        .unwrap_or(0),
+                };
+                if let Some(old) = limits.keys[i] {
+                    limits.evicted += old.count.saturating_sub(RATELIMIT_BURST);
+                    limits.evicted_pending += old.pending;
+                }
+                limits.keys[i] = Some(Limited {
+                    key,
+                    count: 0,
+                    pending: 0,
+                    last_use: 0,
+                });
+                i
+            }
+        };
+
+        let Some(limited) = limits.keys[slot].as_mut() else {
+            return false;
+        };
+        limited.count += 1;
+        limited.last_use = limits.uses;
+        if limited.count <= RATELIMIT_BURST {
+            return true;
+        }
+        limited.pending += 1;
+        return false;
+    });
+}
+
+/// Prints `parts` as a warning line, unless `key` is suppressed, see
+/// [`ratelimit`]
+#[track_caller]
+pub fn warn_ratelimited(key: &'static [u8], parts: &[&[u8]]) {
+    if !ratelimit(key) {
+        return;
+    }
+    for part in parts {
+        print(Level::Warn, part);
+    }
+    print(Level::Warn, b"\n");
+}
+
+/// Prints a warning line made of byte strings, unless its key was seen more
+/// than [`RATELIMIT_BURST`](crate::log::RATELIMIT_BURST) times
+///
+/// `log_warn_ratelimited!(b"uart-rx", b"UART receive error: ", kind)`
+/// counts the warning under the key `uart-rx`; see
+/// [`log::ratelimit`](crate::log::ratelimit).
+#[macro_export]
+macro_rules! log_warn_ratelimited {
+    ($key:expr, $($part:expr),+ $(,)?) => {
+        $crate::log::warn_ratelimited($key, &[$($part),+])
+    };
+}
+
+/// Name the evicted warnings are summarized under
+const OTHER_KINDS: &[u8] = b"of other kinds";
+
+/// Takes the suppressed occurrences counted since the last summary, as
+/// `(key, count)`, with a count of 0 for unused entries
+fn take_pending() -> [(&'static [u8], u64); RATELIMIT_KEYS + 1] {
+    return interrupt::without_interrupts(|| {
+        let limits = &raw mut RATELIMITS;
+        let limits = unsafe { &mut *limits };
+        let mut pending: [(&[u8], u64); RATELIMIT_KEYS + 1] = [(b"", 0); RATELIMIT_KEYS + 1];
+
+        for (slot, limited) in pending.iter_mut().zip(limits.keys.iter_mut().flatten()) {
+            *slot = (limited.key, limited.pending);
+            limited.pending = 0;
+        }
+        pending[RATELIMIT_KEYS] = (OTHER_KINDS, limits.evicted_pending);
+        limits.evicted_pending = 0;
+        limits.last_summary = generic::uptime_ms();
+        return pending;
+    });
+}
+
+/// Prints how many times each warning was suppressed since the last summary
+fn summarize() {
+    let limits = &raw const RATELIMITS;
+    let mut buf = [0u8; 20];
+
+    // Cheap check first, as this runs before every line
+    let any = unsafe {
+        (*limits).evicted_pending != 0 || (*limits).keys.iter().flatten().any(|l| l.pending != 0)
+    };
+    if !any || unsafe { SUMMARIZING } {
+        return;
+    }
+    unsafe {
+        SUMMARIZING = true;
+    }
+    for (key, count) in take_pending().into_iter().filter(|&(_, count)| count != 0) {
+        print(Level::Warn, b"Warning ");
+        print(Level::Warn, key);
+        print(Level::Warn, b" repeated ");
+        print(Level::Warn, u64_to_dec(count, &mut buf));
+        println(Level::Warn, b" more times");
+    }
+    unsafe {
+        SUMMARIZING = false;
+    }
+}
+
+/// Prints the summaries of suppressed warnings if the last one was long
+/// enough ago, called on every timer tick
+///
+/// Nothing is printed in the middle of a console line.
+pub fn tick() {
+    let last = unsafe { RATELIMITS.last_summary };
+
+    if generic::uptime_ms().saturating_sub(last) >= SUMMARY_INTERVAL_MS
+        && pl011::at_line_start()
+        && !record_open()
+    {
+        summarize();
+    }
+}
+
+/// Prints the pending summaries, then how many times each warning was
+/// suppressed in total, if any was
+pub fn report_suppressed() {
+    let limits = &raw const RATELIMITS;
+    let mut buf = [0u8; 20];
+
+    summarize();
+    let limits = interrupt::without_interrupts(|| unsafe { *limits });
+    let suppressed = |l: &Limited| l.count.saturating_sub(RATELIMIT_BURST);
+    if limits.evicted == 0 && limits.keys.iter().flatten().all(|l| suppressed(l) == 0) {
+        return;
+    }
+    println(Level::Warn, b"Suppressed warnings:");
+    for limited in limits.keys.iter().flatten().filter(|l| suppressed(l) != 0) {
+        print(Level::Warn, b"  ");
+        print(Level::Warn, limited.key);
+        print(Level::Warn, b": ");
+        println(Level::Warn, u64_to_dec(suppressed(limited), &mut buf));
+    }
+    if limits.evicted != 0 {
+        print(Level::Warn, b"  ");
+        print(Level::Warn, OTHER_KINDS);
+        print(Level::Warn, b": ");
+        println(Level::Warn, u64_to_dec(limits.evicted, &mut buf));
+    }
+}
+
+/// Size of the register space of a PL011, in 32-bit registers
+const TEST_UART_REGS: usize = 0x1000 / 4;
+/// Value of the data registers of the self-test before anything is written
+const TEST_UNWRITTEN: u32 = 0xffff_ffff;
+
+/// Register spaces standing in for the console and the debug UART in the
+/// self-test, in that order
+static mut TEST_UARTS: [[u32; TEST_UART_REGS]; 2] = [[0; TEST_UART_REGS]; 2];
+
+/// Resets the register spaces of [`TEST_UARTS`] and returns their base
+/// addresses, console first
+fn reset_test_uarts() -> [usize; 2] {
+    let uarts = &raw mut TEST_UARTS;
+    let uarts = unsafe { &mut *uarts };
+
+    for regs in uarts.iter_mut() {
+        regs.fill(0);
+        regs[0] = TEST_UNWRITTEN;
+        regs[pl011::ID_OFF / 4..].copy_from_slice(&pl011::PL011_ID);
+    }
+    return uarts.each_mut().map(|regs| regs.as_mut_ptr() as usize);
+}
+
+/// Self-test: with a debug UART set up besides the console, [`debug_print`]
+/// writes to the data register of the debug UART and [`pl011::print`] to
+/// the console's
+///
+/// Both UARTs are register spaces in RAM, identified as PL011s, where the
+/// last byte written stays in the data register. The debug channel, the
+/// level masks, the console and its mirror are put back afterwards.
+pub fn selftest() -> Outcome {
+    let (channel, sinks) = unsafe { (CHANNEL, SINKS) };
+    let [console, debug] = reset_test_uarts();
+    let data = |base: usize| unsafe { ptr::read_volatile(base as *const u32) };
+
+    if !init_debug_uart(debug, 24_000_000, 115_200) {
+        return Outcome::Fail;
+    }
+    debug_print(b"d");
+    let to_debug = data(debug) == b'd' as u32 && data(console) == TEST_UNWRITTEN;
+
+    // A NUL, which the capture keeps but the display doesn't show
+    let mirror = pl011::set_mirror(None);
+    let previous = pl011::retarget(console);
+    pl011::print(b"\0");
+    pl011::retarget(previous);
+    pl011::set_mirror(mirror);
+    let to_console = data(console) == 0 && data(debug) == b'd' as u32;
+
+    unsafe {
+        CHANNEL = channel;
+        SINKS = sinks;
+    }
+    if to_debug && to_console {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Self-test: [`set_tee`] refuses to start without a debug channel; with
+/// one, each byte printed to the console reaches the debug UART too, before
+/// the next is printed, until the tee is turned off
+///
+/// The UARTs are the register spaces of [`selftest`](fn@selftest). The
+/// debug channel, the level masks, the console, its mirror and the tee are
+/// put back afterwards.
+pub fn selftest_tee() -> Outcome {
+    let (channel, sinks, tee) = unsafe { (CHANNEL, SINKS, MIRRORING) };
+    let [console, debug] = reset_test_uarts();
+    let data = |base: usize| unsafe { ptr::read_volatile(base as *const u32) };
+
+    unsafe {
+        CHANNEL = DebugChannel::None;
+    }
+    let refused = !set_tee(true);
+    if !init_debug_uart(debug, 24_000_000, 115_200) {
+        return Outcome::Fail;
+    }
+
+    let mirror = pl011::set_mirror(None);
+    let previous = pl011::retarget(console);
+    let started = set_tee(true);
+    let mut in_order = true;
+    for &c in b"tee" {
+        pl011::print(&[c]);
+        in_order &= data(console) == c as u32 && data(debug) == c as u32;
+    }
+    set_tee(false);
+    pl011::print(b"!");
+    let stopped = data(console) == b'!' as u32 && data(debug) == b'e' as u32;
+    pl011::retarget(previous);
+    pl011::set_mirror(mirror);
+
+    unsafe {
+        CHANNEL = channel;
+        SINKS = sinks;
+        MIRRORING = tee;
+    }
+    if refused && started && in_order && stopped {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Keys of the rate-limit self-test, one more than there are slots
+const TEST_KEYS: [&[u8]; RATELIMIT_KEYS + 1] = [
+    b"test-0", b"test-1", b"test-2", b"test-3", b"test-4", b"test-5", b"test-6", b"test-7",
+    b"test-8",
+];
+
+/// Self-test: a key is let through [`RATELIMIT_BURST`] times, then its
+/// occurrences are counted as pending; when the slots run out, the least
+/// recently seen key is evicted and its counts go to the totals of other
+/// kinds
+///
+/// The counters are put back afterwards, so nothing is printed.
+pub fn selftest_ratelimit() -> Outcome {
+    let limits = &raw mut RATELIMITS;
+
+    return interrupt::without_interrupts(|| {
+        let saved = unsafe { *limits };
+        unsafe {
+            (*limits).keys = [None; RATELIMIT_KEYS];
+            (*limits).evicted = 0;
+            (*limits).evicted_pending = 0;
+        }
+
+        let allowed = (0..RATELIMIT_BURST + 2)
+            .filter(|_| ratelimit(TEST_KEYS[0]))
+            .count();
+        let burst = allowed as u64 == RATELIMIT_BURST;
+        // Fill the other slots, key 1 with two suppressed occurrences, then
+        // see key 0 again so key 1 is the least recent one
+        (0..RATELIMIT_BURST + 2).for_each(|_| _ = ratelimit(TEST_KEYS[1]));
+        TEST_KEYS[2..RATELIMIT_KEYS]
+            .iter()
+            .for_each(|&key| _ = ratelimit(key));
+        ratelimit(TEST_KEYS[0]);
+        ratelimit(TEST_KEYS[RATELIMIT_KEYS]);
+        let RateLimits {
+            keys,
+            evicted,
+            evicted_pending,
+            ..
+        } = unsafe { *limits };
+        let find = |key: &[u8]| keys.iter().flatten().find(|l| l.key == key).copied();
+        let evicted = find(TEST_KEYS[1]).is_none()
+            && find(TEST_KEYS[RATELIMIT_KEYS]).is_some()
+            && evicted == 2
+            && evicted_pending == 2;
+        let counted =
+            find(TEST_KEYS[0]).is_some_and(|l| l.count == RATELIMIT_BURST + 3 && l.pending == 3);
+
+        unsafe {
+            *limits = saved;
+        }
+        if burst && evicted && counted {
+            return Outcome::Pass;
+        }
+        return Outcome::Fail;
+    });
+}
+
+/// Checks that the record of `body` is `expected`
+fn record_is(ts: u64, level: Level, file: &str, body: Body, expected: &[u8]) -> bool {
+    let mut buf = [0u8; 192];
+    let mut len = 0;
+    let mut fits = true;
+
+    write_record(
+        &mut |s| match buf.get_mut(len..len + s.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(s);
+                len += s.len();
+            }
+            None => fits = false,
+        },
+        ts,
+        level,
+        file,
+        body,
+    );
+    return fits && buf[..len] == *expected;
+}
+
+/// Self-test: messages and events come out as records with their module,
+/// severity and escaped text
+pub fn selftest_records() -> Outcome {
+    let message = Body::Message(b"a \"b\"\\\t\x1b", true);
+    let fields = [
+        (&b"component"[..], Value::Name(b"kernel")),
+        (b"size", Value::Dec(42)),
+        (b"entry", Value::Hex(0x4008_0000)),
+        (b"sha256", Value::Bytes(&[0xab, 0x01])),
+        (b"source", Value::Str(b"ram@0x40")),
+    ];
+    let event = Body::Event(b"measure", &fields);
+
+    let ok = record_is(
+        1234,
+        Level::Warn,
+        "/build/src/drivers/uart/pl011.rs",
+        message,
+        b"ts=1234 level=4 mod=drivers::uart::pl011 msg=\"a \\\"b\\\"\\\\\\t\\x1b\" trunc=1",
+    ) && record_is(
+        0,
+        Level::Info,
+        "src/memory/mod.rs",
+        event,
+        b"ts=0 level=6 mod=memory event=measure component=kernel size=42 entry=0x40080000 \
+          sha256=ab01 source=\"ram@0x40\"",
+    );
+    if ok {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Registers the self-tests of the debug channel, the rate limiting and the
+/// structured records
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"debug-uart",
+        run: selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"tee",
+        run: selftest_tee,
+    });
+    selftest::register(SelfTest {
+        name: b"ratelimit",
+        run: selftest_ratelimit,
+    });
+    selftest::register(SelfTest {
+        name: b"logfmt",
+        run: selftest_records,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::print::CaptureSink;
+
+    #[test]
+    fn tee_writes_both_consoles() {
+        let mut first = [0u8; 16];
+        let mut second = [0u8; 16];
+        let mut tee = TeeConsole::new(CaptureSink::new(&mut first), CaptureSink::new(&mut second));
+
+        tee.write(b"tee");
+        tee.writeln(b"!");
+        let (first, second) = tee.into_parts();
+        assert_eq!(first.bytes(), b"tee!\n");
+        assert_eq!(second.bytes(), b"tee!\n");
+    }
+
+    #[test]
+    fn tee_overflow_is_per_console() {
+        let mut short = [0u8; 2];
+        let mut long = [0u8; 16];
+        let mut tee = TeeConsole::new(CaptureSink::new(&mut short), CaptureSink::new(&mut long));
+
+        tee.write(b"tee");
+        let (short, long) = tee.into_parts();
+        assert_eq!(short.bytes(), b"te");
+        assert!(short.overflowed());
+        assert_eq!(long.bytes(), b"tee");
+        assert!(!long.overflowed());
+    }
+}
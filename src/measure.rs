@@ -0,0 +1,312 @@
+//! Measured-boot event log
+//!
+//! This module keeps an auditable record of exactly what was booted, even
+//! without a TPM. Every component the bootloader loads is hashed with SHA-256
+//! and appended to a TCG-style event log kept in a reserved RAM region, whose
+//! address and size are handed to the payload through the
+//! [`BootInfo`](crate::boot::BootInfo) block and, as
+//! [`CHOSEN_BASE_PROPERTY`] and [`CHOSEN_SIZE_PROPERTY`], the DTB's `/chosen`
+//! node.
+//!
+//! # Log format
+//!
+//! The log starts with a [`LogHeader`] followed by variable-size entries. Each
+//! entry is an [`EventHeader`] followed by the component name and the source
+//! description (not NUL-terminated), padded to 8 bytes. All values are
+//! little-endian.
+//!
+//! The log is append-only: an entry is fully written before the header is
+//! updated to include it. The header carries a CRC-32 of all entries and a
+//! CRC-32 of itself, so a truncated or torn write is detectable by
+//! [`verify`]. The header also carries the [boot ID](crate::bootid), tying
+//! the log to the console output of the boot that wrote it.
+
+use crate::bootid;
+use crate::drivers::uart::pl011;
+use crate::log::{self, Level, Value};
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+use crate::utilities::crc32::{crc32, crc32_update};
+use crate::utilities::print::{print_hex_u8, u64_to_dec};
+use crate::utilities::sha256::sha256;
+
+use core::mem::{self, offset_of};
+use core::{ptr, slice};
+
+/// Log magic number: the ASCII string "MEASLOG\0" read as a little-endian u64
+pub const LOG_MAGIC: u64 = 0x00474f4c5341454d;
+/// Current version of the log format
+pub const LOG_VERSION: u32 = 2;
+/// Size of the reserved log region in bytes
+pub const LOG_SIZE: usize = 16 * 1024;
+/// `/chosen` property holding the address of the log region, as a 64-bit
+/// big-endian number
+pub const CHOSEN_BASE_PROPERTY: &[u8] = b"bootloader,event-log-base";
+/// `/chosen` property holding the size of the log region, as a 64-bit
+/// big-endian number
+pub const CHOSEN_SIZE_PROPERTY: &[u8] = b"bootloader,event-log-size";
+/// PCR index recorded for boot components (PCR 4: boot manager code)
+const PCR_BOOT_COMPONENTS: u32 = 4;
+/// TCG event type for an initial program loader component (EV_IPL)
+const EV_IPL: u32 = 0x0000000d;
+
+/// Header at the start of the event log
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct LogHeader {
+    /// Always [`LOG_MAGIC`]
+    pub magic: u64,
+    /// Format version, currently [`LOG_VERSION`]
+    pub version: u32,
+    /// Size of this header in bytes
+    pub header_size: u32,
+    /// Size of the whole log region in bytes
+    pub capacity: u32,
+    /// Bytes of entries following the header
+    pub used: u32,
+    /// Number of entries
+    pub count: u32,
+    /// CRC-32 of the `used` bytes of entries
+    pub entries_crc: u32,
+    /// CRC-32 of this header with this field set to 0
+    pub header_crc: u32,
+    /// Reserved for future use, always 0
+    pub reserved0: u32,
+    /// [Boot ID](crate::bootid) of the boot that wrote the log (added in
+    /// version 2)
+    pub boot_id: u64,
+}
+
+const _: () = assert!(mem::size_of::<LogHeader>() == 48);
+const _: () = assert!(offset_of!(LogHeader, header_crc) == 32);
+const _: () = assert!(offset_of!(LogHeader, boot_id) == 40);
+
+/// Header of a single event log entry
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EventHeader {
+    /// PCR the event would be extended into on a TPM
+    pub pcr_index: u32,
+    /// TCG event type
+    pub event_type: u32,
+    /// SHA-256 digest of the component
+    pub digest: [u8; 32],
+    /// Size of the component in bytes
+    pub component_size: u64,
+    /// Length of the component name following this header
+    pub name_len: u16,
+    /// Length of the source description following the name
+    pub desc_len: u16,
+    /// Total size of this entry including header and padding
+    pub entry_size: u32,
+}
+
+const _: () = assert!(mem::size_of::<EventHeader>() == 56);
+
+/// Errors reported by the event log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeasureError {
+    /// The log was not initialized
+    NotInitialized,
+    /// No RAM could be reserved for the log
+    NoSpace(ReserveError),
+    /// The entry doesn't fit in the remaining log space
+    LogFull,
+    /// The header or entries checksum doesn't match
+    Corrupted,
+}
+
+/// Base address of the log region, 0 until `init` succeeds
+static mut LOG_BASE: usize = 0;
+
+/// Reserves the log region and writes an empty log header
+///
+/// Calling it again after a successful initialization is a no-op.
+pub fn init() -> Result<(), MeasureError> {
+    if log_base() != 0 {
+        return Ok(());
+    }
+
+    let base = reserve::allocate(LOG_SIZE, 4096, None, ReserveTag::EventLog)
+        .map_err(MeasureError::NoSpace)?;
+    let header = LogHeader {
+        magic: LOG_MAGIC,
+        version: LOG_VERSION,
+        header_size: mem::size_of::<LogHeader>() as u32,
+        capacity: LOG_SIZE as u32,
+        used: 0,
+        count: 0,
+        entries_crc: 0,
+        header_crc: 0,
+        reserved0: 0,
+        boot_id: bootid::id(),
+    };
+
+    unsafe {
+        ptr::write_bytes(base as *mut u8, 0, LOG_SIZE);
+        LOG_BASE = base;
+    }
+    write_header(header);
+    return Ok(());
+}
+
+/// Returns the base address and size of the log region, if initialized
+pub fn region() -> Option<(usize, usize)> {
+    match log_base() {
+        0 => return None,
+        base => return Some((base, LOG_SIZE)),
+    }
+}
+
+/// Returns the base address of the log region, 0 if not initialized
+fn log_base() -> usize {
+    unsafe {
+        return LOG_BASE;
+    }
+}
+
+/// Returns a copy of the log header
+fn read_header() -> LogHeader {
+    unsafe {
+        return ptr::read_volatile(log_base() as *const LogHeader);
+    }
+}
+
+/// Writes `header` with a freshly computed header checksum
+fn write_header(mut header: LogHeader) {
+    header.header_crc = 0;
+    header.header_crc = crc32(as_bytes(&header));
+
+    unsafe {
+        ptr::write_volatile(log_base() as *mut LogHeader, header);
+    }
+}
+
+/// Returns the raw bytes of a `#[repr(C)]` value without padding
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe {
+        return slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>());
+    }
+}
+
+/// Returns the entries area of the log, `used` bytes long
+fn entries(header: &LogHeader) -> &'static [u8] {
+    let start = log_base() + header.header_size as usize;
+
+    unsafe {
+        return slice::from_raw_parts(start as *const u8, header.used as usize);
+    }
+}
+
+/// Hashes `data` and appends it to the log as component `name`
+///
+/// `source` describes where the component came from (e.g., the address it
+/// was loaded from). Returns the SHA-256 digest of `data`.
+pub fn measure(name: &[u8], data: &[u8], source: &[u8]) -> Result<[u8; 32], MeasureError> {
+    if log_base() == 0 {
+        return Err(MeasureError::NotInitialized);
+    }
+
+    let mut header = read_header();
+    let data_len = name.len().min(u16::MAX as usize) + source.len().min(u16::MAX as usize);
+    let entry_size = (mem::size_of::<EventHeader>() + data_len + 7) & !7;
+    let offset = header.header_size as usize + header.used as usize;
+    if offset + entry_size > header.capacity as usize {
+        return Err(MeasureError::LogFull);
+    }
+
+    let event = EventHeader {
+        pcr_index: PCR_BOOT_COMPONENTS,
+        event_type: EV_IPL,
+        digest: sha256(data),
+        component_size: data.len() as u64,
+        name_len: name.len().min(u16::MAX as usize) as u16,
+        desc_len: source.len().min(u16::MAX as usize) as u16,
+        entry_size: entry_size as u32,
+    };
+
+    // Write the whole entry before publishing it in the header
+    let entry = unsafe { slice::from_raw_parts_mut((log_base() + offset) as *mut u8, entry_size) };
+    let (head, rest) = entry.split_at_mut(mem::size_of::<EventHeader>());
+    let (name_buf, rest) = rest.split_at_mut(event.name_len as usize);
+    let (desc_buf, pad) = rest.split_at_mut(event.desc_len as usize);
+    head.copy_from_slice(as_bytes(&event));
+    name_buf.copy_from_slice(&name[..name_buf.len()]);
+    desc_buf.copy_from_slice(&source[..desc_buf.len()]);
+    pad.fill(0);
+
+    header.entries_crc = crc32_update(header.entries_crc, entry);
+    header.used += entry_size as u32;
+    header.count += 1;
+    write_header(header);
+
+    log::event(
+        Level::Info,
+        b"measure",
+        &[
+            (b"component", Value::Name(name)),
+            (b"size", Value::Dec(data.len() as u64)),
+            (b"sha256", Value::Bytes(&event.digest)),
+            (b"source", Value::Str(source)),
+        ],
+    );
+    return Ok(event.digest);
+}
+
+/// Checks the log header and entries against their checksums
+pub fn verify() -> Result<(), MeasureError> {
+    if log_base() == 0 {
+        return Err(MeasureError::NotInitialized);
+    }
+
+    let mut header = read_header();
+    let header_crc = header.header_crc;
+    header.header_crc = 0;
+    if header.magic != LOG_MAGIC
+        || crc32(as_bytes(&header)) != header_crc
+        || header.header_size as usize + header.used as usize > header.capacity as usize
+        || crc32(entries(&header)) != header.entries_crc
+    {
+        return Err(MeasureError::Corrupted);
+    }
+
+    return Ok(());
+}
+
+/// Prints the event log to the console
+///
+/// Each entry is printed as its name, size, source and SHA-256 digest. A log
+/// that fails [`verify`] is reported as corrupted instead.
+pub fn report() {
+    let mut buf = [0u8; 20];
+
+    if verify().is_err() {
+        pl011::println(b"Measured boot log: missing or corrupted");
+        return;
+    }
+
+    let header = read_header();
+    let mut rest = entries(&header);
+    pl011::print(b"Measured boot log (boot ID ");
+    pl011::print(&bootid::format(header.boot_id));
+    pl011::println(b"):");
+    while rest.len() >= mem::size_of::<EventHeader>() {
+        let event = unsafe { ptr::read_unaligned(rest.as_ptr() as *const EventHeader) };
+        let data = &rest[mem::size_of::<EventHeader>()..];
+        let (name, data) = data.split_at(event.name_len as usize);
+        let desc = &data[..event.desc_len as usize];
+
+        pl011::print(b"  ");
+        pl011::print(name);
+        pl011::print(b": ");
+        pl011::print(u64_to_dec(event.component_size, &mut buf));
+        pl011::print(b" bytes from ");
+        pl011::println(desc);
+        pl011::print(b"    sha256 ");
+        for byte in event.digest {
+            print_hex_u8(byte);
+        }
+        pl011::print(b"\n");
+
+        rest = &rest[event.entry_size as usize..];
+    }
+}
@@ -0,0 +1,493 @@
+//! Memory map
+//!
+//! This module records the physical memory ranges of the machine, usually
+//! discovered from the `/memory` nodes of the device tree, and the device
+//! (MMIO) ranges known to the board. The map is a fixed size table so it can
+//! be built before any allocator exists.
+//!
+//! RAM the device tree reserves, in its memory reservation block or under
+//! `/reserved-memory`, is recorded too (see [`add_reserved_from_fdt`]). Such
+//! a region lies within a RAM region and takes precedence over it: the
+//! bootloader neither allocates from it nor loads a kernel over it.
+//!
+//! [`is_ram`] and [`is_device`] classify an address against the map, e.g.,
+//! to refuse loading or running code at an address that can't hold it.
+
+use crate::board;
+use crate::parsers::fdt::{self, Fdt, FdtError};
+use crate::selftest::Outcome;
+
+use core::slice;
+
+/// Maximum number of regions the memory map can hold
+pub const MAX_REGIONS: usize = 16;
+
+/// Kind of a memory map region
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Normal RAM usable by the bootloader and the payload
+    Ram,
+    /// Device registers, never used to hold code or data
+    Device,
+    /// RAM the device tree reserves, left alone by the bootloader
+    Reserved,
+}
+
+/// A region of the memory map
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    /// Physical base address of the region
+    pub base: usize,
+    /// Size of the region in bytes
+    pub size: usize,
+    /// What the region is
+    pub kind: RegionKind,
+}
+
+impl Region {
+    /// Returns the address one past the end of the region
+    pub fn end(&self) -> usize {
+        return self.base + self.size;
+    }
+}
+
+/// Fixed-size table of memory map regions
+struct MemoryMap {
+    regions: [Region; MAX_REGIONS],
+    count: usize,
+}
+
+impl MemoryMap {
+    /// A map without any region
+    const EMPTY: MemoryMap = MemoryMap {
+        regions: [Region {
+            base: 0,
+            size: 0,
+            kind: RegionKind::Ram,
+        }; MAX_REGIONS],
+        count: 0,
+    };
+
+    /// Adds a region, like [`add_region`]
+    fn add(&mut self, base: usize, size: usize, kind: RegionKind) -> bool {
+        if size == 0 {
+            return true;
+        }
+        if self.count == MAX_REGIONS {
+            return false;
+        }
+        self.regions[self.count] = Region { base, size, kind };
+        self.count += 1;
+        return true;
+    }
+
+    /// Returns the regions of the map
+    fn regions(&self) -> &[Region] {
+        return &self.regions[..self.count];
+    }
+}
+
+/// Global memory map
+static mut MEMORY_MAP: MemoryMap = MemoryMap::EMPTY;
+
+/// Adds a region to the memory map
+///
+/// Empty regions are ignored. Returns `false` if the map is full.
+pub fn add_region(base: usize, size: usize, kind: RegionKind) -> bool {
+    unsafe {
+        let map = &raw mut MEMORY_MAP;
+        return (*map).add(base, size, kind);
+    }
+}
+
+/// Returns all regions of the memory map
+pub fn regions() -> &'static [Region] {
+    unsafe {
+        let map = &raw const MEMORY_MAP;
+        let map = &*map;
+        return &map.regions[..map.count];
+    }
+}
+
+/// Returns the kind of the region of `regions` containing `addr`, if any
+///
+/// A reserved region wins over the RAM region it lies in.
+const fn kind_at(regions: &[Region], addr: usize) -> Option<RegionKind> {
+    let mut kind = None;
+    let mut i = 0;
+
+    while i < regions.len() {
+        if addr >= regions[i].base && addr - regions[i].base < regions[i].size {
+            if matches!(regions[i].kind, RegionKind::Reserved) {
+                return Some(RegionKind::Reserved);
+            }
+            if kind.is_none() {
+                kind = Some(regions[i].kind);
+            }
+        }
+        i += 1;
+    }
+    return kind;
+}
+
+/// Checks whether any of the `len` bytes at `addr` lies in a region of
+/// `regions` of kind `kind`
+fn touches_kind(regions: &[Region], kind: RegionKind, addr: usize, len: usize) -> bool {
+    let end = addr.saturating_add(len);
+
+    return len != 0
+        && regions
+            .iter()
+            .any(|r| r.kind == kind && addr < r.end() && r.base < end);
+}
+
+/// Checks whether the map holds a region of `kind`
+fn has_kind(kind: RegionKind) -> bool {
+    return regions().iter().any(|r| r.kind == kind);
+}
+
+/// Checks whether `addr` is RAM
+///
+/// Until the map holds any RAM, the board's RAM ranges are used instead.
+pub fn is_ram(addr: usize) -> bool {
+    if !has_kind(RegionKind::Ram) {
+        return board::config()
+            .ram
+            .iter()
+            .any(|&(base, size)| addr >= base && addr - base < size);
+    }
+    return kind_at(regions(), addr) == Some(RegionKind::Ram);
+}
+
+/// Checks whether `addr` is device memory
+///
+/// Until the map holds any device region, the board's device ranges are
+/// used instead.
+pub fn is_device(addr: usize) -> bool {
+    if !has_kind(RegionKind::Device) {
+        return board::config()
+            .devices
+            .iter()
+            .any(|&(base, size)| addr >= base && addr - base < size);
+    }
+    return kind_at(regions(), addr) == Some(RegionKind::Device);
+}
+
+/// Checks whether `len` bytes at `addr` lie within a single RAM range
+///
+/// Until the map holds any RAM, the board's RAM ranges are used instead.
+pub fn is_ram_range(addr: usize, len: usize) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    let contains = |base: usize, size: usize| addr >= base && end <= base + size;
+
+    if !has_kind(RegionKind::Ram) {
+        return board::config()
+            .ram
+            .iter()
+            .any(|&(base, size)| contains(base, size));
+    }
+    return regions()
+        .iter()
+        .any(|r| r.kind == RegionKind::Ram && contains(r.base, r.size));
+}
+
+/// Checks whether any of the `len` bytes at `addr` is device memory
+pub fn touches_device(addr: usize, len: usize) -> bool {
+    let end = addr.saturating_add(len);
+
+    if len == 0 {
+        return false;
+    }
+    if !has_kind(RegionKind::Device) {
+        return board::config()
+            .devices
+            .iter()
+            .any(|&(base, size)| addr < base + size && base < end);
+    }
+    return touches_kind(regions(), RegionKind::Device, addr, len);
+}
+
+/// Checks whether any of the `len` bytes at `addr` is reserved RAM
+pub fn touches_reserved(addr: usize, len: usize) -> bool {
+    return touches_kind(regions(), RegionKind::Reserved, addr, len);
+}
+
+/// Adds the board's device ranges to the map
+///
+/// Returns `false` if the map is full.
+pub fn add_board_devices() -> bool {
+    return board::config()
+        .devices
+        .iter()
+        .all(|&(base, size)| add_region(base, size, RegionKind::Device));
+}
+
+// Classification against a map with RAM and a device, at the boundaries
+const _: () = {
+    const MAP: [Region; 2] = [
+        Region {
+            base: 0x0900_0000,
+            size: 0x1000,
+            kind: RegionKind::Device,
+        },
+        Region {
+            base: 0x4000_0000,
+            size: 0x0800_0000,
+            kind: RegionKind::Ram,
+        },
+    ];
+
+    assert!(kind_at(&MAP, 0).is_none());
+    assert!(kind_at(&MAP, 0x08ff_ffff).is_none());
+    assert!(matches!(
+        kind_at(&MAP, 0x0900_0000),
+        Some(RegionKind::Device)
+    ));
+    assert!(matches!(
+        kind_at(&MAP, 0x0900_0fff),
+        Some(RegionKind::Device)
+    ));
+    assert!(kind_at(&MAP, 0x0900_1000).is_none());
+    assert!(kind_at(&MAP, 0x3fff_ffff).is_none());
+    assert!(matches!(kind_at(&MAP, 0x4000_0000), Some(RegionKind::Ram)));
+    assert!(matches!(kind_at(&MAP, 0x47ff_ffff), Some(RegionKind::Ram)));
+    assert!(kind_at(&MAP, 0x4800_0000).is_none());
+    assert!(kind_at(&MAP, usize::MAX).is_none());
+};
+
+// A reserved region wins over the RAM around it, whichever comes first
+const _: () = {
+    const MAP: [Region; 3] = [
+        Region {
+            base: 0x4400_0000,
+            size: 0x1000,
+            kind: RegionKind::Reserved,
+        },
+        Region {
+            base: 0x4000_0000,
+            size: 0x0800_0000,
+            kind: RegionKind::Ram,
+        },
+        Region {
+            base: 0x4500_0000,
+            size: 0x1000,
+            kind: RegionKind::Reserved,
+        },
+    ];
+
+    assert!(matches!(kind_at(&MAP, 0x43ff_ffff), Some(RegionKind::Ram)));
+    assert!(matches!(
+        kind_at(&MAP, 0x4400_0000),
+        Some(RegionKind::Reserved)
+    ));
+    assert!(matches!(kind_at(&MAP, 0x4400_1000), Some(RegionKind::Ram)));
+    assert!(matches!(
+        kind_at(&MAP, 0x4500_0fff),
+        Some(RegionKind::Reserved)
+    ));
+};
+
+/// Adds the RAM ranges described by the `/memory` nodes of `fdt`
+pub fn add_ram_from_fdt(fdt: &Fdt) -> Result<(), FdtError> {
+    return fdt.for_each_memory_range(|base, size| {
+        add_region(base as usize, size as usize, RegionKind::Ram);
+    });
+}
+
+/// Adds the ranges `fdt` reserves to `map`, see [`add_reserved_from_fdt`]
+fn add_fdt_reserved(map: &mut MemoryMap, fdt: &Fdt) -> Result<(), FdtError> {
+    let mut full = false;
+
+    fdt.for_each_reserved_range(|base, size| {
+        full |= !map.add(base as usize, size as usize, RegionKind::Reserved);
+    })?;
+    if full {
+        return Err(FdtError::NoSpace);
+    }
+    return Ok(());
+}
+
+/// Adds the RAM ranges `fdt` reserves, in its memory reservation block and
+/// under `/reserved-memory` (see [`Fdt::for_each_reserved_range`]), as
+/// [`RegionKind::Reserved`] regions
+///
+/// Returns [`FdtError::NoSpace`] if the map couldn't hold them all.
+pub fn add_reserved_from_fdt(fdt: &Fdt) -> Result<(), FdtError> {
+    unsafe {
+        let map = &raw mut MEMORY_MAP;
+        return add_fdt_reserved(&mut *map, fdt);
+    }
+}
+
+/// Number of words of [`TEST_DTB`]
+const TEST_DTB_WORDS: usize = 62;
+/// Size of [`TEST_DTB`]
+const TEST_DTB_SIZE: usize = 4 * TEST_DTB_WORDS;
+/// Offset of the strings block of [`TEST_DTB`]
+const TEST_DTB_STRINGS: u32 = TEST_DTB_SIZE as u32 - 32;
+
+/// Self-test blob: the memory reservation block holds a page at
+/// 0x4800_0000, and `/reserved-memory` a child whose `reg`, in one address
+/// and one size cell, is 64 KiB at 0x4900_0000
+static TEST_DTB: [u32; TEST_DTB_WORDS] = {
+    let header = fdt::FDT_HEADER_SIZE as u32;
+    let words = [
+        // Header
+        fdt::FDT_MAGIC,
+        TEST_DTB_SIZE as u32,
+        header + 32,
+        TEST_DTB_STRINGS,
+        header,
+        17,
+        16,
+        0,
+        31,
+        TEST_DTB_STRINGS - header - 32,
+        // Reservation map: one entry and the terminator
+        0,
+        0x4800_0000,
+        0,
+        0x1000,
+        0,
+        0,
+        0,
+        0,
+        // Root node with an empty name, two address and size cells
+        1,
+        0,
+        3,
+        4,
+        0,
+        2,
+        3,
+        4,
+        15,
+        2,
+        // reserved-memory, one address and size cell
+        1,
+        u32::from_be_bytes(*b"rese"),
+        u32::from_be_bytes(*b"rved"),
+        u32::from_be_bytes(*b"-mem"),
+        u32::from_be_bytes(*b"ory\0"),
+        3,
+        4,
+        0,
+        1,
+        3,
+        4,
+        15,
+        1,
+        // fw@49000000
+        1,
+        u32::from_be_bytes(*b"fw@4"),
+        u32::from_be_bytes(*b"9000"),
+        u32::from_be_bytes(*b"000\0"),
+        3,
+        8,
+        27,
+        0x4900_0000,
+        0x1_0000,
+        2,
+        2,
+        2,
+        9,
+        // Strings block: "#address-cells", "#size-cells", "reg"
+        u32::from_be_bytes(*b"#add"),
+        u32::from_be_bytes(*b"ress"),
+        u32::from_be_bytes(*b"-cel"),
+        u32::from_be_bytes(*b"ls\0#"),
+        u32::from_be_bytes(*b"size"),
+        u32::from_be_bytes(*b"-cel"),
+        u32::from_be_bytes(*b"ls\0r"),
+        u32::from_be_bytes(*b"eg\0\0"),
+    ];
+    let mut blob = [0u32; TEST_DTB_WORDS];
+    let mut i = 0;
+    while i < blob.len() {
+        blob[i] = words[i].to_be();
+        i += 1;
+    }
+    blob
+};
+
+/// Self-test: both ranges a blob reserves, one in its memory reservation
+/// block and one under `/reserved-memory`, land in a map as reserved
+/// regions, which win over the RAM around them
+pub fn selftest_reserved() -> Outcome {
+    let blob = unsafe { slice::from_raw_parts(TEST_DTB.as_ptr() as *const u8, TEST_DTB_SIZE) };
+    let Ok(tree) = Fdt::new(blob) else {
+        return Outcome::Fail;
+    };
+    let mut map = MemoryMap::EMPTY;
+
+    map.add(0x4000_0000, 0x1000_0000, RegionKind::Ram);
+    if add_fdt_reserved(&mut map, &tree).is_err() || map.regions().len() != 3 {
+        return Outcome::Fail;
+    }
+
+    let expected = [
+        (0x47ff_ffff, RegionKind::Ram),
+        (0x4800_0000, RegionKind::Reserved),
+        (0x4800_0fff, RegionKind::Reserved),
+        (0x4800_1000, RegionKind::Ram),
+        (0x48ff_ffff, RegionKind::Ram),
+        (0x4900_0000, RegionKind::Reserved),
+        (0x4900_ffff, RegionKind::Reserved),
+        (0x4901_0000, RegionKind::Ram),
+    ];
+    if expected
+        .iter()
+        .any(|&(addr, kind)| kind_at(map.regions(), addr) != Some(kind))
+    {
+        return Outcome::Fail;
+    }
+    if !touches_kind(map.regions(), RegionKind::Reserved, 0x48ff_f000, 0x2000)
+        || touches_kind(map.regions(), RegionKind::Reserved, 0x4801_0000, 0x1000)
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn test_fdt() -> Fdt<'static> {
+        let blob = unsafe { slice::from_raw_parts(TEST_DTB.as_ptr() as *const u8, TEST_DTB_SIZE) };
+        return Fdt::new(blob).unwrap();
+    }
+
+    #[test]
+    fn reserved_ranges_of_both_kinds() {
+        let mut ranges = Vec::new();
+
+        test_fdt()
+            .for_each_reserved_range(|base, size| ranges.push((base, size)))
+            .unwrap();
+        assert_eq!(ranges, [(0x4800_0000, 0x1000), (0x4900_0000, 0x1_0000)]);
+    }
+
+    #[test]
+    fn reserved_self_test_passes() {
+        assert_eq!(selftest_reserved(), Outcome::Pass);
+    }
+
+    #[test]
+    fn reserved_without_room() {
+        let mut map = MemoryMap::EMPTY;
+
+        for i in 0..MAX_REGIONS - 1 {
+            assert!(map.add(i * 0x1000, 0x1000, RegionKind::Device));
+        }
+        assert_eq!(
+            add_fdt_reserved(&mut map, &test_fdt()),
+            Err(FdtError::NoSpace)
+        );
+        assert_eq!(map.regions().len(), MAX_REGIONS);
+        assert_eq!(map.regions()[MAX_REGIONS - 1].base, 0x4800_0000);
+    }
+}
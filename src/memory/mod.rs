@@ -0,0 +1,44 @@
+//! Physical memory bookkeeping
+//!
+//! This module keeps track of which physical memory the bootloader may use
+//! when placing the images it hands to the payload.
+//!
+//! # Submodules
+//!
+//! - [`map`]: The memory map, listing the RAM ranges of the machine and
+//!   those the device tree reserves
+//! - [`reserve`]: The reservation registry, listing the ranges already in use
+//!   (bootloader, kernel, DTB, ...) and finding free space between them
+//! - [`test`]: A pattern test of free RAM, run as a self-test
+//! - [`poison`]: Poisoning of freed memory, checked for stale writes in
+//!   debug builds
+//! - [`space`]: Planning of the room an image needs to be loaded, before
+//!   anything is copied
+
+use crate::selftest::{self, SelfTest};
+
+pub mod map;
+pub mod poison;
+pub mod reserve;
+pub mod space;
+pub mod test;
+
+/// Registers the self-tests of the memory subsystem
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"memory",
+        run: test::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"reserved-memory",
+        run: map::selftest_reserved,
+    });
+    selftest::register(SelfTest {
+        name: b"poison",
+        run: poison::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"space-plan",
+        run: space::selftest,
+    });
+}
@@ -0,0 +1,179 @@
+//! Poisoning of freed memory
+//!
+//! Code still using memory after it was given up, such as a staged image
+//! read again after the kernel was loaded from it, works by chance until
+//! something else is placed there. To catch such bugs in debug builds,
+//! memory is filled with [`POISON`] when it's given up: a reservation
+//! dropped with [`reserve::discard`](crate::memory::reserve::discard), e.g.
+//! the staged kernel once it's loaded. The poisoned ranges are remembered,
+//! and [`scan`] reports any of their bytes that changed since, meaning
+//! something wrote where nothing should have; it runs before the handoff.
+//! Reading freed memory shows up as the recognizable pattern instead.
+//!
+//! A poisoned range reserved again is forgotten, as its new owner may write
+//! it. At most [`MAX_POISONED`] ranges are remembered; the ones past that
+//! are still poisoned, but not scanned.
+//!
+//! Poisoning only happens in debug builds with the `poison` feature (see
+//! [`ENABLED`]); otherwise nothing is filled or scanned. [`check`] and the
+//! monitor's `poison check` work on any range whatever the build.
+
+use crate::drivers::uart::pl011;
+use crate::selftest::Outcome;
+use crate::utilities::print::{u64_to_dec, u64_to_hex};
+
+use core::ptr;
+
+/// Whether freed memory is poisoned: only in debug builds with the `poison`
+/// feature
+pub const ENABLED: bool = cfg!(all(feature = "poison", debug_assertions));
+/// Pattern written over freed memory, as little-endian 32-bit words
+pub const POISON: u32 = 0xdeadbeef;
+/// Maximum number of poisoned ranges remembered
+pub const MAX_POISONED: usize = 16;
+
+/// Poisoned ranges, as `(base, len)`, `None` for free slots
+static mut POISONED: [Option<(usize, usize)>; MAX_POISONED] = [None; MAX_POISONED];
+
+/// Returns the byte of the pattern expected at `addr`
+///
+/// The pattern follows the address, so a range reads the same wherever it
+/// starts.
+fn expected(addr: usize) -> u8 {
+    return POISON.to_le_bytes()[addr % 4];
+}
+
+/// Fills the `len` bytes at `base` with the pattern
+///
+/// The first word is written a byte at a time, then the filled part is
+/// copied after itself, doubling it each time, so the bulk goes through the
+/// compiler's optimized `memcpy` instead of a byte loop.
+///
+/// # Safety
+///
+/// The `len` bytes at `base` must be writable and not in use.
+pub unsafe fn fill(base: usize, len: usize) {
+    let mut filled = len.min(4);
+
+    for addr in base..base + filled {
+        unsafe { ptr::write(addr as *mut u8, expected(addr)) };
+    }
+    // `filled` stays a multiple of 4, keeping the pattern in phase
+    while filled < len {
+        let count = filled.min(len - filled);
+        unsafe { ptr::copy_nonoverlapping(base as *const u8, (base + filled) as *mut u8, count) };
+        filled += count;
+    }
+}
+
+/// Poisons the `len` bytes at `base` and remembers them for [`scan`], if
+/// [`ENABLED`]
+///
+/// # Safety
+///
+/// The `len` bytes at `base` must be writable and no longer in use.
+pub unsafe fn poison(base: usize, len: usize) {
+    let poisoned = &raw mut POISONED;
+
+    if !ENABLED || len == 0 {
+        return;
+    }
+    unsafe {
+        fill(base, len);
+        if let Some(slot) = (*poisoned).iter_mut().find(|r| r.is_none()) {
+            *slot = Some((base, len));
+        }
+    }
+}
+
+/// Forgets the poisoned ranges overlapping the `len` bytes at `base`, which
+/// are in use again
+pub fn forget(base: usize, len: usize) {
+    let poisoned = &raw mut POISONED;
+
+    for range in unsafe { (*poisoned).iter_mut() } {
+        if range.is_some_and(|(b, l)| b < base.saturating_add(len) && base < b + l) {
+            *range = None;
+        }
+    }
+}
+
+/// Calls `f` with the address of every byte of the `len` bytes at `base`
+/// that doesn't hold the pattern, and returns how many there are
+///
+/// # Safety
+///
+/// The `len` bytes at `base` must be readable.
+pub unsafe fn check(base: usize, len: usize, f: &mut dyn FnMut(usize)) -> usize {
+    let mut changed = 0;
+
+    for addr in base..base + len {
+        if unsafe { ptr::read_volatile(addr as *const u8) } != expected(addr) {
+            f(addr);
+            changed += 1;
+        }
+    }
+    return changed;
+}
+
+/// Checks every poisoned range, printing the ones written since they were
+/// poisoned, and returns how many bytes changed
+pub fn scan() -> usize {
+    let poisoned = &raw const POISONED;
+    let mut total = 0;
+
+    for &(base, len) in unsafe { (*poisoned).iter().flatten() } {
+        let mut first = None;
+        let changed = unsafe {
+            check(base, len, &mut |addr| {
+                first.get_or_insert(addr);
+            })
+        };
+        if let Some(first) = first {
+            pl011::print(b"Poisoned memory written: ");
+            pl011::print(u64_to_dec(changed as u64, &mut [0u8; 20]));
+            pl011::print(b" bytes changed, the first at 0x");
+            pl011::println(u64_to_hex(first as u64, &mut [0u8; 16]));
+        }
+        total += changed;
+    }
+    return total;
+}
+
+/// Buffer poisoned by the self-test
+static mut TEST_BUF: [u8; 64] = [0; 64];
+
+/// Self-test: a poisoned buffer reads back as the pattern whatever its
+/// alignment, and a stale write into it is caught, at its address; with
+/// [`ENABLED`], also by [`scan`]
+pub fn selftest() -> Outcome {
+    let buf = &raw mut TEST_BUF;
+    // Unaligned, to check the pattern follows the address
+    let base = unsafe { (*buf).as_mut_ptr() } as usize + 1;
+    let len = 61;
+    let stale = base + 30;
+    let mut found = 0;
+
+    unsafe {
+        fill(base, len);
+        if check(base, len, &mut |_| {}) != 0 || ptr::read(base as *const u8) != expected(base) {
+            return Outcome::Fail;
+        }
+        ptr::write_volatile(stale as *mut u8, 0);
+        if check(base, len, &mut |addr| found = addr) != 1 || found != stale {
+            return Outcome::Fail;
+        }
+    }
+
+    if ENABLED {
+        unsafe { poison(base, len) };
+        let clean = scan() == 0;
+        unsafe { ptr::write_volatile(stale as *mut u8, 0) };
+        let caught = scan() == 1;
+        forget(base, len);
+        if !(clean && caught) {
+            return Outcome::Fail;
+        }
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,411 @@
+//! Reservation registry
+//!
+//! This module records which physical ranges are already in use, each tagged
+//! with its owner, and finds free RAM between them. Anything the bootloader
+//! places in memory for the payload is registered here first, so later
+//! placements can't overlap it by construction.
+//!
+//! The registry also keeps allocation statistics (see [`stats`]), and a
+//! failed [`allocate`] prints a one-line summary of them so the console
+//! shows why a placement didn't fit.
+
+use crate::drivers::uart::pl011;
+use crate::memory::map::{self, RegionKind};
+use crate::memory::poison;
+use crate::utilities::print::{u64_to_dec, u64_to_hex};
+
+/// Maximum number of reservations the registry can hold
+pub const MAX_RESERVATIONS: usize = 32;
+
+/// Owner of a reservation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReserveTag {
+    /// The bootloader image and its stack
+    Bootloader,
+    /// The staged kernel image, before it's loaded
+    Staging,
+    /// The loaded kernel segments
+    Kernel,
+    /// The device tree blob
+    Dtb,
+    /// The measured-boot event log
+    EventLog,
+    /// The persistent boot record, when kept in RAM
+    BootRecord,
+    /// The initial ramdisk
+    Initrd,
+    /// The compressed console capture
+    ConsoleLog,
+    /// Firmware tables for the payload (e.g., SMBIOS)
+    Tables,
+    /// The framebuffer of the display console
+    Framebuffer,
+    /// Memory a device reads and writes by DMA (e.g., NVMe queues)
+    Dma,
+    /// Memory the DTB reserves (e.g., firmware carveouts)
+    Firmware,
+}
+
+impl ReserveTag {
+    /// All tags, in the order they are reported
+    pub const ALL: [ReserveTag; 12] = [
+        ReserveTag::Bootloader,
+        ReserveTag::Staging,
+        ReserveTag::Kernel,
+        ReserveTag::Dtb,
+        ReserveTag::EventLog,
+        ReserveTag::BootRecord,
+        ReserveTag::Initrd,
+        ReserveTag::ConsoleLog,
+        ReserveTag::Tables,
+        ReserveTag::Framebuffer,
+        ReserveTag::Dma,
+        ReserveTag::Firmware,
+    ];
+
+    /// Returns the name of the tag
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            ReserveTag::Bootloader => b"bootloader",
+            ReserveTag::Staging => b"staging",
+            ReserveTag::Kernel => b"kernel",
+            ReserveTag::Dtb => b"dtb",
+            ReserveTag::EventLog => b"event log",
+            ReserveTag::BootRecord => b"boot record",
+            ReserveTag::Initrd => b"initrd",
+            ReserveTag::ConsoleLog => b"console log",
+            ReserveTag::Tables => b"tables",
+            ReserveTag::Framebuffer => b"framebuffer",
+            ReserveTag::Dma => b"dma",
+            ReserveTag::Firmware => b"firmware",
+        };
+    }
+}
+
+/// Errors reported by the reservation registry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReserveError {
+    /// The range overlaps an existing reservation
+    Overlap,
+    /// The registry has no free slot left
+    Full,
+    /// No free range satisfies the request
+    NoSpace,
+}
+
+/// A reserved physical range
+#[derive(Clone, Copy, Debug)]
+pub struct Reservation {
+    /// Physical base address of the range
+    pub base: usize,
+    /// Size of the range in bytes
+    pub size: usize,
+    /// Owner of the range
+    pub tag: ReserveTag,
+}
+
+impl Reservation {
+    /// Returns the address one past the end of the range
+    pub fn end(&self) -> usize {
+        return self.base + self.size;
+    }
+
+    /// Checks whether the range overlaps `[base, base + size)`
+    fn overlaps(&self, base: usize, size: usize) -> bool {
+        return base < self.end() && self.base < base + size;
+    }
+}
+
+/// Allocation statistics of the registry
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocStats {
+    /// Total RAM in the memory map
+    pub capacity: usize,
+    /// Bytes currently reserved
+    pub used: usize,
+    /// Highest value `used` has reached
+    pub high_water: usize,
+    /// Number of failed allocations
+    pub failed: usize,
+    /// Size of the largest failed allocation
+    pub largest_failed: usize,
+}
+
+/// Fixed-size table of reservations
+struct Registry {
+    entries: [Reservation; MAX_RESERVATIONS],
+    count: usize,
+    used: usize,
+    high_water: usize,
+    failed: usize,
+    largest_failed: usize,
+}
+
+/// Global reservation registry
+static mut REGISTRY: Registry = Registry {
+    entries: [Reservation {
+        base: 0,
+        size: 0,
+        tag: ReserveTag::Bootloader,
+    }; MAX_RESERVATIONS],
+    count: 0,
+    used: 0,
+    high_water: 0,
+    failed: 0,
+    largest_failed: 0,
+};
+
+/// Returns the global registry
+fn registry() -> &'static mut Registry {
+    let reg = &raw mut REGISTRY;
+
+    unsafe {
+        return &mut *reg;
+    }
+}
+
+/// Returns all current reservations
+pub fn reservations() -> &'static [Reservation] {
+    let reg = registry();
+
+    return &reg.entries[..reg.count];
+}
+
+/// Checks whether `[base, base + size)` overlaps any reservation
+pub fn is_reserved(base: usize, size: usize) -> bool {
+    return reservations().iter().any(|r| r.overlaps(base, size));
+}
+
+/// Reserves `[base, base + size)` for `tag`
+///
+/// Fails if the range overlaps an existing reservation. Empty ranges are
+/// accepted and not recorded. A reserved range is no longer checked for
+/// writes by [`poison::scan`].
+pub fn reserve(base: usize, size: usize, tag: ReserveTag) -> Result<(), ReserveError> {
+    let reg = registry();
+
+    if size == 0 {
+        return Ok(());
+    }
+    if is_reserved(base, size) {
+        return Err(ReserveError::Overlap);
+    }
+    if reg.count == MAX_RESERVATIONS {
+        return Err(ReserveError::Full);
+    }
+
+    poison::forget(base, size);
+    reg.entries[reg.count] = Reservation { base, size, tag };
+    reg.count += 1;
+    reg.used += size;
+    reg.high_water = reg.high_water.max(reg.used);
+    return Ok(());
+}
+
+/// Releases the reservation starting at `base` owned by `tag`
+///
+/// Returns `false` if there is no such reservation.
+pub fn release(base: usize, tag: ReserveTag) -> bool {
+    let reg = registry();
+
+    for i in 0..reg.count {
+        if reg.entries[i].base == base && reg.entries[i].tag == tag {
+            reg.used -= reg.entries[i].size;
+            reg.entries.copy_within(i + 1..reg.count, i);
+            reg.count -= 1;
+            return true;
+        }
+    }
+    return false;
+}
+
+/// Releases the reservation starting at `base` owned by `tag`, whose
+/// contents are no longer needed
+///
+/// Unlike [`release`], used when the range is only kept out of a search for
+/// a moment, the range is [poisoned](poison) in debug builds, so a later use
+/// of its contents shows. Returns `false` if there is no such reservation.
+pub fn discard(base: usize, tag: ReserveTag) -> bool {
+    let Some(size) = reservations()
+        .iter()
+        .find(|r| r.base == base && r.tag == tag)
+        .map(|r| r.size)
+    else {
+        return false;
+    };
+
+    release(base, tag);
+    unsafe { poison::poison(base, size) };
+    return true;
+}
+
+/// Finds the lowest free RAM range of `size` bytes aligned to `align`
+///
+/// The range lies within a single RAM region of the memory map, overlaps no
+/// reservation and, if `limit` is given, ends at or below `limit`. `align`
+/// must be a power of two.
+pub fn find_free(size: usize, align: usize, limit: Option<usize>) -> Option<usize> {
+    return find_free_within(size, align, 0, limit);
+}
+
+/// Finds a free range like [`find_free`] that also starts at or above `min`
+pub fn find_free_within(
+    size: usize,
+    align: usize,
+    min: usize,
+    limit: Option<usize>,
+) -> Option<usize> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut best: Option<usize> = None;
+
+    for region in map::regions().iter().filter(|r| r.kind == RegionKind::Ram) {
+        // Candidates are the region start, the lower bound and the end of
+        // every reservation
+        let candidates = [region.base, min]
+            .into_iter()
+            .chain(reservations().iter().map(|r| r.end()));
+
+        for candidate in candidates {
+            let Some(base) = align_up(candidate, align) else {
+                continue;
+            };
+            let Some(end) = base.checked_add(size) else {
+                continue;
+            };
+
+            if base < region.base.max(min) || end > region.end() || end > limit {
+                continue;
+            }
+            if is_reserved(base, size) {
+                continue;
+            }
+            if best.is_none_or(|b| base < b) {
+                best = Some(base);
+            }
+        }
+    }
+
+    return best;
+}
+
+/// Calls `f` with the base and size of every free RAM range
+///
+/// Free ranges are the parts of the RAM regions of the memory map not
+/// covered by any reservation, reported in memory map order.
+pub fn for_each_gap(mut f: impl FnMut(usize, usize)) {
+    for region in map::regions().iter().filter(|r| r.kind == RegionKind::Ram) {
+        let mut cursor = region.base;
+
+        while cursor < region.end() {
+            let next = reservations()
+                .iter()
+                .filter(|r| r.overlaps(cursor, region.end() - cursor))
+                .min_by_key(|r| r.base);
+            let Some(next) = next else {
+                f(cursor, region.end() - cursor);
+                break;
+            };
+
+            if next.base > cursor {
+                f(cursor, next.base - cursor);
+            }
+            cursor = next.end();
+        }
+    }
+}
+
+/// Returns the number of bytes reserved for `tag`
+pub fn tag_total(tag: ReserveTag) -> usize {
+    return reservations()
+        .iter()
+        .filter(|r| r.tag == tag)
+        .map(|r| r.size)
+        .sum();
+}
+
+/// Returns the allocation statistics
+pub fn stats() -> AllocStats {
+    let reg = registry();
+    let capacity = map::regions()
+        .iter()
+        .filter(|r| r.kind == RegionKind::Ram)
+        .map(|r| r.size)
+        .sum();
+
+    return AllocStats {
+        capacity,
+        used: reg.used,
+        high_water: reg.high_water,
+        failed: reg.failed,
+        largest_failed: reg.largest_failed,
+    };
+}
+
+/// Finds a free range with [`find_free`] and reserves it for `tag`
+///
+/// On failure, the failure is counted in the statistics and a summary of
+/// them is printed.
+pub fn allocate(
+    size: usize,
+    align: usize,
+    limit: Option<usize>,
+    tag: ReserveTag,
+) -> Result<usize, ReserveError> {
+    return allocate_within(size, align, 0, limit, tag);
+}
+
+/// Reserves a free range like [`allocate`] that also starts at or above
+/// `min`, see [`find_free_within`]
+pub fn allocate_within(
+    size: usize,
+    align: usize,
+    min: usize,
+    limit: Option<usize>,
+    tag: ReserveTag,
+) -> Result<usize, ReserveError> {
+    let result = find_free_within(size, align, min, limit)
+        .ok_or(ReserveError::NoSpace)
+        .and_then(|base| reserve(base, size, tag).map(|_| base));
+
+    if result.is_err() {
+        let reg = registry();
+        reg.failed += 1;
+        reg.largest_failed = reg.largest_failed.max(size);
+        print_failure(size, tag);
+    }
+    return result;
+}
+
+/// Prints a one-line summary of the statistics after a failed allocation
+fn print_failure(size: usize, tag: ReserveTag) {
+    let stats = stats();
+    let mut largest_gap = 0;
+
+    for_each_gap(|_, gap| largest_gap = largest_gap.max(gap));
+
+    pl011::print(b"Allocation of ");
+    print_hex(size);
+    pl011::print(b" bytes for ");
+    pl011::print(tag.name());
+    pl011::print(b" failed: used ");
+    print_hex(stats.used);
+    pl011::print(b" of ");
+    print_hex(stats.capacity);
+    pl011::print(b", largest free ");
+    print_hex(largest_gap);
+    pl011::print(b", failures: ");
+    pl011::println(u64_to_dec(stats.failed as u64, &mut [0u8; 20]));
+}
+
+/// Prints `value` as hexadecimal with a `0x` prefix
+fn print_hex(value: usize) {
+    let mut buf = [0u8; 16];
+
+    pl011::print(b"0x");
+    pl011::print(u64_to_hex(value as u64, &mut buf));
+}
+
+/// Rounds `value` up to a multiple of `align` (a power of two)
+fn align_up(value: usize, align: usize) -> Option<usize> {
+    return Some(value.checked_add(align - 1)? & !(align - 1));
+}
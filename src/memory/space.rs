@@ -0,0 +1,547 @@
+//! Space planning for loading an image
+//!
+//! Loading a kernel needs room for several things at once: the staged image
+//! as it was received, a scratch copy if it must be decoded (decompressed or
+//! decrypted) before it's loaded, the extents its segments are copied to,
+//! and the DTB and initrd handed over with it. When these add up to more
+//! than the free RAM, loading fails half way or, worse, copies segments over
+//! the staged image they are read from. [`plan`] checks all of it against
+//! the memory map and the reservation registry before anything is copied,
+//! and returns a [`Placement`] that fits, or what didn't fit.
+//!
+//! The staged image is released before the destination copy when possible
+//! ([`Strategy::Buffered`]): it's decoded to the scratch first, so the
+//! segments, DTB and initrd may reuse its memory. Without room for the
+//! scratch, an image whose decoder works as it's read is decoded straight to
+//! its destination ([`Strategy::Streaming`]), which then must not overlap
+//! the staged image. A plain image has nothing to decode: it's read straight
+//! from staging, unless its destination overlaps the staged image, in which
+//! case it's moved to a scratch first (see [`relocate`]).
+//!
+//! When nothing fits, [`print_report`] shows how much was needed against
+//! how much each RAM region has free.
+
+use crate::drivers::uart::pl011;
+use crate::memory::map::{self, RegionKind};
+use crate::memory::reserve::{self, MAX_RESERVATIONS, ReserveError, ReserveTag};
+use crate::selftest::Outcome;
+use crate::utilities::print::u64_to_hex;
+
+use core::ptr;
+
+/// Maximum number of destination extents of an image
+pub const MAX_EXTENTS: usize = 16;
+/// Alignment of the scratch copy
+pub const SCRATCH_ALIGN: usize = 4096;
+/// Alignment of the initrd
+pub const INITRD_ALIGN: usize = 4096;
+/// Maximum number of free ranges tracked: every region, split by every
+/// reservation and by everything a plan places
+const MAX_FREE: usize = map::MAX_REGIONS + MAX_RESERVATIONS + MAX_EXTENTS + 4;
+
+/// How the staged image turns into what is loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoding {
+    /// The image is loaded as it is
+    Plain,
+    /// The image is decoded as it's read, to the given number of bytes, so
+    /// the decoder can write straight to the destination (e.g., LZ77)
+    Streamed(usize),
+    /// The image is decoded whole before any of it is used, to the given
+    /// number of bytes (e.g., decrypted, as the tag is checked first)
+    Whole(usize),
+}
+
+/// What loading an image needs room for
+#[derive(Clone, Copy, Debug)]
+pub struct Demand {
+    /// Address and size of the staged image
+    staging: (usize, usize),
+    /// How the staged image is decoded
+    decoding: Decoding,
+    /// Address and size of the destination extents, `count` of them
+    extents: [(usize, usize); MAX_EXTENTS],
+    count: usize,
+    /// Size and alignment of the DTB, size 0 if there is none
+    dtb: (usize, usize),
+    /// Size of the initrd, 0 if there is none
+    initrd: usize,
+}
+
+impl Demand {
+    /// Creates the demand of the plain image staged in the `len` bytes at
+    /// `base`, with no destination yet
+    pub fn new(base: usize, len: usize) -> Self {
+        return Self {
+            staging: (base, len),
+            decoding: Decoding::Plain,
+            extents: [(0, 0); MAX_EXTENTS],
+            count: 0,
+            dtb: (0, 1),
+            initrd: 0,
+        };
+    }
+
+    /// Sets how the staged image is decoded
+    pub fn decoding(mut self, decoding: Decoding) -> Self {
+        self.decoding = decoding;
+        return self;
+    }
+
+    /// Adds the destination extent of `size` bytes at `base`
+    ///
+    /// Past [`MAX_EXTENTS`] extents, it's merged with the last one, which
+    /// then covers the gap between them too.
+    pub fn extent(mut self, base: usize, size: usize) -> Self {
+        if size == 0 {
+            return self;
+        }
+        if self.count == MAX_EXTENTS {
+            let (last, last_size) = self.extents[MAX_EXTENTS - 1];
+            let start = last.min(base);
+            let end = (last + last_size).max(base + size);
+            self.extents[MAX_EXTENTS - 1] = (start, end - start);
+            return self;
+        }
+        self.extents[self.count] = (base, size);
+        self.count += 1;
+        return self;
+    }
+
+    /// Adds a DTB of `size` bytes aligned to `align` (a power of two)
+    pub fn dtb(mut self, size: usize, align: usize) -> Self {
+        self.dtb = (size, align);
+        return self;
+    }
+
+    /// Adds an initrd of `size` bytes
+    pub fn initrd(mut self, size: usize) -> Self {
+        self.initrd = size;
+        return self;
+    }
+
+    /// Returns the destination extents
+    fn extents(&self) -> &[(usize, usize)] {
+        return &self.extents[..self.count];
+    }
+
+    /// Returns the size of the scratch copy: the decoded image, or the
+    /// staged one itself when it's plain
+    fn scratch_size(&self) -> usize {
+        return match self.decoding {
+            Decoding::Plain => self.staging.1,
+            Decoding::Streamed(size) | Decoding::Whole(size) => size,
+        };
+    }
+}
+
+/// How an image gets from staging to its destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// The image is decoded (or copied) to the scratch at the given address,
+    /// the staged image released, then the scratch loaded
+    Buffered {
+        /// Address of the scratch copy
+        scratch: usize,
+    },
+    /// The image is read from staging straight to its destination, decoded
+    /// on the way if needed; the staged image is released afterwards
+    Streaming,
+}
+
+/// Where everything an image needs fits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    /// How the image is loaded
+    pub strategy: Strategy,
+    /// Address of the DTB, if one was asked for
+    pub dtb: Option<usize>,
+    /// Address of the initrd, if one was asked for
+    pub initrd: Option<usize>,
+}
+
+/// What didn't fit in a plan
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceError {
+    /// A destination extent isn't free RAM
+    Destination,
+    /// No room for the scratch copy
+    Scratch,
+    /// No room for the DTB
+    Dtb,
+    /// No room for the initrd
+    Initrd,
+}
+
+/// Set of free ranges
+#[derive(Clone, Copy, Debug)]
+pub struct FreeSet {
+    /// `(base, end)` of the free ranges, sorted and disjoint, `count` of them
+    ranges: [(usize, usize); MAX_FREE],
+    count: usize,
+}
+
+impl Default for FreeSet {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl FreeSet {
+    /// Creates an empty set
+    pub const fn new() -> Self {
+        return Self {
+            ranges: [(0, 0); MAX_FREE],
+            count: 0,
+        };
+    }
+
+    /// Returns the RAM free for loading an image
+    ///
+    /// That's the RAM regions of the memory map minus the reservations,
+    /// except the staging and kernel ones: they belong to the image being
+    /// planned.
+    pub fn current() -> Self {
+        let mut free = Self::new();
+
+        for region in map::regions().iter().filter(|r| r.kind == RegionKind::Ram) {
+            free.add(region.base, region.size);
+        }
+        for r in reserve::reservations() {
+            if !matches!(r.tag, ReserveTag::Staging | ReserveTag::Kernel) {
+                free.remove(r.base, r.size);
+            }
+        }
+        return free;
+    }
+
+    /// Returns the free ranges, as `(base, end)`, in address order
+    pub fn ranges(&self) -> &[(usize, usize)] {
+        return &self.ranges[..self.count];
+    }
+
+    /// Appends `(base, end)`, returning `false` if the set is full
+    fn push(&mut self, base: usize, end: usize) -> bool {
+        if self.count == MAX_FREE {
+            return false;
+        }
+        self.ranges[self.count] = (base, end);
+        self.count += 1;
+        return true;
+    }
+
+    /// Adds the `size` bytes at `base`, merged with the ranges they touch
+    ///
+    /// Returns `false`, leaving the set as it was, if it has no room.
+    pub fn add(&mut self, base: usize, size: usize) -> bool {
+        let mut new = (base, base.saturating_add(size));
+        let mut out = Self::new();
+        let mut inserted = false;
+
+        if size == 0 {
+            return true;
+        }
+        for &(b, e) in self.ranges() {
+            let kept = if e < new.0 {
+                out.push(b, e)
+            } else if b > new.1 {
+                let placed = inserted || out.push(new.0, new.1);
+                inserted = true;
+                placed && out.push(b, e)
+            } else {
+                new = (new.0.min(b), new.1.max(e));
+                true
+            };
+            if !kept {
+                return false;
+            }
+        }
+        if !inserted && !out.push(new.0, new.1) {
+            return false;
+        }
+        *self = out;
+        return true;
+    }
+
+    /// Removes whatever is free of the `size` bytes at `base`
+    ///
+    /// Returns `false`, leaving the set as it was, if it has no room for the
+    /// ranges split in two.
+    pub fn remove(&mut self, base: usize, size: usize) -> bool {
+        let end = base.saturating_add(size);
+        let mut out = Self::new();
+
+        for &(b, e) in self.ranges() {
+            let kept = if e <= base || end <= b {
+                out.push(b, e)
+            } else {
+                (b >= base || out.push(b, base)) && (e <= end || out.push(end, e))
+            };
+            if !kept {
+                return false;
+            }
+        }
+        *self = out;
+        return true;
+    }
+
+    /// Checks whether the `size` bytes at `base` are all free
+    pub fn contains(&self, base: usize, size: usize) -> bool {
+        let Some(end) = base.checked_add(size) else {
+            return false;
+        };
+
+        return self.ranges().iter().any(|&(b, e)| b <= base && end <= e);
+    }
+
+    /// Takes the lowest free range of `size` bytes aligned to `align` (a
+    /// power of two) and returns its address
+    pub fn allocate(&mut self, size: usize, align: usize) -> Option<usize> {
+        let base = self.ranges().iter().find_map(|&(b, e)| {
+            let base = b.checked_next_multiple_of(align)?;
+            return (base.checked_add(size)? <= e).then_some(base);
+        })?;
+
+        return self.remove(base, size).then_some(base);
+    }
+}
+
+/// Plans the loading of `demand` in the RAM free right now, see
+/// [`FreeSet::current`]
+pub fn plan(demand: &Demand) -> Result<Placement, SpaceError> {
+    return plan_in(&FreeSet::current(), demand);
+}
+
+/// Plans the loading of `demand` in `free`
+///
+/// The strategies are tried in the order described in the
+/// [module documentation](self); if none fits, the error is the one of the
+/// last strategy tried.
+pub fn plan_in(free: &FreeSet, demand: &Demand) -> Result<Placement, SpaceError> {
+    return match demand.decoding {
+        Decoding::Plain => try_streaming(free, demand).or_else(|_| try_buffered(free, demand)),
+        Decoding::Streamed(_) => {
+            try_buffered(free, demand).or_else(|_| try_streaming(free, demand))
+        }
+        Decoding::Whole(_) => try_buffered(free, demand),
+    };
+}
+
+/// Plans `demand` with [`Strategy::Buffered`]
+///
+/// The staged image is released before the destination is written, so the
+/// destination, DTB and initrd may reuse it; the scratch may not, as it's
+/// written while the staged image is read.
+fn try_buffered(free: &FreeSet, demand: &Demand) -> Result<Placement, SpaceError> {
+    let (staging, len) = demand.staging;
+    let size = demand.scratch_size();
+    let mut free = *free;
+
+    take_extents(&mut free, demand)?;
+    let mut outside = free;
+    if !outside.remove(staging, len) {
+        return Err(SpaceError::Scratch);
+    }
+    let scratch = outside
+        .allocate(size, SCRATCH_ALIGN)
+        .ok_or(SpaceError::Scratch)?;
+    if !free.remove(scratch, size) {
+        return Err(SpaceError::Scratch);
+    }
+    let (dtb, initrd) = place_rest(&mut free, demand)?;
+
+    return Ok(Placement {
+        strategy: Strategy::Buffered { scratch },
+        dtb,
+        initrd,
+    });
+}
+
+/// Plans `demand` with [`Strategy::Streaming`]
+///
+/// The staged image is read until the destination is written, so nothing
+/// may reuse it: the DTB and initrd may even be placed before the image is
+/// loaded.
+fn try_streaming(free: &FreeSet, demand: &Demand) -> Result<Placement, SpaceError> {
+    let (staging, len) = demand.staging;
+    let mut free = *free;
+
+    if !free.remove(staging, len) {
+        return Err(SpaceError::Destination);
+    }
+    take_extents(&mut free, demand)?;
+    let (dtb, initrd) = place_rest(&mut free, demand)?;
+
+    return Ok(Placement {
+        strategy: Strategy::Streaming,
+        dtb,
+        initrd,
+    });
+}
+
+/// Takes the destination extents of `demand` out of `free`, failing if one
+/// isn't all free
+fn take_extents(free: &mut FreeSet, demand: &Demand) -> Result<(), SpaceError> {
+    for &(base, size) in demand.extents() {
+        if !free.contains(base, size) || !free.remove(base, size) {
+            return Err(SpaceError::Destination);
+        }
+    }
+    return Ok(());
+}
+
+/// Places the DTB and initrd of `demand` in `free`, in that order
+fn place_rest(
+    free: &mut FreeSet,
+    demand: &Demand,
+) -> Result<(Option<usize>, Option<usize>), SpaceError> {
+    let (dtb_size, dtb_align) = demand.dtb;
+    let mut dtb = None;
+    let mut initrd = None;
+
+    if dtb_size != 0 {
+        dtb = Some(free.allocate(dtb_size, dtb_align).ok_or(SpaceError::Dtb)?);
+    }
+    if demand.initrd != 0 {
+        initrd = Some(
+            free.allocate(demand.initrd, INITRD_ALIGN)
+                .ok_or(SpaceError::Initrd)?,
+        );
+    }
+    return Ok((dtb, initrd));
+}
+
+/// Moves the plain staged image of `demand` to the scratch of a buffered
+/// `placement` and returns its new address
+///
+/// The copy is reserved for [`ReserveTag::Staging`] and the staged image
+/// released, so the destination may be written over it. With
+/// [`Strategy::Streaming`], returns the staged image's own address.
+pub fn relocate(demand: &Demand, placement: &Placement) -> Result<usize, ReserveError> {
+    let (base, len) = demand.staging;
+    let Strategy::Buffered { scratch } = placement.strategy else {
+        return Ok(base);
+    };
+
+    reserve::reserve(scratch, len, ReserveTag::Staging)?;
+    unsafe {
+        ptr::copy_nonoverlapping(base as *const u8, scratch as *mut u8, len);
+    }
+    reserve::release(base, ReserveTag::Staging);
+    return Ok(scratch);
+}
+
+/// Prints what `demand` needs, and what each RAM region has free for it
+pub fn print_report(demand: &Demand) {
+    let free = FreeSet::current();
+    let destination: usize = demand.extents().iter().map(|&(_, size)| size).sum();
+
+    pl011::print(b"Needed: staging ");
+    print_hex(demand.staging.1);
+    if demand.decoding != Decoding::Plain {
+        pl011::print(b", decoded ");
+        print_hex(demand.scratch_size());
+    }
+    pl011::print(b", destination ");
+    print_hex(destination);
+    pl011::print(b", dtb ");
+    print_hex(demand.dtb.0);
+    pl011::print(b", initrd ");
+    print_hex(demand.initrd);
+    pl011::println(b"");
+
+    for region in map::regions().iter().filter(|r| r.kind == RegionKind::Ram) {
+        let mut total = 0;
+        let mut largest = 0;
+        for &(b, e) in free.ranges() {
+            let size = e.min(region.end()).saturating_sub(b.max(region.base));
+            total += size;
+            largest = largest.max(size);
+        }
+        pl011::print(b"  RAM ");
+        print_hex(region.base);
+        pl011::print(b"-");
+        print_hex(region.end());
+        pl011::print(b": ");
+        print_hex(total);
+        pl011::print(b" free, largest ");
+        print_hex(largest);
+        pl011::println(b"");
+    }
+}
+
+/// Prints `value` as hexadecimal with a `0x` prefix
+fn print_hex(value: usize) {
+    pl011::print(b"0x");
+    pl011::print(u64_to_hex(value as u64, &mut [0u8; 16]));
+}
+
+/// Base of the self-test's RAM, never accessed
+const TEST_RAM: usize = 0x1000_0000;
+/// Size of the self-test's RAM
+const TEST_RAM_SIZE: usize = 0x10_0000;
+
+/// Self-test: plans in 1 MiB of RAM an image that just fits with a scratch,
+/// one that only fits streamed, and ones that can't fit
+pub fn selftest() -> Outcome {
+    let mut free = FreeSet::new();
+    free.add(TEST_RAM, TEST_RAM_SIZE);
+
+    // Decoded over its own staging: the scratch and DTB fill the rest exactly
+    let just_fits = Demand::new(TEST_RAM, 0x4_0000)
+        .decoding(Decoding::Streamed(0x6_0000))
+        .extent(TEST_RAM, 0x6_0000)
+        .dtb(0x4_0000, 0x4_0000);
+    let expected = Placement {
+        strategy: Strategy::Buffered {
+            scratch: TEST_RAM + 0x6_0000,
+        },
+        dtb: Some(TEST_RAM + 0xc_0000),
+        initrd: None,
+    };
+    if plan_in(&free, &just_fits) != Ok(expected) {
+        return Outcome::Fail;
+    }
+    let one_more = just_fits.dtb(0x4_0000 + 1, 8);
+    if plan_in(&free, &one_more) != Err(SpaceError::Destination) {
+        return Outcome::Fail;
+    }
+
+    // No room for a scratch next to the destination, but streamed it fits
+    let streamed = Demand::new(TEST_RAM, 0x4_0000)
+        .decoding(Decoding::Streamed(0x8_0000))
+        .extent(TEST_RAM + 0x4_0000, 0x8_0000)
+        .dtb(0x4_0000, 0x4_0000);
+    let expected = Placement {
+        strategy: Strategy::Streaming,
+        dtb: Some(TEST_RAM + 0xc_0000),
+        initrd: None,
+    };
+    if plan_in(&free, &streamed) != Ok(expected) {
+        return Outcome::Fail;
+    }
+
+    // The same image decoded whole can't stream, nor anything past the RAM
+    let whole = streamed.decoding(Decoding::Whole(0x8_0000));
+    let outside = Demand::new(TEST_RAM, 0x1000).extent(TEST_RAM + TEST_RAM_SIZE - 0x1000, 0x2000);
+    let no_initrd = Demand::new(TEST_RAM, 0x1000).initrd(TEST_RAM_SIZE);
+    if plan_in(&free, &whole) != Err(SpaceError::Scratch)
+        || plan_in(&free, &outside) != Err(SpaceError::Destination)
+        || plan_in(&free, &no_initrd) != Err(SpaceError::Initrd)
+    {
+        return Outcome::Fail;
+    }
+
+    // A plain image is read from staging, unless it's loaded over it
+    let plain = Demand::new(TEST_RAM, 0x1000).extent(TEST_RAM + 0x1000, 0x2000);
+    let moved = Demand::new(TEST_RAM, 0x1000).extent(TEST_RAM, 0x2000);
+    let relocated = Strategy::Buffered {
+        scratch: TEST_RAM + 0x2000,
+    };
+    if plan_in(&free, &plain).map(|p| p.strategy) != Ok(Strategy::Streaming)
+        || plan_in(&free, &moved).map(|p| p.strategy) != Ok(relocated)
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,81 @@
+//! RAM pattern test
+//!
+//! This module writes known patterns to a region of RAM and reads them back,
+//! to catch stuck or shorted data and address lines on a newly assembled
+//! board. Only memory allocated from the reservation registry is tested, so
+//! nothing in use is overwritten.
+
+use crate::memory::reserve::{self, ReserveTag};
+use crate::selftest::Outcome;
+use crate::utilities::print;
+
+use core::ptr;
+
+/// Size of the region tested by [`selftest`]
+pub const SELFTEST_SIZE: usize = 64 * 1024;
+
+/// A heartbeat dot is printed every this many words written or read (every
+/// 1 MiB)
+const HEARTBEAT_STEP: u64 = (1 << 20) / 8;
+
+/// Data patterns written over the whole region
+const PATTERNS: [u64; 4] = [
+    0x0000_0000_0000_0000,
+    0xffff_ffff_ffff_ffff,
+    0x5555_5555_5555_5555,
+    0xaaaa_aaaa_aaaa_aaaa,
+];
+
+/// Tests `len` bytes at `base`
+///
+/// Writes each of the data patterns, a walking one bit, and finally each
+/// word's own address (catching address lines that alias), printing a
+/// [heartbeat](print::heartbeat) dot as it goes. Returns the address of the
+/// first word read back wrong, if any.
+///
+/// # Safety
+///
+/// `base` must be 8-byte aligned, and the `len` bytes there must be RAM not
+/// used by anything else.
+pub unsafe fn test_region(base: usize, len: usize) -> Result<(), usize> {
+    let words = base as *mut u64;
+    let count = len / 8;
+    let fill_check = |value: &dyn Fn(usize) -> u64| {
+        for i in 0..count {
+            unsafe { ptr::write_volatile(words.add(i), value(i)) };
+            print::heartbeat(HEARTBEAT_STEP);
+        }
+        for i in 0..count {
+            if unsafe { ptr::read_volatile(words.add(i)) } != value(i) {
+                return Err(base + i * 8);
+            }
+            print::heartbeat(HEARTBEAT_STEP);
+        }
+        return Ok(());
+    };
+
+    for pattern in PATTERNS {
+        fill_check(&|_| pattern)?;
+    }
+    for bit in 0..64 {
+        fill_check(&|_| 1 << bit)?;
+    }
+    fill_check(&|i| (base + i * 8) as u64)?;
+    return Ok(());
+}
+
+/// Self-test: tests [`SELFTEST_SIZE`] bytes of free RAM
+///
+/// Skipped if no free RAM is known.
+pub fn selftest() -> Outcome {
+    let Ok(base) = reserve::allocate(SELFTEST_SIZE, 8, None, ReserveTag::Staging) else {
+        return Outcome::Skipped;
+    };
+
+    let result = unsafe { test_region(base, SELFTEST_SIZE) };
+    reserve::discard(base, ReserveTag::Staging);
+    match result {
+        Ok(()) => return Outcome::Pass,
+        Err(_) => return Outcome::Fail,
+    }
+}
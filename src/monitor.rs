@@ -0,0 +1,1681 @@
+//! Interactive debug monitor
+//!
+//! This module implements a small command shell on the console UART, useful
+//! during board bring-up to inspect memory, poke device registers and upload
+//! images without rebuilding the bootloader.
+//!
+//! The monitor can be entered in four ways:
+//!
+//! - Sending a serial break before the kernel is started
+//! - Executing `brk #MONITOR_BRK_IMM` (see [`MONITOR_BRK_IMM`]), either in
+//!   the bootloader or in the payload; the trapped registers are available
+//!   to the `regs` command
+//! - Hitting a hardware watchpoint set with `watch`, after the access is
+//!   reported along with the registers
+//! - Calling [`enter`] directly
+//!
+//! Leaving the monitor with `boot` resumes whatever entered it: the boot
+//! sequence, the instruction following the `brk`, or the watched access.
+//!
+//! Each line is run as a [script](crate::script): commands can be chained
+//! with `;`, `&&` and `||`, and `${name}` is replaced with an
+//! [environment](crate::env) variable, set with `setenv`. `run <name>` runs
+//! a variable as a script, and [`autoboot`] runs `bootcmd` before the
+//! handoff. If `bootdelay` is set to a number of seconds, [`countdown`]
+//! waits that long for a key beforehand, with the boot core idle.
+//!
+//! The command line is edited with the cursor keys. Up and Down recall the
+//! last 16 lines, kept across visits within a boot, `history` lists them
+//! and `!N` runs line `N` again. Tab completes command names.
+//!
+//! Numbers are parsed as hexadecimal by default, with or without a `0x`
+//! prefix. Decimal numbers are written with a `#` prefix (e.g., `#4096`).
+//!
+//! Other modules can add commands with [`register`].
+
+use crate::asm;
+use core::slice;
+
+use crate::board::{self, Performance};
+use crate::boot::{self, HandoffError, HandoffMode};
+use crate::bootid;
+use crate::capture;
+use crate::cpu;
+use crate::debug::{self, Watch, WatchAccess};
+use crate::diagnostics;
+use crate::drivers::nvme::{self, NvmeError};
+use crate::drivers::registry::{self, ProbeError};
+use crate::drivers::timer::generic::{self, Deadline};
+use crate::drivers::uart::pl011::{self, UartError};
+use crate::drivers::video::console;
+use crate::drivers::video::ramfb::{self, PixelFormat};
+use crate::env;
+use crate::error;
+use crate::exception::{self, Conduit, Regs};
+use crate::log::{self, Level};
+use crate::memory::map;
+use crate::memory::poison;
+use crate::memory::reserve::{self, ReserveTag};
+use crate::parsers::fdt;
+use crate::pauth::{self, Policy};
+use crate::power;
+use crate::script::{self, Status};
+use crate::selftest;
+use crate::serial::{xmodem, ymodem};
+use crate::tables::smbios;
+use crate::utilities::mmio::{self, Width};
+use crate::utilities::print::{self, print_hex_u64, print_hex_u8};
+use crate::utilities::readline::{self, History};
+use crate::utilities::sha256::sha256;
+use crate::warmcache::{self, CachedImage};
+
+/// `brk` immediate that enters the monitor
+pub const MONITOR_BRK_IMM: u16 = 0x4d4f;
+/// Maximum number of commands registered by other modules
+pub const MAX_COMMANDS: usize = 16;
+
+/// Maximum length of a command line
+const LINE_SIZE: usize = 128;
+/// Maximum number of `run` commands nested in one another
+const MAX_RUN_DEPTH: usize = 8;
+/// Default number of bytes shown by `md`
+const MD_DEFAULT_LEN: usize = 0x40;
+/// Default maximum size of an image received by `load`
+const LOAD_DEFAULT_MAX: usize = 0x100_0000;
+/// PSCI SYSTEM_RESET function ID
+const PSCI_SYSTEM_RESET: u64 = 0x8400_0009;
+
+/// State shared with the commands of a monitor session
+pub struct Session<'a> {
+    /// Registers trapped by the exception that entered the monitor, if any
+    pub regs: Option<&'a mut Regs>,
+    /// Whether the running command failed, for `&&` and `||`
+    pub failed: bool,
+    /// Number of `run` commands the running command is nested in
+    depth: usize,
+}
+
+impl Session<'_> {
+    /// Marks the running command as failed
+    ///
+    /// Returns `false`, so a handler can `return session.fail();` and stay
+    /// in the monitor.
+    pub fn fail(&mut self) -> bool {
+        self.failed = true;
+        return false;
+    }
+}
+
+/// Handler of a monitor command
+///
+/// Receives the session and the command arguments (the first one being the
+/// command name). Returns `true` to leave the monitor. A command that fails
+/// says so with [`Session::fail`], for the `&&` and `||` of scripts.
+pub type CommandHandler = fn(session: &mut Session, args: &[&[u8]]) -> bool;
+
+/// A monitor command
+#[derive(Clone, Copy)]
+pub struct Command {
+    /// Name typed to run the command
+    pub name: &'static [u8],
+    /// One-line usage shown by `help`
+    pub help: &'static [u8],
+    /// Function implementing the command
+    pub handler: CommandHandler,
+}
+
+/// Commands always available in the monitor
+static BUILTIN_COMMANDS: [Command; 35] = [
+    Command {
+        name: b"md",
+        help: b"md <addr> [len] [width]  Dump memory, 8, 16 or 32 bytes per line",
+        handler: cmd_md,
+    },
+    Command {
+        name: b"mw",
+        help: b"mw <addr> <val> [width]  Write 1, 2, 4 or 8 bytes to memory",
+        handler: cmd_mw,
+    },
+    Command {
+        name: b"sha256",
+        help: b"sha256 <addr> <len>      Hash memory with SHA-256",
+        handler: cmd_sha256,
+    },
+    Command {
+        name: b"regs",
+        help: b"regs                     Dump system and trapped registers",
+        handler: cmd_regs,
+    },
+    Command {
+        name: b"watch",
+        help: b"watch <addr> [len] [acc] Stop on r, w or rw accesses, w by default",
+        handler: cmd_watch,
+    },
+    Command {
+        name: b"delete",
+        help: b"delete watch <n>         Remove watchpoint n",
+        handler: cmd_delete,
+    },
+    Command {
+        name: b"meminfo",
+        help: b"meminfo                  Show memory usage and free ranges",
+        handler: cmd_meminfo,
+    },
+    Command {
+        name: b"poison",
+        help: b"poison check <addr> <n>  Check n bytes still hold the poison pattern",
+        handler: cmd_poison,
+    },
+    Command {
+        name: b"devices",
+        help: b"devices                  List the devices probed from the DTB",
+        handler: cmd_devices,
+    },
+    Command {
+        name: b"fdt",
+        help: b"fdt <addr>               Print the DTB at addr as a tree",
+        handler: cmd_fdt,
+    },
+    Command {
+        name: b"nvme",
+        help: b"nvme [read <a> <b> <n>]  Show the NVMe disk, or read n blocks from b to a",
+        handler: cmd_nvme,
+    },
+    Command {
+        name: b"video",
+        help: b"video [on [WxH] [FMT]]   Show or start the framebuffer console",
+        handler: cmd_video,
+    },
+    Command {
+        name: b"uptime",
+        help: b"uptime                   Show the time since reset",
+        handler: cmd_uptime,
+    },
+    Command {
+        name: b"bootid",
+        help: b"bootid                   Show the ID of this boot",
+        handler: cmd_bootid,
+    },
+    Command {
+        name: b"smbios",
+        help: b"smbios                   Show the SMBIOS tables",
+        handler: cmd_smbios,
+    },
+    Command {
+        name: b"log",
+        help: b"log [start|stop|save]    Control the compressed console capture",
+        handler: cmd_log,
+    },
+    Command {
+        name: b"tee",
+        help: b"tee [on|off]             Mirror the console to the debug channel",
+        handler: cmd_tee,
+    },
+    Command {
+        name: b"baud",
+        help: b"baud [rate]              Show or change the console baud rate",
+        handler: cmd_baud,
+    },
+    Command {
+        name: b"selftest",
+        help: b"selftest [name]          Run the self-tests, or only one",
+        handler: cmd_selftest,
+    },
+    Command {
+        name: b"go",
+        help: b"go <addr>                Call the code at addr",
+        handler: cmd_go,
+    },
+    Command {
+        name: b"load",
+        help: b"load <addr> [len] [secs] Receive an image over XMODEM",
+        handler: cmd_load,
+    },
+    Command {
+        name: b"rb",
+        help: b"rb <addr> [len] [secs]   Receive a file over YMODEM",
+        handler: cmd_rb,
+    },
+    Command {
+        name: b"cache",
+        help: b"cache [clear|boot]       Show, forget or run the image kept across resets",
+        handler: cmd_cache,
+    },
+    Command {
+        name: b"align",
+        help: b"align [on|off]           Show or set strict alignment checking",
+        handler: cmd_align,
+    },
+    Command {
+        name: b"pauth",
+        help: b"pauth [keys|off]         Show or set pointer authentication at handoff",
+        handler: cmd_pauth,
+    },
+    Command {
+        name: b"handoff",
+        help: b"handoff [el1|el2[-vhe]]  Show or set the EL the payload starts at",
+        handler: cmd_handoff,
+    },
+    Command {
+        name: b"crash",
+        help: b"crash [kind]             Trigger a fatal failure, or list them",
+        handler: cmd_crash,
+    },
+    Command {
+        name: b"boot",
+        help: b"boot                     Leave the monitor and resume",
+        handler: cmd_boot,
+    },
+    Command {
+        name: b"reset",
+        help: b"reset                    Reset the system through PSCI",
+        handler: cmd_reset,
+    },
+    Command {
+        name: b"echo",
+        help: b"echo [args]              Print the arguments",
+        handler: cmd_echo,
+    },
+    Command {
+        name: b"setenv",
+        help: b"setenv <name> [value]    Set a variable, or remove it without a value",
+        handler: cmd_setenv,
+    },
+    Command {
+        name: b"printenv",
+        help: b"printenv [name]          Show one or all variables",
+        handler: cmd_printenv,
+    },
+    Command {
+        name: b"run",
+        help: b"run <name>               Run a variable as a script",
+        handler: cmd_run,
+    },
+    Command {
+        name: b"history",
+        help: b"history                  List recent commands, rerun one with !N",
+        handler: cmd_history,
+    },
+    Command {
+        name: b"help",
+        help: b"help                     List the commands",
+        handler: cmd_help,
+    },
+];
+
+/// Command lines entered, kept when the monitor is left and entered again
+static mut HISTORY: History = History::new();
+
+/// Commands registered by other modules
+static mut COMMANDS: [Option<Command>; MAX_COMMANDS] = [None; MAX_COMMANDS];
+
+/// Registers a monitor command
+///
+/// Returns `false` if the command table is full.
+pub fn register(command: Command) -> bool {
+    let commands = &raw mut COMMANDS;
+    let commands = unsafe { &mut *commands };
+
+    match commands.iter_mut().find(|c| c.is_none()) {
+        Some(slot) => {
+            *slot = Some(command);
+            return true;
+        }
+        None => return false,
+    }
+}
+
+/// Returns an iterator over all builtin and registered commands
+fn commands() -> impl Iterator<Item = Command> {
+    let registered = &raw const COMMANDS;
+    let registered = unsafe { &*registered };
+
+    return BUILTIN_COMMANDS
+        .iter()
+        .copied()
+        .chain(registered.iter().flatten().copied());
+}
+
+/// Calls `f` with the name of every command, for completion
+fn command_names(f: &mut dyn FnMut(&[u8])) {
+    for command in commands() {
+        f(command.name);
+    }
+}
+
+/// Replaces a `!N` line with entry `N` of the history, echoing it
+///
+/// Returns the length of the line to run: `len` for other lines, 0 if the
+/// entry doesn't exist.
+fn expand_history(line: &mut [u8], len: usize) -> usize {
+    let history = &raw const HISTORY;
+    let history = unsafe { &*history };
+    let [b'!', number @ ..] = &line[..len] else {
+        return len;
+    };
+    let entry = number
+        .iter()
+        .try_fold(0usize, |n, &c| {
+            if !c.is_ascii_digit() {
+                return None;
+            }
+            return n.checked_mul(10)?.checked_add((c - b'0') as usize);
+        })
+        .and_then(|n| history.get(n));
+
+    match entry {
+        Some(entry) => {
+            line[..entry.len()].copy_from_slice(entry);
+            pl011::println(entry);
+            return entry.len();
+        }
+        None => {
+            pl011::print(b"No such history entry: ");
+            pl011::println(&line[..len]);
+            return 0;
+        }
+    }
+}
+
+/// Runs the command whose name and arguments are `args`
+fn run_command(session: &mut Session, args: &[&[u8]]) -> Status {
+    session.failed = false;
+    match commands().find(|c| c.name == args[0]) {
+        Some(command) => {
+            if (command.handler)(session, args) {
+                return Status::Leave;
+            }
+        }
+        None => {
+            pl011::print(b"Unknown command: ");
+            pl011::println(args[0]);
+            session.fail();
+        }
+    }
+    return match session.failed {
+        true => Status::Failed,
+        false => Status::Ok,
+    };
+}
+
+/// Runs `script` as described in the [`script`] module
+///
+/// A syntax error is printed and counts as a failure.
+fn run_script(session: &mut Session, script: &[u8]) -> Status {
+    match script::execute(script, &mut |args| run_command(session, args)) {
+        Ok(status) => return status,
+        Err(e) => {
+            e.print(script);
+            return Status::Failed;
+        }
+    }
+}
+
+/// Runs `${bootcmd}`, if set, in place of the plain handoff
+///
+/// Returns `None` if `bootcmd` isn't set, and otherwise whether it asked to
+/// boot (with `boot`). When it didn't, the caller falls back to the
+/// monitor.
+pub fn autoboot() -> Option<bool> {
+    let mut session = Session {
+        regs: None,
+        failed: false,
+        depth: 0,
+    };
+    let mut bootcmd = [0u8; env::VALUE_SIZE];
+    let len = env::get(b"bootcmd")?.len();
+
+    bootcmd[..len].copy_from_slice(env::get(b"bootcmd")?);
+    log::println(Level::Info, b"Running bootcmd");
+    if run_script(&mut session, &bootcmd[..len]) == Status::Leave {
+        return Some(true);
+    }
+    log::println(Level::Warn, b"bootcmd did not boot");
+    return Some(false);
+}
+
+/// Longest `bootdelay` accepted, in seconds
+const BOOTDELAY_MAX: u64 = 3600;
+
+/// Counts `${bootdelay}` seconds down before the handoff, and returns
+/// whether a key or a serial break interrupted it
+///
+/// `bootdelay` is a decimal number of seconds. Returns `false` at once if
+/// it isn't set, is 0 or isn't a valid number. The board runs at low
+/// performance meanwhile (see [`board::set_boot_performance`]), and the
+/// boot core idles between interrupts (see [`power::idle`]) rather than
+/// spinning. The key is consumed.
+pub fn countdown() -> bool {
+    let delay = env::get(b"bootdelay").and_then(|value| {
+        if value.is_empty() {
+            return None;
+        }
+        return value.iter().try_fold(0u64, |acc, &c| {
+            let digit = (c as char).to_digit(10)?;
+            return acc.checked_mul(10)?.checked_add(digit as u64);
+        });
+    });
+    let Some(delay) = delay.filter(|&d| d > 0).map(|d| d.min(BOOTDELAY_MAX)) else {
+        return false;
+    };
+    let mut buf = [0u8; 20];
+    let mut interrupted = false;
+
+    board::set_boot_performance(Performance::Low);
+    for left in (1..=delay).rev() {
+        pl011::print(b"\rAutoboot in ");
+        pl011::print(print::u64_to_dec(left, &mut buf));
+        pl011::print(b" s, press a key for the monitor ");
+
+        let deadline = Deadline::after_ms(1000);
+        while !deadline.expired() {
+            if pl011::take_break() || pl011::try_getchar().is_some() {
+                interrupted = true;
+                break;
+            }
+            power::idle();
+        }
+        if interrupted {
+            break;
+        }
+    }
+    pl011::println(b"");
+    board::set_boot_performance(Performance::Full);
+    return interrupted;
+}
+
+/// Runs the monitor until a command asks to leave it
+///
+/// `regs` are the registers trapped by the exception that entered the
+/// monitor, if any. Changes made to them by commands take effect when the
+/// exception returns. Returns at once if the console has no input (see
+/// [`pl011::has_input`]), as no command could ever be typed.
+pub fn enter(regs: Option<&mut Regs>) {
+    let mut session = Session {
+        regs,
+        failed: false,
+        depth: 0,
+    };
+    let mut line = [0u8; LINE_SIZE];
+
+    if !pl011::has_input() {
+        pl011::println(b"\nNo console input, not entering the monitor");
+        return;
+    }
+    pl011::println(b"\nEntering monitor, type 'help' for commands");
+    exception::capture_context(b"monitor");
+    if let Some(image) = warmcache::cached() {
+        print_cached(&image);
+        pl011::println(b"'cache boot' runs it without a new transfer");
+    }
+    loop {
+        let history = &raw mut HISTORY;
+        let history = unsafe { &mut *history };
+        let len = readline::read_line_with(&mut line, b"> ", Some(&*history), Some(command_names));
+        let len = expand_history(&mut line, len);
+        history.push(&line[..len]);
+
+        if run_script(&mut session, &line[..len]) == Status::Leave {
+            return;
+        }
+    }
+}
+
+/// Enters the monitor if `regs` were trapped by the monitor `brk`
+///
+/// Called by the synchronous exception handlers. Returns `true` if the
+/// exception was handled, in which case execution resumes after the `brk`.
+pub fn handle_brk(regs: &mut Regs) -> bool {
+    if exception::esr_ec(regs.esr) != exception::EC_BRK64 {
+        return false;
+    }
+    if regs.esr as u16 != MONITOR_BRK_IMM {
+        return false;
+    }
+
+    enter(Some(regs));
+    // The preferred return address of a brk is the brk itself
+    regs.elr += 4;
+    return true;
+}
+
+/// Parses a number: hex with or without `0x`, or decimal with `#`
+pub fn parse_number(s: &[u8]) -> Option<u64> {
+    let (digits, radix) = match s {
+        [b'#', rest @ ..] => (rest, 10),
+        [b'0', b'x' | b'X', rest @ ..] => (rest, 16),
+        _ => (s, 16),
+    };
+    let mut value: u64 = 0;
+
+    if digits.is_empty() {
+        return None;
+    }
+    for &c in digits {
+        let digit = (c as char).to_digit(radix)?;
+        value = value.checked_mul(radix as u64)?.checked_add(digit as u64)?;
+    }
+    return Some(value);
+}
+
+/// Parses argument `index` of `args` as a number
+///
+/// Returns `default` if the argument is missing; prints an error and returns
+/// `None` if it isn't a valid number.
+fn arg_number(args: &[&[u8]], index: usize, default: Option<u64>) -> Option<u64> {
+    let Some(arg) = args.get(index) else {
+        if default.is_none() {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+        }
+        return default;
+    };
+
+    let value = parse_number(arg);
+    if value.is_none() {
+        pl011::print(b"Invalid number: ");
+        pl011::println(arg);
+    }
+    return value;
+}
+
+/// Prints the usage line of the command called `name`
+fn print_usage(name: &[u8]) {
+    if let Some(command) = commands().find(|c| c.name == name) {
+        pl011::println(command.help);
+    }
+}
+
+/// `md <addr> [len] [width]`
+///
+/// The width is decimal, unlike the other numbers.
+fn cmd_md(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(addr) = arg_number(args, 1, None) else {
+        return session.fail();
+    };
+    let Some(len) = arg_number(args, 2, Some(MD_DEFAULT_LEN as u64)) else {
+        return session.fail();
+    };
+    let width = match args.get(3).copied() {
+        None => print::HEXDUMP_WIDTH,
+        Some(b"8") => 8,
+        Some(b"16") => 16,
+        Some(b"32") => 32,
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    };
+
+    print::hexdump_width(addr as usize, len as usize, width);
+    return false;
+}
+
+/// `sha256 <addr> <len>`
+fn cmd_sha256(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(addr) = arg_number(args, 1, None) else {
+        return session.fail();
+    };
+    let Some(len) = arg_number(args, 2, None) else {
+        return session.fail();
+    };
+
+    let data = unsafe { slice::from_raw_parts(addr as *const u8, len as usize) };
+    for byte in sha256(data) {
+        print_hex_u8(byte);
+    }
+    pl011::print(b"\n");
+    return false;
+}
+
+/// `mw <addr> <val> [width]`
+///
+/// Performs a single volatile access of the given width, so it can be used
+/// on device registers.
+fn cmd_mw(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(addr) = arg_number(args, 1, None) else {
+        return session.fail();
+    };
+    let Some(value) = arg_number(args, 2, None) else {
+        return session.fail();
+    };
+    let Some(width) = arg_number(args, 3, Some(4)) else {
+        return session.fail();
+    };
+
+    let Some(width) = Width::from_bytes(width) else {
+        pl011::println(b"Width must be 1, 2, 4 or 8");
+        return session.fail();
+    };
+    if !(addr as usize).is_multiple_of(width.bytes()) {
+        pl011::println(b"Address is not aligned to the width");
+        return session.fail();
+    }
+
+    unsafe {
+        mmio::write_mmio(addr as usize, 0, width, value);
+    }
+    return false;
+}
+
+/// Prints a named register value
+fn print_reg(name: &[u8], value: u64) {
+    pl011::print(name);
+    pl011::print(b": 0x");
+    print_hex_u64(value);
+    pl011::print(b"\n");
+}
+
+/// `regs`
+fn cmd_regs(session: &mut Session, _args: &[&[u8]]) -> bool {
+    let el = cpu::current_el();
+    let (sctlr, vbar): (u64, u64);
+    let daif: u64;
+
+    unsafe {
+        match el {
+            1 => asm!("mrs {}, sctlr_el1", "mrs {}, vbar_el1", out(reg) sctlr, out(reg) vbar),
+            2 => asm!("mrs {}, sctlr_el2", "mrs {}, vbar_el2", out(reg) sctlr, out(reg) vbar),
+            _ => asm!("mrs {}, sctlr_el3", "mrs {}, vbar_el3", out(reg) sctlr, out(reg) vbar),
+        }
+        asm!("mrs {}, daif", out(reg) daif);
+    }
+
+    print_reg(b"CurrentEL", el as u64);
+    print_reg(b"SCTLR    ", sctlr);
+    print_reg(b"VBAR     ", vbar);
+    print_reg(b"DAIF     ", daif);
+    print_reg(b"CNTFRQ   ", generic::frequency());
+    print_reg(b"CNTPCT   ", generic::counter());
+
+    match session.regs.as_deref() {
+        Some(regs) => regs.print(),
+        None => pl011::println(b"No trapped registers"),
+    }
+    return false;
+}
+
+/// Prints watchpoint `index` and what it watches
+fn print_watch(index: u8, watch: &Watch) {
+    let mut buf = [0u8; 20];
+
+    pl011::print(b"Watchpoint ");
+    pl011::print(print::u64_to_dec(index as u64, &mut buf));
+    pl011::print(b": ");
+    pl011::print(print::u64_to_dec(watch.len, &mut buf));
+    pl011::print(b" bytes at 0x");
+    print_hex_u64(watch.addr);
+    pl011::print(b", ");
+    pl011::println(watch.access.name());
+}
+
+/// `watch [<addr> [len] [r|w|rw]]`
+///
+/// Without arguments, lists the watchpoints. Otherwise sets the first free
+/// one on `len` bytes at `addr`, 4 by default, for stores unless told
+/// otherwise. When the access happens, [`watch_hit`] reports it.
+fn cmd_watch(session: &mut Session, args: &[&[u8]]) -> bool {
+    if args.len() == 1 {
+        let count = debug::watchpoint_count();
+        let mut none = true;
+        for index in 0..count {
+            if let Some(watch) = debug::watchpoint(index) {
+                print_watch(index, &watch);
+                none = false;
+            }
+        }
+        if none {
+            pl011::print(b"No watchpoints set, ");
+            pl011::print(print::u64_to_dec(count as u64, &mut [0u8; 20]));
+            pl011::println(b" available");
+        }
+        return false;
+    }
+    let Some(addr) = arg_number(args, 1, None) else {
+        return session.fail();
+    };
+    let Some(len) = arg_number(args, 2, Some(4)) else {
+        return session.fail();
+    };
+    let access = match args.get(3).copied() {
+        None | Some(b"w") => WatchAccess::Write,
+        Some(b"r") => WatchAccess::Read,
+        Some(b"rw") => WatchAccess::ReadWrite,
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    };
+    let watch = Watch { addr, len, access };
+
+    if debug::watch_registers(watch).is_none() {
+        pl011::println(b"Only up to 8 bytes within a doubleword, or an aligned power of two");
+        return session.fail();
+    }
+    let Some(index) = (0..debug::watchpoint_count()).find(|&i| debug::watchpoint(i).is_none())
+    else {
+        pl011::println(b"No free watchpoint");
+        return session.fail();
+    };
+    debug::set_watchpoint_handler(Some(watch_hit));
+    debug::set_watchpoint(index, watch);
+    print_watch(index, &watch);
+    return false;
+}
+
+/// `delete watch <n>`
+fn cmd_delete(session: &mut Session, args: &[&[u8]]) -> bool {
+    if args.get(1) != Some(&&b"watch"[..]) {
+        pl011::print(b"Usage: ");
+        print_usage(args[0]);
+        return session.fail();
+    }
+    let Some(index) = arg_number(args, 2, None) else {
+        return session.fail();
+    };
+    if index > u8::MAX as u64 || !debug::clear_watchpoint(index as u8) {
+        pl011::println(b"No such watchpoint");
+        return session.fail();
+    }
+    return false;
+}
+
+/// Reports the watchpoint exception trapped in `regs` and enters the
+/// monitor
+///
+/// The access happens once the monitor is left, the watchpoint staying set.
+/// Debug exceptions are masked while the bootloader itself runs, so the
+/// watched accesses are the payload's, trapped when the bootloader runs at
+/// EL2, or those of code that unmasked them.
+fn watch_hit(regs: &mut Regs) -> bool {
+    let hit = debug::watch_hit(regs);
+    let access: &[u8] = match hit.write {
+        true => b": write to 0x",
+        false => b": read of 0x",
+    };
+
+    log::to_all_sinks(|| {
+        pl011::print(b"\nWatchpoint ");
+        match hit.index {
+            Some(index) => pl011::print(print::u64_to_dec(index as u64, &mut [0u8; 20])),
+            None => pl011::print(b"?"),
+        }
+        pl011::print(access);
+        print_hex_u64(hit.addr);
+        pl011::print(b" by the instruction at 0x");
+        print_hex_u64(hit.pc);
+        pl011::print(b"\n");
+    });
+    exception::dump_exception(b"Watchpoint", regs);
+    enter(Some(regs));
+    return true;
+}
+
+/// Number of changed addresses listed by `poison check`
+const POISON_LIST_MAX: usize = 8;
+
+/// `poison check <addr> <len>`
+///
+/// Checks that the range still holds the [`poison`] pattern, listing the
+/// first bytes that don't.
+fn cmd_poison(session: &mut Session, args: &[&[u8]]) -> bool {
+    if args.get(1) != Some(&&b"check"[..]) {
+        pl011::print(b"Usage: ");
+        print_usage(args[0]);
+        return session.fail();
+    }
+    let Some(addr) = arg_number(args, 2, None) else {
+        return session.fail();
+    };
+    let Some(len) = arg_number(args, 3, None) else {
+        return session.fail();
+    };
+
+    let mut listed = 0;
+    let changed = unsafe {
+        poison::check(addr as usize, len as usize, &mut |addr| {
+            if listed < POISON_LIST_MAX {
+                pl011::print(b"Changed: 0x");
+                print_hex_u64(addr as u64);
+                pl011::println(b"");
+                listed += 1;
+            }
+        })
+    };
+    pl011::print(print::u64_to_dec(changed as u64, &mut [0u8; 20]));
+    pl011::println(b" bytes changed");
+    return false;
+}
+
+/// `meminfo`
+///
+/// Prints the allocation statistics, the bytes reserved per owner and the
+/// free RAM ranges.
+fn cmd_meminfo(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    let stats = reserve::stats();
+
+    print_reg(b"RAM           ", stats.capacity as u64);
+    print_reg(b"Used          ", stats.used as u64);
+    print_reg(b"Peak          ", stats.high_water as u64);
+    print_reg(b"Failed allocs ", stats.failed as u64);
+    print_reg(b"Largest failed", stats.largest_failed as u64);
+
+    pl011::println(b"Reserved by owner:");
+    for tag in ReserveTag::ALL {
+        pl011::print(b"  ");
+        print_reg(tag.name(), reserve::tag_total(tag) as u64);
+    }
+
+    pl011::println(b"Free ranges:");
+    reserve::for_each_gap(|base, size| {
+        pl011::print(b"  0x");
+        print_hex_u64(base as u64);
+        pl011::print(b" - 0x");
+        print_hex_u64((base + size) as u64);
+        pl011::print(b"\n");
+    });
+    return false;
+}
+
+/// Width of the node name column of `devices`
+const DEVICE_NAME_WIDTH: usize = 24;
+/// Width of the driver column of `devices`
+const DEVICE_DRIVER_WIDTH: usize = 12;
+
+/// Prints `text` padded with spaces to `width` characters
+fn print_column(text: &[u8], width: usize) {
+    pl011::print(text);
+    for _ in text.len()..width {
+        pl011::print(b" ");
+    }
+}
+
+/// `devices`
+///
+/// Lists the devices found in the DTB with their driver, and whether they
+/// were probed, are still waiting for another device, or why they weren't
+/// used. See [`registry`].
+fn cmd_devices(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    let mut found = false;
+
+    for device in registry::devices() {
+        found = true;
+        print_column(device.name(), DEVICE_NAME_WIDTH);
+        pl011::print(b" ");
+        print_column(device.driver, DEVICE_DRIVER_WIDTH);
+        pl011::print(b" ");
+        match device.result {
+            Ok(()) => pl011::println(b"probed"),
+            Err(ProbeError::Deferred(what)) => {
+                pl011::print(b"deferred, waiting for ");
+                pl011::println(what);
+            }
+            Err(e) => pl011::println(e.message()),
+        }
+    }
+    if !found {
+        pl011::println(b"No devices found in the DTB");
+    }
+    return false;
+}
+
+/// `fdt <addr>`
+///
+/// Prints the nodes and properties of the DTB at `addr`, or why it isn't a
+/// valid one. See [`fdt::dump`].
+fn cmd_fdt(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(addr) = arg_number(args, 1, None) else {
+        return session.fail();
+    };
+
+    if let Err(e) = unsafe { fdt::dump_fdt(addr as usize) } {
+        error::print_error(&e.into());
+    }
+    return false;
+}
+
+/// Prints why an NVMe operation failed
+fn print_nvme_error(e: NvmeError) {
+    pl011::print(b"NVMe: ");
+    pl011::print(e.message());
+    if let NvmeError::Status(status) = e {
+        pl011::print(b", status 0x");
+        print_hex_u64(status as u64);
+    }
+    pl011::print(b"\n");
+}
+
+/// `nvme [read <addr> <lba> <count> [nsid]]`
+///
+/// Sets up the NVMe controller if needed and shows it and its first
+/// namespace. With `read`, reads `count` blocks of namespace `nsid` (1
+/// unless given) from block `lba` to `addr`.
+fn cmd_nvme(session: &mut Session, args: &[&[u8]]) -> bool {
+    let mut dec = [0u8; 20];
+    let controller = match nvme::init() {
+        Ok(controller) => controller,
+        Err(e) => {
+            print_nvme_error(e);
+            return session.fail();
+        }
+    };
+
+    match args.get(1).copied() {
+        None => {}
+        Some(b"read") => return cmd_nvme_read(session, args),
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    }
+
+    pl011::print(b"NVMe controller ");
+    pl011::print(controller.model.trim_ascii_end());
+    pl011::print(b" at 0x");
+    print_hex_u64(controller.base as u64);
+    let function = controller.function;
+    pl011::print(b", PCIe device ");
+    pl011::print(print::u64_to_dec(function.device as u64, &mut dec));
+    pl011::print(b".");
+    pl011::println(print::u64_to_dec(function.function as u64, &mut dec));
+    match nvme::namespace(1) {
+        Ok(namespace) => {
+            pl011::print(b"Namespace 1: ");
+            pl011::print(print::u64_to_dec(namespace.blocks, &mut dec));
+            pl011::print(b" blocks of ");
+            pl011::print(print::u64_to_dec(namespace.block_size as u64, &mut dec));
+            pl011::println(b" bytes");
+        }
+        Err(e) => print_nvme_error(e),
+    }
+    return false;
+}
+
+/// `nvme read <addr> <lba> <count> [nsid]`
+fn cmd_nvme_read(session: &mut Session, args: &[&[u8]]) -> bool {
+    let (Some(addr), Some(lba), Some(count), Some(nsid)) = (
+        arg_number(args, 2, None),
+        arg_number(args, 3, None),
+        arg_number(args, 4, None),
+        arg_number(args, 5, Some(1)),
+    ) else {
+        return session.fail();
+    };
+    let namespace = match nvme::namespace(nsid as u32) {
+        Ok(namespace) => namespace,
+        Err(e) => {
+            print_nvme_error(e);
+            return session.fail();
+        }
+    };
+    let Some(len) = (count as usize).checked_mul(namespace.block_size) else {
+        print_nvme_error(NvmeError::OutOfRange);
+        return session.fail();
+    };
+
+    if reserve::is_reserved(addr as usize, len) {
+        pl011::println(b"Destination overlaps reserved memory");
+        return session.fail();
+    }
+    let dst = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+    if let Err(e) = nvme::read_lbas(namespace.nsid, lba, count as usize, dst) {
+        print_nvme_error(e);
+        return session.fail();
+    }
+    print_reg(b"Read bytes", len as u64);
+    return false;
+}
+
+/// Parses a display resolution: `WIDTHxHEIGHT`, in decimal
+fn parse_resolution(s: &[u8]) -> Option<(usize, usize)> {
+    let x = s.iter().position(|&c| c == b'x')?;
+    let decimal = |digits: &[u8]| core::str::from_utf8(digits).ok()?.parse::<usize>().ok();
+
+    return Some((decimal(&s[..x])?, decimal(&s[x + 1..])?));
+}
+
+/// `video [on [WIDTHxHEIGHT] [FORMAT]]`
+///
+/// Shows the framebuffer console. With `on`, sets up a ramfb display
+/// (1024x768 xrgb8888 unless given; formats are xrgb8888, xbgr8888 and
+/// rgb565) and mirrors the console on it. Once started, the console keeps
+/// its mode.
+fn cmd_video(session: &mut Session, args: &[&[u8]]) -> bool {
+    let mut dec = [0u8; 20];
+
+    match args.get(1).copied() {
+        None => {}
+        Some(b"on") => {
+            let mut mode = ramfb::DEFAULT_MODE;
+            for &arg in &args[2..] {
+                if let Some(format) = PixelFormat::from_name(arg) {
+                    mode.format = format;
+                } else if let Some((width, height)) = parse_resolution(arg) {
+                    mode.width = width;
+                    mode.height = height;
+                } else {
+                    pl011::print(b"Usage: ");
+                    print_usage(args[0]);
+                    return session.fail();
+                }
+            }
+            if let Err(e) = console::start(mode) {
+                pl011::print(b"Framebuffer console not started: ");
+                pl011::println(e.message());
+                return session.fail();
+            }
+        }
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    }
+
+    let Some(fb) = console::framebuffer() else {
+        pl011::println(b"No framebuffer console");
+        return false;
+    };
+    pl011::print(b"Framebuffer console: ");
+    pl011::print(print::u64_to_dec(fb.width as u64, &mut dec));
+    pl011::print(b"x");
+    pl011::print(print::u64_to_dec(fb.height as u64, &mut dec));
+    pl011::print(b" ");
+    pl011::print(fb.format.name());
+    pl011::print(b" at 0x");
+    print_hex_u64(fb.base as u64);
+    pl011::print(b"\n");
+    return false;
+}
+
+/// `uptime`
+fn cmd_uptime(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    generic::print_uptime();
+    return false;
+}
+
+/// `bootid`
+fn cmd_bootid(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    bootid::print();
+    pl011::print(b"\n");
+    return false;
+}
+
+/// `smbios`
+///
+/// Lists the structures of the tables with their strings.
+fn cmd_smbios(session: &mut Session, _args: &[&[u8]]) -> bool {
+    let (Some(entry), Some(table)) = (smbios::entry_point(), smbios::table()) else {
+        pl011::println(b"No SMBIOS tables");
+        return session.fail();
+    };
+    let mut dec = [0u8; 20];
+    let mut off = 0;
+
+    pl011::print(b"Entry point at 0x");
+    print_hex_u64(entry as u64);
+    pl011::print(b"\n");
+    while off < table.len() {
+        pl011::print(b"Type ");
+        pl011::print(print::u64_to_dec(table[off] as u64, &mut dec));
+        pl011::print(b", handle ");
+        let handle = u16::from_le_bytes([table[off + 2], table[off + 3]]);
+        pl011::print(print::u64_to_dec(handle as u64, &mut dec));
+        pl011::print(b"\n");
+        let mut index = 1;
+        loop {
+            let string = smbios::string(table, off, index);
+            if string.is_empty() {
+                break;
+            }
+            pl011::print(b"  ");
+            pl011::println(string);
+            index += 1;
+        }
+        match smbios::next_structure(table, off) {
+            Some(next) => off = next,
+            None => break,
+        }
+    }
+    return false;
+}
+
+/// `log [start|stop|save]`
+///
+/// Without an argument, shows whether capture is on and how much it holds.
+fn cmd_log(session: &mut Session, args: &[&[u8]]) -> bool {
+    match args.get(1).copied() {
+        None => {
+            let (size, compressed) = capture::sizes();
+            match capture::is_active() {
+                true => pl011::println(b"Console capture on"),
+                false => pl011::println(b"Console capture off"),
+            }
+            print_reg(b"Captured  ", size as u64);
+            print_reg(b"Compressed", compressed as u64);
+        }
+        Some(b"start") => {
+            if capture::start().is_err() {
+                pl011::println(b"No room for the console capture");
+                session.fail();
+            }
+        }
+        Some(b"stop") => capture::stop(),
+        Some(b"save") => capture::save(),
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            session.fail();
+        }
+    }
+    return false;
+}
+
+/// `selftest [name]`
+fn cmd_selftest(session: &mut Session, args: &[&[u8]]) -> bool {
+    selftest::register_builtin();
+    let summary = match args.get(1) {
+        None => selftest::run_all(),
+        Some(name) => {
+            let Some(summary) = selftest::run_one(name) else {
+                pl011::print(b"Unknown self-test: ");
+                pl011::println(name);
+                return session.fail();
+            };
+            summary
+        }
+    };
+    if summary.status() != 0 {
+        return session.fail();
+    }
+    return false;
+}
+
+/// `go <addr>`
+///
+/// Calls the code at `addr` as a function and prints its return value.
+/// Refuses device memory, which can't hold code.
+fn cmd_go(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(addr) = arg_number(args, 1, None) else {
+        return session.fail();
+    };
+
+    return call(session, addr as usize);
+}
+
+/// Calls the code at `addr` as a function and prints its return value, as
+/// `go` does
+fn call(session: &mut Session, addr: usize) -> bool {
+    let ret: u64;
+
+    if map::is_device(addr) {
+        pl011::println(b"Address is device memory");
+        return session.fail();
+    }
+
+    unsafe {
+        let entry: extern "C" fn() -> u64 = core::mem::transmute(addr);
+        ret = entry();
+    }
+    print_reg(b"Returned", ret);
+    return false;
+}
+
+/// Parses the arguments shared by `load` and `rb`: `<addr> [maxlen] [secs]`
+///
+/// Returns the destination and the deadline of the transfer, or `None`
+/// after printing why the arguments were refused.
+fn transfer_args(args: &[&[u8]]) -> Option<(&'static mut [u8], Deadline)> {
+    let addr = arg_number(args, 1, None)?;
+    let max_len = arg_number(args, 2, Some(LOAD_DEFAULT_MAX as u64))?;
+    let deadline = match args.get(3) {
+        None => Deadline::NEVER,
+        Some(_) => Deadline::after_ms(arg_number(args, 3, None)?.saturating_mul(1000)),
+    };
+
+    if reserve::is_reserved(addr as usize, max_len as usize) {
+        pl011::println(b"Destination overlaps reserved memory");
+        return None;
+    }
+    let dst = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, max_len as usize) };
+    return Some((dst, deadline));
+}
+
+/// Prints why a serial transfer failed
+fn print_transfer_error(e: xmodem::XmodemError) {
+    match e {
+        xmodem::XmodemError::Timeout => pl011::println(b"Transfer timed out"),
+        xmodem::XmodemError::Aborted => pl011::println(b"Transfer aborted"),
+        xmodem::XmodemError::Cancelled => pl011::println(b"Transfer cancelled"),
+        xmodem::XmodemError::BufferTooSmall => pl011::println(b"Image too large"),
+        xmodem::XmodemError::BadBlock => pl011::println(b"Transfer failed: bad block"),
+        xmodem::XmodemError::TooManyRetries => pl011::println(b"Transfer failed: too many retries"),
+        xmodem::XmodemError::BadHeader => pl011::println(b"Transfer failed: bad file header"),
+    }
+}
+
+/// `load <addr> [maxlen] [secs]`
+///
+/// Refuses to receive into memory reserved by the bootloader. With `secs`,
+/// the transfer is abandoned after that many seconds; `Ctrl-C` aborts it
+/// at any time.
+fn cmd_load(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some((dst, deadline)) = transfer_args(args) else {
+        return session.fail();
+    };
+
+    let base = dst.as_ptr() as usize;
+
+    pl011::println(b"Waiting for XMODEM transfer...");
+    match xmodem::receive(dst, deadline) {
+        Ok(len) => {
+            print_reg(b"Received bytes", len as u64);
+            warmcache::record(b"xmodem", base, len);
+        }
+        Err(e) => {
+            print_transfer_error(e);
+            session.fail();
+        }
+    }
+    return false;
+}
+
+/// `rb <addr> [maxlen] [secs]`
+///
+/// Like `load`, over YMODEM: the file's name is printed, and its length is
+/// exact rather than rounded up to whole blocks.
+fn cmd_rb(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some((dst, deadline)) = transfer_args(args) else {
+        return session.fail();
+    };
+    let base = dst.as_ptr() as usize;
+    // "ymodem:" and the file name, the source of the cached image
+    let mut name = [0u8; LINE_SIZE];
+    name[..7].copy_from_slice(b"ymodem:");
+
+    pl011::println(b"Waiting for YMODEM transfer...");
+    match ymodem::receive(dst, &mut name[7..], deadline) {
+        Ok((_, 0)) => {
+            pl011::println(b"No file sent");
+            session.fail();
+        }
+        Ok((len, name_len)) => {
+            pl011::print(b"Received ");
+            pl011::println(&name[7..7 + name_len]);
+            print_reg(b"Received bytes", len as u64);
+            warmcache::record(&name[..7 + name_len], base, len);
+        }
+        Err(e) => {
+            print_transfer_error(e);
+            session.fail();
+        }
+    }
+    return false;
+}
+
+/// Prints where a cached image came from and where it is
+fn print_cached(image: &CachedImage) {
+    pl011::print(b"Cached image from ");
+    pl011::println(image.source());
+    print_reg(b"  Address", image.base as u64);
+    print_reg(b"  Size", image.len as u64);
+}
+
+/// `cache [clear|boot]`
+///
+/// The image last received by `load` or `rb` is kept across warm resets
+/// (see [`warmcache`]). Without arguments, shows it if it's still intact;
+/// `clear` forgets it, and `boot` calls it as `go` does, saving the
+/// transfer.
+fn cmd_cache(session: &mut Session, args: &[&[u8]]) -> bool {
+    match args.get(1).copied() {
+        Some(b"clear") => {
+            warmcache::clear();
+            return false;
+        }
+        None | Some(b"boot") => {}
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    }
+
+    let Some(image) = warmcache::cached() else {
+        pl011::println(b"No cached image");
+        return session.fail();
+    };
+    if args.len() == 1 {
+        print_cached(&image);
+        return false;
+    }
+    return call(session, image.base);
+}
+
+/// `align [on|off]`
+///
+/// With `on`, unaligned data accesses raise alignment faults; useful before
+/// running a payload under test with `go`.
+fn cmd_align(session: &mut Session, args: &[&[u8]]) -> bool {
+    match args.get(1).copied() {
+        None => {}
+        Some(b"on") => _ = diagnostics::alignment_check(true),
+        Some(b"off") => _ = diagnostics::alignment_check(false),
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    }
+    match diagnostics::alignment_check_enabled() {
+        true => pl011::println(b"Strict alignment checking on"),
+        false => pl011::println(b"Strict alignment checking off"),
+    }
+    return false;
+}
+
+/// `tee [on|off]`
+fn cmd_tee(session: &mut Session, args: &[&[u8]]) -> bool {
+    let enable = match args.get(1).copied() {
+        None => None,
+        Some(b"on") => Some(true),
+        Some(b"off") => Some(false),
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    };
+
+    if let Some(enable) = enable
+        && !log::set_tee(enable)
+    {
+        pl011::println(b"No debug channel");
+        return session.fail();
+    }
+    match log::tee_enabled() {
+        true => pl011::println(b"Console mirrored to the debug channel"),
+        false => pl011::println(b"Console not mirrored"),
+    }
+    return false;
+}
+
+/// `baud [rate]`
+///
+/// The rate is in decimal, as baud rates are always written. The terminal has to be switched to the new rate too; the message before
+/// the change is the last one sent at the old rate.
+fn cmd_baud(session: &mut Session, args: &[&[u8]]) -> bool {
+    let mut buf = [0u8; 20];
+
+    let Some(&arg) = args.get(1) else {
+        pl011::print(print::u64_to_dec(pl011::baudrate() as u64, &mut buf));
+        pl011::println(b" baud");
+        return false;
+    };
+    let decimal = core::str::from_utf8(arg)
+        .ok()
+        .and_then(|r| r.parse::<u32>().ok());
+    let Some(rate) = decimal else {
+        pl011::print(b"Usage: ");
+        print_usage(args[0]);
+        return session.fail();
+    };
+    if pl011::divisor(pl011::base_clock(), rate).is_none() {
+        error::print_error(&UartError::BadBaudrate.into());
+        return session.fail();
+    }
+    pl011::print(b"Switching to ");
+    pl011::print(print::u64_to_dec(rate as u64, &mut buf));
+    pl011::println(b" baud");
+    // Can't fail: the rate was checked against the clock above
+    _ = pl011::set_baud(rate);
+    return false;
+}
+
+/// `pauth [keys|off]`
+///
+/// With `keys`, the payload starts with random pointer authentication keys
+/// and instruction address authentication enabled; with `off`, it starts
+/// with it disabled. See the [`pauth`] module.
+fn cmd_pauth(session: &mut Session, args: &[&[u8]]) -> bool {
+    match args.get(1).copied() {
+        None => {}
+        Some(b"keys") => pauth::set_policy(Policy::Keys),
+        Some(b"off") => pauth::set_policy(Policy::Disabled),
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    }
+    if !cpu::has_pauth() {
+        pl011::println(b"Pointer authentication not implemented");
+        return session.fail();
+    }
+    match pauth::policy() {
+        Policy::Keys => pl011::println(b"Pointer authentication keys set at handoff"),
+        Policy::Disabled => pl011::println(b"Pointer authentication disabled at handoff"),
+    }
+    return false;
+}
+
+/// `handoff [el1|el2|el2-vhe]`
+///
+/// With `el2` or `el2-vhe`, the payload starts at EL2 in the state described
+/// by [`boot::boot_el2`], which needs the bootloader to run at EL2 and, for
+/// `el2-vhe`, FEAT_VHE; with `el1`, it starts at EL1.
+fn cmd_handoff(session: &mut Session, args: &[&[u8]]) -> bool {
+    let mode = match args.get(1).copied() {
+        None => boot::handoff_mode(),
+        Some(b"el1") => HandoffMode::El1,
+        Some(b"el2") => HandoffMode::El2,
+        Some(b"el2-vhe") => HandoffMode::El2Vhe,
+        Some(_) => {
+            pl011::print(b"Usage: ");
+            print_usage(args[0]);
+            return session.fail();
+        }
+    };
+    if mode != HandoffMode::El1 && cpu::current_el() != 2 {
+        error::print_error(&HandoffError::NotAtEl2.into());
+        return session.fail();
+    }
+    if mode == HandoffMode::El2Vhe && !cpu::has_vhe() {
+        error::print_error(&HandoffError::NoVhe.into());
+        return session.fail();
+    }
+    boot::set_handoff_mode(mode);
+    match mode {
+        HandoffMode::El1 => pl011::println(b"Payload entered at EL1"),
+        HandoffMode::El2 => pl011::println(b"Payload entered at EL2 (nVHE)"),
+        HandoffMode::El2Vhe => pl011::println(b"Payload entered at EL2 (VHE)"),
+    }
+    return false;
+}
+
+/// `crash [kind]`
+///
+/// Without a kind, lists the scenarios of [`diagnostics::crash`].
+fn cmd_crash(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(&kind) = args.get(1) else {
+        for crash in &diagnostics::CRASHES {
+            pl011::print(b"  ");
+            pl011::print(crash.name);
+            pl011::print(&b"                "[crash.name.len().min(16)..]);
+            pl011::println(crash.description);
+        }
+        return false;
+    };
+
+    if !diagnostics::crash(kind) {
+        pl011::print(b"Unknown crash: ");
+        pl011::println(kind);
+        return session.fail();
+    }
+    return false;
+}
+
+/// `boot`
+fn cmd_boot(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    return true;
+}
+
+/// `reset`
+///
+/// Issues PSCI SYSTEM_RESET to the firmware, through the board's conduit
+/// (see [`BoardConfig::psci_conduit`](board::BoardConfig::psci_conduit)).
+fn cmd_reset(session: &mut Session, _args: &[&[u8]]) -> bool {
+    unsafe {
+        match board::config().psci_conduit() {
+            Conduit::Smc => asm!("smc #0", inout("x0") PSCI_SYSTEM_RESET => _),
+            Conduit::Hvc => asm!("hvc #0", inout("x0") PSCI_SYSTEM_RESET => _),
+        }
+    }
+    pl011::println(b"PSCI SYSTEM_RESET failed");
+    return session.fail();
+}
+
+/// `echo [args]`
+fn cmd_echo(_session: &mut Session, args: &[&[u8]]) -> bool {
+    for (i, arg) in args[1..].iter().enumerate() {
+        if i > 0 {
+            pl011::print(b" ");
+        }
+        pl011::print(arg);
+    }
+    pl011::print(b"\n");
+    return false;
+}
+
+/// `setenv <name> [value]`
+///
+/// The words of the value are joined with single spaces; quote it to keep
+/// it as typed. Setting `logfmt` changes the [log format](log::Format) at
+/// once.
+fn cmd_setenv(session: &mut Session, args: &[&[u8]]) -> bool {
+    let mut value = [0u8; env::VALUE_SIZE];
+    let mut len = 0;
+
+    let Some(&name) = args.get(1) else {
+        pl011::print(b"Usage: ");
+        print_usage(args[0]);
+        return session.fail();
+    };
+    if args.len() == 2 {
+        env::unset(name);
+        return false;
+    }
+    for (i, word) in args[2..].iter().enumerate() {
+        if len + (i > 0) as usize + word.len() > value.len() {
+            pl011::println(b"Value too long");
+            return session.fail();
+        }
+        if i > 0 {
+            value[len] = b' ';
+            len += 1;
+        }
+        value[len..len + word.len()].copy_from_slice(word);
+        len += word.len();
+    }
+    match env::set(name, &value[..len]) {
+        Ok(()) => {
+            if name == log::FORMAT_VAR {
+                log::load_format();
+            }
+            return false;
+        }
+        Err(env::EnvError::BadName) => pl011::println(b"Invalid variable name"),
+        Err(env::EnvError::ValueTooLong) => pl011::println(b"Value too long"),
+        Err(env::EnvError::Full) => pl011::println(b"Environment full"),
+    }
+    return session.fail();
+}
+
+/// `printenv [name]`
+fn cmd_printenv(session: &mut Session, args: &[&[u8]]) -> bool {
+    let Some(&name) = args.get(1) else {
+        env::for_each(|name, value| {
+            pl011::print(name);
+            pl011::print(b"=");
+            pl011::println(value);
+        });
+        return false;
+    };
+
+    match env::get(name) {
+        Some(value) => {
+            pl011::print(name);
+            pl011::print(b"=");
+            pl011::println(value);
+            return false;
+        }
+        None => {
+            pl011::print(b"Not set: ");
+            pl011::println(name);
+            return session.fail();
+        }
+    }
+}
+
+/// `run <name>`
+///
+/// Runs the variable as a script, with the status of its last command.
+/// Nesting is limited to [`MAX_RUN_DEPTH`], so a variable running itself
+/// fails instead of recursing forever.
+fn cmd_run(session: &mut Session, args: &[&[u8]]) -> bool {
+    let mut script = [0u8; env::VALUE_SIZE];
+
+    let Some(&name) = args.get(1) else {
+        pl011::print(b"Usage: ");
+        print_usage(args[0]);
+        return session.fail();
+    };
+    let Some(value) = env::get(name) else {
+        pl011::print(b"Not set: ");
+        pl011::println(name);
+        return session.fail();
+    };
+    if session.depth == MAX_RUN_DEPTH {
+        pl011::println(b"run: nested too deep");
+        return session.fail();
+    }
+    // The script may change the variable while it runs
+    script[..value.len()].copy_from_slice(value);
+    let len = value.len();
+
+    session.depth += 1;
+    let status = run_script(session, &script[..len]);
+    session.depth -= 1;
+    session.failed = status == Status::Failed;
+    return status == Status::Leave;
+}
+
+/// `history`
+///
+/// Lists the kept command lines with their numbers, oldest first.
+fn cmd_history(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    let history = &raw const HISTORY;
+    let history = unsafe { &*history };
+    let mut dec = [0u8; 20];
+
+    for number in history.first()..=history.last() {
+        if let Some(entry) = history.get(number) {
+            pl011::print(b"  ");
+            pl011::print(print::u64_to_dec(number as u64, &mut dec));
+            pl011::print(b"  ");
+            pl011::println(entry);
+        }
+    }
+    return false;
+}
+
+/// `help`
+fn cmd_help(_session: &mut Session, _args: &[&[u8]]) -> bool {
+    for command in commands() {
+        pl011::println(command.help);
+    }
+    return false;
+}
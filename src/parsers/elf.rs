@@ -5,11 +5,30 @@
 //! headers, and loads executable segments into memory.
 //!
 //! The loader supports loading AArch64 executable files and returns the entry
-//! point address for execution.
+//! point address for execution. Images are read through an
+//! [`ImageSource`], so they don't have to be staged whole in memory: the
+//! headers are read first, then each segment straight to its destination.
+//! Sources may return short reads; an image ending before the data its
+//! headers describe fails with [`ElfError::Truncated`].
+//!
+//! Big-endian images are rejected with [`ElfError::BigEndianUnsupported`].
+//! Built with the `elf-big-endian` feature, the loader also prints the header
+//! of such an image with its fields byte-swapped, so it can still be told
+//! what the image is.
 
+use crate::bootplan::{BootPlan, Component, RamSource};
+use crate::drivers::timer::generic::Deadline;
 use crate::drivers::uart::pl011;
+use crate::env;
+use crate::exception;
+use crate::log::{self, Level, Value};
+use crate::memory::map;
+use crate::memory::reserve::{self, ReserveTag};
+use crate::parsers::image::{ImageSource, Lz77Source, MemorySource};
+use crate::selftest::Outcome;
+use crate::utilities::print;
 
-use core::{mem, ptr};
+use core::{mem, ptr, slice};
 
 /// Size of the ELF magic number
 const SELFMAG: usize = 4;
@@ -23,11 +42,16 @@ const ELFCLASS64: usize = 2;
 const EI_DATA: usize = 5;
 /// 2's complement, little-endian encoding
 const ELFDATA2LSB: usize = 1;
+/// 2's complement, big-endian encoding
+const ELFDATA2MSB: usize = 2;
 /// Index of the OS/ABI identification in e_ident
 const EI_OSABI: usize = 7;
 /// UNIX System V ABI
 const ELFOSABI_SYSV: usize = 0;
 
+/// Current ELF format version
+const EV_CURRENT: usize = 1;
+
 /// Executable file type
 const ET_EXEC: usize = 2;
 
@@ -37,6 +61,24 @@ const EM_AARCH64: usize = 183;
 /// Loadable program segment
 const PT_LOAD: usize = 1;
 
+/// Segments are copied in chunks of this size
+const COPY_CHUNK: usize = 64 * 1024;
+/// Loaded segments are read back from the image in chunks of this size
+const VERIFY_CHUNK: usize = 256;
+/// Environment variable asking [`load_kernel`] to verify the kernel
+pub const VERIFY_VAR: &[u8] = b"verify";
+/// Most program headers an image may have
+const MAX_PHDRS: usize = 16;
+/// A heartbeat dot is printed every this many chunks (every 1 MiB)
+const HEARTBEAT_STEP: u64 = 16;
+
+/// Callback told how far [`load_elf`] is, after every chunk it copies
+///
+/// `copied` counts the segment bytes read so far, out of `total`, the sum of
+/// the `p_filesz` of the loadable segments. [`print::progress`] renders it
+/// on the console.
+pub type Progress = fn(copied: usize, total: usize);
+
 /// ELF64 File Header
 ///
 /// This structure represents the header of a 64-bit ELF file, containing
@@ -74,6 +116,8 @@ struct Elf64Ehdr {
     e_shstrndx: u16,
 }
 
+const _: () = assert!(mem::size_of::<Elf64Ehdr>() == 64);
+
 /// ELF64 Program Header
 ///
 /// This structure describes a segment or other information the system needs
@@ -100,103 +144,817 @@ struct Elf64Phdr {
     p_align: u64,
 }
 
+const _: () = assert!(mem::size_of::<Elf64Phdr>() == 56);
+
+impl Elf64Phdr {
+    /// Program header of type PT_NULL
+    const EMPTY: Self = Self {
+        p_type: 0,
+        p_flags: 0,
+        p_offset: 0,
+        p_vaddr: 0,
+        p_paddr: 0,
+        p_filesz: 0,
+        p_memsz: 0,
+        p_align: 0,
+    };
+}
+
+/// Memory extents and entry point of a loaded ELF image
+///
+/// Describes where the PT_LOAD segments of an image are placed by loading.
+/// The range covers every segment including its BSS.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedImage {
+    /// Lowest virtual address occupied by a loaded segment
+    pub start: usize,
+    /// One past the highest virtual address occupied by a loaded segment
+    pub end: usize,
+    /// Virtual address of the program entry point
+    pub entry: usize,
+}
+
+/// Errors reported while validating an ELF image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// The ELF magic number is missing
+    NotElf,
+    /// The file is not a 64-bit ELF file
+    NotElf64,
+    /// The data encoding is neither little- nor big-endian
+    BadEndianness,
+    /// The file is big-endian, which isn't supported
+    BigEndianUnsupported,
+    /// A loadable segment would be placed over device memory
+    DeviceTarget,
+    /// A loadable segment would be placed over memory the DTB reserves
+    ReservedTarget,
+    /// Loading didn't finish before its deadline
+    Timeout,
+    /// Loading was aborted with `Ctrl-C`
+    Aborted,
+    /// The OS/ABI is not System V
+    BadOsAbi,
+    /// The file is not an executable
+    BadType,
+    /// The file is not built for AArch64
+    BadMachine,
+    /// The ELF format version is not the current one
+    BadVersion,
+    /// The header size doesn't match an ELF64 header
+    BadEhsize,
+    /// The image has more program headers than the loader keeps
+    TooManySegments,
+    /// The image ends before the data its headers describe
+    Truncated,
+    /// The address or file range of a loadable segment overflows, or the
+    /// segment holds more bytes of the file than of memory
+    BadSegment,
+    /// A loaded byte, at this address, differs from the image once read
+    /// back
+    VerifyMismatch(usize),
+}
+
 /// Loads an ELF kernel image from memory
 ///
-/// Main entry point for loading a kernel. It parses the ELF file
-/// at the given base address and loads it into memory.
+/// Main entry point for loading a kernel. It runs a [`BootPlan`] loading the
+/// kernel staged at the given base address and stores its entry point in
+/// `entry`. The kernel is read back once loaded if the `verify` environment
+/// variable is `1`. Returns 0 on success, the staged image being
+/// [discarded](reserve::discard) as it's no longer needed. Otherwise the error is printed and its
+/// [`BootError::code`] returned, so the assembly caller can branch to
+/// [`boot_failed`](crate::error::boot_failed).
+///
+/// [`BootError::code`]: crate::error::BootError::code
 #[unsafe(no_mangle)]
-pub extern "C" fn load_kernel(elf_base: usize) -> usize {
-    return load_elf(elf_base);
+pub extern "C" fn load_kernel(elf_base: usize, entry: &mut usize) -> u32 {
+    exception::capture_context(b"load_kernel");
+    let mut staged = RamSource::new().with(Component::Kernel, elf_base, None);
+    let mut plan = BootPlan::new();
+
+    plan.set_verify(env::get(VERIFY_VAR) == Some(b"1"));
+
+    let loaded = plan
+        .add(Component::Kernel, &mut staged)
+        .and_then(|_| plan.execute());
+    let result = match loaded {
+        Ok(loaded) => {
+            // The plan's only step loads the kernel
+            *entry = loaded.kernel.map_or(0, |image| image.entry);
+            reserve::discard(elf_base, ReserveTag::Staging);
+            0
+        }
+        Err(e) => e.code(),
+    };
+
+    let kernel = (&b"component"[..], Value::Name(b"kernel"));
+    match result {
+        0 => log::event(
+            Level::Info,
+            b"load",
+            &[
+                kernel,
+                (b"result", Value::Name(b"ok")),
+                (b"entry", Value::Hex(*entry as u64)),
+            ],
+        ),
+        code => log::event(
+            Level::Error,
+            b"load",
+            &[
+                kernel,
+                (b"result", Value::Name(b"error")),
+                (b"code", Value::Hex(code as u64)),
+            ],
+        ),
+    }
+    log::stage(b"load_kernel");
+    return result;
 }
 
 /// Validates an ELF64 header
 ///
 /// Checks that the ELF header has the correct magic number,
-/// is a 64-bit little-endian executable for AArch64.
-fn check_elf_header(header: &Elf64Ehdr) -> bool {
+/// is a 64-bit little-endian executable for AArch64, and that its version
+/// and size are the ones of an ELF64 header, to catch corrupted images.
+fn check_elf_header(header: &Elf64Ehdr) -> Result<(), ElfError> {
     // Validate Magic
     if header.e_ident[0..4] != ELFMAG {
-        pl011::println(b"Not an ELF file!");
-        return false;
+        return Err(ElfError::NotElf);
     }
 
     // Validate Bitness
     if header.e_ident[EI_CLASS] != ELFCLASS64 as u8 {
-        pl011::println(b"Not an ELF file!");
-        return false;
+        return Err(ElfError::NotElf64);
     }
 
     // Validate Endianess
+    if header.e_ident[EI_DATA] == ELFDATA2MSB as u8 {
+        return Err(ElfError::BigEndianUnsupported);
+    }
     if header.e_ident[EI_DATA] != ELFDATA2LSB as u8 {
-        pl011::println(b"Invalid endianess!");
-        return false;
+        return Err(ElfError::BadEndianness);
     }
 
     // Validate Class
     if header.e_ident[EI_OSABI] != ELFOSABI_SYSV as u8 {
-        pl011::println(b"Invalid class!");
-        return false;
+        return Err(ElfError::BadOsAbi);
     }
 
     // Validate Type
     if header.e_type != ET_EXEC as u16 {
-        pl011::println(b"Invalid type!");
-        return false;
+        return Err(ElfError::BadType);
     }
 
     // Validate Machine
     if header.e_machine != EM_AARCH64 as u16 {
-        pl011::println(b"Invalid machine!");
-        return false;
+        return Err(ElfError::BadMachine);
+    }
+
+    // Validate Version
+    if header.e_version != EV_CURRENT as u32 {
+        return Err(ElfError::BadVersion);
+    }
+
+    // Validate Header Size
+    if header.e_ehsize as usize != mem::size_of::<Elf64Ehdr>() {
+        return Err(ElfError::BadEhsize);
+    }
+
+    return Ok(());
+}
+
+/// Returns `header` with its multibyte fields byte-swapped
+///
+/// Turns the header of a big-endian image, read as little-endian, into the
+/// values it encodes.
+#[cfg(feature = "elf-big-endian")]
+const fn byteswapped(header: &Elf64Ehdr) -> Elf64Ehdr {
+    return Elf64Ehdr {
+        e_ident: header.e_ident,
+        e_type: header.e_type.swap_bytes(),
+        e_machine: header.e_machine.swap_bytes(),
+        e_version: header.e_version.swap_bytes(),
+        e_entry: header.e_entry.swap_bytes(),
+        e_phoff: header.e_phoff.swap_bytes(),
+        e_shoff: header.e_shoff.swap_bytes(),
+        e_flags: header.e_flags.swap_bytes(),
+        e_ehsize: header.e_ehsize.swap_bytes(),
+        e_phentsize: header.e_phentsize.swap_bytes(),
+        e_phnum: header.e_phnum.swap_bytes(),
+        e_shentsize: header.e_shentsize.swap_bytes(),
+        e_shnum: header.e_shnum.swap_bytes(),
+        e_shstrndx: header.e_shstrndx.swap_bytes(),
+    };
+}
+
+// A big-endian AArch64 executable header reads back byte-swapped
+#[cfg(feature = "elf-big-endian")]
+const _: () = {
+    let mut raw = [0u8; 64];
+    let fields: [(usize, &[u8]); 8] = [
+        (0, &[0x7f, b'E', b'L', b'F', 2, 2, 1]),
+        (16, &[0x00, 0x02]),
+        (18, &[0x00, 0xb7]),
+        (20, &[0x00, 0x00, 0x00, 0x01]),
+        (24, &[0x00, 0x00, 0x00, 0x00, 0x40, 0x20, 0x00, 0x00]),
+        (32, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40]),
+        (52, &[0x00, 0x40, 0x00, 0x38]),
+        (56, &[0x00, 0x03]),
+    ];
+    let mut i = 0;
+    while i < fields.len() {
+        let (offset, bytes) = fields[i];
+        let mut j = 0;
+        while j < bytes.len() {
+            raw[offset + j] = bytes[j];
+            j += 1;
+        }
+        i += 1;
     }
 
-    return true;
+    let header: Elf64Ehdr = unsafe { mem::transmute(raw) };
+    let header = byteswapped(&header);
+    assert!(header.e_ident[EI_DATA] == ELFDATA2MSB as u8);
+    assert!(header.e_type == ET_EXEC as u16);
+    assert!(header.e_machine == EM_AARCH64 as u16);
+    assert!(header.e_version == EV_CURRENT as u32);
+    assert!(header.e_entry == 0x4020_0000);
+    assert!(header.e_phoff == 0x40);
+    assert!(header.e_ehsize == 64 && header.e_phentsize == 56);
+    assert!(header.e_phnum == 3);
+};
+
+/// Prints the fields of `header` that identify an image
+#[cfg(feature = "elf-big-endian")]
+fn dump_header(header: &Elf64Ehdr) {
+    let fields: [(&[u8], u64); 7] = [
+        (b"  type:    0x", header.e_type as u64),
+        (b"  machine: 0x", header.e_machine as u64),
+        (b"  version: 0x", header.e_version as u64),
+        (b"  entry:   0x", header.e_entry),
+        (b"  phoff:   0x", header.e_phoff),
+        (b"  phnum:   0x", header.e_phnum as u64),
+        (b"  ehsize:  0x", header.e_ehsize as u64),
+    ];
+
+    for (label, value) in fields {
+        pl011::print(label);
+        pl011::println(print::u64_to_hex(value, &mut [0u8; 16]));
+    }
 }
 
-/// Loads an ELF file into memory from the given base address
+/// Fills `buf` with the bytes at offset `off` of `src`, however many reads
+/// that takes
+fn read_exact(src: &dyn ImageSource, off: usize, buf: &mut [u8]) -> Result<(), ElfError> {
+    let mut done = 0;
+
+    while done < buf.len() {
+        let count = src.read_at(off + done, &mut buf[done..]);
+        if count == 0 {
+            return Err(ElfError::Truncated);
+        }
+        done += count;
+    }
+    return Ok(());
+}
+
+/// Reads the ELF header of the image in `src`
+///
+/// An image too short for a header isn't an ELF file.
+fn read_header(src: &dyn ImageSource) -> Result<Elf64Ehdr, ElfError> {
+    let mut raw = [0u8; mem::size_of::<Elf64Ehdr>()];
+
+    if read_exact(src, 0, &mut raw).is_err() {
+        return Err(ElfError::NotElf);
+    }
+    return Ok(unsafe { mem::transmute::<[u8; mem::size_of::<Elf64Ehdr>()], Elf64Ehdr>(raw) });
+}
+
+/// Program headers of an image, read once so the segments can then be read
+/// in file order
+struct ProgramHeaders {
+    /// Number of valid entries in `phdrs`
+    count: usize,
+    /// The program headers, in table order
+    phdrs: [Elf64Phdr; MAX_PHDRS],
+}
+
+impl ProgramHeaders {
+    /// Reads the program header table described by `header` from `src`
+    fn read(src: &dyn ImageSource, header: &Elf64Ehdr) -> Result<Self, ElfError> {
+        let count = header.e_phnum as usize;
+        let mut table = Self {
+            count,
+            phdrs: [Elf64Phdr::EMPTY; MAX_PHDRS],
+        };
+
+        if count > MAX_PHDRS {
+            return Err(ElfError::TooManySegments);
+        }
+        // A table past the end of the address space is past the end of any
+        // image
+        if (header.e_phoff as usize)
+            .checked_add(count * mem::size_of::<Elf64Phdr>())
+            .is_none()
+        {
+            return Err(ElfError::Truncated);
+        }
+        for (i, phdr) in table.phdrs[..count].iter_mut().enumerate() {
+            let mut raw = [0u8; mem::size_of::<Elf64Phdr>()];
+            read_exact(
+                src,
+                header.e_phoff as usize + i * mem::size_of::<Elf64Phdr>(),
+                &mut raw,
+            )?;
+            *phdr = unsafe { mem::transmute::<[u8; mem::size_of::<Elf64Phdr>()], Elf64Phdr>(raw) };
+        }
+        return Ok(table);
+    }
+
+    /// Returns an iterator over the PT_LOAD program headers
+    fn loadable(&self) -> impl Iterator<Item = &Elf64Phdr> {
+        return self.phdrs[..self.count]
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD as u32);
+    }
+}
+
+/// Validates the headers of the image in `src` and computes where it would
+/// be loaded
+///
+/// Returns the program headers along with what [`inspect_elf`] returns.
+fn inspect(src: &dyn ImageSource) -> Result<(ProgramHeaders, LoadedImage, usize), ElfError> {
+    let header = read_header(src)?;
+
+    check_elf_header(&header)?;
+
+    let phdrs = ProgramHeaders::read(src, &header)?;
+    let mut file_size =
+        header.e_phoff as usize + header.e_phnum as usize * mem::size_of::<Elf64Phdr>();
+    let mut image = LoadedImage {
+        start: usize::MAX,
+        end: 0,
+        entry: header.e_entry as usize,
+    };
+    for phdr in phdrs.loadable() {
+        let end = phdr.p_vaddr.checked_add(phdr.p_memsz);
+        let file_end = phdr.p_offset.checked_add(phdr.p_filesz);
+        let (Some(end), Some(file_end)) = (end, file_end) else {
+            return Err(ElfError::BadSegment);
+        };
+        // The copy of the file part isn't checked against the memory part
+        if phdr.p_filesz > phdr.p_memsz {
+            return Err(ElfError::BadSegment);
+        }
+        if map::touches_device(phdr.p_vaddr as usize, phdr.p_memsz as usize) {
+            return Err(ElfError::DeviceTarget);
+        }
+        if map::touches_reserved(phdr.p_vaddr as usize, phdr.p_memsz as usize) {
+            return Err(ElfError::ReservedTarget);
+        }
+        image.start = image.start.min(phdr.p_vaddr as usize);
+        image.end = image.end.max(end as usize);
+        file_size = file_size.max(file_end as usize);
+    }
+
+    // Refuse an image known to be cut short before writing anything
+    if file_size > src.len() {
+        return Err(ElfError::Truncated);
+    }
+
+    // No loadable segment: report an empty range at the entry point
+    if image.start > image.end {
+        image.start = image.entry;
+        image.end = image.entry;
+    }
+
+    return Ok((phdrs, image, file_size));
+}
+
+/// Computes where an ELF file would be loaded without loading it
+///
+/// Validates the header read from `src` and walks the program headers to
+/// compute the extents of the PT_LOAD segments and the entry point, plus the
+/// number of bytes of the file itself that loading reads. An image with a
+/// segment placed over device memory (see [`map::touches_device`]) or
+/// reserved memory (see [`map::touches_reserved`]) is refused, as is one
+/// whose segment bounds overflow ([`ElfError::BadSegment`]) or one shorter
+/// than that number of bytes when `src` knows its length.
+pub fn inspect_elf(src: &dyn ImageSource) -> Result<(LoadedImage, usize), ElfError> {
+    let (_, image, file_size) = inspect(src)?;
+
+    return Ok((image, file_size));
+}
+
+/// Loads an ELF file read from `src` into memory
 ///
 /// Performs the complete ELF loading process:
 /// 1. Validates the ELF header
-/// 2. Iterates through all program headers
-/// 3. Loads PT_LOAD segments to their target virtual addresses
-/// 4. Zeros out BSS sections (when p_memsz > p_filesz)
-fn load_elf(elf_base: usize) -> usize {
-    let phdr_base;
-    let header = unsafe { &*(elf_base as *const Elf64Ehdr) };
-
-    // Validate ELF
-    if !check_elf_header(header) {
-        pl011::println(b"Not an ELF file!");
-        panic!();
-    }
-
-    // Parse program headers
-    phdr_base = elf_base + header.e_phoff as usize;
-    for i in 0..header.e_phnum {
-        let phdr = unsafe {
-            &*((phdr_base + i as usize * mem::size_of::<Elf64Phdr>()) as *const Elf64Phdr)
-        };
+/// 2. Reads all program headers
+/// 3. Reads PT_LOAD segments straight to their target virtual addresses,
+///    calling `progress` after every chunk, or without one printing a
+///    heartbeat dot for every MiB copied
+/// 4. With `verify`, reads each segment from `src` again and compares it
+///    with the copy, to catch bad RAM or a faulty copy during bring-up
+/// 5. Zeros out BSS sections (when p_memsz > p_filesz)
+///
+/// Everything is read once, at increasing offsets for the usual images
+/// whose segments are in file order, which suits sources that decompress as
+/// they go; verifying reads each segment twice, which such a source pays
+/// for by starting over. Between two chunks of a copy or a check, loading
+/// stops if `deadline` has passed or `Ctrl-C` was typed, leaving the image
+/// partially copied; so does a source of unknown length ending early.
+///
+/// Returns the extents of the loaded segments and the entry point, or why the
+/// header was rejected or loading stopped.
+pub fn load_elf(
+    src: &dyn ImageSource,
+    deadline: Deadline,
+    progress: Option<Progress>,
+    verify: bool,
+) -> Result<LoadedImage, ElfError> {
+    let inspected = inspect(src);
+    #[cfg(feature = "elf-big-endian")]
+    if matches!(inspected, Err(ElfError::BigEndianUnsupported)) {
+        pl011::println(b"Big-endian ELF header:");
+        if let Ok(header) = read_header(src) {
+            dump_header(&byteswapped(&header));
+        }
+    }
+    let (phdrs, image, _) = inspected?;
+    let total = phdrs.loadable().fold(0usize, |total, phdr| {
+        total.saturating_add(phdr.p_filesz as usize)
+    });
+    let mut done = 0;
 
-        if phdr.p_type == PT_LOAD as u32 {
-            // PT_LOAD
-            // Copy segment from ELF to target address
-            let src = elf_base + phdr.p_offset as usize;
-            let dst = phdr.p_vaddr as usize;
-            let size = phdr.p_filesz as usize;
-            unsafe {
-                ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, size);
+    for phdr in phdrs.loadable() {
+        // Read the segment to its target address
+        let dst = phdr.p_vaddr as usize;
+        let size = phdr.p_filesz as usize;
+        let mut copied = 0;
+        while copied < size {
+            check_interrupted(deadline)?;
+            let len = COPY_CHUNK.min(size - copied);
+            let chunk = unsafe { slice::from_raw_parts_mut((dst + copied) as *mut u8, len) };
+            read_exact(src, phdr.p_offset as usize + copied, chunk)?;
+            copied += len;
+            done += len;
+            match progress {
+                Some(progress) => progress(done, total),
+                None => print::heartbeat(HEARTBEAT_STEP),
             }
+        }
+        if verify {
+            verify_segment(src, phdr.p_offset as usize, dst, size, deadline)?;
+        }
 
-            // Zero out BSS if memsz > filesz
-            if phdr.p_memsz > phdr.p_filesz {
-                let bss_start = dst + size;
-                let bss_size = (phdr.p_memsz - phdr.p_filesz) as usize;
-                unsafe {
-                    ptr::write_bytes(bss_start as *mut u8, 0, bss_size);
-                }
+        // Zero out BSS if memsz > filesz
+        if phdr.p_memsz > phdr.p_filesz {
+            let bss_start = dst + size;
+            let bss_size = (phdr.p_memsz - phdr.p_filesz) as usize;
+            unsafe {
+                ptr::write_bytes(bss_start as *mut u8, 0, bss_size);
             }
         }
     }
 
-    return header.e_entry as usize;
+    return Ok(image);
+}
+
+/// Fails if `deadline` has passed or `Ctrl-C` was typed
+fn check_interrupted(deadline: Deadline) -> Result<(), ElfError> {
+    if deadline.expired() {
+        return Err(ElfError::Timeout);
+    }
+    if pl011::ctrlc() {
+        return Err(ElfError::Aborted);
+    }
+    return Ok(());
+}
+
+/// Compares the `size` bytes loaded at `dst` with those at offset `off` of
+/// `src`, read again
+///
+/// Returns [`ElfError::VerifyMismatch`] with the address of the first byte
+/// that differs.
+fn verify_segment(
+    src: &dyn ImageSource,
+    off: usize,
+    dst: usize,
+    size: usize,
+    deadline: Deadline,
+) -> Result<(), ElfError> {
+    let mut buf = [0u8; VERIFY_CHUNK];
+    let mut checked = 0;
+
+    while checked < size {
+        check_interrupted(deadline)?;
+        let len = VERIFY_CHUNK.min(size - checked);
+        read_exact(src, off + checked, &mut buf[..len])?;
+        let loaded = unsafe { slice::from_raw_parts((dst + checked) as *const u8, len) };
+        if let Some(i) = loaded.iter().zip(&buf[..len]).position(|(a, b)| a != b) {
+            return Err(ElfError::VerifyMismatch(dst + checked + i));
+        }
+        checked += len;
+    }
+    return Ok(());
+}
+
+/// Offset of the self-test image's segments, past its two program headers
+const TEST_SEGMENT_OFFSET: usize = 192;
+/// Bytes of the self-test image's segments in the file
+const TEST_FILESZ: usize = 200;
+/// Bytes of the self-test image's segments in memory
+const TEST_MEMSZ: usize = 256;
+/// Bytes of the file in the first segment, the second one loading the rest
+/// right after it
+const TEST_SPLIT: usize = 120;
+/// Size of the self-test image
+const TEST_IMAGE_SIZE: usize = TEST_SEGMENT_OFFSET + TEST_FILESZ;
+
+/// Where the self-test image's segment is loaded
+static mut TEST_SEGMENT: [u8; TEST_MEMSZ] = [0; TEST_MEMSZ];
+
+/// Builds an image loaded at `vaddr`: a run of one byte, then counting
+/// bytes, then BSS, split over two adjacent segments
+fn test_image(vaddr: u64) -> [u8; TEST_IMAGE_SIZE] {
+    let mut image = [0u8; TEST_IMAGE_SIZE];
+    let fields: [(usize, &[u8]); 10] = [
+        (0, &ELFMAG),
+        (
+            EI_CLASS,
+            &[ELFCLASS64 as u8, ELFDATA2LSB as u8, EV_CURRENT as u8],
+        ),
+        (16, &(ET_EXEC as u16).to_le_bytes()),
+        (18, &(EM_AARCH64 as u16).to_le_bytes()),
+        (20, &(EV_CURRENT as u32).to_le_bytes()),
+        (24, &(vaddr + 4).to_le_bytes()),
+        (32, &(mem::size_of::<Elf64Ehdr>() as u64).to_le_bytes()),
+        (52, &(mem::size_of::<Elf64Ehdr>() as u16).to_le_bytes()),
+        (54, &(mem::size_of::<Elf64Phdr>() as u16).to_le_bytes()),
+        (56, &2u16.to_le_bytes()),
+    ];
+    for (offset, bytes) in fields {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    let segments = [
+        (0, TEST_SPLIT, TEST_SPLIT),
+        (
+            TEST_SPLIT,
+            TEST_FILESZ - TEST_SPLIT,
+            TEST_MEMSZ - TEST_SPLIT,
+        ),
+    ];
+    for (i, (start, filesz, memsz)) in segments.into_iter().enumerate() {
+        let phdr = Elf64Phdr {
+            p_type: PT_LOAD as u32,
+            p_offset: (TEST_SEGMENT_OFFSET + start) as u64,
+            p_vaddr: vaddr + start as u64,
+            p_paddr: vaddr + start as u64,
+            p_filesz: filesz as u64,
+            p_memsz: memsz as u64,
+            ..Elf64Phdr::EMPTY
+        };
+        let raw: [u8; mem::size_of::<Elf64Phdr>()] = unsafe { mem::transmute(phdr) };
+        let at = 64 + i * raw.len();
+        image[at..at + raw.len()].copy_from_slice(&raw);
+    }
+
+    for (i, b) in image[TEST_SEGMENT_OFFSET..].iter_mut().enumerate() {
+        *b = if i < TEST_FILESZ / 2 { 0xa5 } else { i as u8 };
+    }
+    return image;
+}
+
+/// Compresses `data` into `out` in the [`lz77`](crate::utilities::lz77)
+/// format, encoding runs of one byte as matches at distance 1, and returns
+/// the compressed size
+fn test_pack(data: &[u8], out: &mut [u8]) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(130)
+            .take_while(|&&b| i > 0 && b == data[i - 1])
+            .count();
+        if run >= 3 {
+            out[len..len + 3].copy_from_slice(&[0x80 | (run - 3) as u8, 1, 0]);
+            len += 3;
+            i += run;
+            continue;
+        }
+        out[len] = 0;
+        out[len + 1] = data[i];
+        len += 2;
+        i += 1;
+    }
+    return len;
+}
+
+/// Bytes a [`ChunkedSource`] returns per read at most
+///
+/// Not a divisor of the header sizes, so reads end mid-structure.
+const TEST_CHUNK: usize = 7;
+
+/// Self-test source returning at most [`TEST_CHUNK`] bytes per read, as a
+/// block device would return a block
+struct ChunkedSource<'a> {
+    /// The image
+    data: &'a [u8],
+}
+
+impl ImageSource for ChunkedSource<'_> {
+    fn read_at(&self, off: usize, buf: &mut [u8]) -> usize {
+        let count = buf
+            .len()
+            .min(TEST_CHUNK)
+            .min(self.data.len().saturating_sub(off));
+
+        buf[..count].copy_from_slice(&self.data[off..off + count]);
+        return count;
+    }
+
+    fn len(&self) -> usize {
+        return self.data.len();
+    }
+}
+
+/// Checks the self-test segment was loaded from [`test_image`], then fills
+/// it with garbage for the next load
+fn test_check_segment(image: &[u8]) -> bool {
+    let segment = &raw mut TEST_SEGMENT;
+    let segment = unsafe { &mut *segment };
+    let loaded = segment[..TEST_FILESZ] == image[TEST_SEGMENT_OFFSET..]
+        && segment[TEST_FILESZ..].iter().all(|&b| b == 0);
+
+    segment.fill(0xff);
+    return loaded;
+}
+
+/// Calls to [`test_progress`] since the last [`test_progress_ok`], as
+/// `(copied, total)`
+static mut TEST_PROGRESS: [(usize, usize); 4] = [(0, 0); 4];
+/// Number of calls to [`test_progress`], counting those past the array
+static mut TEST_PROGRESS_CALLS: usize = 0;
+
+/// Progress callback of the self-test, recording its calls
+fn test_progress(copied: usize, total: usize) {
+    let progress = &raw mut TEST_PROGRESS;
+
+    unsafe {
+        if let Some(call) = (*progress).get_mut(TEST_PROGRESS_CALLS) {
+            *call = (copied, total);
+        }
+        TEST_PROGRESS_CALLS += 1;
+    }
+}
+
+/// Checks that [`test_progress`] was called once per segment, with `copied`
+/// increasing up to the total of the file bytes of the segments, and forgets
+/// the calls
+fn test_progress_ok() -> bool {
+    let (calls, progress) = unsafe { (TEST_PROGRESS_CALLS, TEST_PROGRESS) };
+
+    unsafe {
+        TEST_PROGRESS_CALLS = 0;
+    }
+    return calls == 2 && progress[..2] == [(TEST_SPLIT, TEST_FILESZ), (TEST_FILESZ, TEST_FILESZ)];
+}
+
+/// Offset in the self-test segment of the byte [`test_corrupt`] flips, in
+/// the second segment
+const TEST_CORRUPT_OFFSET: usize = TEST_SPLIT + 9;
+
+/// Progress callback of the self-test acting as RAM that doesn't hold a
+/// write: flips a loaded byte once the second segment is copied
+fn test_corrupt(copied: usize, _total: usize) {
+    let segment = &raw mut TEST_SEGMENT;
+
+    if copied == TEST_FILESZ {
+        unsafe {
+            (*segment)[TEST_CORRUPT_OFFSET] ^= 0x10;
+        }
+    }
+}
+
+/// Self-test: an image is loaded and verified alike from memory, from a
+/// source returning short reads and from a compressed copy decompressed as
+/// it's read, segment data and BSS included, its progress reported after
+/// each segment; a byte altered in memory after the copy is reported at its
+/// address by verifying, and only then; the same image cut short is refused
+pub fn selftest() -> Outcome {
+    let vaddr = &raw const TEST_SEGMENT as u64;
+    let image = test_image(vaddr);
+    let mut packed = [0u8; 2 * TEST_IMAGE_SIZE];
+    let packed_len = test_pack(&image, &mut packed);
+    let base = vaddr as usize;
+    let expected = (base, base + TEST_MEMSZ, base + 4);
+
+    test_check_segment(&image);
+    let memory = unsafe { MemorySource::new(image.as_ptr() as usize, image.len()) };
+    let chunked = ChunkedSource { data: &image };
+    let compressed = Lz77Source::new(&packed[..packed_len]);
+    for src in [&memory as &dyn ImageSource, &chunked, &compressed] {
+        match load_elf(src, Deadline::NEVER, Some(test_progress), true) {
+            Ok(loaded) if (loaded.start, loaded.end, loaded.entry) == expected => {}
+            _ => return Outcome::Fail,
+        }
+        if !test_check_segment(&image) || !test_progress_ok() {
+            return Outcome::Fail;
+        }
+    }
+
+    let corrupted = base + TEST_CORRUPT_OFFSET;
+    if load_elf(&memory, Deadline::NEVER, Some(test_corrupt), true).err()
+        != Some(ElfError::VerifyMismatch(corrupted))
+    {
+        return Outcome::Fail;
+    }
+    test_check_segment(&image);
+    if load_elf(&memory, Deadline::NEVER, Some(test_corrupt), false).is_err() {
+        return Outcome::Fail;
+    }
+    test_check_segment(&image);
+
+    // Known to be short, refused before loading; found short while loading
+    let cut = unsafe { MemorySource::new(image.as_ptr() as usize, image.len() - 1) };
+    let cut_compressed = Lz77Source::new(&packed[..packed_len - 2]);
+    for src in [&cut as &dyn ImageSource, &cut_compressed] {
+        if !matches!(
+            load_elf(src, Deadline::NEVER, None, false),
+            Err(ElfError::Truncated)
+        ) {
+            return Outcome::Fail;
+        }
+    }
+
+    // Reading back the header starts decompressing again
+    let mut magic = [0u8; SELFMAG];
+    compressed.read_at(0, &mut magic);
+    if magic != ELFMAG {
+        return Outcome::Fail;
+    }
+
+    return Outcome::Pass;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::offset_of;
+
+    /// Where the host tests' image is loaded
+    const VADDR: u64 = 0x4008_0000;
+
+    /// Writes `value` over the u64 field at `field` of program header `i`
+    fn set_phdr(image: &mut [u8], i: usize, field: usize, value: u64) {
+        let at = 64 + i * mem::size_of::<Elf64Phdr>() + field;
+        image[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Inspects `image`, read a few bytes at a time
+    fn inspect_image(image: &[u8]) -> Result<(LoadedImage, usize), ElfError> {
+        return inspect_elf(&ChunkedSource { data: image });
+    }
+
+    #[test]
+    fn inspect_valid_image() {
+        let (image, file_size) = inspect_image(&test_image(VADDR)).unwrap();
+        assert_eq!(image.start, VADDR as usize);
+        assert_eq!(image.end, VADDR as usize + TEST_MEMSZ);
+        assert_eq!(file_size, TEST_IMAGE_SIZE);
+    }
+
+    #[test]
+    fn segment_address_overflow() {
+        let mut image = test_image(VADDR);
+        set_phdr(&mut image, 0, offset_of!(Elf64Phdr, p_vaddr), u64::MAX - 8);
+        assert_eq!(inspect_image(&image).map(|_| ()), Err(ElfError::BadSegment));
+    }
+
+    #[test]
+    fn segment_offset_overflow() {
+        let mut image = test_image(VADDR);
+        set_phdr(&mut image, 1, offset_of!(Elf64Phdr, p_offset), u64::MAX - 8);
+        assert_eq!(inspect_image(&image).map(|_| ()), Err(ElfError::BadSegment));
+    }
+
+    #[test]
+    fn segment_file_larger_than_memory() {
+        let mut image = test_image(VADDR);
+        let memsz = (TEST_MEMSZ - TEST_SPLIT) as u64;
+        set_phdr(&mut image, 1, offset_of!(Elf64Phdr, p_filesz), memsz + 1);
+        assert_eq!(inspect_image(&image).map(|_| ()), Err(ElfError::BadSegment));
+    }
+
+    #[test]
+    fn program_headers_overflow() {
+        let mut image = test_image(VADDR);
+        image[32..40].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        assert_eq!(inspect_image(&image).map(|_| ()), Err(ElfError::Truncated));
+    }
 }
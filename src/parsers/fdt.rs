@@ -0,0 +1,1911 @@
+//! Flattened Device Tree (FDT) parser
+//!
+//! This module provides functionality to validate and walk a Flattened Device
+//! Tree blob (DTB) as passed by firmware in `x0`. All multi-byte values in a
+//! DTB are big-endian; every access is bounds-checked against the blob so a
+//! malformed tree yields an error instead of an out-of-range read.
+//!
+//! The blob comes from the firmware and isn't trusted. [`Fdt::new`] checks
+//! that the blocks the header points at lie within `totalsize`, past the
+//! header and apart from each other, that the memory reservation map is
+//! terminated, and walks the whole structure block once: every token must
+//! decode within the block, every name must be NUL-terminated within its
+//! block, and the nodes must nest into a single root. Lookups then work on
+//! a tree known to be well-formed, and still check each access.
+//!
+//! The structure block is exposed as a stream of [`Token`]s through
+//! [`Fdt::tokens`], which is enough to implement lookups on top of it.
+//! Properties are read by node path with [`Fdt::get_prop`], or already
+//! decoded with [`Fdt::get_u32`], [`Fdt::get_u64`] and [`Fdt::get_str`].
+//!
+//! Edits are limited to what the bootloader hands over to the payload:
+//! [`set_chosen_property`] sets a property of `/chosen` in place, using the
+//! free room that follows a relocated blob, and [`set_chosen_u64`] and
+//! [`set_chosen_str`] do the same for a blob known by its address.
+//!
+//! The bootloader may also be started without a DTB, with anything in
+//! `x0`: [`probe_blob`] looks for one there without faulting, and
+//! [`synthesize`] builds one from the board configuration to use instead.
+
+use crate::board::{self, BoardConfig, GicKind, UartKind};
+use crate::cpu;
+use crate::drivers::uart::pl011;
+use crate::exception::Conduit;
+use crate::selftest::Outcome;
+use crate::utilities::mmio;
+use crate::utilities::print::{u64_to_dec, u64_to_hex, u64_to_hex_padded};
+
+use core::slice;
+
+/// FDT magic number
+pub const FDT_MAGIC: u32 = 0xd00dfeed;
+/// Size of the FDT header in bytes
+pub const FDT_HEADER_SIZE: usize = 40;
+/// Oldest FDT version this parser understands
+const FDT_LAST_COMP_VERSION: u32 = 16;
+/// Oldest FDT version with all the header fields read by the parser
+const FDT_MIN_VERSION: u32 = 17;
+/// Largest blob accepted, as by Linux on arm64
+pub const FDT_MAX_SIZE: usize = 2 << 20;
+/// Size of a memory reservation map entry: address and size
+const FDT_RSV_ENTRY_SIZE: usize = 16;
+
+/// Start of a node, followed by its NUL-terminated name
+const FDT_BEGIN_NODE: u32 = 0x1;
+/// End of a node
+const FDT_END_NODE: u32 = 0x2;
+/// Property, followed by its length, name offset and value
+const FDT_PROP: u32 = 0x3;
+/// No-op token
+const FDT_NOP: u32 = 0x4;
+/// End of the structure block
+const FDT_END: u32 = 0x9;
+
+/// Default `#address-cells` when a node doesn't specify it
+pub const DEFAULT_ADDRESS_CELLS: u32 = 2;
+/// Default `#size-cells` when a node doesn't specify it
+pub const DEFAULT_SIZE_CELLS: u32 = 1;
+/// Maximum node depth the lookup helpers descend to
+pub const MAX_DEPTH: usize = 16;
+
+/// Errors reported while parsing an FDT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FdtError {
+    /// The blob doesn't start with [`FDT_MAGIC`]
+    BadMagic,
+    /// The blob uses a version this parser doesn't understand
+    BadVersion,
+    /// The blob or one of its blocks is shorter than advertised
+    Truncated,
+    /// The structure block contains an invalid token or layout
+    BadStructure,
+    /// The header places a block outside the blob, over the header or over
+    /// another block, or misaligned, or the blob is larger than
+    /// [`FDT_MAX_SIZE`]; or the blocks of a blob to edit aren't in the order
+    /// `dtc` writes them
+    BadLayout,
+    /// The node to edit doesn't exist
+    NoNode,
+    /// The edit doesn't fit in the room after the blob
+    NoSpace,
+    /// Nothing answers at the address of the blob
+    Unreadable,
+}
+
+/// FDT header
+///
+/// All fields are converted to native endianness.
+#[derive(Clone, Copy, Debug)]
+pub struct FdtHeader {
+    /// Magic number, always [`FDT_MAGIC`]
+    pub magic: u32,
+    /// Total size of the blob in bytes
+    pub totalsize: u32,
+    /// Offset of the structure block
+    pub off_dt_struct: u32,
+    /// Offset of the strings block
+    pub off_dt_strings: u32,
+    /// Offset of the memory reservation block
+    pub off_mem_rsvmap: u32,
+    /// Version of the blob format
+    pub version: u32,
+    /// Oldest version the blob is backwards compatible with
+    pub last_comp_version: u32,
+    /// Physical ID of the boot CPU
+    pub boot_cpuid_phys: u32,
+    /// Size of the strings block
+    pub size_dt_strings: u32,
+    /// Size of the structure block
+    pub size_dt_struct: u32,
+}
+
+/// Reads a big-endian u32 at `off`, if it's within `data`
+pub fn be32(data: &[u8], off: usize) -> Option<u32> {
+    let bytes = data.get(off..off.checked_add(4)?)?;
+
+    return Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+}
+
+/// Reads a big-endian u64 at `off`, if it's within `data`
+pub fn be64(data: &[u8], off: usize) -> Option<u64> {
+    let hi = be32(data, off)? as u64;
+    let lo = be32(data, off.checked_add(4)?)? as u64;
+
+    return Some((hi << 32) | lo);
+}
+
+/// Reads a `cells`-wide big-endian number (1 or 2 cells) at `off`
+pub fn read_cells(data: &[u8], off: usize, cells: u32) -> Option<u64> {
+    match cells {
+        1 => return be32(data, off).map(|v| v as u64),
+        2 => return be64(data, off),
+        _ => return None,
+    }
+}
+
+/// Returns the NUL-terminated string starting at `off`, without the NUL
+fn cstr(data: &[u8], off: usize) -> Option<&[u8]> {
+    let rest = data.get(off..)?;
+    let len = rest.iter().position(|&c| c == 0)?;
+
+    return Some(&rest[..len]);
+}
+
+/// Parses and validates the header at the start of `blob`
+///
+/// Checks the magic and version, that the blob is at least `totalsize` bytes
+/// long, that the structure and strings blocks and the memory reservation
+/// map lie within `totalsize` without overlapping the header or each other,
+/// and that the structure block and the map are aligned.
+pub fn parse_header(blob: &[u8]) -> Result<FdtHeader, FdtError> {
+    let field = |idx: usize| be32(blob, idx * 4).ok_or(FdtError::Truncated);
+    let header = FdtHeader {
+        magic: field(0)?,
+        totalsize: field(1)?,
+        off_dt_struct: field(2)?,
+        off_dt_strings: field(3)?,
+        off_mem_rsvmap: field(4)?,
+        version: field(5)?,
+        last_comp_version: field(6)?,
+        boot_cpuid_phys: field(7)?,
+        size_dt_strings: field(8)?,
+        size_dt_struct: field(9)?,
+    };
+
+    if header.magic != FDT_MAGIC {
+        return Err(FdtError::BadMagic);
+    }
+    if header.version < FDT_MIN_VERSION || header.last_comp_version > FDT_LAST_COMP_VERSION {
+        return Err(FdtError::BadVersion);
+    }
+
+    let total = header.totalsize as usize;
+    if total > FDT_MAX_SIZE {
+        return Err(FdtError::BadLayout);
+    }
+    if total < FDT_HEADER_SIZE || blob.len() < total {
+        return Err(FdtError::Truncated);
+    }
+
+    let structs = block(total, header.off_dt_struct, header.size_dt_struct)?;
+    let strings = block(total, header.off_dt_strings, header.size_dt_strings)?;
+    let rsvmap = rsvmap(&blob[..total], header.off_mem_rsvmap)?;
+    if !structs.0.is_multiple_of(4)
+        || !structs.1.is_multiple_of(4)
+        || !rsvmap.0.is_multiple_of(8)
+        || overlap(structs, strings)
+        || overlap(structs, rsvmap)
+        || overlap(strings, rsvmap)
+    {
+        return Err(FdtError::BadLayout);
+    }
+
+    return Ok(header);
+}
+
+/// Returns the start and end of the block of `size` bytes at `off`, if it
+/// lies between the header and `total`
+fn block(total: usize, off: u32, size: u32) -> Result<(usize, usize), FdtError> {
+    let (start, end) = (off as usize, off as usize + size as usize);
+
+    if start < FDT_HEADER_SIZE || end > total {
+        return Err(FdtError::BadLayout);
+    }
+    return Ok((start, end));
+}
+
+/// Returns the start and end of the memory reservation map at `off` of
+/// `blob`, its terminating entry included
+fn rsvmap(blob: &[u8], off: u32) -> Result<(usize, usize), FdtError> {
+    let start = off as usize;
+    let mut end = start;
+
+    if start < FDT_HEADER_SIZE {
+        return Err(FdtError::BadLayout);
+    }
+    loop {
+        let address = be64(blob, end).ok_or(FdtError::BadLayout)?;
+        let size = be64(blob, end + 8).ok_or(FdtError::BadLayout)?;
+        end += FDT_RSV_ENTRY_SIZE;
+        if address == 0 && size == 0 {
+            return Ok((start, end));
+        }
+    }
+}
+
+/// Checks whether the ranges `a` and `b` overlap
+fn overlap(a: (usize, usize), b: (usize, usize)) -> bool {
+    return a.0 < b.1 && b.0 < a.1;
+}
+
+/// Returns the DTB at `addr` as a slice covering its `totalsize`
+///
+/// Only the header is read before the length is known, which must be at
+/// most [`FDT_MAX_SIZE`]; the rest of the blob is validated by
+/// [`parse_header`].
+///
+/// # Safety
+///
+/// The caller must ensure `addr` points to readable memory at least
+/// [`FDT_HEADER_SIZE`] bytes long and, if the magic matches, `totalsize` bytes
+/// long.
+pub unsafe fn blob_at(addr: usize) -> Result<&'static [u8], FdtError> {
+    if addr == 0 || !addr.is_multiple_of(4) {
+        return Err(FdtError::BadMagic);
+    }
+
+    unsafe {
+        let head = slice::from_raw_parts(addr as *const u8, FDT_HEADER_SIZE);
+        if be32(head, 0) != Some(FDT_MAGIC) {
+            return Err(FdtError::BadMagic);
+        }
+        let total = be32(head, 4).ok_or(FdtError::Truncated)? as usize;
+        if total < FDT_HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+        if total > FDT_MAX_SIZE {
+            return Err(FdtError::BadLayout);
+        }
+        return Ok(slice::from_raw_parts(addr as *const u8, total));
+    }
+}
+
+/// Returns the DTB at `addr` like [`blob_at`], reading its ends with
+/// [`mmio::probe_read32`] first
+///
+/// Meant for the address passed by the firmware, which may be anything
+/// when the bootloader is started without a DTB: an address nothing answers
+/// at is reported as [`FdtError::Unreadable`] instead of faulting. The magic
+/// and `totalsize` are probed, then the last word of the blob.
+///
+/// # Safety
+///
+/// Memory whose first and last words can be read must be readable in
+/// between, as RAM and flash are.
+pub unsafe fn probe_blob(addr: usize) -> Result<&'static [u8], FdtError> {
+    let word = |off: usize| mmio::probe_read32(addr, off).map_err(|_| FdtError::Unreadable);
+
+    if addr == 0 || !addr.is_multiple_of(4) {
+        return Err(FdtError::BadMagic);
+    }
+    if u32::from_be(word(0)?) != FDT_MAGIC {
+        return Err(FdtError::BadMagic);
+    }
+    let total = u32::from_be(word(4)?) as usize;
+    if (FDT_HEADER_SIZE..=FDT_MAX_SIZE).contains(&total) {
+        word((total - 4) & !3)?;
+    }
+    return unsafe { blob_at(addr) };
+}
+
+/// A validated device tree blob
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    /// The whole blob, `totalsize` bytes long
+    blob: &'a [u8],
+    /// The parsed header
+    header: FdtHeader,
+}
+
+/// A token of the FDT structure block
+#[derive(Clone, Copy, Debug)]
+pub enum Token<'a> {
+    /// Start of a node with the given name (unit address included)
+    BeginNode(&'a [u8]),
+    /// End of the most recently started node
+    EndNode,
+    /// A property of the current node
+    Prop {
+        /// Property name, from the strings block
+        name: &'a [u8],
+        /// Raw property value
+        value: &'a [u8],
+    },
+}
+
+impl<'a> Fdt<'a> {
+    /// Validates `blob` and wraps it
+    ///
+    /// Nothing past the end of `blob` is read, whatever the header says.
+    /// Besides the header (see [`parse_header`]), the structure block is
+    /// walked to the end: it must hold a single root node, with every node
+    /// ended before the END token.
+    pub fn new(blob: &'a [u8]) -> Result<Self, FdtError> {
+        let header = parse_header(blob)?;
+        let fdt = Fdt {
+            blob: &blob[..header.totalsize as usize],
+            header,
+        };
+
+        fdt.check_structure()?;
+        return Ok(fdt);
+    }
+
+    /// Checks that every token of the structure block decodes, and that
+    /// they nest into a single, unnamed root node
+    fn check_structure(&self) -> Result<(), FdtError> {
+        let mut depth = 0usize;
+        let mut roots = 0;
+
+        for token in self.tokens() {
+            match token? {
+                Token::BeginNode(name) => {
+                    if depth == 0 {
+                        roots += 1;
+                        if roots > 1 || !name.is_empty() {
+                            return Err(FdtError::BadStructure);
+                        }
+                    }
+                    depth += 1;
+                }
+                Token::EndNode => {
+                    depth = depth.checked_sub(1).ok_or(FdtError::BadStructure)?;
+                }
+                Token::Prop { .. } if depth == 0 => return Err(FdtError::BadStructure),
+                Token::Prop { .. } => {}
+            }
+        }
+        if depth != 0 || roots != 1 {
+            return Err(FdtError::BadStructure);
+        }
+        return Ok(());
+    }
+
+    /// Returns the parsed header
+    pub fn header(&self) -> &FdtHeader {
+        return &self.header;
+    }
+
+    /// Returns the raw blob, `totalsize` bytes long
+    pub fn as_bytes(&self) -> &'a [u8] {
+        return self.blob;
+    }
+
+    /// Returns an iterator over the tokens of the structure block
+    ///
+    /// NOP tokens are skipped. The iterator stops at the END token; a
+    /// malformed block yields a single `Err` and then stops.
+    pub fn tokens(&self) -> TokenIter<'a> {
+        let start = self.header.off_dt_struct as usize;
+        let end = start + self.header.size_dt_struct as usize;
+        let strings_start = self.header.off_dt_strings as usize;
+        let strings_end = strings_start + self.header.size_dt_strings as usize;
+
+        return TokenIter {
+            structs: &self.blob[start..end],
+            strings: &self.blob[strings_start..strings_end],
+            off: 0,
+            done: false,
+        };
+    }
+
+    /// Returns the first node, in structure block order, for which `f` is true
+    ///
+    /// Nodes nested deeper than [`MAX_DEPTH`] are skipped. Each node gets
+    /// the translation of its `reg` addresses through the `ranges` of its
+    /// ancestors, see [`Node::translation`].
+    pub fn find_node<F: FnMut(&Node<'a>) -> bool>(
+        &self,
+        mut f: F,
+    ) -> Result<Option<Node<'a>>, FdtError> {
+        // Cells declared by the node at each depth, for its children
+        let mut cells = [(DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS); MAX_DEPTH + 1];
+        // `ranges` of the node at each depth
+        let mut ranges: [Option<&[u8]>; MAX_DEPTH + 1] = [None; MAX_DEPTH + 1];
+        // Translation of the `reg` addresses of the node at each depth
+        let mut translation = [0u64; MAX_DEPTH + 1];
+        let mut depth = 0usize;
+        let mut tokens = self.tokens();
+
+        while let Some(token) = tokens.next() {
+            match token? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+                    if depth > MAX_DEPTH {
+                        continue;
+                    }
+                    cells[depth] = (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS);
+                    ranges[depth] = None;
+                    translation[depth] = translation[depth - 1];
+                    // The parent's properties all came before this node
+                    if depth >= 2
+                        && let Some(offset) =
+                            range_offset(ranges[depth - 1], cells[depth - 1].0, cells[depth - 2].0)
+                    {
+                        translation[depth] = translation[depth].wrapping_add(offset);
+                    }
+                    let node = Node {
+                        tokens: tokens.clone(),
+                        name,
+                        depth,
+                        address_cells: cells[depth - 1].0,
+                        size_cells: cells[depth - 1].1,
+                        translation: translation[depth],
+                    };
+                    if f(&node) {
+                        return Ok(Some(node));
+                    }
+                }
+                Token::EndNode => {
+                    depth = depth.checked_sub(1).ok_or(FdtError::BadStructure)?;
+                }
+                Token::Prop { name, value } if depth <= MAX_DEPTH => {
+                    if name == b"#address-cells" {
+                        cells[depth].0 = be32(value, 0).ok_or(FdtError::BadStructure)?;
+                    } else if name == b"#size-cells" {
+                        cells[depth].1 = be32(value, 0).ok_or(FdtError::BadStructure)?;
+                    } else if name == b"ranges" {
+                        ranges[depth] = Some(value);
+                    }
+                }
+                Token::Prop { .. } => {}
+            }
+        }
+
+        return Ok(None);
+    }
+
+    /// Returns the first node whose `compatible` list contains `compatible`
+    pub fn find_compatible(&self, compatible: &[u8]) -> Result<Option<Node<'a>>, FdtError> {
+        return self.find_node(|node| node.is_compatible(compatible));
+    }
+
+    /// Returns the node whose `phandle` property is `phandle`
+    pub fn find_phandle(&self, phandle: u32) -> Result<Option<Node<'a>>, FdtError> {
+        return self
+            .find_node(|node| node.property(b"phandle").and_then(|v| be32(v, 0)) == Some(phandle));
+    }
+
+    /// Returns the node at `path`, e.g. `/psci` or `/soc/serial@7e201000`
+    ///
+    /// `/` is the root node. A component without a unit address matches the
+    /// first node of that name whatever its unit address.
+    pub fn find_path(&self, path: &[u8]) -> Result<Option<Node<'a>>, FdtError> {
+        let components = || path.split(|&c| c == b'/').filter(|c| !c.is_empty());
+        let count = components().count();
+        // Number of leading components matched by the ancestors of the node
+        let mut matched = 0;
+
+        return self.find_node(|node| {
+            // The root node is at depth 1, its children match component 0
+            let Some(index) = node.depth.checked_sub(2) else {
+                return count == 0;
+            };
+            matched = matched.min(index);
+            if matched == index
+                && components()
+                    .nth(index)
+                    .is_some_and(|component| matches_component(node.name, component))
+            {
+                matched += 1;
+                return matched == count;
+            }
+            return false;
+        });
+    }
+
+    /// Returns the value of the property `name` of the node at `path`
+    ///
+    /// `None` if the node or the property doesn't exist, or the blob is
+    /// malformed. See [`find_path`](Self::find_path) for `path`.
+    pub fn get_prop(&self, path: &[u8], name: &[u8]) -> Option<&'a [u8]> {
+        return self.find_path(path).ok()??.property(name);
+    }
+
+    /// Returns the property `name` of the node at `path` as a u32, like
+    /// [`Node::property_u32`]
+    pub fn get_u32(&self, path: &[u8], name: &[u8]) -> Option<u32> {
+        return self.find_path(path).ok()??.property_u32(name);
+    }
+
+    /// Returns the property `name` of the node at `path` as a u64, like
+    /// [`Node::property_u64`]
+    pub fn get_u64(&self, path: &[u8], name: &[u8]) -> Option<u64> {
+        return self.find_path(path).ok()??.property_u64(name);
+    }
+
+    /// Returns the property `name` of the node at `path` as a string, like
+    /// [`Node::property_str`]
+    pub fn get_str(&self, path: &[u8], name: &[u8]) -> Option<&'a [u8]> {
+        return self.find_path(path).ok()??.property_str(name);
+    }
+
+    /// Returns the `/chosen` node
+    pub fn chosen(&self) -> Result<Option<Node<'a>>, FdtError> {
+        return self.find_node(|node| node.depth == 2 && node.name == b"chosen");
+    }
+
+    /// Calls `f` with every `(base, size)` pair of the `/memory` nodes' `reg`
+    ///
+    /// Matches top-level nodes named `memory` or `memory@<unit>`, decoding
+    /// `reg` with the root node's `#address-cells`/`#size-cells`.
+    pub fn for_each_memory_range<F: FnMut(u64, u64)>(&self, mut f: F) -> Result<(), FdtError> {
+        let mut depth = 0usize;
+        let mut in_memory = false;
+        let mut address_cells = DEFAULT_ADDRESS_CELLS;
+        let mut size_cells = DEFAULT_SIZE_CELLS;
+
+        for token in self.tokens() {
+            match token? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+                    in_memory = depth == 2 && (name == b"memory" || name.starts_with(b"memory@"));
+                }
+                Token::EndNode => {
+                    depth = depth.checked_sub(1).ok_or(FdtError::BadStructure)?;
+                    in_memory = false;
+                }
+                Token::Prop { name, value } => {
+                    if depth == 1 && name == b"#address-cells" {
+                        address_cells = be32(value, 0).ok_or(FdtError::BadStructure)?;
+                    } else if depth == 1 && name == b"#size-cells" {
+                        size_cells = be32(value, 0).ok_or(FdtError::BadStructure)?;
+                    } else if in_memory && name == b"reg" {
+                        for_each_reg(value, address_cells, size_cells, &mut f)?;
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Calls `f` with every `(base, size)` pair the blob reserves: the
+    /// entries of the memory reservation block, then the `reg` of every
+    /// child of `/reserved-memory`
+    ///
+    /// The children's `reg` is decoded with the `#address-cells` and
+    /// `#size-cells` of `/reserved-memory`. A child without `reg` asks the
+    /// bootloader for dynamically placed memory, which isn't supported;
+    /// nothing is reported for it.
+    pub fn for_each_reserved_range<F: FnMut(u64, u64)>(&self, mut f: F) -> Result<(), FdtError> {
+        // The map was checked to be terminated by parse_header
+        let mut off = self.header.off_mem_rsvmap as usize;
+        loop {
+            let address = be64(self.blob, off).ok_or(FdtError::BadLayout)?;
+            let size = be64(self.blob, off + 8).ok_or(FdtError::BadLayout)?;
+            if address == 0 && size == 0 {
+                break;
+            }
+            f(address, size);
+            off += FDT_RSV_ENTRY_SIZE;
+        }
+
+        let mut depth = 0usize;
+        let mut in_reserved = false;
+        let mut address_cells = DEFAULT_ADDRESS_CELLS;
+        let mut size_cells = DEFAULT_SIZE_CELLS;
+
+        for token in self.tokens() {
+            match token? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+                    if depth == 2 {
+                        in_reserved = name == b"reserved-memory";
+                    }
+                }
+                Token::EndNode => {
+                    depth = depth.checked_sub(1).ok_or(FdtError::BadStructure)?;
+                    if depth < 2 {
+                        in_reserved = false;
+                    }
+                }
+                Token::Prop { name, value } => {
+                    let cells = depth == 1 || (in_reserved && depth == 2);
+                    if cells && name == b"#address-cells" {
+                        address_cells = be32(value, 0).ok_or(FdtError::BadStructure)?;
+                    } else if cells && name == b"#size-cells" {
+                        size_cells = be32(value, 0).ok_or(FdtError::BadStructure)?;
+                    } else if in_reserved && depth == 3 && name == b"reg" {
+                        for_each_reg(value, address_cells, size_cells, &mut f)?;
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// A node of the structure block, as found by [`Fdt::find_node`]
+#[derive(Clone)]
+pub struct Node<'a> {
+    /// Tokens following the node's BEGIN_NODE token
+    tokens: TokenIter<'a>,
+    /// Node name (unit address included)
+    pub name: &'a [u8],
+    /// Depth of the node, the root node being at depth 1
+    pub depth: usize,
+    /// Parent's `#address-cells`, used to decode `reg`
+    pub address_cells: u32,
+    /// Parent's `#size-cells`, used to decode `reg`
+    pub size_cells: u32,
+    /// Offset added to the addresses of `reg` to get CPU addresses, from
+    /// the `ranges` of the node's ancestors; 0 for nodes on the root's bus
+    pub translation: u64,
+}
+
+impl<'a> Node<'a> {
+    /// Returns the value of the property called `name`, if the node has it
+    pub fn property(&self, name: &[u8]) -> Option<&'a [u8]> {
+        // Properties always come before subnodes
+        for token in self.tokens.clone() {
+            match token {
+                Ok(Token::Prop { name: n, value }) if n == name => return Some(value),
+                Ok(Token::Prop { .. }) => continue,
+                _ => return None,
+            }
+        }
+        return None;
+    }
+
+    /// Returns the `(base, size)` pair at `index` of the `reg` property,
+    /// the base translated to a CPU address
+    pub fn reg(&self, index: usize) -> Option<(u64, u64)> {
+        let value = self.property(b"reg")?;
+        let entry_size = (self.address_cells as usize + self.size_cells as usize) * 4;
+        let off = index.checked_mul(entry_size)?;
+
+        let base = read_cells(value, off, self.address_cells)?;
+        let size = read_cells(
+            value,
+            off.checked_add(self.address_cells as usize * 4)?,
+            self.size_cells,
+        )?;
+        return Some((base.wrapping_add(self.translation), size));
+    }
+
+    /// Checks whether the `compatible` string list contains `compatible`
+    pub fn is_compatible(&self, compatible: &[u8]) -> bool {
+        return self
+            .property(b"compatible")
+            .is_some_and(|list| list.split(|&c| c == 0).any(|s| s == compatible));
+    }
+
+    /// Returns the property called `name` as a u32, if it's one cell long
+    pub fn property_u32(&self, name: &[u8]) -> Option<u32> {
+        return self
+            .property(name)
+            .filter(|v| v.len() == 4)
+            .and_then(|v| be32(v, 0));
+    }
+
+    /// Returns the property called `name` as a u64, if it's one or two
+    /// cells long
+    ///
+    /// Properties like `clock-frequency` are either, depending on the value.
+    pub fn property_u64(&self, name: &[u8]) -> Option<u64> {
+        let value = self.property(name)?;
+
+        return read_cells(value, 0, value.len() as u32 / 4).filter(|_| value.len() % 4 == 0);
+    }
+
+    /// Returns the property called `name` as a string, without its NUL
+    ///
+    /// The value must be NUL-terminated; for a string list, the first string
+    /// is returned.
+    pub fn property_str(&self, name: &[u8]) -> Option<&'a [u8]> {
+        let value = self.property(name)?;
+
+        if value.last() != Some(&0) {
+            return None;
+        }
+        return value.split(|&c| c == 0).next();
+    }
+}
+
+/// Checks whether the node called `name` matches the path component
+/// `component`
+///
+/// A component without a unit address matches any unit address, e.g.
+/// `memory` matches `memory@40000000`.
+fn matches_component(name: &[u8], component: &[u8]) -> bool {
+    if name == component {
+        return true;
+    }
+    return !component.contains(&b'@') && name.split(|&c| c == b'@').next() == Some(component);
+}
+
+/// Returns the offset from child to parent addresses of a bus node's
+/// `ranges`, decoded with the bus's and its parent's `#address-cells`
+///
+/// Only the first range is used, which is enough for the simple buses of
+/// virtual machines such as QEMU's platform bus. `None` if the bus has no
+/// `ranges`, an empty one (an identity mapping) or one that can't be
+/// decoded.
+fn range_offset(
+    ranges: Option<&[u8]>,
+    address_cells: u32,
+    parent_address_cells: u32,
+) -> Option<u64> {
+    let ranges = ranges?;
+    let child = read_cells(ranges, 0, address_cells)?;
+    let parent = read_cells(ranges, address_cells as usize * 4, parent_address_cells)?;
+
+    return Some(parent.wrapping_sub(child));
+}
+
+/// Calls `f` with every `(base, size)` pair of a `reg` property value
+pub fn for_each_reg<F: FnMut(u64, u64)>(
+    value: &[u8],
+    address_cells: u32,
+    size_cells: u32,
+    f: &mut F,
+) -> Result<(), FdtError> {
+    let entry_size = (address_cells as usize + size_cells as usize) * 4;
+    if entry_size == 0 || !value.len().is_multiple_of(entry_size) {
+        return Err(FdtError::BadStructure);
+    }
+
+    for entry in value.chunks_exact(entry_size) {
+        let base = read_cells(entry, 0, address_cells).ok_or(FdtError::BadStructure)?;
+        let size = read_cells(entry, address_cells as usize * 4, size_cells)
+            .ok_or(FdtError::BadStructure)?;
+        f(base, size);
+    }
+
+    return Ok(());
+}
+
+/// Iterator over the tokens of an FDT structure block
+#[derive(Clone)]
+pub struct TokenIter<'a> {
+    /// The structure block
+    structs: &'a [u8],
+    /// The strings block
+    strings: &'a [u8],
+    /// Offset of the next token within the structure block
+    off: usize,
+    /// Set once END was reached or an error was reported
+    done: bool,
+}
+
+impl<'a> TokenIter<'a> {
+    /// Decodes the token at the current offset and advances past it
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, FdtError> {
+        loop {
+            let token = be32(self.structs, self.off).ok_or(FdtError::Truncated)?;
+            self.off += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(self.structs, self.off).ok_or(FdtError::Truncated)?;
+                    self.off = align4(self.off + name.len() + 1);
+                    return Ok(Some(Token::BeginNode(name)));
+                }
+                FDT_END_NODE => return Ok(Some(Token::EndNode)),
+                FDT_PROP => {
+                    let len = be32(self.structs, self.off).ok_or(FdtError::Truncated)? as usize;
+                    let nameoff =
+                        be32(self.structs, self.off + 4).ok_or(FdtError::Truncated)? as usize;
+                    let start = self.off + 8;
+                    let value = start
+                        .checked_add(len)
+                        .and_then(|end| self.structs.get(start..end))
+                        .ok_or(FdtError::Truncated)?;
+                    let name = cstr(self.strings, nameoff).ok_or(FdtError::BadStructure)?;
+                    self.off = align4(start + len);
+                    return Ok(Some(Token::Prop { name, value }));
+                }
+                FDT_NOP => continue,
+                FDT_END => return Ok(None),
+                _ => return Err(FdtError::BadStructure),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for TokenIter<'a> {
+    type Item = Result<Token<'a>, FdtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_token() {
+            Ok(Some(token)) => return Some(Ok(token)),
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Indentation of each level of nodes in [`dump`]
+const DUMP_INDENT: &[u8] = b"    ";
+
+/// Writes the tree of `fdt` to `out` as device tree source, like `fdtdump`
+///
+/// Each node is shown with its properties, then its subnodes indented a
+/// level further. A property value is shown as strings if it's a list of
+/// printable NUL-terminated strings, as 32-bit cells if its size is a
+/// multiple of 4, and as bytes otherwise.
+pub fn dump(fdt: &Fdt, out: &mut dyn FnMut(&[u8])) -> Result<(), FdtError> {
+    let mut depth = 0usize;
+
+    for token in fdt.tokens() {
+        match token? {
+            Token::BeginNode(name) => {
+                (0..depth).for_each(|_| out(DUMP_INDENT));
+                out(if name.is_empty() { b"/" } else { name });
+                out(b" {\n");
+                depth += 1;
+            }
+            Token::EndNode => {
+                depth = depth.saturating_sub(1);
+                (0..depth).for_each(|_| out(DUMP_INDENT));
+                out(b"};\n");
+            }
+            Token::Prop { name, value } => {
+                (0..depth).for_each(|_| out(DUMP_INDENT));
+                out(name);
+                if !value.is_empty() {
+                    out(b" = ");
+                    dump_value(value, out);
+                }
+                out(b";\n");
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Checks whether `value` is a list of printable NUL-terminated strings
+fn is_string_list(value: &[u8]) -> bool {
+    let Some(strings) = value.strip_suffix(b"\0") else {
+        return false;
+    };
+
+    return strings.split(|&c| c == 0).all(|s| {
+        !s.is_empty()
+            && s.iter()
+                .all(|&c| (b' '..=b'~').contains(&c) && c != b'"' && c != b'\\')
+    });
+}
+
+/// Writes a property value for [`dump`]: `"a", "b"`, `<0x00000001>` or
+/// `[01 02 03]`
+fn dump_value(value: &[u8], out: &mut dyn FnMut(&[u8])) {
+    if is_string_list(value) {
+        for (i, s) in value[..value.len() - 1].split(|&c| c == 0).enumerate() {
+            out(if i == 0 { b"\"" } else { b", \"" });
+            out(s);
+            out(b"\"");
+        }
+    } else if value.len().is_multiple_of(4) {
+        for (i, cell) in value.chunks_exact(4).enumerate() {
+            out(if i == 0 { b"<0x" } else { b" 0x" });
+            let cell = u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]);
+            out(u64_to_hex_padded(cell as u64, &mut [0u8; 8]));
+        }
+        out(b">");
+    } else {
+        for (i, &byte) in value.iter().enumerate() {
+            out(if i == 0 { b"[" } else { b" " });
+            out(u64_to_hex_padded(byte as u64, &mut [0u8; 2]));
+        }
+        out(b"]");
+    }
+}
+
+/// Prints the tree of the DTB at `addr` on the console, see [`dump`]
+///
+/// # Safety
+///
+/// Same as [`blob_at`].
+pub unsafe fn dump_fdt(addr: usize) -> Result<(), FdtError> {
+    let fdt = Fdt::new(unsafe { blob_at(addr) }?)?;
+
+    return dump(&fdt, &mut |s| pl011::print(s));
+}
+
+/// Writes a big-endian u32 at `off` of `data`
+fn put_be32(data: &mut [u8], off: usize, value: u32) {
+    data[off..off + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Sets the property `name` of the `/chosen` node of the DTB at the start of
+/// `buf` to `value`
+///
+/// `buf` is the blob followed by the room it may grow into, e.g. the
+/// headroom left by relocation. A property of the same size is overwritten;
+/// otherwise it's replaced by NOP tokens and the new one is inserted at the
+/// start of the node, after which the structure and strings blocks move up.
+/// A name not in the strings block yet is appended to it, so the strings
+/// block must end the blob, as `dtc` lays it out; a blob whose memory
+/// reservation block or strings block doesn't follow the same order is
+/// refused with [`FdtError::BadLayout`]. The blob is left untouched on
+/// failure.
+pub fn set_chosen_property(buf: &mut [u8], name: &[u8], value: &[u8]) -> Result<(), FdtError> {
+    return set_chosen(buf, name, value, b"");
+}
+
+/// Sets the property `name` of `/chosen` of the DTB at `fdt` to `value`, as
+/// a big-endian u64 like `linux,initrd-start`
+///
+/// The blob may grow up to `capacity` bytes, as described for
+/// [`set_chosen_property`].
+///
+/// # Safety
+///
+/// `fdt` must point to `capacity` bytes of writable memory starting with
+/// the blob.
+pub unsafe fn set_chosen_u64(
+    fdt: usize,
+    capacity: usize,
+    name: &[u8],
+    value: u64,
+) -> Result<(), FdtError> {
+    let buf = unsafe { slice::from_raw_parts_mut(fdt as *mut u8, capacity) };
+
+    return set_chosen(buf, name, &value.to_be_bytes(), b"");
+}
+
+/// Sets the property `name` of `/chosen` of the DTB at `fdt` to the string
+/// `value`, like `bootargs`
+///
+/// `value` is stored NUL-terminated and must not contain a NUL itself. The
+/// blob may grow up to `capacity` bytes, as described for
+/// [`set_chosen_property`].
+///
+/// # Safety
+///
+/// `fdt` must point to `capacity` bytes of writable memory starting with
+/// the blob.
+pub unsafe fn set_chosen_str(
+    fdt: usize,
+    capacity: usize,
+    name: &[u8],
+    value: &[u8],
+) -> Result<(), FdtError> {
+    let buf = unsafe { slice::from_raw_parts_mut(fdt as *mut u8, capacity) };
+
+    return set_chosen(buf, name, value, b"\0");
+}
+
+/// Sets the property `name` of `/chosen` to `value` followed by `suffix`, as
+/// described for [`set_chosen_property`]
+fn set_chosen(buf: &mut [u8], name: &[u8], value: &[u8], suffix: &[u8]) -> Result<(), FdtError> {
+    let fdt = Fdt::new(buf)?;
+    let header = *fdt.header();
+    let chosen = fdt.chosen()?.ok_or(FdtError::NoNode)?;
+    let struct_start = header.off_dt_struct as usize;
+    let strings_start = header.off_dt_strings as usize;
+    let strings_end = strings_start + header.size_dt_strings as usize;
+    let total = header.totalsize as usize;
+    // The blocks after the insertion point move up together
+    if header.off_mem_rsvmap > header.off_dt_struct || strings_start < struct_start {
+        return Err(FdtError::BadLayout);
+    }
+    // Start of the node's first token, where a new property goes
+    let insert = struct_start + chosen.tokens.off;
+    let size = value.len() + suffix.len();
+    let mut existing = None;
+
+    let mut tokens = chosen.tokens.clone();
+    loop {
+        let off = struct_start + tokens.off;
+        match tokens.next_token()? {
+            Some(Token::Prop { name: n, value: v }) if n == name => {
+                existing = Some((off, v.len(), struct_start + tokens.off));
+                break;
+            }
+            Some(Token::Prop { .. }) => continue,
+            _ => break,
+        }
+    }
+    let nameoff = fdt
+        .tokens()
+        .strings
+        .windows(name.len() + 1)
+        .position(|w| w[..name.len()] == *name && w[name.len()] == 0);
+
+    if let Some((off, len, _)) = existing
+        && len == size
+    {
+        buf[off + 12..off + 12 + value.len()].copy_from_slice(value);
+        buf[off + 12 + value.len()..off + 12 + size].copy_from_slice(suffix);
+        return Ok(());
+    }
+
+    let prop_size = 12 + align4(size);
+    let name_size = if nameoff.is_none() { name.len() + 1 } else { 0 };
+    if nameoff.is_none() && strings_end != total {
+        return Err(FdtError::NoSpace);
+    }
+    if total + prop_size + name_size > buf.len() {
+        return Err(FdtError::NoSpace);
+    }
+    // Only once the new property is sure to fit, so a failed edit leaves
+    // the blob as it was
+    if let Some((off, _, end)) = existing {
+        for word in (off..end).step_by(4) {
+            put_be32(buf, word, FDT_NOP);
+        }
+    }
+
+    buf.copy_within(insert..total, insert + prop_size);
+    let nameoff = match nameoff {
+        Some(off) => off,
+        None => {
+            let off = strings_end + prop_size;
+            buf[off..off + name.len()].copy_from_slice(name);
+            buf[off + name.len()] = 0;
+            header.size_dt_strings as usize
+        }
+    };
+    put_be32(buf, insert, FDT_PROP);
+    put_be32(buf, insert + 4, size as u32);
+    put_be32(buf, insert + 8, nameoff as u32);
+    buf[insert + 12..insert + prop_size].fill(0);
+    buf[insert + 12..insert + 12 + value.len()].copy_from_slice(value);
+    buf[insert + 12 + value.len()..insert + 12 + size].copy_from_slice(suffix);
+
+    put_be32(buf, 4, (total + prop_size + name_size) as u32);
+    put_be32(buf, 12, (strings_start + prop_size) as u32);
+    put_be32(
+        buf,
+        32,
+        (header.size_dt_strings as usize + name_size) as u32,
+    );
+    put_be32(buf, 36, header.size_dt_struct + prop_size as u32);
+    return Ok(());
+}
+
+/// Rounds `off` up to the next 4-byte boundary
+fn align4(off: usize) -> usize {
+    return (off + 3) & !3;
+}
+
+/// Room given to a blob built by [`synthesize`]
+pub const SYNTH_SIZE: usize = 2048;
+/// Room for the strings block of a blob built by [`synthesize`]
+const SYNTH_STRINGS_SIZE: usize = 256;
+/// Offset of the structure block of a blob built by [`synthesize`]: right
+/// after the header and an empty memory reservation block
+const SYNTH_STRUCT: usize = FDT_HEADER_SIZE + FDT_RSV_ENTRY_SIZE;
+/// Phandle of the interrupt controller of a synthesized blob
+const SYNTH_GIC_PHANDLE: u32 = 1;
+/// Phandle of the UART reference clock of a synthesized blob
+const SYNTH_CLOCK_PHANDLE: u32 = 2;
+/// Size of the GICv2 distributor and CPU interface register frames
+const GICV2_SIZES: (u64, u64) = (0x1000, 0x2000);
+/// Size of the GICv3 distributor and of the redistributor frames of one
+/// core
+const GICV3_SIZES: (u64, u64) = (0x1_0000, 0x2_0000);
+/// Size of the PL011 register frame
+const PL011_SIZE: u64 = 0x1000;
+/// Secure, non-secure, virtual and hypervisor timer PPIs, level triggered
+const TIMER_INTERRUPTS: [u32; 12] = [1, 13, 4, 1, 14, 4, 1, 11, 4, 1, 10, 4];
+
+/// Buffer a blob is built in by [`synthesize`], aligned as the kernel wants
+/// its DTB
+#[repr(C, align(8))]
+pub struct SynthBuffer(pub [u8; SYNTH_SIZE]);
+
+/// Writes a blob for [`synthesize`]
+///
+/// The structure block is written in place; property names are collected
+/// apart and become the strings block once the tree is finished. Writes
+/// past the end of the buffer are dropped and remembered, so the tree is
+/// written without checking each step and [`finish`](Self::finish) fails
+/// if it didn't fit.
+struct Writer<'a> {
+    /// Buffer the blob is written to
+    buf: &'a mut [u8],
+    /// End of the structure block so far
+    off: usize,
+    /// Strings block so far
+    strings: [u8; SYNTH_STRINGS_SIZE],
+    /// Length of the strings block
+    strings_len: usize,
+    /// Whether something didn't fit
+    full: bool,
+}
+
+impl<'a> Writer<'a> {
+    /// Starts a blob at the start of `buf`
+    fn new(buf: &'a mut [u8]) -> Self {
+        return Writer {
+            buf,
+            off: SYNTH_STRUCT,
+            strings: [0; SYNTH_STRINGS_SIZE],
+            strings_len: 0,
+            full: false,
+        };
+    }
+
+    /// Appends `bytes` to the structure block
+    fn put(&mut self, bytes: &[u8]) {
+        let end = self.off + bytes.len();
+
+        match self.buf.get_mut(self.off..end) {
+            Some(dst) => dst.copy_from_slice(bytes),
+            None => self.full = true,
+        }
+        self.off = end;
+    }
+
+    /// Appends zeros up to the next 4-byte boundary
+    fn pad(&mut self) {
+        let len = align4(self.off) - self.off;
+
+        self.put(&[0; 3][..len]);
+    }
+
+    /// Appends the big-endian `value`
+    fn put_u32(&mut self, value: u32) {
+        self.put(&value.to_be_bytes());
+    }
+
+    /// Returns the offset of `name` in the strings block, adding it if it
+    /// isn't there yet
+    fn string(&mut self, name: &[u8]) -> u32 {
+        let strings = &self.strings[..self.strings_len];
+        let found = strings
+            .windows(name.len() + 1)
+            .position(|w| w[..name.len()] == *name && w[name.len()] == 0);
+
+        if let Some(off) = found {
+            return off as u32;
+        }
+        let off = self.strings_len;
+        match self.strings.get_mut(off..off + name.len() + 1) {
+            Some(dst) => {
+                dst[..name.len()].copy_from_slice(name);
+                dst[name.len()] = 0;
+                self.strings_len += name.len() + 1;
+            }
+            None => self.full = true,
+        }
+        return off as u32;
+    }
+
+    /// Opens the node `name`, with `unit` as its unit address if given
+    fn begin_node(&mut self, name: &[u8], unit: Option<u64>) {
+        self.put_u32(FDT_BEGIN_NODE);
+        self.put(name);
+        if let Some(unit) = unit {
+            self.put(b"@");
+            self.put(u64_to_hex(unit, &mut [0u8; 16]));
+        }
+        self.put(&[0]);
+        self.pad();
+    }
+
+    /// Closes the last node opened
+    fn end_node(&mut self) {
+        self.put_u32(FDT_END_NODE);
+    }
+
+    /// Adds the property `name` whose value is `parts` put together
+    fn prop(&mut self, name: &[u8], parts: &[&[u8]]) {
+        let nameoff = self.string(name);
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+
+        self.put_u32(FDT_PROP);
+        self.put_u32(len as u32);
+        self.put_u32(nameoff);
+        for part in parts {
+            self.put(part);
+        }
+        self.pad();
+    }
+
+    /// Adds the string property `name`
+    fn prop_str(&mut self, name: &[u8], value: &[u8]) {
+        self.prop(name, &[value, b"\0"]);
+    }
+
+    /// Adds the property `name` made of `cells`
+    fn prop_cells(&mut self, name: &[u8], cells: &[u32]) {
+        let nameoff = self.string(name);
+
+        self.put_u32(FDT_PROP);
+        self.put_u32(4 * cells.len() as u32);
+        self.put_u32(nameoff);
+        for &cell in cells {
+            self.put_u32(cell);
+        }
+    }
+
+    /// Adds a `reg` property of `(base, size)` ranges, two cells each
+    fn prop_reg(&mut self, ranges: &[(u64, u64)]) {
+        let nameoff = self.string(b"reg");
+
+        self.put_u32(FDT_PROP);
+        self.put_u32(16 * ranges.len() as u32);
+        self.put_u32(nameoff);
+        for &(base, size) in ranges {
+            self.put(&base.to_be_bytes());
+            self.put(&size.to_be_bytes());
+        }
+    }
+
+    /// Ends the structure block, appends the strings block and writes the
+    /// header, with `boot_cpu` as the physical ID of the boot CPU
+    ///
+    /// Returns the size of the blob.
+    fn finish(mut self, boot_cpu: u32) -> Result<usize, FdtError> {
+        let (strings, strings_len) = (self.strings, self.strings_len);
+
+        self.put_u32(FDT_END);
+        let struct_size = self.off - SYNTH_STRUCT;
+        self.put(&strings[..strings_len]);
+        if self.full {
+            return Err(FdtError::NoSpace);
+        }
+
+        let header = [
+            FDT_MAGIC,
+            self.off as u32,
+            SYNTH_STRUCT as u32,
+            (SYNTH_STRUCT + struct_size) as u32,
+            FDT_HEADER_SIZE as u32,
+            FDT_MIN_VERSION,
+            FDT_LAST_COMP_VERSION,
+            boot_cpu,
+            strings_len as u32,
+            struct_size as u32,
+        ];
+        for (i, &field) in header.iter().enumerate() {
+            put_be32(self.buf, 4 * i, field);
+        }
+        self.buf[FDT_HEADER_SIZE..SYNTH_STRUCT].fill(0);
+        return Ok(self.off);
+    }
+}
+
+/// Builds a DTB describing `config` at the start of `buf`, for booting
+/// without one from the firmware
+///
+/// The tree holds what a Linux kernel needs to start and print:
+///
+/// - `/chosen`, with the board's `bootargs` and `stdout-path`
+/// - a `/memory` node per RAM range
+/// - `/cpus`, with the boot CPU only, started through PSCI
+/// - `/psci`, with the board's [conduit](BoardConfig::psci_conduit)
+/// - the GIC and the generic timer, if the board has a GIC
+/// - the console PL011 and its reference clock, if the board has one. The
+///   board doesn't know its interrupt, so the kernel can only use it as a
+///   polled console.
+///
+/// Returns the size of the blob, or [`FdtError::NoSpace`] if it doesn't
+/// fit in `buf`.
+pub fn synthesize(config: &BoardConfig, buf: &mut [u8]) -> Result<usize, FdtError> {
+    let mut w = Writer::new(buf);
+    let uart = config.uart;
+    let gic = config.gic;
+    let boot_cpu = (cpu::read_mpidr() & 0x00ff_ffff) as u32;
+    let product = match config.identity.product {
+        b"" => config.name,
+        product => product,
+    };
+
+    w.begin_node(b"", None);
+    w.prop_cells(b"#address-cells", &[2]);
+    w.prop_cells(b"#size-cells", &[2]);
+    w.prop_str(b"model", product);
+    if gic.kind != GicKind::None {
+        w.prop_cells(b"interrupt-parent", &[SYNTH_GIC_PHANDLE]);
+    }
+
+    w.begin_node(b"chosen", None);
+    w.prop_str(b"bootargs", config.bootargs);
+    if uart.kind == UartKind::Pl011 {
+        let mut hex = [0u8; 16];
+        let mut baud = [0u8; 20];
+        w.prop(
+            b"stdout-path",
+            &[
+                b"/pl011@",
+                u64_to_hex(uart.base as u64, &mut hex),
+                b":",
+                u64_to_dec(uart.baudrate as u64, &mut baud),
+                b"\0",
+            ],
+        );
+    }
+    w.end_node();
+
+    for &(base, size) in config.ram {
+        w.begin_node(b"memory", Some(base as u64));
+        w.prop_str(b"device_type", b"memory");
+        w.prop_reg(&[(base as u64, size as u64)]);
+        w.end_node();
+    }
+
+    w.begin_node(b"cpus", None);
+    w.prop_cells(b"#address-cells", &[1]);
+    w.prop_cells(b"#size-cells", &[0]);
+    w.begin_node(b"cpu", Some(boot_cpu as u64));
+    w.prop_str(b"device_type", b"cpu");
+    w.prop_str(b"compatible", b"arm,armv8");
+    w.prop_cells(b"reg", &[boot_cpu]);
+    w.prop_str(b"enable-method", b"psci");
+    w.end_node();
+    w.end_node();
+
+    w.begin_node(b"psci", None);
+    w.prop_str(b"compatible", b"arm,psci-0.2");
+    w.prop_str(
+        b"method",
+        match config.psci_conduit() {
+            Conduit::Smc => b"smc",
+            Conduit::Hvc => b"hvc",
+        },
+    );
+    w.end_node();
+
+    let sizes = match gic.kind {
+        GicKind::V2 => Some((&b"arm,cortex-a15-gic"[..], GICV2_SIZES)),
+        GicKind::V3 => Some((&b"arm,gic-v3"[..], GICV3_SIZES)),
+        GicKind::None => None,
+    };
+    if let Some((compatible, (dist_size, cpu_size))) = sizes {
+        w.begin_node(b"intc", Some(gic.dist_base as u64));
+        w.prop_str(b"compatible", compatible);
+        w.prop_cells(b"#interrupt-cells", &[3]);
+        w.prop_cells(b"#address-cells", &[0]);
+        w.prop(b"interrupt-controller", &[]);
+        w.prop_reg(&[
+            (gic.dist_base as u64, dist_size),
+            (gic.cpu_base as u64, cpu_size),
+        ]);
+        w.prop_cells(b"phandle", &[SYNTH_GIC_PHANDLE]);
+        w.end_node();
+
+        w.begin_node(b"timer", None);
+        w.prop_str(b"compatible", b"arm,armv8-timer");
+        w.prop_cells(b"interrupts", &TIMER_INTERRUPTS);
+        w.end_node();
+    }
+
+    if uart.kind == UartKind::Pl011 {
+        w.begin_node(b"apb-pclk", None);
+        w.prop_str(b"compatible", b"fixed-clock");
+        w.prop_cells(b"#clock-cells", &[0]);
+        w.prop_cells(b"clock-frequency", &[uart.clock]);
+        w.prop_cells(b"phandle", &[SYNTH_CLOCK_PHANDLE]);
+        w.end_node();
+
+        w.begin_node(b"pl011", Some(uart.base as u64));
+        w.prop(b"compatible", &[b"arm,pl011\0arm,primecell\0"]);
+        w.prop_reg(&[(uart.base as u64, PL011_SIZE)]);
+        w.prop_cells(b"clocks", &[SYNTH_CLOCK_PHANDLE, SYNTH_CLOCK_PHANDLE]);
+        w.prop(b"clock-names", &[b"uartclk\0apb_pclk\0"]);
+        w.end_node();
+    }
+
+    w.end_node();
+    return w.finish(boot_cpu);
+}
+
+/// Room the self-test's blob may grow into
+const TEST_DTB_CAPACITY: usize = 320;
+/// Strings block of the self-test's blob
+const TEST_DTB_STRINGS: &[u8] = b"bootargs\0clock-frequency\0";
+/// Offset of the self-test's structure block: right after the header and
+/// an empty memory reservation block
+const TEST_DTB_STRUCT: usize = FDT_HEADER_SIZE + 16;
+/// Structure block of the self-test's blob: a root node with a `/chosen`
+/// whose `bootargs` is `console`, and a `/uart@9000000` whose
+/// `clock-frequency` is 24 MHz
+const TEST_DTB_STRUCTS: [u32; 23] = [
+    FDT_BEGIN_NODE,
+    0,
+    FDT_BEGIN_NODE,
+    u32::from_be_bytes(*b"chos"),
+    u32::from_be_bytes(*b"en\0\0"),
+    FDT_PROP,
+    8,
+    0,
+    u32::from_be_bytes(*b"cons"),
+    u32::from_be_bytes(*b"ole\0"),
+    FDT_END_NODE,
+    FDT_BEGIN_NODE,
+    u32::from_be_bytes(*b"uart"),
+    u32::from_be_bytes(*b"@900"),
+    u32::from_be_bytes(*b"0000"),
+    0,
+    FDT_PROP,
+    4,
+    9,
+    24_000_000,
+    FDT_END_NODE,
+    FDT_END_NODE,
+    FDT_END,
+];
+/// Size of the self-test's blob before any edit
+const TEST_DTB_SIZE: usize = TEST_DTB_STRUCT + 4 * TEST_DTB_STRUCTS.len() + TEST_DTB_STRINGS.len();
+
+/// Builds the self-test's blob, followed by free room
+const fn test_dtb() -> [u8; TEST_DTB_CAPACITY] {
+    let strings = TEST_DTB_STRUCT + 4 * TEST_DTB_STRUCTS.len();
+    let header = [
+        FDT_MAGIC,
+        TEST_DTB_SIZE as u32,
+        TEST_DTB_STRUCT as u32,
+        strings as u32,
+        FDT_HEADER_SIZE as u32,
+        17,
+        16,
+        0,
+        TEST_DTB_STRINGS.len() as u32,
+        4 * TEST_DTB_STRUCTS.len() as u32,
+    ];
+    let mut buf = [0u8; TEST_DTB_CAPACITY];
+    let mut i = 0;
+
+    while i < 4 * header.len() {
+        buf[i] = header[i / 4].to_be_bytes()[i % 4];
+        i += 1;
+    }
+    i = 0;
+    while i < 4 * TEST_DTB_STRUCTS.len() {
+        buf[TEST_DTB_STRUCT + i] = TEST_DTB_STRUCTS[i / 4].to_be_bytes()[i % 4];
+        i += 1;
+    }
+    i = 0;
+    while i < TEST_DTB_STRINGS.len() {
+        buf[strings + i] = TEST_DTB_STRINGS[i];
+        i += 1;
+    }
+    return buf;
+}
+
+/// Blob edited by the self-test
+static mut TEST_DTB: [u8; TEST_DTB_CAPACITY] = [0; TEST_DTB_CAPACITY];
+
+/// Re-parses the self-test's blob and returns its size and the values of
+/// `bootargs` and `linux,initrd-start` in `/chosen`
+///
+/// Every token is decoded first, so a blob left malformed is `None`.
+fn test_parse(buf: &[u8]) -> Option<(usize, &[u8], Option<u64>)> {
+    let fdt = Fdt::new(buf).ok()?;
+
+    if fdt.tokens().any(|token| token.is_err()) {
+        return None;
+    }
+    let chosen = fdt.chosen().ok()??;
+    let initrd = chosen
+        .property(b"linux,initrd-start")
+        .and_then(|v| be64(v, 0));
+    return Some((
+        fdt.header().totalsize as usize,
+        chosen.property(b"bootargs")?,
+        initrd,
+    ));
+}
+
+/// Self-test: properties are found by path, and decoded as numbers or
+/// strings only when their size allows it
+pub fn selftest_lookup() -> Outcome {
+    let buf = test_dtb();
+    let Ok(fdt) = Fdt::new(&buf) else {
+        return Outcome::Fail;
+    };
+
+    let found = fdt.get_u32(b"/uart@9000000", b"clock-frequency") == Some(24_000_000)
+        && fdt.get_u64(b"/uart", b"clock-frequency") == Some(24_000_000)
+        && fdt.get_str(b"/chosen", b"bootargs") == Some(&b"console"[..])
+        && fdt.get_prop(b"/chosen/", b"bootargs") == Some(&b"console\0"[..])
+        && fdt
+            .find_path(b"/")
+            .is_ok_and(|node| node.is_some_and(|n| n.depth == 1));
+    let refused = fdt.get_u32(b"/chosen", b"bootargs").is_none()
+        && fdt.get_str(b"/uart", b"clock-frequency").is_none()
+        && fdt.get_prop(b"/chosen", b"stdout-path").is_none()
+        && fdt.get_prop(b"/uart@9000001", b"clock-frequency").is_none()
+        && fdt.get_prop(b"/chosen/uart", b"clock-frequency").is_none();
+
+    if found && refused {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Self-test: properties of `/chosen` are added, overwritten in place and
+/// resized, each edit parsing back with the other properties intact, and
+/// an edit past the capacity is refused
+pub fn selftest() -> Outcome {
+    let buf = &raw mut TEST_DTB;
+    let buf = unsafe { &mut *buf };
+    let fdt = buf.as_mut_ptr() as usize;
+    let cap = TEST_DTB_CAPACITY;
+
+    *buf = test_dtb();
+    if test_parse(buf) != Some((TEST_DTB_SIZE, b"console\0", None)) {
+        return Outcome::Fail;
+    }
+
+    // A new name grows both blocks: 20 bytes of token, 19 of string
+    let added = TEST_DTB_SIZE + 20 + 19;
+    if unsafe { set_chosen_u64(fdt, cap, b"linux,initrd-start", 0x4800_0000) }.is_err()
+        || test_parse(buf) != Some((added, b"console\0", Some(0x4800_0000)))
+    {
+        return Outcome::Fail;
+    }
+
+    // Same size: the blob doesn't move
+    if unsafe { set_chosen_u64(fdt, cap, b"linux,initrd-start", 0x4900_0000) }.is_err()
+        || unsafe { set_chosen_str(fdt, cap, b"bootargs", b"ttyAMA0") }.is_err()
+        || test_parse(buf) != Some((added, b"ttyAMA0\0", Some(0x4900_0000)))
+    {
+        return Outcome::Fail;
+    }
+
+    // Another size: the old value turns to NOPs, the name is reused
+    let args = b"console=ttyAMA0 quiet";
+    if unsafe { set_chosen_str(fdt, cap, b"bootargs", args) }.is_err() {
+        return Outcome::Fail;
+    }
+    match test_parse(buf) {
+        Some((size, value, Some(0x4900_0000)))
+            if size == added + 12 + align4(args.len() + 1)
+                && value.strip_suffix(b"\0") == Some(&args[..]) => {}
+        _ => return Outcome::Fail,
+    }
+
+    let size = test_parse(buf).map_or(0, |parsed| parsed.0);
+    let result = unsafe { set_chosen_u64(fdt, size, b"linux,initrd-end", 0x4a00_0000) };
+    if result != Err(FdtError::NoSpace) || test_parse(buf).map(|parsed| parsed.0) != Some(size) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Corruptions of the self-test's blob, as the offset of a big-endian word
+/// and the value written there, each of which must be refused
+const TEST_CORRUPTIONS: [(usize, u32); 22] = [
+    // totalsize: giant, then smaller than the header
+    (4, u32::MAX),
+    (4, FDT_HEADER_SIZE as u32 - 1),
+    // off_dt_struct: past the end, over the header, misaligned
+    (8, TEST_DTB_SIZE as u32),
+    (8, 0),
+    (8, TEST_DTB_STRUCT as u32 + 2),
+    // off_dt_strings: within the structure block, then giant
+    (12, TEST_DTB_STRUCT as u32),
+    (12, u32::MAX),
+    // off_mem_rsvmap: unterminated before the end, over the header
+    (16, TEST_DTB_SIZE as u32 - 8),
+    (16, 0),
+    // version too old, last compatible version too new
+    (20, 16),
+    (24, 18),
+    // size_dt_strings and size_dt_struct: giant
+    (32, u32::MAX),
+    (36, u32::MAX),
+    // size_dt_struct cut before END
+    (36, 4 * (TEST_DTB_STRUCTS.len() as u32 - 1)),
+    // size_dt_strings cut before the last NUL
+    (32, TEST_DTB_STRINGS.len() as u32 - 1),
+    // bootargs: giant length, name out of the strings block
+    (TEST_DTB_STRUCT + 4 * 6, u32::MAX),
+    (TEST_DTB_STRUCT + 4 * 7, TEST_DTB_STRINGS.len() as u32),
+    // /chosen never ended, root never ended, END_NODE past the root
+    (TEST_DTB_STRUCT + 4 * 10, FDT_BEGIN_NODE),
+    (TEST_DTB_STRUCT + 4 * 21, FDT_NOP),
+    (TEST_DTB_STRUCT + 4 * 22, FDT_END_NODE),
+    // Unknown token, named root
+    (TEST_DTB_STRUCT + 4 * 5, 7),
+    (TEST_DTB_STRUCT + 4, u32::from_be_bytes(*b"a\0\0\0")),
+];
+
+/// Walks everything a boot reads from `blob`, if it's accepted, and returns
+/// whether every token of an accepted blob decodes
+fn test_walk(blob: &[u8]) -> bool {
+    let Ok(fdt) = Fdt::new(blob) else {
+        return true;
+    };
+
+    let _ = fdt.find_path(b"/uart@9000000/none");
+    let _ = fdt.get_str(b"/chosen", b"bootargs");
+    let _ = fdt.for_each_memory_range(|_, _| {});
+    if let Ok(Some(node)) = fdt.find_path(b"/uart") {
+        let _ = node.reg(usize::MAX);
+    }
+    return fdt.tokens().all(|token| token.is_ok());
+}
+
+/// Self-test: blobs cut short, with blocks outside the blob or over each
+/// other, with tokens running past their block or nodes not nesting are
+/// refused, and randomly corrupted blobs are refused or walked without a
+/// fault
+pub fn selftest_corrupt() -> Outcome {
+    let mut buf = test_dtb();
+
+    if Fdt::new(&buf).is_err() {
+        return Outcome::Fail;
+    }
+    for len in 0..TEST_DTB_SIZE {
+        put_be32(&mut buf, 4, len as u32);
+        if Fdt::new(&test_dtb()[..len]).is_ok() || Fdt::new(&buf).is_ok() {
+            return Outcome::Fail;
+        }
+    }
+    for (off, value) in TEST_CORRUPTIONS {
+        buf = test_dtb();
+        put_be32(&mut buf, off, value);
+        if Fdt::new(&buf).is_ok() {
+            return Outcome::Fail;
+        }
+    }
+    if for_each_reg(&[0; 8], u32::MAX, u32::MAX, &mut |_, _| {}).is_ok() {
+        return Outcome::Fail;
+    }
+
+    // xorshift32: the same corruptions on every run
+    let mut seed = 0x2545_f491u32;
+    for _ in 0..256 {
+        buf = test_dtb();
+        for _ in 0..4 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            buf[seed as usize % TEST_DTB_SIZE] = (seed >> 24) as u8;
+        }
+        if !test_walk(&buf[..TEST_DTB_SIZE]) {
+            return Outcome::Fail;
+        }
+    }
+    return Outcome::Pass;
+}
+
+/// Tree of the self-test's blob, as written by [`dump`]
+const TEST_DUMP: &[u8] = b"/ {
+    chosen {
+        bootargs = \"console\";
+    };
+    uart@9000000 {
+        clock-frequency = <0x016e3600>;
+    };
+};
+";
+
+/// Output of [`dump`] collected by the self-test, cut at 256 bytes
+struct TestText {
+    /// Output so far
+    buf: [u8; 256],
+    /// Length of the output
+    len: usize,
+}
+
+impl TestText {
+    /// Appends `s` to the output
+    fn write(&mut self, s: &[u8]) {
+        let end = (self.len + s.len()).min(self.buf.len());
+
+        self.buf[self.len..end].copy_from_slice(&s[..end - self.len]);
+        self.len = end;
+    }
+
+    /// Returns the output
+    fn text(&self) -> &[u8] {
+        return &self.buf[..self.len];
+    }
+}
+
+/// Self-test: the self-test's blob dumps as the expected tree, and values
+/// that aren't string lists dump as cells or bytes
+pub fn selftest_dump() -> Outcome {
+    let buf = test_dtb();
+    let Ok(fdt) = Fdt::new(&buf) else {
+        return Outcome::Fail;
+    };
+    let mut tree = TestText {
+        buf: [0; 256],
+        len: 0,
+    };
+    let mut values = TestText {
+        buf: [0; 256],
+        len: 0,
+    };
+
+    if dump(&fdt, &mut |s| tree.write(s)).is_err() || tree.text() != TEST_DUMP {
+        return Outcome::Fail;
+    }
+    for value in [
+        &b"a\0bc\0"[..],
+        b"a\0\0\0",
+        b"ok\0\x01\xff",
+        b"\x01\x02\x03\x04\x05\x06\x07\x08",
+    ] {
+        dump_value(value, &mut |s| values.write(s));
+        values.write(b";");
+    }
+    if values.text() != b"\"a\", \"bc\";<0x61000000>;[6f 6b 00 01 ff];<0x01020304 0x05060708>;" {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Blob built by the self-test
+static mut TEST_SYNTH: SynthBuffer = SynthBuffer([0; SYNTH_SIZE]);
+
+/// Self-test: the blob built from the board configuration is well-formed,
+/// is found at its address, and gives back the board's command line, RAM,
+/// PSCI conduit and console clock; a buffer too small is refused
+pub fn selftest_synthesize() -> Outcome {
+    let buf = &raw mut TEST_SYNTH;
+    let buf = unsafe { &mut (*buf).0 };
+    let config = board::config();
+    let mut ram = config.ram.iter();
+    let mut ram_ok = true;
+
+    let Ok(size) = synthesize(config, buf) else {
+        return Outcome::Fail;
+    };
+    match unsafe { probe_blob(buf.as_ptr() as usize) } {
+        Ok(blob) if blob.len() == size => {}
+        _ => return Outcome::Fail,
+    }
+    if unsafe { probe_blob(buf.as_ptr() as usize + 4) }.err() != Some(FdtError::BadMagic) {
+        return Outcome::Fail;
+    }
+    let Ok(fdt) = Fdt::new(&buf[..size]) else {
+        return Outcome::Fail;
+    };
+    if fdt.tokens().any(|token| token.is_err())
+        || fdt.get_str(b"/chosen", b"bootargs") != Some(config.bootargs)
+    {
+        return Outcome::Fail;
+    }
+    let walked = fdt.for_each_memory_range(|base, size| {
+        ram_ok &= ram.next() == Some(&(base as usize, size as usize));
+    });
+    if walked.is_err() || !ram_ok || ram.next().is_some() {
+        return Outcome::Fail;
+    }
+    let method: &[u8] = match config.psci_conduit() {
+        Conduit::Smc => b"smc",
+        Conduit::Hvc => b"hvc",
+    };
+    if fdt.get_str(b"/psci", b"method") != Some(method) {
+        return Outcome::Fail;
+    }
+    if config.uart.kind == UartKind::Pl011
+        && pl011::fdt_clock(&fdt, config.uart.base, 0) != config.uart.clock
+    {
+        return Outcome::Fail;
+    }
+
+    if synthesize(config, &mut buf[..size - 1]) != Err(FdtError::NoSpace) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the self-test's blob with its strings block moved before its
+    /// structure block
+    fn strings_first_dtb() -> [u8; TEST_DTB_CAPACITY] {
+        let dtb = test_dtb();
+        let struct_len = 4 * TEST_DTB_STRUCTS.len();
+        let strings = TEST_DTB_STRUCT;
+        let structs = strings + align4(TEST_DTB_STRINGS.len());
+        let mut buf = [0u8; TEST_DTB_CAPACITY];
+
+        buf[..TEST_DTB_STRUCT].copy_from_slice(&dtb[..TEST_DTB_STRUCT]);
+        buf[strings..strings + TEST_DTB_STRINGS.len()].copy_from_slice(TEST_DTB_STRINGS);
+        buf[structs..structs + struct_len]
+            .copy_from_slice(&dtb[TEST_DTB_STRUCT..TEST_DTB_STRUCT + struct_len]);
+        put_be32(&mut buf, 4, (structs + struct_len) as u32);
+        put_be32(&mut buf, 8, structs as u32);
+        put_be32(&mut buf, 12, strings as u32);
+        return buf;
+    }
+
+    /// Returns the self-test's blob with its memory reservation block moved
+    /// after its strings block
+    fn rsvmap_last_dtb() -> [u8; TEST_DTB_CAPACITY] {
+        let mut buf = test_dtb();
+        let rsvmap = (TEST_DTB_SIZE + 7) & !7;
+
+        put_be32(&mut buf, 4, (rsvmap + FDT_RSV_ENTRY_SIZE) as u32);
+        put_be32(&mut buf, 16, rsvmap as u32);
+        return buf;
+    }
+
+    #[test]
+    fn edit_refuses_other_block_orders() {
+        for mut buf in [strings_first_dtb(), rsvmap_last_dtb()] {
+            let before = buf;
+            assert_eq!(
+                test_parse(&buf).map(|parsed| parsed.1),
+                Some(&b"console\0"[..])
+            );
+
+            let result = set_chosen_property(&mut buf, b"bootargs", b"console=ttyAMA0\0");
+            assert_eq!(result, Err(FdtError::BadLayout));
+            assert_eq!(buf, before);
+        }
+    }
+
+    #[test]
+    fn resize_str_without_room() {
+        let mut buf = test_dtb();
+        let before = buf;
+        let fdt = buf.as_mut_ptr() as usize;
+
+        let result =
+            unsafe { set_chosen_str(fdt, TEST_DTB_SIZE + 8, b"bootargs", b"console=ttyAMA0") };
+        assert_eq!(result, Err(FdtError::NoSpace));
+        assert_eq!(
+            test_parse(&buf),
+            Some((TEST_DTB_SIZE, &b"console\0"[..], None))
+        );
+        assert_eq!(buf, before);
+    }
+
+    #[test]
+    fn resize_u64_without_room() {
+        let mut buf = test_dtb();
+        let fdt = buf.as_mut_ptr() as usize;
+
+        let added = set_chosen_property(
+            &mut buf,
+            b"linux,initrd-start",
+            &0x4900_0000u32.to_be_bytes(),
+        );
+        assert_eq!(added, Ok(()));
+        let size = test_parse(&buf).unwrap().0;
+        let before = buf;
+
+        let result = unsafe { set_chosen_u64(fdt, size + 8, b"linux,initrd-start", 0x4a00_0000) };
+        assert_eq!(result, Err(FdtError::NoSpace));
+        assert_eq!(buf, before);
+        let parsed = Fdt::new(&buf).unwrap();
+        assert_eq!(parsed.header().totalsize as usize, size);
+        assert_eq!(
+            parsed.get_prop(b"/chosen", b"linux,initrd-start"),
+            Some(&0x4900_0000u32.to_be_bytes()[..])
+        );
+        assert_eq!(
+            parsed.get_str(b"/chosen", b"bootargs"),
+            Some(&b"console"[..])
+        );
+    }
+
+    #[test]
+    fn edit_keeps_dtc_order() {
+        let mut buf = test_dtb();
+
+        let result = set_chosen_property(&mut buf, b"bootargs", b"console=ttyAMA0\0");
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            test_parse(&buf).map(|parsed| parsed.1),
+            Some(&b"console=ttyAMA0\0"[..])
+        );
+    }
+}
@@ -0,0 +1,467 @@
+//! GUID Partition Table
+//!
+//! A GPT disk starts with a protective MBR in LBA 0 and the GPT header in
+//! LBA 1, which points to an array of partition entries:
+//!
+//! | Offset | Size | Field                                        |
+//! |-------:|-----:|----------------------------------------------|
+//! |      0 |    8 | Signature, `EFI PART`                        |
+//! |      8 |    4 | Revision, 1.0 (`0x00010000`)                 |
+//! |     12 |    4 | Size of the header, at least 92              |
+//! |     16 |    4 | CRC-32 of the header, this field taken as 0  |
+//! |     24 |    8 | LBA of this header, 1                        |
+//! |     40 |    8 | First LBA usable by partitions               |
+//! |     48 |    8 | Last LBA usable by partitions                |
+//! |     56 |   16 | Disk GUID                                    |
+//! |     72 |    8 | LBA of the partition entries                 |
+//! |     80 |    4 | Number of partition entries                  |
+//! |     84 |    4 | Size of a partition entry, 128 × 2ⁿ          |
+//! |     88 |    4 | CRC-32 of the partition entries              |
+//!
+//! Each entry holds the partition type GUID (nil for an unused entry), the
+//! partition's own GUID, its first and last LBA and its attributes. Fields
+//! are little-endian and GUIDs are in the mixed-endian order of
+//! [`Guid::from_mixed_endian`].
+//!
+//! [`Gpt::read`] checks the header and the entries against their CRCs;
+//! partitions are then looked up by type with [`Gpt::find_by_type`], e.g.
+//! [`EFI_SYSTEM`](crate::utilities::guid::EFI_SYSTEM), or by their own GUID
+//! with [`Gpt::find_by_guid`]. Only the primary table is read, with 512-byte
+//! logical blocks.
+
+use crate::parsers::image::ImageSource;
+use crate::utilities::crc32::{crc32, crc32_update};
+use crate::utilities::guid::Guid;
+
+/// Size of a logical block
+pub const LBA_SIZE: usize = 512;
+/// Signature starting a GPT header
+pub const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+/// Revision of the header format, 1.0
+const GPT_REVISION: u32 = 0x0001_0000;
+/// Size of the header fields, which the header size may exceed
+const GPT_HEADER_SIZE: usize = 92;
+/// Offset of the CRC of the header, taken as 0 while computing it
+const HEADER_CRC_OFFSET: usize = 16;
+/// Size of a partition entry in the original format, which larger entries
+/// start with
+const ENTRY_SIZE: usize = 128;
+
+/// Errors reported when reading a GPT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GptError {
+    /// The disk ends before the header or the partition entries
+    Truncated,
+    /// No GPT signature in LBA 1
+    BadSignature,
+    /// Unknown revision, or a header size, entry size or entry array
+    /// outside what the format allows
+    BadHeader,
+    /// The header is corrupted
+    BadHeaderCrc,
+    /// The partition entries are corrupted
+    BadEntriesCrc,
+    /// A partition ends before it starts or outside the usable LBAs
+    BadEntry,
+}
+
+/// Fields of a GPT header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GptHeader {
+    /// GUID of the disk
+    pub disk_guid: Guid,
+    /// First LBA usable by partitions
+    pub first_usable_lba: u64,
+    /// Last LBA usable by partitions
+    pub last_usable_lba: u64,
+    /// LBA of the partition entries
+    pub entries_lba: u64,
+    /// Number of partition entries
+    pub entry_count: u32,
+    /// Size of a partition entry in bytes
+    pub entry_size: u32,
+}
+
+/// A used partition entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Partition {
+    /// Index of the entry in the table
+    pub index: u32,
+    /// Type of the partition
+    pub type_guid: Guid,
+    /// GUID of the partition itself
+    pub guid: Guid,
+    /// First LBA of the partition
+    pub first_lba: u64,
+    /// Last LBA of the partition, inclusive
+    pub last_lba: u64,
+    /// Attribute flags
+    pub attributes: u64,
+}
+
+impl Partition {
+    /// Returns the offset of the partition on the disk in bytes
+    pub fn offset(&self) -> u64 {
+        return self.first_lba * LBA_SIZE as u64;
+    }
+
+    /// Returns the size of the partition in bytes
+    pub fn size(&self) -> u64 {
+        return (self.last_lba - self.first_lba + 1) * LBA_SIZE as u64;
+    }
+}
+
+/// A partition table checked by [`Gpt::read`]
+pub struct Gpt<'a> {
+    /// The disk
+    src: &'a dyn ImageSource,
+    /// Its header
+    header: GptHeader,
+}
+
+/// Reads `buf.len()` bytes at `off` of `src`
+fn read_exact(src: &dyn ImageSource, off: usize, buf: &mut [u8]) -> Result<(), GptError> {
+    let mut done = 0;
+
+    while done < buf.len() {
+        let count = src.read_at(off + done, &mut buf[done..]);
+        if count == 0 {
+            return Err(GptError::Truncated);
+        }
+        done += count;
+    }
+    return Ok(());
+}
+
+/// Reads the little-endian `u32` at `off` of `bytes`
+fn read_u32(bytes: &[u8], off: usize) -> u32 {
+    let mut field = [0u8; 4];
+
+    field.copy_from_slice(&bytes[off..off + 4]);
+    return u32::from_le_bytes(field);
+}
+
+/// Reads the little-endian `u64` at `off` of `bytes`
+fn read_u64(bytes: &[u8], off: usize) -> u64 {
+    let mut field = [0u8; 8];
+
+    field.copy_from_slice(&bytes[off..off + 8]);
+    return u64::from_le_bytes(field);
+}
+
+/// Reads the mixed-endian GUID at `off` of `bytes`
+fn read_guid(bytes: &[u8], off: usize) -> Guid {
+    let mut field = [0u8; 16];
+
+    field.copy_from_slice(&bytes[off..off + 16]);
+    return Guid::from_mixed_endian(field);
+}
+
+impl<'a> Gpt<'a> {
+    /// Reads the GPT of the disk `src`, checking the header and the
+    /// partition entries against their CRCs
+    pub fn read(src: &'a dyn ImageSource) -> Result<Self, GptError> {
+        let mut lba = [0u8; LBA_SIZE];
+
+        read_exact(src, LBA_SIZE, &mut lba)?;
+        if lba[0..8] != GPT_SIGNATURE {
+            return Err(GptError::BadSignature);
+        }
+        let header_size = read_u32(&lba, 12) as usize;
+        if read_u32(&lba, 8) != GPT_REVISION || !(GPT_HEADER_SIZE..=LBA_SIZE).contains(&header_size)
+        {
+            return Err(GptError::BadHeader);
+        }
+        let crc = crc32_update(crc32(&lba[..HEADER_CRC_OFFSET]), &[0; 4]);
+        let crc = crc32_update(crc, &lba[HEADER_CRC_OFFSET + 4..header_size]);
+        if read_u32(&lba, HEADER_CRC_OFFSET) != crc {
+            return Err(GptError::BadHeaderCrc);
+        }
+
+        let header = GptHeader {
+            disk_guid: read_guid(&lba, 56),
+            first_usable_lba: read_u64(&lba, 40),
+            last_usable_lba: read_u64(&lba, 48),
+            entries_lba: read_u64(&lba, 72),
+            entry_count: read_u32(&lba, 80),
+            entry_size: read_u32(&lba, 84),
+        };
+        let entries_crc = read_u32(&lba, 88);
+        let entry_size = header.entry_size as usize;
+        // The last usable LBA must leave the size of any partition in range
+        let disk_end = header
+            .last_usable_lba
+            .checked_add(1)
+            .and_then(|lbas| lbas.checked_mul(LBA_SIZE as u64));
+        if read_u64(&lba, 24) != 1
+            || !entry_size.is_multiple_of(ENTRY_SIZE)
+            || !(entry_size / ENTRY_SIZE).is_power_of_two()
+            || disk_end.is_none()
+        {
+            return Err(GptError::BadHeader);
+        }
+        let gpt = Gpt { src, header };
+        let Some((start, len)) = gpt.entries() else {
+            return Err(GptError::BadHeader);
+        };
+
+        let mut crc = 0;
+        let mut done = 0;
+        while done < len {
+            let chunk = LBA_SIZE.min(len - done);
+            read_exact(src, start + done, &mut lba[..chunk])?;
+            crc = crc32_update(crc, &lba[..chunk]);
+            done += chunk;
+        }
+        if entries_crc != crc {
+            return Err(GptError::BadEntriesCrc);
+        }
+        return Ok(gpt);
+    }
+
+    /// Returns the header
+    pub fn header(&self) -> &GptHeader {
+        return &self.header;
+    }
+
+    /// Returns the offset and size of the partition entries in bytes, or
+    /// `None` if they'd end past the end of the address space
+    fn entries(&self) -> Option<(usize, usize)> {
+        let start = usize::try_from(self.header.entries_lba)
+            .ok()?
+            .checked_mul(LBA_SIZE)?;
+        let len =
+            (self.header.entry_count as usize).checked_mul(self.header.entry_size as usize)?;
+
+        start.checked_add(len)?;
+        return Some((start, len));
+    }
+
+    /// Reads entry `index`, returning `None` for an unused one
+    ///
+    /// `index` must be below the number of entries.
+    fn entry(&self, index: u32) -> Result<Option<Partition>, GptError> {
+        let (start, _) = self.entries().ok_or(GptError::BadHeader)?;
+        let mut raw = [0u8; ENTRY_SIZE];
+
+        read_exact(
+            self.src,
+            start + index as usize * self.header.entry_size as usize,
+            &mut raw,
+        )?;
+        let partition = Partition {
+            index,
+            type_guid: read_guid(&raw, 0),
+            guid: read_guid(&raw, 16),
+            first_lba: read_u64(&raw, 32),
+            last_lba: read_u64(&raw, 40),
+            attributes: read_u64(&raw, 48),
+        };
+        if partition.type_guid.is_nil() {
+            return Ok(None);
+        }
+        if partition.first_lba > partition.last_lba
+            || partition.first_lba < self.header.first_usable_lba
+            || partition.last_lba > self.header.last_usable_lba
+        {
+            return Err(GptError::BadEntry);
+        }
+        return Ok(Some(partition));
+    }
+
+    /// Returns the first used partition `matches` accepts
+    fn find(&self, matches: impl Fn(&Partition) -> bool) -> Result<Option<Partition>, GptError> {
+        for index in 0..self.header.entry_count {
+            if let Some(partition) = self.entry(index)?
+                && matches(&partition)
+            {
+                return Ok(Some(partition));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Returns the first partition of type `type_guid`
+    pub fn find_by_type(&self, type_guid: Guid) -> Result<Option<Partition>, GptError> {
+        return self.find(|partition| partition.type_guid == type_guid);
+    }
+
+    /// Returns the partition whose own GUID is `guid`
+    pub fn find_by_guid(&self, guid: Guid) -> Result<Option<Partition>, GptError> {
+        return self.find(|partition| partition.guid == guid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::image::MemorySource;
+    use crate::utilities::guid::{EFI_SYSTEM, LINUX_FILESYSTEM, LINUX_ROOT_ARM64, guid};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Number of LBAs of the test disk
+    const DISK_LBAS: usize = 64;
+    /// Number of partition entries of the test disk, filling one LBA
+    const ENTRIES: u32 = 4;
+    /// GUID of the test disk's root partition
+    const ROOT: Guid = guid("6a9c4a38-5b17-4e3c-9d1c-0a4d2c6e8f10");
+
+    /// Writes `value` at `off` of `disk`
+    fn put(disk: &mut [u8], off: usize, value: &[u8]) {
+        disk[off..off + value.len()].copy_from_slice(value);
+    }
+
+    /// Recomputes the CRCs of the test disk's header and entries
+    fn seal(disk: &mut [u8]) {
+        let entries = &disk[2 * LBA_SIZE..3 * LBA_SIZE];
+        let crc = crc32(entries);
+        put(disk, LBA_SIZE + 88, &crc.to_le_bytes());
+        put(disk, LBA_SIZE + HEADER_CRC_OFFSET, &[0; 4]);
+        let crc = crc32(&disk[LBA_SIZE..LBA_SIZE + GPT_HEADER_SIZE]);
+        put(disk, LBA_SIZE + HEADER_CRC_OFFSET, &crc.to_le_bytes());
+    }
+
+    /// Builds a disk with an EFI System Partition in entry 0 and an AArch64
+    /// root partition in entry 2, the others unused
+    fn disk() -> Vec<u8> {
+        let mut disk = vec![0u8; DISK_LBAS * LBA_SIZE];
+        let header = LBA_SIZE;
+        let entries = 2 * LBA_SIZE;
+
+        put(&mut disk, header, &GPT_SIGNATURE);
+        put(&mut disk, header + 8, &GPT_REVISION.to_le_bytes());
+        put(
+            &mut disk,
+            header + 12,
+            &(GPT_HEADER_SIZE as u32).to_le_bytes(),
+        );
+        put(&mut disk, header + 24, &1u64.to_le_bytes());
+        put(
+            &mut disk,
+            header + 32,
+            &(DISK_LBAS as u64 - 1).to_le_bytes(),
+        );
+        put(&mut disk, header + 40, &3u64.to_le_bytes());
+        put(
+            &mut disk,
+            header + 48,
+            &(DISK_LBAS as u64 - 2).to_le_bytes(),
+        );
+        put(&mut disk, header + 72, &2u64.to_le_bytes());
+        put(&mut disk, header + 80, &ENTRIES.to_le_bytes());
+        put(&mut disk, header + 84, &(ENTRY_SIZE as u32).to_le_bytes());
+
+        // The EFI System Partition type as GPT stores it
+        put(
+            &mut disk,
+            entries,
+            &[
+                0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e,
+                0xc9, 0x3b,
+            ],
+        );
+        put(&mut disk, entries + 32, &3u64.to_le_bytes());
+        put(&mut disk, entries + 40, &10u64.to_le_bytes());
+
+        let root = entries + 2 * ENTRY_SIZE;
+        put(&mut disk, root, &LINUX_ROOT_ARM64.to_mixed_endian());
+        put(&mut disk, root + 16, &ROOT.to_mixed_endian());
+        put(&mut disk, root + 32, &11u64.to_le_bytes());
+        put(&mut disk, root + 40, &(DISK_LBAS as u64 - 2).to_le_bytes());
+        put(&mut disk, root + 48, &(1u64 << 60).to_le_bytes());
+
+        seal(&mut disk);
+        return disk;
+    }
+
+    /// Returns a source serving `disk`
+    fn source(disk: &[u8]) -> MemorySource {
+        return unsafe { MemorySource::new(disk.as_ptr() as usize, disk.len()) };
+    }
+
+    #[test]
+    fn find_by_type() {
+        let disk = disk();
+        let src = source(&disk);
+        let gpt = Gpt::read(&src).unwrap();
+
+        let esp = gpt.find_by_type(EFI_SYSTEM).unwrap().unwrap();
+        assert_eq!(esp.index, 0);
+        assert_eq!(esp.guid, Guid::NIL);
+        assert_eq!(esp.offset(), 3 * LBA_SIZE as u64);
+        assert_eq!(esp.size(), 8 * LBA_SIZE as u64);
+
+        let root = gpt.find_by_type(LINUX_ROOT_ARM64).unwrap().unwrap();
+        assert_eq!(root.index, 2);
+        assert_eq!(root.guid, ROOT);
+        assert_eq!(root.attributes, 1 << 60);
+
+        assert_eq!(gpt.find_by_type(LINUX_FILESYSTEM), Ok(None));
+    }
+
+    #[test]
+    fn find_by_guid() {
+        let disk = disk();
+        let src = source(&disk);
+        let gpt = Gpt::read(&src).unwrap();
+
+        let root = gpt.find_by_guid(ROOT).unwrap().unwrap();
+        assert_eq!(root.type_guid, LINUX_ROOT_ARM64);
+        assert_eq!(root.first_lba, 11);
+        assert_eq!(gpt.find_by_guid(EFI_SYSTEM), Ok(None));
+        assert_eq!(gpt.header().entry_count, ENTRIES);
+    }
+
+    #[test]
+    fn corrupted_disks() {
+        let read = |disk: &[u8]| Gpt::read(&source(disk)).map(|gpt| *gpt.header());
+
+        let mut bad = disk();
+        bad[LBA_SIZE] = b'e';
+        assert_eq!(read(&bad), Err(GptError::BadSignature));
+
+        let mut bad = disk();
+        bad[LBA_SIZE + 56] ^= 1;
+        assert_eq!(read(&bad), Err(GptError::BadHeaderCrc));
+
+        let mut bad = disk();
+        bad[2 * LBA_SIZE + 40] ^= 1;
+        assert_eq!(read(&bad), Err(GptError::BadEntriesCrc));
+
+        let mut bad = disk();
+        put(
+            &mut bad,
+            LBA_SIZE + 84,
+            &(3 * ENTRY_SIZE as u32).to_le_bytes(),
+        );
+        seal(&mut bad);
+        assert_eq!(read(&bad), Err(GptError::BadHeader));
+
+        let mut bad = disk();
+        put(&mut bad, LBA_SIZE + 72, &(u64::MAX / 2).to_le_bytes());
+        seal(&mut bad);
+        assert_eq!(read(&bad), Err(GptError::BadHeader));
+
+        assert_eq!(read(&disk()[..2 * LBA_SIZE]), Err(GptError::Truncated));
+    }
+
+    #[test]
+    fn partition_outside_usable_lbas() {
+        let mut disk = disk();
+        put(
+            &mut disk,
+            2 * LBA_SIZE + 2 * ENTRY_SIZE + 40,
+            &(DISK_LBAS as u64).to_le_bytes(),
+        );
+        seal(&mut disk);
+        let src = source(&disk);
+        let gpt = Gpt::read(&src).unwrap();
+
+        assert_eq!(
+            gpt.find_by_type(EFI_SYSTEM).map(|p| p.map(|p| p.index)),
+            Ok(Some(0))
+        );
+        assert_eq!(gpt.find_by_guid(ROOT), Err(GptError::BadEntry));
+    }
+}
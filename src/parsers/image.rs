@@ -0,0 +1,118 @@
+//! Image sources
+//!
+//! Loaders read the image they parse through an [`ImageSource`], by offset,
+//! instead of assuming it sits whole in memory. [`MemorySource`] serves an
+//! image staged in RAM; [`Lz77Source`] decompresses one as it's read, so a
+//! compressed kernel is decoded straight into its segments without a
+//! buffer for the whole decompressed file. A source may return fewer bytes
+//! than asked for, so one backed by a block device can serve a block per
+//! read.
+
+use crate::utilities::lz77::Lz77Reader;
+
+use core::cell::RefCell;
+use core::ptr;
+
+/// Length reported by sources that can't tell it without reading everything
+pub const UNKNOWN_LEN: usize = usize::MAX;
+
+/// Random-access reader of an image
+///
+/// Reads may be short, as from a device returning one block at a time:
+/// callers loop until they have what they need, and take a read of nothing
+/// as the end of the image.
+pub trait ImageSource {
+    /// Reads the bytes at offset `off` of the image into the start of `buf`
+    ///
+    /// Returns how many were read: at least one unless `buf` is empty or
+    /// `off` is at or past the end of the image.
+    fn read_at(&self, off: usize, buf: &mut [u8]) -> usize;
+
+    /// Returns the size of the image, or [`UNKNOWN_LEN`]
+    fn len(&self) -> usize;
+
+    /// Checks whether the image is known to be empty
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+/// Image staged in memory
+#[derive(Clone, Copy, Debug)]
+pub struct MemorySource {
+    /// Address of the first byte of the image
+    base: usize,
+    /// Size of the image, or [`UNKNOWN_LEN`]
+    len: usize,
+}
+
+impl MemorySource {
+    /// Creates a source for the `len` bytes at `base`
+    ///
+    /// `len` is [`UNKNOWN_LEN`] when the size of a staged image wasn't
+    /// recorded; the image then ends wherever its headers say.
+    ///
+    /// # Safety
+    ///
+    /// Every offset the loader reads below `len` must be readable memory
+    /// from `base`.
+    pub const unsafe fn new(base: usize, len: usize) -> Self {
+        return Self { base, len };
+    }
+}
+
+impl ImageSource for MemorySource {
+    fn read_at(&self, off: usize, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.len.saturating_sub(off));
+
+        unsafe {
+            ptr::copy_nonoverlapping((self.base + off) as *const u8, buf.as_mut_ptr(), count);
+        }
+        return count;
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+}
+
+/// Image compressed in the [`lz77`](crate::utilities::lz77) format
+///
+/// Reads at increasing offsets decode the stream once, front to back. A
+/// read before the last one decodes again from the start, so loaders should
+/// read their headers first and their data in file order. The decompressed
+/// size isn't stored in the stream, so [`len`](ImageSource::len) is
+/// [`UNKNOWN_LEN`].
+pub struct Lz77Source<'a> {
+    /// Decompressor, positioned at the end of the last read
+    reader: RefCell<Lz77Reader<'a>>,
+}
+
+impl<'a> Lz77Source<'a> {
+    /// Creates a source decompressing `compressed`
+    pub const fn new(compressed: &'a [u8]) -> Self {
+        return Self {
+            reader: RefCell::new(Lz77Reader::new(compressed)),
+        };
+    }
+}
+
+impl ImageSource for Lz77Source<'_> {
+    fn read_at(&self, off: usize, buf: &mut [u8]) -> usize {
+        let mut reader = self.reader.borrow_mut();
+
+        if off < reader.position() {
+            reader.rewind();
+        }
+        while reader.position() < off {
+            if reader.next_byte().is_none() {
+                return 0;
+            }
+        }
+        return reader.read(buf);
+    }
+
+    fn len(&self) -> usize {
+        return UNKNOWN_LEN;
+    }
+}
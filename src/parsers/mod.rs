@@ -4,4 +4,42 @@
 //! needs to understand. Each parser provides validation and loading functionality
 //! for its respective format.
 
+use crate::selftest::{self, SelfTest};
+
 pub mod elf;
+pub mod fdt;
+pub mod gpt;
+pub mod image;
+pub mod stage2;
+
+/// Registers the self-tests of the parsers
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"elf",
+        run: elf::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"fdt",
+        run: fdt::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"fdt-lookup",
+        run: fdt::selftest_lookup,
+    });
+    selftest::register(SelfTest {
+        name: b"fdt-corrupt",
+        run: fdt::selftest_corrupt,
+    });
+    selftest::register(SelfTest {
+        name: b"fdt-dump",
+        run: fdt::selftest_dump,
+    });
+    selftest::register(SelfTest {
+        name: b"fdt-synth",
+        run: fdt::selftest_synthesize,
+    });
+    selftest::register(SelfTest {
+        name: b"stage2",
+        run: stage2::selftest,
+    });
+}
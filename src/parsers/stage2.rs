@@ -0,0 +1,258 @@
+//! Stage-2 image header
+//!
+//! In a two-stage boot, a small first stage built with the `stage1` feature
+//! runs from ROM or on-chip SRAM, loads the full bootloader from flash and
+//! jumps to it. The full bootloader, with the kernel appended if it's booted
+//! that way, is stored behind a header telling the first stage where it goes
+//! and letting it check the image arrived intact:
+//!
+//! | Offset | Size | Field                                   |
+//! |-------:|-----:|-----------------------------------------|
+//! |      0 |    8 | Magic, `AA64STG2`                       |
+//! |      8 |    4 | Version, 1                              |
+//! |     12 |    4 | Size of the header, 48                  |
+//! |     16 |    8 | Load address                            |
+//! |     24 |    8 | Entry point, within the loaded image    |
+//! |     32 |    8 | Size of the image following the header  |
+//! |     40 |    4 | CRC-32 of the image                     |
+//! |     44 |    4 | CRC-32 of the 44 bytes before           |
+//!
+//! Fields are little-endian. [`build_stage2_header`] creates a header, as
+//! `cargo xtask stage2-header` does on the host to put one in front of
+//! `bootloader.bin`. [`parse`] checks a header and [`load`] copies the image
+//! it describes to its load address, checking the copy.
+
+use crate::selftest::Outcome;
+use crate::utilities::crc32::crc32;
+
+use core::{ptr, slice};
+
+/// Magic number starting a stage-2 header
+pub const STAGE2_MAGIC: [u8; 8] = *b"AA64STG2";
+/// Version of the header format
+pub const STAGE2_VERSION: u32 = 1;
+/// Size of the header, which the image follows
+pub const STAGE2_HEADER_SIZE: usize = 48;
+/// Offset of the CRC of the header, which covers the bytes before it
+const HEADER_CRC_OFFSET: usize = 44;
+
+/// Errors reported when checking or loading a stage-2 image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage2Error {
+    /// Fewer bytes than a header
+    Truncated,
+    /// No stage-2 magic number
+    BadMagic,
+    /// Unknown version or header size
+    BadVersion,
+    /// The header is corrupted
+    BadHeaderCrc,
+    /// The entry point is outside the loaded image
+    BadEntry,
+    /// The loaded image doesn't match its CRC
+    BadCrc,
+}
+
+impl Stage2Error {
+    /// Returns a description of the error
+    pub fn message(&self) -> &'static [u8] {
+        return match self {
+            Stage2Error::Truncated => b"truncated stage-2 header",
+            Stage2Error::BadMagic => b"no stage-2 image",
+            Stage2Error::BadVersion => b"unsupported stage-2 header version",
+            Stage2Error::BadHeaderCrc => b"corrupted stage-2 header",
+            Stage2Error::BadEntry => b"stage-2 entry point outside the image",
+            Stage2Error::BadCrc => b"stage-2 image CRC mismatch",
+        };
+    }
+}
+
+/// Fields of a stage-2 header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stage2Header {
+    /// Address the image is copied to
+    pub load_addr: u64,
+    /// Address jumped to once the image is loaded
+    pub entry: u64,
+    /// Size of the image in bytes
+    pub size: u64,
+    /// CRC-32 of the image
+    pub crc32: u32,
+}
+
+impl Stage2Header {
+    /// Returns the header in its stored form
+    pub fn to_bytes(&self) -> [u8; STAGE2_HEADER_SIZE] {
+        let mut bytes = [0u8; STAGE2_HEADER_SIZE];
+
+        bytes[0..8].copy_from_slice(&STAGE2_MAGIC);
+        bytes[8..12].copy_from_slice(&STAGE2_VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&(STAGE2_HEADER_SIZE as u32).to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.load_addr.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.entry.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.size.to_le_bytes());
+        bytes[40..44].copy_from_slice(&self.crc32.to_le_bytes());
+        let crc = crc32(&bytes[..HEADER_CRC_OFFSET]);
+        bytes[HEADER_CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        return bytes;
+    }
+
+    /// Returns the address past the end of the loaded image, or `None` if
+    /// it would wrap around
+    fn load_end(&self) -> Option<u64> {
+        return self.load_addr.checked_add(self.size);
+    }
+}
+
+/// Returns the header of `image`, to be loaded at `load_addr` and entered
+/// at `entry`
+pub fn build_stage2_header(image: &[u8], load_addr: u64, entry: u64) -> [u8; STAGE2_HEADER_SIZE] {
+    let header = Stage2Header {
+        load_addr,
+        entry,
+        size: image.len() as u64,
+        crc32: crc32(image),
+    };
+
+    return header.to_bytes();
+}
+
+/// Reads the little-endian `u32` at `off` of `bytes`
+fn read_u32(bytes: &[u8], off: usize) -> u32 {
+    let mut field = [0u8; 4];
+
+    field.copy_from_slice(&bytes[off..off + 4]);
+    return u32::from_le_bytes(field);
+}
+
+/// Reads the little-endian `u64` at `off` of `bytes`
+fn read_u64(bytes: &[u8], off: usize) -> u64 {
+    let mut field = [0u8; 8];
+
+    field.copy_from_slice(&bytes[off..off + 8]);
+    return u64::from_le_bytes(field);
+}
+
+/// Checks the stage-2 header at the start of `bytes` and returns its fields
+///
+/// The image itself isn't checked, as it may not be in `bytes`: [`load`]
+/// checks it once copied.
+pub fn parse(bytes: &[u8]) -> Result<Stage2Header, Stage2Error> {
+    if bytes.len() < STAGE2_HEADER_SIZE {
+        return Err(Stage2Error::Truncated);
+    }
+    if bytes[0..8] != STAGE2_MAGIC {
+        return Err(Stage2Error::BadMagic);
+    }
+    if read_u32(bytes, 8) != STAGE2_VERSION || read_u32(bytes, 12) as usize != STAGE2_HEADER_SIZE {
+        return Err(Stage2Error::BadVersion);
+    }
+    if read_u32(bytes, HEADER_CRC_OFFSET) != crc32(&bytes[..HEADER_CRC_OFFSET]) {
+        return Err(Stage2Error::BadHeaderCrc);
+    }
+
+    let header = Stage2Header {
+        load_addr: read_u64(bytes, 16),
+        entry: read_u64(bytes, 24),
+        size: read_u64(bytes, 32),
+        crc32: read_u32(bytes, 40),
+    };
+    match header.load_end() {
+        Some(end) if (header.load_addr..end).contains(&header.entry) => {}
+        _ => return Err(Stage2Error::BadEntry),
+    }
+    return Ok(header);
+}
+
+/// Copies the stage-2 image stored at `base`, behind its header, to its
+/// load address and checks it there
+///
+/// The CRC is computed over the copy, so a flash read or a copy gone wrong
+/// is caught alike. Returns the header, whose entry point is then safe to
+/// jump to.
+///
+/// # Safety
+///
+/// The header and image at `base` must be readable, and the memory at the
+/// load address they give writable and not in use, except by the image
+/// itself.
+pub unsafe fn load(base: usize) -> Result<Stage2Header, Stage2Error> {
+    let header = parse(unsafe { slice::from_raw_parts(base as *const u8, STAGE2_HEADER_SIZE) })?;
+    let (dest, size) = (header.load_addr as usize, header.size as usize);
+
+    let loaded = unsafe {
+        ptr::copy(
+            (base + STAGE2_HEADER_SIZE) as *const u8,
+            dest as *mut u8,
+            size,
+        );
+        slice::from_raw_parts(dest as *const u8, size)
+    };
+    if crc32(loaded) != header.crc32 {
+        return Err(Stage2Error::BadCrc);
+    }
+    return Ok(header);
+}
+
+/// Image of the self-test
+const TEST_IMAGE: &[u8] = b"stage 2 test image";
+/// Header of [`TEST_IMAGE`] loaded at 0x4008_0000 and entered 4 bytes in,
+/// as `cargo xtask stage2-header` makes it
+const TEST_HEADER: [u8; STAGE2_HEADER_SIZE] = [
+    0x41, 0x41, 0x36, 0x34, 0x53, 0x54, 0x47, 0x32, 0x01, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x08, 0x40, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x08, 0x40, 0x00, 0x00, 0x00, 0x00,
+    0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xc1, 0xcc, 0x0f, 0xd2, 0x5f, 0xdc, 0x6a,
+];
+
+/// Header and image staged by the self-test, as stored in flash
+static mut TEST_STAGED: [u8; STAGE2_HEADER_SIZE + TEST_IMAGE.len()] =
+    [0; STAGE2_HEADER_SIZE + TEST_IMAGE.len()];
+/// Where the self-test loads its image
+static mut TEST_DEST: [u8; TEST_IMAGE.len()] = [0; TEST_IMAGE.len()];
+
+/// Self-test: a header matches the one made on the host, an image loads
+/// to its address, and corrupted headers and images are refused
+pub fn selftest() -> Outcome {
+    let staged = &raw mut TEST_STAGED;
+    let dest = &raw mut TEST_DEST;
+    let (staged, dest) = unsafe { (&mut *staged, &mut *dest) };
+    let load_addr = dest.as_ptr() as u64;
+
+    if build_stage2_header(TEST_IMAGE, 0x4008_0000, 0x4008_0004) != TEST_HEADER {
+        return Outcome::Fail;
+    }
+    let header = build_stage2_header(TEST_IMAGE, load_addr, load_addr);
+    staged[..STAGE2_HEADER_SIZE].copy_from_slice(&header);
+    staged[STAGE2_HEADER_SIZE..].copy_from_slice(TEST_IMAGE);
+    let base = staged.as_ptr() as usize;
+    match unsafe { load(base) } {
+        Ok(loaded) if loaded.entry == load_addr && dest[..] == *TEST_IMAGE => {}
+        _ => return Outcome::Fail,
+    }
+
+    // A flipped image bit is caught after the copy, a flipped header bit
+    // before it
+    staged[STAGE2_HEADER_SIZE + 3] ^= 1;
+    if unsafe { load(base) } != Err(Stage2Error::BadCrc) {
+        return Outcome::Fail;
+    }
+    staged[20] ^= 1;
+    if unsafe { load(base) } != Err(Stage2Error::BadHeaderCrc) {
+        return Outcome::Fail;
+    }
+    let past_end = build_stage2_header(TEST_IMAGE, load_addr, load_addr + TEST_IMAGE.len() as u64);
+    let mut newer = TEST_HEADER;
+    newer[8] = 2;
+    let refused = [
+        (&TEST_HEADER[..40], Stage2Error::Truncated),
+        (&[0u8; STAGE2_HEADER_SIZE][..], Stage2Error::BadMagic),
+        (&newer[..], Stage2Error::BadVersion),
+        (&past_end[..], Stage2Error::BadEntry),
+    ];
+    for (bytes, error) in refused {
+        if parse(bytes) != Err(error) {
+            return Outcome::Fail;
+        }
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,186 @@
+//! Pointer authentication and branch target identification at handoff
+//!
+//! Kernels built with `-mbranch-protection` sign return addresses with
+//! PACIASP/AUTIASP (FEAT_PAuth) and start indirect branch targets with BTI
+//! instructions (FEAT_BTI). Their early code runs before they program
+//! SCTLR_EL1 and the keys, so what the bootloader leaves there decides
+//! whether those instructions are no-ops, work, or fault. [`prepare_handoff`]
+//! leaves a defined state for the payload, which runs at EL1:
+//!
+//! | Control                 | [`Policy::Disabled`] | [`Policy::Keys`] |
+//! |-------------------------|----------------------|------------------|
+//! | SCTLR_EL1.EnIA, EnIB    | 0                    | 1                |
+//! | APIAKey_EL1, APIBKey_EL1| unchanged            | random           |
+//! | SCTLR_EL1.EnDA, EnDB    | 0                    | 0                |
+//! | SCTLR_EL1.BT0, BT1      | 0                    | 0                |
+//! | HCR_EL2.API, APK        | 1                    | 1                |
+//!
+//! With EnIA and EnIB clear, PACIASP and AUTIASP are no-ops, so a signed
+//! return can't fail before the payload sets its own keys. HCR_EL2.API and
+//! APK are set either way, so pointer authentication instructions and key
+//! accesses at EL1 don't trap to the bootloader (EC 0x09). BT0 and BT1 are
+//! clear, so PACIASP and PACIBSP stay valid landing pads for every branch
+//! type; BTI is only enforced in guarded pages, which exist once the payload
+//! turns on its MMU, and it sets those bits then. On cores without a
+//! feature, its bits are RES0 and left alone.
+//!
+//! The policy defaults to [`Policy::Keys`] when built with the `pauth-keys`
+//! feature and [`Policy::Disabled`] otherwise; the monitor's `pauth` command
+//! changes it for the current boot.
+
+use crate::cpu;
+use crate::log::{self, Level};
+use crate::utilities::rng;
+
+use crate::asm;
+
+/// SCTLR_EL1.EnIA: instruction addresses authenticated with the A key
+const SCTLR_ENIA: u64 = 1 << 31;
+/// SCTLR_EL1.EnIB: instruction addresses authenticated with the B key
+const SCTLR_ENIB: u64 = 1 << 30;
+/// SCTLR_EL1.EnDA: data addresses authenticated with the A key
+const SCTLR_ENDA: u64 = 1 << 27;
+/// SCTLR_EL1.EnDB: data addresses authenticated with the B key
+const SCTLR_ENDB: u64 = 1 << 13;
+/// SCTLR_EL1.BT0: PACIxSP isn't a landing pad for every branch at EL0
+const SCTLR_BT0: u64 = 1 << 35;
+/// SCTLR_EL1.BT1: PACIxSP isn't a landing pad for every branch at EL1
+const SCTLR_BT1: u64 = 1 << 36;
+/// HCR_EL2.APK: key registers accessible from EL1
+const HCR_APK: u64 = 1 << 40;
+/// HCR_EL2.API: pointer authentication instructions usable at EL1
+const HCR_API: u64 = 1 << 41;
+
+/// Pointer authentication state handed to the payload
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Instruction address authentication disabled
+    Disabled,
+    /// Random APIA and APIB keys, instruction address authentication enabled
+    Keys,
+}
+
+/// Policy applied when the feature doesn't say otherwise
+#[cfg(not(feature = "pauth-keys"))]
+const DEFAULT_POLICY: Policy = Policy::Disabled;
+/// Policy applied when the feature doesn't say otherwise
+#[cfg(feature = "pauth-keys")]
+const DEFAULT_POLICY: Policy = Policy::Keys;
+
+/// Policy applied by [`prepare_handoff`]
+static mut POLICY: Policy = DEFAULT_POLICY;
+
+/// Returns the policy applied at handoff
+pub fn policy() -> Policy {
+    return unsafe { POLICY };
+}
+
+/// Sets the policy applied at handoff
+pub fn set_policy(policy: Policy) {
+    unsafe {
+        POLICY = policy;
+    }
+}
+
+/// Returns the SCTLR_EL1 bits to set and to clear for `policy`, given the
+/// features of the core
+const fn sctlr_bits(policy: Policy, pauth: bool, bti: bool) -> (u64, u64) {
+    let mut set = 0;
+    let mut clear = 0;
+
+    if pauth {
+        clear |= SCTLR_ENDA | SCTLR_ENDB;
+        match policy {
+            Policy::Disabled => clear |= SCTLR_ENIA | SCTLR_ENIB,
+            Policy::Keys => set |= SCTLR_ENIA | SCTLR_ENIB,
+        }
+    }
+    if bti {
+        clear |= SCTLR_BT0 | SCTLR_BT1;
+    }
+    return (set, clear);
+}
+
+const _: () = assert!(sctlr_bits(Policy::Keys, false, false).0 == 0);
+const _: () = assert!(sctlr_bits(Policy::Keys, false, false).1 == 0);
+const _: () = assert!(sctlr_bits(Policy::Keys, true, false).0 == SCTLR_ENIA | SCTLR_ENIB);
+const _: () = assert!(sctlr_bits(Policy::Disabled, true, false).0 == 0);
+const _: () = assert!(sctlr_bits(Policy::Disabled, true, false).1 & SCTLR_ENIA != 0);
+const _: () = assert!(sctlr_bits(Policy::Keys, true, true).1 & (SCTLR_BT0 | SCTLR_BT1) != 0);
+const _: () = {
+    let (set, clear) = sctlr_bits(Policy::Keys, true, true);
+    assert!(set & clear == 0);
+};
+
+/// Returns a random key half
+///
+/// Without RNDR, the counter is the only entropy: the keys differ between
+/// boots but can be guessed, which the payload replacing them makes
+/// harmless.
+fn key_half() -> u64 {
+    return rng::random_u64().unwrap_or_else(rng::counter_entropy);
+}
+
+/// Programs random APIA and APIB keys
+fn program_keys() {
+    let keys = [key_half(), key_half(), key_half(), key_half()];
+
+    // APIAKeyLo/Hi_EL1 and APIBKeyLo/Hi_EL1, spelled out for assemblers
+    // without FEAT_PAuth
+    unsafe {
+        asm!(
+            "msr s3_0_c2_c1_0, {}",
+            "msr s3_0_c2_c1_1, {}",
+            "msr s3_0_c2_c1_2, {}",
+            "msr s3_0_c2_c1_3, {}",
+            in(reg) keys[0],
+            in(reg) keys[1],
+            in(reg) keys[2],
+            in(reg) keys[3],
+            options(nomem, nostack),
+        );
+    }
+}
+
+/// Sets up pointer authentication and BTI for the payload, as described in
+/// the [module documentation](self)
+pub fn prepare_handoff() {
+    let pauth = cpu::has_pauth();
+    let bti = cpu::has_bti();
+    let policy = policy();
+    let (set, clear) = sctlr_bits(policy, pauth, bti);
+    let mut sctlr: u64;
+
+    if !pauth && !bti {
+        return;
+    }
+    if pauth && policy == Policy::Keys {
+        program_keys();
+    }
+    unsafe {
+        asm!("mrs {}, sctlr_el1", out(reg) sctlr, options(nomem, nostack));
+        sctlr = (sctlr & !clear) | set;
+        asm!("msr sctlr_el1, {}", "isb", in(reg) sctlr, options(nostack));
+        if pauth && cpu::current_el() == 2 {
+            asm!(
+                "mrs {tmp}, hcr_el2",
+                "orr {tmp}, {tmp}, {bits}",
+                "msr hcr_el2, {tmp}",
+                "isb",
+                tmp = out(reg) _,
+                bits = in(reg) HCR_API | HCR_APK,
+                options(nostack),
+            );
+        }
+    }
+
+    if pauth {
+        match policy {
+            Policy::Disabled => log::println(Level::Info, b"Pointer authentication: disabled"),
+            Policy::Keys => log::println(Level::Info, b"Pointer authentication: random keys"),
+        }
+    }
+    if bti {
+        log::println(Level::Info, b"BTI: PACIxSP accepted as landing pads");
+    }
+}
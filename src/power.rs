@@ -0,0 +1,212 @@
+//! Power management of the boot core
+//!
+//! The bootloader sometimes only waits for the user, e.g. during the
+//! autoboot countdown (see [`monitor::countdown`](crate::monitor::countdown)).
+//! [`idle`] lets the boot core sleep until the next interrupt meanwhile
+//! instead of spinning: in a shallow idle state entered with PSCI
+//! CPU_SUSPEND, or with [`idle_wfi`] when there is no firmware to ask.
+//!
+//! Only standby (retention) states are used. The core keeps its context in
+//! them and CPU_SUSPEND returns like a `wfi` once an interrupt is pending, so
+//! the generic timer tick, and the heartbeat and countdown driven by the
+//! counter, go on across the suspend. The state is the first one of the
+//! `/cpus/idle-states` node of the DTB that is a standby state and keeps the
+//! local timer running, or the core standby state, power state 0, if the DTB
+//! describes none. If it describes only states that lose the context or
+//! stop the timer, the core idles with `wfi`.
+//!
+//! A PSCI error isn't reported: CPU_SUSPEND is left alone for the rest of
+//! the boot and `wfi` used instead.
+//!
+//! The board can also drop its clocks and voltages around slow phases, see
+//! [`board::set_boot_performance`].
+
+use crate::board::{self, heartbeat};
+use crate::cpu;
+use crate::drivers::timer::generic;
+use crate::exception::Conduit;
+use crate::parsers::fdt::Fdt;
+use crate::selftest::{self, Outcome, SelfTest};
+
+use crate::asm;
+
+/// CPU_SUSPEND function ID, 64-bit convention
+const PSCI_CPU_SUSPEND_64: u32 = 0xc400_0001;
+/// PSCI return code: success
+const PSCI_SUCCESS: u64 = 0;
+/// Power state parameter: StateType bit of the original format, set for
+/// powerdown states
+const POWER_STATE_TYPE: u32 = 1 << 16;
+/// Power state parameter: StateType bit of the extended format, set for
+/// powerdown states
+const POWER_STATE_TYPE_EXTENDED: u32 = 1 << 30;
+/// Power state of the core standby state, used without idle states in the
+/// DTB
+const DEFAULT_SUSPEND_PARAM: u32 = 0;
+
+/// Power state CPU_SUSPEND is called with, or `None` to idle with `wfi`
+static mut SUSPEND_PARAM: Option<u32> = Some(DEFAULT_SUSPEND_PARAM);
+
+/// Checks whether an idle state with power state `param` that does or
+/// doesn't stop the local timer can be used by [`idle`]
+///
+/// The StateType bit isn't at the same place in both formats of the power
+/// state, so a state is taken for a powerdown one if either is set.
+const fn is_usable(param: Option<u32>, timer_stops: bool) -> bool {
+    return match param {
+        Some(param) => param & (POWER_STATE_TYPE | POWER_STATE_TYPE_EXTENDED) == 0 && !timer_stops,
+        None => false,
+    };
+}
+
+const _: () = assert!(is_usable(Some(0x0000_0001), false));
+const _: () = assert!(!is_usable(Some(0x0001_0000), false));
+const _: () = assert!(!is_usable(Some(0x4000_0000), false));
+const _: () = assert!(!is_usable(Some(0), true));
+const _: () = assert!(!is_usable(None, false));
+
+/// Returns the power state [`idle`] suspends the core with, from the idle
+/// states of `tree`
+///
+/// The first `arm,idle-state` node whose `arm,psci-suspend-param` is usable
+/// (see [`is_usable`]) is taken. Returns [`DEFAULT_SUSPEND_PARAM`] if the
+/// DTB describes no idle state, and `None` if none of those it describes is
+/// usable.
+fn fdt_suspend_param(tree: &Fdt) -> Option<u32> {
+    let mut described = false;
+    let found = tree.find_node(|node| {
+        if !node.is_compatible(b"arm,idle-state") {
+            return false;
+        }
+        described = true;
+        return is_usable(
+            node.property_u32(b"arm,psci-suspend-param"),
+            node.property(b"local-timer-stop").is_some(),
+        );
+    });
+
+    return match found {
+        Ok(Some(node)) => node.property_u32(b"arm,psci-suspend-param"),
+        _ if described => None,
+        _ => Some(DEFAULT_SUSPEND_PARAM),
+    };
+}
+
+/// Chooses how [`idle`] suspends the core, from the idle states of `tree`
+///
+/// At EL3 there is no firmware to ask, so the core idles with `wfi`.
+pub fn init(tree: Option<&Fdt>) {
+    let param = match tree {
+        _ if cpu::current_el() == 3 => None,
+        Some(tree) => fdt_suspend_param(tree),
+        None => Some(DEFAULT_SUSPEND_PARAM),
+    };
+
+    unsafe {
+        SUSPEND_PARAM = param;
+    }
+}
+
+/// Returns the power state [`idle`] suspends the core with, or `None` if it
+/// idles with `wfi`
+pub fn suspend_param() -> Option<u32> {
+    return unsafe { SUSPEND_PARAM };
+}
+
+/// Waits for an interrupt with `wfi`
+///
+/// Pending memory accesses complete first. The core wakes up on any
+/// pending interrupt, even one masked in PSTATE.
+pub fn idle_wfi() {
+    unsafe {
+        asm!("dsb sy", "wfi", options(nostack));
+    }
+}
+
+/// Suspends the core in the standby state `param` with PSCI CPU_SUSPEND,
+/// through the board's conduit, and returns the PSCI result
+///
+/// No entry point is needed: a standby state returns from the call.
+fn cpu_suspend(param: u32) -> u64 {
+    let result: u64;
+
+    unsafe {
+        match board::config().psci_conduit() {
+            Conduit::Smc => asm!(
+                "smc #0",
+                inlateout("x0") PSCI_CPU_SUSPEND_64 as u64 => result,
+                inlateout("x1") param as u64 => _,
+                inlateout("x2") 0u64 => _,
+                inlateout("x3") 0u64 => _,
+                clobber_abi("C"),
+            ),
+            Conduit::Hvc => asm!(
+                "hvc #0",
+                inlateout("x0") PSCI_CPU_SUSPEND_64 as u64 => result,
+                inlateout("x1") param as u64 => _,
+                inlateout("x2") 0u64 => _,
+                inlateout("x3") 0u64 => _,
+                clobber_abi("C"),
+            ),
+        }
+    }
+    return result;
+}
+
+/// Lets the boot core sleep until the next interrupt
+///
+/// Suspends it with CPU_SUSPEND, or [`idle_wfi`] if there's no usable idle
+/// state or CPU_SUSPEND failed, see the [module documentation](self).
+/// Returns at once unless the heartbeat's timer interrupt is running (see
+/// [`heartbeat::is_ticking`]): without a periodic interrupt nothing may ever
+/// wake the core. Callers check what they wait for and call it again.
+pub fn idle() {
+    if !heartbeat::is_ticking() {
+        return;
+    }
+    if let Some(param) = suspend_param()
+        && cpu_suspend(param) == PSCI_SUCCESS
+    {
+        return;
+    }
+    unsafe {
+        SUSPEND_PARAM = None;
+    }
+    idle_wfi();
+}
+
+/// Number of times the self-test idles
+const TEST_IDLES: u64 = 3;
+
+/// Self-test: the core comes back from each [`idle`] on the next timer
+/// tick, so the heartbeat keeps going and the counter keeps counting
+///
+/// Skipped if the heartbeat's timer interrupt isn't running.
+pub fn selftest() -> Outcome {
+    if !heartbeat::is_ticking() {
+        return Outcome::Skipped;
+    }
+
+    let (ticks, start) = (generic::tick_count(), generic::counter());
+    for _ in 0..TEST_IDLES {
+        idle();
+    }
+    let elapsed = generic::ticks_to_us(generic::counter() - start, generic::frequency());
+
+    // Every tick woke the core, and no wait lasted much past one step
+    if generic::tick_count() < ticks + TEST_IDLES
+        || elapsed > 2 * TEST_IDLES * heartbeat::STEP_US
+        || !heartbeat::is_ticking()
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Registers the self-test of the idle states
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"idle",
+        run: selftest,
+    });
+}
@@ -0,0 +1,373 @@
+//! Minimal PSCI responder for a payload at EL1
+//!
+//! When the bootloader stays resident at EL2 under its payload, it can
+//! answer the payload's PSCI calls itself, the way a hypervisor does.
+//! [`install_psci_trap`] sets HCR_EL2.TSC, so `smc` instructions at EL1 trap
+//! to the bootloader, and the lower-EL synchronous handler passes them to
+//! [`handle_call`]. [`dispatch`] decides what to do with each call:
+//!
+//! | Function      | Response                                           |
+//! |---------------|----------------------------------------------------|
+//! | PSCI_VERSION  | Emulated: version 0.2                              |
+//! | CPU_ON        | Forwarded, the core entering through a trampoline  |
+//! | SYSTEM_OFF    | Forwarded                                          |
+//! | SYSTEM_RESET  | Forwarded                                          |
+//! | Anything else | Emulated: `NOT_SUPPORTED`                          |
+//!
+//! The result goes back in x0 and the payload resumes after its `smc`.
+//! Forwarded calls are made again with `smc` from EL2, to the real firmware
+//! (QEMU's built-in PSCI on the `virt` machine).
+//!
+//! The firmware starts a core at the EL it was asked from, EL2 here, so
+//! CPU_ON is forwarded with `psci_cpu_entry` (transition.S) as the entry
+//! point. The trampoline gives the core the boot core's HCR_EL2, without
+//! the trap, and drops to the payload's entry point at EL1 with its context
+//! ID in x0. Secondary cores therefore make their PSCI calls straight to the
+//! firmware, and the bootloader needs no stack on them.
+//!
+//! Built with the `psci-responder` feature, the trap is installed right
+//! before the handoff.
+
+use crate::cpu;
+use crate::exception::{self, Conduit, PSCI_SYSTEM_OFF, Regs, SMCCC_OWNER_STANDARD, SmcInfo};
+use crate::log::{self, Level};
+use crate::selftest::{self, Outcome, SelfTest};
+
+use crate::asm;
+use core::mem::offset_of;
+
+/// PSCI_VERSION function ID
+pub const PSCI_VERSION: u32 = 0x8400_0000;
+/// CPU_ON function ID, 32-bit convention
+pub const PSCI_CPU_ON_32: u32 = 0x8400_0003;
+/// CPU_ON function ID, 64-bit convention
+pub const PSCI_CPU_ON_64: u32 = 0xc400_0003;
+/// SYSTEM_RESET function ID
+pub const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+/// CPU_OFF function ID, not supported by the responder
+const PSCI_CPU_OFF: u32 = 0x8400_0002;
+/// Version reported by PSCI_VERSION: 0.2, major in the top half
+pub const RESPONDER_VERSION: u64 = 0x0000_0002;
+/// PSCI return code: the function isn't implemented
+pub const PSCI_NOT_SUPPORTED: u64 = -1i64 as u64;
+/// PSCI return code: an argument is invalid, e.g. an unknown core
+pub const PSCI_INVALID_PARAMETERS: u64 = -2i64 as u64;
+/// Cores CPU_ON can start, by linear number (see
+/// [`mpidr_to_core_id`](cpu::mpidr_to_core_id)): two clusters
+pub const MAX_CORES: usize = 32;
+/// HCR_EL2.TSC: SMC instructions at EL1 trap to EL2
+const HCR_EL2_TSC: u64 = 1 << 19;
+
+/// What the responder does with a call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// Returns the value to the caller, without the firmware
+    Return(u64),
+    /// Makes the same call to the firmware and returns its result
+    Forward,
+    /// Starts core `core` through the firmware, at the trampoline, which
+    /// then enters `entry` at EL1 with `context` in x0
+    CpuOn {
+        /// Linear number of the core
+        core: usize,
+        /// MPIDR affinity fields of the core, as given by the caller
+        target: u64,
+        /// Entry point of the payload on the core
+        entry: u64,
+        /// Context ID passed to the entry point
+        context: u64,
+    },
+}
+
+/// Where a core started by CPU_ON goes once out of the trampoline
+///
+/// Read by `psci_cpu_entry` in transition.S, with the MMU off: the offsets
+/// are checked below.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CpuOnTarget {
+    /// Entry point of the payload
+    entry: u64,
+    /// Context ID, passed in x0
+    context: u64,
+    /// HCR_EL2 value of the core
+    hcr: u64,
+}
+
+const _: () = assert!(offset_of!(CpuOnTarget, entry) == 0);
+const _: () = assert!(offset_of!(CpuOnTarget, context) == 8);
+const _: () = assert!(offset_of!(CpuOnTarget, hcr) == 16);
+
+/// Entry point and context of the cores started by CPU_ON, by linear number
+static mut CPU_ON_TARGETS: [CpuOnTarget; MAX_CORES] = [CpuOnTarget {
+    entry: 0,
+    context: 0,
+    hcr: 0,
+}; MAX_CORES];
+/// Whether [`install_psci_trap`] was called
+static mut INSTALLED: bool = false;
+
+unsafe extern "C" {
+    /// Trampoline of the cores started by CPU_ON (provided by transition.S)
+    fn psci_cpu_entry();
+}
+
+/// Makes the payload's SMC calls trap to the bootloader, which answers them
+/// with [`handle_call`]
+///
+/// Only possible when the bootloader runs at EL2; otherwise a warning is
+/// printed and the calls keep going to the firmware.
+pub fn install_psci_trap() {
+    if cpu::current_el() != 2 {
+        log::println(Level::Warn, b"Not at EL2, PSCI calls go to the firmware");
+        return;
+    }
+    unsafe {
+        asm!(
+            "mrs {tmp}, hcr_el2",
+            "orr {tmp}, {tmp}, {tsc}",
+            "msr hcr_el2, {tmp}",
+            "isb",
+            tmp = out(reg) _,
+            tsc = in(reg) HCR_EL2_TSC,
+        );
+        INSTALLED = true;
+    }
+}
+
+/// Reads the CPU_ON arguments of `call`, of the width of its convention
+const fn cpu_on_args(call: &SmcInfo) -> (u64, u64, u64) {
+    let [target, entry, context, ..] = call.args;
+
+    if call.is_64() {
+        return (target, entry, context);
+    }
+    return (
+        target as u32 as u64,
+        entry as u32 as u64,
+        context as u32 as u64,
+    );
+}
+
+/// Decides how the responder answers `call`
+pub const fn dispatch(call: &SmcInfo) -> Response {
+    if !call.is_fast() || call.owner() != SMCCC_OWNER_STANDARD {
+        return Response::Return(PSCI_NOT_SUPPORTED);
+    }
+    return match call.function_id {
+        PSCI_VERSION => Response::Return(RESPONDER_VERSION),
+        PSCI_CPU_ON_32 | PSCI_CPU_ON_64 => {
+            let (target, entry, context) = cpu_on_args(call);
+            let core = cpu::mpidr_to_core_id(target);
+            if core >= MAX_CORES {
+                return Response::Return(PSCI_INVALID_PARAMETERS);
+            }
+            Response::CpuOn {
+                core,
+                target,
+                entry,
+                context,
+            }
+        }
+        PSCI_SYSTEM_OFF | PSCI_SYSTEM_RESET => Response::Forward,
+        _ => Response::Return(PSCI_NOT_SUPPORTED),
+    };
+}
+
+/// Answers the SMC or HVC call trapped from the payload, if the trap is
+/// installed
+///
+/// Returns `false` for any other exception, left to the other handlers.
+pub fn handle_call(regs: &mut Regs) -> bool {
+    if !unsafe { INSTALLED } {
+        return false;
+    }
+    return match exception::decode_smc(regs) {
+        Some(call) => {
+            respond(regs, &call);
+            true
+        }
+        None => false,
+    };
+}
+
+/// Answers `call` and moves the payload past it
+fn respond(regs: &mut Regs, call: &SmcInfo) {
+    regs.x0 = match dispatch(call) {
+        Response::Return(value) => value,
+        Response::Forward => forward(call.function_id, &call.args),
+        Response::CpuOn {
+            core,
+            target,
+            entry,
+            context,
+        } => cpu_on(core, target, entry, context),
+    };
+    // The ELR of a trapped SMC is the SMC itself, that of an HVC the next
+    // instruction
+    if call.conduit == Conduit::Smc {
+        regs.elr += 4;
+    }
+}
+
+/// Makes the call `function_id` with `args` to the firmware and returns its
+/// result
+fn forward(function_id: u32, args: &[u64; 6]) -> u64 {
+    let result: u64;
+
+    unsafe {
+        asm!(
+            "smc #0",
+            inlateout("x0") function_id as u64 => result,
+            inlateout("x1") args[0] => _,
+            inlateout("x2") args[1] => _,
+            inlateout("x3") args[2] => _,
+            inlateout("x4") args[3] => _,
+            inlateout("x5") args[4] => _,
+            inlateout("x6") args[5] => _,
+            clobber_abi("C"),
+            options(nostack),
+        );
+    }
+    return result;
+}
+
+/// Starts core `core` through the firmware, sending it to `entry` at EL1
+/// with `context`
+fn cpu_on(core: usize, target: u64, entry: u64, context: u64) -> u64 {
+    let targets = &raw mut CPU_ON_TARGETS;
+    let hcr: u64;
+
+    unsafe {
+        asm!("mrs {}, hcr_el2", out(reg) hcr);
+        (*targets)[core] = CpuOnTarget {
+            entry,
+            context,
+            hcr: hcr & !HCR_EL2_TSC,
+        };
+    }
+    let slot = unsafe { &raw const (*targets)[core] } as u64;
+    // The core reads its target with the MMU and caches off: write back
+    // the cache lines of its first and last bytes, which may differ
+    unsafe {
+        asm!(
+            "dc civac, {start}",
+            "dc civac, {end}",
+            "dsb sy",
+            start = in(reg) slot,
+            end = in(reg) slot + size_of::<CpuOnTarget>() as u64 - 1,
+            options(nostack),
+        );
+    }
+    return forward(
+        PSCI_CPU_ON_64,
+        &[target, psci_cpu_entry as *const () as u64, slot, 0, 0, 0],
+    );
+}
+
+/// Returns a fast call to the standard service `function_id` with `args`,
+/// made with `smc #0`
+const fn test_call(function_id: u32, args: [u64; 3]) -> SmcInfo {
+    return SmcInfo {
+        conduit: Conduit::Smc,
+        imm: 0,
+        function_id,
+        args: [args[0], args[1], args[2], 0, 0, 0],
+    };
+}
+
+// Each supported function ID
+const _: () = assert!(matches!(
+    dispatch(&test_call(PSCI_VERSION, [0; 3])),
+    Response::Return(RESPONDER_VERSION)
+));
+const _: () = assert!(matches!(
+    dispatch(&test_call(
+        PSCI_CPU_ON_64,
+        [0x101, 0x4020_0000, 0x1234_5678_9abc]
+    )),
+    Response::CpuOn {
+        core: 17,
+        target: 0x101,
+        entry: 0x4020_0000,
+        context: 0x1234_5678_9abc,
+    }
+));
+// The 32-bit convention ignores the upper halves of the arguments
+const _: () = assert!(matches!(
+    dispatch(&test_call(
+        PSCI_CPU_ON_32,
+        [0xffff_0000_0000_0003, 0x1_4020_0000, 0x1_0000_0042]
+    )),
+    Response::CpuOn {
+        core: 3,
+        target: 3,
+        entry: 0x4020_0000,
+        context: 0x42,
+    }
+));
+const _: () = assert!(matches!(
+    dispatch(&test_call(PSCI_SYSTEM_OFF, [0; 3])),
+    Response::Forward
+));
+const _: () = assert!(matches!(
+    dispatch(&test_call(PSCI_SYSTEM_RESET, [0; 3])),
+    Response::Forward
+));
+// A core past the table, another PSCI function, another service and a
+// yielding call
+const _: () = assert!(matches!(
+    dispatch(&test_call(PSCI_CPU_ON_64, [0x200, 0x4020_0000, 0])),
+    Response::Return(PSCI_INVALID_PARAMETERS)
+));
+const _: () = assert!(matches!(
+    dispatch(&test_call(PSCI_CPU_OFF, [0; 3])),
+    Response::Return(PSCI_NOT_SUPPORTED)
+));
+const _: () = assert!(matches!(
+    dispatch(&test_call(0x8000_0000, [0; 3])),
+    Response::Return(PSCI_NOT_SUPPORTED)
+));
+const _: () = assert!(matches!(
+    dispatch(&test_call(PSCI_VERSION & !(1 << 31), [0; 3])),
+    Response::Return(PSCI_NOT_SUPPORTED)
+));
+
+/// Registers the self-test of the responder
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"psci",
+        run: selftest,
+    });
+}
+
+/// Self-test: emulated calls return their result in x0, and the payload
+/// resumes after a trapped SMC but at the ELR of an HVC
+pub fn selftest() -> Outcome {
+    // EC 0x17 (SMC64) and 0x16 (HVC64), 32-bit instruction
+    let mut smc = Regs {
+        x0: PSCI_VERSION as u64,
+        esr: 0x5e00_0000,
+        elr: 0x4020_0000,
+        ..Regs::ZERO
+    };
+    let mut hvc = Regs {
+        x0: PSCI_CPU_OFF as u64,
+        esr: 0x5a00_0000,
+        ..smc
+    };
+
+    for regs in [&mut smc, &mut hvc] {
+        match exception::decode_smc(regs) {
+            Some(call) => respond(regs, &call),
+            None => return Outcome::Fail,
+        }
+    }
+    if (smc.x0, smc.elr) != (RESPONDER_VERSION, 0x4020_0004) {
+        return Outcome::Fail;
+    }
+    if (hvc.x0, hvc.elr) != (PSCI_NOT_SUPPORTED, 0x4020_0000) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,425 @@
+//! Command sequences
+//!
+//! The monitor runs each line it reads, and the `bootcmd` variable at
+//! autoboot, as a small script:
+//!
+//! - Commands are separated by `;`, which runs the next one regardless,
+//!   `&&`, which runs it only if the previous one succeeded, or `||`, which
+//!   runs it only if the previous one failed. A skipped command leaves the
+//!   status as it was, so `a && b || c` runs `c` when `a` fails.
+//! - `${name}` is replaced with the value of the
+//!   [environment variable](crate::env) `name`, or nothing if it isn't set;
+//!   the value is split into words but its `;`, `&&` and `||` aren't
+//!   operators (the monitor's `run <name>` runs a variable as a script).
+//! - Text between single quotes is one word, taken literally: spaces,
+//!   operators and `${...}` included.
+//!
+//! The whole script is checked before its first command runs; a syntax
+//! error is reported with its position in the script, and nothing runs.
+//! Only a value substituted into a command can still make it too long,
+//! which is reported when that command is reached.
+
+use crate::drivers::uart::pl011;
+use crate::env;
+use crate::selftest::{self, Outcome, SelfTest};
+
+/// Maximum number of words of a command, including its name
+pub const MAX_WORDS: usize = 8;
+/// Maximum number of bytes of the words of a command, after substitution
+pub const WORDS_SIZE: usize = 256;
+
+/// Outcome of a command run by a script
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The command succeeded
+    Ok,
+    /// The command failed
+    Failed,
+    /// The command asks to stop running commands (e.g., `boot`)
+    Leave,
+}
+
+/// What a syntax error is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxError {
+    /// A quote is never closed
+    UnterminatedQuote,
+    /// A `${` is never closed
+    UnterminatedVariable,
+    /// A `${...}` holds an invalid variable name
+    BadVariableName,
+    /// A single `&` or `|`
+    BadOperator,
+    /// `&&`, `||` or `;` without a command before it, or `&&` or `||`
+    /// without one after it
+    EmptyCommand,
+    /// A command has more than [`MAX_WORDS`] words
+    TooManyWords,
+    /// A command is longer than [`WORDS_SIZE`] bytes
+    TooLong,
+}
+
+impl SyntaxError {
+    /// Returns a description of the error
+    pub fn message(self) -> &'static [u8] {
+        return match self {
+            SyntaxError::UnterminatedQuote => b"unterminated quote",
+            SyntaxError::UnterminatedVariable => b"unterminated ${",
+            SyntaxError::BadVariableName => b"bad variable name",
+            SyntaxError::BadOperator => b"expected && or ||",
+            SyntaxError::EmptyCommand => b"missing command",
+            SyntaxError::TooManyWords => b"too many arguments",
+            SyntaxError::TooLong => b"command too long",
+        };
+    }
+}
+
+/// A syntax error and where it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    /// What is wrong
+    pub error: SyntaxError,
+    /// Offset in the script of the byte where it was found
+    pub position: usize,
+}
+
+impl ScriptError {
+    /// Prints `script` with a caret under the error, then the error
+    pub fn print(&self, script: &[u8]) {
+        pl011::print(b"  ");
+        pl011::println(script);
+        pl011::print(b"  ");
+        for _ in 0..self.position {
+            pl011::print(b" ");
+        }
+        pl011::println(b"^");
+        pl011::print(b"Syntax error: ");
+        pl011::println(self.error.message());
+    }
+}
+
+/// How the command following an operator depends on the status
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Connector {
+    /// `;` or start of the script: always runs
+    Always,
+    /// `&&`: runs if the status is a success
+    IfOk,
+    /// `||`: runs if the status is a failure
+    IfFailed,
+}
+
+/// The words of a command
+struct Words {
+    /// The bytes of the words, one after the other
+    buf: [u8; WORDS_SIZE],
+    /// Bytes used in `buf`
+    len: usize,
+    /// Where each word ends in `buf`
+    ends: [usize; MAX_WORDS],
+    /// Number of complete words
+    count: usize,
+    /// Whether a word is started and not ended yet
+    open: bool,
+}
+
+impl Words {
+    /// Creates an empty list
+    const fn new() -> Self {
+        return Self {
+            buf: [0; WORDS_SIZE],
+            len: 0,
+            ends: [0; MAX_WORDS],
+            count: 0,
+            open: false,
+        };
+    }
+
+    /// Appends `c` to the current word, starting one if needed
+    fn push(&mut self, c: u8) -> Result<(), SyntaxError> {
+        self.start()?;
+        if self.len == WORDS_SIZE {
+            return Err(SyntaxError::TooLong);
+        }
+        self.buf[self.len] = c;
+        self.len += 1;
+        return Ok(());
+    }
+
+    /// Starts a word if none is open, even if nothing is added to it
+    fn start(&mut self) -> Result<(), SyntaxError> {
+        if !self.open {
+            if self.count == MAX_WORDS {
+                return Err(SyntaxError::TooManyWords);
+            }
+            self.open = true;
+        }
+        return Ok(());
+    }
+
+    /// Ends the current word, if any
+    fn end(&mut self) {
+        if self.open {
+            self.ends[self.count] = self.len;
+            self.count += 1;
+            self.open = false;
+        }
+    }
+
+    /// Appends `text` as it would read unquoted: spaces separate words
+    fn push_split(&mut self, text: &[u8]) -> Result<(), SyntaxError> {
+        for &c in text {
+            match c {
+                b' ' | b'\t' => self.end(),
+                _ => self.push(c)?,
+            }
+        }
+        return Ok(());
+    }
+
+    /// Fills `args` with the words and returns how many there are
+    fn split<'a>(&'a self, args: &mut [&'a [u8]; MAX_WORDS]) -> usize {
+        let mut start = 0;
+
+        for (i, &end) in self.ends[..self.count].iter().enumerate() {
+            args[i] = &self.buf[start..end];
+            start = end;
+        }
+        return self.count;
+    }
+}
+
+/// Reads commands from a script one at a time
+struct Parser<'a> {
+    /// The script
+    script: &'a [u8],
+    /// Offset of the next byte to read
+    pos: usize,
+    /// Whether `${...}` is replaced with the variable's value
+    substitute: bool,
+}
+
+impl Parser<'_> {
+    /// Returns the error `error` at offset `position`
+    fn error(&self, error: SyntaxError, position: usize) -> ScriptError {
+        return ScriptError { error, position };
+    }
+
+    /// Reads the next command into `words`
+    ///
+    /// Returns the connector following the command, or `None` at the end
+    /// of the script.
+    fn command(&mut self, words: &mut Words) -> Result<Option<Connector>, ScriptError> {
+        let script = self.script;
+
+        *words = Words::new();
+        let connector = loop {
+            let Some(&c) = script.get(self.pos) else {
+                break None;
+            };
+            let at = self.pos;
+            let wrap = |e| ScriptError {
+                error: e,
+                position: at,
+            };
+            self.pos += 1;
+            match c {
+                b' ' | b'\t' => words.end(),
+                b';' => break Some(Connector::Always),
+                b'&' | b'|' => {
+                    if script.get(self.pos) != Some(&c) {
+                        return Err(self.error(SyntaxError::BadOperator, at));
+                    }
+                    self.pos += 1;
+                    break Some(match c {
+                        b'&' => Connector::IfOk,
+                        _ => Connector::IfFailed,
+                    });
+                }
+                b'\'' => {
+                    let Some(len) = script[self.pos..].iter().position(|&q| q == b'\'') else {
+                        return Err(self.error(SyntaxError::UnterminatedQuote, at));
+                    };
+                    words.start().map_err(wrap)?;
+                    for &q in &script[self.pos..self.pos + len] {
+                        words.push(q).map_err(wrap)?;
+                    }
+                    self.pos += len + 1;
+                }
+                b'$' if script.get(self.pos) == Some(&b'{') => {
+                    let name_start = self.pos + 1;
+                    let Some(len) = script[name_start..].iter().position(|&q| q == b'}') else {
+                        return Err(self.error(SyntaxError::UnterminatedVariable, at));
+                    };
+                    let name = &script[name_start..name_start + len];
+                    if !env::is_valid_name(name) {
+                        return Err(self.error(SyntaxError::BadVariableName, name_start));
+                    }
+                    self.pos = name_start + len + 1;
+                    if !self.substitute {
+                        words.start().map_err(wrap)?;
+                    } else if let Some(value) = env::get(name) {
+                        words.push_split(value).map_err(wrap)?;
+                    }
+                }
+                _ => words.push(c).map_err(wrap)?,
+            }
+        };
+        words.end();
+
+        // A command is missing before an operator. Checked without
+        // substitution: a variable counts as a word even if it turns out
+        // empty, so what passes the check also parses when run.
+        if !self.substitute && words.count == 0 && connector.is_some() {
+            return Err(self.error(SyntaxError::EmptyCommand, self.operator_start()));
+        }
+        return Ok(connector);
+    }
+
+    /// Returns the offset of the operator just read
+    fn operator_start(&self) -> usize {
+        return match self.script[self.pos - 1] {
+            b';' => self.pos - 1,
+            _ => self.pos - 2,
+        };
+    }
+}
+
+/// Checks the syntax of `script` without running anything
+///
+/// Variables aren't substituted, so only the script itself is checked.
+pub fn check(script: &[u8]) -> Result<(), ScriptError> {
+    let mut parser = Parser {
+        script,
+        pos: 0,
+        substitute: false,
+    };
+    let mut words = Words::new();
+    let mut after = Connector::Always;
+
+    loop {
+        match parser.command(&mut words)? {
+            Some(connector) => after = connector,
+            None => {
+                // The last command may only be missing after a `;`
+                if words.count == 0 && after != Connector::Always {
+                    return Err(parser.error(SyntaxError::EmptyCommand, script.len()));
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs `script`, passing each command's words to `run`
+///
+/// The script is [checked](check) first. Returns [`Status::Leave`] as soon
+/// as a command returns it; otherwise the status of the last command run,
+/// or [`Status::Ok`] if none ran.
+pub fn execute(
+    script: &[u8],
+    run: &mut dyn FnMut(&[&[u8]]) -> Status,
+) -> Result<Status, ScriptError> {
+    let mut parser = Parser {
+        script,
+        pos: 0,
+        substitute: true,
+    };
+    let mut words = Words::new();
+    let mut connector = Connector::Always;
+    let mut status = Status::Ok;
+
+    check(script)?;
+    loop {
+        let next = parser.command(&mut words)?;
+        let wanted = match connector {
+            Connector::Always => true,
+            Connector::IfOk => status == Status::Ok,
+            Connector::IfFailed => status == Status::Failed,
+        };
+        let mut args: [&[u8]; MAX_WORDS] = [&[]; MAX_WORDS];
+        let count = words.split(&mut args);
+        if wanted && count > 0 {
+            status = run(&args[..count]);
+            if status == Status::Leave {
+                return Ok(status);
+            }
+        }
+        match next {
+            Some(next) => connector = next,
+            None => return Ok(status),
+        }
+    }
+}
+
+/// Script run by [`selftest`], with the commands it should run
+const TEST_SCRIPT: &[u8] = b"ok a; fail && skipped || ok '${x} y' ${test_var};ok";
+/// Commands [`TEST_SCRIPT`] should run, with `test_var` set to `b  c`
+const TEST_RUN: [&[&[u8]]; 4] = [
+    &[b"ok", b"a"],
+    &[b"fail"],
+    &[b"ok", b"${x} y", b"b", b"c"],
+    &[b"ok"],
+];
+
+/// Scripts with a syntax error, and where it is
+const TEST_ERRORS: [(&[u8], SyntaxError, usize); 6] = [
+    (b"md 0 && && md 0", SyntaxError::EmptyCommand, 8),
+    (b"; md 0", SyntaxError::EmptyCommand, 0),
+    (b"md 0 ||", SyntaxError::EmptyCommand, 7),
+    (b"md 0 & md 0", SyntaxError::BadOperator, 5),
+    (b"echo 'a b", SyntaxError::UnterminatedQuote, 5),
+    (b"echo ${a-b}", SyntaxError::BadVariableName, 7),
+];
+
+/// Self-test: commands run depending on the status of the previous ones,
+/// with variables substituted, and syntax errors are found where they are
+pub fn selftest() -> Outcome {
+    let mut ran = 0;
+    let mut matched = true;
+
+    for (script, error, position) in TEST_ERRORS {
+        if check(script) != Err(ScriptError { error, position }) {
+            return Outcome::Fail;
+        }
+    }
+
+    if env::set(b"test_var", b"b  c").is_err() {
+        return Outcome::Skipped;
+    }
+    let result = execute(TEST_SCRIPT, &mut |args| {
+        matched &= TEST_RUN.get(ran) == Some(&args);
+        ran += 1;
+        return match args[0] {
+            b"fail" => Status::Failed,
+            _ => Status::Ok,
+        };
+    });
+    env::unset(b"test_var");
+
+    if result != Ok(Status::Ok) || !matched || ran != TEST_RUN.len() {
+        return Outcome::Fail;
+    }
+    // Nothing runs after a command leaving, nor in a script with an error
+    ran = 0;
+    let left = execute(b"boot; ok", &mut |_| {
+        ran += 1;
+        return Status::Leave;
+    });
+    let refused = execute(b"ok; ok &&", &mut |_| {
+        ran += 1;
+        return Status::Ok;
+    });
+    if left != Ok(Status::Leave) || refused.is_ok() || ran != 1 {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
+/// Registers the self-test of the script interpreter
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"script",
+        run: selftest,
+    });
+}
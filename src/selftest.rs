@@ -0,0 +1,245 @@
+//! Boot-time self-test suite
+//!
+//! Each driver provides a self-test and registers it with [`register`], so
+//! the suite matches the drivers compiled in. [`register_builtin`] registers
+//! the tests of every driver and subsystem of the bootloader.
+//!
+//! A test reports one [`Outcome`]: passed, failed, or skipped when the
+//! device it exercises is absent. [`run_all`] runs every test and prints a
+//! line per test and a summary; [`run_one`] runs a single test by name. The
+//! monitor runs them with `selftest [name]`.
+//!
+//! Built with the `selftest-exit` feature, the bootloader runs the suite
+//! right after preparing the boot and exits through semihosting with
+//! [`Summary::status`], so a CI job can run it headlessly in QEMU.
+
+use crate::boot;
+use crate::cpu;
+use crate::debug;
+use crate::drivers;
+use crate::drivers::uart::pl011;
+use crate::error;
+use crate::exception;
+use crate::log;
+use crate::memory;
+use crate::parsers;
+use crate::power;
+use crate::psci;
+#[cfg(not(feature = "stage1"))]
+use crate::script;
+use crate::serial;
+use crate::warmcache;
+use crate::utilities;
+use crate::utilities::print::u64_to_dec;
+
+/// Maximum number of registered self-tests
+pub const MAX_TESTS: usize = 64;
+
+/// Result of a self-test
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The device works as expected
+    Pass,
+    /// The device misbehaved
+    Fail,
+    /// The device is absent or not configured
+    Skipped,
+}
+
+impl Outcome {
+    /// Returns the name of the outcome, as printed in the results
+    pub fn name(self) -> &'static [u8] {
+        return match self {
+            Outcome::Pass => b"pass",
+            Outcome::Fail => b"FAIL",
+            Outcome::Skipped => b"skipped",
+        };
+    }
+}
+
+/// A self-test
+#[derive(Clone, Copy)]
+pub struct SelfTest {
+    /// Name used to select the test
+    pub name: &'static [u8],
+    /// Function running the test
+    pub run: fn() -> Outcome,
+}
+
+/// Results of a run of the suite
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of tests that passed
+    pub passed: u32,
+    /// Number of tests that failed
+    pub failed: u32,
+    /// Number of tests skipped
+    pub skipped: u32,
+}
+
+impl Summary {
+    /// Returns the aggregate status: 0 if no test failed, the number of
+    /// failed tests otherwise
+    pub fn status(&self) -> u32 {
+        return self.failed;
+    }
+
+    /// Counts `outcome`
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Pass => self.passed += 1,
+            Outcome::Fail => self.failed += 1,
+            Outcome::Skipped => self.skipped += 1,
+        }
+    }
+}
+
+/// Registered self-tests
+static mut TESTS: [Option<SelfTest>; MAX_TESTS] = [None; MAX_TESTS];
+
+/// Whether [`register_builtin`] already ran
+static mut BUILTIN_REGISTERED: bool = false;
+
+/// Whether a self-test was dropped because the table was full
+static mut OVERFLOWED: bool = false;
+
+/// Registers a self-test
+///
+/// Returns `false` if the table is full or a test with the same name is
+/// already registered. A full table also makes [`register_builtin`] panic.
+pub fn register(test: SelfTest) -> bool {
+    let tests = &raw mut TESTS;
+    let tests = unsafe { &mut *tests };
+
+    if tests.iter().flatten().any(|t| t.name == test.name) {
+        return false;
+    }
+    match tests.iter_mut().find(|t| t.is_none()) {
+        Some(slot) => {
+            *slot = Some(test);
+            return true;
+        }
+        None => {
+            unsafe {
+                OVERFLOWED = true;
+            }
+            return false;
+        }
+    }
+}
+
+/// Registers the self-tests of the drivers and subsystems compiled in
+///
+/// Calling it again does nothing. Panics if they don't all fit in the table,
+/// so a new test can't silently go missing from the suite.
+pub fn register_builtin() {
+    unsafe {
+        if BUILTIN_REGISTERED {
+            return;
+        }
+        BUILTIN_REGISTERED = true;
+    }
+    boot::register_selftests();
+    cpu::register_selftests();
+    debug::register_selftests();
+    drivers::register_selftests();
+    error::register_selftests();
+    exception::register_selftests();
+    log::register_selftests();
+    memory::register_selftests();
+    parsers::register_selftests();
+    power::register_selftests();
+    psci::register_selftests();
+    #[cfg(not(feature = "stage1"))]
+    script::register_selftests();
+    serial::register_selftests();
+    utilities::register_selftests();
+    warmcache::register_selftests();
+    if unsafe { OVERFLOWED } {
+        panic!("Self-test table full, raise MAX_TESTS");
+    }
+}
+
+/// Returns an iterator over the registered self-tests
+fn tests() -> impl Iterator<Item = SelfTest> {
+    let tests = &raw const TESTS;
+    let tests = unsafe { &*tests };
+
+    return tests.iter().flatten().copied();
+}
+
+/// Runs `test` and prints its outcome
+fn run(test: &SelfTest) -> Outcome {
+    pl011::print(b"  ");
+    pl011::print(test.name);
+    pl011::print(b": ");
+    let outcome = (test.run)();
+    pl011::println(outcome.name());
+    return outcome;
+}
+
+/// Prints `summary` as the last line of a run
+fn print_summary(summary: &Summary) {
+    let mut buf = [0u8; 20];
+
+    pl011::print(b"Self-test: ");
+    pl011::print(u64_to_dec(summary.passed as u64, &mut buf));
+    pl011::print(b" passed, ");
+    pl011::print(u64_to_dec(summary.failed as u64, &mut buf));
+    pl011::print(b" failed, ");
+    pl011::print(u64_to_dec(summary.skipped as u64, &mut buf));
+    pl011::println(b" skipped");
+}
+
+/// Runs every registered self-test
+pub fn run_all() -> Summary {
+    let mut summary = Summary::default();
+
+    pl011::println(b"Running self-tests");
+    for test in tests() {
+        summary.add(run(&test));
+    }
+    print_summary(&summary);
+    return summary;
+}
+
+/// Runs the self-test called `name`
+///
+/// Returns `None` if there is no such test.
+pub fn run_one(name: &[u8]) -> Option<Summary> {
+    let test = tests().find(|t| t.name == name)?;
+    let mut summary = Summary::default();
+
+    summary.add(run(&test));
+    print_summary(&summary);
+    return Some(summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+    use std::format;
+
+    fn pass() -> Outcome {
+        return Outcome::Pass;
+    }
+
+    #[test]
+    fn full_table_is_recorded() {
+        for i in 0..MAX_TESTS {
+            let name = Box::leak(format!("test-{i}").into_boxed_str());
+            assert!(register(SelfTest {
+                name: name.as_bytes(),
+                run: pass,
+            }));
+        }
+        assert!(!unsafe { OVERFLOWED });
+        assert!(!register(SelfTest {
+            name: b"one-too-many",
+            run: pass,
+        }));
+        assert!(unsafe { OVERFLOWED });
+        assert_eq!(tests().count(), MAX_TESTS);
+    }
+}
@@ -0,0 +1,21 @@
+//! Serial transfer protocols
+//!
+//! This module contains receivers for the protocols used to upload images to
+//! the bootloader over the console UART.
+
+use crate::selftest::{self, SelfTest};
+
+pub mod xmodem;
+pub mod ymodem;
+
+/// Registers the self-tests of the serial protocols
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"xmodem",
+        run: xmodem::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"ymodem",
+        run: ymodem::selftest,
+    });
+}
@@ -0,0 +1,381 @@
+//! XMODEM receiver
+//!
+//! This module implements the receiving side of the original XMODEM protocol
+//! (128-byte blocks with an 8-bit checksum) over the console UART, so images
+//! can be uploaded with any terminal program (e.g., `sx` or minicom).
+//!
+//! The receiver starts the transfer by sending NAK until the sender begins,
+//! acknowledges every valid block and stops at EOT. Since XMODEM pads the
+//! last block, the returned length is a multiple of 128 bytes.
+//!
+//! Errors are handled as the protocol describes: a block with a bad
+//! checksum or block number complement, one that stops short, or noise
+//! where a block should start is rejected with NAK once the line is quiet,
+//! so the sender retransmits it. A repeated block, sent again because our
+//! ACK was lost, is acknowledged and dropped. Only a block out of sequence
+//! is fatal. At most [`MAX_RETRIES`] blocks are rejected or repeated in a
+//! transfer.
+//!
+//! The whole transfer is bounded by a [`Deadline`], and `Ctrl-C` typed
+//! where a block is expected aborts it, so a stalled sender can't hold the
+//! board forever.
+
+use crate::drivers::timer::generic::Deadline;
+use crate::drivers::uart::pl011;
+use crate::selftest::Outcome;
+
+/// Start of a 128-byte block
+pub(super) const SOH: u8 = 0x01;
+/// End of transmission
+pub(super) const EOT: u8 = 0x04;
+/// Block acknowledged
+pub(super) const ACK: u8 = 0x06;
+/// Block rejected / start transfer in checksum mode
+pub(super) const NAK: u8 = 0x15;
+/// Transfer cancelled
+pub(super) const CAN: u8 = 0x18;
+/// `Ctrl-C`, typed to abort the transfer
+pub(super) const ETX: u8 = 0x03;
+
+/// Size of an XMODEM data block
+pub const BLOCK_SIZE: usize = 128;
+/// Time to wait for each byte within a block
+pub(super) const BYTE_TIMEOUT_MS: u64 = 1000;
+/// Time to wait for the sender to start, between NAKs
+pub(super) const START_TIMEOUT_MS: u64 = 3000;
+/// Number of NAKs sent before giving up on the sender
+pub(super) const START_RETRIES: usize = 20;
+/// Time the line must stay quiet before a rejected block is NAKed
+pub(super) const PURGE_TIMEOUT_MS: u64 = 1000;
+/// Maximum number of blocks rejected or repeated in a transfer
+pub const MAX_RETRIES: usize = 10;
+
+/// Errors reported by the XMODEM receiver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XmodemError {
+    /// The sender didn't start or stopped sending, or the deadline passed
+    Timeout,
+    /// The transfer was aborted with `Ctrl-C`
+    Aborted,
+    /// The sender cancelled the transfer
+    Cancelled,
+    /// The transfer doesn't fit in the destination buffer
+    BufferTooSmall,
+    /// A block arrived out of sequence
+    BadBlock,
+    /// More than [`MAX_RETRIES`] blocks were rejected or repeated
+    TooManyRetries,
+    /// A YMODEM block 0 doesn't hold a valid file name and size
+    BadHeader,
+}
+
+/// The line the transfer runs over
+pub trait Link {
+    /// Waits up to `timeout_ms` milliseconds, and until `deadline` at most,
+    /// for a byte
+    fn read(&mut self, timeout_ms: u64, deadline: Deadline) -> Option<u8>;
+    /// Sends `bytes`
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// The console UART
+///
+/// Replies go out with [`pl011::write_raw`], so the line prefixes, the
+/// mirror and the sinks of the console never add bytes to the protocol.
+pub struct Console;
+
+impl Link for Console {
+    fn read(&mut self, timeout_ms: u64, deadline: Deadline) -> Option<u8> {
+        let deadline = Deadline::after_ms(timeout_ms).min(deadline);
+
+        loop {
+            if let Some(c) = pl011::try_getchar() {
+                return Some(c);
+            }
+            if deadline.expired() {
+                return None;
+            }
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        pl011::write_raw(bytes);
+    }
+}
+
+/// What to do with a received block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Verdict {
+    /// The block is the expected one and intact
+    Accept,
+    /// The block is the previous one again
+    Repeat,
+    /// The block is damaged and must be sent again
+    Reject,
+    /// The block is out of sequence: the transfer can't continue
+    Fatal,
+}
+
+/// Checks a block (number, complement, data, checksum) against the block
+/// number `expected`
+const fn check_block(block: &[u8; BLOCK_SIZE + 3], expected: u8) -> Verdict {
+    let mut checksum = 0u8;
+    let mut i = 2;
+
+    if block[0] != !block[1] {
+        return Verdict::Reject;
+    }
+    while i < BLOCK_SIZE + 2 {
+        checksum = checksum.wrapping_add(block[i]);
+        i += 1;
+    }
+    if block[BLOCK_SIZE + 2] != checksum {
+        return Verdict::Reject;
+    }
+    if block[0] == expected {
+        return Verdict::Accept;
+    }
+    if block[0] == expected.wrapping_sub(1) {
+        return Verdict::Repeat;
+    }
+    return Verdict::Fatal;
+}
+
+/// Builds block `number` filled with `fill`, with a valid checksum
+const fn test_block(number: u8, fill: u8) -> [u8; BLOCK_SIZE + 3] {
+    let mut block = [fill; BLOCK_SIZE + 3];
+
+    block[0] = number;
+    block[1] = !number;
+    block[BLOCK_SIZE + 2] = fill.wrapping_mul(BLOCK_SIZE as u8);
+    return block;
+}
+
+const _: () = assert!(matches!(
+    check_block(&test_block(1, 0x5a), 1),
+    Verdict::Accept
+));
+const _: () = assert!(matches!(
+    check_block(&test_block(1, 0x5a), 2),
+    Verdict::Repeat
+));
+const _: () = assert!(matches!(
+    check_block(&test_block(3, 0x5a), 1),
+    Verdict::Fatal
+));
+const _: () = {
+    let mut block = test_block(1, 0x5a);
+    block[40] ^= 0x10;
+    assert!(matches!(check_block(&block, 1), Verdict::Reject));
+
+    let mut block = test_block(1, 0x5a);
+    block[1] = 0;
+    assert!(matches!(check_block(&block, 1), Verdict::Reject));
+};
+
+/// Receives a file over XMODEM on the console into `dst`, giving up at
+/// `deadline`
+///
+/// Returns the number of bytes received (a multiple of [`BLOCK_SIZE`]).
+pub fn receive(dst: &mut [u8], deadline: Deadline) -> Result<usize, XmodemError> {
+    return receive_on(&mut Console, dst, deadline);
+}
+
+/// Receives a file over XMODEM on `link` into `dst`, giving up at `deadline`
+///
+/// Returns the number of bytes received (a multiple of [`BLOCK_SIZE`]).
+pub fn receive_on<L: Link>(
+    link: &mut L,
+    dst: &mut [u8],
+    deadline: Deadline,
+) -> Result<usize, XmodemError> {
+    let mut received = 0;
+    let mut expected: u8 = 1;
+    let mut retries = 0;
+    let mut block = [0u8; BLOCK_SIZE + 3];
+
+    // Ask the sender to start in checksum mode
+    let mut first = None;
+    for _ in 0..START_RETRIES {
+        link.write(&[NAK]);
+        first = link.read(START_TIMEOUT_MS, deadline);
+        if first.is_some() || deadline.expired() {
+            break;
+        }
+    }
+    let mut header = first.ok_or(XmodemError::Timeout)?;
+
+    loop {
+        let verdict = match header {
+            SOH => {
+                // Block number, its complement, data and checksum
+                let mut complete = true;
+                for byte in block.iter_mut() {
+                    match link.read(BYTE_TIMEOUT_MS, deadline) {
+                        Some(c) => *byte = c,
+                        None => {
+                            complete = false;
+                            break;
+                        }
+                    }
+                }
+                if complete {
+                    check_block(&block, expected)
+                } else {
+                    Verdict::Reject
+                }
+            }
+            EOT => {
+                link.write(&[ACK]);
+                return Ok(received);
+            }
+            CAN => return Err(XmodemError::Cancelled),
+            ETX => {
+                link.write(&[CAN, CAN]);
+                return Err(XmodemError::Aborted);
+            }
+            _ => Verdict::Reject,
+        };
+
+        if deadline.expired() {
+            link.write(&[CAN, CAN]);
+            return Err(XmodemError::Timeout);
+        }
+        match verdict {
+            Verdict::Accept => {
+                if received + BLOCK_SIZE > dst.len() {
+                    link.write(&[CAN, CAN]);
+                    return Err(XmodemError::BufferTooSmall);
+                }
+                dst[received..received + BLOCK_SIZE].copy_from_slice(&block[2..BLOCK_SIZE + 2]);
+                received += BLOCK_SIZE;
+                expected = expected.wrapping_add(1);
+                link.write(&[ACK]);
+            }
+            Verdict::Repeat | Verdict::Reject => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    link.write(&[CAN, CAN]);
+                    return Err(XmodemError::TooManyRetries);
+                }
+                if verdict == Verdict::Repeat {
+                    link.write(&[ACK]);
+                } else {
+                    // Let the rest of the damaged block go by
+                    while link.read(PURGE_TIMEOUT_MS, deadline).is_some() {}
+                    link.write(&[NAK]);
+                }
+            }
+            Verdict::Fatal => {
+                link.write(&[CAN, CAN]);
+                return Err(XmodemError::BadBlock);
+            }
+        }
+
+        header = link
+            .read(BYTE_TIMEOUT_MS * 10, deadline)
+            .ok_or(XmodemError::Timeout)?;
+    }
+}
+
+/// Line replaying a scripted transfer, for the self-tests
+///
+/// The sender's bytes come in bursts with the line quiet in between, so a
+/// receiver purging the line stops at the end of the current burst.
+pub(super) struct ScriptedLink<'a> {
+    /// Bursts of bytes sent by the simulated sender
+    bursts: &'a [&'a [u8]],
+    /// Current burst
+    burst: usize,
+    /// Number of bytes of the current burst read so far
+    read: usize,
+    /// Replies of the receiver
+    output: [u8; 16],
+    /// Number of bytes in `output`
+    written: usize,
+}
+
+impl<'a> ScriptedLink<'a> {
+    /// Creates a link replaying `bursts`
+    pub(super) fn new(bursts: &'a [&'a [u8]]) -> Self {
+        return ScriptedLink {
+            bursts,
+            burst: 0,
+            read: 0,
+            output: [0; 16],
+            written: 0,
+        };
+    }
+
+    /// Returns the replies of the receiver
+    pub(super) fn replies(&self) -> &[u8] {
+        return &self.output[..self.written];
+    }
+}
+
+impl Link for ScriptedLink<'_> {
+    fn read(&mut self, _timeout_ms: u64, _deadline: Deadline) -> Option<u8> {
+        let burst = self.bursts.get(self.burst)?;
+
+        if let Some(&c) = burst.get(self.read) {
+            self.read += 1;
+            return Some(c);
+        }
+        // The quiet line between two bursts
+        self.burst += 1;
+        self.read = 0;
+        return None;
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.written < self.output.len() {
+                self.output[self.written] = b;
+                self.written += 1;
+            }
+        }
+    }
+}
+
+/// Self-test: a corrupted block is NAKed and its retransmission accepted,
+/// a repeated block is acknowledged and dropped, and a sender that never
+/// gets a block through runs out of retries
+pub fn selftest() -> Outcome {
+    let good = test_block(1, 0xa5);
+    let mut bad = good;
+    let mut packets = [[SOH; BLOCK_SIZE + 4]; 2];
+    let mut dst = [0u8; 2 * BLOCK_SIZE];
+
+    bad[BLOCK_SIZE / 2] ^= 0x01;
+    packets[0][1..].copy_from_slice(&bad);
+    packets[1][1..].copy_from_slice(&good);
+
+    // Corrupted block, its retransmission, the same block again and EOT
+    let mut retransmission = [0u8; 2 * (BLOCK_SIZE + 4) + 1];
+    retransmission[..BLOCK_SIZE + 4].copy_from_slice(&packets[1]);
+    retransmission[BLOCK_SIZE + 4..2 * (BLOCK_SIZE + 4)].copy_from_slice(&packets[1]);
+    retransmission[2 * (BLOCK_SIZE + 4)] = EOT;
+    let bursts: [&[u8]; 2] = [&packets[0], &retransmission];
+    let mut link = ScriptedLink::new(&bursts);
+    let result = receive_on(&mut link, &mut dst, Deadline::NEVER);
+    if result != Ok(BLOCK_SIZE)
+        || link.replies() != [NAK, NAK, ACK, ACK, ACK]
+        || dst[..BLOCK_SIZE].iter().any(|&b| b != 0xa5)
+        || dst[BLOCK_SIZE..].iter().any(|&b| b != 0)
+    {
+        return Outcome::Fail;
+    }
+
+    // Noise instead of blocks, once more than allowed
+    let bursts: [&[u8]; MAX_RETRIES + 1] = [&[0x55]; MAX_RETRIES + 1];
+    let mut link = ScriptedLink::new(&bursts);
+    let result = receive_on(&mut link, &mut dst, Deadline::NEVER);
+    if result != Err(XmodemError::TooManyRetries)
+        || link.replies()[..MAX_RETRIES + 1].iter().any(|&c| c != NAK)
+        || link.replies()[MAX_RETRIES + 1..] != [CAN, CAN]
+    {
+        return Outcome::Fail;
+    }
+
+    return Outcome::Pass;
+}
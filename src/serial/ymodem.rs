@@ -0,0 +1,448 @@
+//! YMODEM receiver
+//!
+//! This module implements the receiving side of YMODEM batch transfers over
+//! the console UART (e.g., from `sb` or minicom). YMODEM builds on XMODEM:
+//! blocks hold 128 or 1024 bytes and are protected by a CRC-16, and each
+//! file is announced by a block 0 holding its name and decimal size. The
+//! size lets the receiver drop the padding of the last block, so the
+//! returned length is the file's own.
+//!
+//! The receiver asks for block 0 by sending `C` (CRC mode), acknowledges it
+//! and sends `C` again to start the data blocks, which are handled as
+//! [`xmodem`](super::xmodem) handles them: damaged blocks are NAKed,
+//! repeated ones acknowledged and dropped, at most [`MAX_RETRIES`] times.
+//! After EOT, the receiver asks for the next block 0; an empty file name
+//! ends the batch.
+//!
+//! Only the first file of a batch is received: a batch announcing a second
+//! one is cancelled, and the first file is returned.
+
+use super::xmodem::{
+    ACK, BLOCK_SIZE, BYTE_TIMEOUT_MS, CAN, Console, EOT, ETX, Link, MAX_RETRIES, NAK,
+    PURGE_TIMEOUT_MS, SOH, START_RETRIES, START_TIMEOUT_MS, ScriptedLink, Verdict, XmodemError,
+};
+use crate::drivers::timer::generic::Deadline;
+use crate::selftest::Outcome;
+
+/// Start of a 1024-byte block
+const STX: u8 = 0x02;
+/// Start transfer in CRC mode
+const CRC_MODE: u8 = b'C';
+
+/// Size of a large YMODEM data block
+pub const LARGE_BLOCK_SIZE: usize = 1024;
+
+/// File announced by a block 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Header {
+    /// A file named by the first `name_len` bytes of the block, of `size`
+    /// bytes if the sender gave it
+    File {
+        name_len: usize,
+        size: Option<usize>,
+    },
+    /// The empty block ending the batch
+    EndOfBatch,
+}
+
+/// What arrived where a block was expected
+enum Packet {
+    /// An intact block holding this many bytes of data
+    Block(usize),
+    /// The end of the file
+    End,
+}
+
+/// Computes the CRC-16 (polynomial 0x1021, initial value 0) of `data`
+const fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    let mut i = 0;
+
+    while i < data.len() {
+        crc ^= (data[i] as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+            bit += 1;
+        }
+        i += 1;
+    }
+    return crc;
+}
+
+const _: () = assert!(crc16(b"123456789") == 0x31c3);
+
+/// Checks a block (number, complement, `len` bytes of data, CRC) against
+/// the block number `expected`
+const fn check_block(block: &[u8], len: usize, expected: u8) -> Verdict {
+    let (number, rest) = block.split_at(2);
+    let (data, crc) = rest.split_at(len);
+
+    if number[0] != !number[1] {
+        return Verdict::Reject;
+    }
+    if crc16(data) != (crc[0] as u16) << 8 | crc[1] as u16 {
+        return Verdict::Reject;
+    }
+    if number[0] == expected {
+        return Verdict::Accept;
+    }
+    if number[0] == expected.wrapping_sub(1) {
+        return Verdict::Repeat;
+    }
+    return Verdict::Fatal;
+}
+
+/// Parses the data of a block 0: the file name, a NUL, then optionally the
+/// decimal size followed by a space or a NUL
+///
+/// Returns `None` if the name isn't NUL-terminated or the size is malformed.
+const fn parse_header(data: &[u8]) -> Option<Header> {
+    let mut i = 0;
+    let mut size = 0usize;
+    let mut digits = 0;
+
+    if data.is_empty() || data[0] == 0 {
+        return Some(Header::EndOfBatch);
+    }
+    while i < data.len() && data[i] != 0 {
+        i += 1;
+    }
+    if i == data.len() {
+        return None;
+    }
+    let name_len = i;
+    i += 1;
+
+    while i < data.len() && data[i].is_ascii_digit() {
+        size = match size.checked_mul(10) {
+            Some(size) => match size.checked_add((data[i] - b'0') as usize) {
+                Some(size) => size,
+                None => return None,
+            },
+            None => return None,
+        };
+        digits += 1;
+        i += 1;
+    }
+    if i < data.len() && data[i] != b' ' && data[i] != 0 {
+        return None;
+    }
+    let size = match digits {
+        0 => None,
+        _ => Some(size),
+    };
+    return Some(Header::File { name_len, size });
+}
+
+const _: () = assert!(matches!(
+    parse_header(b"kernel.elf\x0010240 14764205057 100644\x00"),
+    Some(Header::File {
+        name_len: 10,
+        size: Some(10240)
+    })
+));
+const _: () = assert!(matches!(
+    parse_header(b"image\x00\x00\x00\x00"),
+    Some(Header::File {
+        name_len: 5,
+        size: None
+    })
+));
+const _: () = assert!(matches!(
+    parse_header(&[0; BLOCK_SIZE]),
+    Some(Header::EndOfBatch)
+));
+const _: () = assert!(parse_header(b"unterminated").is_none());
+const _: () = assert!(parse_header(b"image\x0012k\x00").is_none());
+const _: () = assert!(parse_header(b"image\x00184467440737095516160\x00").is_none());
+
+/// Builds a 128-byte block, with its SOH, numbered `number` and holding
+/// `data` padded with zeros
+const fn test_block(number: u8, data: &[u8]) -> [u8; BLOCK_SIZE + 5] {
+    let mut block = [0u8; BLOCK_SIZE + 5];
+    let mut i = 0;
+
+    block[0] = SOH;
+    block[1] = number;
+    block[2] = !number;
+    while i < data.len() {
+        block[3 + i] = data[i];
+        i += 1;
+    }
+    let (_, rest) = block.split_at(3);
+    let (data, _) = rest.split_at(BLOCK_SIZE);
+    let crc = crc16(data);
+    block[BLOCK_SIZE + 3] = (crc >> 8) as u8;
+    block[BLOCK_SIZE + 4] = crc as u8;
+    return block;
+}
+
+const _: () = {
+    let block = test_block(1, b"data");
+    let (_, block) = block.split_at(1);
+    assert!(matches!(check_block(block, BLOCK_SIZE, 1), Verdict::Accept));
+    assert!(matches!(check_block(block, BLOCK_SIZE, 2), Verdict::Repeat));
+    assert!(matches!(check_block(block, BLOCK_SIZE, 3), Verdict::Fatal));
+
+    let mut block = test_block(1, b"data");
+    block[4] ^= 0x01;
+    let (_, block) = block.split_at(1);
+    assert!(matches!(check_block(block, BLOCK_SIZE, 1), Verdict::Reject));
+};
+
+/// State of a transfer
+struct Receiver<'a, L: Link> {
+    /// The line the transfer runs over
+    link: &'a mut L,
+    /// When the transfer is given up
+    deadline: Deadline,
+    /// Number of blocks rejected or repeated so far
+    retries: usize,
+    /// Number, complement, data and CRC of the last block
+    block: [u8; LARGE_BLOCK_SIZE + 4],
+}
+
+impl<L: Link> Receiver<'_, L> {
+    /// Asks the sender for a block 0 in CRC mode, and returns its first byte
+    fn start(&mut self) -> Result<u8, XmodemError> {
+        for _ in 0..START_RETRIES {
+            self.link.write(&[CRC_MODE]);
+            if let Some(c) = self.link.read(START_TIMEOUT_MS, self.deadline) {
+                return Ok(c);
+            }
+            if self.deadline.expired() {
+                break;
+            }
+        }
+        return Err(XmodemError::Timeout);
+    }
+
+    /// Waits for the first byte of the next block
+    fn header(&mut self) -> Result<u8, XmodemError> {
+        return self
+            .link
+            .read(BYTE_TIMEOUT_MS * 10, self.deadline)
+            .ok_or(XmodemError::Timeout);
+    }
+
+    /// Cancels the transfer
+    fn cancel(&mut self) {
+        self.link.write(&[CAN, CAN]);
+    }
+
+    /// Receives the block numbered `expected`, starting with `header`
+    ///
+    /// Damaged blocks are NAKed and repeated ones acknowledged until the
+    /// expected block arrives intact; it's left unacknowledged in
+    /// [`block`](Self::block).
+    fn next(&mut self, mut header: u8, expected: u8) -> Result<Packet, XmodemError> {
+        loop {
+            let (verdict, len) = match header {
+                SOH | STX => {
+                    let len = match header {
+                        STX => LARGE_BLOCK_SIZE,
+                        _ => BLOCK_SIZE,
+                    };
+                    let block = &mut self.block[..len + 4];
+                    let mut complete = true;
+                    for byte in block.iter_mut() {
+                        match self.link.read(BYTE_TIMEOUT_MS, self.deadline) {
+                            Some(c) => *byte = c,
+                            None => {
+                                complete = false;
+                                break;
+                            }
+                        }
+                    }
+                    if complete {
+                        (check_block(block, len, expected), len)
+                    } else {
+                        (Verdict::Reject, 0)
+                    }
+                }
+                EOT => return Ok(Packet::End),
+                CAN => return Err(XmodemError::Cancelled),
+                ETX => {
+                    self.cancel();
+                    return Err(XmodemError::Aborted);
+                }
+                _ => (Verdict::Reject, 0),
+            };
+
+            if self.deadline.expired() {
+                self.cancel();
+                return Err(XmodemError::Timeout);
+            }
+            match verdict {
+                Verdict::Accept => return Ok(Packet::Block(len)),
+                Verdict::Repeat | Verdict::Reject => {
+                    self.retries += 1;
+                    if self.retries > MAX_RETRIES {
+                        self.cancel();
+                        return Err(XmodemError::TooManyRetries);
+                    }
+                    if verdict == Verdict::Repeat {
+                        self.link.write(&[ACK]);
+                    } else {
+                        // Let the rest of the damaged block go by
+                        while self.link.read(PURGE_TIMEOUT_MS, self.deadline).is_some() {}
+                        self.link.write(&[NAK]);
+                    }
+                }
+                Verdict::Fatal => {
+                    self.cancel();
+                    return Err(XmodemError::BadBlock);
+                }
+            }
+            header = self.header()?;
+        }
+    }
+}
+
+/// Receives a file over YMODEM on the console into `dst`, and its name into
+/// `name`, giving up at `deadline`
+///
+/// Returns the length of the file and of its name, truncated to the size of
+/// `name`.
+pub fn receive(
+    dst: &mut [u8],
+    name: &mut [u8],
+    deadline: Deadline,
+) -> Result<(usize, usize), XmodemError> {
+    return receive_on(&mut Console, dst, name, deadline);
+}
+
+/// Receives a file over YMODEM on `link` into `dst`, and its name into
+/// `name`, giving up at `deadline`
+///
+/// Returns the length of the file and of its name, truncated to the size of
+/// `name`.
+pub fn receive_on<L: Link>(
+    link: &mut L,
+    dst: &mut [u8],
+    name: &mut [u8],
+    deadline: Deadline,
+) -> Result<(usize, usize), XmodemError> {
+    let mut rx = Receiver {
+        link,
+        deadline,
+        retries: 0,
+        block: [0; LARGE_BLOCK_SIZE + 4],
+    };
+
+    // Block 0: the file's name and size
+    let header = rx.start()?;
+    let Packet::Block(len) = rx.next(header, 0)? else {
+        rx.cancel();
+        return Err(XmodemError::BadHeader);
+    };
+    let (name_len, size) = match parse_header(&rx.block[2..len + 2]) {
+        Some(Header::File { name_len, size }) => (name_len.min(name.len()), size),
+        Some(Header::EndOfBatch) => {
+            rx.link.write(&[ACK]);
+            return Ok((0, 0));
+        }
+        None => {
+            rx.cancel();
+            return Err(XmodemError::BadHeader);
+        }
+    };
+    if size.is_some_and(|size| size > dst.len()) {
+        rx.cancel();
+        return Err(XmodemError::BufferTooSmall);
+    }
+    name[..name_len].copy_from_slice(&rx.block[2..name_len + 2]);
+    rx.link.write(&[ACK, CRC_MODE]);
+
+    // The file's data, without the padding past its size
+    let limit = size.unwrap_or(dst.len());
+    let mut received = 0;
+    let mut expected: u8 = 1;
+    loop {
+        let header = rx.header()?;
+        let Packet::Block(len) = rx.next(header, expected)? else {
+            rx.link.write(&[ACK]);
+            break;
+        };
+        if size.is_none() && received + len > dst.len() {
+            rx.cancel();
+            return Err(XmodemError::BufferTooSmall);
+        }
+        let kept = len.min(limit - received);
+        dst[received..received + kept].copy_from_slice(&rx.block[2..kept + 2]);
+        received += kept;
+        expected = expected.wrapping_add(1);
+        rx.link.write(&[ACK]);
+    }
+
+    // The empty block 0 ending the batch
+    let header = rx.start()?;
+    let Packet::Block(len) = rx.next(header, 0)? else {
+        rx.cancel();
+        return Err(XmodemError::BadHeader);
+    };
+    match parse_header(&rx.block[2..len + 2]) {
+        Some(Header::EndOfBatch) => rx.link.write(&[ACK]),
+        Some(Header::File { .. }) => rx.cancel(),
+        None => {
+            rx.cancel();
+            return Err(XmodemError::BadHeader);
+        }
+    }
+    return Ok((received, name_len));
+}
+
+/// Self-test: a file announced with its name and size is received without
+/// its padding up to the end of the batch, an empty batch yields no file,
+/// and a malformed block 0 cancels the transfer
+pub fn selftest() -> Outcome {
+    const PACKET: usize = BLOCK_SIZE + 5;
+    let mut data = [0u8; 200];
+    let mut dst = [0xffu8; 2 * BLOCK_SIZE];
+    let mut name = [0u8; 8];
+
+    for (i, b) in data.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    let mut transfer = [0u8; 4 * PACKET + 1];
+    transfer[..PACKET].copy_from_slice(&test_block(0, b"boot.img\x00200 0 0\x00"));
+    transfer[PACKET..2 * PACKET].copy_from_slice(&test_block(1, &data[..BLOCK_SIZE]));
+    transfer[2 * PACKET..3 * PACKET].copy_from_slice(&test_block(2, &data[BLOCK_SIZE..]));
+    transfer[3 * PACKET] = EOT;
+    transfer[3 * PACKET + 1..].copy_from_slice(&test_block(0, &[]));
+    let bursts: [&[u8]; 1] = [&transfer];
+    let mut link = ScriptedLink::new(&bursts);
+    let result = receive_on(&mut link, &mut dst, &mut name, Deadline::NEVER);
+    if result != Ok((200, 8))
+        || link.replies() != [CRC_MODE, ACK, CRC_MODE, ACK, ACK, ACK, CRC_MODE, ACK]
+        || name != *b"boot.img"
+        || dst[..200] != data
+        || dst[200..].iter().any(|&b| b != 0xff)
+    {
+        return Outcome::Fail;
+    }
+
+    // Nothing but the end of the batch
+    let block = test_block(0, &[]);
+    let bursts: [&[u8]; 1] = [&block];
+    let mut link = ScriptedLink::new(&bursts);
+    let result = receive_on(&mut link, &mut dst, &mut name, Deadline::NEVER);
+    if result != Ok((0, 0)) || link.replies() != [CRC_MODE, ACK] {
+        return Outcome::Fail;
+    }
+
+    // A size that isn't a number
+    let block = test_block(0, b"boot.img\x0012k\x00");
+    let bursts: [&[u8]; 1] = [&block];
+    let mut link = ScriptedLink::new(&bursts);
+    let result = receive_on(&mut link, &mut dst, &mut name, Deadline::NEVER);
+    if result != Err(XmodemError::BadHeader) || link.replies() != [CRC_MODE, CAN, CAN] {
+        return Outcome::Fail;
+    }
+
+    return Outcome::Pass;
+}
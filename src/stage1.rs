@@ -0,0 +1,82 @@
+//! First stage of a two-stage boot
+//!
+//! Built with the `stage1` feature (`make STAGE1=1`), the crate becomes a
+//! loader small enough for on-chip SRAM. The entry code calls
+//! [`stage1_main`] instead of going through the full boot: it brings up the
+//! console the board was built with, copies the stage-2 image from the
+//! board's flash to where its [header](crate::parsers::stage2) says, checks
+//! it and jumps to it. The stage-2 image is normally the full bootloader,
+//! which starts over as if it had been loaded directly.
+//!
+//! The monitor with its scripts and the display drivers are compiled out,
+//! and the link drops whatever else [`stage1_main`] doesn't reach. The UART
+//! driver, the flash driver and the CRC-32 are the ones of the full
+//! bootloader.
+
+use crate::board;
+use crate::drivers::flash::cfi;
+use crate::drivers::uart::pl011;
+use crate::parsers::stage2;
+use crate::utilities::print::u64_to_hex;
+
+use crate::asm;
+use core::mem;
+use core::panic::PanicInfo;
+
+/// Offset of the stage-2 image in the board flash: past the environment and
+/// the boot record, each an erase block of QEMU's `virt` flash
+pub const STAGE2_OFFSET: usize = 0x8_0000;
+
+/// Entry point of a loaded stage 2, taking the DTB like the bootloader does
+type Stage2Entry = extern "C" fn(dtb: usize) -> !;
+
+/// Loads the stage-2 image from flash and jumps to it, passing on `dtb`
+///
+/// Called by the assembly entry code in place of the full boot, with the
+/// stack and the early exception vectors set up. If there is no valid
+/// stage-2 image, says why and halts.
+#[unsafe(no_mangle)]
+pub extern "C" fn stage1_main(dtb: usize) -> ! {
+    board::init_console();
+    pl011::println(b"Stage 1");
+
+    let Some(flash) = board::config().flash else {
+        halt(b"no flash on this board");
+    };
+    // Also leaves the flash in read array mode, to be read like memory
+    if !unsafe { cfi::query(flash.base) } {
+        halt(b"no CFI flash answering");
+    }
+    let base = flash.base + STAGE2_OFFSET;
+    pl011::print(b"Loading stage 2 from 0x");
+    pl011::println(u64_to_hex(base as u64, &mut [0u8; 16]));
+    let header = match unsafe { stage2::load(base) } {
+        Ok(header) => header,
+        Err(e) => halt(e.message()),
+    };
+
+    pl011::print(b"Starting stage 2 at 0x");
+    pl011::println(u64_to_hex(header.entry, &mut [0u8; 16]));
+    unsafe {
+        // The image was written through the data side
+        asm!("dsb ish", "ic iallu", "dsb ish", "isb", options(nostack));
+        let entry: Stage2Entry = mem::transmute(header.entry as usize);
+        entry(dtb);
+    }
+}
+
+/// Prints why the first stage stopped and halts
+fn halt(reason: &[u8]) -> ! {
+    pl011::print(b"Stage 1 failed: ");
+    pl011::println(reason);
+    loop {
+        unsafe { asm!("wfe", options(nomem, nostack)) };
+    }
+}
+
+/// Panic handler of the first stage, which leaves out the heartbeat and the
+/// boot record of the full one
+#[panic_handler]
+pub fn panic(_info: &PanicInfo) -> ! {
+    halt(b"panic");
+}
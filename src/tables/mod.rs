@@ -0,0 +1,10 @@
+//! Tables handed to the payload
+//!
+//! This module builds the firmware tables a payload may look for besides
+//! the device tree.
+//!
+//! # Submodules
+//!
+//! - [`smbios`]: SMBIOS tables identifying the board
+
+pub mod smbios;
@@ -0,0 +1,385 @@
+//! SMBIOS tables
+//!
+//! Gives the payload the identity of the board (vendor, model, serial
+//! number) the way firmware does, so tools like `dmidecode` work without a
+//! full firmware stack. The tables follow SMBIOS 3.0: a 64-bit entry point
+//! followed by these structures:
+//!
+//! - Type 0 (BIOS information): this bootloader's name and version
+//! - Type 1 (system information) and Type 2 (baseboard information): the
+//!   board [`Identity`]
+//! - Type 127 (end of table)
+//!
+//! Each structure is a formatted area, whose second byte is its length,
+//! followed by its strings, each NUL-terminated, and one more NUL. Fields
+//! refer to strings by 1-based index, 0 meaning "not specified", so empty
+//! strings are left out. A structure without strings ends with two NULs.
+//!
+//! The identity comes from the board configuration, each string being
+//! overridable at build time with the `SMBIOS_VENDOR`, `SMBIOS_PRODUCT`,
+//! `SMBIOS_VERSION` and `SMBIOS_SERIAL` environment variables.
+//!
+//! [`install`] builds the tables in a page reserved for
+//! [`ReserveTag::Tables`]. The payload finds the entry point in the
+//! [`BootInfo`](crate::boot::BootInfo) block and in the
+//! [`CHOSEN_PROPERTY`] of the DTB's `/chosen` node.
+
+use crate::board::{self, Identity};
+use crate::memory::reserve::{self, ReserveError, ReserveTag};
+
+/// Size of the region holding the entry point and the structure table
+pub const REGION_SIZE: usize = 4096;
+/// `/chosen` property holding the address of the entry point, as a 64-bit
+/// big-endian number
+pub const CHOSEN_PROPERTY: &[u8] = b"linux,smbios3-entrypoint";
+
+/// Anchor string of the 64-bit entry point
+const ANCHOR: &[u8] = b"_SM3_";
+/// Size of the 64-bit entry point
+const ENTRY_POINT_SIZE: usize = 0x18;
+/// Offset of the structure table in the region
+const TABLE_OFFSET: usize = 0x20;
+/// SMBIOS version implemented
+const VERSION: (u8, u8) = (3, 0);
+/// Revision of the entry point structure
+const ENTRY_POINT_REVISION: u8 = 1;
+/// Longest string copied into the tables
+const MAX_STRING: usize = 64;
+
+/// Structure type: BIOS information
+const TYPE_BIOS: u8 = 0;
+/// Structure type: system information
+const TYPE_SYSTEM: u8 = 1;
+/// Structure type: baseboard information
+const TYPE_BASEBOARD: u8 = 2;
+/// Structure type: end of table
+const TYPE_END: u8 = 127;
+
+/// BIOS characteristics: none are supported
+const BIOS_CHARACTERISTICS_NOT_SUPPORTED: u64 = 1 << 3;
+/// System wake-up type: power switch
+const WAKEUP_POWER_SWITCH: u8 = 6;
+/// Baseboard feature flags: hosting board
+const BOARD_HOSTING: u8 = 1 << 0;
+/// Baseboard type: motherboard
+const BOARD_MOTHERBOARD: u8 = 0x0a;
+
+/// Address of the entry point, 0 until [`install`] is called
+static mut ENTRY_POINT: usize = 0;
+
+/// Returns the identity reported in the tables
+///
+/// Values given at build time take precedence over the board's.
+fn identity() -> Identity {
+    let board = board::config().identity;
+    let pick = |build: Option<&'static str>, value| build.map_or(value, str::as_bytes);
+
+    return Identity {
+        vendor: pick(option_env!("SMBIOS_VENDOR"), board.vendor),
+        product: pick(option_env!("SMBIOS_PRODUCT"), board.product),
+        version: pick(option_env!("SMBIOS_VERSION"), board.version),
+        serial: pick(option_env!("SMBIOS_SERIAL"), board.serial),
+    };
+}
+
+/// Builds the tables and places them in a reserved page
+///
+/// Returns the address of the entry point. Calling it again returns the
+/// same tables.
+pub fn install() -> Result<usize, ReserveError> {
+    if let Some(addr) = entry_point() {
+        return Ok(addr);
+    }
+
+    let base = reserve::allocate(REGION_SIZE, 4096, None, ReserveTag::Tables)?;
+    let region = unsafe { &mut *(base as *mut [u8; REGION_SIZE]) };
+
+    region.fill(0);
+    build(region, &identity(), base as u64);
+    unsafe {
+        ENTRY_POINT = base;
+    }
+    return Ok(base);
+}
+
+/// Returns the address of the entry point, if the tables are installed
+pub fn entry_point() -> Option<usize> {
+    let addr = unsafe { ENTRY_POINT };
+
+    return (addr != 0).then_some(addr);
+}
+
+/// Returns the installed structure table
+pub fn table() -> Option<&'static [u8]> {
+    let addr = entry_point()?;
+
+    unsafe {
+        let region = &*(addr as *const [u8; REGION_SIZE]);
+        let len = u32::from_le_bytes([region[12], region[13], region[14], region[15]]) as usize;
+        return Some(region.split_at(TABLE_OFFSET).1.split_at(len).0);
+    }
+}
+
+/// Returns the length of `s` as copied into the tables: up to its first NUL
+/// and at most [`MAX_STRING`] bytes
+const fn string_len(s: &[u8]) -> usize {
+    let mut len = 0;
+
+    while len < s.len() && len < MAX_STRING && s[len] != 0 {
+        len += 1;
+    }
+    return len;
+}
+
+/// Returns the index `strings[i]` gets in its structure's string set, 0 if
+/// it's empty
+const fn string_index(strings: &[&[u8]], i: usize) -> u8 {
+    let mut index = 0;
+    let mut j = 0;
+
+    if string_len(strings[i]) == 0 {
+        return 0;
+    }
+    while j <= i {
+        if string_len(strings[j]) != 0 {
+            index += 1;
+        }
+        j += 1;
+    }
+    return index;
+}
+
+/// Writes `data` at `off` of `buf` and returns the offset past it
+const fn put(buf: &mut [u8; REGION_SIZE], off: usize, data: &[u8]) -> usize {
+    let mut i = 0;
+
+    while i < data.len() {
+        buf[off + i] = data[i];
+        i += 1;
+    }
+    return off + data.len();
+}
+
+/// Writes a structure at `off` of `buf` and returns the offset past it
+///
+/// `formatted` is the formatted area, header included; `strings` are its
+/// strings in index order, empty ones being left out.
+const fn put_structure(
+    buf: &mut [u8; REGION_SIZE],
+    off: usize,
+    formatted: &[u8],
+    strings: &[&[u8]],
+) -> usize {
+    let mut off = put(buf, off, formatted);
+    let mut written = 0;
+    let mut i = 0;
+
+    while i < strings.len() {
+        let len = string_len(strings[i]);
+        if len != 0 {
+            off = put(buf, off, strings[i].split_at(len).0);
+            off = put(buf, off, &[0]);
+            written += 1;
+        }
+        i += 1;
+    }
+    if written == 0 {
+        off = put(buf, off, &[0]);
+    }
+    return put(buf, off, &[0]);
+}
+
+/// Builds the entry point and structure table into `buf`, which is zeroed
+/// and will be at address `base`
+const fn build(buf: &mut [u8; REGION_SIZE], identity: &Identity, base: u64) {
+    let mut off = TABLE_OFFSET;
+
+    // Type 0: vendor, version, release date
+    let strings: [&[u8]; 3] = [
+        env!("CARGO_PKG_NAME").as_bytes(),
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        b"",
+    ];
+    let c = BIOS_CHARACTERISTICS_NOT_SUPPORTED.to_le_bytes();
+    #[rustfmt::skip]
+    let bios = [
+        TYPE_BIOS, 0x18, 0x00, 0x00,
+        string_index(&strings, 0), string_index(&strings, 1),
+        0x00, 0x00, // No BIOS segment
+        string_index(&strings, 2),
+        0x00, // ROM size
+        c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7],
+        0x00, 0x00, // Characteristic extensions
+        0xff, 0xff, // System BIOS release, not specified
+        0xff, 0xff, // Embedded controller release, none
+    ];
+    assert!(bios.len() == bios[1] as usize);
+    off = put_structure(buf, off, &bios, &strings);
+
+    // Type 1: manufacturer, product, version, serial, SKU, family
+    let strings: [&[u8]; 6] = [
+        identity.vendor,
+        identity.product,
+        identity.version,
+        identity.serial,
+        b"",
+        b"",
+    ];
+    #[rustfmt::skip]
+    let system = [
+        TYPE_SYSTEM, 0x1b, 0x01, 0x00,
+        string_index(&strings, 0), string_index(&strings, 1),
+        string_index(&strings, 2), string_index(&strings, 3),
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // UUID, not set
+        WAKEUP_POWER_SWITCH,
+        string_index(&strings, 4), string_index(&strings, 5),
+    ];
+    assert!(system.len() == system[1] as usize);
+    off = put_structure(buf, off, &system, &strings);
+
+    // Type 2: manufacturer, product, version, serial, asset tag, location
+    let strings: [&[u8]; 6] = [
+        identity.vendor,
+        identity.product,
+        identity.version,
+        identity.serial,
+        b"",
+        b"",
+    ];
+    #[rustfmt::skip]
+    let baseboard = [
+        TYPE_BASEBOARD, 0x0f, 0x02, 0x00,
+        string_index(&strings, 0), string_index(&strings, 1),
+        string_index(&strings, 2), string_index(&strings, 3),
+        string_index(&strings, 4),
+        BOARD_HOSTING,
+        string_index(&strings, 5),
+        0x00, 0x00, // No chassis
+        BOARD_MOTHERBOARD,
+        0x00, // No contained objects
+    ];
+    assert!(baseboard.len() == baseboard[1] as usize);
+    off = put_structure(buf, off, &baseboard, &strings);
+
+    off = put_structure(buf, off, &[TYPE_END, 0x04, 0x03, 0x00], &[]);
+
+    let table_len = ((off - TABLE_OFFSET) as u32).to_le_bytes();
+    let table_addr = (base + TABLE_OFFSET as u64).to_le_bytes();
+    #[rustfmt::skip]
+    let entry = [
+        ANCHOR[0], ANCHOR[1], ANCHOR[2], ANCHOR[3], ANCHOR[4],
+        0x00, // Checksum, set below
+        ENTRY_POINT_SIZE as u8,
+        VERSION.0, VERSION.1, 0x00,
+        ENTRY_POINT_REVISION,
+        0x00,
+        table_len[0], table_len[1], table_len[2], table_len[3],
+        table_addr[0], table_addr[1], table_addr[2], table_addr[3],
+        table_addr[4], table_addr[5], table_addr[6], table_addr[7],
+    ];
+    put(buf, 0, &entry);
+    buf[5] = 0u8.wrapping_sub(checksum(buf.split_at(ENTRY_POINT_SIZE).0));
+}
+
+/// Returns the 8-bit sum of `data`
+const fn checksum(data: &[u8]) -> u8 {
+    let mut sum = 0u8;
+    let mut i = 0;
+
+    while i < data.len() {
+        sum = sum.wrapping_add(data[i]);
+        i += 1;
+    }
+    return sum;
+}
+
+/// Returns the offset of the structure following the one at `off` of
+/// `table`, as `dmidecode` finds it: past the formatted area, then past
+/// the first double NUL
+pub const fn next_structure(table: &[u8], off: usize) -> Option<usize> {
+    let mut i = off + table[off + 1] as usize;
+
+    while i + 1 < table.len() {
+        if table[i] == 0 && table[i + 1] == 0 {
+            return Some(i + 2);
+        }
+        i += 1;
+    }
+    return None;
+}
+
+/// Returns string `index` of the structure at `off` of `table`
+///
+/// Index 0 and indices past the structure's strings give an empty string.
+pub const fn string(table: &[u8], off: usize, index: u8) -> &[u8] {
+    let mut start = off + table[off + 1] as usize;
+    let mut current = 1;
+
+    if index == 0 {
+        return &[];
+    }
+    while start < table.len() && table[start] != 0 {
+        let mut end = start;
+        while end < table.len() && table[end] != 0 {
+            end += 1;
+        }
+        if current == index {
+            return table.split_at(end).0.split_at(start).1;
+        }
+        start = end + 1;
+        current += 1;
+    }
+    return &[];
+}
+
+/// Identity the tables are checked against at build time
+const TEST_IDENTITY: Identity = Identity {
+    vendor: b"ACME",
+    product: b"Widget",
+    version: b"",
+    serial: b"SN-0042",
+};
+/// Tables built for [`TEST_IDENTITY`] at address 0x1000
+const TEST_REGION: [u8; REGION_SIZE] = {
+    let mut buf = [0; REGION_SIZE];
+    build(&mut buf, &TEST_IDENTITY, 0x1000);
+    buf
+};
+
+// The entry point sums to 0 and points to the table
+const _: () = assert!(checksum(TEST_REGION.split_at(ENTRY_POINT_SIZE).0) == 0);
+const _: () = assert!(TEST_REGION[16] == 0x20 && TEST_REGION[17] == 0x10);
+
+// The structures come in order, the end of table closing the table
+const _: () = {
+    let len = u32::from_le_bytes([TEST_REGION[12], TEST_REGION[13], 0, 0]) as usize;
+    let table = TEST_REGION.split_at(TABLE_OFFSET).1.split_at(len).0;
+    let types = [TYPE_BIOS, TYPE_SYSTEM, TYPE_BASEBOARD, TYPE_END];
+    let mut off = 0;
+    let mut i = 0;
+
+    while i < types.len() {
+        assert!(table[off] == types[i]);
+        assert!(table[off + 2] == i as u8);
+        match next_structure(table, off) {
+            Some(next) => off = next,
+            None => panic!(),
+        }
+        i += 1;
+    }
+    assert!(off == table.len());
+};
+
+// Empty strings are left out of the string set and referred to as 0
+const _: () = {
+    let table = TEST_REGION.split_at(TABLE_OFFSET).1;
+    let system = match next_structure(table, 0) {
+        Some(off) => off,
+        None => panic!(),
+    };
+
+    assert!(table[system + 6] == 0);
+    assert!(table[system + 7] == 3);
+    assert!(string(table, system, table[system + 5]).len() == b"Widget".len());
+    assert!(string(table, system, table[system + 7])[3] == b'0');
+};
@@ -0,0 +1,34 @@
+//! CRC-32 checksum
+//!
+//! This module implements the standard CRC-32 (IEEE 802.3, reflected
+//! polynomial `0xedb88320`) as used by zlib, gzip and PNG. It is computed
+//! bitwise without a lookup table to keep the bootloader image small.
+//!
+//! The checksum can be computed incrementally with [`crc32_update`], starting
+//! from 0 and feeding the data in any number of pieces.
+
+/// Reflected CRC-32 polynomial
+const CRC32_POLY: u32 = 0xedb88320;
+
+/// Continues a CRC-32 computation over `data`
+///
+/// `crc` is the checksum of the data seen so far (0 for none), and the
+/// checksum including `data` is returned.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (CRC32_POLY & mask);
+        }
+    }
+
+    return !crc;
+}
+
+/// Computes the CRC-32 of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    return crc32_update(0, data);
+}
@@ -0,0 +1,231 @@
+//! GUIDs (UUIDs)
+//!
+//! A [`Guid`] is kept in the byte order its canonical string form reads,
+//! e.g. `c12a7328-f81f-11d2-ba4b-00a0c93ec93b`, the big-endian order of RFC
+//! 4122. GPT and UEFI store the first three fields little-endian instead;
+//! [`Guid::from_mixed_endian`] and [`Guid::to_mixed_endian`] convert from
+//! and to that order, e.g. for the partition entries read by
+//! [`gpt`](crate::parsers::gpt).
+//!
+//! Parsing and formatting are `const`, so well-known GUIDs are written as
+//! strings, and the conversions are checked at compile time against known
+//! vectors.
+
+/// Length of the canonical string form of a GUID
+pub const GUID_STRING_SIZE: usize = 36;
+/// Positions of the dashes in the canonical string form
+const DASHES: [usize; 4] = [8, 13, 18, 23];
+/// Lengths of the fields stored little-endian by GPT and UEFI
+const MIXED_FIELDS: [(usize, usize); 3] = [(0, 4), (4, 2), (6, 2)];
+
+/// A GUID, in the byte order of its string form
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Guid([u8; 16]);
+
+impl Guid {
+    /// The nil GUID, all zeros
+    pub const NIL: Guid = Guid([0; 16]);
+
+    /// Creates a GUID from its bytes, in the order of its string form
+    pub const fn from_bytes(bytes: [u8; 16]) -> Self {
+        return Guid(bytes);
+    }
+
+    /// Returns the bytes of the GUID, in the order of its string form
+    pub const fn as_bytes(&self) -> &[u8; 16] {
+        return &self.0;
+    }
+
+    /// Checks whether this is the nil GUID
+    pub const fn is_nil(&self) -> bool {
+        return same(&self.0, &Guid::NIL.0);
+    }
+
+    /// Parses the canonical string form, `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`
+    ///
+    /// Hex digits may be upper or lower case. Returns `None` if `s` isn't in
+    /// that form.
+    pub const fn parse(s: &[u8]) -> Option<Guid> {
+        let mut bytes = [0u8; 16];
+        let mut i = 0;
+        let mut digits = 0;
+
+        if s.len() != GUID_STRING_SIZE {
+            return None;
+        }
+        while i < GUID_STRING_SIZE {
+            if is_dash(i) {
+                if s[i] != b'-' {
+                    return None;
+                }
+                i += 1;
+                continue;
+            }
+            let digit = match s[i] {
+                b'0'..=b'9' => s[i] - b'0',
+                b'a'..=b'f' => s[i] - b'a' + 10,
+                b'A'..=b'F' => s[i] - b'A' + 10,
+                _ => return None,
+            };
+            bytes[digits / 2] = bytes[digits / 2] << 4 | digit;
+            digits += 1;
+            i += 1;
+        }
+        return Some(Guid(bytes));
+    }
+
+    /// Returns the canonical string form, in lower case
+    pub const fn format(&self) -> [u8; GUID_STRING_SIZE] {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut out = [b'-'; GUID_STRING_SIZE];
+        let mut i = 0;
+        let mut digits = 0;
+
+        while i < GUID_STRING_SIZE {
+            if !is_dash(i) {
+                let byte = self.0[digits / 2];
+                let nibble = match digits % 2 {
+                    0 => byte >> 4,
+                    _ => byte & 0xf,
+                };
+                out[i] = HEX[nibble as usize];
+                digits += 1;
+            }
+            i += 1;
+        }
+        return out;
+    }
+
+    /// Creates a GUID from the byte order of GPT and UEFI, whose first three
+    /// fields are little-endian
+    pub const fn from_mixed_endian(bytes: [u8; 16]) -> Self {
+        return Guid(swap_fields(bytes));
+    }
+
+    /// Returns the bytes of the GUID in the byte order of GPT and UEFI
+    pub const fn to_mixed_endian(&self) -> [u8; 16] {
+        return swap_fields(self.0);
+    }
+}
+
+/// Parses the canonical string form of a GUID, failing the build if it's
+/// malformed; for constants
+pub const fn guid(s: &str) -> Guid {
+    return match Guid::parse(s.as_bytes()) {
+        Some(guid) => guid,
+        None => panic!("malformed GUID"),
+    };
+}
+
+/// GPT partition type of an EFI System Partition
+pub const EFI_SYSTEM: Guid = guid("c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+/// GPT partition type of Linux filesystem data
+pub const LINUX_FILESYSTEM: Guid = guid("0fc63daf-8483-4772-8e79-3d69d8477de4");
+/// GPT partition type of a Linux root partition for AArch64
+pub const LINUX_ROOT_ARM64: Guid = guid("b921b045-1df0-41c3-af44-4c6f280d3fae");
+
+/// Checks whether position `i` of the string form holds a dash
+const fn is_dash(i: usize) -> bool {
+    let mut d = 0;
+
+    while d < DASHES.len() {
+        if DASHES[d] == i {
+            return true;
+        }
+        d += 1;
+    }
+    return false;
+}
+
+/// Reverses the byte order of the fields GPT stores little-endian; its own
+/// inverse
+const fn swap_fields(mut bytes: [u8; 16]) -> [u8; 16] {
+    let mut f = 0;
+
+    while f < MIXED_FIELDS.len() {
+        let (start, len) = MIXED_FIELDS[f];
+        let mut i = 0;
+        while i < len / 2 {
+            let tmp = bytes[start + i];
+            bytes[start + i] = bytes[start + len - 1 - i];
+            bytes[start + len - 1 - i] = tmp;
+            i += 1;
+        }
+        f += 1;
+    }
+    return bytes;
+}
+
+/// Compares two byte strings in a const context
+const fn same(a: &[u8], b: &[u8]) -> bool {
+    let mut i = 0;
+
+    if a.len() != b.len() {
+        return false;
+    }
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+// Known vectors: the string form, its bytes, and the GPT on-disk bytes
+const _: () = assert!(same(
+    EFI_SYSTEM.as_bytes(),
+    &[
+        0xc1, 0x2a, 0x73, 0x28, 0xf8, 0x1f, 0x11, 0xd2, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9,
+        0x3b
+    ]
+));
+const _: () = assert!(same(
+    &EFI_SYSTEM.to_mixed_endian(),
+    &[
+        0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9,
+        0x3b
+    ]
+));
+const _: () = assert!(same(
+    &LINUX_FILESYSTEM.to_mixed_endian(),
+    &[
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d,
+        0xe4
+    ]
+));
+const _: () = assert!(same(
+    &LINUX_ROOT_ARM64.to_mixed_endian(),
+    &[
+        0x45, 0xb0, 0x21, 0xb9, 0xf0, 0x1d, 0xc3, 0x41, 0xaf, 0x44, 0x4c, 0x6f, 0x28, 0x0d, 0x3f,
+        0xae
+    ]
+));
+const _: () = assert!(same(
+    Guid::from_mixed_endian(LINUX_ROOT_ARM64.to_mixed_endian()).as_bytes(),
+    LINUX_ROOT_ARM64.as_bytes()
+));
+
+// Round trips through the string form, which is formatted in lower case
+const _: () = assert!(same(
+    &EFI_SYSTEM.format(),
+    b"c12a7328-f81f-11d2-ba4b-00a0c93ec93b"
+));
+const _: () = assert!(same(
+    &LINUX_FILESYSTEM.format(),
+    b"0fc63daf-8483-4772-8e79-3d69d8477de4"
+));
+const _: () = assert!(same(
+    guid("B921B045-1DF0-41C3-AF44-4C6F280D3FAE").as_bytes(),
+    LINUX_ROOT_ARM64.as_bytes()
+));
+const _: () = assert!(same(
+    &Guid::NIL.format(),
+    b"00000000-0000-0000-0000-000000000000"
+));
+const _: () = assert!(Guid::NIL.is_nil() && !EFI_SYSTEM.is_nil());
+
+// Malformed strings: too short, misplaced dash, not a hex digit
+const _: () = assert!(Guid::parse(b"c12a7328-f81f-11d2-ba4b-00a0c93ec93").is_none());
+const _: () = assert!(Guid::parse(b"c12a7328f-81f-11d2-ba4b-00a0c93ec93b").is_none());
+const _: () = assert!(Guid::parse(b"c12a7328-f81f-11d2-ba4b-00a0c93ec93g").is_none());
@@ -0,0 +1,356 @@
+//! Streaming LZ77 compressor and decompressor
+//!
+//! This module compresses a byte stream one byte at a time into a
+//! caller-provided buffer, with a fixed, small amount of work per byte: one
+//! hash table lookup to find a match, and one comparison to extend it. It
+//! trades ratio for predictable cost, which suits compressing console output
+//! as it is printed. [`Lz77Reader`] decodes such a stream on demand, so a
+//! compressed image can be decoded straight to where it belongs.
+//!
+//! # Format
+//!
+//! The output is a sequence of tokens:
+//!
+//! - `0x00..=0x7f`: a literal run; the `token + 1` bytes that follow are
+//!   copied to the output
+//! - `0x80..=0xff`: a match; the next two bytes are a little-endian distance
+//!   `d` (1 to [`MAX_DISTANCE`]), and `(token & 0x7f) + 3` bytes are copied,
+//!   one at a time, from `d` bytes back in the output
+//!
+//! Tokens are self-contained, so the output of [`Lz77::flush`] can be
+//! decoded at any point and compression resumed afterwards.
+
+/// Bytes of history kept for matches
+const WINDOW_SIZE: usize = 4096;
+/// log2 of the number of hash table entries
+const HASH_BITS: u32 = 12;
+/// Shortest match encoded
+const MIN_MATCH: usize = 3;
+/// Longest match encoded
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+/// Longest literal run encoded
+const MAX_LITERALS: usize = 128;
+/// Farthest match encoded, keeping a whole match start in the window
+pub const MAX_DISTANCE: usize = WINDOW_SIZE - MIN_MATCH;
+/// Token flag of a match
+const MATCH_FLAG: u8 = 0x80;
+
+/// Incremental compressor writing to a fixed buffer
+///
+/// When the buffer can't hold the next token, compression stops and
+/// [`Lz77::is_full`] turns true; the output holds everything up to that
+/// token.
+pub struct Lz77 {
+    /// Last [`WINDOW_SIZE`] bytes of input, indexed by position
+    window: [u8; WINDOW_SIZE],
+    /// Position + 1 of the last occurrence of each 3-byte hash, 0 if none
+    table: [u32; 1 << HASH_BITS],
+    /// Literals not written yet
+    literals: [u8; MAX_LITERALS],
+    /// Number of valid bytes in `literals`
+    literal_len: usize,
+    /// Distance of the match being extended
+    match_distance: usize,
+    /// Length of the match being extended, 0 if none
+    match_len: usize,
+    /// Number of input bytes seen
+    pos: usize,
+    /// Number of input bytes written out as tokens
+    encoded_len: usize,
+    /// Output buffer
+    out: *mut u8,
+    /// Size of the output buffer in bytes
+    capacity: usize,
+    /// Number of bytes written to the output buffer
+    out_len: usize,
+    /// Whether the output buffer overflowed
+    full: bool,
+}
+
+impl Lz77 {
+    /// Creates a compressor without an output buffer
+    ///
+    /// Everything is dropped until [`Lz77::reset`] provides a buffer.
+    pub const fn new() -> Self {
+        return Self {
+            window: [0; WINDOW_SIZE],
+            table: [0; 1 << HASH_BITS],
+            literals: [0; MAX_LITERALS],
+            literal_len: 0,
+            match_distance: 0,
+            match_len: 0,
+            pos: 0,
+            encoded_len: 0,
+            out: core::ptr::null_mut(),
+            capacity: 0,
+            out_len: 0,
+            full: true,
+        };
+    }
+
+    /// Starts a new stream written to the `capacity` bytes at `out`
+    ///
+    /// # Safety
+    ///
+    /// The `capacity` bytes at `out` must stay writable, and not be used for
+    /// anything else, until the next reset.
+    pub unsafe fn reset(&mut self, out: *mut u8, capacity: usize) {
+        self.table.fill(0);
+        self.literal_len = 0;
+        self.match_len = 0;
+        self.pos = 0;
+        self.encoded_len = 0;
+        self.out = out;
+        self.capacity = capacity;
+        self.out_len = 0;
+        self.full = false;
+    }
+
+    /// Compresses `c`
+    pub fn push(&mut self, c: u8) {
+        let pos = self.pos;
+
+        if self.full {
+            return;
+        }
+        if self.match_len != 0 {
+            let earlier = self.window[(pos - self.match_distance) % WINDOW_SIZE];
+            if earlier == c && self.match_len < MAX_MATCH {
+                self.window[pos % WINDOW_SIZE] = c;
+                self.pos += 1;
+                self.match_len += 1;
+                return;
+            }
+            self.write_match();
+        }
+
+        self.window[pos % WINDOW_SIZE] = c;
+        self.pos += 1;
+        self.literals[self.literal_len] = c;
+        self.literal_len += 1;
+        if self.literal_len >= MIN_MATCH {
+            // Look up the 3 bytes ending here, and remember where they are
+            let start = pos + 1 - MIN_MATCH;
+            let tail = &self.literals[self.literal_len - MIN_MATCH..self.literal_len];
+            let hash = hash(tail[0], tail[1], tail[2]);
+            let candidate = self.table[hash] as usize;
+            self.table[hash] = start as u32 + 1;
+
+            if candidate != 0 && start - (candidate - 1) <= MAX_DISTANCE {
+                let earlier = candidate - 1;
+                let matches =
+                    (0..MIN_MATCH).all(|i| self.window[(earlier + i) % WINDOW_SIZE] == tail[i]);
+                if matches {
+                    self.literal_len -= MIN_MATCH;
+                    self.write_literals();
+                    self.match_distance = start - earlier;
+                    self.match_len = MIN_MATCH;
+                    return;
+                }
+            }
+        }
+        if self.literal_len == MAX_LITERALS {
+            self.write_literals();
+        }
+    }
+
+    /// Writes out the pending match or literals
+    ///
+    /// Afterwards the output decodes to all the input so far.
+    pub fn flush(&mut self) {
+        if self.match_len != 0 {
+            self.write_match();
+        }
+        self.write_literals();
+    }
+
+    /// Returns the tokens written so far
+    pub fn output(&self) -> &[u8] {
+        if self.out.is_null() {
+            return &[];
+        }
+        return unsafe { core::slice::from_raw_parts(self.out, self.out_len) };
+    }
+
+    /// Returns the number of input bytes the output decodes to
+    pub fn encoded_len(&self) -> usize {
+        return self.encoded_len;
+    }
+
+    /// Checks whether the output buffer overflowed
+    pub fn is_full(&self) -> bool {
+        return self.full;
+    }
+
+    /// Writes the pending match as a token
+    fn write_match(&mut self) {
+        let distance = (self.match_distance as u16).to_le_bytes();
+        let token = MATCH_FLAG | (self.match_len - MIN_MATCH) as u8;
+
+        if self.has_room(3) {
+            self.write(&[token, distance[0], distance[1]]);
+            self.encoded_len += self.match_len;
+        }
+        self.match_len = 0;
+    }
+
+    /// Writes the pending literals as a token
+    fn write_literals(&mut self) {
+        let len = self.literal_len;
+
+        if len == 0 {
+            return;
+        }
+        if self.has_room(1 + len) {
+            let literals = self.literals;
+            self.write(&[(len - 1) as u8]);
+            self.write(&literals[..len]);
+            self.encoded_len += len;
+        }
+        self.literal_len = 0;
+    }
+
+    /// Checks whether `len` more bytes fit in the output
+    ///
+    /// Stops compressing if they don't.
+    fn has_room(&mut self, len: usize) -> bool {
+        if self.out_len + len > self.capacity {
+            self.full = true;
+        }
+        return !self.full;
+    }
+
+    /// Appends `bytes` to the output, which must have room for them
+    fn write(&mut self, bytes: &[u8]) {
+        unsafe {
+            let dst = self.out.add(self.out_len);
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len());
+        }
+        self.out_len += bytes.len();
+    }
+}
+
+impl Default for Lz77 {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Incremental decompressor reading tokens from a buffer
+///
+/// Output is produced on demand, keeping the last [`WINDOW_SIZE`] bytes for
+/// the matches to copy from. A match reaching before the start of the
+/// output, or a token cut short, ends the stream.
+pub struct Lz77Reader<'a> {
+    /// Tokens to decode
+    input: &'a [u8],
+    /// Offset of the next input byte
+    in_pos: usize,
+    /// Last [`WINDOW_SIZE`] bytes of output, indexed by position
+    window: [u8; WINDOW_SIZE],
+    /// Number of output bytes produced
+    out_pos: usize,
+    /// Bytes left in the literal run being copied
+    literals: usize,
+    /// Bytes left in the match being copied
+    match_len: usize,
+    /// Distance of the match being copied
+    match_distance: usize,
+}
+
+impl<'a> Lz77Reader<'a> {
+    /// Creates a decompressor for the tokens in `input`
+    pub const fn new(input: &'a [u8]) -> Self {
+        return Self {
+            input,
+            in_pos: 0,
+            window: [0; WINDOW_SIZE],
+            out_pos: 0,
+            literals: 0,
+            match_len: 0,
+            match_distance: 0,
+        };
+    }
+
+    /// Returns the number of bytes decoded so far
+    pub fn position(&self) -> usize {
+        return self.out_pos;
+    }
+
+    /// Starts decoding again from the first token
+    pub fn rewind(&mut self) {
+        self.in_pos = 0;
+        self.out_pos = 0;
+        self.literals = 0;
+        self.match_len = 0;
+    }
+
+    /// Decodes the next byte, or returns `None` at the end of the stream
+    pub fn next_byte(&mut self) -> Option<u8> {
+        if self.literals == 0 && self.match_len == 0 && !self.next_token() {
+            // Anything after a corrupted token is garbage
+            self.in_pos = self.input.len();
+            return None;
+        }
+
+        let c = if self.literals != 0 {
+            self.literals -= 1;
+            self.in_pos += 1;
+            self.input[self.in_pos - 1]
+        } else {
+            self.match_len -= 1;
+            self.window[(self.out_pos - self.match_distance) % WINDOW_SIZE]
+        };
+        self.window[self.out_pos % WINDOW_SIZE] = c;
+        self.out_pos += 1;
+        return Some(c);
+    }
+
+    /// Decodes bytes into `buf`, and returns how many, fewer than its length
+    /// only at the end of the stream
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        for (i, b) in buf.iter_mut().enumerate() {
+            match self.next_byte() {
+                Some(c) => *b = c,
+                None => return i,
+            }
+        }
+        return buf.len();
+    }
+
+    /// Starts the next token, returning `false` at the end of the input or
+    /// if the token is corrupted
+    fn next_token(&mut self) -> bool {
+        let Some(&token) = self.input.get(self.in_pos) else {
+            return false;
+        };
+
+        if token & MATCH_FLAG == 0 {
+            let len = token as usize + 1;
+            if self.in_pos + 1 + len > self.input.len() {
+                return false;
+            }
+            self.in_pos += 1;
+            self.literals = len;
+            return true;
+        }
+        let Some(&[low, high]) = self.input.get(self.in_pos + 1..self.in_pos + 3) else {
+            return false;
+        };
+        let distance = u16::from_le_bytes([low, high]) as usize;
+        if distance == 0 || distance > MAX_DISTANCE || distance > self.out_pos {
+            return false;
+        }
+        self.in_pos += 3;
+        self.match_distance = distance;
+        self.match_len = (token & !MATCH_FLAG) as usize + MIN_MATCH;
+        return true;
+    }
+}
+
+/// Hashes 3 bytes into a table index
+fn hash(a: u8, b: u8, c: u8) -> usize {
+    let value = (a as u32) << 16 | (b as u32) << 8 | c as u32;
+
+    return (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+}
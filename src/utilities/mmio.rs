@@ -4,9 +4,25 @@
 //! interacting with memory-mapped hardware registers. All functions use
 //! volatile reads and writes to ensure the compiler doesn't optimize away
 //! hardware accesses.
+//!
+//! Drivers can also be written against the [`MmioBus`] trait, so the same
+//! code drives the hardware through [`PhysBus`] and can be checked
+//! off-target against the mock bus, built for the host tests and with the
+//! `mock` feature.
+//!
+//! Addresses that may not be backed by anything can be tested with
+//! [`probe_read32`], which turns the resulting data abort or SError into a
+//! [`ProbeError`].
+
+use crate::exception::{self, Regs, VectorTable};
+use crate::selftest::Outcome;
 
+use crate::asm;
 use core::ptr::{read_volatile, write_volatile};
 
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
 /// Reads a 32-bit value from a memory-mapped I/O register
 ///
 /// Performs a volatile read from the register at `base + offset`. The volatile
@@ -25,6 +41,140 @@ pub unsafe fn read_mmio32(base: usize, offset: usize) -> u32 {
     }
 }
 
+/// Errors reported by [`probe_read32`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeError {
+    /// The read was aborted synchronously: nothing decodes the address
+    DecodeError,
+    /// The read raised an SError, which is how interconnects usually report
+    /// a device that never responds
+    Timeout,
+    /// A probe is already in progress (e.g., probing from an exception
+    /// handler that interrupted one)
+    Busy,
+}
+
+/// Address being read by [`probe_read32`], if a probe is in progress
+static mut PROBE_ADDR: Option<usize> = None;
+/// Set by the probe's exception handlers when the read failed
+static mut PROBE_ERROR: Option<ProbeError> = None;
+
+/// Reads the 32-bit register at `base + offset`, reporting if it isn't there
+///
+/// This is the classic bus probe used in device discovery. For the duration
+/// of the read, the full exception vectors and temporary data abort and
+/// SError handlers are installed, and SErrors are unmasked. The read is
+/// followed by a `dsb` and an `isb`, so an asynchronous external abort it
+/// causes is taken within that window. An abort on the probed address
+/// resumes past the load; aborts on any other address are still fatal.
+///
+/// Probes don't nest: one started while another is in progress fails with
+/// [`ProbeError::Busy`]. `base + offset` must be 4-byte aligned.
+pub fn probe_read32(base: usize, offset: usize) -> Result<u32, ProbeError> {
+    let addr = base + offset;
+    let value: u32;
+    let daif: u64;
+
+    unsafe {
+        let current = PROBE_ADDR;
+        if current.is_some() {
+            return Err(ProbeError::Busy);
+        }
+        PROBE_ADDR = Some(addr);
+        PROBE_ERROR = None;
+    }
+    let vectors = exception::install_vectors(VectorTable::Full);
+    let abort_handler = exception::set_data_abort_handler(Some(probe_abort));
+    let serror_handler = exception::set_serror_handler(Some(probe_serror));
+
+    // A single known load, so the abort handler can skip exactly one
+    // instruction, then a synchronization point for a pending SError
+    unsafe {
+        asm!("mrs {}, daif", out(reg) daif);
+        asm!(
+            "msr daifclr, #4",
+            "ldr {value:w}, [{addr}]",
+            "dsb sy",
+            "isb",
+            value = out(reg) value,
+            addr = in(reg) addr,
+        );
+        asm!("msr daif, {}", in(reg) daif);
+    }
+
+    exception::set_serror_handler(serror_handler);
+    exception::set_data_abort_handler(abort_handler);
+    if let Some(vectors) = vectors {
+        exception::install_vectors(vectors);
+    }
+    unsafe {
+        PROBE_ADDR = None;
+        if let Some(error) = PROBE_ERROR {
+            return Err(error);
+        }
+    }
+    return Ok(value);
+}
+
+/// Data abort handler installed by [`probe_read32`]
+///
+/// Recovers from an abort on the probed address by skipping the load. An
+/// external abort may not report a valid fault address, in which case the
+/// abort is attributed to the probe.
+fn probe_abort(regs: &mut Regs, far: u64) -> bool {
+    unsafe {
+        if !exception::far_not_valid(regs.esr) && PROBE_ADDR != Some(far as usize) {
+            return false;
+        }
+        PROBE_ERROR = Some(ProbeError::DecodeError);
+    }
+    regs.elr += 4;
+    return true;
+}
+
+/// SError handler installed by [`probe_read32`]
+///
+/// Records the SError as the probe's; execution resumes where it was taken.
+fn probe_serror(_regs: &mut Regs) -> bool {
+    unsafe {
+        PROBE_ERROR = Some(ProbeError::Timeout);
+    }
+    return true;
+}
+
+/// Address no bus decodes: bits 52 to 55 are above the largest physical
+/// address space, so reading it takes an address size or translation fault
+const TEST_UNBACKED_ADDR: usize = 0x00f0_0000_0000_0000;
+
+/// Word read back by [`selftest_probe`]
+static TEST_PROBE_WORD: u32 = 0x5eed_c0de;
+
+/// Self-test: [`probe_read32`] returns the value of a readable word, reports
+/// an address nothing decodes as [`ProbeError::DecodeError`] without
+/// crashing, and refuses to start while another probe is in progress
+pub fn selftest_probe() -> Outcome {
+    let word = &raw const TEST_PROBE_WORD as usize;
+
+    if probe_read32(word, 0) != Ok(TEST_PROBE_WORD) {
+        return Outcome::Fail;
+    }
+    if probe_read32(TEST_UNBACKED_ADDR, 0) != Err(ProbeError::DecodeError) {
+        return Outcome::Fail;
+    }
+
+    unsafe {
+        PROBE_ADDR = Some(word);
+    }
+    let nested = probe_read32(word, 0);
+    unsafe {
+        PROBE_ADDR = None;
+    }
+    if nested != Err(ProbeError::Busy) || probe_read32(word, 0) != Ok(TEST_PROBE_WORD) {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
+
 /// Writes a 32-bit value to a memory-mapped I/O register
 ///
 /// Performs a volatile write to the register at `base + offset`. The volatile
@@ -43,3 +193,334 @@ pub unsafe fn write_mmio32(base: usize, offset: usize, value: u32) {
         write_volatile(ptr, value);
     }
 }
+
+/// Tests a single bit of a 32-bit memory-mapped I/O register
+///
+/// Reads the register at `base + offset` and returns whether bit number
+/// `bit` (0 being the least significant) is set.
+///
+/// # Safety
+///
+/// Same requirements as [`read_mmio32`].
+pub unsafe fn test_bit32(base: usize, offset: usize, bit: u32) -> bool {
+    unsafe {
+        return PhysBus.test_bit32(base + offset, bit);
+    }
+}
+
+/// Width of a memory-mapped I/O access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    B8,
+    B16,
+    B32,
+    B64,
+}
+
+impl Width {
+    /// Returns the width of an access of `bytes` bytes, if there is one
+    pub const fn from_bytes(bytes: u64) -> Option<Width> {
+        return match bytes {
+            1 => Some(Width::B8),
+            2 => Some(Width::B16),
+            4 => Some(Width::B32),
+            8 => Some(Width::B64),
+            _ => None,
+        };
+    }
+
+    /// Returns the size of the access in bytes
+    pub const fn bytes(self) -> usize {
+        return match self {
+            Width::B8 => 1,
+            Width::B16 => 2,
+            Width::B32 => 4,
+            Width::B64 => 8,
+        };
+    }
+}
+
+const _: () = assert!(matches!(Width::from_bytes(1), Some(Width::B8)));
+const _: () = assert!(matches!(Width::from_bytes(2), Some(Width::B16)));
+const _: () = assert!(matches!(Width::from_bytes(4), Some(Width::B32)));
+const _: () = assert!(matches!(Width::from_bytes(8), Some(Width::B64)));
+const _: () = assert!(Width::from_bytes(0).is_none() && Width::from_bytes(3).is_none());
+const _: () = assert!(Width::from_bytes(16).is_none() && Width::from_bytes(u64::MAX).is_none());
+const _: () = assert!(Width::B8.bytes() == 1 && Width::B16.bytes() == 2);
+const _: () = assert!(Width::B32.bytes() == 4 && Width::B64.bytes() == 8);
+
+/// Reads a memory-mapped I/O register of the given width
+///
+/// Performs a single volatile access of `width` at `base + offset` and
+/// returns the value zero-extended to 64 bits.
+///
+/// # Safety
+///
+/// The caller must ensure `base + offset` points to a valid, accessible
+/// register of `width`, aligned to its size.
+pub unsafe fn read_mmio(base: usize, offset: usize, width: Width) -> u64 {
+    unsafe {
+        return read_mmio_on(&mut PhysBus, base + offset, width);
+    }
+}
+
+/// Reads the register of `width` at `addr` through `bus`, as [`read_mmio`]
+///
+/// # Safety
+///
+/// See [`read_mmio`].
+unsafe fn read_mmio_on<B: MmioBus>(bus: &mut B, addr: usize, width: Width) -> u64 {
+    unsafe {
+        return match width {
+            Width::B8 => bus.read8(addr) as u64,
+            Width::B16 => bus.read16(addr) as u64,
+            Width::B32 => bus.read32(addr) as u64,
+            Width::B64 => bus.read64(addr),
+        };
+    }
+}
+
+/// Writes a memory-mapped I/O register of the given width
+///
+/// Performs a single volatile access of `width` at `base + offset`. Only the
+/// low `width` bits of `value` are written.
+///
+/// # Safety
+///
+/// The caller must ensure `base + offset` points to a valid, writable
+/// register of `width`, aligned to its size, and that writing `value` won't
+/// cause undefined behavior or system instability.
+pub unsafe fn write_mmio(base: usize, offset: usize, width: Width, value: u64) {
+    unsafe {
+        write_mmio_on(&mut PhysBus, base + offset, width, value);
+    }
+}
+
+/// Writes the register of `width` at `addr` through `bus`, as
+/// [`write_mmio`]
+///
+/// # Safety
+///
+/// See [`write_mmio`].
+unsafe fn write_mmio_on<B: MmioBus>(bus: &mut B, addr: usize, width: Width, value: u64) {
+    unsafe {
+        match width {
+            Width::B8 => bus.write8(addr, value as u8),
+            Width::B16 => bus.write16(addr, value as u16),
+            Width::B32 => bus.write32(addr, value as u32),
+            Width::B64 => bus.write64(addr, value),
+        }
+    }
+}
+
+/// Self-test: [`MmioBus::test_bit32`], run on a [`MockBus`], reports set
+/// and clear bits at both ends and in the middle of the register, reading
+/// it once and writing nothing
+///
+/// [`MockBus`]: mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_test_bit() -> Outcome {
+    use mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const ADDR: usize = 0x1000;
+    // Register value, bit tested, whether it is set
+    const CASES: [(u32, u32, bool); 8] = [
+        (0x0000_0001, 0, true),
+        (0xffff_fffe, 0, false),
+        (0x8000_0000, 31, true),
+        (0x7fff_ffff, 31, false),
+        (0x0000_0020, 5, true),
+        (0xffff_ffdf, 5, false),
+        (0xffff_ffff, 17, true),
+        (0x0000_0000, 17, false),
+    ];
+
+    for (value, bit, set) in CASES {
+        let mut bus = MockBus::new();
+
+        bus.set_read(ADDR, value as u64);
+        let result = unsafe { bus.test_bit32(ADDR, bit) };
+        let log = bus.log();
+        let single = log.len() == 1
+            && log[0].kind == AccessKind::Read
+            && log[0].addr == ADDR
+            && log[0].width == 4;
+        if result != set || !single {
+            return Outcome::Fail;
+        }
+    }
+    return Outcome::Pass;
+}
+
+/// Self-test: [`write_mmio`] and [`read_mmio`], run on a [`MockBus`], make
+/// a single access of each width, writing only the low bits of the value
+/// and zero-extending what they read
+///
+/// [`MockBus`]: mock::MockBus
+#[cfg(feature = "mock")]
+pub fn selftest_width() -> Outcome {
+    use mock::{AccessKind, MockBus};
+
+    // Never accessed: the mock only records the addresses
+    const ADDR: usize = 0x1000;
+    const VALUE: u64 = 0x8877_6655_4433_2211;
+    let widths = [Width::B8, Width::B16, Width::B32, Width::B64];
+
+    for width in widths {
+        let mut bus = MockBus::new();
+        let bits = width.bytes() as u32 * 8;
+        let low = VALUE & (u64::MAX >> (64 - bits));
+
+        bus.expect_write(ADDR, width.bytes() as u8, low);
+        let read = unsafe {
+            write_mmio_on(&mut bus, ADDR, width, VALUE);
+            bus.set_read(ADDR, VALUE);
+            read_mmio_on(&mut bus, ADDR, width)
+        };
+        let log = bus.log();
+        let single = log.len() == 2
+            && log[1].kind == AccessKind::Read
+            && log[1].width as usize == width.bytes();
+        if !bus.expectations_met() || !single || read != low {
+            return Outcome::Fail;
+        }
+    }
+    return Outcome::Pass;
+}
+
+/// Bus through which drivers access their registers
+///
+/// Drivers that are generic over the bus can be exercised off-target with a
+/// mock implementation (see `mock::MockBus` in the host tests or with the
+/// `mock` feature), while on the target [`PhysBus`] compiles down to the
+/// same volatile accesses as [`read_mmio32`] and [`write_mmio32`].
+///
+/// # Safety
+///
+/// Callers of the methods must uphold the same requirements as for
+/// [`read_mmio32`] and [`write_mmio32`] on the physical bus, `addr` being the
+/// full register address.
+pub trait MmioBus {
+    /// Reads an 8-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn read8(&mut self, addr: usize) -> u8;
+    /// Reads a 16-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn read16(&mut self, addr: usize) -> u16;
+    /// Reads a 32-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn read32(&mut self, addr: usize) -> u32;
+    /// Reads a 64-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn read64(&mut self, addr: usize) -> u64;
+    /// Writes an 8-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn write8(&mut self, addr: usize, value: u8);
+    /// Writes a 16-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn write16(&mut self, addr: usize, value: u16);
+    /// Writes a 32-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn write32(&mut self, addr: usize, value: u32);
+    /// Writes a 64-bit register at `addr`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn write64(&mut self, addr: usize, value: u64);
+
+    /// Reads the 32-bit register at `addr` and tests bit number `bit`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmioBus`].
+    unsafe fn test_bit32(&mut self, addr: usize, bit: u32) -> bool {
+        unsafe {
+            return self.read32(addr) & (1 << bit) != 0;
+        }
+    }
+}
+
+/// The physical bus: volatile accesses to the given addresses
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PhysBus;
+
+impl MmioBus for PhysBus {
+    #[inline(always)]
+    unsafe fn read8(&mut self, addr: usize) -> u8 {
+        unsafe {
+            return read_volatile(addr as *const u8);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn read16(&mut self, addr: usize) -> u16 {
+        unsafe {
+            return read_volatile(addr as *const u16);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn read32(&mut self, addr: usize) -> u32 {
+        unsafe {
+            return read_volatile(addr as *const u32);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn read64(&mut self, addr: usize) -> u64 {
+        unsafe {
+            return read_volatile(addr as *const u64);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn write8(&mut self, addr: usize, value: u8) {
+        unsafe {
+            write_volatile(addr as *mut u8, value);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn write16(&mut self, addr: usize, value: u16) {
+        unsafe {
+            write_volatile(addr as *mut u16, value);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn write32(&mut self, addr: usize, value: u32) {
+        unsafe {
+            write_volatile(addr as *mut u32, value);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn write64(&mut self, addr: usize, value: u64) {
+        unsafe {
+            write_volatile(addr as *mut u64, value);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Mock MMIO bus
+//!
+//! [`MockBus`] stands in for the physical bus when driver code is exercised
+//! off-target. It keeps a small register map whose read values can be
+//! programmed, records every access in order, and checks writes against a
+//! list of expectations. Everything is stored in fixed-size tables so it
+//! works without an allocator.
+
+use super::MmioBus;
+
+/// Maximum number of distinct registers the mock keeps values for
+pub const MOCK_REGS: usize = 32;
+/// Maximum number of accesses recorded, and of expected writes
+pub const MOCK_LOG: usize = 128;
+
+/// Direction of a recorded access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    /// A register read
+    Read,
+    /// A register write
+    Write,
+}
+
+/// A recorded (or expected) register access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    /// Read or write
+    pub kind: AccessKind,
+    /// Address of the register
+    pub addr: usize,
+    /// Access width in bytes
+    pub width: u8,
+    /// Value read or written
+    pub value: u64,
+}
+
+/// Empty access, used to fill the tables
+const NO_ACCESS: Access = Access {
+    kind: AccessKind::Read,
+    addr: 0,
+    width: 0,
+    value: 0,
+};
+
+/// A bus backed by a register map, with an access log and write expectations
+pub struct MockBus {
+    /// `(addr, value)` of the registers read or written so far
+    regs: [(usize, u64); MOCK_REGS],
+    reg_count: usize,
+    /// Every access, in order
+    log: [Access; MOCK_LOG],
+    log_len: usize,
+    /// Writes expected, in order
+    expected: [Access; MOCK_LOG],
+    expected_len: usize,
+    /// Index of the next expected write
+    next_expected: usize,
+    /// Index in the log of the first write that didn't match expectations
+    mismatch: Option<usize>,
+}
+
+impl Default for MockBus {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl MockBus {
+    /// Creates a bus where every register reads as 0 and no write is expected
+    pub const fn new() -> Self {
+        return MockBus {
+            regs: [(0, 0); MOCK_REGS],
+            reg_count: 0,
+            log: [NO_ACCESS; MOCK_LOG],
+            log_len: 0,
+            expected: [NO_ACCESS; MOCK_LOG],
+            expected_len: 0,
+            next_expected: 0,
+            mismatch: None,
+        };
+    }
+
+    /// Sets the value reads of the register at `addr` return
+    ///
+    /// Writes to the register also update it.
+    pub fn set_read(&mut self, addr: usize, value: u64) {
+        if let Some(reg) = self.regs[..self.reg_count].iter_mut().find(|r| r.0 == addr) {
+            reg.1 = value;
+        } else if self.reg_count < MOCK_REGS {
+            self.regs[self.reg_count] = (addr, value);
+            self.reg_count += 1;
+        }
+    }
+
+    /// Adds a write the code under test is expected to perform next
+    pub fn expect_write(&mut self, addr: usize, width: u8, value: u64) {
+        if self.expected_len < MOCK_LOG {
+            self.expected[self.expected_len] = Access {
+                kind: AccessKind::Write,
+                addr,
+                width,
+                value,
+            };
+            self.expected_len += 1;
+        }
+    }
+
+    /// Returns every access performed so far, in order
+    pub fn log(&self) -> &[Access] {
+        return &self.log[..self.log_len];
+    }
+
+    /// Returns an iterator over the writes performed so far, in order
+    pub fn writes(&self) -> impl Iterator<Item = &Access> {
+        return self.log().iter().filter(|a| a.kind == AccessKind::Write);
+    }
+
+    /// Returns the log index of the first write that didn't match, if any
+    pub fn mismatch(&self) -> Option<usize> {
+        return self.mismatch;
+    }
+
+    /// Checks that exactly the expected writes were performed, in order
+    pub fn expectations_met(&self) -> bool {
+        return self.mismatch.is_none() && self.next_expected == self.expected_len;
+    }
+
+    /// Returns the current value of the register at `addr`
+    fn value(&self, addr: usize) -> u64 {
+        return self.regs[..self.reg_count]
+            .iter()
+            .find(|r| r.0 == addr)
+            .map_or(0, |r| r.1);
+    }
+
+    /// Records an access
+    fn record(&mut self, kind: AccessKind, addr: usize, width: u8, value: u64) {
+        let access = Access {
+            kind,
+            addr,
+            width,
+            value,
+        };
+
+        if kind == AccessKind::Write {
+            let expected = self.expected[..self.expected_len].get(self.next_expected);
+            if expected == Some(&access) {
+                self.next_expected += 1;
+            } else if self.mismatch.is_none() {
+                self.mismatch = Some(self.log_len);
+            }
+            self.set_read(addr, value);
+        }
+        if self.log_len < MOCK_LOG {
+            self.log[self.log_len] = access;
+            self.log_len += 1;
+        }
+    }
+
+    /// Performs a read of `width` bytes
+    fn read(&mut self, addr: usize, width: u8) -> u64 {
+        let value = self.value(addr);
+
+        self.record(AccessKind::Read, addr, width, value);
+        return value;
+    }
+}
+
+impl MmioBus for MockBus {
+    unsafe fn read8(&mut self, addr: usize) -> u8 {
+        return self.read(addr, 1) as u8;
+    }
+
+    unsafe fn read16(&mut self, addr: usize) -> u16 {
+        return self.read(addr, 2) as u16;
+    }
+
+    unsafe fn read32(&mut self, addr: usize) -> u32 {
+        return self.read(addr, 4) as u32;
+    }
+
+    unsafe fn read64(&mut self, addr: usize) -> u64 {
+        return self.read(addr, 8);
+    }
+
+    unsafe fn write8(&mut self, addr: usize, value: u8) {
+        self.record(AccessKind::Write, addr, 1, value as u64);
+    }
+
+    unsafe fn write16(&mut self, addr: usize, value: u16) {
+        self.record(AccessKind::Write, addr, 2, value as u64);
+    }
+
+    unsafe fn write32(&mut self, addr: usize, value: u32) {
+        self.record(AccessKind::Write, addr, 4, value as u64);
+    }
+
+    unsafe fn write64(&mut self, addr: usize, value: u64) {
+        self.record(AccessKind::Write, addr, 8, value);
+    }
+}
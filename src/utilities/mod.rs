@@ -6,15 +6,105 @@
 //!
 //! # Available Utilities
 //!
+//! - [`aes`]: AES-256 and AES-256-GCM
+//!   - Software implementation, or the ARMv8 AES instructions when present
+//!   - Used to decrypt encrypted payloads
+//!
+//! - [`crc32`]: CRC-32 checksum
+//!   - Incremental, table-free implementation of the IEEE CRC-32
+//!   - Used to detect corruption of records kept in memory
+//!
+//! - [`disasm`]: Minimal A64 instruction decoder
+//!   - Decodes common instruction classes into a mnemonic and operands
+//!   - Used by exception handlers to show the faulting instruction
+//!
+//! - [`guid`]: GUID type and well-known GUIDs
+//!   - Parsing and formatting of the canonical string form
+//!   - Conversion from and to the mixed-endian byte order of GPT and UEFI
+//!   - Well-known GPT partition types
+//!   - Used to tag the components in the BootInfo block
+//!
+//! - [`lz77`]: Streaming LZ77 compressor
+//!   - Compresses one byte at a time with a fixed cost per byte
+//!   - Used to capture the console output
+//!
 //! - [`mmio`]: Memory-mapped I/O operations
 //!   - Safe wrappers for volatile memory reads and writes
-//!   - Bit manipulation helpers (set/clear bits)
+//!   - Bit test helper for single-flag checks
+//!   - Accesses of a width chosen at run time
+//!   - Bus probe that survives reads of unbacked addresses
 //!   - Used by hardware drivers to access device registers
 //!
-//! - [`print`]: Hexadecimal printing utilities
+//! - [`print`]: Integer formatting and printing utilities
 //!   - Format and print u64 and u8 values in hexadecimal
+//!   - Format u64 values as hex or decimal into caller buffers
+//!   - Print fixed-point decimal values without floating point
+//!   - Print heartbeat dots to show progress in long loops
+//!   - Hex dump memory ranges with an ASCII gutter, 8, 16 or 32 bytes per line
+//!   - Console trait, to write to the UART or capture output in memory
 //!   - Used by exception handlers for debugging output
 //!   - Operates directly on UART without requiring formatting traits
+//!
+//! - [`readline`]: Line input from the console
+//!   - Reads a line from UART with echo and basic editing
+//!   - Used by the interactive monitor
+//!
+//! - [`rng`]: Random numbers
+//!   - RNDR register of cores with FEAT_RNG, or a mix of the counter
+//!   - Used to generate the boot ID
+//!
+//! - [`sha256`]: SHA-256 hash
+//!   - Incremental, software implementation
+//!   - Used by measured boot and the monitor's `sha256` command
+//!
+//! - [`semihosting`]: Arm semihosting calls
+//!   - Exit the emulator with a status
+//!   - Used to report the self-test result to a CI host
 
+use crate::selftest::{self, SelfTest};
+
+pub mod aes;
+pub mod crc32;
+pub mod disasm;
+pub mod guid;
+pub mod lz77;
 pub mod mmio;
 pub mod print;
+pub mod readline;
+pub mod rng;
+pub mod semihosting;
+pub mod sha256;
+
+/// Registers the self-tests of the utilities
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"aes-gcm",
+        run: aes::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"disasm",
+        run: disasm::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"sha256",
+        run: sha256::selftest,
+    });
+    selftest::register(SelfTest {
+        name: b"heartbeat",
+        run: print::selftest_heartbeat,
+    });
+    selftest::register(SelfTest {
+        name: b"mmio-probe",
+        run: mmio::selftest_probe,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"mmio-width",
+        run: mmio::selftest_width,
+    });
+    #[cfg(feature = "mock")]
+    selftest::register(SelfTest {
+        name: b"mmio-test-bit",
+        run: mmio::selftest_test_bit,
+    });
+}
@@ -1,23 +1,195 @@
-//! Hexadecimal printing utilities
+//! Integer formatting and printing utilities
 //!
 //! This module provides functions to format and print integer values in
-//! hexadecimal format. These are particularly useful for debugging and
-//! exception handling where standard formatting traits are not available
+//! hexadecimal and decimal format. These are particularly useful for debugging
+//! and exception handling where standard formatting traits are not available
 //! in a `no_std` environment.
 //!
-//! All functions output directly to the UART using the PL011 driver.
+//! The `print_*` functions output directly to the UART using the PL011
+//! driver, while the `*_to_*` functions format into a caller-provided buffer
+//! so the result can be combined into larger messages. The `write_*`
+//! functions output to any [`Console`], such as a [`CaptureSink`] keeping
+//! the output in memory to compare it with an expected one.
 
 use crate::drivers::uart::pl011;
+use crate::selftest::Outcome;
 
 /// Lookup table for hexadecimal digit conversion
-const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+pub const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+/// Destination of formatted output
+///
+/// Formatting code written against a console produces the same bytes
+/// whether they go to the UART ([`Uart`]) or into memory ([`CaptureSink`]).
+pub trait Console {
+    /// Writes `bytes`
+    fn write(&mut self, bytes: &[u8]);
+
+    /// Writes `bytes` followed by a newline
+    fn writeln(&mut self, bytes: &[u8]) {
+        self.write(bytes);
+        self.write(b"\n");
+    }
+}
+
+/// The console UART, see [`pl011::print`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Uart;
+
+impl Console for Uart {
+    fn write(&mut self, bytes: &[u8]) {
+        pl011::print(bytes);
+    }
+}
+
+/// Console keeping its output in a caller-provided buffer
+///
+/// Output past the end of the buffer is dropped and remembered, so a
+/// truncated capture is never taken for a complete one.
+#[derive(Debug)]
+pub struct CaptureSink<'a> {
+    /// Where the output goes
+    buf: &'a mut [u8],
+    /// Number of bytes captured
+    len: usize,
+    /// Whether output was dropped
+    overflowed: bool,
+}
+
+impl<'a> CaptureSink<'a> {
+    /// Returns an empty sink capturing into `buf`
+    pub fn new(buf: &'a mut [u8]) -> CaptureSink<'a> {
+        return CaptureSink {
+            buf,
+            len: 0,
+            overflowed: false,
+        };
+    }
+
+    /// Returns the bytes captured so far
+    pub fn bytes(&self) -> &[u8] {
+        return &self.buf[..self.len];
+    }
+
+    /// Checks whether output was dropped for lack of room
+    pub fn overflowed(&self) -> bool {
+        return self.overflowed;
+    }
+
+    /// Forgets the bytes captured so far
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.overflowed = false;
+    }
+}
+
+impl Console for CaptureSink<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        let room = self.buf.len() - self.len;
+        let n = bytes.len().min(room);
+
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+        if n < bytes.len() {
+            self.overflowed = true;
+        }
+    }
+}
+
+/// Number of calls to `heartbeat` so far
+static mut HEARTBEAT_COUNT: u64 = 0;
+/// Percentage last printed by [`progress`], `u64::MAX` between copies
+static mut PROGRESS_PERCENT: u64 = u64::MAX;
+
+/// Formats a u64 value as hexadecimal into a caller-provided buffer
+///
+/// Digits are written right-aligned into `buf` without leading zeros or a
+/// `0x` prefix, and the populated sub-slice is returned (e.g., `2a` for the
+/// value 42). A buffer of 16 bytes is always large enough; if `buf` is
+/// shorter, only the least significant digits that fit are kept.
+pub const fn u64_to_hex(mut value: u64, buf: &mut [u8]) -> &[u8] {
+    let mut pos = buf.len();
+
+    // Convert to hex digits (right to left)
+    while pos > 0 {
+        pos -= 1;
+        buf[pos] = HEX_CHARS[(value & 0xF) as usize];
+        value >>= 4;
+        if value == 0 {
+            break;
+        }
+    }
+
+    return buf.split_at(pos).1;
+}
+
+/// Formats a u64 value as decimal into a caller-provided buffer
+///
+/// Digits are written right-aligned into `buf` without leading zeros, and the
+/// populated sub-slice is returned (e.g., `42` for the value 42). A buffer of
+/// 20 bytes is always large enough; if `buf` is shorter, only the least
+/// significant digits that fit are kept.
+pub const fn u64_to_dec(mut value: u64, buf: &mut [u8]) -> &[u8] {
+    let mut pos = buf.len();
+
+    // Convert to decimal digits (right to left)
+    while pos > 0 {
+        pos -= 1;
+        buf[pos] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+
+    return buf.split_at(pos).1;
+}
+
+/// Formats `value` with [`u64_to_hex`], or [`u64_to_dec`] if `dec`, into a
+/// buffer of `len` bytes and compares the digits with `expected`, in a const
+/// context
+const fn digits_are(value: u64, dec: bool, len: usize, expected: &[u8]) -> bool {
+    let mut buf = [0u8; 20];
+    let buf = buf.split_at_mut(len).0;
+    let digits = if dec {
+        u64_to_dec(value, buf)
+    } else {
+        u64_to_hex(value, buf)
+    };
+    let mut i = 0;
+
+    if digits.len() != expected.len() {
+        return false;
+    }
+    while i < digits.len() {
+        if digits[i] != expected[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(digits_are(0, false, 16, b"0") && digits_are(0x2a, false, 16, b"2a"));
+const _: () = assert!(digits_are(u64::MAX, false, 16, b"ffffffffffffffff"));
+// A short buffer keeps the least significant digits
+const _: () = assert!(digits_are(0x1234_5678, false, 3, b"678"));
+const _: () = assert!(digits_are(0, true, 20, b"0") && digits_are(42, true, 20, b"42"));
+const _: () = assert!(digits_are(u64::MAX, true, 20, b"18446744073709551615"));
+const _: () = assert!(digits_are(123_456, true, 4, b"3456"));
 
 /// Prints a u64 value as a 16-digit hexadecimal number to UART
 ///
 /// This function formats the value as a zero-padded 16-character hexadecimal
 /// string (e.g., `0000000000000042` for the value 66).
 ///
-pub fn print_hex_u64(mut value: u64) {
+pub fn print_hex_u64(value: u64) {
+    write_hex_u64(&mut Uart, value);
+}
+
+/// Writes a u64 value as a 16-digit hexadecimal number to `out`, as
+/// [`print_hex_u64`] prints it
+pub fn write_hex_u64(out: &mut dyn Console, mut value: u64) {
     let mut buf = [0u8; 16];
 
     // Convert to hex digits (right to left)
@@ -26,7 +198,7 @@ pub fn print_hex_u64(mut value: u64) {
         value >>= 4;
     }
 
-    pl011::print(&buf);
+    out.write(&buf);
 }
 
 /// Prints an 8-bit value as a 2-digit hexadecimal number to UART
@@ -34,6 +206,12 @@ pub fn print_hex_u64(mut value: u64) {
 /// This function formats the value as a zero-padded 2-character hexadecimal
 /// string using uppercase letters (e.g., `2A` for the value 42).
 pub fn print_hex_u8(val: u8) {
+    write_hex_u8(&mut Uart, val);
+}
+
+/// Writes an 8-bit value as a 2-digit hexadecimal number to `out`, as
+/// [`print_hex_u8`] prints it
+pub fn write_hex_u8(out: &mut dyn Console, val: u8) {
     let mut buf = [0u8; 2];
 
     for i in 0..2 {
@@ -45,5 +223,342 @@ pub fn print_hex_u8(val: u8) {
         };
     }
 
+    out.write(&buf);
+}
+
+/// Longest output of [`format_fixed`]: 20 integer digits, the decimal point
+/// and 19 fractional digits
+const FIXED_MAX: usize = 20 + 1 + 19;
+
+/// Formats `value`, scaled by `10^frac_digits`, as [`print_fixed`] prints it
+/// into `out` and returns its length
+const fn format_fixed(value: u64, frac_digits: u8, out: &mut [u8; FIXED_MAX]) -> usize {
+    let mut digits = [0u8; 20];
+    let frac_digits = if frac_digits > 19 {
+        19
+    } else {
+        frac_digits as usize
+    };
+    let scale = 10u64.pow(frac_digits as u32);
+    let int = u64_to_dec(value / scale, &mut digits);
+    let mut len = 0;
+    let mut i = 0;
+
+    while i < int.len() {
+        out[len] = int[i];
+        len += 1;
+        i += 1;
+    }
+    if frac_digits == 0 {
+        return len;
+    }
+
+    // Zero-pad the fractional part on the left up to frac_digits
+    let frac = u64_to_dec(value % scale, digits.split_at_mut(frac_digits).0);
+    out[len] = b'.';
+    len += 1;
+    i = 0;
+    while i < frac_digits - frac.len() {
+        out[len] = b'0';
+        len += 1;
+        i += 1;
+    }
+    i = 0;
+    while i < frac.len() {
+        out[len] = frac[i];
+        len += 1;
+        i += 1;
+    }
+    return len;
+}
+
+/// Formats `value` like [`format_fixed`] and compares it with `expected`, in
+/// a const context
+const fn fixed_is(value: u64, frac_digits: u8, expected: &[u8]) -> bool {
+    let mut out = [0u8; FIXED_MAX];
+    let len = format_fixed(value, frac_digits, &mut out);
+    let mut i = 0;
+
+    if len != expected.len() {
+        return false;
+    }
+    while i < len {
+        if out[i] != expected[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(fixed_is(0, 0, b"0") && fixed_is(11534, 0, b"11534"));
+const _: () = assert!(fixed_is(11534, 1, b"1153.4") && fixed_is(11534, 3, b"11.534"));
+// Values below 1 keep their leading zeros
+const _: () = assert!(fixed_is(5, 3, b"0.005") && fixed_is(0, 3, b"0.000"));
+const _: () = assert!(fixed_is(7, 1, b"0.7") && fixed_is(999, 3, b"0.999"));
+const _: () = assert!(fixed_is(u64::MAX, 255, b"1.8446744073709551615"));
+
+/// Prints a fixed-point decimal number to UART
+///
+/// `value` is interpreted as scaled by `10^frac_digits` and printed with a
+/// decimal point, keeping exactly `frac_digits` fractional digits (e.g.,
+/// `print_fixed(11534, 2)` prints `115.34` and `print_fixed(5, 3)` prints
+/// `0.005`). With `frac_digits == 0` only the integer is printed. Values of
+/// `frac_digits` above 19 are clamped, as `10^20` doesn't fit in a u64.
+pub fn print_fixed(value: u64, frac_digits: u8) {
+    let mut buf = [0u8; FIXED_MAX];
+    let len = format_fixed(value, frac_digits, &mut buf);
+
+    pl011::print(&buf[..len]);
+}
+
+/// Prints a single dot to UART every `step` calls
+///
+/// Meant to be called once per iteration of a long-running loop (large copies,
+/// memory tests) to show progress without flooding the console. A `step` of 0
+/// is treated as 1.
+pub fn heartbeat(step: u64) {
+    write_heartbeat(&mut Uart, step);
+}
+
+/// Writes a single dot to `out` every `step` calls, counting the calls
+/// together with [`heartbeat`]
+pub fn write_heartbeat(out: &mut dyn Console, step: u64) {
+    let count;
+
+    unsafe {
+        HEARTBEAT_COUNT += 1;
+        count = HEARTBEAT_COUNT;
+    }
+    if count.is_multiple_of(step.max(1)) {
+        out.write(b".");
+    }
+}
+
+/// Self-test: starting from a fresh count, a dot is written on every
+/// `step`th call and on no other, and a `step` of 0 dots every call
+pub fn selftest_heartbeat() -> Outcome {
+    let saved = unsafe { HEARTBEAT_COUNT };
+    let mut buf = [0u8; 16];
+    let mut sink = CaptureSink::new(&mut buf);
+    let mut cadence = true;
+
+    unsafe {
+        HEARTBEAT_COUNT = 0;
+    }
+    for call in 1..=9u64 {
+        let before = sink.bytes().len();
+        write_heartbeat(&mut sink, 3);
+        cadence &= (sink.bytes().len() > before) == call.is_multiple_of(3);
+    }
+    let every_third = sink.bytes() == b"...";
+
+    sink.clear();
+    for _ in 0..4 {
+        write_heartbeat(&mut sink, 0);
+    }
+    let every_call = sink.bytes() == b"....";
+
+    unsafe {
+        HEARTBEAT_COUNT = saved;
+    }
+    if cadence && every_third && every_call {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Returns the whole percentage `done` is of `total`, 100 for an empty total
+pub const fn percent(done: usize, total: usize) -> u64 {
+    if total == 0 || done >= total {
+        return 100;
+    }
+    return (done as u128 * 100 / total as u128) as u64;
+}
+
+const _: () = assert!(percent(0, 10) == 0 && percent(5, 10) == 50 && percent(10, 10) == 100);
+const _: () = assert!(percent(999, 1000) == 99 && percent(0, 0) == 100);
+const _: () = assert!(percent(usize::MAX - 1, usize::MAX) == 99);
+
+/// Prints how much of a copy is done as a percentage, rewriting the line
+///
+/// Meant to be passed as the [`Progress`](crate::parsers::elf::Progress)
+/// callback of a load. The line is only rewritten when the percentage
+/// changes, and ended once it reaches 100%.
+pub fn progress(copied: usize, total: usize) {
+    let percent = percent(copied, total);
+    let mut buf = [b' '; 3];
+
+    if percent == unsafe { PROGRESS_PERCENT } {
+        return;
+    }
+    // Right-aligned in the blanks
+    u64_to_dec(percent, &mut buf);
+    pl011::print(b"\rLoading: ");
     pl011::print(&buf);
+    pl011::print(b"%");
+    // The next copy starts over on a line of its own
+    if percent == 100 {
+        pl011::print(b"\n");
+    }
+    unsafe {
+        PROGRESS_PERCENT = if percent == 100 { u64::MAX } else { percent };
+    }
+}
+
+/// Bytes per line of [`hexdump`]
+pub const HEXDUMP_WIDTH: usize = 16;
+/// Most bytes per line of [`hexdump_width`]
+const HEXDUMP_MAX_WIDTH: usize = 32;
+/// Longest line of a hex dump: the address, a colon, three characters per
+/// byte, the gutter between bars
+const HEXDUMP_LINE_MAX: usize = 16 + 1 + 3 * HEXDUMP_MAX_WIDTH + 2 + HEXDUMP_MAX_WIDTH + 1;
+
+/// Formats the hex dump line of `bytes`, read at `addr`, into `out` and
+/// returns its length
+///
+/// The hexadecimal column is padded to `width` bytes, so the ASCII gutter of
+/// a short last line lines up with the lines above. `bytes` must not be
+/// longer than `width`, nor `width` than [`HEXDUMP_MAX_WIDTH`].
+const fn format_hexdump_line(
+    addr: u64,
+    bytes: &[u8],
+    width: usize,
+    out: &mut [u8; HEXDUMP_LINE_MAX],
+) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+
+    while i < 16 {
+        out[len] = HEX_CHARS[((addr >> ((15 - i) * 4)) & 0xf) as usize];
+        len += 1;
+        i += 1;
+    }
+    out[len] = b':';
+    len += 1;
+    i = 0;
+    while i < width {
+        if i < bytes.len() {
+            out[len] = b' ';
+            out[len + 1] = HEX_CHARS[(bytes[i] >> 4) as usize];
+            out[len + 2] = HEX_CHARS[(bytes[i] & 0xf) as usize];
+        } else {
+            out[len] = b' ';
+            out[len + 1] = b' ';
+            out[len + 2] = b' ';
+        }
+        len += 3;
+        i += 1;
+    }
+    out[len] = b' ';
+    out[len + 1] = b'|';
+    len += 2;
+    i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        out[len] = if byte.is_ascii_graphic() || byte == b' ' {
+            byte
+        } else {
+            b'.'
+        };
+        len += 1;
+        i += 1;
+    }
+    out[len] = b'|';
+    return len + 1;
+}
+
+/// Formats the hex dump line of `bytes` like [`format_hexdump_line`] and
+/// compares it with `expected`, in a const context
+const fn hexdump_line_is(addr: u64, bytes: &[u8], width: usize, expected: &[u8]) -> bool {
+    let mut out = [0u8; HEXDUMP_LINE_MAX];
+    let len = format_hexdump_line(addr, bytes, width, &mut out);
+    let mut i = 0;
+
+    if len != expected.len() {
+        return false;
+    }
+    while i < len {
+        if out[i] != expected[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+// 8 bytes per line: a full line, then a short last line padded so its
+// gutter lines up
+const _: () = assert!(hexdump_line_is(
+    0x4008_0000,
+    b"\x1f\x20\x03\xd5AB\n~",
+    8,
+    b"0000000040080000: 1f 20 03 d5 41 42 0a 7e |. ..AB.~|"
+));
+const _: () = assert!(hexdump_line_is(
+    0x4008_0008,
+    b"hi",
+    8,
+    b"0000000040080008: 68 69                   |hi|"
+));
+const _: () = assert!(hexdump_line_is(
+    0xffff_0000_0000_0010,
+    &[0; 16],
+    16,
+    b"ffff000000000010: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 |................|"
+));
+
+/// Prints a hex dump of `len` bytes of memory starting at `addr` to UART
+///
+/// Each line shows the address, [`HEXDUMP_WIDTH`] bytes in hexadecimal and
+/// their ASCII representation (non-printable bytes are shown as `.`):
+///
+/// ```text
+/// 0000000040080000: 1f 20 03 d5 ... |. ..............|
+/// ```
+///
+/// Memory is read with volatile byte accesses.
+pub fn hexdump(addr: usize, len: usize) {
+    hexdump_width(addr, len, HEXDUMP_WIDTH);
+}
+
+/// Prints a hex dump like [`hexdump`] with `bytes_per_line` bytes per line
+///
+/// The width is 8, 16 or 32 bytes, for narrow terminals or wide
+/// inspection; any other value prints 16. The addresses step and the ASCII
+/// gutter widens with it:
+///
+/// ```text
+/// 0000000040080000: 1f 20 03 d5 41 42 0a 7e |. ..AB.~|
+/// ```
+pub fn hexdump_width(addr: usize, len: usize, bytes_per_line: usize) {
+    let width = match bytes_per_line {
+        8 | 16 | 32 => bytes_per_line,
+        _ => HEXDUMP_WIDTH,
+    };
+    let mut bytes = [0u8; HEXDUMP_MAX_WIDTH];
+    let mut line = [0u8; HEXDUMP_LINE_MAX];
+    let mut offset = 0;
+
+    while offset < len {
+        let count = (len - offset).min(width);
+
+        for (i, byte) in bytes[..count].iter_mut().enumerate() {
+            *byte = unsafe { ((addr + offset + i) as *const u8).read_volatile() };
+        }
+        let line_len =
+            format_hexdump_line((addr + offset) as u64, &bytes[..count], width, &mut line);
+        pl011::println(&line[..line_len]);
+
+        offset += count;
+    }
+}
+
+/// Formats the low digits of `value` as zero-padded hex filling all of `buf`
+pub fn u64_to_hex_padded(mut value: u64, buf: &mut [u8]) -> &[u8] {
+    for byte in buf.iter_mut().rev() {
+        *byte = HEX_CHARS[(value & 0xF) as usize];
+        value >>= 4;
+    }
+    return buf;
 }
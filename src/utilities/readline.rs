@@ -0,0 +1,648 @@
+//! Line input from the console
+//!
+//! This module reads a line of text typed on the UART with in-line editing,
+//! echoing the characters back as they are typed, optionally with a
+//! [`History`] of earlier lines and completion of the command name. It is
+//! used by the interactive monitor.
+//!
+//! # Keys
+//!
+//! Terminals send the cursor and editing keys as ANSI escape sequences:
+//! `ESC [ A` for Up, `ESC [ 3 ~` for Delete, `ESC O H` for Home on some
+//! of them. [`read_key`] decodes those with a small state machine and
+//! returns one [`Key`] per key press. Sequences it doesn't know are
+//! swallowed whole instead of ending up in the line. A bare ESC can't be
+//! told from the start of a sequence until the next byte is late: after
+//! [`ESC_TIMEOUT_MS`] without one, it is delivered as `Key::Char(ESC)`.
+
+use crate::drivers::timer::generic::Deadline;
+use crate::drivers::uart::pl011;
+
+/// ASCII backspace
+const BS: u8 = 0x08;
+/// ASCII delete, sent by most terminals for the backspace key
+const DEL: u8 = 0x7f;
+/// ASCII escape, starts the sequences of the cursor keys
+pub const ESC: u8 = 0x1b;
+/// Ctrl-C, discards the line
+const CTRL_C: u8 = 0x03;
+/// Ctrl-U, erases the whole line
+const CTRL_U: u8 = 0x15;
+/// Tab, completes the command name
+const TAB: u8 = 0x09;
+
+/// Time allowed between the bytes of an escape sequence, in milliseconds
+///
+/// Terminals send a sequence in one write, so its bytes arrive back to back
+/// even at 9600 baud; a person can't type ESC and `[` that fast.
+pub const ESC_TIMEOUT_MS: u64 = 50;
+
+/// Longest parameter kept from a sequence; larger ones are unknown keys
+const MAX_PARAM: u16 = 99;
+
+/// A key press
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// Any other character, printable or not
+    Char(u8),
+    /// Cursor up
+    Up,
+    /// Cursor down
+    Down,
+    /// Cursor left
+    Left,
+    /// Cursor right
+    Right,
+    /// Home, start of the line
+    Home,
+    /// End, end of the line
+    End,
+    /// Delete, the character under the cursor
+    Delete,
+    /// Carriage return or line feed
+    Enter,
+    /// Backspace or DEL, the character before the cursor
+    Backspace,
+    /// Ctrl-C
+    CtrlC,
+    /// Break condition on the line
+    Break,
+}
+
+/// Where the decoder is in an escape sequence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Between keys
+    Ground,
+    /// After ESC
+    Escape,
+    /// After `ESC [`, with the first parameter so far
+    Csi(u16),
+    /// After `ESC [` and a byte that makes the sequence unknown
+    CsiUnknown,
+    /// After `ESC O`
+    Ss3,
+}
+
+/// Escape sequence decoder, fed one received byte at a time
+#[derive(Clone, Copy, Debug)]
+struct Decoder {
+    /// Progress through the current sequence
+    state: State,
+}
+
+impl Decoder {
+    /// Creates a decoder between keys
+    const fn new() -> Self {
+        return Self {
+            state: State::Ground,
+        };
+    }
+
+    /// Checks whether the decoder is inside an escape sequence
+    const fn pending(&self) -> bool {
+        return !matches!(self.state, State::Ground);
+    }
+
+    /// Feeds the next received byte
+    ///
+    /// Returns the key it completes, or `None` if it starts or continues a
+    /// sequence, or ends one that isn't known.
+    const fn feed(&mut self, c: u8) -> Option<Key> {
+        match self.state {
+            State::Ground => {
+                return match c {
+                    ESC => {
+                        self.state = State::Escape;
+                        None
+                    }
+                    b'\r' | b'\n' => Some(Key::Enter),
+                    BS | DEL => Some(Key::Backspace),
+                    CTRL_C => Some(Key::CtrlC),
+                    _ => Some(Key::Char(c)),
+                };
+            }
+            State::Escape => {
+                self.state = match c {
+                    b'[' => State::Csi(0),
+                    b'O' => State::Ss3,
+                    _ => State::Ground,
+                };
+                return None;
+            }
+            State::Csi(param) => {
+                self.state = match c {
+                    b'0'..=b'9' if param * 10 + ((c - b'0') as u16) <= MAX_PARAM => {
+                        State::Csi(param * 10 + (c - b'0') as u16)
+                    }
+                    // Parameter and intermediate bytes of an unknown key
+                    0x20..=0x3f => State::CsiUnknown,
+                    _ => State::Ground,
+                };
+                return match c {
+                    b'A' if param == 0 => Some(Key::Up),
+                    b'B' if param == 0 => Some(Key::Down),
+                    b'C' if param == 0 => Some(Key::Right),
+                    b'D' if param == 0 => Some(Key::Left),
+                    b'H' if param == 0 => Some(Key::Home),
+                    b'F' if param == 0 => Some(Key::End),
+                    b'~' if param == 1 || param == 7 => Some(Key::Home),
+                    b'~' if param == 4 || param == 8 => Some(Key::End),
+                    b'~' if param == 3 => Some(Key::Delete),
+                    _ => None,
+                };
+            }
+            State::CsiUnknown => {
+                // Anything outside parameters and intermediates ends it
+                if !matches!(c, 0x20..=0x3f) {
+                    self.state = State::Ground;
+                }
+                return None;
+            }
+            State::Ss3 => {
+                self.state = State::Ground;
+                return match c {
+                    b'A' => Some(Key::Up),
+                    b'B' => Some(Key::Down),
+                    b'C' => Some(Key::Right),
+                    b'D' => Some(Key::Left),
+                    b'H' => Some(Key::Home),
+                    b'F' => Some(Key::End),
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    /// Ends the current sequence because its next byte is late
+    ///
+    /// Returns `Key::Char(ESC)` for a bare ESC; a sequence cut short is
+    /// dropped.
+    const fn timeout(&mut self) -> Option<Key> {
+        let escape = matches!(self.state, State::Escape);
+
+        self.state = State::Ground;
+        if escape {
+            return Some(Key::Char(ESC));
+        }
+        return None;
+    }
+}
+
+/// Decodes `bytes`, then times out, and returns the last key and how many
+/// keys there were
+const fn decode(bytes: &[u8]) -> (Option<Key>, usize) {
+    let mut decoder = Decoder::new();
+    let mut last = None;
+    let mut count = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(key) = decoder.feed(bytes[i]) {
+            last = Some(key);
+            count += 1;
+        }
+        i += 1;
+    }
+    if let Some(key) = decoder.timeout() {
+        last = Some(key);
+        count += 1;
+    }
+    return (last, count);
+}
+
+const _: () = assert!(matches!(decode(b"a"), (Some(Key::Char(b'a')), 1)));
+const _: () = assert!(matches!(decode(b"\r"), (Some(Key::Enter), 1)));
+const _: () = assert!(matches!(decode(b"\x7f"), (Some(Key::Backspace), 1)));
+const _: () = assert!(matches!(decode(b"\x1b[A"), (Some(Key::Up), 1)));
+const _: () = assert!(matches!(decode(b"\x1b[B"), (Some(Key::Down), 1)));
+const _: () = assert!(matches!(decode(b"\x1b[C"), (Some(Key::Right), 1)));
+const _: () = assert!(matches!(decode(b"\x1b[D"), (Some(Key::Left), 1)));
+const _: () = assert!(matches!(decode(b"\x1bOH"), (Some(Key::Home), 1)));
+const _: () = assert!(matches!(decode(b"\x1b[4~"), (Some(Key::End), 1)));
+const _: () = assert!(matches!(decode(b"\x1b[3~"), (Some(Key::Delete), 1)));
+// A bare ESC is delivered once the sequence times out
+const _: () = assert!(matches!(decode(b"\x1b"), (Some(Key::Char(ESC)), 1)));
+// Unknown sequences, with parameters or not, leave nothing behind
+const _: () = assert!(decode(b"\x1b[1;5C").1 == 0);
+const _: () = assert!(decode(b"\x1b[15~").1 == 0);
+const _: () = assert!(decode(b"\x1b[Z").1 == 0);
+const _: () = assert!(decode(b"\x1b[12345~").1 == 0);
+const _: () = assert!(decode(b"\x1bx").1 == 0);
+// The key after an unknown sequence comes through
+const _: () = assert!(matches!(decode(b"\x1b[1;5Cq"), (Some(Key::Char(b'q')), 1)));
+// A sequence cut short is dropped
+const _: () = assert!(decode(b"\x1b[3").1 == 0);
+
+/// Waits for a key press and returns it
+///
+/// Escape sequences are decoded as described in the
+/// [module documentation](self). A break received on the line is returned
+/// as [`Key::Break`].
+pub fn read_key() -> Key {
+    let mut decoder = Decoder::new();
+    let mut deadline = Deadline::NEVER;
+
+    loop {
+        if pl011::take_break() {
+            return Key::Break;
+        }
+        match pl011::try_getchar() {
+            Some(c) => {
+                if let Some(key) = decoder.feed(c) {
+                    return key;
+                }
+                if decoder.pending() {
+                    deadline = Deadline::after_ms(ESC_TIMEOUT_MS);
+                }
+            }
+            None if decoder.pending() && deadline.expired() => {
+                if let Some(key) = decoder.timeout() {
+                    return key;
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Number of lines kept by a [`History`]
+pub const HISTORY_DEPTH: usize = 16;
+/// Longest line kept by a [`History`]; longer ones are cut
+pub const HISTORY_LINE_SIZE: usize = 128;
+
+/// Most recent lines entered, in fixed buffers
+///
+/// Lines are numbered from 1 in the order they were added; once
+/// [`HISTORY_DEPTH`] are kept, adding one drops the oldest, but the numbers
+/// of the others don't change.
+pub struct History {
+    /// Kept lines, the one numbered `n` at `(n - 1) % HISTORY_DEPTH`
+    lines: [[u8; HISTORY_LINE_SIZE]; HISTORY_DEPTH],
+    /// Lengths of the lines in `lines`
+    lens: [usize; HISTORY_DEPTH],
+    /// Number of lines ever added, also the number of the newest
+    added: usize,
+}
+
+impl History {
+    /// Creates an empty history
+    pub const fn new() -> Self {
+        return Self {
+            lines: [[0; HISTORY_LINE_SIZE]; HISTORY_DEPTH],
+            lens: [0; HISTORY_DEPTH],
+            added: 0,
+        };
+    }
+
+    /// Returns the number of lines kept
+    pub const fn len(&self) -> usize {
+        if self.added < HISTORY_DEPTH {
+            return self.added;
+        }
+        return HISTORY_DEPTH;
+    }
+
+    /// Checks whether no line was added yet
+    pub const fn is_empty(&self) -> bool {
+        return self.added == 0;
+    }
+
+    /// Returns the number of the oldest line kept
+    pub const fn first(&self) -> usize {
+        return self.added - self.len() + 1;
+    }
+
+    /// Returns the number of the newest line
+    pub const fn last(&self) -> usize {
+        return self.added;
+    }
+
+    /// Returns the line numbered `number`, if it is still kept
+    pub const fn get(&self, number: usize) -> Option<&[u8]> {
+        if number == 0 || number < self.first() || number > self.added {
+            return None;
+        }
+        let slot = (number - 1) % HISTORY_DEPTH;
+        return Some(self.lines[slot].split_at(self.lens[slot]).0);
+    }
+
+    /// Returns the line added `back` lines before the newest one
+    pub const fn recent(&self, back: usize) -> Option<&[u8]> {
+        if back >= self.added {
+            return None;
+        }
+        return self.get(self.added - back);
+    }
+
+    /// Adds `line` as the newest line
+    ///
+    /// Empty lines and repeats of the newest line aren't added.
+    pub const fn push(&mut self, line: &[u8]) {
+        let len = if line.len() < HISTORY_LINE_SIZE {
+            line.len()
+        } else {
+            HISTORY_LINE_SIZE
+        };
+
+        if len == 0 {
+            return;
+        }
+        if let Some(newest) = self.recent(0)
+            && same_bytes(newest, line.split_at(len).0)
+        {
+            return;
+        }
+        let slot = self.added % HISTORY_DEPTH;
+        let mut i = 0;
+        while i < len {
+            self.lines[slot][i] = line[i];
+            i += 1;
+        }
+        self.lens[slot] = len;
+        self.added += 1;
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Checks whether `a` and `b` hold the same bytes
+const fn same_bytes(a: &[u8], b: &[u8]) -> bool {
+    let mut i = 0;
+
+    if a.len() != b.len() {
+        return false;
+    }
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = {
+    let mut history = History::new();
+
+    assert!(history.is_empty() && history.recent(0).is_none());
+    history.push(b"md 0");
+    history.push(b"");
+    history.push(b"md 0");
+    assert!(history.len() == 1);
+    assert!(same_bytes(history.recent(0).unwrap(), b"md 0"));
+
+    let mut i = 0;
+    while i < HISTORY_DEPTH + 3 {
+        history.push(&[b'a' + i as u8]);
+        i += 1;
+    }
+    // The oldest lines were dropped, the numbers of the others kept
+    assert!(history.len() == HISTORY_DEPTH);
+    assert!(history.first() == 5 && history.last() == HISTORY_DEPTH + 4);
+    assert!(history.get(4).is_none() && history.get(HISTORY_DEPTH + 5).is_none());
+    assert!(same_bytes(history.get(5).unwrap(), b"d"));
+    assert!(same_bytes(history.recent(1).unwrap(), b"r"));
+};
+
+/// Completion candidates: calls the given function with each of them
+pub type Candidates = fn(&mut dyn FnMut(&[u8]));
+
+/// Moves the cursor `n` characters left
+fn cursor_left(n: usize) {
+    for _ in 0..n {
+        pl011::print(&[BS]);
+    }
+}
+
+/// Reprints `tail` from the cursor, clears the `erased` characters after
+/// it, and puts the cursor back where it was
+fn redraw(tail: &[u8], erased: usize) {
+    pl011::print(tail);
+    for _ in 0..erased {
+        pl011::print(b" ");
+    }
+    cursor_left(tail.len() + erased);
+}
+
+/// Line being edited, echoed on the console
+struct Line<'a> {
+    /// Storage of the line
+    buf: &'a mut [u8],
+    /// Length of the line
+    len: usize,
+    /// Position of the cursor in the line
+    cursor: usize,
+}
+
+impl Line<'_> {
+    /// Inserts `text` at the cursor, as much of it as fits
+    fn insert(&mut self, text: &[u8]) {
+        let count = text.len().min(self.buf.len() - self.len);
+
+        self.buf
+            .copy_within(self.cursor..self.len, self.cursor + count);
+        self.buf[self.cursor..self.cursor + count].copy_from_slice(&text[..count]);
+        self.len += count;
+        pl011::print(&text[..count]);
+        self.cursor += count;
+        redraw(&self.buf[self.cursor..self.len], 0);
+    }
+
+    /// Replaces the whole line with `text`, leaving the cursor at its end
+    fn replace(&mut self, text: &[u8]) {
+        let old_len = self.len;
+
+        cursor_left(self.cursor);
+        self.len = text.len().min(self.buf.len());
+        self.buf[..self.len].copy_from_slice(&text[..self.len]);
+        self.cursor = self.len;
+        pl011::print(&self.buf[..self.len]);
+        redraw(&[], old_len.saturating_sub(self.len));
+    }
+
+    /// Prints `prompt` and the line again on a new line
+    fn reprint(&self, prompt: &[u8]) {
+        pl011::print(prompt);
+        pl011::print(&self.buf[..self.len]);
+        cursor_left(self.len - self.cursor);
+    }
+
+    /// Completes the word before the cursor with `candidates`
+    ///
+    /// Only the first word, the command name, is completed. A unique
+    /// candidate is inserted whole, followed by a space; several are
+    /// completed up to their common prefix, and listed if there is none
+    /// longer than the word and `list` is set. Returns whether a list was
+    /// printed.
+    fn complete(&mut self, candidates: Candidates, list: bool) -> bool {
+        let mut word = [0u8; HISTORY_LINE_SIZE];
+        let mut common = [0u8; HISTORY_LINE_SIZE];
+        let mut common_len = 0;
+        let mut matches = 0;
+
+        if self.cursor > word.len() || self.buf[..self.cursor].contains(&b' ') {
+            return false;
+        }
+        let word_len = self.cursor;
+        word[..word_len].copy_from_slice(&self.buf[..word_len]);
+        candidates(&mut |candidate| {
+            if !candidate.starts_with(&word[..word_len]) || candidate.len() > common.len() {
+                return;
+            }
+            if matches == 0 {
+                common[..candidate.len()].copy_from_slice(candidate);
+                common_len = candidate.len();
+            } else {
+                common_len = common[..common_len]
+                    .iter()
+                    .zip(candidate)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            }
+            matches += 1;
+        });
+
+        if matches == 1 {
+            self.insert(&common[word_len..common_len]);
+            self.insert(b" ");
+        } else if common_len > word_len {
+            self.insert(&common[word_len..common_len]);
+        } else if matches > 1 && list {
+            pl011::print(b"\n");
+            candidates(&mut |candidate| {
+                if candidate.starts_with(&word[..word_len]) {
+                    pl011::print(candidate);
+                    pl011::print(b"  ");
+                }
+            });
+            pl011::print(b"\n");
+            return true;
+        }
+        return false;
+    }
+}
+
+/// Reads a line into `buf` and returns its length
+///
+/// Same as [`read_line_with`] without a prompt, history or completion.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    return read_line_with(buf, b"", None, None);
+}
+
+/// Prints `prompt`, reads a line into `buf` and returns its length
+///
+/// Reading stops at Enter, which is not stored. Left, Right, Home and End
+/// move the cursor within the line, characters are inserted at it,
+/// Backspace erases the character before it and Delete the one under it.
+/// Ctrl-U erases the whole line, and Ctrl-C or a break discards the line
+/// (returning 0). Characters that don't fit in `buf`, non-printable
+/// characters and other keys are ignored.
+///
+/// With a `history`, Up and Down replace the line with older and newer
+/// lines from it, which can then be edited; Down past the newest brings
+/// back the line being typed. The caller adds the lines it accepts. With
+/// `candidates`, Tab completes the command name before the cursor, and a
+/// second Tab lists the candidates when completion can't go further.
+pub fn read_line_with(
+    buf: &mut [u8],
+    prompt: &[u8],
+    history: Option<&History>,
+    candidates: Option<Candidates>,
+) -> usize {
+    let mut line = Line {
+        buf,
+        len: 0,
+        cursor: 0,
+    };
+    // Number of history lines stepped back from the one being typed
+    let mut back = 0;
+    let mut draft = [0u8; HISTORY_LINE_SIZE];
+    let mut draft_len = 0;
+    let mut last_tab = false;
+
+    pl011::print(prompt);
+    loop {
+        let key = read_key();
+        let tab = key == Key::Char(TAB);
+
+        match key {
+            Key::Enter => {
+                pl011::print(b"\n");
+                return line.len;
+            }
+            Key::Left if line.cursor > 0 => {
+                line.cursor -= 1;
+                cursor_left(1);
+            }
+            Key::Right if line.cursor < line.len => {
+                pl011::print(&line.buf[line.cursor..line.cursor + 1]);
+                line.cursor += 1;
+            }
+            Key::Home => {
+                cursor_left(line.cursor);
+                line.cursor = 0;
+            }
+            Key::End => {
+                pl011::print(&line.buf[line.cursor..line.len]);
+                line.cursor = line.len;
+            }
+            Key::Backspace if line.cursor > 0 => {
+                line.buf.copy_within(line.cursor..line.len, line.cursor - 1);
+                line.len -= 1;
+                line.cursor -= 1;
+                cursor_left(1);
+                redraw(&line.buf[line.cursor..line.len], 1);
+            }
+            Key::Delete if line.cursor < line.len => {
+                line.buf.copy_within(line.cursor + 1..line.len, line.cursor);
+                line.len -= 1;
+                redraw(&line.buf[line.cursor..line.len], 1);
+            }
+            Key::Up => {
+                if let Some(older) = history.and_then(|h| h.recent(back)) {
+                    if back == 0 {
+                        draft_len = line.len.min(draft.len());
+                        draft[..draft_len].copy_from_slice(&line.buf[..draft_len]);
+                    }
+                    back += 1;
+                    line.replace(older);
+                }
+            }
+            Key::Down if back > 0 => {
+                back -= 1;
+                let newer = match back {
+                    0 => None,
+                    _ => history.and_then(|h| h.recent(back - 1)),
+                };
+                line.replace(newer.unwrap_or(&draft[..draft_len]));
+            }
+            Key::Char(CTRL_U) => {
+                cursor_left(line.cursor);
+                redraw(&[], line.len);
+                line.len = 0;
+                line.cursor = 0;
+            }
+            Key::CtrlC | Key::Break => {
+                pl011::println(b"^C");
+                return 0;
+            }
+            Key::Char(TAB) => {
+                if let Some(candidates) = candidates
+                    && line.complete(candidates, last_tab)
+                {
+                    line.reprint(prompt);
+                }
+            }
+            Key::Char(c) if c.is_ascii_graphic() || c == b' ' => {
+                line.insert(&[c]);
+            }
+            _ => {}
+        }
+        last_tab = tab;
+    }
+}
@@ -0,0 +1,67 @@
+//! Random numbers
+//!
+//! This module gets random numbers from the RNDR register of cores that
+//! implement FEAT_RNG. Cores without it can fall back to
+//! [`counter_entropy`], which only mixes the virtual counter: good enough to
+//! tell boots apart, not for anything secret.
+
+use crate::cpu;
+
+use crate::asm;
+
+/// Number of RNDR reads attempted before giving up
+const RNDR_RETRIES: usize = 8;
+
+/// Returns a random number from RNDR
+///
+/// Returns `None` if the core has no RNDR or it keeps failing, which it
+/// may do when the entropy source is exhausted.
+pub fn random_u64() -> Option<u64> {
+    if !cpu::has_rndr() {
+        return None;
+    }
+    for _ in 0..RNDR_RETRIES {
+        let value: u64;
+        let failed: u64;
+
+        // RNDR sets NZCV to 0b0100 on failure, and reads 0
+        unsafe {
+            asm!(
+                "mrs {value}, s3_3_c2_c4_0",
+                "cset {failed}, eq",
+                value = out(reg) value,
+                failed = out(reg) failed,
+                options(nomem, nostack),
+            );
+        }
+        if failed == 0 {
+            return Some(value);
+        }
+    }
+    return None;
+}
+
+/// Returns a number derived from the virtual counter (`CNTVCT_EL0`)
+///
+/// The counter is mixed so that close readings give unrelated values. Its
+/// only entropy is how long the boot took to get here.
+pub fn counter_entropy() -> u64 {
+    let ticks: u64;
+
+    unsafe {
+        asm!("isb", "mrs {}, cntvct_el0", out(reg) ticks, options(nomem, nostack));
+    }
+    return mix(ticks);
+}
+
+/// Mixes the bits of `value` (the SplitMix64 finalizer)
+pub const fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    return z ^ (z >> 31);
+}
+
+// Reference output of SplitMix64 seeded with 0
+const _: () = assert!(mix(0) == 0xe220_a839_7b1d_cdaf);
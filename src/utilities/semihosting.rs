@@ -0,0 +1,58 @@
+//! Arm semihosting calls
+//!
+//! Semihosting lets code running under a debugger or an emulator (e.g., QEMU
+//! with `-semihosting`) request services from the host. Calls are made with
+//! `hlt #0xf000`, which raises an exception when no host is listening, so
+//! they must only be used when the bootloader is known to run under one.
+
+use crate::asm;
+
+/// SYS_WRITEC operation number
+const SYS_WRITEC: u64 = 0x03;
+/// SYS_EXIT operation number
+const SYS_EXIT: u64 = 0x18;
+/// SYS_EXIT reason: the application exited, with a status
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Exits the emulator or stops the debug session with `status`
+///
+/// QEMU exits with `status` as its own exit status.
+pub fn exit(status: u32) -> ! {
+    let block: [u64; 2] = [ADP_STOPPED_APPLICATION_EXIT, status as u64];
+
+    unsafe {
+        asm!(
+            "hlt #0xf000",
+            in("w0") SYS_EXIT as u32,
+            in("x1") block.as_ptr(),
+            options(nostack),
+        );
+    }
+    // SYS_EXIT doesn't return
+    loop {
+        unsafe {
+            asm!("wfe", options(nomem, nostack));
+        }
+    }
+}
+
+/// Writes `c` to the host's debug console (SYS_WRITEC)
+///
+/// QEMU sends it to the `semihosting` chardev, or to its stderr by default.
+pub fn write_char(c: u8) {
+    unsafe {
+        asm!(
+            "hlt #0xf000",
+            inout("w0") SYS_WRITEC as u32 => _,
+            in("x1") &c as *const u8,
+            options(nostack),
+        );
+    }
+}
+
+/// Writes `s` to the host's debug console, one byte at a time
+pub fn write(s: &[u8]) {
+    for &c in s {
+        write_char(c);
+    }
+}
@@ -0,0 +1,202 @@
+//! SHA-256 hash
+//!
+//! This module implements SHA-256 (FIPS 180-4) in software, for measured
+//! boot and image verification. [`Sha256`] hashes data incrementally, fed in
+//! any number of pieces; [`sha256`] hashes a single slice.
+
+use crate::selftest::Outcome;
+
+/// Size of a SHA-256 digest in bytes
+pub const DIGEST_SIZE: usize = 32;
+
+/// SHA-256 round constants
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 initial hash value
+const SHA256_H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256 hasher
+#[derive(Clone)]
+pub struct Sha256 {
+    /// Current hash state
+    state: [u32; 8],
+    /// Partial block waiting for more data
+    block: [u8; 64],
+    /// Number of valid bytes in `block`
+    block_len: usize,
+    /// Total number of bytes hashed
+    total_len: u64,
+}
+
+impl Sha256 {
+    /// Creates a hasher with the initial state
+    pub fn new() -> Self {
+        return Sha256 {
+            state: SHA256_H0,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        };
+    }
+
+    /// Feeds `data` into the hash
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pads the message and returns the digest
+    pub fn finalize(mut self) -> [u8; DIGEST_SIZE] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        let mut digest = [0u8; DIGEST_SIZE];
+
+        self.block[self.block_len] = 0x80;
+        self.block[self.block_len + 1..].fill(0);
+        if self.block_len >= 56 {
+            let block = self.block;
+            self.compress(&block);
+            self.block.fill(0);
+        }
+        self.block[56..].copy_from_slice(&bit_len.to_be_bytes());
+        let block = self.block;
+        self.compress(&block);
+
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        return digest;
+    }
+
+    /// Processes one 64-byte block
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Computes the SHA-256 digest of `data`
+pub fn sha256(data: &[u8]) -> [u8; DIGEST_SIZE] {
+    let mut hasher = Sha256::new();
+
+    hasher.update(data);
+    return hasher.finalize();
+}
+
+/// The 448-bit message of FIPS 180-4, whose padding needs a second block
+const TEST_MESSAGE_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+/// Digest of the empty message
+const TEST_DIGEST_EMPTY: [u8; DIGEST_SIZE] = [
+    0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
+    0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+];
+/// Digest of `abc`
+const TEST_DIGEST_ABC: [u8; DIGEST_SIZE] = [
+    0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+    0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+];
+/// Digest of [`TEST_MESSAGE_448`]
+const TEST_DIGEST_448: [u8; DIGEST_SIZE] = [
+    0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e, 0x60, 0x39,
+    0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4, 0x19, 0xdb, 0x06, 0xc1,
+];
+/// Digest of one million `a`
+const TEST_DIGEST_MILLION: [u8; DIGEST_SIZE] = [
+    0xcd, 0xc7, 0x6e, 0x5c, 0x99, 0x14, 0xfb, 0x92, 0x81, 0xa1, 0xc7, 0xe2, 0x84, 0xd7, 0x3e, 0x67,
+    0xf1, 0x80, 0x9a, 0x48, 0xa4, 0x97, 0x20, 0x0e, 0x04, 0x6d, 0x39, 0xcc, 0xc7, 0x11, 0x2c, 0xd0,
+];
+
+/// Self-test: the digests of the FIPS 180-4 example messages match,
+/// whether the message is hashed at once or fed in uneven pieces
+pub fn selftest() -> Outcome {
+    let chunk = [b'a'; 1000];
+    let mut million = Sha256::new();
+    let mut pieces = Sha256::new();
+
+    for _ in 0..1000 {
+        million.update(&chunk);
+    }
+    for piece in [
+        &TEST_MESSAGE_448[..1],
+        &TEST_MESSAGE_448[1..55],
+        &TEST_MESSAGE_448[55..],
+    ] {
+        pieces.update(piece);
+    }
+
+    if sha256(b"") != TEST_DIGEST_EMPTY
+        || sha256(b"abc") != TEST_DIGEST_ABC
+        || sha256(TEST_MESSAGE_448) != TEST_DIGEST_448
+        || pieces.finalize() != TEST_DIGEST_448
+        || million.finalize() != TEST_DIGEST_MILLION
+    {
+        return Outcome::Fail;
+    }
+    return Outcome::Pass;
+}
@@ -0,0 +1,260 @@
+//! Warm-reboot image cache
+//!
+//! Reloading an image over the console after every reset is slow, while the
+//! previous upload usually sits intact in RAM across a warm reset. After a
+//! transfer, [`record`] keeps where the image went, a description of where
+//! it came from (e.g., `ymodem:Image`) and its SHA-256 in a record placed in
+//! the `.noinit` section, which a reset that keeps RAM doesn't clear. The
+//! next boot finds it again with [`cached`], or with [`lookup`] for a given
+//! source description, and can use the image without transferring it.
+//!
+//! A cold boot leaves garbage in the record, so it's only trusted once its
+//! magic and CRC-32 check out, its description fits and its range is RAM;
+//! only then is the range hashed, and a different hash means the image was
+//! overwritten since. A record failing any check is cleared, as is one
+//! looked up for a different source description.
+
+use crate::memory::map;
+use crate::selftest::{self, Outcome, SelfTest};
+use crate::utilities::crc32::crc32;
+use crate::utilities::sha256::{DIGEST_SIZE, sha256};
+
+use core::mem::{offset_of, size_of};
+use core::{ptr, slice};
+
+/// Record magic number: the ASCII string "WARM" read as a little-endian u32
+const CACHE_MAGIC: u32 = 0x4d524157;
+/// Longest source description
+pub const SOURCE_SIZE: usize = 64;
+
+/// Cache record, as kept across resets
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CacheRecord {
+    /// [`CACHE_MAGIC`] once an image is recorded
+    magic: u32,
+    /// CRC-32 of the fields following this one
+    crc: u32,
+    /// Address of the image
+    base: u64,
+    /// Size of the image in bytes
+    len: u64,
+    /// Length of the source description
+    source_len: u32,
+    /// Reserved, always 0
+    reserved: u32,
+    /// Source description, `source_len` bytes long
+    source: [u8; SOURCE_SIZE],
+    /// SHA-256 of the image
+    hash: [u8; DIGEST_SIZE],
+}
+
+const _: () = assert!(size_of::<CacheRecord>() == 128);
+const _: () = assert!(offset_of!(CacheRecord, base) == 8);
+
+impl CacheRecord {
+    /// A record holding no image
+    const EMPTY: CacheRecord = CacheRecord {
+        magic: 0,
+        crc: 0,
+        base: 0,
+        len: 0,
+        source_len: 0,
+        reserved: 0,
+        source: [0; SOURCE_SIZE],
+        hash: [0; DIGEST_SIZE],
+    };
+
+    /// Returns the CRC-32 of the fields covered by `crc`
+    fn checksum(&self) -> u32 {
+        let start = offset_of!(CacheRecord, base);
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                (self as *const CacheRecord as *const u8).add(start),
+                size_of::<CacheRecord>() - start,
+            )
+        };
+
+        return crc32(bytes);
+    }
+
+    /// Creates the record of the `len` bytes at `base`, from `source`
+    ///
+    /// Returns `None` if the description is too long or the range isn't
+    /// RAM.
+    fn new(source: &[u8], base: usize, len: usize) -> Option<CacheRecord> {
+        if source.len() > SOURCE_SIZE || len == 0 || !map::is_ram_range(base, len) {
+            return None;
+        }
+        let image = unsafe { slice::from_raw_parts(base as *const u8, len) };
+        let mut record = CacheRecord {
+            magic: CACHE_MAGIC,
+            base: base as u64,
+            len: len as u64,
+            source_len: source.len() as u32,
+            hash: sha256(image),
+            ..CacheRecord::EMPTY
+        };
+
+        record.source[..source.len()].copy_from_slice(source);
+        record.crc = record.checksum();
+        return Some(record);
+    }
+
+    /// Returns the image the record describes, if the record is intact and
+    /// the image still hashes the same
+    fn check(&self) -> Option<CachedImage> {
+        if self.magic != CACHE_MAGIC || self.crc != self.checksum() {
+            return None;
+        }
+        let source_len = self.source_len as usize;
+        let (base, len) = (self.base as usize, self.len as usize);
+        if source_len > SOURCE_SIZE || len == 0 || !map::is_ram_range(base, len) {
+            return None;
+        }
+        let image = unsafe { slice::from_raw_parts(base as *const u8, len) };
+        if sha256(image) != self.hash {
+            return None;
+        }
+        return Some(CachedImage {
+            base,
+            len,
+            source: self.source,
+            source_len,
+        });
+    }
+}
+
+/// An image found in the cache
+#[derive(Clone, Copy, Debug)]
+pub struct CachedImage {
+    /// Address of the image
+    pub base: usize,
+    /// Size of the image in bytes
+    pub len: usize,
+    /// Source description, `source_len` bytes long
+    source: [u8; SOURCE_SIZE],
+    /// Length of the source description
+    source_len: usize,
+}
+
+impl CachedImage {
+    /// Returns the description of where the image came from
+    pub fn source(&self) -> &[u8] {
+        return &self.source[..self.source_len];
+    }
+}
+
+/// The cache record
+///
+/// Kept out of the loaded image and never cleared at boot, so it survives a
+/// reset that keeps RAM.
+#[unsafe(link_section = ".noinit")]
+static mut RECORD: CacheRecord = CacheRecord::EMPTY;
+
+/// Records the `len` bytes at `base`, received from `source`, replacing
+/// the image cached before
+///
+/// Returns `false`, leaving the cache empty, if the description is longer
+/// than [`SOURCE_SIZE`] or the range isn't RAM.
+pub fn record(source: &[u8], base: usize, len: usize) -> bool {
+    let record = CacheRecord::new(source, base, len);
+
+    unsafe {
+        ptr::write_volatile(&raw mut RECORD, record.unwrap_or(CacheRecord::EMPTY));
+    }
+    return record.is_some();
+}
+
+/// Returns the cached image, if it's still intact
+///
+/// A record failing its checks is cleared.
+pub fn cached() -> Option<CachedImage> {
+    let image = unsafe { ptr::read_volatile(&raw const RECORD) }.check();
+
+    if image.is_none() {
+        clear();
+    }
+    return image;
+}
+
+/// Returns the image cached from `source`, if it's still intact
+///
+/// The cache is cleared if it holds an image from another source.
+pub fn lookup(source: &[u8]) -> Option<CachedImage> {
+    let image = cached()?;
+
+    if image.source() != source {
+        clear();
+        return None;
+    }
+    return Some(image);
+}
+
+/// Forgets the cached image
+pub fn clear() {
+    unsafe {
+        ptr::write_volatile(&raw mut RECORD, CacheRecord::EMPTY);
+    }
+}
+
+/// Size of [`TEST_IMAGE`]
+const TEST_IMAGE_SIZE: usize = 64;
+
+/// Image cached by the self-test
+static mut TEST_IMAGE: [u8; TEST_IMAGE_SIZE] = [0; TEST_IMAGE_SIZE];
+
+/// Self-test: a recorded image is found again with its source, and a record
+/// is refused once the image changed, once the record itself changed, or
+/// when it's garbage as after a cold boot
+///
+/// Skipped if no RAM is known.
+pub fn selftest() -> Outcome {
+    let image = &raw mut TEST_IMAGE;
+    let image = unsafe { &mut *image };
+    let base = image.as_ptr() as usize;
+
+    if map::regions().is_empty() {
+        return Outcome::Skipped;
+    }
+    for (i, byte) in image.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let Some(mut record) = CacheRecord::new(b"xmodem", base, TEST_IMAGE_SIZE) else {
+        return Outcome::Fail;
+    };
+    match record.check() {
+        Some(found)
+            if found.base == base
+                && found.len == TEST_IMAGE_SIZE
+                && found.source() == b"xmodem" => {}
+        _ => return Outcome::Fail,
+    }
+    if CacheRecord::new(&[b'x'; SOURCE_SIZE + 1], base, TEST_IMAGE_SIZE).is_some() {
+        return Outcome::Fail;
+    }
+
+    image[TEST_IMAGE_SIZE - 1] ^= 1;
+    let overwritten = record.check().is_none();
+    image[TEST_IMAGE_SIZE - 1] ^= 1;
+    record.len -= 1;
+    let tampered = record.check().is_none();
+    let garbage = unsafe {
+        ptr::write_bytes(&raw mut record as *mut u8, 0xa5, size_of::<CacheRecord>());
+        record.magic = CACHE_MAGIC;
+        record.check().is_none()
+    };
+
+    if overwritten && tampered && garbage {
+        return Outcome::Pass;
+    }
+    return Outcome::Fail;
+}
+
+/// Registers the self-test of the warm cache
+pub fn register_selftests() {
+    selftest::register(SelfTest {
+        name: b"warmcache",
+        run: selftest,
+    });
+}
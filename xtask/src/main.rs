@@ -0,0 +1,1190 @@
+//! Host-side tasks for the bootloader
+//!
+//! Run with `cargo xtask <task>` from the repository root:
+//!
+//! - `qemu-test [scenario...]`: builds the bootloader for the QEMU `virt`
+//!   machine and the test kernels from `tests/fixtures`, boots them in
+//!   `qemu-system-aarch64` and checks the console output and the semihosting
+//!   exit status. Without a scenario name, all scenarios run. The task is
+//!   skipped (successfully) when QEMU or the cross toolchain isn't installed.
+//! - `stage1-size`: builds the first stage of a two-stage boot
+//!   (`make STAGE1=1`) and fails if `stage1.bin` outgrew [`STAGE1_MAX_SIZE`].
+//!   Skipped (successfully) when the cross toolchain isn't installed.
+//! - `stage2-flash <image> <flash> [load-addr]`: writes a QEMU `virt` flash
+//!   bank holding `<image>`, normally `bootloader.bin`, behind a stage-2
+//!   header, where the first stage looks for it. The image is loaded and
+//!   entered at `load-addr`, by default where the bootloader is linked.
+//!
+//! The cross tools default to the ones used by the Makefile and can be
+//! overridden with the `CC`, `LD` and `NM` environment variables. The
+//! `no-dtb` scenario also checks the DTB it dumps with `dtc`, if installed.
+
+mod qemu;
+mod stage2;
+mod xmodem;
+mod ymodem;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::{Duration, SystemTime};
+
+use qemu::Qemu;
+
+/// Alignment of the kernel after the bootloader binary (`KERNEL_ALIGN`)
+const KERNEL_ALIGN: u64 = 0x1000;
+/// Time allowed for each expected console marker
+const TIMEOUT: Duration = Duration::from_secs(20);
+/// Address the XMODEM and YMODEM scenarios upload to
+const XMODEM_ADDR: u64 = 0x4100_0000;
+/// Time limit given to the transfer of the XMODEM timeout scenario
+const XMODEM_LIMIT: Duration = Duration::from_secs(2);
+/// Largest first stage allowed, the on-chip SRAM it's meant for
+const STAGE1_MAX_SIZE: u64 = 32 * 1024;
+/// Size of a QEMU `virt` flash bank
+const FLASH_SIZE: usize = 0x400_0000;
+/// Offset of the stage-2 image in the flash (`stage1::STAGE2_OFFSET`)
+const STAGE2_OFFSET: usize = 0x8_0000;
+/// Address the bootloader is linked at on QEMU `virt` (`LOAD_ADDR`)
+const LOAD_ADDR: u64 = 0x4008_0000;
+/// First instruction of the entry code, `msr daifset, #4`
+const ENTRY_INSN: u32 = 0xd503_44df;
+/// Where x0 points in the no-DTB scenario: past the 128 MiB of RAM, where
+/// nothing answers
+const NO_DTB_ADDR: u64 = 0xdead_0000;
+/// `movz x0, #0xdead, lsl #16`, loading [`NO_DTB_ADDR`]
+const CLOBBER_X0_INSN: u32 = 0xd2a0_0000 | ((NO_DTB_ADDR as u32 >> 16) << 5);
+/// Size of the NVMe scenario's disk image, 2048 blocks of 512 bytes
+const DISK_SIZE: usize = 1024 * 1024;
+/// Room given to the DTB built from the board configuration
+/// (`fdt::SYNTH_SIZE`)
+const SYNTH_DTB_SIZE: usize = 2048;
+
+/// A test scenario
+struct Scenario {
+    /// Name used to select the scenario on the command line
+    name: &'static str,
+    /// Runs the scenario against the built artifacts
+    run: fn(&Artifacts) -> Result<(), String>,
+}
+
+/// Scenarios, in the order they run
+const SCENARIOS: [Scenario; 35] = [
+    Scenario {
+        name: "elf-boot",
+        run: elf_boot,
+    },
+    Scenario {
+        name: "bad-elf",
+        run: bad_elf,
+    },
+    Scenario {
+        name: "bad-elf-version",
+        run: bad_elf_version,
+    },
+    Scenario {
+        name: "bad-elf-ehsize",
+        run: bad_elf_ehsize,
+    },
+    Scenario {
+        name: "big-endian-elf",
+        run: big_endian_elf,
+    },
+    Scenario {
+        name: "bti-pauth-off",
+        run: bti_pauth_off,
+    },
+    Scenario {
+        name: "bti-pauth-keys",
+        run: bti_pauth_keys,
+    },
+    Scenario {
+        name: "handoff-el1",
+        run: handoff_el1,
+    },
+    Scenario {
+        name: "handoff-el2",
+        run: handoff_el2,
+    },
+    Scenario {
+        name: "handoff-el2-vhe",
+        run: handoff_el2_vhe,
+    },
+    Scenario {
+        name: "handoff-no-vhe",
+        run: handoff_no_vhe,
+    },
+    Scenario {
+        name: "logfmt-kv",
+        run: logfmt_kv,
+    },
+    Scenario {
+        name: "exception-dump",
+        run: exception_dump,
+    },
+    Scenario {
+        name: "dump-golden",
+        run: dump_golden,
+    },
+    Scenario {
+        name: "cpu-idle",
+        run: cpu_idle,
+    },
+    Scenario {
+        name: "nvme-read",
+        run: nvme_read,
+    },
+    Scenario {
+        name: "console-sweep",
+        run: console_sweep,
+    },
+    Scenario {
+        name: "xmodem-load",
+        run: xmodem_load,
+    },
+    Scenario {
+        name: "xmodem-retry",
+        run: xmodem_retry,
+    },
+    Scenario {
+        name: "xmodem-timeout",
+        run: xmodem_timeout,
+    },
+    Scenario {
+        name: "ymodem-load",
+        run: ymodem_load,
+    },
+    Scenario {
+        name: "smbios",
+        run: smbios,
+    },
+    Scenario {
+        name: "line-editing",
+        run: line_editing,
+    },
+    Scenario {
+        name: "monitor-history",
+        run: monitor_history,
+    },
+    Scenario {
+        name: "monitor-script",
+        run: monitor_script,
+    },
+    Scenario {
+        name: "warm-cache",
+        run: warm_cache,
+    },
+    Scenario {
+        name: "devices",
+        run: devices,
+    },
+    Scenario {
+        name: "no-dtb",
+        run: no_dtb,
+    },
+    Scenario {
+        name: "ramfb-console",
+        run: ramfb_console,
+    },
+    Scenario {
+        name: "rtc-date",
+        run: rtc_date,
+    },
+    Scenario {
+        name: "crash-sp-align",
+        run: crash_sp_align,
+    },
+    Scenario {
+        name: "crash-brk",
+        run: crash_brk,
+    },
+    Scenario {
+        name: "crash-wild-branch",
+        run: crash_wild_branch,
+    },
+    Scenario {
+        name: "crash-stack-overflow",
+        run: crash_stack_overflow,
+    },
+    Scenario {
+        name: "crash-panic",
+        run: crash_panic,
+    },
+];
+
+/// Files produced by the build step
+struct Artifacts {
+    /// Bootloader followed by the hello test kernel
+    hello: PathBuf,
+    /// Bootloader followed by a hello kernel with a corrupted ELF header
+    bad_elf: PathBuf,
+    /// Bootloader followed by a hello kernel with a wrong ELF version
+    bad_version: PathBuf,
+    /// Bootloader followed by a hello kernel with a wrong ELF header size
+    bad_ehsize: PathBuf,
+    /// Bootloader followed by a hello kernel marked big-endian
+    big_endian: PathBuf,
+    /// Bootloader followed by the fault test kernel
+    fault: PathBuf,
+    /// Bootloader followed by the branch protection test kernel
+    bti: PathBuf,
+    /// Bootloader followed by the exception level test kernel
+    el: PathBuf,
+    /// Bootloader entered with x0 pointing at nothing instead of the DTB,
+    /// followed by the hello test kernel
+    no_dtb: PathBuf,
+    /// Raw disk image for the NVMe drive: the hello test kernel, padded to
+    /// [`DISK_SIZE`]
+    disk: PathBuf,
+    /// Bootloader built for the generic-dtb board and entered without a
+    /// DTB, followed by the hello test kernel
+    unknown_board: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("qemu-test") => return qemu_test(&args[1..]),
+        Some("stage1-size") => return stage1_size(),
+        Some("stage2-flash") if (3..=4).contains(&args.len()) => {
+            return stage2_flash(&args[1..]);
+        }
+        _ => {
+            eprintln!("usage: cargo xtask qemu-test [scenario...]");
+            eprintln!("       cargo xtask stage1-size");
+            eprintln!("       cargo xtask stage2-flash <image> <flash> [load-addr]");
+            eprintln!("scenarios:");
+            for scenario in &SCENARIOS {
+                eprintln!("  {}", scenario.name);
+            }
+            return ExitCode::FAILURE;
+        }
+    }
+}
+
+/// Runs the QEMU integration scenarios named in `names`, or all of them
+fn qemu_test(names: &[String]) -> ExitCode {
+    let tools = [qemu::QEMU.to_string(), tool("CC"), tool("LD"), tool("NM")];
+    for program in &tools {
+        if !is_installed(program) {
+            println!("skipping QEMU tests: {program} not found");
+            return ExitCode::SUCCESS;
+        }
+    }
+
+    let artifacts = match build() {
+        Ok(artifacts) => artifacts,
+        Err(e) => {
+            eprintln!("build failed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut failed = 0;
+    for scenario in SCENARIOS
+        .iter()
+        .filter(|s| names.is_empty() || names.iter().any(|n| n == s.name))
+    {
+        match (scenario.run)(&artifacts) {
+            Ok(()) => println!("PASS {}", scenario.name),
+            Err(e) => {
+                println!("FAIL {}: {e}", scenario.name);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed != 0 {
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
+}
+
+/// Builds the first stage and checks it still fits in [`STAGE1_MAX_SIZE`]
+fn stage1_size() -> ExitCode {
+    let root = root();
+
+    for program in [tool("CC"), tool("LD")] {
+        if !is_installed(&program) {
+            println!("skipping the stage 1 size check: {program} not found");
+            return ExitCode::SUCCESS;
+        }
+    }
+    let size = run(Command::new("make")
+        .args(["BOARD=qemu-virt", "STAGE1=1"])
+        .current_dir(&root))
+    .and_then(|_| fs::metadata(root.join("stage1.bin")).map_err(|e| e.to_string()));
+    return match size.map(|m| m.len()) {
+        Ok(size) if size <= STAGE1_MAX_SIZE => {
+            println!("PASS stage1-size: {size} of {STAGE1_MAX_SIZE} bytes");
+            ExitCode::SUCCESS
+        }
+        Ok(size) => {
+            println!("FAIL stage1-size: {size} bytes, over {STAGE1_MAX_SIZE}");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("build failed: {e}");
+            ExitCode::FAILURE
+        }
+    };
+}
+
+/// Writes the flash bank of `args`, holding the image of `args` behind a
+/// stage-2 header at [`STAGE2_OFFSET`]
+///
+/// The rest of the bank is left erased, so the environment and the boot
+/// record start empty.
+fn stage2_flash(args: &[String]) -> ExitCode {
+    let load_addr = match args.get(2) {
+        Some(addr) => match u64::from_str_radix(addr.trim_start_matches("0x"), 16) {
+            Ok(addr) => addr,
+            Err(_) => {
+                eprintln!("bad load address: {addr}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => LOAD_ADDR,
+    };
+    let image = match fs::read(&args[0]) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}: {e}", args[0]);
+            return ExitCode::FAILURE;
+        }
+    };
+    if STAGE2_OFFSET + stage2::HEADER_SIZE + image.len() > FLASH_SIZE {
+        eprintln!("{}: too large for the flash", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let mut flash = vec![0xffu8; FLASH_SIZE];
+    let stored = stage2::with_header(&image, load_addr, load_addr);
+    flash[STAGE2_OFFSET..STAGE2_OFFSET + stored.len()].copy_from_slice(&stored);
+    if let Err(e) = fs::write(&args[1], flash) {
+        eprintln!("{}: {e}", args[1]);
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
+}
+
+/// Returns the cross tool configured by environment variable `var`
+fn tool(var: &str) -> String {
+    if let Ok(value) = env::var(var) {
+        return value;
+    }
+    return match var {
+        "CC" => "aarch64-linux-gnu-gcc-14",
+        "LD" => "aarch64-linux-gnu-ld",
+        _ => "aarch64-linux-gnu-nm",
+    }
+    .to_string();
+}
+
+/// Checks whether `program` can be run
+fn is_installed(program: &str) -> bool {
+    return Command::new(program)
+        .arg("--version")
+        .output()
+        .is_ok_and(|out| out.status.success());
+}
+
+/// Runs `command`, failing with its stderr if it doesn't succeed
+fn run(command: &mut Command) -> Result<Vec<u8>, String> {
+    let out = command.output().map_err(|e| format!("{command:?}: {e}"))?;
+
+    if !out.status.success() {
+        return Err(format!(
+            "{command:?}: {}",
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    return Ok(out.stdout);
+}
+
+/// Returns the repository root
+fn root() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf();
+}
+
+/// Builds the bootloader and the test images
+fn build() -> Result<Artifacts, String> {
+    let root = root();
+    let out = root.join("target").join("qemu-tests");
+    let fixtures = root.join("tests").join("fixtures");
+    fs::create_dir_all(&out).map_err(|e| e.to_string())?;
+
+    // A board nothing is known of: generic-dtb entered without a DTB. Built
+    // first, so the build left in the tree is the qemu-virt one; make
+    // doesn't see the board change, hence -B
+    run(Command::new("make")
+        .args(["-B", "BOARD=generic-dtb"])
+        .current_dir(&root))?;
+    let elf = root.join("bootloader.elf");
+    let unknown_board = without_dtb(
+        fs::read(root.join("bootloader.bin")).map_err(|e| e.to_string())?,
+        &elf,
+    )?;
+    let unknown_offset = kernel_offset(&elf)?;
+
+    run(Command::new("make")
+        .args(["-B", "BOARD=qemu-virt"])
+        .current_dir(&root))?;
+    let bootloader = fs::read(root.join("bootloader.bin")).map_err(|e| e.to_string())?;
+    let kernel_offset = kernel_offset(&elf)?;
+    let no_dtb = without_dtb(bootloader.clone(), &elf)?;
+
+    let hello = build_kernel(&fixtures, &out, "hello", None)?;
+    let fault = build_kernel(&fixtures, &out, "fault", Some("0xffff00000000"))?;
+    let bti = build_kernel(&fixtures, &out, "bti", None)?;
+    let el = build_kernel(&fixtures, &out, "el", None)?;
+    let mut bad = hello.clone();
+    // e_machine = EM_X86_64
+    bad[18..20].copy_from_slice(&62u16.to_le_bytes());
+    let mut bad_version = hello.clone();
+    // e_version = 2
+    bad_version[20..24].copy_from_slice(&2u32.to_le_bytes());
+    let mut bad_ehsize = hello.clone();
+    // e_ehsize = 52, the ELF32 header size
+    bad_ehsize[52..54].copy_from_slice(&52u16.to_le_bytes());
+    let mut big_endian = hello.clone();
+    // EI_DATA = ELFDATA2MSB
+    big_endian[5] = 2;
+
+    let artifacts = Artifacts {
+        hello: out.join("hello.img"),
+        bad_elf: out.join("bad-elf.img"),
+        bad_version: out.join("bad-elf-version.img"),
+        bad_ehsize: out.join("bad-elf-ehsize.img"),
+        big_endian: out.join("big-endian-elf.img"),
+        fault: out.join("fault.img"),
+        bti: out.join("bti.img"),
+        el: out.join("el.img"),
+        no_dtb: out.join("no-dtb.img"),
+        disk: out.join("nvme-disk.img"),
+        unknown_board: out.join("unknown-board.img"),
+    };
+    for (path, loader, kernel) in [
+        (&artifacts.hello, &bootloader, &hello),
+        (&artifacts.bad_elf, &bootloader, &bad),
+        (&artifacts.bad_version, &bootloader, &bad_version),
+        (&artifacts.bad_ehsize, &bootloader, &bad_ehsize),
+        (&artifacts.big_endian, &bootloader, &big_endian),
+        (&artifacts.fault, &bootloader, &fault),
+        (&artifacts.bti, &bootloader, &bti),
+        (&artifacts.el, &bootloader, &el),
+        (&artifacts.no_dtb, &no_dtb, &hello),
+    ] {
+        let mut image = loader.clone();
+        image.resize(kernel_offset as usize, 0);
+        image.extend_from_slice(kernel);
+        fs::write(path, image).map_err(|e| e.to_string())?;
+    }
+    let mut disk = hello.clone();
+    disk.resize(DISK_SIZE, 0);
+    fs::write(&artifacts.disk, disk).map_err(|e| e.to_string())?;
+    let mut image = unknown_board;
+    image.resize(unknown_offset as usize, 0);
+    image.extend_from_slice(&hello);
+    fs::write(&artifacts.unknown_board, image).map_err(|e| e.to_string())?;
+
+    return Ok(artifacts);
+}
+
+/// Returns `bootloader`, whose ELF is `elf`, patched to be entered with x0
+/// pointing at nothing instead of the DTB
+fn without_dtb(mut bootloader: Vec<u8>, elf: &Path) -> Result<Vec<u8>, String> {
+    // Straight out of reset SError is masked already, so the instruction
+    // masking it can load x0 instead
+    let entry = (symbol(elf, "_start")? - symbol(elf, "__bootloader_start")?) as usize;
+
+    if bootloader.get(entry..entry + 4) != Some(&ENTRY_INSN.to_le_bytes()[..]) {
+        return Err("unexpected first instruction at _start".to_string());
+    }
+    bootloader[entry..entry + 4].copy_from_slice(&CLOBBER_X0_INSN.to_le_bytes());
+    return Ok(bootloader);
+}
+
+/// Returns the offset of the kernel from the start of the bootloader image
+///
+/// Mirrors the entry code: the kernel starts at the first `KERNEL_ALIGN`
+/// boundary after `__bootloader_end`.
+fn kernel_offset(elf: &Path) -> Result<u64, String> {
+    let start = symbol(elf, "__bootloader_start")?;
+    let end = symbol(elf, "__bootloader_end")?;
+    return Ok(((end + KERNEL_ALIGN) & !(KERNEL_ALIGN - 1)) - start);
+}
+
+/// Returns the address of symbol `name` of `elf`
+fn symbol(elf: &Path, name: &str) -> Result<u64, String> {
+    let symbols = run(Command::new(tool("NM")).arg(elf))?;
+
+    return String::from_utf8_lossy(&symbols)
+        .lines()
+        .find(|line| line.ends_with(&format!(" {name}")))
+        .and_then(|line| u64::from_str_radix(line.split(' ').next()?, 16).ok())
+        .ok_or(format!("symbol {name} not found"));
+}
+
+/// Assembles and links test kernel `name`, returning the ELF file contents
+fn build_kernel(
+    fixtures: &Path,
+    out: &Path,
+    name: &str,
+    base: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    let obj = out.join(format!("{name}.o"));
+    let elf = out.join(format!("{name}.elf"));
+
+    run(Command::new(tool("CC"))
+        .args(["-c", "-x", "assembler-with-cpp", "-o"])
+        .arg(&obj)
+        .arg(fixtures.join(format!("{name}.S"))))?;
+
+    let mut ld = Command::new(tool("LD"));
+    ld.arg("-T").arg(fixtures.join("kernel.ld"));
+    if let Some(base) = base {
+        ld.arg(format!("--defsym=KERNEL_BASE={base}"));
+    }
+    run(ld.arg("-o").arg(&elf).arg(&obj))?;
+
+    return fs::read(&elf).map_err(|e| e.to_string());
+}
+
+/// A valid ELF kernel is loaded and started
+fn elf_boot(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// On a core with pointer authentication and BTI, a kernel built for branch
+/// protection starts with pointer authentication disabled by default
+fn bti_pauth_off(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn_on(&artifacts.bti, "max")?;
+
+    qemu.expect(b"Pointer authentication: disabled", TIMEOUT)?;
+    qemu.expect(b"TEST-KERNEL: pauth off", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// The same kernel starts with random keys when the monitor asks for them,
+/// and its signed pointers authenticate
+fn bti_pauth_keys(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn_on(&artifacts.bti, "max")?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"pauth keys\r")?;
+    qemu.expect(b"Pointer authentication keys set at handoff", TIMEOUT)?;
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"Pointer authentication: random keys", TIMEOUT)?;
+    qemu.expect(b"TEST-KERNEL: pauth keys", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Started at EL2 (`virtualization=on`), the bootloader drops the payload
+/// to EL1 by default
+fn handoff_el1(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.el)?;
+
+    qemu.expect(b"TEST-KERNEL: EL1", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// `handoff el2` leaves the payload at EL2 with E2H clear
+fn handoff_el2(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.el)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"handoff el2\r")?;
+    qemu.expect(b"Payload entered at EL2 (nVHE)", TIMEOUT)?;
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: EL2 nVHE", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// On a core with VHE, `handoff el2-vhe` leaves the payload at EL2 with E2H
+/// set
+fn handoff_el2_vhe(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn_on(&artifacts.el, "max")?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"handoff el2-vhe\r")?;
+    qemu.expect(b"Payload entered at EL2 (VHE)", TIMEOUT)?;
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: EL2 VHE", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// With `logfmt` set to `kv`, the rest of the boot is logged as key=value
+/// records, with the measurement and handoff events
+fn logfmt_kv(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"setenv logfmt kv\r")?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"mod=measure event=measure component=dtb size=", TIMEOUT)?;
+    qemu.expect(b"mod=boot event=stage stage=before_handoff us=", TIMEOUT)?;
+    qemu.expect(b"mod=boot event=handoff entry=0x", TIMEOUT)?;
+    qemu.expect(b" el=1 vhe=0", TIMEOUT)?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// VHE is refused on a core without it, and the payload still starts at EL1
+fn handoff_no_vhe(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.el)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"handoff el2-vhe\r")?;
+    qemu.expect(b"Error 0x000b0200: handoff: VHE not implemented", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: EL1", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// A kernel with a bad ELF header is rejected with the right error
+fn bad_elf(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.bad_elf)?;
+
+    qemu.expect(b"Error 0x00010600: ELF: invalid machine", TIMEOUT)?;
+    return qemu.expect(b"Boot failed with error 0x00010600", TIMEOUT);
+}
+
+/// A kernel with a wrong ELF version is rejected
+fn bad_elf_version(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.bad_version)?;
+
+    qemu.expect(b"Error 0x00010700: ELF: invalid version", TIMEOUT)?;
+    return qemu.expect(b"Boot failed with error 0x00010700", TIMEOUT);
+}
+
+/// A kernel with a wrong ELF header size is rejected
+fn bad_elf_ehsize(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.bad_ehsize)?;
+
+    qemu.expect(b"Error 0x00010800: ELF: invalid header size", TIMEOUT)?;
+    return qemu.expect(b"Boot failed with error 0x00010800", TIMEOUT);
+}
+
+/// A big-endian kernel is rejected as unsupported
+fn big_endian_elf(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.big_endian)?;
+
+    qemu.expect(
+        b"Error 0x00010900: ELF: big-endian images are not supported",
+        TIMEOUT,
+    )?;
+    return qemu.expect(b"Boot failed with error 0x00010900", TIMEOUT);
+}
+
+/// A fault in the bootloader prints the exception dump
+fn exception_dump(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.fault)?;
+
+    qemu.expect(b"Synchronous Exception handler", TIMEOUT)?;
+    qemu.expect(b"Faulting instruction at 0x", TIMEOUT)?;
+    qemu.expect(b"Code:", TIMEOUT)?;
+    qemu.expect(b"\n=> 0x", TIMEOUT)?;
+    qemu.expect(b"Registers:", TIMEOUT)?;
+    // Data abort from the current exception level
+    return qemu.expect(b"esr: 0x0000000096", TIMEOUT);
+}
+
+/// The dumps of synthetic exceptions match the files of `tests/golden`
+fn dump_golden(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"selftest dump-golden\r")?;
+    return qemu.expect(b"dump-golden: pass", TIMEOUT);
+}
+
+/// On a board nothing describes, the console sweep finds the PL011 of the
+/// machine and says so in the banner
+fn console_sweep(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.unknown_board)?;
+
+    qemu.expect(
+        format!("No valid DTB at 0x{NO_DTB_ADDR:x}").as_bytes(),
+        TIMEOUT,
+    )?;
+    return qemu.expect(b"Console found by probing: PL011 at 0x9000000", TIMEOUT);
+}
+
+/// The NVMe drive is found behind the PCIe host, its namespace identified,
+/// and blocks read through a PRP list match those read one at a time
+fn nvme_read(artifacts: &Artifacts) -> Result<(), String> {
+    let drive = format!(
+        "file={},if=none,id=disk,format=raw",
+        artifacts.disk.display()
+    );
+    let extra = [
+        "-drive".to_string(),
+        drive,
+        "-device".to_string(),
+        "nvme,serial=xtask,drive=disk".to_string(),
+    ];
+    let mut qemu = Qemu::spawn_with(&artifacts.hello, &extra)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"nvme\r")?;
+    qemu.expect(b"NVMe controller ", TIMEOUT)?;
+    qemu.expect(
+        format!("Namespace 1: {} blocks of 512 bytes", DISK_SIZE / 512).as_bytes(),
+        TIMEOUT,
+    )?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"nvme read 0x48000000 0 #16\r")?;
+    qemu.expect(b"Read bytes: 0x", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"selftest nvme\r")?;
+    return qemu.expect(b"nvme: pass", TIMEOUT);
+}
+
+/// The boot core idles through PSCI CPU_SUSPEND and the timer tick wakes it
+fn cpu_idle(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"selftest idle\r")?;
+    return qemu.expect(b"idle: pass", TIMEOUT);
+}
+
+/// Runs `crash <kind>` from the monitor and expects `markers`, in order
+///
+/// Every fatal path ends with the panic report, so the markers end with it:
+/// a path hanging half-way misses it.
+fn crash(artifacts: &Artifacts, kind: &str, markers: &[&[u8]]) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(format!("crash {kind}\r").as_bytes())?;
+    for marker in markers {
+        qemu.expect(marker, TIMEOUT)?;
+    }
+    return Ok(());
+}
+
+/// A misaligned stack pointer is reported from the exception stack
+fn crash_sp_align(artifacts: &Artifacts) -> Result<(), String> {
+    return crash(
+        artifacts,
+        "sp-align",
+        &[
+            b"Bad stack pointer in exception handler",
+            b"EC=0x26",
+            b"Registers:",
+            b"(not a multiple of 16)",
+            b"Panic at ",
+        ],
+    );
+}
+
+/// An unclaimed `brk` is fatal
+fn crash_brk(artifacts: &Artifacts) -> Result<(), String> {
+    return crash(
+        artifacts,
+        "brk",
+        &[
+            b"Synchronous Exception handler",
+            b"EC=0x3C",
+            b"Registers:",
+            b"Panic at ",
+        ],
+    );
+}
+
+/// A branch to nowhere is reported as an instruction abort
+fn crash_wild_branch(artifacts: &Artifacts) -> Result<(), String> {
+    return crash(
+        artifacts,
+        "wild-branch",
+        &[
+            b"Synchronous Exception handler",
+            b"EC=0x21",
+            b"Registers:",
+            b"Panic at ",
+        ],
+    );
+}
+
+/// A stack overflow is reported from the exception stack
+fn crash_stack_overflow(artifacts: &Artifacts) -> Result<(), String> {
+    return crash(
+        artifacts,
+        "stack-overflow",
+        &[
+            b"Bad stack pointer in exception handler",
+            b"Registers:",
+            b"(stack overflow, limit 0x",
+            b"Panic at ",
+        ],
+    );
+}
+
+/// An explicit panic is reported with its location and message, and the
+/// registers last recorded, on entering the monitor
+fn crash_panic(artifacts: &Artifacts) -> Result<(), String> {
+    return crash(
+        artifacts,
+        "panic",
+        &[
+            b"Panic at src/diagnostics.rs:",
+            b": crash test",
+            b"Last known context: monitor",
+            b"Registers:",
+            b"x30: 0x",
+            b"sp : 0x",
+        ],
+    );
+}
+
+/// An image uploaded over XMODEM from the monitor lands in memory
+fn xmodem_load(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+    let payload: Vec<u8> = (0..=255u8).cycle().take(1024).collect();
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(format!("load {XMODEM_ADDR:x} 1000\r").as_bytes())?;
+    qemu.expect(b"Waiting for XMODEM transfer...", TIMEOUT)?;
+    xmodem::send(&mut qemu, &payload)?;
+    qemu.expect(b"Received bytes: 0x0000000000000400", TIMEOUT)?;
+
+    qemu.send(format!("md {XMODEM_ADDR:x} 10\r").as_bytes())?;
+    qemu.expect(
+        format!("{XMODEM_ADDR:016x}: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f").as_bytes(),
+        TIMEOUT,
+    )?;
+
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Corrupted blocks are NAKed and their retransmissions land in memory
+fn xmodem_retry(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+    let payload: Vec<u8> = (0..=255u8).cycle().take(1024).collect();
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(format!("load {XMODEM_ADDR:x} 1000\r").as_bytes())?;
+    qemu.expect(b"Waiting for XMODEM transfer...", TIMEOUT)?;
+    xmodem::send_with_errors(&mut qemu, &payload, &[0, 5])?;
+    qemu.expect(b"Received bytes: 0x0000000000000400", TIMEOUT)?;
+
+    qemu.send(format!("md {:x} 10\r", XMODEM_ADDR + 0x280).as_bytes())?;
+    return qemu.expect(
+        format!(
+            "{:016x}: 80 81 82 83 84 85 86 87 88 89 8a 8b 8c 8d 8e 8f",
+            XMODEM_ADDR + 0x280
+        )
+        .as_bytes(),
+        TIMEOUT,
+    );
+}
+
+/// A file uploaded over YMODEM lands in memory with its exact length
+fn ymodem_load(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+    let payload: Vec<u8> = (0..=255u8).cycle().take(1500).collect();
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(format!("rb {XMODEM_ADDR:x} 1000\r").as_bytes())?;
+    qemu.expect(b"Waiting for YMODEM transfer...", TIMEOUT)?;
+    ymodem::send(&mut qemu, "payload.bin", &payload)?;
+    qemu.expect(b"Received payload.bin\n", TIMEOUT)?;
+    qemu.expect(b"Received bytes: 0x00000000000005dc", TIMEOUT)?;
+
+    qemu.send(format!("md {:x} 10\r", XMODEM_ADDR + 0x5d0).as_bytes())?;
+    return qemu.expect(
+        format!(
+            "{:016x}: d0 d1 d2 d3 d4 d5 d6 d7 d8 d9 da db 00 00 00 00",
+            XMODEM_ADDR + 0x5d0
+        )
+        .as_bytes(),
+        TIMEOUT,
+    );
+}
+
+/// The SMBIOS tables identify the board, with well-formed string sets
+fn smbios(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"smbios\r")?;
+    qemu.expect(b"Entry point at 0x", TIMEOUT)?;
+    qemu.expect(b"Type 0, handle 0\n  aarch64_bootloader", TIMEOUT)?;
+    qemu.expect(b"Type 1, handle 1\n  QEMU\n  QEMU virt\n", TIMEOUT)?;
+    qemu.expect(b"Type 2, handle 2\n  QEMU\n  QEMU virt\n", TIMEOUT)?;
+    qemu.expect(b"Type 127, handle 3\n> ", TIMEOUT)?;
+
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Cursor keys edit the monitor's command line, and unknown escape
+/// sequences don't end up in it
+fn line_editing(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    // "hep", Left, "l", Ctrl-Right (unknown), End
+    qemu.send(b"hep\x1b[Dl\x1b[1;5C\x1b[F\r")?;
+    qemu.expect(b"help                     List the commands", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    // "xboot", Home, Delete
+    qemu.send(b"xboot\x1b[H\x1b[3~\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Earlier commands are recalled with Up, listed by `history` and rerun
+/// with `!N`, and command names complete with Tab
+fn monitor_history(artifacts: &Artifacts) -> Result<(), String> {
+    const HELP_LINE: &[u8] = b"help                     List the commands\n";
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"help\r")?;
+    qemu.expect(HELP_LINE, TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    // Up recalls "help"; running it again doesn't add a repeat
+    qemu.send(b"\x1b[A\r")?;
+    qemu.expect(HELP_LINE, TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"history\r")?;
+    qemu.expect(b"  1  help\n  2  history\n> ", TIMEOUT)?;
+    qemu.send(b"!1\r")?;
+    qemu.expect(HELP_LINE, TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    // "boo" completes to "boot", the prefix shared by "boot" and "bootid"
+    qemu.send(b"boo\t\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Command lines chain commands on their status, substitute variables and
+/// run them, and report syntax errors where they are
+fn monitor_script(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"setenv greet 'echo hi'\r")?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"run greet && echo ok || echo no\r")?;
+    qemu.expect(b"hi\nok\n> ", TIMEOUT)?;
+    qemu.send(b"echo ${greet}; nosuch && echo no || echo recovered\r")?;
+    qemu.expect(b"echo hi\nUnknown command: nosuch\nrecovered\n> ", TIMEOUT)?;
+    qemu.send(b"echo a && && echo b\r")?;
+    qemu.expect(b"  echo a && && echo b\n            ^\n", TIMEOUT)?;
+    qemu.expect(b"Syntax error: missing command\n> ", TIMEOUT)?;
+    qemu.send(b"setenv again 'run again'; run again || echo stopped\r")?;
+    qemu.expect(b"run: nested too deep\nstopped\n> ", TIMEOUT)?;
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// A file uploaded over YMODEM is offered again after a warm reset, until
+/// the cache is cleared; a cold boot has nothing cached
+fn warm_cache(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+    let payload: Vec<u8> = (0..=255u8).cycle().take(1500).collect();
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"cache\r")?;
+    qemu.expect(b"No cached image\n> ", TIMEOUT)?;
+    qemu.send(format!("rb {XMODEM_ADDR:x} 1000\r").as_bytes())?;
+    qemu.expect(b"Waiting for YMODEM transfer...", TIMEOUT)?;
+    ymodem::send(&mut qemu, "payload.bin", &payload)?;
+    qemu.expect(b"Received bytes: 0x00000000000005dc", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+
+    qemu.send(b"reset\r")?;
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"Cached image from ymodem:payload.bin\n", TIMEOUT)?;
+    qemu.expect(
+        format!("  Address: 0x{XMODEM_ADDR:016x}\n  Size: 0x00000000000005dc\n").as_bytes(),
+        TIMEOUT,
+    )?;
+    qemu.expect(b"'cache boot' runs it without a new transfer\n> ", TIMEOUT)?;
+    qemu.send(b"cache clear; cache\r")?;
+    qemu.expect(b"No cached image\n> ", TIMEOUT)?;
+
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// `devices` lists the devices of the DTB: the console UART and the GIC,
+/// already set up by the board, and the timer probed once the GIC is known
+fn devices(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    // One listing per device, as the order follows the DTB
+    for (name, driver, status) in [
+        ("intc@8000000", "gicv3", "already present"),
+        ("pl011@9000000", "pl011", "already present"),
+        ("timer", "timer", "probed"),
+    ] {
+        qemu.send(b"devices\r")?;
+        qemu.expect(
+            format!("{name:<24} {driver:<12} {status}\n").as_bytes(),
+            TIMEOUT,
+        )?;
+        qemu.expect(b"> ", TIMEOUT)?;
+    }
+
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Entered with x0 pointing where nothing answers, the bootloader builds a
+/// DTB from the board configuration, which `dtc` accepts, and boots the
+/// kernel with it
+fn no_dtb(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.no_dtb)?;
+    let dump = artifacts.no_dtb.with_file_name("no-dtb.dtb");
+    let mut addr = String::new();
+
+    qemu.expect(
+        format!("No valid DTB at 0x{NO_DTB_ADDR:x}").as_bytes(),
+        TIMEOUT,
+    )?;
+    qemu.expect(b"Built a DTB from the board configuration at 0x", TIMEOUT)?;
+    loop {
+        match qemu.read_byte(TIMEOUT)? {
+            b'\n' => break,
+            byte => addr.push(byte as char),
+        }
+    }
+    let addr = u64::from_str_radix(addr.trim(), 16).map_err(|e| format!("{addr:?}: {e}"))?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.save_memory(addr, SYNTH_DTB_SIZE, &dump, TIMEOUT)?;
+    if is_installed("dtc") {
+        let out = Command::new("dtc")
+            .args(["-I", "dtb", "-O", "dts", "-o", "/dev/null"])
+            .arg(&dump)
+            .output()
+            .map_err(|e| format!("dtc: {e}"))?;
+        if !out.status.success() || !out.stderr.is_empty() {
+            return Err(format!("dtc: {}", String::from_utf8_lossy(&out.stderr)));
+        }
+    } else {
+        println!("dtc not found, the DTB built isn't checked");
+    }
+
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// `video on` sets up the ramfb display in the mode asked for, which stays
+/// once the console is started
+fn ramfb_console(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"video\r")?;
+    qemu.expect(b"No framebuffer console\n> ", TIMEOUT)?;
+    qemu.send(b"video on 800x600 xbgr8888\r")?;
+    qemu.expect(b"Framebuffer console: 800x600 xbgr8888 at 0x", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"video on 640x480\r")?;
+    qemu.expect(b"Framebuffer console: 800x600 xbgr8888 at 0x", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(b"video on 640\r")?;
+    qemu.expect(b"Usage: video [on [WxH] [FMT]]", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+
+    qemu.send(b"boot\r")?;
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// Returns the current UTC date on the host as `YYYY-MM-DD`
+fn host_date() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // Days since 1 March 0000, so the leap day ends the year
+    let days = seconds / 86_400 + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    return format!("{year:04}-{month:02}-{day:02}");
+}
+
+/// The banner shows the current date read from the machine's RTC, which
+/// QEMU starts from the host's clock
+fn rtc_date(artifacts: &Artifacts) -> Result<(), String> {
+    let before = host_date();
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.expect(b"Date: ", TIMEOUT)?;
+    let mut date = Vec::new();
+    for _ in 0..before.len() {
+        date.push(qemu.read_byte(TIMEOUT)?);
+    }
+    // The boot may straddle midnight
+    let date = String::from_utf8_lossy(&date).into_owned();
+    if date != before && date != host_date() {
+        return Err(format!("RTC date {date}, host date {before}"));
+    }
+    qemu.expect(b" UTC (pl031)\n", TIMEOUT)?;
+
+    qemu.expect(b"TEST-KERNEL: hello", TIMEOUT)?;
+    return qemu.expect_exit(0, TIMEOUT);
+}
+
+/// An upload from a sender that never starts gives up within its time limit
+fn xmodem_timeout(artifacts: &Artifacts) -> Result<(), String> {
+    let mut qemu = Qemu::spawn(&artifacts.hello)?;
+
+    qemu.break_until(b"Entering monitor", TIMEOUT)?;
+    qemu.expect(b"> ", TIMEOUT)?;
+    qemu.send(format!("load {XMODEM_ADDR:x} 1000 #{}\r", XMODEM_LIMIT.as_secs()).as_bytes())?;
+    qemu.expect(b"Waiting for XMODEM transfer...", TIMEOUT)?;
+    qemu.expect(b"Transfer timed out", XMODEM_LIMIT + Duration::from_secs(1))?;
+    return qemu.expect(b"> ", TIMEOUT);
+}
@@ -0,0 +1,238 @@
+//! Driving a QEMU instance from the tests
+//!
+//! QEMU runs the `virt` machine with the serial port multiplexed on stdio,
+//! so the tests read the console from its stdout and type on its stdin. A
+//! serial break is sent with the multiplexer's `Ctrl-A b` sequence, and a
+//! literal `Ctrl-A` byte has to be doubled. The machine has a ramfb display,
+//! which isn't shown, for the framebuffer console.
+
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// QEMU binary
+pub const QEMU: &str = "qemu-system-aarch64";
+/// CPU model emulated unless a scenario asks for another
+const DEFAULT_CPU: &str = "cortex-a57";
+/// Multiplexer escape character (`Ctrl-A`)
+const ESCAPE: u8 = 0x01;
+/// Interval between serial breaks sent by [`Qemu::break_until`]
+const BREAK_INTERVAL: Duration = Duration::from_millis(20);
+/// Interval between checks of the console output
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A running QEMU instance, killed when dropped
+pub struct Qemu {
+    child: Child,
+    stdin: ChildStdin,
+    /// Everything printed on the console so far
+    output: Arc<Mutex<Vec<u8>>>,
+    /// Offset in `output` up to which output has been consumed
+    cursor: usize,
+}
+
+impl Qemu {
+    /// Boots `image` as the `-kernel` of a QEMU `virt` machine
+    pub fn spawn(image: &Path) -> Result<Self, String> {
+        return Self::spawn_on(image, DEFAULT_CPU);
+    }
+
+    /// Boots `image` like [`spawn`](Self::spawn), emulating CPU model `cpu`
+    pub fn spawn_on(image: &Path, cpu: &str) -> Result<Self, String> {
+        return Self::spawn_args(image, cpu, &[]);
+    }
+
+    /// Boots `image` like [`spawn`](Self::spawn), with `extra` QEMU
+    /// arguments (e.g., more devices)
+    pub fn spawn_with(image: &Path, extra: &[String]) -> Result<Self, String> {
+        return Self::spawn_args(image, DEFAULT_CPU, extra);
+    }
+
+    /// Boots `image` on CPU model `cpu` with `extra` QEMU arguments
+    fn spawn_args(image: &Path, cpu: &str, extra: &[String]) -> Result<Self, String> {
+        let mut child = Command::new(QEMU)
+            .args([
+                "-machine",
+                "virt,gic-version=3,virtualization=on",
+                "-cpu",
+                cpu,
+                "-m",
+                "128M",
+                "-display",
+                "none",
+                "-device",
+                "ramfb",
+                "-serial",
+                "mon:stdio",
+                "-semihosting",
+            ])
+            .args(extra)
+            .arg("-kernel")
+            .arg(image)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{QEMU}: {e}"))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(len) = stdout.read(&mut buf) {
+                if len == 0 {
+                    break;
+                }
+                sink.lock().unwrap().extend_from_slice(&buf[..len]);
+            }
+        });
+
+        return Ok(Qemu {
+            child,
+            stdin,
+            output,
+            cursor: 0,
+        });
+    }
+
+    /// Waits for `marker` on the console and consumes output up to its end
+    pub fn expect(&mut self, marker: &[u8], timeout: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self.consume(marker) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "timed out waiting for {:?}, console:\n{}",
+                    String::from_utf8_lossy(marker),
+                    String::from_utf8_lossy(&self.output.lock().unwrap()),
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Waits for a single byte on the console and consumes it
+    pub fn read_byte(&mut self, timeout: Duration) -> Result<u8, String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(&byte) = self.output.lock().unwrap().get(self.cursor) {
+                self.cursor += 1;
+                return Ok(byte);
+            }
+            if Instant::now() >= deadline {
+                return Err("timed out waiting for a byte".to_string());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Consumes output up to the end of `marker`, if it was printed
+    fn consume(&mut self, marker: &[u8]) -> bool {
+        let output = self.output.lock().unwrap();
+        let pending = &output[self.cursor..];
+
+        match pending.windows(marker.len()).position(|w| w == marker) {
+            Some(pos) => {
+                self.cursor += pos + marker.len();
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    /// Types `data` on the console
+    pub fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut escaped = Vec::with_capacity(data.len());
+
+        for &byte in data {
+            escaped.push(byte);
+            if byte == ESCAPE {
+                escaped.push(ESCAPE);
+            }
+        }
+        return self.write_raw(&escaped);
+    }
+
+    /// Sends serial breaks until `marker` is printed
+    ///
+    /// The bootloader only checks for a break once, just before starting the
+    /// kernel, so breaks are sent repeatedly from boot onwards.
+    pub fn break_until(&mut self, marker: &[u8], timeout: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            self.write_raw(&[ESCAPE, b'b'])?;
+            if self.consume(marker) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "timed out sending breaks for {:?}",
+                    String::from_utf8_lossy(marker)
+                ));
+            }
+            thread::sleep(BREAK_INTERVAL);
+        }
+    }
+
+    /// Saves `len` bytes of guest memory at `addr` to `path`
+    ///
+    /// The QEMU monitor, on the same multiplexer as the console, is switched
+    /// to with `Ctrl-A c` for a `pmemsave`, then switched back from. The
+    /// file is written by the time the monitor prompts again.
+    pub fn save_memory(
+        &mut self,
+        addr: u64,
+        len: usize,
+        path: &Path,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        self.write_raw(&[ESCAPE, b'c'])?;
+        self.expect(b"(qemu) ", timeout)?;
+        self.write_raw(format!("pmemsave 0x{addr:x} {len} {}\n", path.display()).as_bytes())?;
+        self.expect(b"(qemu) ", timeout)?;
+        return self.write_raw(&[ESCAPE, b'c']);
+    }
+
+    /// Waits for QEMU to exit with `status` (set through semihosting)
+    pub fn expect_exit(&mut self, status: i32, timeout: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(exit) = self.child.try_wait().map_err(|e| e.to_string())? {
+                if exit.code() == Some(status) {
+                    return Ok(());
+                }
+                return Err(format!("QEMU exited with {exit}, expected {status}"));
+            }
+            if Instant::now() >= deadline {
+                return Err("timed out waiting for QEMU to exit".to_string());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Writes `data` to QEMU's stdin unmodified
+    fn write_raw(&mut self, data: &[u8]) -> Result<(), String> {
+        self.stdin
+            .write_all(data)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for Qemu {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,47 @@
+//! Stage-2 header writer
+//!
+//! Counterpart of the bootloader's `parsers::stage2`: the 48-byte header a
+//! first stage checks before loading the image behind it, with the magic,
+//! version, header size, load address, entry point, image size, CRC-32 of
+//! the image and CRC-32 of the header, all little-endian.
+
+/// Magic number starting a stage-2 header
+const MAGIC: &[u8; 8] = b"AA64STG2";
+/// Version of the header format
+const VERSION: u32 = 1;
+/// Size of the header
+pub const HEADER_SIZE: usize = 48;
+
+/// Computes the CRC-32 (IEEE 802.3) of `data`
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xedb8_8320,
+            };
+        }
+    }
+    return !crc;
+}
+
+/// Returns `image` behind its header, to be loaded at `load_addr` and
+/// entered at `entry`
+pub fn with_header(image: &[u8], load_addr: u64, entry: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_SIZE + image.len());
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    out.extend_from_slice(&load_addr.to_le_bytes());
+    out.extend_from_slice(&entry.to_le_bytes());
+    out.extend_from_slice(&(image.len() as u64).to_le_bytes());
+    out.extend_from_slice(&crc32(image).to_le_bytes());
+    let header_crc = crc32(&out);
+    out.extend_from_slice(&header_crc.to_le_bytes());
+    out.extend_from_slice(image);
+    return out;
+}
@@ -0,0 +1,73 @@
+//! XMODEM sender
+//!
+//! Counterpart of the bootloader's receiver: 128-byte blocks with an 8-bit
+//! checksum, started by the receiver's NAK. A block the receiver NAKs is
+//! sent again, up to [`MAX_RETRIES`] times.
+
+use std::time::Duration;
+
+use crate::qemu::Qemu;
+
+/// Start of a 128-byte block
+const SOH: u8 = 0x01;
+/// End of transmission
+const EOT: u8 = 0x04;
+/// Block acknowledged
+const ACK: u8 = 0x06;
+/// Start transfer in checksum mode
+const NAK: u8 = 0x15;
+/// Size of a data block
+const BLOCK_SIZE: usize = 128;
+/// Padding of the last block
+const PAD: u8 = 0x1a;
+/// Time to wait for the receiver's replies
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of times a NAKed block is sent again
+const MAX_RETRIES: usize = 10;
+
+/// Sends `data` to the XMODEM receiver on the console
+pub fn send(qemu: &mut Qemu, data: &[u8]) -> Result<(), String> {
+    return send_with_errors(qemu, data, &[]);
+}
+
+/// Sends `data` like [`send`], corrupting the first transmission of the
+/// blocks whose 0-based index is in `corrupt`
+pub fn send_with_errors(qemu: &mut Qemu, data: &[u8], corrupt: &[usize]) -> Result<(), String> {
+    // Wait for the receiver to ask for the transfer
+    while qemu.read_byte(REPLY_TIMEOUT)? != NAK {}
+
+    for (i, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+        let number = (i + 1) as u8;
+        let mut block = [PAD; BLOCK_SIZE];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let checksum = block.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+        let mut packet = vec![SOH, number, !number];
+        packet.extend_from_slice(&block);
+        packet.push(checksum);
+
+        let mut attempt = 0;
+        loop {
+            if attempt == 0 && corrupt.contains(&i) {
+                let mut damaged = packet.clone();
+                damaged[3 + BLOCK_SIZE / 2] ^= 0x01;
+                qemu.send(&damaged)?;
+            } else {
+                qemu.send(&packet)?;
+            }
+
+            match qemu.read_byte(REPLY_TIMEOUT)? {
+                ACK => break,
+                NAK if attempt < MAX_RETRIES => attempt += 1,
+                reply => return Err(format!("block {number} not acknowledged: {reply:#04x}")),
+            }
+        }
+    }
+
+    qemu.send(&[EOT])?;
+    let reply = qemu.read_byte(REPLY_TIMEOUT)?;
+    if reply != ACK {
+        return Err(format!("EOT not acknowledged: {reply:#04x}"));
+    }
+    return Ok(());
+}
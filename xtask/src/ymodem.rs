@@ -0,0 +1,93 @@
+//! YMODEM sender
+//!
+//! Counterpart of the bootloader's YMODEM receiver: a block 0 announcing the
+//! file's name and size, 1024-byte data blocks with a CRC-16, then an empty
+//! block 0 ending the batch. Each block 0 is started by the receiver's `C`.
+
+use std::time::Duration;
+
+use crate::qemu::Qemu;
+
+/// Start of a 128-byte block
+const SOH: u8 = 0x01;
+/// Start of a 1024-byte block
+const STX: u8 = 0x02;
+/// End of transmission
+const EOT: u8 = 0x04;
+/// Block acknowledged
+const ACK: u8 = 0x06;
+/// Start transfer in CRC mode
+const CRC_MODE: u8 = b'C';
+/// Size of block 0
+const HEADER_SIZE: usize = 128;
+/// Size of a data block
+const BLOCK_SIZE: usize = 1024;
+/// Padding of the last block
+const PAD: u8 = 0x1a;
+/// Time to wait for the receiver's replies
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Computes the CRC-16 (polynomial 0x1021, initial value 0) of `data`
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    return crc;
+}
+
+/// Sends block `number` holding `data`, padded with `pad`, and checks it's
+/// acknowledged
+fn send_block(qemu: &mut Qemu, number: u8, data: &[u8], pad: u8) -> Result<(), String> {
+    let (start, size) = match data.len() > HEADER_SIZE {
+        true => (STX, BLOCK_SIZE),
+        false => (SOH, HEADER_SIZE),
+    };
+    let mut block = vec![pad; size];
+    block[..data.len()].copy_from_slice(data);
+
+    let mut packet = vec![start, number, !number];
+    packet.extend_from_slice(&block);
+    packet.extend_from_slice(&crc16(&block).to_be_bytes());
+    qemu.send(&packet)?;
+
+    let reply = qemu.read_byte(REPLY_TIMEOUT)?;
+    if reply != ACK {
+        return Err(format!("block {number} not acknowledged: {reply:#04x}"));
+    }
+    return Ok(());
+}
+
+/// Waits for the receiver to ask for a block 0
+fn wait_start(qemu: &mut Qemu) -> Result<(), String> {
+    while qemu.read_byte(REPLY_TIMEOUT)? != CRC_MODE {}
+    return Ok(());
+}
+
+/// Sends `data` as the file `name` to the YMODEM receiver on the console
+pub fn send(qemu: &mut Qemu, name: &str, data: &[u8]) -> Result<(), String> {
+    let header = format!("{name}\0{} 0 0\0", data.len());
+
+    wait_start(qemu)?;
+    send_block(qemu, 0, header.as_bytes(), 0)?;
+    wait_start(qemu)?;
+    for (i, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+        send_block(qemu, (i + 1) as u8, chunk, PAD)?;
+    }
+
+    qemu.send(&[EOT])?;
+    let reply = qemu.read_byte(REPLY_TIMEOUT)?;
+    if reply != ACK {
+        return Err(format!("EOT not acknowledged: {reply:#04x}"));
+    }
+
+    wait_start(qemu)?;
+    return send_block(qemu, 0, &[], 0);
+}